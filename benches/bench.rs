@@ -3,14 +3,46 @@
 #[macro_use]
 extern crate bencher;
 extern crate binjs;
+extern crate tempdir;
 
 #[macro_use]
 extern crate lazy_static;
 
+use binjs::batch::BatchEncoder;
+use binjs::io::bytes::compress::{BufferPool, Compression, CompressionState, DecompressionOptions};
+use binjs::io::bytes::serialize::{BytesDeserializer, Deserializer};
+use binjs::io::multipart::{Options, Statistics, Targets};
+use binjs::io::{CompressionTarget, Format};
 use binjs::source::*;
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+use binjs::specialized::es6::Enrich;
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{Cursor, Read, Seek};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::thread;
+
+use tempdir::TempDir;
 
 const PATHS: &[&str] = &["tests/data/frameworks/angular.1.6.5.min.js"];
 
+/// The number of files encoded by each iteration of the small files benchmarks.
+const SMALL_FILES: usize = 1000;
+
+/// The number of blocks compressed by each iteration of the small blocks benchmarks.
+const SMALL_BLOCKS: usize = 100;
+
+/// The number of threads decompressing concurrently in the pooled benchmarks.
+const CONCURRENT_DECODERS: usize = 4;
+
+/// The number of blocks decompressed by each thread, for each iteration of
+/// the pooled benchmarks.
+const BLOCKS_PER_DECODER: usize = 25;
+
 fn launch_shift() -> Shift {
     Shift::try_new().expect("Could not launch Shift")
 }
@@ -47,9 +79,333 @@ fn bench_parsing_aux(parser: Option<&Shift>, bencher: &mut bencher::Bencher) {
     });
 }
 
+/// Write `SMALL_FILES` small, distinct, sources to a temporary directory.
+fn small_files() -> (TempDir, Vec<PathBuf>) {
+    let dir = TempDir::new("bench_small_files").expect("Could not create directory");
+    let paths = (0..SMALL_FILES)
+        .map(|i| {
+            let path = dir.path().join(format!("{}.js", i));
+            let source = format!(
+                "function add_{i}(a, b) {{ return a + b + {i}; }}\nconsole.log(add_{i}(1, 2));\n",
+                i = i
+            );
+            fs::write(&path, source).expect("Could not write source");
+            path
+        })
+        .collect();
+    (dir, paths)
+}
+
+fn multipart() -> Format {
+    Format::Multipart {
+        targets: Targets {
+            grammar_table: CompressionTarget::default(),
+            strings_table: CompressionTarget::default(),
+            numbers_table: CompressionTarget::default(),
+            tree: CompressionTarget::default(),
+        },
+        options: Options::default(),
+        stats: Rc::new(RefCell::new(Statistics::default())),
+    }
+}
+
+/// The total byte length of `paths`, so that encoding benchmarks report
+/// their throughput.
+fn total_bytes(paths: &[PathBuf]) -> u64 {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).expect("Could not stat source").len())
+        .sum()
+}
+
+/// Encode small files one at a time, setting up the encoder and format for
+/// each file.
+///
+/// Both small files benchmarks share a single parser, so that they compare
+/// the per-file setup of encoding rather than the launch of the parser.
+fn bench_encoding_small_files_one_by_one(bencher: &mut bencher::Bencher) {
+    let (_dir, paths) = small_files();
+    bencher.bytes = total_bytes(&paths);
+    bencher.iter(|| {
+        for path in &paths {
+            let mut ast = SHIFT.parse_file(path).expect("Could not parse source");
+            Enrich::default()
+                .enrich(&mut ast)
+                .expect("Could not enrich AST");
+            let mut format = multipart();
+            bencher::black_box(
+                Encoder::new()
+                    .encode(None, &mut format, &ast)
+                    .expect("Could not encode"),
+            );
+        }
+    });
+}
+
+/// Encode small files with a `BatchEncoder`, setting up everything once.
+fn bench_encoding_small_files_batch(bencher: &mut bencher::Bencher) {
+    let (_dir, paths) = small_files();
+    bencher.bytes = total_bytes(&paths);
+    let mut encoder =
+        BatchEncoder::try_new(multipart(), Enrich::default()).expect("Could not launch encoder");
+    bencher.iter(|| {
+        for path in &paths {
+            bencher::black_box(encoder.encode_file(path).expect("Could not encode"));
+        }
+    });
+}
+
+/// Small blocks, as the sections of small files.
+fn small_blocks() -> Vec<Vec<u8>> {
+    (0..SMALL_BLOCKS)
+        .map(|i| format!("function add_{i}(a, b) {{ return a + b + {i}; }}", i = i).into_bytes())
+        .collect()
+}
+
+fn bench_compress_small_blocks_aux(
+    compression: Compression,
+    reuse_state: bool,
+    bencher: &mut bencher::Bencher,
+) {
+    let blocks = small_blocks();
+    let mut state = CompressionState::default();
+    let mut out = Vec::new();
+    bencher.iter(|| {
+        for block in &blocks {
+            out.clear();
+            let result = if reuse_state {
+                compression.compress_with_state(block, &mut out, &mut state)
+            } else {
+                compression.compress(block, &mut out)
+            };
+            bencher::black_box(result.expect("Could not compress"));
+        }
+    });
+}
+
+fn bench_compress_small_blocks_brotli_fresh_state(bencher: &mut bencher::Bencher) {
+    bench_compress_small_blocks_aux(Compression::Brotli, false, bencher);
+}
+
+fn bench_compress_small_blocks_brotli_reused_state(bencher: &mut bencher::Bencher) {
+    bench_compress_small_blocks_aux(Compression::Brotli, true, bencher);
+}
+
+fn bench_compress_small_blocks_deflate_fresh_state(bencher: &mut bencher::Bencher) {
+    bench_compress_small_blocks_aux(Compression::Deflate, false, bencher);
+}
+
+fn bench_compress_small_blocks_deflate_reused_state(bencher: &mut bencher::Bencher) {
+    bench_compress_small_blocks_aux(Compression::Deflate, true, bencher);
+}
+
+/// Deserialize a bunch of bytes into itself.
+struct BufDeserializer;
+impl Deserializer for BufDeserializer {
+    type Target = Vec<u8>;
+    fn read<R: Read + Seek>(&self, reader: &mut R) -> Result<Vec<u8>, std::io::Error> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Decompress a large block, compressed with or without its decompressed
+/// byte length. Without it, the decompressed buffer is reallocated as it grows.
+fn bench_decompress_large_block_aux(size_hint: bool, bencher: &mut bencher::Bencher) {
+    let block = fs::read(PATHS[0]).expect("Could not read source");
+    let mut state = CompressionState::default();
+    state.set_size_hint(size_hint);
+    let mut compressed = Vec::new();
+    Compression::Gzip
+        .compress_with_state(&block, &mut compressed, &mut state)
+        .expect("Could not compress");
+    bencher.iter(|| {
+        bencher::black_box(
+            Compression::decompress(&mut Cursor::new(&compressed), &BufDeserializer)
+                .expect("Could not decompress"),
+        );
+    });
+}
+
+fn bench_decompress_large_block_without_size_hint(bencher: &mut bencher::Bencher) {
+    bench_decompress_large_block_aux(false, bencher);
+}
+
+fn bench_decompress_large_block_with_size_hint(bencher: &mut bencher::Bencher) {
+    bench_decompress_large_block_aux(true, bencher);
+}
+
+/// Decompress a large brotli block, streaming it through a buffer of
+/// `buffer_size` bytes.
+fn bench_decompress_brotli_buffer_aux(buffer_size: usize, bencher: &mut bencher::Bencher) {
+    let block = fs::read(PATHS[0]).expect("Could not read source");
+    let mut compressed = Vec::new();
+    Compression::Brotli
+        .compress(&block, &mut compressed)
+        .expect("Could not compress");
+    let options = DecompressionOptions {
+        buffer_size,
+        ..DecompressionOptions::default()
+    };
+    bencher.bytes = block.len() as u64;
+    bencher.iter(|| {
+        bencher::black_box(
+            Compression::decompress_with_options(
+                &mut Cursor::new(&compressed),
+                &BufDeserializer,
+                &options,
+            )
+            .expect("Could not decompress"),
+        );
+    });
+}
+
+fn bench_decompress_brotli_buffer_4k(bencher: &mut bencher::Bencher) {
+    bench_decompress_brotli_buffer_aux(4 * 1024, bencher);
+}
+
+fn bench_decompress_brotli_buffer_64k(bencher: &mut bencher::Bencher) {
+    bench_decompress_brotli_buffer_aux(64 * 1024, bencher);
+}
+
+/// Decompress a large uncompressed block, as the tree of `Targets::fast_tree`,
+/// copying it or borrowing it from the input.
+fn bench_decompress_identity_aux(borrowed: bool, bencher: &mut bencher::Bencher) {
+    let block = fs::read(PATHS[0]).expect("Could not read source");
+    let mut compressed = Vec::new();
+    Compression::Identity
+        .compress(&block, &mut compressed)
+        .expect("Could not compress");
+    bencher.iter(|| {
+        if borrowed {
+            bencher::black_box(
+                Compression::decompress_borrowed(
+                    &mut Cursor::new(&compressed[..]),
+                    &BytesDeserializer,
+                    std::usize::MAX,
+                )
+                .expect("Could not decompress")
+                .len(),
+            );
+        } else {
+            bencher::black_box(
+                Compression::decompress(&mut Cursor::new(&compressed), &BufDeserializer)
+                    .expect("Could not decompress")
+                    .len(),
+            );
+        }
+    });
+}
+
+fn bench_decompress_identity_copied(bencher: &mut bencher::Bencher) {
+    bench_decompress_identity_aux(false, bencher);
+}
+
+fn bench_decompress_identity_borrowed(bencher: &mut bencher::Bencher) {
+    bench_decompress_identity_aux(true, bencher);
+}
+
+/// Decode a file whose sections are all uncompressed, copying its tree or
+/// reading it in place (see `Decoder::decode_shared`).
+fn bench_decode_identity_aux(shared: bool, bencher: &mut bencher::Bencher) {
+    let ast = SHIFT.parse_file(PATHS[0]).expect("Could not parse source");
+    let mut format = Format::Multipart {
+        targets: Targets::uniform(Compression::Identity),
+        options: Options::default(),
+        stats: Rc::new(RefCell::new(Statistics::default())),
+    };
+    let data = Rc::new(
+        Encoder::new()
+            .encode(None, &mut format, &ast)
+            .expect("Could not encode")
+            .to_vec(),
+    );
+    bencher.iter(|| {
+        let script: Script = if shared {
+            Decoder::new()
+                .decode_shared(data.clone())
+                .expect("Could not decode")
+        } else {
+            Decoder::new()
+                .decode(&mut format, Cursor::new(&data[..]))
+                .expect("Could not decode")
+        };
+        bencher::black_box(script);
+    });
+}
+
+fn bench_decode_identity_copied(bencher: &mut bencher::Bencher) {
+    bench_decode_identity_aux(false, bencher);
+}
+
+fn bench_decode_identity_shared(bencher: &mut bencher::Bencher) {
+    bench_decode_identity_aux(true, bencher);
+}
+
+/// Decompress a small block from several threads at once, drawing buffers
+/// from a pool shared by all threads, or allocating them if `pooled` is `false`.
+fn bench_decompress_concurrent_aux(pooled: bool, bencher: &mut bencher::Bencher) {
+    let block = small_blocks().concat();
+    let mut compressed = Vec::new();
+    Compression::Gzip
+        .compress(&block, &mut compressed)
+        .expect("Could not compress");
+    let compressed = Arc::new(compressed);
+    // A pool that keeps no buffer allocates every buffer.
+    let pool = BufferPool::new(if pooled { CONCURRENT_DECODERS } else { 0 });
+    bencher.iter(|| {
+        let threads: Vec<_> = (0..CONCURRENT_DECODERS)
+            .map(|_| {
+                let compressed = compressed.clone();
+                let pool = pool.clone();
+                thread::spawn(move || {
+                    for _ in 0..BLOCKS_PER_DECODER {
+                        bencher::black_box(
+                            Compression::decompress_pooled(
+                                &mut Cursor::new(&*compressed),
+                                &pool,
+                                std::usize::MAX,
+                            )
+                            .expect("Could not decompress"),
+                        );
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().expect("Error in decoding thread");
+        }
+    });
+}
+
+fn bench_decompress_concurrent_without_pool(bencher: &mut bencher::Bencher) {
+    bench_decompress_concurrent_aux(false, bencher);
+}
+
+fn bench_decompress_concurrent_with_pool(bencher: &mut bencher::Bencher) {
+    bench_decompress_concurrent_aux(true, bencher);
+}
+
 benchmark_group!(
     bench,
     bench_parsing_one_parser_per_run,
-    bench_parsing_reuse_parser
+    bench_parsing_reuse_parser,
+    bench_encoding_small_files_one_by_one,
+    bench_encoding_small_files_batch,
+    bench_compress_small_blocks_brotli_fresh_state,
+    bench_compress_small_blocks_brotli_reused_state,
+    bench_compress_small_blocks_deflate_fresh_state,
+    bench_compress_small_blocks_deflate_reused_state,
+    bench_decompress_large_block_without_size_hint,
+    bench_decompress_large_block_with_size_hint,
+    bench_decompress_brotli_buffer_4k,
+    bench_decompress_brotli_buffer_64k,
+    bench_decompress_identity_copied,
+    bench_decompress_identity_borrowed,
+    bench_decode_identity_copied,
+    bench_decode_identity_shared,
+    bench_decompress_concurrent_without_pool,
+    bench_decompress_concurrent_with_pool
 );
 benchmark_main!(bench);
@@ -0,0 +1,130 @@
+use ast::*;
+
+use binjs_io::multipart::Diagnostic;
+use binjs_shared::VisitMe;
+
+use std::collections::HashMap;
+
+type EnterResult = Result<VisitMe<()>, ()>;
+
+/// Collect non-fatal observations on constructions that are valid but
+/// generally considered dubious:
+///
+/// - `"with"`, for each `with` statement;
+/// - `"eval"`, for each call to a function named `eval`;
+/// - `"duplicate-key"`, for each object literal defining the same property
+///   twice, e.g. `{ a: 1, a: 2 }`, except for a getter and a setter;
+/// - `"proto"`, for each `__proto__: value` property, which sets the
+///   prototype of the object rather than defining a property.
+///
+/// Such objects are encoded as written, with all their properties in order.
+///
+/// Non-strict octal literals are not reported, as the AST only stores
+/// the value of number literals, not their source.
+pub struct DiagnosticsVisitor {
+    diagnostics: Vec<Diagnostic>,
+}
+impl DiagnosticsVisitor {
+    /// Collect the diagnostics of `script`, in source order.
+    pub fn collect(script: &mut Script) -> Vec<Diagnostic> {
+        let mut visitor = DiagnosticsVisitor {
+            diagnostics: vec![],
+        };
+        script
+            .walk(&mut WalkPath::new(), &mut visitor)
+            .expect("Collecting diagnostics cannot fail");
+        visitor.diagnostics
+    }
+
+    fn report(&mut self, kind: &str, path: &WalkPath) {
+        self.diagnostics.push(Diagnostic {
+            kind: kind.to_string(),
+            path: format!("{:?}", path),
+        });
+    }
+}
+
+/// How a property of an object literal is defined.
+#[derive(Clone, Copy, PartialEq)]
+enum PropertyKind {
+    /// A value, or a method.
+    Data,
+    Getter,
+    Setter,
+}
+
+/// Record that property `name` is defined as `kind`, returning `true` if
+/// this redefines it.
+fn define<'a>(
+    seen: &mut HashMap<&'a str, Vec<PropertyKind>>,
+    name: &'a str,
+    kind: PropertyKind,
+) -> bool {
+    let kinds = seen.entry(name).or_insert_with(Vec::new);
+    // A getter and a setter, in either order, define a single accessor.
+    let redefines = match kind {
+        PropertyKind::Data => !kinds.is_empty(),
+        _ => kinds.contains(&kind) || kinds.contains(&PropertyKind::Data),
+    };
+    kinds.push(kind);
+    redefines
+}
+
+impl Visitor<()> for DiagnosticsVisitor {
+    fn enter_with_statement(&mut self, path: &WalkPath, _node: &mut WithStatement) -> EnterResult {
+        self.report("with", path);
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_call_expression(&mut self, path: &WalkPath, node: &mut CallExpression) -> EnterResult {
+        if let ExpressionOrSuper::IdentifierExpression(ref id) = node.callee {
+            if id.name == "eval" {
+                self.report("eval", path);
+            }
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_object_expression(
+        &mut self,
+        path: &WalkPath,
+        node: &mut ObjectExpression,
+    ) -> EnterResult {
+        let mut seen = HashMap::new();
+        let mut has_duplicate = false;
+        for property in &node.properties {
+            let (name, kind) = match *property {
+                ObjectProperty::DataProperty(ref property) => (&property.name, PropertyKind::Data),
+                ObjectProperty::ShorthandProperty(ref property) => {
+                    has_duplicate |=
+                        define(&mut seen, property.name.name.as_str(), PropertyKind::Data);
+                    continue;
+                }
+                ObjectProperty::EagerMethod(ref method) => (&method.name, PropertyKind::Data),
+                ObjectProperty::LazyMethod(ref method) => (&method.name, PropertyKind::Data),
+                ObjectProperty::EagerGetter(ref getter) => (&getter.name, PropertyKind::Getter),
+                ObjectProperty::LazyGetter(ref getter) => (&getter.name, PropertyKind::Getter),
+                ObjectProperty::EagerSetter(ref setter) => (&setter.name, PropertyKind::Setter),
+                ObjectProperty::LazySetter(ref setter) => (&setter.name, PropertyKind::Setter),
+                _ => continue,
+            };
+            // Computed names are only known at runtime.
+            let name = match *name {
+                PropertyName::LiteralPropertyName(ref name) => name.value.as_str(),
+                _ => continue,
+            };
+            if name == "__proto__" && kind == PropertyKind::Data {
+                if let ObjectProperty::DataProperty(_) = *property {
+                    // Sets the prototype rather than defining a property.
+                    self.report("proto", path);
+                    continue;
+                }
+            }
+            has_duplicate |= define(&mut seen, name, kind);
+        }
+        if has_duplicate {
+            self.report("duplicate-key", path);
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+}
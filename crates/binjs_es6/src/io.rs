@@ -1,12 +1,27 @@
+#[cfg(feature = "parallel")]
+use ast::{
+    ArrowExpressionContentsWithExpression, ArrowExpressionContentsWithFunctionBody,
+    FunctionExpressionContents, FunctionOrMethodContents, GetterContents, SetterContents,
+};
+use ast::{
+    LazyArrowExpressionWithExpression, LazyArrowExpressionWithFunctionBody,
+    LazyFunctionDeclaration, LazyFunctionExpression, LazyGetter, LazyMethod, LazySetter, Script,
+    Visitor, WalkPath, Walker,
+};
+use binjs_io::metrics::{MetricsSink, Phase, PhaseMetrics, Stopwatch};
+use binjs_io::multipart::{ContainerHash, Metadata, ReadLimits};
 use binjs_io::{
-    self, Deserialization, TokenReader, TokenReaderError, TokenWriterError, TokenWriterTreeAdapter,
+    self, BinjsError, CancellationToken, DecodeWarning, Deserialization, TokenReader,
+    TokenReaderError, TokenWriterError, TokenWriterTreeAdapter,
 };
 pub use binjs_io::{Serialization, TokenSerializer, TokenWriter};
 use binjs_shared::{
-    self, FieldName, IdentifierName, InterfaceName, Offset, PropertyKey, SharedString,
+    self, FieldName, IdentifierName, InterfaceName, Offset, PropertyKey, SharedString, VisitMe,
 };
 
-use std::io::{Read, Seek};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Cursor, Read, Seek};
+use std::rc::Rc;
 
 /// A path used when (de)serializing ES6 ASTs.
 pub type IOPath = binjs_shared::ast::Path<
@@ -23,13 +38,517 @@ where
     R: TokenReader,
 {
     pub reader: R,
+
+    /// If `true`, leave the contents of lazy fields undecoded.
+    defer_lazy_fields: bool,
+
+    /// If `true`, accept values of string enums unknown to the grammar.
+    lenient_enums: bool,
+
+    /// Non-fatal observations made while deserializing.
+    warnings: Vec<DecodeWarning>,
 }
 impl<R> Deserializer<R>
 where
     R: TokenReader,
 {
     pub fn new(reader: R) -> Self {
-        Self { reader }
+        Self {
+            reader,
+            defer_lazy_fields: false,
+            lenient_enums: false,
+            warnings: vec![],
+        }
+    }
+
+    /// Configure whether the contents of lazy fields (e.g. the `contents` of a
+    /// `LazyFunctionDeclaration`) are decoded immediately.
+    ///
+    /// If `value == true` and the reader supports it, the contents of lazy fields
+    /// are skipped and replaced with `Default::default()`, while the matching
+    /// `*_position` field holds the position of the contents, to decode them later
+    /// with `deserialize_lazy`. Otherwise, lazy fields are decoded as any other field.
+    pub fn with_deferred_lazy_fields(mut self, value: bool) -> Self {
+        self.defer_lazy_fields = value;
+        self
+    }
+
+    /// Return `true` if the contents of lazy fields are left undecoded.
+    pub fn defers_lazy_fields(&self) -> bool {
+        self.defer_lazy_fields
+    }
+
+    /// Configure whether values of string enums unknown to the grammar, e.g. a
+    /// binary operator introduced by a more recent encoder, are rejected.
+    ///
+    /// If `value == true`, such values are decoded as the `Unknown` variant of
+    /// the enum, which is encoded back as the same value, so that the file may
+    /// still be processed structurally. Otherwise, they are decoding errors.
+    ///
+    /// Formats that store enums as indices into the known values (e.g. entropy)
+    /// reject unknown values while reading, regardless of this option.
+    pub fn with_lenient_enums(mut self, value: bool) -> Self {
+        self.lenient_enums = value;
+        self
+    }
+
+    /// Return `true` if values of string enums unknown to the grammar are accepted.
+    pub fn has_lenient_enums(&self) -> bool {
+        self.lenient_enums
+    }
+
+    /// Record a non-fatal observation, e.g. an unknown enum value accepted
+    /// with lenient enums.
+    pub fn add_warning(&mut self, warning: DecodeWarning) {
+        self.warnings.push(warning);
+    }
+
+    /// Non-fatal observations made so far, by the reader, then while deserializing.
+    pub fn warnings(&self) -> Vec<DecodeWarning> {
+        let mut warnings = self.reader.warnings().to_vec();
+        warnings.extend(self.warnings.iter().cloned());
+        warnings
+    }
+
+    /// Decode the contents of a lazy field left undecoded because of
+    /// `with_deferred_lazy_fields`, given the matching `*_position` field.
+    pub fn deserialize_lazy<T>(
+        &mut self,
+        position: u64,
+        path: &mut IOPath,
+    ) -> Result<T, TokenReaderError>
+    where
+        Self: Deserialization<T>,
+    {
+        self.reader.seek_lazy_at(position, path)?;
+        self.deserialize(path)
+    }
+
+    /// Decode the contents of all the lazy fields of `script`, which must
+    /// have been decoded by this deserializer, with deferred lazy fields.
+    ///
+    /// Nested lazy fields are decoded, too. Once decoded, the `*_position`
+    /// fields are reset to `None`.
+    ///
+    /// Contents are decoded one after the other, see also
+    /// `decode_all_bodies_parallel`.
+    pub fn decode_all_bodies(&mut self, script: &mut Script) -> Result<(), TokenReaderError> {
+        if !self.defer_lazy_fields {
+            // Everything has been decoded already.
+            return Ok(());
+        }
+        let mut visitor = LazyContentsDecoder { deserializer: self };
+        script.walk(&mut WalkPath::new(), &mut visitor)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<R> Deserializer<R>
+where
+    R: TokenReader,
+{
+    /// Decode the contents of all the lazy fields of `script`, as
+    /// `decode_all_bodies`, but concurrently, with `rayon`.
+    ///
+    /// The AST is not `Send`, as strings are shared through `Rc`, so each
+    /// thread decodes with its own reader, created by `new_reader` over the
+    /// same data as this deserializer, and contents are sent back to this
+    /// thread as JSON. Errors of other threads are reported as
+    /// `TokenReaderError::GenericError`.
+    pub fn decode_all_bodies_parallel<F, R2>(
+        &mut self,
+        script: &mut Script,
+        new_reader: F,
+    ) -> Result<(), TokenReaderError>
+    where
+        F: Fn() -> Result<R2, TokenReaderError> + Send + Sync,
+        R2: TokenReader,
+    {
+        use rayon::prelude::*;
+
+        if !self.defer_lazy_fields {
+            // Everything has been decoded already.
+            return Ok(());
+        }
+
+        // The offset table: the position of each undecoded contents, in
+        // the order of the walk.
+        let mut collector = LazyPositionCollector { positions: vec![] };
+        script.walk(&mut WalkPath::new(), &mut collector)?;
+
+        let lenient_enums = self.lenient_enums;
+        let decoded: Vec<(serde_json::Value, Vec<DecodeWarning>)> = collector
+            .positions
+            .par_iter()
+            .map_init(
+                || {
+                    new_reader()
+                        .map(|reader| Deserializer::new(reader).with_lenient_enums(lenient_enums))
+                        .map_err(|err| format!("{:?}", err))
+                },
+                |deserializer,
+                 &(kind, position)|
+                 -> Result<(serde_json::Value, Vec<DecodeWarning>), String> {
+                    let deserializer = deserializer.as_mut().map_err(|err| err.clone())?;
+                    let value = match kind {
+                        LazyContentsKind::FunctionExpression => deserializer
+                            .deserialize_lazy_to_json::<FunctionExpressionContents>(position),
+                        LazyContentsKind::FunctionOrMethod => deserializer
+                            .deserialize_lazy_to_json::<FunctionOrMethodContents>(position),
+                        LazyContentsKind::Getter => {
+                            deserializer.deserialize_lazy_to_json::<GetterContents>(position)
+                        }
+                        LazyContentsKind::Setter => {
+                            deserializer.deserialize_lazy_to_json::<SetterContents>(position)
+                        }
+                        LazyContentsKind::ArrowWithFunctionBody => deserializer
+                            .deserialize_lazy_to_json::<ArrowExpressionContentsWithFunctionBody>(
+                                position,
+                            ),
+                        LazyContentsKind::ArrowWithExpression => deserializer
+                            .deserialize_lazy_to_json::<ArrowExpressionContentsWithExpression>(
+                                position,
+                            ),
+                    }?;
+                    let warnings = std::mem::replace(&mut deserializer.warnings, vec![]);
+                    Ok((value, warnings))
+                },
+            )
+            .collect::<Result<_, String>>()
+            .map_err(TokenReaderError::GenericError)?;
+
+        let mut contents = Vec::with_capacity(decoded.len());
+        for (value, warnings) in decoded {
+            contents.push(value);
+            self.warnings.extend(warnings);
+        }
+        let mut visitor = LazyContentsSetter {
+            contents: contents.into_iter(),
+        };
+        script.walk(&mut WalkPath::new(), &mut visitor)?;
+        Ok(())
+    }
+
+    /// Decode the contents at `position`, without deferring nested lazy
+    /// fields, as JSON.
+    fn deserialize_lazy_to_json<T>(&mut self, position: u64) -> Result<serde_json::Value, String>
+    where
+        Self: Deserialization<T>,
+        T: serde::Serialize,
+    {
+        let contents: T = self
+            .deserialize_lazy(position, &mut IOPath::new())
+            .map_err(|err| format!("{:?}", err))?;
+        serde_json::to_value(&contents).map_err(|err| format!("{:?}", err))
+    }
+}
+
+/// The type of the contents of a lazy field.
+#[cfg(feature = "parallel")]
+#[derive(Clone, Copy)]
+enum LazyContentsKind {
+    FunctionExpression,
+    FunctionOrMethod,
+    Getter,
+    Setter,
+    ArrowWithFunctionBody,
+    ArrowWithExpression,
+}
+
+/// A visitor collecting the positions of the contents of lazy fields left
+/// undecoded.
+#[cfg(feature = "parallel")]
+struct LazyPositionCollector {
+    positions: Vec<(LazyContentsKind, u64)>,
+}
+#[cfg(feature = "parallel")]
+impl LazyPositionCollector {
+    fn collect(&mut self, kind: LazyContentsKind, position: Option<u64>) {
+        if let Some(position) = position {
+            self.positions.push((kind, position));
+        }
+    }
+}
+#[cfg(feature = "parallel")]
+impl Visitor<TokenReaderError> for LazyPositionCollector {
+    fn enter_lazy_function_declaration(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyFunctionDeclaration,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        self.collect(LazyContentsKind::FunctionOrMethod, node.contents_position);
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_function_expression(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyFunctionExpression,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        self.collect(LazyContentsKind::FunctionExpression, node.contents_position);
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_method(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyMethod,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        self.collect(LazyContentsKind::FunctionOrMethod, node.contents_position);
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_getter(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyGetter,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        self.collect(LazyContentsKind::Getter, node.contents_position);
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_setter(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazySetter,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        self.collect(LazyContentsKind::Setter, node.contents_position);
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_arrow_expression_with_function_body(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyArrowExpressionWithFunctionBody,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        self.collect(
+            LazyContentsKind::ArrowWithFunctionBody,
+            node.contents_position,
+        );
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_arrow_expression_with_expression(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyArrowExpressionWithExpression,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        self.collect(
+            LazyContentsKind::ArrowWithExpression,
+            node.contents_position,
+        );
+        Ok(VisitMe::HoldThis(()))
+    }
+}
+
+/// A visitor setting the contents of lazy fields left undecoded, in the
+/// order of `LazyPositionCollector`, from JSON.
+#[cfg(feature = "parallel")]
+struct LazyContentsSetter {
+    contents: std::vec::IntoIter<serde_json::Value>,
+}
+#[cfg(feature = "parallel")]
+impl LazyContentsSetter {
+    fn next_contents<T>(
+        &mut self,
+        position: &mut Option<u64>,
+    ) -> Result<Option<T>, TokenReaderError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        if position.take().is_none() {
+            return Ok(None);
+        }
+        let value = self.contents.next().ok_or_else(|| {
+            TokenReaderError::GenericError("Missing decoded contents".to_string())
+        })?;
+        let contents = serde_json::from_value(value)
+            .map_err(|err| TokenReaderError::GenericError(format!("{:?}", err)))?;
+        Ok(Some(contents))
+    }
+}
+#[cfg(feature = "parallel")]
+impl Visitor<TokenReaderError> for LazyContentsSetter {
+    fn enter_lazy_function_declaration(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyFunctionDeclaration,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        if let Some(contents) = self.next_contents(&mut node.contents_position)? {
+            node.contents = contents;
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_function_expression(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyFunctionExpression,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        if let Some(contents) = self.next_contents(&mut node.contents_position)? {
+            node.contents = contents;
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_method(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyMethod,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        if let Some(contents) = self.next_contents(&mut node.contents_position)? {
+            node.contents = contents;
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_getter(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyGetter,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        if let Some(contents) = self.next_contents(&mut node.contents_position)? {
+            node.contents = contents;
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_setter(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazySetter,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        if let Some(contents) = self.next_contents(&mut node.contents_position)? {
+            node.contents = contents;
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_arrow_expression_with_function_body(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyArrowExpressionWithFunctionBody,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        if let Some(contents) = self.next_contents(&mut node.contents_position)? {
+            node.contents = contents;
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_arrow_expression_with_expression(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyArrowExpressionWithExpression,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        if let Some(contents) = self.next_contents(&mut node.contents_position)? {
+            node.contents = contents;
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+}
+
+/// A visitor decoding the contents of lazy fields left undecoded, before
+/// walking through them.
+struct LazyContentsDecoder<'a, R>
+where
+    R: TokenReader + 'a,
+{
+    deserializer: &'a mut Deserializer<R>,
+}
+impl<'a, R> Visitor<TokenReaderError> for LazyContentsDecoder<'a, R>
+where
+    R: TokenReader,
+{
+    fn enter_lazy_function_declaration(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyFunctionDeclaration,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        if let Some(position) = node.contents_position.take() {
+            node.contents = self
+                .deserializer
+                .deserialize_lazy(position, &mut IOPath::new())?;
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_function_expression(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyFunctionExpression,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        if let Some(position) = node.contents_position.take() {
+            node.contents = self
+                .deserializer
+                .deserialize_lazy(position, &mut IOPath::new())?;
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_method(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyMethod,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        if let Some(position) = node.contents_position.take() {
+            node.contents = self
+                .deserializer
+                .deserialize_lazy(position, &mut IOPath::new())?;
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_getter(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyGetter,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        if let Some(position) = node.contents_position.take() {
+            node.contents = self
+                .deserializer
+                .deserialize_lazy(position, &mut IOPath::new())?;
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_setter(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazySetter,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        if let Some(position) = node.contents_position.take() {
+            node.contents = self
+                .deserializer
+                .deserialize_lazy(position, &mut IOPath::new())?;
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_arrow_expression_with_function_body(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyArrowExpressionWithFunctionBody,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        if let Some(position) = node.contents_position.take() {
+            node.contents = self
+                .deserializer
+                .deserialize_lazy(position, &mut IOPath::new())?;
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_lazy_arrow_expression_with_expression(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut LazyArrowExpressionWithExpression,
+    ) -> Result<VisitMe<()>, TokenReaderError> {
+        if let Some(position) = node.contents_position.take() {
+            node.contents = self
+                .deserializer
+                .deserialize_lazy(position, &mut IOPath::new())?;
+        }
+        Ok(VisitMe::HoldThis(()))
     }
 }
 
@@ -340,16 +859,98 @@ where
     }
 }
 
-pub struct Decoder;
+pub struct Decoder {
+    /// See `Deserializer::with_lenient_enums`.
+    lenient_enums: bool,
+
+    /// See `Decoder::with_cancellation`.
+    cancellation: Option<CancellationToken>,
+}
 impl Decoder {
     pub fn new() -> Self {
-        Decoder
+        Decoder {
+            lenient_enums: false,
+            cancellation: None,
+        }
     }
+
+    /// Decode values of string enums unknown to the grammar as the `Unknown`
+    /// variant of the enum, rather than failing (see `Deserializer::with_lenient_enums`).
+    pub fn with_lenient_enums(self, value: bool) -> Self {
+        Decoder {
+            lenient_enums: value,
+            ..self
+        }
+    }
+
+    /// Fail with `BinjsError::Cancelled` once `token` is cancelled.
+    ///
+    /// Only checked by multipart files, between sections and as tagged
+    /// tuples and lists are read (see `ReadLimits::cancellation`).
+    pub fn with_cancellation(self, token: CancellationToken) -> Self {
+        Decoder {
+            cancellation: Some(token),
+            ..self
+        }
+    }
+
+    /// Read a multipart container, checking for cancellation.
+    fn multipart_reader<R: Read + Seek>(
+        &self,
+        source: R,
+    ) -> Result<binjs_io::multipart::TreeTokenReader, TokenReaderError> {
+        binjs_io::multipart::TreeTokenReader::with_limits(
+            source,
+            ReadLimits {
+                cancellation: self.cancellation.clone(),
+                ..ReadLimits::default()
+            },
+        )
+    }
+    /// Decode a multipart container held in memory, e.g. a file cached by
+    /// a server, checking for cancellation.
+    ///
+    /// If the tree is uncompressed, e.g. with `Targets::fast_tree`, it is
+    /// read in place rather than copied (see `TreeTokenReader::from_shared`).
+    pub fn decode_shared<AST>(&self, data: Rc<Vec<u8>>) -> Result<AST, BinjsError>
+    where
+        Deserializer<binjs_io::multipart::TreeTokenReader>: Deserialization<AST>,
+    {
+        let reader = binjs_io::multipart::TreeTokenReader::from_shared(
+            data,
+            ReadLimits {
+                cancellation: self.cancellation.clone(),
+                ..ReadLimits::default()
+            },
+        )?;
+        let mut deserializer = Deserializer::new(reader).with_lenient_enums(self.lenient_enums);
+        let ast = deserializer.deserialize(&mut IOPath::new())?;
+        Ok(ast)
+    }
+
     pub fn decode<R: Read + Seek, AST>(
         &self,
         format: &mut binjs_io::Format,
         source: R,
-    ) -> Result<AST, TokenReaderError>
+    ) -> Result<AST, BinjsError>
+    where
+        Deserializer<binjs_io::simple::TreeTokenReader<R>>: Deserialization<AST>,
+        Deserializer<binjs_io::multipart::TreeTokenReader>: Deserialization<AST>,
+        Deserializer<binjs_io::binjs_json::read::Decoder<R>>: Deserialization<AST>,
+        Deserializer<binjs_io::entropy::read::Decoder>: Deserialization<AST>,
+    {
+        let (ast, _) = self.decode_with_warnings(format, source)?;
+        Ok(ast)
+    }
+
+    /// Decode an AST, along with the non-fatal observations made while
+    /// decoding it, e.g. a deprecated format version, skipped sections or,
+    /// with lenient enums, unknown enum values.
+    pub fn decode_with_warnings<R: Read + Seek, AST>(
+        &self,
+        format: &mut binjs_io::Format,
+        source: R,
+    ) -> Result<(AST, Vec<DecodeWarning>), BinjsError>
     where
         Deserializer<binjs_io::simple::TreeTokenReader<R>>: Deserialization<AST>,
         Deserializer<binjs_io::multipart::TreeTokenReader>: Deserialization<AST>,
@@ -360,43 +961,291 @@ impl Decoder {
         match *format {
             binjs_io::Format::Simple { .. } => {
                 let reader = binjs_io::simple::TreeTokenReader::new(source);
-                let mut deserializer = Deserializer::new(reader);
+                let mut deserializer =
+                    Deserializer::new(reader).with_lenient_enums(self.lenient_enums);
                 let ast = deserializer.deserialize(&mut path)?;
-                Ok(ast)
+                Ok((ast, deserializer.warnings()))
             }
             binjs_io::Format::Multipart { .. } => {
-                let reader = binjs_io::multipart::TreeTokenReader::new(source)?;
-                let mut deserializer = Deserializer::new(reader);
+                let reader = self.multipart_reader(source)?;
+                let mut deserializer =
+                    Deserializer::new(reader).with_lenient_enums(self.lenient_enums);
                 let ast = deserializer.deserialize(&mut path)?;
-                Ok(ast)
+                Ok((ast, deserializer.warnings()))
             }
             binjs_io::Format::JSON { .. } => {
                 let reader = binjs_io::binjs_json::read::Decoder::new(source)?;
-                let mut deserializer = Deserializer::new(reader);
+                let mut deserializer =
+                    Deserializer::new(reader).with_lenient_enums(self.lenient_enums);
                 let ast = deserializer.deserialize(&mut path)?;
-                Ok(ast)
+                Ok((ast, deserializer.warnings()))
             }
             binjs_io::Format::Entropy { ref options } => {
                 let reader = binjs_io::entropy::read::Decoder::new(options, source)?;
-                let mut deserializer = Deserializer::new(reader);
+                let mut deserializer =
+                    Deserializer::new(reader).with_lenient_enums(self.lenient_enums);
                 let ast = deserializer.deserialize(&mut path)?;
-                Ok(ast)
+                Ok((ast, deserializer.warnings()))
+            }
+            binjs_io::Format::XML => Err(BinjsError::Unsupported("Decoding from XML".to_string())),
+        }
+    }
+
+    /// Decode a script, then rewrite it with `transform`, e.g. to instrument
+    /// it for coverage or profiling without parsing its source again.
+    ///
+    /// The rewritten script is returned as is: rewrites that introduce or
+    /// remove bindings are responsible for keeping scope annotations
+    /// consistent, should the script be encoded again.
+    pub fn decode_with_transform<R, V, E>(
+        &self,
+        format: &mut binjs_io::Format,
+        source: R,
+        transform: &mut V,
+    ) -> Result<Script, BinjsError>
+    where
+        R: Read + Seek,
+        V: Visitor<E>,
+        BinjsError: From<E>,
+    {
+        let mut script: Script = self.decode(format, source)?;
+        script.walk(&mut WalkPath::new(), transform)?;
+        Ok(script)
+    }
+
+    /// Decode an AST, along with the metadata on its source, e.g. a shebang.
+    ///
+    /// Formats that do not store metadata return an empty `Metadata`.
+    pub fn decode_with_metadata<R: Read + Seek, AST>(
+        &self,
+        format: &mut binjs_io::Format,
+        source: R,
+    ) -> Result<(AST, Metadata), BinjsError>
+    where
+        Deserializer<binjs_io::simple::TreeTokenReader<R>>: Deserialization<AST>,
+        Deserializer<binjs_io::multipart::TreeTokenReader>: Deserialization<AST>,
+        Deserializer<binjs_io::binjs_json::read::Decoder<R>>: Deserialization<AST>,
+        Deserializer<binjs_io::entropy::read::Decoder>: Deserialization<AST>,
+    {
+        match *format {
+            binjs_io::Format::Multipart { .. } => {
+                let reader = self.multipart_reader(source)?;
+                let metadata = reader.metadata().clone();
+                let mut deserializer =
+                    Deserializer::new(reader).with_lenient_enums(self.lenient_enums);
+                let ast = deserializer.deserialize(&mut IOPath::new())?;
+                Ok((ast, metadata))
+            }
+            _ => {
+                let ast = self.decode(format, source)?;
+                Ok((ast, Metadata::default()))
             }
-            _ => unimplemented!(),
         }
     }
+
+    /// Decode a script into its BinAST JSON, one object per node of the
+    /// grammar, with its kind in field `type`, e.g. `{"type": "Script", ...}`
+    /// or `{"type": "EagerFunctionDeclaration", ...}`, and one field per
+    /// attribute of the interface.
+    ///
+    /// This is not ESTree: it is the JSON produced by the source parsers and
+    /// read by `binjs::source::json::script_from_json`, useful to inspect or compare
+    /// decoded files without depending on the typed AST.
+    pub fn decode_to_binast_json<R: Read + Seek>(
+        &self,
+        format: &mut binjs_io::Format,
+        source: R,
+    ) -> Result<serde_json::Value, BinjsError> {
+        let script: Script = self.decode(format, source)?;
+        serde_json::to_value(&script)
+            .map_err(|err| BinjsError::Format(format!("Could not convert AST to JSON: {}", err)))
+    }
+}
+/// The maximal byte length of a file decoded by `decode_untrusted`.
+pub const UNTRUSTED_MAX_INPUT_BYTES: usize = 64 * 1024 * 1024;
+
+/// Decode a multipart file from an untrusted source, e.g. uploaded by a user,
+/// with conservative limits on resources.
+///
+/// The file is read to memory, up to `UNTRUSTED_MAX_INPUT_BYTES`, then decoded
+/// with `ReadLimits::untrusted()`, bounding the number of sections, their size
+/// once decompressed and the nesting of the tree. Every byte of the file
+/// must belong to a section, and every byte of the tree to the script.
+pub fn decode_untrusted<R: Read>(inp: R) -> Result<Script, BinjsError> {
+    let reader = binjs_io::multipart::TreeTokenReader::from_stream(
+        inp,
+        UNTRUSTED_MAX_INPUT_BYTES,
+        ReadLimits::untrusted(),
+    )?;
+    let mut deserializer = Deserializer::new(reader);
+    let script: Script = deserializer.deserialize(&mut IOPath::new())?;
+    let remaining = deserializer.reader.remaining_tree_bytes()?;
+    if remaining != 0 {
+        return Err(BinjsError::Format(format!(
+            "{} unexpected bytes after the end of the tree",
+            remaining
+        )));
+    }
+    Ok(script)
+}
+
+/// A decoder keeping the most recently decoded ASTs in memory, so that
+/// decoding the same file again is served from memory.
+///
+/// Files are identified by their byte length and their SHA-256, computed
+/// once per decode, rather than by the hash stored in their footer, which
+/// may be a non-cryptographic hash. As the dictionary of the entropy format
+/// is part of its options, entropy files are always decoded from scratch.
+pub struct CachingDecoder<AST> {
+    /// The maximal number of ASTs kept in memory.
+    capacity: usize,
+
+    /// The cached ASTs, along with the time of their last use.
+    entries: HashMap<CacheKey, (AST, u64)>,
+
+    /// The keys of `entries`, by time of last use.
+    recency: BTreeMap<u64, CacheKey>,
+
+    /// Incremented on each lookup.
+    clock: u64,
+
+    /// If specified, the destination for cache hit/miss metrics.
+    metrics: Option<Rc<dyn MetricsSink>>,
+}
+
+/// The identity of a file in a `CachingDecoder`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    /// The name of the format, see `Format::name`.
+    format: String,
+    byte_len: u64,
+    /// The SHA-256 of the file.
+    digest: Vec<u8>,
+}
+
+impl<AST> CachingDecoder<AST>
+where
+    AST: Clone,
+{
+    /// Create a decoder keeping up to `capacity` ASTs in memory.
+    pub fn new(capacity: usize) -> Self {
+        CachingDecoder {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            recency: BTreeMap::new(),
+            clock: 0,
+            metrics: None,
+        }
+    }
+
+    /// Report cache hits and misses to `sink`.
+    pub fn with_metrics(self, sink: Rc<dyn MetricsSink>) -> Self {
+        CachingDecoder {
+            metrics: Some(sink),
+            ..self
+        }
+    }
+
+    pub fn decode<R: Read>(
+        &mut self,
+        format: &mut binjs_io::Format,
+        mut source: R,
+    ) -> Result<AST, BinjsError>
+    where
+        Deserializer<binjs_io::simple::TreeTokenReader<Cursor<Vec<u8>>>>: Deserialization<AST>,
+        Deserializer<binjs_io::multipart::TreeTokenReader>: Deserialization<AST>,
+        Deserializer<binjs_io::binjs_json::read::Decoder<Cursor<Vec<u8>>>>: Deserialization<AST>,
+        Deserializer<binjs_io::entropy::read::Decoder>: Deserialization<AST>,
+    {
+        let mut data = vec![];
+        source.read_to_end(&mut data).map_err(BinjsError::Io)?;
+        if let binjs_io::Format::Entropy { .. } = *format {
+            return Decoder::new().decode(format, Cursor::new(data));
+        }
+        let key = CacheKey {
+            format: format.name(),
+            byte_len: data.len() as u64,
+            digest: ContainerHash::Sha256.digest(&data),
+        };
+
+        self.clock += 1;
+        let found = match self.entries.get_mut(&key) {
+            Some(&mut (ref ast, ref mut last_use)) => {
+                self.recency.remove(&*last_use);
+                *last_use = self.clock;
+                self.recency.insert(self.clock, key.clone());
+                Some(ast.clone())
+            }
+            None => None,
+        };
+        if let Some(ref sink) = self.metrics {
+            sink.record_cache_lookup(found.is_some());
+        }
+        if let Some(ast) = found {
+            return Ok(ast);
+        }
+
+        let ast: AST = Decoder::new().decode(format, Cursor::new(data))?;
+        if self.capacity > 0 {
+            if self.entries.len() >= self.capacity {
+                // Evict the least recently used AST.
+                let oldest = *self.recency.keys().next().expect("Empty cache is not full");
+                let evicted = self.recency.remove(&oldest).unwrap();
+                self.entries.remove(&evicted);
+            }
+            self.recency.insert(self.clock, key.clone());
+            self.entries.insert(key, (ast.clone(), self.clock));
+        }
+        Ok(ast)
+    }
+}
+
+/// Encode an AST in any of the formats.
+pub struct Encoder {
+    /// If specified, the destination for timing metrics.
+    metrics: Option<Rc<dyn MetricsSink>>,
+
+    /// See `Encoder::with_cancellation`.
+    cancellation: Option<CancellationToken>,
 }
-pub struct Encoder;
 impl Encoder {
     pub fn new() -> Self {
-        Encoder
+        Encoder {
+            metrics: None,
+            cancellation: None,
+        }
+    }
+
+    /// Report the time spent in each phase of encoding to `sink`.
+    ///
+    /// Phases `Tokenize` and `Write` are reported by the encoder itself,
+    /// phase `Compress` by the compression layer, while `Write` is still
+    /// ongoing.
+    pub fn with_metrics(self, sink: Rc<dyn MetricsSink>) -> Self {
+        Encoder {
+            metrics: Some(sink),
+            ..self
+        }
     }
+
+    /// Fail with `BinjsError::Cancelled` once `token` is cancelled.
+    ///
+    /// Checked once the AST has been tokenized and, for multipart files,
+    /// as tagged tuples are written and between sections (see
+    /// `multipart::Options::cancellation`).
+    pub fn with_cancellation(self, token: CancellationToken) -> Self {
+        Encoder {
+            cancellation: Some(token),
+            ..self
+        }
+    }
+
     pub fn encode<AST>(
         &self,
         path: Option<&std::path::Path>,
         format: &mut binjs_io::Format,
         ast: &AST,
-    ) -> Result<Box<[u8]>, TokenWriterError>
+    ) -> Result<Box<[u8]>, BinjsError>
     where
         Serializer<TokenWriterTreeAdapter<binjs_io::simple::TreeTokenWriter>>: Serialization<AST>,
         Serializer<TokenWriterTreeAdapter<binjs_io::multipart::TreeTokenWriter>>:
@@ -405,43 +1254,70 @@ impl Encoder {
         Serializer<binjs_io::binjs_json::write::TreeTokenWriter>: Serialization<AST>,
         Serializer<binjs_io::entropy::write::Encoder>: Serialization<AST>,
     {
-        let mut io_path = IOPath::new();
         match *format {
             binjs_io::Format::Simple { .. } => {
                 let writer = binjs_io::simple::TreeTokenWriter::new();
-                let mut serializer = Serializer::new(TokenWriterTreeAdapter::new(writer));
-                serializer.serialize(ast, &mut io_path)?;
-                serializer.done()
+                self.encode_with(TokenWriterTreeAdapter::new(writer), ast)
             }
             binjs_io::Format::Multipart {
-                ref mut targets, ..
+                ref targets,
+                ref options,
+                ref stats,
             } => {
-                let writer = binjs_io::multipart::TreeTokenWriter::new(targets.clone());
-                let mut serializer = Serializer::new(TokenWriterTreeAdapter::new(writer));
-                serializer.serialize(ast, &mut io_path)?;
-                serializer.done()
+                let mut targets = targets.clone();
+                targets.set_metrics(self.metrics.clone());
+                let mut options = options.clone();
+                options.statistics = Some(stats.clone());
+                if self.cancellation.is_some() {
+                    options.cancellation = self.cancellation.clone();
+                }
+                let writer = binjs_io::multipart::TreeTokenWriter::with_options(targets, options);
+                self.encode_with(TokenWriterTreeAdapter::new(writer), ast)
             }
 
             binjs_io::Format::XML => {
                 let writer = binjs_io::xml::Encoder::new();
-                let mut serializer = Serializer::new(TokenWriterTreeAdapter::new(writer));
-                serializer.serialize(ast, &mut io_path)?;
-                serializer.done()
+                self.encode_with(TokenWriterTreeAdapter::new(writer), ast)
             }
             binjs_io::Format::JSON => {
                 let writer = binjs_io::binjs_json::write::TreeTokenWriter::new();
-                let mut serializer = Serializer::new(writer);
-                serializer.serialize(ast, &mut io_path)?;
-                serializer.done()
+                self.encode_with(writer, ast)
             }
             binjs_io::Format::Entropy { ref options } => {
                 // FIXME: Extract strings + frequency.
                 // FIXME: Use info.
                 let writer = binjs_io::entropy::write::Encoder::new(path, (*options).clone());
-                let mut serializer = Serializer::new(writer);
-                serializer.serialize(ast, &mut io_path)?;
-                serializer.done()
+                self.encode_with(writer, ast)
             }
         }
     }
+
+    /// Serialize an AST with a specific writer, reporting metrics.
+    fn encode_with<W, AST>(&self, writer: W, ast: &AST) -> Result<Box<[u8]>, BinjsError>
+    where
+        W: TokenWriter<Data = Box<[u8]>>,
+        Serializer<W>: Serialization<AST>,
+    {
+        let mut serializer = Serializer::new(writer);
+
+        let stopwatch = Stopwatch::start(Phase::Tokenize);
+        serializer.serialize(ast, &mut IOPath::new())?;
+        self.record(stopwatch.stop(None));
+
+        if let Some(ref token) = self.cancellation {
+            token.check(BinjsError::Cancelled)?;
+        }
+
+        let stopwatch = Stopwatch::start(Phase::Write);
+        let data = serializer.done()?;
+        self.record(stopwatch.stop(Some(data.len())));
+
+        Ok(data)
+    }
+
+    fn record(&self, metrics: PhaseMetrics) {
+        if let Some(ref sink) = self.metrics {
+            sink.record(metrics);
+        }
+    }
 }
@@ -118,6 +118,7 @@ impl Visitor<EnrichError, Option<LevelGuard>> for LazifierVisitor {
                     name: stolen.name,
                     directives: stolen.directives,
                     contents_skip: Offset::default(),
+                    contents_position: None,
                     contents: stolen.contents,
                 }
                 .into()
@@ -128,6 +129,7 @@ impl Visitor<EnrichError, Option<LevelGuard>> for LazifierVisitor {
                     length: stolen.length,
                     directives: stolen.directives,
                     contents_skip: Offset::default(),
+                    contents_position: None,
                     contents: stolen.contents,
                 }
                 .into()
@@ -140,6 +142,7 @@ impl Visitor<EnrichError, Option<LevelGuard>> for LazifierVisitor {
                     length: stolen.length,
                     directives: stolen.directives,
                     contents_skip: Offset::default(),
+                    contents_position: None,
                     contents: stolen.contents,
                 }
                 .into()
@@ -175,6 +178,7 @@ impl Visitor<EnrichError, Option<LevelGuard>> for LazifierVisitor {
                         length: stolen.length,
                         directives: stolen.directives,
                         contents_skip: Offset::default(),
+                        contents_position: None,
                         contents: stolen.contents,
                     }
                     .into()
@@ -219,6 +223,7 @@ impl Visitor<EnrichError, Option<LevelGuard>> for LazifierVisitor {
                         length: stolen.length,
                         directives: stolen.directives,
                         contents_skip: Offset::default(),
+                        contents_position: None,
                         contents: stolen.contents,
                     }
                     .into()
@@ -11,8 +11,11 @@ extern crate binjs_shared;
 extern crate assert_matches;
 extern crate clap;
 extern crate itertools;
+#[cfg(feature = "parallel")]
+extern crate rayon;
 #[macro_use]
 extern crate serde;
+extern crate serde_json;
 #[macro_use]
 extern crate log;
 
@@ -22,6 +25,9 @@ pub mod ast;
 /// Serialization/deserialization utilities.
 pub mod io;
 
+/// Collecting non-fatal observations on an AST, e.g. use of `eval`.
+pub mod diagnostics;
+
 /// Errors encountered during a call to `Enrich`.
 ///
 /// These errors generally mean that we have encountered a fragment of JavaScript
@@ -29,6 +35,13 @@ pub mod io;
 #[derive(Debug)]
 pub enum EnrichError {
     ScopeError(scopes::ScopeError),
+
+    /// The enrichments would introduce a construct, e.g. an interface, which
+    /// is not part of the target grammar (see `Enrich::target_grammar`).
+    NotInTargetGrammar {
+        construct: &'static str,
+        target: TargetGrammar,
+    },
 }
 impl From<scopes::ScopeError> for EnrichError {
     fn from(err: scopes::ScopeError) -> Self {
@@ -36,6 +49,40 @@ impl From<scopes::ScopeError> for EnrichError {
     }
 }
 
+/// A version of the grammar, for decoders which do not support the most
+/// recent one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TargetGrammar {
+    /// `spec/es6.webidl`, without the extensions of `spec/es6-extension.webidl`,
+    /// nor BigInt literals, which were added to `spec/es6.webidl` later.
+    Es6,
+
+    /// `spec/es6.webidl`, along with the extensions of `spec/es6-extension.webidl`.
+    Es6Extension,
+}
+impl Default for TargetGrammar {
+    fn default() -> Self {
+        TargetGrammar::Es6Extension
+    }
+}
+impl TargetGrammar {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TargetGrammar::Es6 => "es6",
+            TargetGrammar::Es6Extension => "es6-extension",
+        }
+    }
+
+    pub fn parse(name: Option<&str>) -> Option<Self> {
+        match name {
+            None => Some(Self::default()),
+            Some("es6") => Some(TargetGrammar::Es6),
+            Some("es6-extension") => Some(TargetGrammar::Es6Extension),
+            _ => None,
+        }
+    }
+}
+
 /// A mechanism used to enrich an AST obtained from parsing with additional information
 /// and/or domain-specific rewrites.
 ///
@@ -51,6 +98,26 @@ pub struct Enrich {
     ///
     /// Default: `None` (deactivated), as this is an optional, WIP, optimization.
     pub pure_data_threshold: Option<usize>,
+
+    /// If `true`, remove `debugger;` statements.
+    ///
+    /// Default: `false`, as this changes the behavior of the code.
+    pub strip_debugger: bool,
+
+    /// If `true`, remove statements `console.foo(...);`, i.e. calls
+    /// to `console` whose result isn't used.
+    ///
+    /// Default: `false`, as this changes the behavior of the code.
+    pub strip_console: bool,
+
+    /// The grammar of the enriched AST.
+    ///
+    /// Constructs of more recent grammars are desugared if possible, e.g.
+    /// scoped dictionaries, which are replaced by their expression, or
+    /// rejected with `EnrichError::NotInTargetGrammar`.
+    ///
+    /// Default: the most recent grammar.
+    pub target_grammar: TargetGrammar,
 }
 const_with_str! {
     const DEFAULT_LAZY_THRESHOLD: u32 = 0;
@@ -63,6 +130,9 @@ impl Default for Enrich {
         Enrich {
             lazy_threshold: DEFAULT_LAZY_THRESHOLD,
             pure_data_threshold: DEFAULT_PURE_DATA_THRESHOLD,
+            strip_debugger: false,
+            strip_console: false,
+            target_grammar: TargetGrammar::default(),
         }
     }
 }
@@ -83,6 +153,19 @@ impl Enrich {
                 .takes_value(true)
                 .help("Rewrite the AST to introduce scoped dictionary changes around pure data fragments with size >= SIZE")
                 .default_value(defaults_as_strings::DEFAULT_PURE_DATA_THRESHOLD),
+            Arg::with_name("strip-debugger")
+                .long("strip-debugger")
+                .help("Remove `debugger;` statements. This changes the behavior of the code."),
+            Arg::with_name("strip-console")
+                .long("strip-console")
+                .help("Remove calls to `console.*` whose result isn't used. This changes the behavior of the code."),
+            Arg::with_name("target-grammar")
+                .long("target-grammar")
+                .takes_value(true)
+                .value_name("VERSION")
+                .possible_values(&["es6", "es6-extension"])
+                .help("Only use constructs of grammar VERSION, for decoders which do not support more recent grammars.")
+                .default_value(TargetGrammar::default().name()),
         ]
     }
 
@@ -100,11 +183,24 @@ impl Enrich {
         Enrich {
             lazy_threshold,
             pure_data_threshold,
+            strip_debugger: matches.is_present("strip-debugger"),
+            strip_console: matches.is_present("strip-console"),
+            target_grammar: TargetGrammar::parse(matches.value_of("target-grammar"))
+                .expect("Could not parse target-grammar"),
         }
     }
 
     /// Perform enrichments.
     pub fn enrich(&self, script: &mut ast::Script) -> Result<(), EnrichError> {
+        if self.target_grammar == TargetGrammar::Es6 && self.pure_data_threshold.is_some() {
+            return Err(EnrichError::NotInTargetGrammar {
+                construct: "BinASTExpressionWithProbabilityTable",
+                target: self.target_grammar,
+            });
+        }
+        if self.strip_debugger || self.strip_console {
+            strip::StripVisitor::rewrite_script(self.strip_debugger, self.strip_console, script)?;
+        }
         if self.lazy_threshold > 0 {
             let mut visitor = lazy::LazifierVisitor::new(self.lazy_threshold);
             visitor.annotate_script(script)?;
@@ -116,6 +212,11 @@ impl Enrich {
         if let Some(threshold) = self.pure_data_threshold {
             sublanguages::InjectVisitor::rewrite_script(threshold, script)?;
         }
+        if self.target_grammar == TargetGrammar::Es6 {
+            // The AST may come from a file of a more recent grammar.
+            sublanguages::CleanupVisitor::rewrite_script(script);
+            target::TargetGrammarVisitor::check_script(self.target_grammar, script)?;
+        }
         Ok(())
     }
 }
@@ -152,3 +253,9 @@ mod lazy;
 
 /// Rewriting language fragments.
 mod sublanguages;
+
+/// Removing debugging constructs.
+mod strip;
+
+/// Checking that an AST belongs to the target grammar.
+mod target;
@@ -1,5 +1,5 @@
 use ast::*;
-use binjs_shared::{IdentifierName, VisitMe};
+use binjs_shared::{IdentifierName, SharedString, VisitMe};
 
 use std::collections::{HashMap, HashSet};
 
@@ -19,6 +19,10 @@ pub enum ScopeError {
         const_lexical: bool,
         name: IdentifierName,
     },
+
+    /// A variable declaration uses a kind unknown to the grammar, e.g. decoded
+    /// with `Deserializer::with_lenient_enums`.
+    UnknownVariableDeclarationKind(SharedString),
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -714,6 +718,17 @@ fn maybe_exit_destructuring_parameter(visitor: &mut AnnotationVisitor, path: &Wa
     visitor.binding_kind_stack.pop();
 }
 
+fn binding_kind(kind: &VariableDeclarationKind) -> Result<BindingKind, ScopeError> {
+    match *kind {
+        VariableDeclarationKind::Let => Ok(BindingKind::NonConstLexical),
+        VariableDeclarationKind::Const => Ok(BindingKind::ConstLexical),
+        VariableDeclarationKind::Var => Ok(BindingKind::Var),
+        VariableDeclarationKind::Unknown(ref kind) => {
+            Err(ScopeError::UnknownVariableDeclarationKind(kind.clone()))
+        }
+    }
+}
+
 impl Visitor<ScopeError> for AnnotationVisitor {
     // Identifiers
 
@@ -957,11 +972,7 @@ impl Visitor<ScopeError> for AnnotationVisitor {
         _path: &WalkPath,
         node: &mut ForInOfBinding,
     ) -> EnterResult {
-        let kind = match node.kind {
-            VariableDeclarationKind::Let => BindingKind::NonConstLexical,
-            VariableDeclarationKind::Const => BindingKind::ConstLexical,
-            VariableDeclarationKind::Var => BindingKind::Var,
-        };
+        let kind = binding_kind(&node.kind)?;
         self.binding_kind_stack.push(kind);
         Ok(VisitMe::HoldThis(()))
     }
@@ -970,11 +981,7 @@ impl Visitor<ScopeError> for AnnotationVisitor {
         _path: &WalkPath,
         node: &mut ForInOfBinding,
     ) -> ExitResult<ForInOfBinding> {
-        let kind = match node.kind {
-            VariableDeclarationKind::Let => BindingKind::NonConstLexical,
-            VariableDeclarationKind::Const => BindingKind::ConstLexical,
-            VariableDeclarationKind::Var => BindingKind::Var,
-        };
+        let kind = binding_kind(&node.kind)?;
         assert_eq!(self.binding_kind_stack.pop().unwrap(), kind);
         Ok(None)
     }
@@ -984,11 +991,7 @@ impl Visitor<ScopeError> for AnnotationVisitor {
         _path: &WalkPath,
         node: &mut VariableDeclaration,
     ) -> EnterResult {
-        let kind = match node.kind {
-            VariableDeclarationKind::Let => BindingKind::NonConstLexical,
-            VariableDeclarationKind::Const => BindingKind::ConstLexical,
-            VariableDeclarationKind::Var => BindingKind::Var,
-        };
+        let kind = binding_kind(&node.kind)?;
         self.binding_kind_stack.push(kind);
         Ok(VisitMe::HoldThis(()))
     }
@@ -997,11 +1000,7 @@ impl Visitor<ScopeError> for AnnotationVisitor {
         _path: &WalkPath,
         node: &mut VariableDeclaration,
     ) -> ExitResult<VariableDeclaration> {
-        let kind = match node.kind {
-            VariableDeclarationKind::Let => BindingKind::NonConstLexical,
-            VariableDeclarationKind::Const => BindingKind::ConstLexical,
-            VariableDeclarationKind::Var => BindingKind::Var,
-        };
+        let kind = binding_kind(&node.kind)?;
         assert_eq!(self.binding_kind_stack.pop().unwrap(), kind);
         Ok(None)
     }
@@ -0,0 +1,144 @@
+//! Removing debugging constructs (`debugger;`, `console.*(...)`) from an AST.
+//!
+//! This changes the behavior of the code, so it is only performed on request,
+//! typically for production builds.
+
+use ast::*;
+use binjs_shared::VisitMe;
+use EnrichError;
+
+type EnterResult = Result<VisitMe<()>, EnrichError>;
+type ExitResult<T> = Result<Option<T>, EnrichError>;
+
+/// Return `true` if `expression` is a call `console.foo(...)`.
+///
+/// Note that the arguments are removed along with the call, even if they
+/// have side-effects.
+fn is_console_call(expression: &Expression) -> bool {
+    if let Expression::CallExpression(ref call) = *expression {
+        if let ExpressionOrSuper::StaticMemberExpression(ref member) = call.callee {
+            if let ExpressionOrSuper::IdentifierExpression(ref id) = member.object {
+                return id.name == "console";
+            }
+        }
+    }
+    false
+}
+
+pub struct StripVisitor {
+    /// If `true`, remove `debugger;` statements.
+    debugger: bool,
+
+    /// If `true`, remove statements `console.foo(...);`.
+    ///
+    /// Calls whose result is used, e.g. `const x = console.log(1)`, are kept.
+    console: bool,
+}
+impl StripVisitor {
+    pub fn rewrite_script(
+        debugger: bool,
+        console: bool,
+        script: &mut Script,
+    ) -> Result<(), EnrichError> {
+        let mut visitor = StripVisitor { debugger, console };
+        script.walk(&mut WalkPath::new(), &mut visitor)?;
+        Ok(())
+    }
+
+    fn is_stripped(&self, statement: &Statement) -> bool {
+        match *statement {
+            Statement::DebuggerStatement(_) => self.debugger,
+            Statement::ExpressionStatement(ref statement) => {
+                self.console && is_console_call(&statement.expression)
+            }
+            _ => false,
+        }
+    }
+
+    /// Remove stripped statements from a list, before visiting it.
+    fn strip(&self, statements: &mut Vec<Statement>) -> EnterResult {
+        statements.retain(|statement| !self.is_stripped(statement));
+        Ok(VisitMe::HoldThis(()))
+    }
+}
+
+impl Visitor<EnrichError> for StripVisitor {
+    // Statements in lists are removed.
+
+    fn enter_script(&mut self, _path: &WalkPath, node: &mut Script) -> EnterResult {
+        self.strip(&mut node.statements)
+    }
+
+    fn enter_block(&mut self, _path: &WalkPath, node: &mut Block) -> EnterResult {
+        self.strip(&mut node.statements)
+    }
+
+    fn enter_switch_case(&mut self, _path: &WalkPath, node: &mut SwitchCase) -> EnterResult {
+        self.strip(&mut node.consequent)
+    }
+
+    fn enter_switch_default(&mut self, _path: &WalkPath, node: &mut SwitchDefault) -> EnterResult {
+        self.strip(&mut node.consequent)
+    }
+
+    fn enter_function_or_method_contents(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut FunctionOrMethodContents,
+    ) -> EnterResult {
+        self.strip(&mut node.body)
+    }
+
+    fn enter_function_expression_contents(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut FunctionExpressionContents,
+    ) -> EnterResult {
+        self.strip(&mut node.body)
+    }
+
+    fn enter_getter_contents(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut GetterContents,
+    ) -> EnterResult {
+        self.strip(&mut node.body)
+    }
+
+    fn enter_setter_contents(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut SetterContents,
+    ) -> EnterResult {
+        self.strip(&mut node.body)
+    }
+
+    fn enter_arrow_expression_contents_with_function_body(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut ArrowExpressionContentsWithFunctionBody,
+    ) -> EnterResult {
+        self.strip(&mut node.body)
+    }
+
+    // Other statements, e.g. `if (x) debugger;`, are replaced with `;`.
+
+    fn exit_statement(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut ViewMutStatement,
+    ) -> ExitResult<Statement> {
+        let stripped = match *node {
+            ViewMutStatement::DebuggerStatement(_) => self.debugger,
+            ViewMutStatement::ExpressionStatement(ref statement) => {
+                self.console && is_console_call(&statement.expression)
+            }
+            _ => false,
+        };
+        if stripped {
+            Ok(Some(EmptyStatement {}.into()))
+        } else {
+            Ok(None)
+        }
+    }
+}
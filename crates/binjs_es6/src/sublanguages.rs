@@ -269,6 +269,7 @@ impl Visitor<EnrichError, PropertiesGuard> for InjectVisitor {
             | ViewMutExpression::ObjectExpression(_)
             | ViewMutExpression::LiteralStringExpression(_)
             | ViewMutExpression::LiteralNullExpression(_)
+            | ViewMutExpression::LiteralBigIntExpression(_)
             | ViewMutExpression::LiteralBooleanExpression(_)
             | ViewMutExpression::ArrayExpression(_) =>
             // For the moment, this is is pure data
@@ -0,0 +1,39 @@
+//! Checking that an AST only uses constructs of a target grammar.
+
+use ast::*;
+use binjs_shared::VisitMe;
+use {EnrichError, TargetGrammar};
+
+type EnterResult = Result<VisitMe<()>, EnrichError>;
+
+/// Reject the constructs of `spec/es6.webidl` which plain es6 decoders do
+/// not know, i.e. BigInt literals, when targeting `TargetGrammar::Es6`.
+///
+/// As BigInt literals have no equivalent in plain es6, they cannot be
+/// desugared.
+pub struct TargetGrammarVisitor {
+    target: TargetGrammar,
+}
+impl TargetGrammarVisitor {
+    pub fn check_script(target: TargetGrammar, script: &mut Script) -> Result<(), EnrichError> {
+        let mut visitor = TargetGrammarVisitor { target };
+        script.walk(&mut WalkPath::new(), &mut visitor)?;
+        Ok(())
+    }
+}
+
+impl Visitor<EnrichError> for TargetGrammarVisitor {
+    fn enter_literal_big_int_expression(
+        &mut self,
+        _path: &WalkPath,
+        _node: &mut LiteralBigIntExpression,
+    ) -> EnterResult {
+        match self.target {
+            TargetGrammar::Es6 => Err(EnrichError::NotInTargetGrammar {
+                construct: "LiteralBigIntExpression",
+                target: self.target,
+            }),
+            TargetGrammar::Es6Extension => Ok(VisitMe::HoldThis(())),
+        }
+    }
+}
@@ -113,7 +113,7 @@ impl RustExporter {
         let mut ast_buffer = String::new();
         ast_buffer.push_str("
 use binjs_shared::{ FieldName, IdentifierName, InterfaceName, Offset, PropertyKey, SharedString, VisitMe };
-use binjs_io::{ Deserialization, InnerDeserialization, Serialization, TokenReader, TokenReaderError, TokenWriter, TokenWriterError };
+use binjs_io::{ DecodeWarning, Deserialization, InnerDeserialization, Serialization, TokenReader, TokenReaderError, TokenWriter, TokenWriterError };
 
 use io::*;
 
@@ -214,7 +214,11 @@ impl<'de> serde::Deserialize<'de> for TypeKey {
 /// Implementation of string enum {name}
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub enum {rust_name} {{
-{values}
+{values},
+
+    /// A value unknown to this version of the grammar, e.g. written by a more
+    /// recent encoder. Only read by deserializers `with_lenient_enums(true)`.
+    Unknown(SharedString)
 }}
 ",
                     name = name,
@@ -255,6 +259,13 @@ impl<R> Deserializer<R> where R: TokenReader {{
         let key = self.reader.string_enum_at(path)?;
         match key.as_str() {{
 {variants}
+            _ if self.has_lenient_enums() => {{
+                self.add_warning(DecodeWarning::UnknownEnumValue {{
+                    name: \"{name}\".to_string(),
+                    value: key.to_string(),
+                }});
+                Ok({name}::Unknown(key.clone()))
+            }}
             _ => Err(From::from(TokenReaderError::invalid_value(&\"{lowercase_name}\"))),
         }}
     }}
@@ -291,7 +302,7 @@ impl<W> Serialization<{name}> for Serializer<W> where W: TokenWriter {{
     fn serialize(&mut self, value: &{name}, path: &mut IOPath) -> Result<(), TokenWriterError> {{
         debug!(target: \"serialize_es6\", \"Serializing string enum {name}\");
         let str = match *value {{
-{variants}
+{variants}            {name}::Unknown(ref value) => return self.writer.string_enum_at(value, path),
         }};
         self.writer.string_enum_at(&SharedString::from_str(str), path)
     }}
@@ -1040,6 +1051,13 @@ impl<'a> Walker<'a> for ViewMut{name}<'a> {{
                     .collect();
                 let field_specs_map: HashMap<_, _> =
                     field_specs.iter().map(|(a, b)| (a.clone(), b)).collect();
+                let lazy_fields: HashSet<_> = interface
+                    .contents()
+                    .fields()
+                    .iter()
+                    .filter(|field| field.is_lazy())
+                    .map(|field| field.name())
+                    .collect();
                 let definition = format!(
                     "
 /// Implementation of interface {spec_name}.
@@ -1063,7 +1081,20 @@ impl binjs_shared::Node for {rust_name} {{
                         .iter()
                         .map(|(field_name, spec)| format!(
                             "    #[serde(rename = \"{spec_name}\")]
-    pub {rust_name}: {contents}",
+    pub {rust_name}: {contents}{position}",
+                            position = if lazy_fields.contains(field_name) {
+                                Cow::from(format!(
+                                    ",
+
+    /// The position of the contents of `{rust_name}` in the tree, if left
+    /// undecoded (see `Deserializer::with_deferred_lazy_fields`).
+    #[serde(skip)]
+    pub {rust_name}_position: Option<u64>",
+                                    rust_name = field_name.to_rust_identifier_case()
+                                ))
+                            } else {
+                                Cow::from("")
+                            },
                             rust_name = field_name.to_rust_identifier_case(),
                             spec_name = field_name.to_str(),
                             contents =
@@ -1182,14 +1213,36 @@ impl<R> Deserialization<Option<{rust_name}>> for Deserializer<R> where R: TokenR
         print_file_structure!(self.reader, \".{field_name}\");
         let path_field = ({index}, FieldName::from_str(\"{field_name}\")); // String is shared
         path.enter_field(path_field.clone());
-        let data_{rust_field_name} = self.deserialize(path) as Result<{spec}, TokenReaderError>;
+{deserialize_field}
         path.exit_field(path_field);
         let data_{rust_field_name} = data_{rust_field_name}?;
 {maybe_enter_scoped_dictionary}
 ",
                             rust_field_name = field.name().to_rust_identifier_case(),
                             field_name = field.name().to_str(),
-                            spec = field_specs_map.get(field.name()).unwrap(),
+                            deserialize_field = if field.is_lazy() {
+                                // The `*_skip` field has been read just before this one.
+                                // If requested, skip the contents and store their position
+                                // in the `*_position` field, for use by `deserialize_lazy`.
+                                Cow::from(format!("
+        let lazy_position = if self.defers_lazy_fields() {{
+            self.reader.skip_lazy_at(data_{rust_field_name}_skip.0, path)
+        }} else {{
+            Ok(None)
+        }};
+        let (data_{rust_field_name}_position, data_{rust_field_name}) = match lazy_position {{
+            Ok(Some(position)) => (Some(position), Ok(Default::default())),
+            Ok(None) => (None, self.deserialize(path) as Result<{spec}, TokenReaderError>),
+            Err(err) => (None, Err(err)),
+        }};",
+                                    rust_field_name = field.name().to_rust_identifier_case(),
+                                    spec = field_specs_map.get(field.name()).unwrap()))
+                            } else {
+                                Cow::from(format!("
+        let data_{rust_field_name} = self.deserialize(path) as Result<{spec}, TokenReaderError>;",
+                                    rust_field_name = field.name().to_rust_identifier_case(),
+                                    spec = field_specs_map.get(field.name()).unwrap()))
+                            },
                             index = index,
                             maybe_enter_scoped_dictionary =
                                 loop { // Fake loop, to be able to break from any point.
@@ -1209,8 +1262,14 @@ impl<R> Deserialization<Option<{rust_name}>> for Deserializer<R> where R: TokenR
                     fields_use = interface.contents()
                         .fields()
                         .iter()
-                        .map(|field| format!("            {name}: data_{name},",
-                            name = field.name().to_rust_identifier_case()))
+                        .map(|field| if field.is_lazy() {
+                            format!("            {name}: data_{name},
+            {name}_position: data_{name}_position,",
+                                name = field.name().to_rust_identifier_case())
+                        } else {
+                            format!("            {name}: data_{name},",
+                                name = field.name().to_rust_identifier_case())
+                        })
                         .format("\n"),
                     maybe_exit_scoped_dictionary =
                         if let Some(field_name) = interface.scoped_dictionary() {
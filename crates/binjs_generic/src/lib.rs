@@ -12,5 +12,11 @@ pub mod es6;
 /// Generating random ASTs (for fuzzing purposes).
 pub mod pick;
 
+/// Exporting the grammar as a JSON schema.
+pub mod schema;
+
+/// Checking ASTs against the grammar (e.g. after decoding).
+pub mod validate;
+
 /// Walkers, comparisons.
 pub mod util;
@@ -0,0 +1,206 @@
+//! Exporting the grammar as a machine-readable (JSON) schema.
+//!
+//! The schema is built from the same `Spec` as the one used by encoders and
+//! decoders, so it describes exactly the node kinds, fields and types they
+//! accept. Its format is:
+//!
+//! ```json
+//! {
+//!   "root": "Script",
+//!   "interfaces": {
+//!     "Script": {
+//!       "fields": [ { "name": "directives", "type": TYPE, "lazy": false }, ... ],
+//!       "scope": false
+//!     },
+//!     ...
+//!   },
+//!   "string_enums": { "VariableDeclarationKind": ["var", "let", "const"], ... },
+//!   "typedefs": { "Statement": TYPE, ... }
+//! }
+//! ```
+//!
+//! where each `TYPE` is an object `{ "kind": ..., "optional": true|false }`,
+//! with `kind` one of `"array"` (with fields `contents` and `supports_empty`),
+//! `"named"` (with field `name`), `"sum"` (with field `types`, whose items
+//! have no `optional` field) or one of the primitive kinds `"boolean"`,
+//! `"string"`, `"number"`, `"unsigned_long"`, `"offset"`, `"void"`,
+//! `"identifier_name"`, `"property_key"`.
+
+use binjs_meta::spec::*;
+use validate::ValidationError;
+
+use serde_json::{Map, Value as JSON};
+
+fn reject(path: &str, message: String) -> ValidationError {
+    ValidationError {
+        path: path.to_string(),
+        message,
+    }
+}
+
+pub struct Schema;
+impl Schema {
+    /// Export `syntax` as a JSON schema.
+    pub fn to_json(syntax: &Spec) -> JSON {
+        let mut interfaces = Map::new();
+        for (name, interface) in syntax.interfaces_by_name() {
+            let fields = interface
+                .contents()
+                .fields()
+                .iter()
+                .map(|field| {
+                    let mut result = Map::new();
+                    result.insert(
+                        "name".to_string(),
+                        JSON::from(field.name().to_str().to_string()),
+                    );
+                    result.insert("type".to_string(), Self::type_(field.type_()));
+                    result.insert("lazy".to_string(), JSON::from(field.is_lazy()));
+                    JSON::Object(result)
+                })
+                .collect::<Vec<_>>();
+            let mut result = Map::new();
+            result.insert("fields".to_string(), JSON::Array(fields));
+            result.insert("scope".to_string(), JSON::from(interface.is_scope()));
+            interfaces.insert(name.to_string().clone(), JSON::Object(result));
+        }
+
+        let mut string_enums = Map::new();
+        for (name, string_enum) in syntax.string_enums_by_name() {
+            string_enums.insert(
+                name.to_string().clone(),
+                JSON::from(string_enum.strings().to_vec()),
+            );
+        }
+
+        let mut typedefs = Map::new();
+        for (name, typedef) in syntax.typedefs_by_name() {
+            typedefs.insert(name.to_string().clone(), Self::type_(typedef));
+        }
+
+        let mut result = Map::new();
+        result.insert(
+            "root".to_string(),
+            JSON::from(syntax.get_root_name().to_string().clone()),
+        );
+        result.insert("interfaces".to_string(), JSON::Object(interfaces));
+        result.insert("string_enums".to_string(), JSON::Object(string_enums));
+        result.insert("typedefs".to_string(), JSON::Object(typedefs));
+        JSON::Object(result)
+    }
+
+    /// Check that an AST in JSON format only uses node kinds and fields
+    /// present in `schema`, e.g. one exported by an older version.
+    ///
+    /// Unlike `Validator::validate`, this does not stop at the first error,
+    /// but reports all the offending nodes.
+    pub fn check(schema: &JSON, value: &JSON) -> Result<(), Vec<ValidationError>> {
+        let interfaces = schema
+            .get("interfaces")
+            .and_then(JSON::as_object)
+            .ok_or_else(|| vec![reject("", "Invalid schema: no interfaces".to_string())])?;
+        let mut errors = vec![];
+        Self::check_value(interfaces, value, &mut String::new(), &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn check_value(
+        interfaces: &Map<String, JSON>,
+        value: &JSON,
+        path: &mut String,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        let len = path.len();
+        match *value {
+            JSON::Array(ref items) => {
+                for (i, item) in items.iter().enumerate() {
+                    path.push_str(&format!("[{}]", i));
+                    Self::check_value(interfaces, item, path, errors);
+                    path.truncate(len);
+                }
+            }
+            JSON::Object(ref obj) => {
+                let name = obj.get("type").and_then(JSON::as_str).unwrap_or("");
+                if path.is_empty() {
+                    path.push_str(name);
+                }
+                // If the node kind is unknown, its fields are not checked,
+                // but its children still are.
+                let fields = match interfaces.get(name) {
+                    Some(interface) => interface.get("fields").and_then(JSON::as_array),
+                    None => {
+                        errors.push(reject(path, format!("Unknown node kind {}", name)));
+                        None
+                    }
+                };
+                let len = path.len();
+                for (field_name, field_value) in obj {
+                    if field_name == "type" {
+                        continue;
+                    }
+                    path.push('.');
+                    path.push_str(field_name);
+                    if let Some(fields) = fields {
+                        if !fields.iter().any(|field| {
+                            field.get("name") == Some(&JSON::from(field_name.as_str()))
+                        }) {
+                            errors.push(reject(
+                                path,
+                                format!("Unknown field {} of {}", field_name, name),
+                            ));
+                        }
+                    }
+                    Self::check_value(interfaces, field_value, path, errors);
+                    path.truncate(len);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn type_(type_: &Type) -> JSON {
+        let mut result = match Self::type_spec(type_.spec()) {
+            JSON::Object(result) => result,
+            _ => unreachable!(),
+        };
+        result.insert("optional".to_string(), JSON::from(type_.is_optional()));
+        JSON::Object(result)
+    }
+
+    fn type_spec(spec: &TypeSpec) -> JSON {
+        let mut result = Map::new();
+        let kind = match *spec {
+            TypeSpec::Array {
+                ref contents,
+                supports_empty,
+            } => {
+                result.insert("contents".to_string(), Self::type_(contents));
+                result.insert("supports_empty".to_string(), JSON::from(supports_empty));
+                "array"
+            }
+            TypeSpec::NamedType(ref name) => {
+                result.insert("name".to_string(), JSON::from(name.to_string().clone()));
+                "named"
+            }
+            TypeSpec::TypeSum(ref sum) => {
+                let types = sum.types().iter().map(Self::type_spec).collect::<Vec<_>>();
+                result.insert("types".to_string(), JSON::Array(types));
+                "sum"
+            }
+            TypeSpec::Boolean => "boolean",
+            TypeSpec::String => "string",
+            TypeSpec::Number => "number",
+            TypeSpec::UnsignedLong => "unsigned_long",
+            TypeSpec::Offset => "offset",
+            TypeSpec::Void => "void",
+            TypeSpec::IdentifierName => "identifier_name",
+            TypeSpec::PropertyKey => "property_key",
+        };
+        result.insert("kind".to_string(), JSON::from(kind));
+        JSON::Object(result)
+    }
+}
@@ -0,0 +1,199 @@
+//! Checking that an AST in generic (i.e. JSON) format matches the grammar.
+//!
+//! Decoders check that each token is well-formed, but a file may still
+//! decode to a tree that the grammar does not allow, e.g. an empty
+//! `[NonEmpty]` list or a `null` in place of a required node.
+
+use binjs_meta::spec::*;
+
+use serde_json::Value as JSON;
+
+use std::fmt;
+
+/// An error encountered while validating an AST.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationError {
+    /// The path to the offending node, e.g.
+    /// `Script.statements[0].declarators`.
+    pub path: String,
+
+    /// What is wrong with the node.
+    pub message: String,
+}
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "At {}: {}", self.path, self.message)
+    }
+}
+
+pub trait Validate {
+    /// Check that `value` matches `self`.
+    ///
+    /// `path` is the path to `value`. It is extended while walking and
+    /// restored before returning `Ok`.
+    fn validate(
+        &self,
+        syntax: &Spec,
+        value: &JSON,
+        path: &mut String,
+    ) -> Result<(), ValidationError>;
+}
+
+fn reject(path: &str, message: String) -> ValidationError {
+    ValidationError {
+        path: path.to_string(),
+        message,
+    }
+}
+
+pub struct Validator;
+impl Validator {
+    /// Check that `value` is a valid AST, starting from the root of the grammar.
+    pub fn validate(syntax: &Spec, value: &JSON) -> Result<(), ValidationError> {
+        let mut path = String::new();
+        syntax.get_root().validate(syntax, value, &mut path)
+    }
+}
+
+impl Validate for NamedType {
+    fn validate(
+        &self,
+        syntax: &Spec,
+        value: &JSON,
+        path: &mut String,
+    ) -> Result<(), ValidationError> {
+        match *self {
+            NamedType::Interface(ref interface) => interface.validate(syntax, value, path),
+            NamedType::Typedef(ref typedef) => typedef.validate(syntax, value, path),
+            NamedType::StringEnum(ref string_enum) => match value.as_str() {
+                Some(string) if string_enum.strings().iter().any(|s| s == string) => Ok(()),
+                _ => Err(reject(
+                    path,
+                    format!(
+                        "Expected one of {:?}, found {}",
+                        string_enum.strings(),
+                        value
+                    ),
+                )),
+            },
+        }
+    }
+}
+
+impl Validate for TypeSpec {
+    fn validate(
+        &self,
+        syntax: &Spec,
+        value: &JSON,
+        path: &mut String,
+    ) -> Result<(), ValidationError> {
+        match *self {
+            TypeSpec::Array {
+                supports_empty,
+                contents: ref type_,
+            } => {
+                let items = value
+                    .as_array()
+                    .ok_or_else(|| reject(path, format!("Expected a list, found {}", value)))?;
+                if !supports_empty && items.is_empty() {
+                    return Err(reject(path, "Expected a non-empty list".to_string()));
+                }
+                let len = path.len();
+                for (i, item) in items.iter().enumerate() {
+                    path.push_str(&format!("[{}]", i));
+                    type_.validate(syntax, item, path)?;
+                    path.truncate(len);
+                }
+                Ok(())
+            }
+            TypeSpec::NamedType(ref name) => match syntax.get_type_by_name(name) {
+                Some(named) => named.validate(syntax, value, path),
+                None => Err(reject(
+                    path,
+                    format!("Could not find named type {:?}", name),
+                )),
+            },
+            TypeSpec::TypeSum(ref sum) => {
+                let interface = value
+                    .get("type")
+                    .and_then(JSON::as_str)
+                    .and_then(|name| syntax.get_node_name(name))
+                    .and_then(|name| sum.get_interface(syntax, name));
+                match interface {
+                    Some(interface) => interface.validate(syntax, value, path),
+                    None => Err(reject(
+                        path,
+                        format!("Expected one of {:?}, found {}", sum.interfaces(), value),
+                    )),
+                }
+            }
+            TypeSpec::Boolean if value.is_boolean() => Ok(()),
+            TypeSpec::String | TypeSpec::PropertyKey | TypeSpec::IdentifierName
+                if value.is_string() =>
+            {
+                Ok(())
+            }
+            TypeSpec::Number if value.is_number() => Ok(()),
+            TypeSpec::Offset | TypeSpec::UnsignedLong if value.is_u64() => Ok(()),
+            TypeSpec::Void if value.is_null() => Ok(()),
+            _ => Err(reject(
+                path,
+                format!("Expected {:?}, found {}", self, value),
+            )),
+        }
+    }
+}
+
+impl Validate for Type {
+    fn validate(
+        &self,
+        syntax: &Spec,
+        value: &JSON,
+        path: &mut String,
+    ) -> Result<(), ValidationError> {
+        if self.is_optional() && value.is_null() {
+            return Ok(());
+        }
+        self.spec().validate(syntax, value, path)
+    }
+}
+
+impl Validate for Interface {
+    fn validate(
+        &self,
+        syntax: &Spec,
+        value: &JSON,
+        path: &mut String,
+    ) -> Result<(), ValidationError> {
+        let name = self.name().to_str();
+        if path.is_empty() {
+            path.push_str(name);
+        }
+        let obj = value.as_object().ok_or_else(|| {
+            reject(
+                path,
+                format!("Expected an instance of {}, found {}", name, value),
+            )
+        })?;
+        if let Some(found) = obj.get("type") {
+            if found.as_str() != Some(name) {
+                return Err(reject(
+                    path,
+                    format!("Expected an instance of {}, found {}", name, found),
+                ));
+            }
+        }
+        let len = path.len();
+        for field in self.contents().fields() {
+            let field_name = field.name().to_str();
+            path.push('.');
+            path.push_str(field_name);
+            match obj.get(field_name) {
+                Some(field_value) => field.type_().validate(syntax, field_value, path)?,
+                None => return Err(reject(path, "Missing field".to_string())),
+            }
+            path.truncate(len);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+//! Reading/writing bit-level data, e.g. packed booleans.
+//!
+//! Bits are written most significant first, and fill each byte from its most
+//! significant bit. Bytes, e.g. those of a varnum written through `Write`,
+//! are written at the current bit position, unless the writer is aligned
+//! first with `align`, in which case they are written as is.
+
+use std;
+use std::io::{Read, Write};
+
+/// The maximal number of bits read or written at once.
+pub const MAX_BITS: u32 = 32;
+
+fn check_bits(n: u32) -> Result<(), std::io::Error> {
+    if n > MAX_BITS {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Cannot handle more than {} bits at once, got {}",
+                MAX_BITS, n
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// A writer of bits, writing each byte to its sink once complete.
+///
+/// Bytes written through `Write`, e.g. with `WriteVarNum::write_varnum`,
+/// start at the current bit position. The last byte is only complete once
+/// padded with zeros by `align` or `done`.
+pub struct BitWriter<W: Write> {
+    sink: W,
+
+    /// The bits written since the last complete byte, in the lowest bits.
+    pending: u8,
+
+    /// The number of bits in `pending`, always less than 8.
+    pending_len: u32,
+}
+impl<W: Write> BitWriter<W> {
+    pub fn new(sink: W) -> Self {
+        BitWriter {
+            sink,
+            pending: 0,
+            pending_len: 0,
+        }
+    }
+
+    /// Write the `n` lowest bits of `value`, most significant first.
+    ///
+    /// Fails if `n` exceeds `MAX_BITS` or `value` does not fit in `n` bits.
+    pub fn write_bits(&mut self, value: u32, n: u32) -> Result<(), std::io::Error> {
+        check_bits(n)?;
+        if n < MAX_BITS && value >> n != 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Value {} does not fit in {} bits", value, n),
+            ));
+        }
+        for i in (0..n).rev() {
+            self.pending = (self.pending << 1) | ((value >> i) & 1) as u8;
+            self.pending_len += 1;
+            if self.pending_len == 8 {
+                self.sink.write_all(&[self.pending])?;
+                self.pending = 0;
+                self.pending_len = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// `true` if the next bit starts a byte.
+    pub fn is_aligned(&self) -> bool {
+        self.pending_len == 0
+    }
+
+    /// Pad the current byte with zeros, if it has started, so that the next
+    /// bit starts a byte.
+    pub fn align(&mut self) -> Result<(), std::io::Error> {
+        if self.pending_len != 0 {
+            let padding = 8 - self.pending_len;
+            self.write_bits(0, padding)?;
+        }
+        Ok(())
+    }
+
+    /// Pad the last byte with zeros, flush, and return the sink.
+    pub fn done(mut self) -> Result<W, std::io::Error> {
+        self.align()?;
+        self.sink.flush()?;
+        Ok(self.sink)
+    }
+}
+impl<W: Write> Write for BitWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        if self.is_aligned() {
+            return self.sink.write(buf);
+        }
+        for byte in buf {
+            self.write_bits(*byte as u32, 8)?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Flush the complete bytes, but not the current byte, which only
+    /// `align` or `done` complete.
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.sink.flush()
+    }
+}
+
+/// A reader of bits written by a `BitWriter`.
+///
+/// Bytes read through `Read`, e.g. with `ReadVarNum::read_varnum`, start at
+/// the current bit position.
+pub struct BitReader<R: Read> {
+    source: R,
+
+    /// The byte being read, whose unread bits are the `remaining` lowest ones.
+    current: u8,
+
+    /// The number of bits of `current` not read yet.
+    remaining: u32,
+}
+impl<R: Read> BitReader<R> {
+    pub fn new(source: R) -> Self {
+        BitReader {
+            source,
+            current: 0,
+            remaining: 0,
+        }
+    }
+
+    /// Read `n` bits, most significant first.
+    ///
+    /// Fails if `n` exceeds `MAX_BITS`, or with `UnexpectedEof` if the
+    /// source ends first.
+    pub fn read_bits(&mut self, n: u32) -> Result<u32, std::io::Error> {
+        check_bits(n)?;
+        let mut value: u32 = 0;
+        for _ in 0..n {
+            if self.remaining == 0 {
+                let mut buf = [0];
+                self.source.read_exact(&mut buf)?;
+                self.current = buf[0];
+                self.remaining = 8;
+            }
+            self.remaining -= 1;
+            let bit = (self.current >> self.remaining) & 1;
+            // `value` has fewer than 32 significant bits here.
+            value = (value << 1) | bit as u32;
+        }
+        Ok(value)
+    }
+
+    /// `true` if the next bit starts a byte.
+    pub fn is_aligned(&self) -> bool {
+        self.remaining == 0
+    }
+
+    /// Skip the rest of the current byte, if it has started, e.g. the
+    /// padding written by `BitWriter::align`.
+    pub fn align(&mut self) {
+        self.remaining = 0;
+    }
+
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+}
+impl<R: Read> Read for BitReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        if self.is_aligned() {
+            return self.source.read(buf);
+        }
+        for (i, byte) in buf.iter_mut().enumerate() {
+            match self.read_bits(8) {
+                Ok(bits) => *byte = bits as u8,
+                Err(ref err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(i),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(buf.len())
+    }
+}
+
+#[test]
+fn test_bits() {
+    use bytes::varnum::{ReadVarNum, WriteVarNum};
+
+    // Values of 1, 3 and 13 bits, then varnums, unaligned then aligned.
+    let values: Vec<(u32, u32)> = (0..100)
+        .map(|i| match i % 3 {
+            0 => (i % 2, 1),
+            1 => (i % 8, 3),
+            _ => ((i * 97) % 8192, 13),
+        })
+        .collect();
+    let total_bits: u32 = values.iter().map(|&(_, n)| n).sum();
+
+    let mut writer = BitWriter::new(vec![]);
+    for &(value, n) in &values {
+        writer.write_bits(value, n).unwrap();
+    }
+    writer.write_bits(0b1, 1).unwrap();
+    writer.write_varnum(300).unwrap();
+    writer.align().unwrap();
+    assert!(writer.is_aligned());
+    writer.write_varnum(1234).unwrap();
+    writer.write_bits(std::u32::MAX, 32).unwrap();
+    writer.write_bits(0b101, 3).unwrap();
+
+    // Out of range.
+    assert!(writer.write_bits(8, 3).is_err());
+    assert!(writer.write_bits(0, 33).is_err());
+
+    let data = writer.done().unwrap();
+    let unaligned_bits = total_bits + 1 + 16;
+    let aligned_bytes = (unaligned_bits + 7) / 8;
+    // 2 bytes of varnum, 4 bytes of `u32::MAX`, 3 bits padded to a byte.
+    assert_eq!(data.len() as u32, aligned_bytes + 2 + 4 + 1);
+
+    let mut reader = BitReader::new(std::io::Cursor::new(&data));
+    for &(value, n) in &values {
+        assert_eq!(reader.read_bits(n).unwrap(), value);
+    }
+    assert_eq!(reader.read_bits(1).unwrap(), 0b1);
+    assert_eq!(reader.read_varnum().unwrap(), 300);
+    reader.align();
+    assert_eq!(reader.read_varnum().unwrap(), 1234);
+    assert_eq!(reader.read_bits(32).unwrap(), std::u32::MAX);
+    assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+    // The padding, then nothing.
+    assert_eq!(reader.read_bits(5).unwrap(), 0);
+    assert!(reader.read_bits(1).is_err());
+
+    // Aligned varnums are those of byte streams.
+    let mut plain = vec![];
+    plain.write_varnum(1234).unwrap();
+    let start = aligned_bytes as usize;
+    assert_eq!(&data[start..start + plain.len()], &plain[..]);
+}
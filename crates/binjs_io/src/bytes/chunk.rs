@@ -0,0 +1,178 @@
+//! Content-defined chunking.
+//!
+//! The chunker cuts a stream of bytes wherever a rolling hash of the last
+//! few bytes matches a pattern. As cut points depend only on the content,
+//! an edit only changes the chunks around it, so chunks may be shared
+//! (e.g. in a content-addressed store) between similar files.
+//!
+//! Smaller chunks improve deduplication, at the cost of a larger overhead
+//! per chunk.
+
+use std;
+
+/// Default value for `min_chunk`.
+pub const DEFAULT_MIN_CHUNK: usize = 2 * 1024;
+
+/// Default value for `avg_chunk`.
+pub const DEFAULT_AVG_CHUNK: usize = 8 * 1024;
+
+/// Default value for `max_chunk`.
+pub const DEFAULT_MAX_CHUNK: usize = 32 * 1024;
+
+/// A content-defined chunker, using a Gear rolling hash.
+#[derive(Clone, Debug)]
+pub struct Chunker {
+    /// No chunk is shorter than this, except possibly the last one.
+    min_chunk: usize,
+
+    /// The expected length of a chunk.
+    avg_chunk: usize,
+
+    /// No chunk is longer than this.
+    max_chunk: usize,
+
+    /// A cut point is found when the hash matches `mask`.
+    ///
+    /// Derived from `avg_chunk`, rounded to the nearest power of two.
+    mask: u64,
+
+    /// The random value associated to each byte by the rolling hash.
+    gear: [u64; 256],
+}
+impl Default for Chunker {
+    fn default() -> Self {
+        Self::new(DEFAULT_MIN_CHUNK, DEFAULT_AVG_CHUNK, DEFAULT_MAX_CHUNK)
+            .expect("Invalid default chunk sizes")
+    }
+}
+impl Chunker {
+    /// Create a chunker.
+    ///
+    /// Fails unless `0 < min_chunk <= avg_chunk <= max_chunk`.
+    pub fn new(
+        min_chunk: usize,
+        avg_chunk: usize,
+        max_chunk: usize,
+    ) -> Result<Self, std::io::Error> {
+        if min_chunk == 0 || min_chunk > avg_chunk || avg_chunk > max_chunk {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Invalid chunk sizes: expected 0 < min ({}) <= avg ({}) <= max ({})",
+                    min_chunk, avg_chunk, max_chunk
+                ),
+            ));
+        }
+
+        // Use the top `bits` bits of the hash, as they depend on more
+        // bytes than the bottom bits.
+        let bits = (avg_chunk as f64).log2().round() as u32;
+        let mask = if bits == 0 {
+            0
+        } else {
+            !0u64 << (64 - std::cmp::min(bits, 63))
+        };
+
+        // Generate the table deterministically (splitmix64), so that the
+        // same content is always cut at the same points.
+        let mut gear = [0u64; 256];
+        let mut state: u64 = 0;
+        for value in gear.iter_mut() {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            *value = z ^ (z >> 31);
+        }
+
+        Ok(Chunker {
+            min_chunk,
+            avg_chunk,
+            max_chunk,
+            mask,
+            gear,
+        })
+    }
+
+    pub fn min_chunk(&self) -> usize {
+        self.min_chunk
+    }
+    pub fn avg_chunk(&self) -> usize {
+        self.avg_chunk
+    }
+    pub fn max_chunk(&self) -> usize {
+        self.max_chunk
+    }
+
+    /// Find the length of the first chunk of `data`.
+    fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_chunk {
+            return data.len();
+        }
+        let end = std::cmp::min(data.len(), self.max_chunk);
+        let mut hash: u64 = 0;
+        for (i, byte) in data[..end].iter().enumerate() {
+            hash = (hash << 1).wrapping_add(self.gear[*byte as usize]);
+            if i + 1 >= self.min_chunk && hash & self.mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+
+    /// Split `data` into chunks.
+    ///
+    /// The chunks are returned in order and their concatenation is `data`.
+    /// A non-empty input produces at least one chunk.
+    pub fn chunks<'a>(&self, data: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut chunks = vec![];
+        let mut rest = data;
+        while !rest.is_empty() {
+            let (chunk, tail) = rest.split_at(self.cut(rest));
+            chunks.push(chunk);
+            rest = tail;
+        }
+        chunks
+    }
+}
+
+#[test]
+fn test_chunker() {
+    // Pseudo-random, but reproducible, data.
+    let mut state: u32 = 1;
+    let data: Vec<u8> = (0..1024 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+
+    let small = Chunker::new(256, 1024, 4096).expect("Creating small chunker");
+    let large = Chunker::default();
+
+    let small_chunks = small.chunks(&data);
+    let large_chunks = large.chunks(&data);
+    assert!(small_chunks.len() > large_chunks.len());
+
+    for (chunker, chunks) in &[(&small, &small_chunks), (&large, &large_chunks)] {
+        assert_eq!(chunks.concat(), data);
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() >= chunker.min_chunk());
+            assert!(chunk.len() <= chunker.max_chunk());
+        }
+    }
+    let average = |chunks: &[&[u8]]| data.len() / chunks.len();
+    assert!(average(&small_chunks) < average(&large_chunks));
+
+    // A tiny input is a single chunk.
+    assert_eq!(large.chunks(&data[..100]), vec![&data[..100]]);
+    assert!(large.chunks(&[]).is_empty());
+
+    // Sizes must be ordered.
+    assert!(Chunker::new(0, 8, 32).is_err());
+    assert!(Chunker::new(16, 8, 32).is_err());
+    assert!(Chunker::new(2, 64, 32).is_err());
+    assert!(Chunker::new(8, 8, 8).is_ok());
+}
@@ -1,7 +1,8 @@
 //! Compressing bytes from/to bytes.
 
+use bytes::frame::{read_length_prefixed, read_length_prefixed_into, write_length_prefixed};
 use bytes::serialize::*;
-use bytes::varnum::*;
+use bytes::varnum::{ReadVarNum, WriteVarNum, VARNUM_MAX_BYTES_U32};
 
 use rand::distributions::Distribution;
 use rand::distributions::Standard;
@@ -9,15 +10,162 @@ use rand::seq::SliceRandom;
 use rand::thread_rng;
 use rand::Rng;
 
+use twox_hash::XxHash64;
+
 use std;
-use std::collections::HashSet;
-use std::io::{Cursor, Read, Write};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hasher;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
 
-const BROTLI_BUFFER_SIZE: usize = 4096;
 const BROTLI_QUALITY: u32 = 11;
 const BROTLI_LG_WINDOW_SIZE: u32 = 20;
+
+/// Maximal window size of brotli's large-window mode, i.e. 1 GiB.
+const BROTLI_MAX_LARGE_LG_WINDOW_SIZE: u32 = 30;
 const LZW_MIN_CODE_SIZE: u8 = 8;
 
+/// Byte length of each of the slices compressed by `Compression::estimate_ratio`.
+const ESTIMATE_SAMPLE_LEN: usize = 16 * 1024;
+
+/// Number of slices compressed by `Compression::estimate_ratio`, spread
+/// evenly across the data.
+const ESTIMATE_SAMPLES: usize = 4;
+
+/// Maximal byte length of a compressed section.
+///
+/// Anything larger is certainly the result of a corrupted length.
+const MAX_COMPRESSED_LENGTH: usize = 1 << 30;
+
+/// Maximal ratio of the size hint of a block to its compressed byte length
+/// up to which the decompressed buffer is allocated from the hint.
+///
+/// Hints are read from untrusted input, so a larger hint only preallocates
+/// this many bytes per compressed byte and the buffer grows as needed. Blocks
+/// whose decompressed byte length differs from their hint are rejected.
+const MAX_PREALLOCATION_RATIO: usize = 64;
+
+/// Appended to the compression header, e.g. `br+size;`, if the block
+/// starts with its decompressed byte length (see `CompressionState::set_size_hint`).
+const SIZE_HINT_FLAG: &[u8] = b"+size";
+
+/// The compression header of lzw blocks whose codes are packed most
+/// significant bit first (see `Compression::LzwMsb`).
+const LZW_MSB_HEADER: &[u8] = b"compress+msb";
+
+/// Written in place of an empty block, i.e. an empty compression header
+/// without a byte length (see `CompressionState::set_empty_marker`).
+const EMPTY_BLOCK_MARKER: &[u8] = b";";
+
+/// Maximal byte length of a compression header, including `;`.
+const MAX_HEADER_LENGTH: usize = 32;
+
+/// Default byte length of the buffers of streaming decoders (see
+/// `DecompressionOptions::buffer_size`).
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Maximal capacity of the buffers kept by a `BufferPool`, so that a single
+/// large block does not pin its memory for the lifetime of the pool.
+const MAX_POOLED_CAPACITY: usize = 1 << 20;
+
+/// Options of `Compression::decompress_with_options`.
+#[derive(Clone, Debug)]
+pub struct DecompressionOptions {
+    /// Fail if a block exceeds this number of bytes once decompressed, e.g.
+    /// a decompression bomb.
+    pub max_bytes: usize,
+
+    /// The byte length of the buffer through which brotli decoders stream
+    /// the block, and the maximal initial capacity of the decompressed bytes
+    /// of blocks without size hint.
+    ///
+    /// Larger buffers take fewer iterations to decompress large blocks.
+    pub buffer_size: usize,
+}
+impl DecompressionOptions {
+    /// The default options, failing past `max_bytes`.
+    pub fn with_max_bytes(max_bytes: usize) -> Self {
+        DecompressionOptions {
+            max_bytes,
+            ..Self::default()
+        }
+    }
+}
+impl Default for DecompressionOptions {
+    fn default() -> Self {
+        DecompressionOptions {
+            max_bytes: std::usize::MAX,
+            buffer_size: DEFAULT_READ_BUFFER_SIZE,
+        }
+    }
+}
+
+/// The compression header of a block, as read by `Compression::probe_header`.
+struct BlockHeader {
+    compression: Compression,
+
+    /// If `true`, the block is an `EMPTY_BLOCK_MARKER`, with neither a byte
+    /// length nor compressed bytes.
+    is_empty: bool,
+
+    /// If `true`, the block starts with its decompressed byte length.
+    has_size_hint: bool,
+}
+
+/// State reused across calls to `Compression::compress_with_state`.
+///
+/// Deflate reuses its encoder, reset between blocks. The brotli crate cannot
+/// reset an encoder, so brotli, like the other algorithms, only reuses the
+/// buffer receiving the compressed data.
+#[derive(Default)]
+pub struct CompressionState {
+    deflate: Option<flate2::Compress>,
+    buffer: Vec<u8>,
+    size_hint: bool,
+    gzip_mtime: u32,
+    empty_marker: bool,
+    brotli_quality: Option<u32>,
+}
+impl CompressionState {
+    /// If `size_hint` is `true`, store the decompressed byte length of each
+    /// block, so that `Compression::decompress` may allocate exactly once.
+    ///
+    /// The length is stored as a varnum at the start of the length-prefixed
+    /// block, flagged by `+size` in the compression header, e.g. `br+size;`.
+    /// Readers that only skip the block need not know about the flag.
+    pub fn set_size_hint(&mut self, size_hint: bool) {
+        self.size_hint = size_hint;
+    }
+
+    /// Set the modification time stored in the header of gzip blocks, in
+    /// seconds since the epoch.
+    ///
+    /// Defaults to `0`, i.e. no modification time, so that compressing the
+    /// same data always yields the same bytes.
+    pub fn set_gzip_mtime(&mut self, mtime: u32) {
+        self.gzip_mtime = mtime;
+    }
+
+    /// If `empty_marker` is `true`, write empty blocks as the single byte
+    /// `;`, i.e. an empty compression header, without a byte length or a
+    /// frame of the codec, whatever the compression.
+    ///
+    /// Readers report such blocks as empty `Compression::Identity` blocks.
+    pub fn set_empty_marker(&mut self, empty_marker: bool) {
+        self.empty_marker = empty_marker;
+    }
+
+    /// Set the quality of brotli blocks, from `0`, the fastest, to `11`, the
+    /// smallest.
+    ///
+    /// Defaults to `11`. Readers need not know the quality.
+    pub fn set_brotli_quality(&mut self, quality: u32) {
+        self.brotli_quality = Some(std::cmp::min(quality, BROTLI_QUALITY));
+    }
+}
+
 /// The compression mechanisms supported by this encoder.
 /// They are designed to match HTTP's Accept-Encoding:
 /// https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Accept-Encoding
@@ -31,8 +179,20 @@ pub enum Compression {
     Deflate,
     /// brotly compression (`br;`)
     Brotli,
-    /// Lwz compression (`compress;`)
+    /// brotli compression with a window large enough for the input, up to
+    /// 1 GiB (`br-large;`)
+    ///
+    /// This is not a valid HTTP content-coding, as decoders must opt in to
+    /// large windows.
+    BrotliLargeWindow,
+    /// Lwz compression (`compress;`), packing codes least significant bit
+    /// first, as `compress(1)`
     Lzw,
+    /// Lzw compression packing codes most significant bit first, as TIFF and
+    /// PDF (`compress+msb;`)
+    ///
+    /// This is not a valid HTTP content-coding.
+    LzwMsb,
 }
 
 impl Distribution<Compression> for Standard {
@@ -52,6 +212,22 @@ pub struct CompressionResult {
     pub before_bytes: usize,
     pub after_bytes: usize,
     pub algorithms: HashSet<Compression>,
+
+    /// The hash of the uncompressed bytes (see `input_hash`), computed
+    /// while compressing them.
+    ///
+    /// Callers may compare it against a hash of the same bytes re-read later,
+    /// e.g. to detect that its input was modified during encoding.
+    ///
+    /// `None` once results of several compressions have been added up.
+    pub input_hash: Option<u64>,
+}
+
+/// The hash used to identify the input of a compression: 64-bit xxHash, seed 0.
+pub fn input_hash(data: &[u8]) -> u64 {
+    let mut hasher = XxHash64::with_seed(0);
+    hasher.write(data);
+    hasher.finish()
 }
 
 impl Compression {
@@ -62,7 +238,9 @@ impl Compression {
             Gzip => "Gzip",
             Deflate => "Deflate",
             Brotli => "Brotli",
+            BrotliLargeWindow => "BrotliLargeWindow",
             Lzw => "Lzw",
+            LzwMsb => "LzwMsb",
         }
     }
 
@@ -73,7 +251,9 @@ impl Compression {
             Gzip => "gzip",
             Deflate => "deflate",
             Brotli => "br",
+            BrotliLargeWindow => "br-large",
             Lzw => "lzw",
+            LzwMsb => "lzw-msb",
         }
     }
 
@@ -81,7 +261,9 @@ impl Compression {
         let result = match name {
             None | Some("identity") => Compression::Identity,
             Some("lzw") => Compression::Lzw,
+            Some("lzw-msb") => Compression::LzwMsb,
             Some("br") => Compression::Brotli,
+            Some("br-large") => Compression::BrotliLargeWindow,
             Some("gzip") => Compression::Gzip,
             Some("deflate") => Compression::Deflate,
             Some("random") => thread_rng().gen(),
@@ -92,6 +274,157 @@ impl Compression {
         Some(result)
     }
 
+    /// Map a content-coding token, as it appears in HTTP's `Accept-Encoding`
+    /// or `Content-Encoding`, to a compression mechanism.
+    ///
+    /// Matching is case-insensitive. Returns `None` for unknown tokens,
+    /// including the wildcard `*`.
+    pub fn from_http_token(token: &str) -> Option<Compression> {
+        let token = token.trim().to_ascii_lowercase();
+        let result = match token.as_str() {
+            "identity" => Compression::Identity,
+            "gzip" | "x-gzip" => Compression::Gzip,
+            "deflate" => Compression::Deflate,
+            "br" => Compression::Brotli,
+            "compress" | "x-compress" => Compression::Lzw,
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    /// Pick the best compression mechanism acceptable to a client, given the
+    /// value of its `Accept-Encoding` header, e.g. `br;q=1.0, gzip;q=0.8`.
+    ///
+    /// Only mechanisms of `Compression::values()` are considered. Tokens without
+    /// a `q` parameter have quality 1, `*` applies to every mechanism not listed
+    /// explicitly and a quality of 0 disables a mechanism. In case of a tie, we
+    /// prefer Brotli, then Gzip, then Deflate, then Identity.
+    ///
+    /// If no mechanism is acceptable (e.g. `identity;q=0` without any supported
+    /// alternative), fall back to `Compression::Identity`.
+    pub fn negotiate(accept_encoding_header: &str) -> Compression {
+        // Quality per mechanism, in thousandths, as specified by the client.
+        let mut explicit: Vec<(Compression, u32)> = vec![];
+        let mut wildcard: Option<u32> = None;
+        for item in accept_encoding_header.split(',') {
+            let mut parts = item.split(';');
+            let token = match parts.next() {
+                Some(token) if !token.trim().is_empty() => token.trim(),
+                _ => continue,
+            };
+            let mut quality = Some(1000);
+            for param in parts {
+                let mut kv = param.splitn(2, '=');
+                let key = kv.next().unwrap_or("").trim();
+                if !key.eq_ignore_ascii_case("q") {
+                    continue;
+                }
+                quality = kv
+                    .next()
+                    .and_then(|value| value.trim().parse::<f32>().ok())
+                    .filter(|value| *value >= 0. && *value <= 1.)
+                    .map(|value| (value * 1000.).round() as u32);
+            }
+            let quality = match quality {
+                Some(quality) => quality,
+                None => continue, // Malformed `q`, ignore the token.
+            };
+            if token == "*" {
+                wildcard = Some(quality);
+            } else if let Some(compression) = Self::from_http_token(token) {
+                explicit.retain(|&(ref c, _)| *c != compression);
+                explicit.push((compression, quality));
+            }
+        }
+
+        // Server-side preference, used to break ties: best compression first.
+        let supported = Self::values();
+        let mut by_preference: Vec<&Compression> = supported.iter().collect();
+        by_preference.sort_by_key(|compression| match **compression {
+            Compression::Brotli | Compression::BrotliLargeWindow => 0,
+            Compression::Gzip => 1,
+            Compression::Deflate => 2,
+            Compression::Lzw | Compression::LzwMsb => 3,
+            Compression::Identity => 4,
+        });
+
+        let mut best: Option<(&Compression, u32)> = None;
+        for compression in by_preference {
+            let quality = match explicit.iter().find(|&&(ref c, _)| c == compression) {
+                Some(&(_, quality)) => quality,
+                None => match (wildcard, compression) {
+                    (Some(quality), _) => quality,
+                    // `identity` is acceptable unless explicitly excluded.
+                    (None, &Compression::Identity) => 1,
+                    (None, _) => 0,
+                },
+            };
+            if quality == 0 {
+                continue;
+            }
+            let is_better = match best {
+                None => true,
+                Some((_, best_quality)) => quality > best_quality,
+            };
+            if is_better {
+                best = Some((compression, quality));
+            }
+        }
+        best.map(|(compression, _)| compression.clone())
+            .unwrap_or(Compression::Identity)
+    }
+
+    /// Estimate the ratio of compressed to uncompressed bytes achievable on
+    /// `data`, between 0 (entirely redundant) and 1 (incompressible), without
+    /// compressing all of it, e.g. to skip compressing random data.
+    ///
+    /// This is the smallest of two cheap estimates, which catch distinct
+    /// kinds of redundancy: the order-0 Shannon entropy of the bytes, and
+    /// the ratio achieved by fast deflate on a few slices of `data`.
+    /// Actual ratios of brotli are usually lower.
+    pub fn estimate_ratio(data: &[u8]) -> f64 {
+        if data.is_empty() {
+            return 1.;
+        }
+
+        // Order-0 entropy, in bits per byte.
+        let mut counts = [0usize; 256];
+        for byte in data {
+            counts[*byte as usize] += 1;
+        }
+        let len = data.len() as f64;
+        let entropy: f64 = counts
+            .iter()
+            .filter(|count| **count != 0)
+            .map(|count| {
+                let probability = *count as f64 / len;
+                -probability * probability.log2()
+            })
+            .sum();
+
+        // Sampled compression.
+        let sample: Vec<u8> = if data.len() <= ESTIMATE_SAMPLE_LEN * ESTIMATE_SAMPLES {
+            data.to_vec()
+        } else {
+            let stride = (data.len() - ESTIMATE_SAMPLE_LEN) / (ESTIMATE_SAMPLES - 1);
+            (0..ESTIMATE_SAMPLES)
+                .flat_map(|i| &data[i * stride..i * stride + ESTIMATE_SAMPLE_LEN])
+                .cloned()
+                .collect()
+        };
+        let mut encoder = flate2::write::DeflateEncoder::new(
+            Vec::with_capacity(sample.len()),
+            flate2::Compression::fast(),
+        );
+        let sampled = encoder
+            .write_all(&sample)
+            .and_then(|_| encoder.finish())
+            .map(|compressed| compressed.len() as f64 / sample.len() as f64)
+            .unwrap_or(1.);
+
+        f64::min(entropy / 8., sampled).min(1.)
+    }
+
     pub fn values() -> Box<[Self]> {
         use self::Compression::*;
         Box::new([
@@ -115,109 +448,197 @@ impl Compression {
         &self,
         data: &[u8],
         out: &mut W,
+    ) -> Result<CompressionResult, std::io::Error> {
+        self.compress_with_state(data, out, &mut CompressionState::default())
+    }
+
+    /// Same as `compress`, but reusing `state` rather than allocating a new
+    /// state for each block, e.g. to compress many small blocks.
+    pub fn compress_with_state<W: Write>(
+        &self,
+        data: &[u8],
+        out: &mut W,
+        state: &mut CompressionState,
     ) -> Result<CompressionResult, std::io::Error> {
         let before_bytes = data.len();
+        let input_hash = input_hash(data);
+        if state.empty_marker && data.is_empty() {
+            out.write_all(EMPTY_BLOCK_MARKER)?;
+            return Ok(CompressionResult {
+                before_bytes,
+                after_bytes: 0,
+                algorithms: [self.clone()].iter().cloned().collect(),
+                input_hash: Some(input_hash),
+            });
+        }
+        let size_hint = if state.size_hint {
+            Some(before_bytes)
+        } else {
+            None
+        };
+        let brotli_quality = state.brotli_quality.unwrap_or(BROTLI_QUALITY);
+        let mut buffer = std::mem::replace(&mut state.buffer, Vec::new());
+        buffer.clear();
+        buffer.reserve(data.len());
         let after_bytes = match *self {
             Compression::Identity => {
-                out.write_all(b"identity;")?;
-                out.write_varnum(data.len() as u32)?;
-                out.write_all(data)?;
+                write_header(out, b"identity", size_hint)?;
+                write_frame(out, data, size_hint)?;
                 data.len()
             }
             Compression::Gzip => {
-                out.write_all(b"gzip;")?;
+                write_header(out, b"gzip", size_hint)?;
                 // Compress
-                let buffer = Vec::with_capacity(data.len());
-                let mut encoder =
-                    flate2::write::GzEncoder::new(buffer, flate2::Compression::best());
+                // Never the current time, for reproducible outputs.
+                let mut encoder = flate2::GzBuilder::new()
+                    .mtime(state.gzip_mtime)
+                    .write(buffer, flate2::Compression::best());
                 encoder.write_all(data)?;
-                let buffer = encoder.finish()?;
+                buffer = encoder.finish()?;
                 // Write
-                out.write_varnum(buffer.len() as u32)?;
-                out.write_all(&buffer)?;
+                write_frame(out, &buffer, size_hint)?;
                 buffer.len()
             }
             Compression::Deflate => {
-                out.write_all(b"deflate;")?;
-                // Compress
-                let buffer = Vec::with_capacity(data.len());
-                let mut encoder =
-                    flate2::write::ZlibEncoder::new(buffer, flate2::Compression::best());
-                encoder.write(data)?;
-                let buffer = encoder.finish()?;
+                write_header(out, b"deflate", size_hint)?;
+                // Compress, with the zlib header, as `flate2::write::ZlibEncoder`.
+                let encoder = state.deflate.get_or_insert_with(|| {
+                    flate2::Compress::new(flate2::Compression::best(), true)
+                });
+                encoder.reset();
+                loop {
+                    let consumed = encoder.total_in() as usize;
+                    // `compress_vec` only writes to the spare capacity.
+                    buffer.reserve(std::cmp::max(data.len() - consumed, 1024));
+                    let status = encoder
+                        .compress_vec(
+                            &data[consumed..],
+                            &mut buffer,
+                            flate2::FlushCompress::Finish,
+                        )
+                        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+                    if let flate2::Status::StreamEnd = status {
+                        break;
+                    }
+                }
                 // Write
-                out.write_varnum(buffer.len() as u32)?;
-                out.write_all(&buffer)?;
+                write_frame(out, &buffer, size_hint)?;
                 buffer.len()
             }
             Compression::Brotli => {
-                out.write_all(b"br;")?;
+                write_header(out, b"br", size_hint)?;
                 // Compress
-                let mut buffer = Vec::with_capacity(data.len());
-                {
-                    let mut encoder = brotli::CompressorWriter::new(
-                        &mut buffer,
-                        BROTLI_BUFFER_SIZE,
-                        BROTLI_QUALITY,
-                        BROTLI_LG_WINDOW_SIZE,
-                    );
-                    encoder.write(data)?;
-                }
+                let mut params = brotli::enc::BrotliEncoderParams::default();
+                params.quality = brotli_quality as i32;
+                params.lgwin = BROTLI_LG_WINDOW_SIZE as i32;
+                compress_brotli(data, &params, &mut buffer)?;
                 // Write
-                out.write_varnum(buffer.len() as u32)?;
-                out.write_all(&buffer)?;
+                write_frame(out, &buffer, size_hint)?;
                 buffer.len()
             }
-            Compression::Lzw => {
-                out.write_all(b"compress;")?;
+            Compression::BrotliLargeWindow => {
+                write_header(out, b"br-large", size_hint)?;
                 // Compress
-                let mut buffer = Vec::with_capacity(data.len());
-                {
-                    let writer = lzw::LsbWriter::new(&mut buffer);
-                    let mut encoder = lzw::Encoder::new(writer, LZW_MIN_CODE_SIZE)?;
-                    encoder.encode_bytes(data)?;
-                }
+                let mut params = brotli::enc::BrotliEncoderParams::default();
+                params.quality = brotli_quality as i32;
+                params.lgwin = large_window_bits(data.len()) as i32;
+                params.large_window = true;
+                params.size_hint = data.len();
+                compress_brotli(data, &params, &mut buffer)?;
+                // Write
+                write_frame(out, &buffer, size_hint)?;
+                buffer.len()
+            }
+            Compression::Lzw => {
+                write_header(out, b"compress", size_hint)?;
+                encode_lzw(lzw::LsbWriter::new(&mut buffer), data)?;
+                // Write
+                write_frame(out, &buffer, size_hint)?;
+                buffer.len()
+            }
+            Compression::LzwMsb => {
+                write_header(out, LZW_MSB_HEADER, size_hint)?;
+                encode_lzw(lzw::MsbWriter::new(&mut buffer), data)?;
                 // Write
-                out.write_varnum(buffer.len() as u32)?;
-                out.write_all(&buffer)?;
+                write_frame(out, &buffer, size_hint)?;
                 buffer.len()
             }
         };
+        state.buffer = buffer;
         Ok(CompressionResult {
             before_bytes,
             after_bytes,
             algorithms: [self.clone()].iter().cloned().collect(),
+            input_hash: Some(input_hash),
         })
     }
 
-    pub fn decompress<R: Read, T>(
-        inp: &mut R,
-        deserializer: &T,
-    ) -> Result<T::Target, std::io::Error>
-    where
-        T: Deserializer,
-    {
-        const MAX_LENGTH: usize = 32;
-        let mut header = Vec::with_capacity(MAX_LENGTH);
-        let mut found = false;
-
-        // Scan for `;` in the first 32 bytes.
-        for _ in 0..MAX_LENGTH {
-            let mut buf = [0];
-            inp.read_exact(&mut buf)?;
-            if buf[0] != b';' {
-                header.push(buf[0]);
-            } else {
-                found = true;
-                break;
+    /// Read the compression header of a block, e.g. `br;`, leaving `inp`
+    /// at the byte length of the compressed data.
+    pub fn probe<R: Read>(inp: &mut R) -> Result<Compression, std::io::Error> {
+        Self::probe_header(inp).map(|header| header.compression)
+    }
+
+    /// As `probe`, also reading the byte length of the compressed data,
+    /// leaving `inp` at the compressed data.
+    ///
+    /// Empty blocks (see `CompressionState::set_empty_marker`) have no byte
+    /// length, and are reported as 0 bytes of `Compression::Identity`.
+    pub fn probe_with_len<R: Read>(inp: &mut R) -> Result<(Compression, usize), std::io::Error> {
+        let header = Self::probe_header(inp)?;
+        if header.is_empty {
+            return Ok((header.compression, 0));
+        }
+        let byte_len = inp.read_varnum()? as usize;
+        Ok((header.compression, byte_len))
+    }
+
+    /// As `probe`, but inspecting the first bytes of a block without
+    /// consuming them, e.g. to route a stream that must be handed off intact.
+    ///
+    /// Returns the compression format and the byte length of the compression
+    /// header, including `;`, or `None` if `buf` does not start with a
+    /// complete compression header.
+    pub fn sniff(buf: &[u8]) -> Option<(Compression, usize)> {
+        let mut inp = Cursor::new(buf);
+        let header = Self::probe_header(&mut inp).ok()?;
+        Some((header.compression, inp.position() as usize))
+    }
+
+    /// The offset of the compressed bytes of `block`, after its compression
+    /// header, byte length and size hint, if any, e.g. to align them, or
+    /// `None` if `block` does not start with a complete prefix.
+    pub fn body_offset(block: &[u8]) -> Option<usize> {
+        let mut inp = Cursor::new(block);
+        let header = Self::probe_header(&mut inp).ok()?;
+        if !header.is_empty {
+            inp.read_varnum().ok()?;
+            if header.has_size_hint {
+                inp.read_varnum().ok()?;
             }
         }
+        Some(inp.position() as usize)
+    }
 
-        if !found {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Invalid compression header",
-            ));
+    /// As `probe`, also reading the flags of the header.
+    fn probe_header<R: Read>(inp: &mut R) -> Result<BlockHeader, std::io::Error> {
+        Self::parse_header(read_header(inp)?)
+    }
+
+    /// The compression format and flags of a header, as read by `read_header`.
+    fn parse_header(mut header: Vec<u8>) -> Result<BlockHeader, std::io::Error> {
+        if header.is_empty() {
+            return Ok(BlockHeader {
+                compression: Compression::Identity,
+                is_empty: true,
+                has_size_hint: false,
+            });
+        }
+
+        let has_size_hint = header.ends_with(SIZE_HINT_FLAG);
+        if has_size_hint {
+            let len = header.len() - SIZE_HINT_FLAG.len();
+            header.truncate(len);
         }
 
         let compression = if &header == b"identity" {
@@ -228,54 +649,1713 @@ impl Compression {
             Compression::Deflate
         } else if &header == b"br" {
             Compression::Brotli
+        } else if &header == b"br-large" {
+            Compression::BrotliLargeWindow
         } else if &header == b"compress" {
             Compression::Lzw
+        } else if &header == LZW_MSB_HEADER {
+            Compression::LzwMsb
         } else {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "Invalid compression header",
             ));
         };
+        Ok(BlockHeader {
+            compression,
+            is_empty: false,
+            has_size_hint,
+        })
+    }
 
-        let mut byte_len = 0;
-        inp.read_varnum_to(&mut byte_len)?;
+    /// The byte length of a block once decompressed, if it can be found without
+    /// decompressing the block, leaving `inp` at the start of the block.
+    ///
+    /// This is the case for blocks compressed with a size hint (see
+    /// `CompressionState::set_size_hint`), uncompressed blocks and gzip blocks,
+    /// which end with their decompressed byte length, modulo 2^32. For gzip
+    /// blocks made of several members, this is the length of the last member
+    /// only. Returns `None` for other blocks, e.g. brotli or lzw blocks
+    /// without a size hint, or if reading fails.
+    pub fn decompressed_size_hint<R: Read + Seek>(inp: &mut R) -> Option<u64> {
+        let start = inp.seek(SeekFrom::Current(0)).ok()?;
+        let result = Self::read_decompressed_size_hint(inp);
+        inp.seek(SeekFrom::Start(start)).ok()?;
+        result
+    }
+
+    /// Implementation of `decompressed_size_hint`, moving `inp`.
+    fn read_decompressed_size_hint<R: Read + Seek>(inp: &mut R) -> Option<u64> {
+        let header = Self::probe_header(inp).ok()?;
+        if header.is_empty {
+            return Some(0);
+        }
+        let compressed_len = inp.read_varnum().ok()? as u64;
+        if header.has_size_hint {
+            return inp.read_varnum().ok().map(|size| size as u64);
+        }
+        match header.compression {
+            Compression::Identity => Some(compressed_len),
+            Compression::Gzip => {
+                // The trailer of a gzip member ends with ISIZE, a little-endian u32.
+                const ISIZE_LEN: u64 = 4;
+                if compressed_len < ISIZE_LEN {
+                    return None;
+                }
+                inp.seek(SeekFrom::Current((compressed_len - ISIZE_LEN) as i64))
+                    .ok()?;
+                let mut buf = [0; ISIZE_LEN as usize];
+                inp.read_exact(&mut buf).ok()?;
+                Some(u32::from_le_bytes(buf) as u64)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn decompress<R: Read, T>(
+        inp: &mut R,
+        deserializer: &T,
+    ) -> Result<T::Target, std::io::Error>
+    where
+        T: Deserializer,
+    {
+        Self::decompress_with_limit(inp, deserializer, std::usize::MAX)
+    }
+
+    /// As `decompress`, but fail if the block exceeds `max_bytes` bytes once
+    /// decompressed, e.g. a decompression bomb.
+    ///
+    /// Streaming codecs stop decompressing past the limit. Lzw blocks are
+    /// only checked once decompressed.
+    pub fn decompress_with_limit<R: Read, T>(
+        inp: &mut R,
+        deserializer: &T,
+        max_bytes: usize,
+    ) -> Result<T::Target, std::io::Error>
+    where
+        T: Deserializer,
+    {
+        Self::decompress_with_options(
+            inp,
+            deserializer,
+            &DecompressionOptions::with_max_bytes(max_bytes),
+        )
+    }
+
+    /// As `decompress_with_limit`, with the options of the decoders, e.g.
+    /// the size of their buffers.
+    ///
+    /// The decompressed bytes do not depend on the options, only the
+    /// resources used to decompress them.
+    pub fn decompress_with_options<R: Read, T>(
+        inp: &mut R,
+        deserializer: &T,
+        options: &DecompressionOptions,
+    ) -> Result<T::Target, std::io::Error>
+    where
+        T: Deserializer,
+    {
+        let decompressed_bytes = Self::decompress_bytes(inp, options, Vec::new())?;
+        let value = deserializer.read(&mut Cursor::new(decompressed_bytes))?;
+        Ok(value)
+    }
+
+    /// As `decompress_with_limit`, but reading the block from a slice.
+    ///
+    /// If the block is uncompressed, `deserializer` is handed the contents
+    /// of the block as a slice of `inp`, without copying them, e.g. for the
+    /// tree of `Targets::fast_tree`. Otherwise, it is handed the
+    /// decompressed bytes.
+    pub fn decompress_borrowed<'a, T>(
+        inp: &mut Cursor<&'a [u8]>,
+        deserializer: &T,
+        max_bytes: usize,
+    ) -> Result<T::Target, std::io::Error>
+    where
+        T: BorrowingDeserializer<'a>,
+    {
+        Self::decompress_borrowed_with_options(
+            inp,
+            deserializer,
+            &DecompressionOptions::with_max_bytes(max_bytes),
+        )
+    }
+
+    /// As `decompress_borrowed`, with the options of the decoders of
+    /// compressed blocks (see `decompress_with_options`).
+    pub fn decompress_borrowed_with_options<'a, T>(
+        inp: &mut Cursor<&'a [u8]>,
+        deserializer: &T,
+        options: &DecompressionOptions,
+    ) -> Result<T::Target, std::io::Error>
+    where
+        T: BorrowingDeserializer<'a>,
+    {
+        let max_bytes = options.max_bytes;
+        let header = Self::probe_header(inp)?;
+        if header.compression != Compression::Identity || header.is_empty {
+            let decompressed_bytes = Self::decompress_block(inp, &header, options, Vec::new())?;
+            return deserializer.read_bytes(Cow::Owned(decompressed_bytes));
+        }
+
+        // As `decompress_block`, borrowing rather than reading the frame.
+        let byte_len = inp.read_varnum()? as usize;
+        let max_compressed = std::cmp::min(
+            MAX_COMPRESSED_LENGTH,
+            max_bytes.saturating_add(VARNUM_MAX_BYTES_U32),
+        );
+        if byte_len > max_compressed {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Frame length {} exceeds maximal length {}",
+                    byte_len, max_compressed
+                ),
+            ));
+        }
+        let data: &'a [u8] = *inp.get_ref();
+        let start = inp.position() as usize;
+        let end = start.saturating_add(byte_len);
+        if end > data.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "Truncated block",
+            ));
+        }
+        inp.set_position(end as u64);
+
+        let mut bytes = &data[start..end];
+        if header.has_size_hint {
+            let size = bytes.read_varnum()? as usize;
+            if size != bytes.len() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "Decompressed byte length {} does not match size hint {}",
+                        bytes.len(),
+                        size
+                    ),
+                ));
+            }
+        }
+        if bytes.len() > max_bytes {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Decompressed block exceeds {} bytes", max_bytes),
+            ));
+        }
+        deserializer.read_bytes(Cow::Borrowed(bytes))
+    }
+
+    /// Read a single block, whatever its compression, and write it again,
+    /// compressed with `target`, without deserializing its contents.
+    ///
+    /// Returns the result of compressing the block with `target`.
+    pub fn transcode<R: Read, W: Write>(
+        inp: &mut R,
+        out: &mut W,
+        target: Compression,
+    ) -> Result<CompressionResult, std::io::Error> {
+        let decompressed_bytes =
+            Self::decompress_bytes(inp, &DecompressionOptions::default(), Vec::new())?;
+        target.compress(&decompressed_bytes, out)
+    }
+
+    /// Compress `data` with `codec` as a single block in memory, then
+    /// decompress the block, returning the recovered bytes, e.g. to check
+    /// that a codec preserves some data.
+    ///
+    /// Errors of either step, e.g. a corrupted frame, are returned as is.
+    pub fn roundtrip(data: &[u8], codec: Compression) -> Result<Vec<u8>, std::io::Error> {
+        let mut compressed = vec![];
+        codec.compress(data, &mut compressed)?;
+        Self::decompress_bytes(
+            &mut Cursor::new(&compressed),
+            &DecompressionOptions::default(),
+            Vec::new(),
+        )
+    }
+
+    /// As `decompress_with_limit`, but return the decompressed bytes in a
+    /// buffer drawn from `pool`, which returns to `pool` once dropped.
+    pub fn decompress_pooled<R: Read>(
+        inp: &mut R,
+        pool: &BufferPool,
+        max_bytes: usize,
+    ) -> Result<PooledBuffer, std::io::Error> {
+        let buffer = pool.take();
+        let decompressed_bytes = Self::decompress_bytes(
+            inp,
+            &DecompressionOptions::with_max_bytes(max_bytes),
+            buffer,
+        )?;
+        Ok(PooledBuffer {
+            buffer: decompressed_bytes,
+            pool: pool.clone(),
+        })
+    }
+
+    /// Decompress a block to `buf`, which must be empty.
+    fn decompress_bytes<R: Read>(
+        inp: &mut R,
+        options: &DecompressionOptions,
+        buf: Vec<u8>,
+    ) -> Result<Vec<u8>, std::io::Error> {
+        let header = Self::probe_header(inp)?;
+        Self::decompress_block(inp, &header, options, buf)
+    }
+
+    /// As `decompress_bytes`, once the compression header has been read.
+    fn decompress_block<R: Read>(
+        inp: &mut R,
+        header: &BlockHeader,
+        options: &DecompressionOptions,
+        mut buf: Vec<u8>,
+    ) -> Result<Vec<u8>, std::io::Error> {
+        debug_assert!(buf.is_empty());
+        let max_bytes = options.max_bytes;
+        // Decoders make no progress without a buffer.
+        let buffer_size = std::cmp::max(options.buffer_size, 1);
+        if header.is_empty {
+            return Ok(buf);
+        }
+
+        // Identity blocks are as long as their contents, plus a size hint.
+        let max_compressed = match header.compression {
+            Compression::Identity => std::cmp::min(
+                MAX_COMPRESSED_LENGTH,
+                max_bytes.saturating_add(VARNUM_MAX_BYTES_U32),
+            ),
+            _ => MAX_COMPRESSED_LENGTH,
+        };
+        // Identity blocks are their own contents, so read them to `buf`.
+        let mut compressed_bytes = match header.compression {
+            Compression::Identity => {
+                read_length_prefixed_into(inp, max_compressed, &mut buf)?;
+                std::mem::replace(&mut buf, Vec::new())
+            }
+            _ => read_length_prefixed(inp, max_compressed)?,
+        };
 
-        let mut compressed_bytes = Vec::with_capacity(byte_len as usize);
-        unsafe { compressed_bytes.set_len(byte_len as usize) };
-        inp.read_exact(&mut compressed_bytes)?;
+        let size_hint = if header.has_size_hint {
+            let (size, hint_len) = {
+                let mut cursor = Cursor::new(&compressed_bytes);
+                let size = cursor.read_varnum()? as usize;
+                (size, cursor.position() as usize)
+            };
+            if size > MAX_COMPRESSED_LENGTH {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Invalid decompressed byte length {}", size),
+                ));
+            }
+            compressed_bytes.drain(..hint_len);
+            Some(size)
+        } else {
+            None
+        };
+        let exceeded = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Decompressed block exceeds {} bytes", max_bytes),
+            )
+        };
+        if size_hint.map_or(false, |size| size > max_bytes) {
+            return Err(exceeded());
+        }
+        // Without a size hint, start with at most a buffer, so that small
+        // blocks remain cheap, and let it grow.
+        let expected = std::cmp::min(
+            size_hint.unwrap_or(max_bytes),
+            compressed_bytes
+                .len()
+                .saturating_mul(MAX_PREALLOCATION_RATIO),
+        );
+        let capacity = match size_hint {
+            Some(_) => expected,
+            None => std::cmp::min(buffer_size, expected),
+        };
+        // Read one byte past the limit, to detect blocks exceeding it.
+        let read_limit = (max_bytes as u64).saturating_add(1);
 
-        let decompressed_bytes = match compression {
+        let decompressed_bytes = match header.compression {
             Compression::Identity => compressed_bytes,
             Compression::Gzip => {
-                let mut decoder = flate2::read::GzDecoder::new(Cursor::new(&compressed_bytes));
-                let mut buf = Vec::with_capacity(1024);
-                decoder.read_to_end(&mut buf)?;
+                // Producers may concatenate several gzip members, e.g. when
+                // appending to a stream. Read all of them, not just the first one.
+                let decoder = flate2::read::MultiGzDecoder::new(Cursor::new(&compressed_bytes));
+                buf.reserve(capacity);
+                decoder.take(read_limit).read_to_end(&mut buf)?;
                 buf
             }
             Compression::Deflate => {
-                let mut decoder = flate2::read::ZlibDecoder::new(Cursor::new(&compressed_bytes));
-                let mut buf = Vec::with_capacity(1024);
-                decoder.read_to_end(&mut buf)?;
+                let decoder = flate2::read::ZlibDecoder::new(Cursor::new(&compressed_bytes));
+                buf.reserve(capacity);
+                decoder.take(read_limit).read_to_end(&mut buf)?;
                 buf
             }
             Compression::Brotli => {
-                let mut decoder =
-                    brotli::Decompressor::new(Cursor::new(&compressed_bytes), BROTLI_BUFFER_SIZE);
-                let mut buf = Vec::with_capacity(1024);
-                decoder.read_to_end(&mut buf)?;
+                let decoder =
+                    brotli::Decompressor::new(Cursor::new(&compressed_bytes), buffer_size);
+                buf.reserve(capacity);
+                decoder.take(read_limit).read_to_end(&mut buf)?;
                 buf
             }
+            Compression::BrotliLargeWindow => {
+                buf.reserve(capacity);
+                decompress_large_window(&compressed_bytes, buf, buffer_size, max_bytes)?
+            }
+            Compression::LzwMsb => {
+                buf.reserve(capacity);
+                decode_lzw(lzw::MsbReader::new(), &compressed_bytes, max_bytes, buf)?
+            }
             Compression::Lzw => {
-                let reader = lzw::LsbReader::new();
-                let mut decoder = lzw::Decoder::new(reader, LZW_MIN_CODE_SIZE);
-                let (_, data) = decoder.decode_bytes(&compressed_bytes)?;
-                let mut buf = Vec::with_capacity(data.len());
-                buf.extend_from_slice(data);
-                buf
+                buf.reserve(capacity);
+                decode_lzw(lzw::LsbReader::new(), &compressed_bytes, max_bytes, buf)?
             }
         };
+        if decompressed_bytes.len() > max_bytes {
+            return Err(exceeded());
+        }
+        if let Some(size) = size_hint {
+            if decompressed_bytes.len() != size {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "Decompressed byte length {} does not match size hint {}",
+                        decompressed_bytes.len(),
+                        size
+                    ),
+                ));
+            }
+        }
+        Ok(decompressed_bytes)
+    }
+}
 
-        let value = deserializer.read(&mut Cursor::new(decompressed_bytes))?;
-        Ok(value)
+/// A codec, compressing or decompressing a block to a new buffer.
+pub type Codec = Box<dyn Fn(&[u8]) -> Result<Vec<u8>, std::io::Error>>;
+
+/// Codecs not shipped with this crate, e.g. proprietary ones, identified by
+/// the token of their compression header, e.g. `xor` for `xor;`.
+///
+/// `compress` and `decompress` dispatch to the codec registered for a token,
+/// and to `Compression` for built-in tokens. Blocks compressed by custom codecs
+/// have the same format as other blocks, without size hint, so readers that
+/// only skip blocks need not know about them.
+#[derive(Default)]
+pub struct CodecRegistry {
+    codecs: HashMap<String, (Codec, Codec)>,
+}
+impl CodecRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a codec for `token`, replacing any codec previously
+    /// registered for the same token.
+    ///
+    /// Fails with `InvalidInput` if `token` is a built-in token or one of
+    /// its aliases, e.g. `br` or `x-gzip`, or if it cannot appear in a
+    /// compression header, e.g. if it is empty, contains `;` or is too long.
+    pub fn register<E, D>(
+        &mut self,
+        token: &str,
+        encode: E,
+        decode: D,
+    ) -> Result<(), std::io::Error>
+    where
+        E: Fn(&[u8]) -> Result<Vec<u8>, std::io::Error> + 'static,
+        D: Fn(&[u8]) -> Result<Vec<u8>, std::io::Error> + 'static,
+    {
+        let invalid = |detail: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Cannot register codec {:?}: {}", token, detail),
+            )
+        };
+        if token.is_empty()
+            || token.len() >= MAX_HEADER_LENGTH
+            || !token
+                .bytes()
+                .all(|byte| byte.is_ascii_graphic() && byte != b';')
+            || token.as_bytes().ends_with(SIZE_HINT_FLAG)
+        {
+            return Err(invalid("invalid compression header"));
+        }
+        let is_builtin = Compression::parse(Some(token)).is_some()
+            || Compression::from_http_token(token).is_some()
+            || Compression::parse_header(token.as_bytes().to_vec()).is_ok();
+        if is_builtin {
+            return Err(invalid("built-in compression"));
+        }
+        self.codecs
+            .insert(token.to_string(), (Box::new(encode), Box::new(decode)));
+        Ok(())
+    }
+
+    /// `true` if a codec is registered for `token`.
+    pub fn contains(&self, token: &str) -> bool {
+        self.codecs.contains_key(token)
+    }
+
+    /// Compress `data` with the codec of `token`, or the built-in compression
+    /// named `token`, e.g. `br`, in the format of `Compression::compress`.
+    ///
+    /// Returns the byte length of the compressed data, without header.
+    pub fn compress<W: Write>(
+        &self,
+        token: &str,
+        data: &[u8],
+        out: &mut W,
+    ) -> Result<usize, std::io::Error> {
+        if let Some(&(ref encode, _)) = self.codecs.get(token) {
+            let compressed = encode(data)?;
+            write_header(out, token.as_bytes(), None)?;
+            write_frame(out, &compressed, None)?;
+            return Ok(compressed.len());
+        }
+        match Compression::parse(Some(token)) {
+            Some(compression) => Ok(compression.compress(data, out)?.after_bytes),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unknown compression {:?}", token),
+            )),
+        }
+    }
+
+    /// Decompress a block compressed by `compress`, or by `Compression::compress`,
+    /// failing if it exceeds `max_bytes` bytes once decompressed.
+    ///
+    /// Returns the token of the compression header and the decompressed bytes.
+    pub fn decompress<R: Read>(
+        &self,
+        inp: &mut R,
+        max_bytes: usize,
+    ) -> Result<(String, Vec<u8>), std::io::Error> {
+        let header = read_header(inp)?;
+        let token = String::from_utf8_lossy(&header).into_owned();
+        let decompressed_bytes = match self.codecs.get(&token) {
+            Some(&(_, ref decode)) => decode(&read_length_prefixed(inp, MAX_COMPRESSED_LENGTH)?)?,
+            None => {
+                let header = Compression::parse_header(header)?;
+                Compression::decompress_block(
+                    inp,
+                    &header,
+                    &DecompressionOptions::with_max_bytes(max_bytes),
+                    Vec::new(),
+                )?
+            }
+        };
+        if decompressed_bytes.len() > max_bytes {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Decompressed block exceeds {} bytes", max_bytes),
+            ));
+        }
+        Ok((token, decompressed_bytes))
+    }
+}
+
+/// A pool of buffers, reused by `Compression::decompress_pooled` to limit
+/// allocations, e.g. in a server decoding many files concurrently.
+///
+/// Clones share the same buffers. Buffers are cleared before they return
+/// to the pool, so their contents never leak into another decompression.
+#[derive(Clone)]
+pub struct BufferPool {
+    buffers: Arc<Mutex<Vec<Vec<u8>>>>,
+
+    /// The maximal number of buffers kept while unused.
+    max_buffers: usize,
+}
+impl BufferPool {
+    /// Create an empty pool, keeping at most `max_buffers` unused buffers.
+    pub fn new(max_buffers: usize) -> Self {
+        BufferPool {
+            buffers: Arc::new(Mutex::new(Vec::with_capacity(max_buffers))),
+            max_buffers,
+        }
+    }
+
+    /// The number of unused buffers in the pool.
+    pub fn len(&self) -> usize {
+        self.buffers
+            .lock()
+            .map(|buffers| buffers.len())
+            .unwrap_or(0)
+    }
+
+    /// `true` if the pool has no unused buffer.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Take an empty buffer from the pool, or allocate one if the pool is empty.
+    fn take(&self) -> Vec<u8> {
+        self.buffers
+            .lock()
+            .ok()
+            .and_then(|mut buffers| buffers.pop())
+            .unwrap_or_else(Vec::new)
+    }
+
+    /// Clear a buffer and return it to the pool, unless the pool is full or
+    /// the buffer exceeds `MAX_POOLED_CAPACITY`.
+    fn give_back(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+        buffer.clear();
+        if let Ok(mut buffers) = self.buffers.lock() {
+            if buffers.len() < self.max_buffers {
+                buffers.push(buffer);
+            }
+        }
+    }
+}
+
+/// Decompressed bytes, in a buffer that returns to its `BufferPool` once dropped.
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: BufferPool,
+}
+impl Deref for PooledBuffer {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let buffer = std::mem::replace(&mut self.buffer, Vec::new());
+        self.pool.give_back(buffer);
+    }
+}
+
+/// A `Read` yielding the decompressed bytes of a single block, whatever
+/// its compression.
+///
+/// The block is decompressed at once when the reader is created, as its
+/// compressed bytes, prefixed by their number, must be read first anyway.
+///
+/// Blocks carry no checksum of their own: the integrity of a multipart
+/// container is checked against the hash of its footer, before any block
+/// is decompressed (see `multipart::footer`).
+pub struct CompressionReader {
+    compression: Compression,
+    decompressed_bytes: Cursor<Vec<u8>>,
+}
+impl CompressionReader {
+    /// Read a block from `inp`, leaving `inp` at the end of the block.
+    ///
+    /// Neither the compressed nor the decompressed bytes may exceed
+    /// `MAX_COMPRESSED_LENGTH`.
+    pub fn new<R: Read>(inp: &mut R) -> Result<Self, std::io::Error> {
+        Self::with_limit(inp, MAX_COMPRESSED_LENGTH)
+    }
+
+    /// As `new`, but fail if the block exceeds `max_bytes` bytes once
+    /// decompressed, as `Compression::decompress_with_limit`.
+    pub fn with_limit<R: Read>(inp: &mut R, max_bytes: usize) -> Result<Self, std::io::Error> {
+        let header = Compression::probe_header(inp)?;
+        let decompressed_bytes = Compression::decompress_block(
+            inp,
+            &header,
+            &DecompressionOptions::with_max_bytes(max_bytes),
+            Vec::new(),
+        )?;
+        Ok(CompressionReader {
+            compression: header.compression,
+            decompressed_bytes: Cursor::new(decompressed_bytes),
+        })
+    }
+
+    /// The compression of the block.
+    pub fn compression(&self) -> &Compression {
+        &self.compression
+    }
+}
+impl Read for CompressionReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        self.decompressed_bytes.read(buf)
+    }
+}
+
+/// Compress `data` to `buffer` as a single brotli stream.
+///
+/// The stream is finished explicitly, rather than when dropping an encoder,
+/// which would ignore errors: it ends with a last meta-block, so that frames
+/// may be concatenated, then decoded independently given their lengths.
+fn compress_brotli(
+    data: &[u8],
+    params: &brotli::enc::BrotliEncoderParams,
+    buffer: &mut Vec<u8>,
+) -> Result<(), std::io::Error> {
+    brotli::BrotliCompress(&mut Cursor::new(data), buffer, params)?;
+    Ok(())
+}
+
+/// Compress `data` with lzw, packing codes with `writer`.
+fn encode_lzw<W: lzw::BitWriter>(writer: W, data: &[u8]) -> Result<(), std::io::Error> {
+    // Dropping `encoder` flushes the last codes.
+    let mut encoder = lzw::Encoder::new(writer, LZW_MIN_CODE_SIZE)?;
+    encoder.encode_bytes(data)
+}
+
+/// Decompress lzw data to `buf`, unpacking codes with `reader`.
+///
+/// The decoder yields the bytes of one code at a time, so decompression
+/// stops as soon as the output exceeds `max_bytes`, rather than after
+/// decoding the entire block.
+fn decode_lzw<R: lzw::BitReader>(
+    reader: R,
+    compressed: &[u8],
+    max_bytes: usize,
+    mut buf: Vec<u8>,
+) -> Result<Vec<u8>, std::io::Error> {
+    let mut decoder = lzw::Decoder::new(reader, LZW_MIN_CODE_SIZE);
+    let mut remaining = compressed;
+    while !remaining.is_empty() {
+        let (consumed, data) = decoder.decode_bytes(remaining)?;
+        if consumed == 0 && data.is_empty() {
+            break;
+        }
+        if buf.len() + data.len() > max_bytes {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Decompressed block exceeds {} bytes", max_bytes),
+            ));
+        }
+        buf.extend_from_slice(data);
+        remaining = &remaining[consumed..];
+    }
+    Ok(buf)
+}
+
+/// Read a compression header, e.g. `br;`, returning it without `;`.
+fn read_header<R: Read>(inp: &mut R) -> Result<Vec<u8>, std::io::Error> {
+    let mut header = Vec::with_capacity(MAX_HEADER_LENGTH);
+
+    // Scan for `;` in the first 32 bytes.
+    for _ in 0..MAX_HEADER_LENGTH {
+        let mut buf = [0];
+        inp.read_exact(&mut buf)?;
+        if buf[0] == b';' {
+            return Ok(header);
+        }
+        header.push(buf[0]);
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        "Invalid compression header",
+    ))
+}
+
+/// Write a compression header, e.g. `br;`, flagged by `+size` if the
+/// block starts with its decompressed byte length.
+fn write_header<W: Write>(
+    out: &mut W,
+    name: &[u8],
+    size_hint: Option<usize>,
+) -> Result<(), std::io::Error> {
+    out.write_all(name)?;
+    if size_hint.is_some() {
+        out.write_all(SIZE_HINT_FLAG)?;
+    }
+    out.write_all(b";")
+}
+
+/// Write a block of compressed bytes as a frame, starting with the
+/// decompressed byte length, if specified.
+fn write_frame<W: Write>(
+    out: &mut W,
+    compressed: &[u8],
+    size_hint: Option<usize>,
+) -> Result<usize, std::io::Error> {
+    match size_hint {
+        None => write_length_prefixed(out, compressed),
+        Some(size) => {
+            let mut hint = Vec::with_capacity(5);
+            hint.write_varnum(size as u32)?;
+            let len = out.write_varnum((hint.len() + compressed.len()) as u32)?;
+            out.write_all(&hint)?;
+            out.write_all(compressed)?;
+            Ok(len + hint.len() + compressed.len())
+        }
+    }
+}
+
+/// The smallest brotli window covering `len` bytes, at least the default
+/// window and at most the maximal large window.
+fn large_window_bits(len: usize) -> u32 {
+    let mut bits = BROTLI_LG_WINDOW_SIZE;
+    // A window of `bits` holds `(1 << bits) - 16` bytes.
+    while bits < BROTLI_MAX_LARGE_LG_WINDOW_SIZE && (1 << bits) - 16 < len {
+        bits += 1;
+    }
+    bits
+}
+
+/// Decompress a brotli stream to `result`, accepting large windows.
+///
+/// `brotli::Decompressor` rejects them, so we drive the decoder ourselves.
+/// Stops early once the result exceeds `max_bytes`.
+fn decompress_large_window(
+    compressed: &[u8],
+    mut result: Vec<u8>,
+    buffer_size: usize,
+    max_bytes: usize,
+) -> Result<Vec<u8>, std::io::Error> {
+    use brotli::enc::StandardAlloc;
+    use brotli::{BrotliDecompressStream, BrotliResult, BrotliState};
+
+    let mut state = BrotliState::new(
+        StandardAlloc::default(),
+        StandardAlloc::default(),
+        StandardAlloc::default(),
+    );
+    state.large_window = true;
+
+    let mut buf = vec![0; buffer_size];
+    let mut available_in = compressed.len();
+    let mut input_offset = 0;
+    let mut total_out = 0;
+    loop {
+        let mut available_out = buf.len();
+        let mut output_offset = 0;
+        let status = BrotliDecompressStream(
+            &mut available_in,
+            &mut input_offset,
+            compressed,
+            &mut available_out,
+            &mut output_offset,
+            &mut buf,
+            &mut total_out,
+            &mut state,
+        );
+        result.extend_from_slice(&buf[..output_offset]);
+        if result.len() > max_bytes {
+            // Reported by the caller.
+            return Ok(result);
+        }
+        match status {
+            BrotliResult::ResultSuccess => return Ok(result),
+            BrotliResult::NeedsMoreOutput => continue,
+            BrotliResult::NeedsMoreInput | BrotliResult::ResultFailure => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Invalid brotli stream",
+                ));
+            }
+        }
+    }
+}
+
+/// Deserialize a bunch of bytes into itself.
+#[cfg(test)]
+struct BufDeserializer;
+#[cfg(test)]
+impl Deserializer for BufDeserializer {
+    type Target = Vec<u8>;
+    fn read<R: Read + Seek>(&self, reader: &mut R) -> Result<Vec<u8>, std::io::Error> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+#[test]
+fn test_negotiate() {
+    assert_eq!(Compression::negotiate(""), Compression::Identity);
+    assert_eq!(
+        Compression::negotiate("gzip, deflate, br"),
+        Compression::Brotli
+    );
+    assert_eq!(
+        Compression::negotiate("br;q=1.0, gzip;q=0.8"),
+        Compression::Brotli
+    );
+    assert_eq!(
+        Compression::negotiate("br;q=0.5, gzip;q=0.8"),
+        Compression::Gzip
+    );
+    assert_eq!(
+        Compression::negotiate("GZIP;Q=0.5, deflate;q=0.5"),
+        Compression::Gzip
+    );
+    assert_eq!(
+        Compression::negotiate("deflate, gzip;q=0"),
+        Compression::Deflate
+    );
+    assert_eq!(Compression::negotiate("*"), Compression::Brotli);
+    assert_eq!(Compression::negotiate("br;q=0, *;q=0.1"), Compression::Gzip);
+    assert_eq!(
+        Compression::negotiate("identity;q=0, *;q=0"),
+        Compression::Identity
+    );
+    assert_eq!(
+        Compression::negotiate("compress, sdch"),
+        Compression::Identity
+    );
+    assert_eq!(
+        Compression::negotiate("gzip;q=2, deflate;q=abc"),
+        Compression::Identity
+    );
+    assert_eq!(
+        Compression::from_http_token("x-gzip"),
+        Some(Compression::Gzip)
+    );
+    assert_eq!(Compression::from_http_token("*"), None);
+}
+
+#[test]
+fn test_input_hash() {
+    let data = b"Some data that may change while we are compressing it".to_vec();
+    let expected = {
+        let mut hasher = XxHash64::with_seed(0);
+        hasher.write(&data);
+        hasher.finish()
+    };
+    for compression in &[
+        Compression::Identity,
+        Compression::Gzip,
+        Compression::Deflate,
+        Compression::Brotli,
+    ] {
+        let result = compression
+            .compress(&data, &mut Vec::new())
+            .expect("Could not compress");
+        assert_eq!(result.input_hash, Some(expected));
+    }
+
+    let mut modified = data.clone();
+    modified[0] ^= 1;
+    assert_ne!(input_hash(&modified), expected);
+}
+
+#[test]
+fn test_gzip_multiple_members() {
+    let mut members = Vec::new();
+    for part in &[&b"first member, "[..], &b"second member"[..]] {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(part).unwrap();
+        members.extend(encoder.finish().unwrap());
+    }
+    let mut framed = Vec::new();
+    framed.write_all(b"gzip;").unwrap();
+    write_length_prefixed(&mut framed, &members).unwrap();
+
+    let decompressed = Compression::decompress(&mut Cursor::new(framed), &BufDeserializer)
+        .expect("Could not decompress");
+    assert_eq!(decompressed, b"first member, second member".to_vec());
+}
+
+#[test]
+fn test_brotli_large_window() {
+    assert_eq!(large_window_bits(0), BROTLI_LG_WINDOW_SIZE);
+    assert_eq!(large_window_bits(1 << 20), 21);
+    assert_eq!(large_window_bits(std::usize::MAX), 30);
+
+    // A block of noise slightly larger than the default window, repeated:
+    // only a large window may refer to the first copy from the second one.
+    let mut state: u32 = 1;
+    let block: Vec<u8> = (0..(3 << 19))
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect();
+    let data = [&block[..], &block[..]].concat();
+
+    let mut default_window = Vec::new();
+    Compression::Brotli
+        .compress(&data, &mut default_window)
+        .expect("Could not compress");
+    let mut large_window = Vec::new();
+    let result = Compression::BrotliLargeWindow
+        .compress(&data, &mut large_window)
+        .expect("Could not compress");
+    assert!(large_window.starts_with(b"br-large;"));
+    assert!(
+        large_window.len() < default_window.len(),
+        "Large window {} should be smaller than default window {}",
+        large_window.len(),
+        default_window.len()
+    );
+    assert!(result.after_bytes < block.len() + block.len() / 10);
+
+    let decompressed = Compression::decompress(&mut Cursor::new(large_window), &BufDeserializer)
+        .expect("Could not decompress");
+    assert_eq!(decompressed, data);
+}
+
+#[test]
+fn test_compress_with_state() {
+    let blocks: Vec<Vec<u8>> = (0..20)
+        .map(|i| format!("block {} {}", i, "abc".repeat(i * 100)).into_bytes())
+        .collect();
+    for compression in Compression::values().iter() {
+        // A state reused across blocks, including a larger block then a
+        // smaller one, yields the same output as a fresh state.
+        let mut state = CompressionState::default();
+        for block in blocks.iter().chain(blocks.iter().rev()) {
+            let mut expected = Vec::new();
+            compression
+                .compress(block, &mut expected)
+                .expect("Could not compress");
+            let mut reused = Vec::new();
+            compression
+                .compress_with_state(block, &mut reused, &mut state)
+                .expect("Could not compress");
+            assert_eq!(reused, expected, "Output of {:?}", compression);
+
+            let decompressed = Compression::decompress(&mut Cursor::new(reused), &BufDeserializer)
+                .expect("Could not decompress");
+            assert_eq!(&decompressed, block);
+        }
+    }
+}
+
+#[test]
+fn test_size_hint() {
+    let data = "Some data, repeated. ".repeat(1000).into_bytes();
+    for compression in Compression::values()
+        .iter()
+        .chain(&[Compression::BrotliLargeWindow])
+    {
+        println!("* {:?} without hint", compression);
+        let mut without_hint = Vec::new();
+        compression
+            .compress_with_state(&data, &mut without_hint, &mut CompressionState::default())
+            .expect("Could not compress");
+        let mut expected = Vec::new();
+        compression
+            .compress(&data, &mut expected)
+            .expect("Could not compress");
+        assert_eq!(without_hint, expected);
+        let decompressed =
+            Compression::decompress(&mut Cursor::new(&without_hint), &BufDeserializer)
+                .expect("Could not decompress");
+        assert_eq!(decompressed, data);
+
+        println!("* {:?} with hint", compression);
+        let mut state = CompressionState::default();
+        state.set_size_hint(true);
+        let mut with_hint = Vec::new();
+        compression
+            .compress_with_state(&data, &mut with_hint, &mut state)
+            .expect("Could not compress");
+        let header = with_hint
+            .iter()
+            .position(|byte| *byte == b';')
+            .expect("Missing header");
+        assert!(with_hint[..header].ends_with(SIZE_HINT_FLAG));
+        assert_eq!(
+            Compression::probe(&mut Cursor::new(&with_hint)).expect("Could not probe"),
+            *compression
+        );
+
+        // The hint is part of the frame, which remains length-prefixed.
+        let mut cursor = Cursor::new(&with_hint[header + 1..]);
+        let frame =
+            read_length_prefixed(&mut cursor, MAX_COMPRESSED_LENGTH).expect("Could not read frame");
+        assert_eq!(cursor.position() as usize, with_hint.len() - header - 1);
+        assert_eq!(
+            Cursor::new(&frame)
+                .read_varnum()
+                .expect("Could not read hint") as usize,
+            data.len()
+        );
+
+        let decompressed = Compression::decompress(&mut Cursor::new(&with_hint), &BufDeserializer)
+            .expect("Could not decompress");
+        assert_eq!(decompressed, data);
+
+        println!("* {:?} with a forged hint", compression);
+        let name = &without_hint[..without_hint.iter().position(|byte| *byte == b';').unwrap()];
+        let compressed = read_length_prefixed(
+            &mut Cursor::new(&without_hint[name.len() + 1..]),
+            MAX_COMPRESSED_LENGTH,
+        )
+        .expect("Could not read frame");
+        for forged in &[data.len() - 1, data.len() + 1, MAX_COMPRESSED_LENGTH] {
+            let mut forged_block = Vec::new();
+            write_header(&mut forged_block, name, Some(*forged)).unwrap();
+            write_frame(&mut forged_block, &compressed, Some(*forged)).unwrap();
+            let error = Compression::decompress_bytes(
+                &mut Cursor::new(&forged_block),
+                &DecompressionOptions::default(),
+                Vec::new(),
+            )
+            .expect_err("Forged hint accepted");
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+}
+
+#[test]
+fn test_decompressed_size_hint() {
+    let data = "Some data, repeated. ".repeat(1000).into_bytes();
+
+    println!("* Gzip stores the decompressed size in its trailer");
+    let mut gzip = b"prefix".to_vec();
+    Compression::Gzip
+        .compress(&data, &mut gzip)
+        .expect("Could not compress");
+    let mut cursor = Cursor::new(&gzip);
+    cursor.set_position(6);
+    assert_eq!(
+        Compression::decompressed_size_hint(&mut cursor),
+        Some(data.len() as u64)
+    );
+    assert_eq!(cursor.position(), 6);
+
+    println!("* Identity stores the size as its byte length");
+    let mut identity = vec![];
+    Compression::Identity
+        .compress(&data, &mut identity)
+        .expect("Could not compress");
+    assert_eq!(
+        Compression::decompressed_size_hint(&mut Cursor::new(&identity)),
+        Some(data.len() as u64)
+    );
+
+    println!("* Brotli only stores the size with an explicit hint");
+    let mut brotli = vec![];
+    Compression::Brotli
+        .compress(&data, &mut brotli)
+        .expect("Could not compress");
+    assert_eq!(
+        Compression::decompressed_size_hint(&mut Cursor::new(&brotli)),
+        None
+    );
+    let mut state = CompressionState::default();
+    state.set_size_hint(true);
+    let mut brotli = vec![];
+    Compression::Brotli
+        .compress_with_state(&data, &mut brotli, &mut state)
+        .expect("Could not compress");
+    assert_eq!(
+        Compression::decompressed_size_hint(&mut Cursor::new(&brotli)),
+        Some(data.len() as u64)
+    );
+
+    println!("* Truncated blocks have no size");
+    assert_eq!(
+        Compression::decompressed_size_hint(&mut Cursor::new(&gzip[6..gzip.len() - 2])),
+        None
+    );
+}
+
+#[test]
+fn test_gzip_reproducible() {
+    let data = "Some data, repeated. ".repeat(100).into_bytes();
+    let compress = |state: &mut CompressionState| {
+        let mut out = vec![];
+        Compression::Gzip
+            .compress_with_state(&data, &mut out, state)
+            .expect("Could not compress");
+        // Skip `gzip;` and the byte length, to get to the gzip header.
+        let start = out.len()
+            - read_length_prefixed(&mut Cursor::new(&out[5..]), MAX_COMPRESSED_LENGTH)
+                .expect("Could not read frame")
+                .len();
+        let mtime = out[start + 4..start + 8].to_vec();
+        (out, mtime)
+    };
+
+    println!("* Compressing the same data twice yields the same bytes");
+    let (first, mtime) = compress(&mut CompressionState::default());
+    let (second, _) = compress(&mut CompressionState::default());
+    assert_eq!(first, second);
+    assert_eq!(mtime, vec![0, 0, 0, 0]);
+
+    println!("* The modification time may be specified");
+    let mut state = CompressionState::default();
+    state.set_gzip_mtime(0x01020304);
+    let (_, mtime) = compress(&mut state);
+    assert_eq!(mtime, vec![4, 3, 2, 1]);
+}
+
+#[test]
+fn test_lzw_msb() {
+    let data = b"TOBEORNOTTOBEORTOBEORNOT#".repeat(10);
+
+    println!("* LSB is the default");
+    let mut lsb = vec![];
+    Compression::Lzw
+        .compress(&data, &mut lsb)
+        .expect("Could not compress");
+    assert!(lsb.starts_with(b"compress;"));
+
+    println!("* MSB streams are flagged and round-trip");
+    let mut msb = vec![];
+    Compression::LzwMsb
+        .compress(&data, &mut msb)
+        .expect("Could not compress");
+    assert!(msb.starts_with(b"compress+msb;"));
+    assert_eq!(
+        Compression::probe(&mut Cursor::new(&msb)).expect("Could not probe"),
+        Compression::LzwMsb
+    );
+    assert_eq!(
+        Compression::parse(Some("lzw-msb")),
+        Some(Compression::LzwMsb)
+    );
+    assert_eq!(Compression::from_http_token("compress+msb"), None);
+    assert_ne!(&msb[b"compress+msb;".len()..], &lsb[b"compress;".len()..]);
+    let decompressed = Compression::decompress(&mut Cursor::new(&msb), &BufDeserializer)
+        .expect("Could not decompress");
+    assert_eq!(decompressed, data);
+
+    println!("* The flag selects the bit order on decode");
+    let mut unflagged = b"compress;".to_vec();
+    unflagged.extend_from_slice(&msb[b"compress+msb;".len()..]);
+    let misread = Compression::decompress(&mut Cursor::new(&unflagged), &BufDeserializer);
+    assert!(misread.map(|bytes| bytes != data).unwrap_or(true));
+}
+
+#[test]
+fn test_decompress_with_limit() {
+    let data = vec![0; 1024 * 1024];
+    for compression in Compression::values().iter() {
+        println!("* Limiting {:?}", compression);
+        let mut compressed = vec![];
+        compression
+            .compress(&data, &mut compressed)
+            .expect("Could not compress");
+
+        let decompressed = Compression::decompress_with_limit(
+            &mut Cursor::new(&compressed),
+            &BufDeserializer,
+            data.len(),
+        )
+        .expect("Could not decompress within the limit");
+        assert_eq!(decompressed, data);
+
+        let result = Compression::decompress_with_limit(
+            &mut Cursor::new(&compressed),
+            &BufDeserializer,
+            data.len() - 1,
+        );
+        assert!(result.is_err());
+    }
+}
+
+#[test]
+fn test_brotli_concatenated_frames() {
+    let first = "first frame, repeated. ".repeat(100).into_bytes();
+    let second = "second frame, also repeated. ".repeat(200).into_bytes();
+
+    println!("* Raw frames, concatenated, decode independently given their lengths");
+    let mut concatenated = Vec::new();
+    let mut lengths = Vec::new();
+    for data in &[&first, &second] {
+        let mut frame = Vec::new();
+        let mut params = brotli::enc::BrotliEncoderParams::default();
+        params.quality = BROTLI_QUALITY as i32;
+        params.lgwin = BROTLI_LG_WINDOW_SIZE as i32;
+        compress_brotli(data, &params, &mut frame).expect("Could not compress");
+        lengths.push(frame.len());
+        concatenated.extend(frame);
+    }
+    let mut start = 0;
+    for (data, len) in [&first, &second].iter().zip(&lengths) {
+        let mut decompressed = Vec::new();
+        brotli::BrotliDecompress(
+            &mut Cursor::new(&concatenated[start..start + len]),
+            &mut decompressed,
+        )
+        .expect("Could not decompress frame");
+        assert_eq!(&decompressed, *data);
+        start += len;
+    }
+    assert_eq!(start, concatenated.len());
+
+    println!("* Blocks, concatenated, decode one after the other");
+    let mut blocks = Vec::new();
+    for compression in &[Compression::Brotli, Compression::BrotliLargeWindow] {
+        blocks.clear();
+        compression
+            .compress(&first, &mut blocks)
+            .expect("Could not compress");
+        compression
+            .compress(&second, &mut blocks)
+            .expect("Could not compress");
+        let mut inp = Cursor::new(&blocks);
+        let decompressed =
+            Compression::decompress(&mut inp, &BufDeserializer).expect("Could not decompress");
+        assert_eq!(decompressed, first);
+        let decompressed =
+            Compression::decompress(&mut inp, &BufDeserializer).expect("Could not decompress");
+        assert_eq!(decompressed, second);
+        assert_eq!(inp.position() as usize, blocks.len());
+    }
+}
+
+#[test]
+fn test_estimate_ratio() {
+    // Noise, from a linear congruential generator.
+    let mut state: u32 = 1;
+    let random: Vec<u8> = (0..100_000)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect();
+    let ratio = Compression::estimate_ratio(&random);
+    assert!(ratio > 0.95, "Random data estimated at {}", ratio);
+
+    // Redundancy that order-0 entropy cannot see: a block of noise, repeated.
+    let repeated: Vec<u8> = random[..1000].repeat(100);
+    let ratio = Compression::estimate_ratio(&repeated);
+    assert!(ratio < 0.2, "Repeated noise estimated at {}", ratio);
+
+    let repetitive = "abcd".repeat(100_000).into_bytes();
+    let ratio = Compression::estimate_ratio(&repetitive);
+    assert!(ratio < 0.05, "Repetitive data estimated at {}", ratio);
+
+    assert_eq!(Compression::estimate_ratio(&[]), 1.);
+    assert_eq!(Compression::estimate_ratio(&[0; 10_000]), 0.);
+}
+
+#[test]
+fn test_decompress_pooled() {
+    let pool = BufferPool::new(1);
+    let long = vec![42; 64 * 1024];
+    let short = b"short".to_vec();
+    for compression in Compression::values().iter() {
+        println!("* Pooling {:?}", compression);
+        let mut compressed_long = vec![];
+        compression
+            .compress(&long, &mut compressed_long)
+            .expect("Could not compress");
+        let mut compressed_short = vec![];
+        compression
+            .compress(&short, &mut compressed_short)
+            .expect("Could not compress");
+
+        let decompressed = Compression::decompress_pooled(
+            &mut Cursor::new(&compressed_long),
+            &pool,
+            std::usize::MAX,
+        )
+        .expect("Could not decompress");
+        assert_eq!(&*decompressed, &long[..]);
+        drop(decompressed);
+        assert_eq!(pool.len(), 1);
+
+        println!("* Reused buffers hold none of their previous contents");
+        let decompressed = Compression::decompress_pooled(
+            &mut Cursor::new(&compressed_short),
+            &pool,
+            std::usize::MAX,
+        )
+        .expect("Could not decompress");
+        assert_eq!(pool.len(), 0);
+        assert_eq!(&*decompressed, &short[..]);
+        drop(decompressed);
+
+        println!("* Limits still apply");
+        assert!(Compression::decompress_pooled(
+            &mut Cursor::new(&compressed_long),
+            &pool,
+            long.len() - 1
+        )
+        .is_err());
+    }
+
+    println!("* The pool keeps at most `max_buffers` buffers");
+    let mut compressed = vec![];
+    Compression::Identity
+        .compress(&short, &mut compressed)
+        .expect("Could not compress");
+    let buffers: Vec<_> = (0..3)
+        .map(|_| {
+            Compression::decompress_pooled(&mut Cursor::new(&compressed), &pool, std::usize::MAX)
+                .expect("Could not decompress")
+        })
+        .collect();
+    drop(buffers);
+    assert_eq!(pool.len(), 1);
+
+    println!("* The pool drops buffers exceeding `MAX_POOLED_CAPACITY`");
+    let pool = BufferPool::new(1);
+    let huge = vec![42; MAX_POOLED_CAPACITY + 1];
+    let mut compressed = vec![];
+    Compression::Identity
+        .compress(&huge, &mut compressed)
+        .expect("Could not compress");
+    let decompressed =
+        Compression::decompress_pooled(&mut Cursor::new(&compressed), &pool, std::usize::MAX)
+            .expect("Could not decompress");
+    assert_eq!(&*decompressed, &huge[..]);
+    drop(decompressed);
+    assert_eq!(pool.len(), 0);
+}
+
+#[test]
+fn test_empty_marker() {
+    let mut state = CompressionState::default();
+    state.set_empty_marker(true);
+    for compression in Compression::values().iter() {
+        println!("* Empty block with {:?}", compression);
+        let mut compressed = vec![];
+        let result = compression
+            .compress_with_state(&[], &mut compressed, &mut state)
+            .expect("Could not compress");
+        assert_eq!(compressed, EMPTY_BLOCK_MARKER);
+        assert_eq!(result.after_bytes, 0);
+        assert_eq!(
+            Compression::probe_with_len(&mut Cursor::new(&compressed)).unwrap(),
+            (Compression::Identity, 0)
+        );
+        assert_eq!(
+            Compression::decompressed_size_hint(&mut Cursor::new(&compressed)),
+            Some(0)
+        );
+        let decompressed = Compression::decompress(&mut Cursor::new(&compressed), &BufDeserializer)
+            .expect("Could not decompress");
+        assert_eq!(decompressed, Vec::<u8>::new());
+
+        println!("* One-byte block with {:?}", compression);
+        let mut compressed = vec![];
+        compression
+            .compress_with_state(&[42], &mut compressed, &mut state)
+            .expect("Could not compress");
+        assert!(compressed.len() > EMPTY_BLOCK_MARKER.len());
+        assert_eq!(
+            Compression::probe(&mut Cursor::new(&compressed)).unwrap(),
+            *compression
+        );
+        let decompressed = Compression::decompress(&mut Cursor::new(&compressed), &BufDeserializer)
+            .expect("Could not decompress");
+        assert_eq!(decompressed, vec![42]);
+    }
+}
+
+#[test]
+fn test_transcode() {
+    let data = b"function foo(x, y) { return x + y; } foo(1, 2); foo(3, 4);".repeat(16);
+    let mut brotli = vec![];
+    Compression::Brotli
+        .compress(&data, &mut brotli)
+        .expect("Could not compress");
+    // Followed by another block, which must be left alone.
+    Compression::Identity
+        .compress(b"next", &mut brotli)
+        .expect("Could not compress");
+
+    println!("* Transcoding a brotli block to gzip");
+    let mut inp = Cursor::new(&brotli);
+    let mut gzip = vec![];
+    let result = Compression::transcode(&mut inp, &mut gzip, Compression::Gzip)
+        .expect("Could not transcode");
+    assert_eq!(result.before_bytes, data.len());
+    assert_eq!(
+        Compression::probe(&mut Cursor::new(&gzip)).unwrap(),
+        Compression::Gzip
+    );
+
+    println!("* Decompressing both yields the same bytes");
+    let original = Compression::decompress(&mut Cursor::new(&brotli), &BufDeserializer)
+        .expect("Could not decompress original");
+    let transcoded = Compression::decompress(&mut Cursor::new(&gzip), &BufDeserializer)
+        .expect("Could not decompress transcoded");
+    assert_eq!(original, transcoded);
+    assert_eq!(transcoded, data);
+
+    println!("* The reader is left at the next block");
+    let next = Compression::decompress(&mut inp, &BufDeserializer).expect("Could not decompress");
+    assert_eq!(next, b"next");
+}
+
+#[test]
+fn test_roundtrip() {
+    let data = b"function foo(x, y) { return x + y; } foo(1, 2); foo(3, 4);".repeat(16);
+    for compression in Compression::values().iter().chain(&[
+        Compression::BrotliLargeWindow,
+        Compression::Lzw,
+        Compression::LzwMsb,
+    ]) {
+        println!("* Round-tripping {:?}", compression);
+        let recovered =
+            Compression::roundtrip(&data, compression.clone()).expect("Could not round-trip");
+        assert_eq!(recovered, data);
+    }
+
+    println!("* A truncated frame is reported as an error");
+    let mut compressed = vec![];
+    Compression::Brotli
+        .compress(&data, &mut compressed)
+        .expect("Could not compress");
+    let truncated = &compressed[..compressed.len() - 1];
+    assert!(Compression::decompress_bytes(
+        &mut Cursor::new(truncated),
+        &DecompressionOptions::default(),
+        Vec::new()
+    )
+    .is_err());
+}
+
+#[test]
+fn test_compression_reader() {
+    let data = b"function foo(x, y) { return x + y; } foo(1, 2); foo(3, 4);".repeat(16);
+    for compression in Compression::values()
+        .iter()
+        .chain(&[Compression::BrotliLargeWindow, Compression::Lzw])
+    {
+        println!("* Reading {:?} through CompressionReader", compression);
+        let mut compressed = vec![];
+        compression
+            .compress(&data, &mut compressed)
+            .expect("Could not compress");
+        // Followed by another block, which must be left alone.
+        Compression::Identity
+            .compress(b"next", &mut compressed)
+            .expect("Could not compress");
+
+        let mut inp = Cursor::new(&compressed);
+        let mut reader = CompressionReader::new(&mut inp).expect("Could not read block");
+        assert_eq!(reader.compression(), compression);
+        let mut decompressed = vec![];
+        reader
+            .read_to_end(&mut decompressed)
+            .expect("Could not decompress");
+        assert_eq!(decompressed, data);
+
+        let mut next = vec![];
+        CompressionReader::new(&mut inp)
+            .expect("Could not read next block")
+            .read_to_end(&mut next)
+            .expect("Could not decompress next block");
+        assert_eq!(next, b"next");
+    }
+
+    println!("* Blocks exceeding the limit are rejected");
+    let mut compressed = vec![];
+    Compression::Brotli
+        .compress(&data, &mut compressed)
+        .expect("Could not compress");
+    assert!(CompressionReader::with_limit(&mut Cursor::new(&compressed), data.len() - 1).is_err());
+}
+
+#[test]
+fn test_sniff() {
+    for compression in Compression::values().iter() {
+        let mut block = vec![];
+        compression
+            .compress(b"Some data to sniff", &mut block)
+            .expect("Could not compress");
+
+        let (sniffed, header_len) = Compression::sniff(&block).expect("Could not sniff");
+        assert_eq!(sniffed, *compression);
+        assert_eq!(block[header_len - 1], b';');
+        assert_eq!(
+            Compression::probe(&mut Cursor::new(&block)).expect("Could not probe"),
+            sniffed
+        );
+
+        // The header alone is enough.
+        assert_eq!(
+            Compression::sniff(&block[..header_len]),
+            Some((compression.clone(), header_len))
+        );
+        // A truncated header is not.
+        assert_eq!(Compression::sniff(&block[..header_len - 1]), None);
+    }
+
+    // Flags are part of the header.
+    assert_eq!(
+        Compression::sniff(b"br+size;\x10"),
+        Some((Compression::Brotli, 8))
+    );
+    assert_eq!(
+        Compression::sniff(b"compress+msb;"),
+        Some((Compression::LzwMsb, 13))
+    );
+
+    // Unknown prefixes are rejected.
+    assert_eq!(Compression::sniff(b"zstd;\x10"), None);
+    assert_eq!(Compression::sniff(&[b'x'; 64]), None);
+}
+
+#[test]
+fn test_decompress_borrowed() {
+    let data = b"Some data, borrowed rather than copied if uncompressed".to_vec();
+    for compression in Compression::values().iter() {
+        for size_hint in &[false, true] {
+            println!("* {:?}, size hint: {}", compression, size_hint);
+            let mut state = CompressionState::default();
+            state.set_size_hint(*size_hint);
+            let mut block = vec![];
+            compression
+                .compress_with_state(&data, &mut block, &mut state)
+                .expect("Could not compress");
+            // Followed by another block, which should be left alone.
+            let next = block.len();
+            block.extend_from_slice(b"identity;");
+
+            let mut inp = Cursor::new(&block[..]);
+            let borrowed =
+                Compression::decompress_borrowed(&mut inp, &BytesDeserializer, std::usize::MAX)
+                    .expect("Could not decompress borrowed");
+            assert_eq!(inp.position(), next as u64);
+            let copied = Compression::decompress_bytes(
+                &mut Cursor::new(&block[..]),
+                &DecompressionOptions::default(),
+                vec![],
+            )
+            .expect("Could not decompress");
+            assert_eq!(*borrowed, copied[..]);
+            assert_eq!(*borrowed, data[..]);
+
+            // Only uncompressed blocks are borrowed.
+            match borrowed {
+                Cow::Borrowed(_) => assert_eq!(*compression, Compression::Identity),
+                Cow::Owned(_) => assert!(*compression != Compression::Identity),
+            }
+
+            // Limits are enforced, whether borrowed or not.
+            assert!(Compression::decompress_borrowed(
+                &mut Cursor::new(&block[..]),
+                &BytesDeserializer,
+                data.len() - 1
+            )
+            .is_err());
+        }
+    }
+
+    // Truncated blocks are rejected.
+    let mut block = vec![];
+    Compression::Identity
+        .compress(&data, &mut block)
+        .expect("Could not compress");
+    block.pop();
+    assert!(Compression::decompress_borrowed(
+        &mut Cursor::new(&block[..]),
+        &BytesDeserializer,
+        std::usize::MAX
+    )
+    .is_err());
+}
+
+#[test]
+fn test_codec_registry() {
+    let xor = |data: &[u8]| -> Result<Vec<u8>, std::io::Error> {
+        Ok(data.iter().map(|byte| byte ^ 0x5A).collect())
+    };
+    let mut registry = CodecRegistry::new();
+    registry
+        .register("xor", xor, xor)
+        .expect("Could not register");
+    assert!(registry.contains("xor"));
+
+    // Built-in tokens and their aliases are rejected, as are invalid headers.
+    for token in &[
+        "br",
+        "gzip",
+        "x-gzip",
+        "compress",
+        "compress+msb",
+        "lzw",
+        "identity",
+        "random",
+        "",
+        "a;b",
+        "xor+size",
+    ] {
+        assert!(
+            registry.register(token, xor, xor).is_err(),
+            "Registered {:?}",
+            token
+        );
+    }
+    assert!(!registry.contains("br"));
+
+    let data = "Some data, repeated. ".repeat(100).into_bytes();
+    let mut compressed = Vec::new();
+    let len = registry
+        .compress("xor", &data, &mut compressed)
+        .expect("Could not compress");
+    assert_eq!(len, data.len());
+    assert!(compressed.starts_with(b"xor;"));
+
+    let (token, decompressed) = registry
+        .decompress(&mut Cursor::new(&compressed), std::usize::MAX)
+        .expect("Could not decompress");
+    assert_eq!(token, "xor");
+    assert_eq!(decompressed, data);
+
+    // Built-in tokens are dispatched to `Compression`.
+    let mut compressed = Vec::new();
+    registry
+        .compress("br", &data, &mut compressed)
+        .expect("Could not compress");
+    assert!(compressed.starts_with(b"br;"));
+    let (token, decompressed) = registry
+        .decompress(&mut Cursor::new(&compressed), std::usize::MAX)
+        .expect("Could not decompress");
+    assert_eq!(token, "br");
+    assert_eq!(decompressed, data);
+
+    // Without the codec, the block cannot be decompressed.
+    let mut compressed = Vec::new();
+    registry
+        .compress("xor", &data, &mut compressed)
+        .expect("Could not compress");
+    assert!(CodecRegistry::new()
+        .decompress(&mut Cursor::new(&compressed), std::usize::MAX)
+        .is_err());
+    assert!(registry
+        .decompress(&mut Cursor::new(&compressed), 10)
+        .is_err());
+    assert!(registry.compress("rot13", &data, &mut Vec::new()).is_err());
+}
+
+#[test]
+fn test_decompression_buffer_size() {
+    let data: Vec<u8> = (0..200_000u32)
+        .flat_map(|i| format!("{} ", i % 1000).into_bytes())
+        .collect();
+    for compression in Compression::values()
+        .iter()
+        .chain(&[Compression::BrotliLargeWindow, Compression::Lzw])
+    {
+        let mut compressed = Vec::new();
+        compression
+            .compress(&data, &mut compressed)
+            .expect("Could not compress");
+        for &buffer_size in &[0, 1, 4096, DEFAULT_READ_BUFFER_SIZE, 1 << 20] {
+            let options = DecompressionOptions {
+                buffer_size,
+                ..DecompressionOptions::default()
+            };
+            let decompressed = Compression::decompress_with_options(
+                &mut Cursor::new(&compressed),
+                &BufDeserializer,
+                &options,
+            )
+            .expect("Could not decompress");
+            assert!(
+                decompressed == data,
+                "Output of {:?} with a buffer of {} bytes",
+                compression,
+                buffer_size
+            );
+        }
+
+        // Limits are enforced, whatever the buffer size.
+        let options = DecompressionOptions {
+            max_bytes: data.len() - 1,
+            buffer_size: 4096,
+        };
+        assert!(Compression::decompress_with_options(
+            &mut Cursor::new(&compressed),
+            &BufDeserializer,
+            &options,
+        )
+        .is_err());
     }
 }
@@ -0,0 +1,129 @@
+//! Reading/writing length-prefixed frames, i.e. a byte length (`varnum`)
+//! followed by exactly that many bytes.
+
+use bytes::varnum::*;
+
+use std;
+use std::io::{Read, Write};
+
+/// Write `data` as a frame.
+///
+/// Returns the number of bytes written, including the length.
+pub fn write_length_prefixed<W: Write>(out: &mut W, data: &[u8]) -> Result<usize, std::io::Error> {
+    let len = out.write_varnum(data.len() as u32)?;
+    out.write_all(data)?;
+    Ok(len + data.len())
+}
+
+/// Read exactly `len` bytes.
+///
+/// Unlike `read_exact` into a buffer of `len` bytes, this allocates no more
+/// than (roughly) the bytes actually available, so a corrupted length read
+/// from the input cannot cause a huge allocation.
+pub fn read_exactly<R: Read>(inp: &mut R, len: usize) -> Result<Vec<u8>, std::io::Error> {
+    let mut buf = vec![];
+    read_exactly_into(inp, len, &mut buf)?;
+    Ok(buf)
+}
+
+/// As `read_exactly`, appending the bytes to `buf`, e.g. a reused buffer.
+pub fn read_exactly_into<R: Read>(
+    inp: &mut R,
+    len: usize,
+    buf: &mut Vec<u8>,
+) -> Result<(), std::io::Error> {
+    let start = buf.len();
+    inp.by_ref().take(len as u64).read_to_end(buf)?;
+    if buf.len() - start != len {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("Expected {} bytes, found {}", len, buf.len() - start),
+        ));
+    }
+    Ok(())
+}
+
+/// Read a frame, rejecting frames longer than `max` bytes.
+///
+/// The length is checked before reading anything, and the bytes are read
+/// with `read_exactly`, so a corrupted length cannot cause a huge allocation,
+/// even with a large `max`.
+pub fn read_length_prefixed<R: Read>(inp: &mut R, max: usize) -> Result<Vec<u8>, std::io::Error> {
+    let mut buf = vec![];
+    read_length_prefixed_into(inp, max, &mut buf)?;
+    Ok(buf)
+}
+
+/// As `read_length_prefixed`, appending the bytes to `buf`, e.g. a reused
+/// buffer.
+pub fn read_length_prefixed_into<R: Read>(
+    inp: &mut R,
+    max: usize,
+    buf: &mut Vec<u8>,
+) -> Result<(), std::io::Error> {
+    let len = inp.read_varnum()? as usize;
+    if len > max {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Frame length {} exceeds maximal length {}", len, max),
+        ));
+    }
+    read_exactly_into(inp, len, buf)
+}
+
+#[test]
+fn test_length_prefixed() {
+    use std::io::Cursor;
+
+    println!("* Frames round-trip");
+    let mut data = vec![];
+    for frame in &[&b""[..], &b"a"[..], &[42; 300][..]] {
+        let written = write_length_prefixed(&mut data, frame).expect("Writing frame");
+        assert_eq!(written, frame.len() + if frame.len() < 128 { 1 } else { 2 });
+    }
+    let mut cursor = Cursor::new(&data);
+    assert_eq!(read_length_prefixed(&mut cursor, 300).unwrap(), b"");
+    assert_eq!(read_length_prefixed(&mut cursor, 300).unwrap(), b"a");
+    assert_eq!(
+        read_length_prefixed(&mut cursor, 300).unwrap(),
+        vec![42; 300]
+    );
+
+    println!("* Frames longer than the maximal length are rejected");
+    let mut cursor = Cursor::new(&data);
+    read_length_prefixed(&mut cursor, 0).unwrap();
+    read_length_prefixed(&mut cursor, 1).unwrap();
+    let err = read_length_prefixed(&mut cursor, 299).expect_err("Frame should be rejected");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    println!("* Truncated frames are rejected");
+    let mut cursor = Cursor::new(&data[..data.len() - 1]);
+    read_length_prefixed(&mut cursor, 300).unwrap();
+    read_length_prefixed(&mut cursor, 300).unwrap();
+    let err = read_length_prefixed(&mut cursor, 300).expect_err("Frame should be rejected");
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+
+    println!("* Truncated frames are rejected, whatever the maximal length");
+    let mut truncated = vec![];
+    truncated.write_varnum(std::u32::MAX).unwrap();
+    truncated.extend_from_slice(b"abc");
+    let err = read_length_prefixed(&mut Cursor::new(&truncated), std::usize::MAX)
+        .expect_err("Frame should be rejected");
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_read_exactly() {
+    use std::io::Cursor;
+
+    println!("* Reading exactly the bytes available");
+    let mut cursor = Cursor::new(vec![1, 2, 3]);
+    assert_eq!(read_exactly(&mut cursor, 2).unwrap(), vec![1, 2]);
+    assert_eq!(read_exactly(&mut cursor, 1).unwrap(), vec![3]);
+    assert_eq!(read_exactly(&mut cursor, 0).unwrap(), vec![]);
+
+    println!("* Huge lengths are rejected without allocating them");
+    let mut cursor = Cursor::new(vec![1, 2, 3]);
+    let err = read_exactly(&mut cursor, std::usize::MAX).expect_err("Read should be rejected");
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
@@ -1,14 +1,23 @@
 //! Tools for manipulating byte-level data.
 
+/// Reading/writing bit-level data.
+pub mod bits;
+
 /// Encoding/decoding booleans.
 pub mod bool;
 
+/// Splitting data into content-defined chunks.
+pub mod chunk;
+
 /// Compressing/decompressing from/to common formats.
 pub mod compress;
 
 /// Decoders for streams of floats, etc.
 pub mod decoders;
 
+/// Reading/writing length-prefixed frames.
+pub mod frame;
+
 /// Encoding/decoding floating-point numbers.
 pub mod float;
 
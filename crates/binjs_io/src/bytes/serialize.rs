@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::io::{Error, Read, Seek, Write};
 
 pub trait Serializer<T>
@@ -15,3 +16,23 @@ where
     type Target;
     fn read<R: Read + Seek>(&self, &mut R) -> Result<Self::Target, Error>;
 }
+
+/// As `Deserializer`, but deserializing the bytes of a block read from a
+/// slice, which are borrowed from the slice, rather than copied, if the block
+/// is uncompressed (see `Compression::decompress_borrowed`).
+pub trait BorrowingDeserializer<'a>
+where
+    Self::Target: Sized,
+{
+    type Target;
+    fn read_bytes(&self, Cow<'a, [u8]>) -> Result<Self::Target, Error>;
+}
+
+/// Deserialize the bytes of a block into themselves, borrowed if possible.
+pub struct BytesDeserializer;
+impl<'a> BorrowingDeserializer<'a> for BytesDeserializer {
+    type Target = Cow<'a, [u8]>;
+    fn read_bytes(&self, bytes: Cow<'a, [u8]>) -> Result<Self::Target, Error> {
+        Ok(bytes)
+    }
+}
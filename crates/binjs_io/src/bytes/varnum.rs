@@ -8,10 +8,24 @@ pub const VARNUM_INVALID_ZERO_4: [u8; 5] = [1, 1, 1, 1, 0];
 pub const VARNUM_INVALID_ZERO_5: [u8; 6] = [1, 1, 1, 1, 1, 0];
 pub const VARNUM_INVALID_ZERO_6: [u8; 7] = [1, 1, 1, 1, 1, 1, 0];
 
+/// The maximal number of bytes of a varnum holding a `u32`.
+///
+/// Each byte holds 7 bits, so `ceil(32 / 7)`.
+pub const VARNUM_MAX_BYTES_U32: usize = 5;
+
+/// The maximal number of bytes of a varnum holding a `u64`, i.e. `ceil(64 / 7)`.
+pub const VARNUM_MAX_BYTES_U64: usize = 10;
+
 pub trait WriteVarNum {
     fn write_maybe_varnum(&mut self, value: Option<u32>) -> Result<usize, std::io::Error>;
     fn write_varnum(&mut self, num: u32) -> Result<usize, std::io::Error>;
     fn write_signed_varnum(&mut self, num: i32) -> Result<usize, std::io::Error>;
+
+    /// As `write_varnum`, but with the most significant 7 bits first, for
+    /// interoperability with external tools.
+    ///
+    /// Not used by any of the formats of this crate.
+    fn write_varnum_be(&mut self, num: u32) -> Result<usize, std::io::Error>;
 }
 
 pub trait ReadVarNum {
@@ -25,6 +39,9 @@ pub trait ReadVarNum {
     /// Used to implement e.g. `read_varfloat`.
     fn read_extended_varnum_to(&mut self, num: &mut u32) -> Result<usize, std::io::Error>;
     fn read_extended_signed_varnum_to(&mut self, num: &mut i32) -> Result<usize, std::io::Error>;
+
+    /// Read a varnum written by `write_varnum_be`.
+    fn read_varnum_be(&mut self) -> Result<u32, std::io::Error>;
 }
 
 impl<T> WriteVarNum for T
@@ -62,6 +79,26 @@ where
         let as_unsigned = i32_to_u32(value);
         return self.write_varnum(as_unsigned);
     }
+
+    fn write_varnum_be(&mut self, mut value: u32) -> Result<usize, std::io::Error> {
+        // Same bytes as `write_varnum`, in reverse order, with the
+        // continuation bit set on all bytes but the last one.
+        let mut bytes = Vec::with_capacity(4);
+        loop {
+            let mut byte = ((value & 0x7F) << 1) as u8;
+            if !bytes.is_empty() {
+                byte |= 1;
+            }
+            bytes.push(byte);
+            value >>= 7;
+            if value == 0 {
+                break;
+            }
+        }
+        bytes.reverse();
+        self.write_all(&bytes)?;
+        Ok(bytes.len())
+    }
 }
 
 /// Convert an i32 to a u32 fit for writing as varnum.
@@ -151,10 +188,13 @@ where
         let mut shift: u32 = 0;
         let mut buf: [u8; 1] = [0];
         loop {
-            if shift >= 32 {
+            if bytes >= VARNUM_MAX_BYTES_U32 {
                 return Err(std::io::Error::new(
                     std::io::ErrorKind::InvalidData,
-                    "Invalid varnum (doesn't fit in 32 bits)",
+                    format!(
+                        "Invalid varnum (longer than {} bytes)",
+                        VARNUM_MAX_BYTES_U32
+                    ),
                 ));
             }
             bytes += self.read(&mut buf)?;
@@ -179,6 +219,33 @@ where
         }
         Ok(bytes)
     }
+
+    fn read_varnum_be(&mut self) -> Result<u32, std::io::Error> {
+        let mut result: u32 = 0;
+        let mut buf: [u8; 1] = [0];
+        for _ in 0..VARNUM_MAX_BYTES_U32 {
+            if result > std::u32::MAX >> 7 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Invalid varnum (larger than 32 bits)",
+                ));
+            }
+            self.read_exact(&mut buf)?;
+
+            let byte = buf[0];
+            result = (result << 7) | (byte as u32 >> 1);
+            if byte & 1 == 0 {
+                return Ok(result);
+            }
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "Invalid varnum (longer than {} bytes)",
+                VARNUM_MAX_BYTES_U32
+            ),
+        ))
+    }
 }
 
 #[test]
@@ -234,3 +301,66 @@ fn test_varnum() {
     }
     test_one_maybe_value(None);
 }
+
+#[test]
+fn test_varnum_max_bytes() {
+    use std::io::Cursor;
+
+    // The longest varnums.
+    let mut encoded = vec![];
+    encoded.write_varnum(std::u32::MAX).unwrap();
+    assert_eq!(encoded.len(), VARNUM_MAX_BYTES_U32);
+    assert_eq!(Cursor::new(encoded).read_varnum().unwrap(), std::u32::MAX);
+
+    // Continuation is the lowest bit, so `0x80` ends a varnum (unlike the
+    // varnums of `context::varnum`).
+    let mut cursor = Cursor::new(vec![0x80; 11]);
+    assert_eq!(cursor.read_varnum().unwrap(), 0x40);
+    assert_eq!(cursor.position(), 1);
+
+    // A run of continuation bytes is rejected after the maximal width.
+    for byte in &[0x01, 0x81, 0xFF] {
+        let mut cursor = Cursor::new(vec![*byte; 11]);
+        assert!(cursor.read_varnum().is_err());
+        assert_eq!(cursor.position(), VARNUM_MAX_BYTES_U32 as u64);
+        let mut cursor = Cursor::new(vec![*byte; 11]);
+        assert!(cursor.read_maybe_varnum().is_err());
+    }
+}
+
+#[test]
+fn test_varnum_be() {
+    use std::io::Cursor;
+
+    for value in &[0, 1, 0x7F, 0x80, 300, 0xABCDEF, std::u32::MAX] {
+        println!("test_varnum_be, testing with {}", value);
+        let mut le = vec![];
+        le.write_varnum(*value).unwrap();
+        let mut be = vec![];
+        let encoded_bytes = be.write_varnum_be(*value).unwrap();
+        assert_eq!(encoded_bytes, be.len());
+        assert_eq!(be.len(), le.len());
+        println!("test_varnum_be, encoded as {:?} (LE {:?})", be, le);
+
+        // Multi-byte encodings differ.
+        assert_eq!(be == le, *value <= 0x7F);
+
+        let mut cursor = Cursor::new(be);
+        assert_eq!(cursor.read_varnum_be().unwrap(), *value);
+        assert_eq!(cursor.position(), encoded_bytes as u64);
+        assert_eq!(Cursor::new(le).read_varnum().unwrap(), *value);
+    }
+
+    // 300 is 0b10_0101100, so two 7-bit groups, the most significant first.
+    let mut encoded = vec![];
+    encoded.write_varnum_be(300).unwrap();
+    assert_eq!(encoded, vec![(0b10 << 1) | 1, 0b0101100 << 1]);
+
+    // Values that do not fit in 32 bits are rejected.
+    assert!(Cursor::new(vec![0xFF; 5]).read_varnum_be().is_err());
+    assert!(Cursor::new(vec![0x21, 0xFF, 0xFF, 0xFF, 0xFE])
+        .read_varnum_be()
+        .is_err());
+    // Truncated input, too.
+    assert!(Cursor::new(vec![0x03]).read_varnum_be().is_err());
+}
@@ -12,6 +12,8 @@
 //! A first bit of `1` indicates a non-last byte.
 //! A first bit of `0` indicates a last byte.
 
+use bytes::varnum::VARNUM_MAX_BYTES_U32;
+
 use std::io::{self, Error, Read, Result, Write};
 
 // --------------- Reading
@@ -67,7 +69,7 @@ where
                 });
             }
 
-            if shift >= 32 {
+            if (shift / 7) as usize >= VARNUM_MAX_BYTES_U32 {
                 return Err(Error::new(
                     io::ErrorKind::InvalidData,
                     "Overflow during read_varu32_no_normalization (too many digits)",
@@ -160,6 +162,17 @@ fn test_read_varu32_no_normalization_must_fail() {
         expect_error(&buf, io::ErrorKind::InvalidData);
     }
     buf.clear();
+
+    // A run of continuation bytes is rejected after the maximal width,
+    // rather than consumed.
+    buf.resize(11, 0b10000000);
+    {
+        expect_error(&buf, io::ErrorKind::InvalidData);
+        let mut cursor = io::Cursor::new(&buf);
+        assert!(cursor.read_varu32_no_normalization().is_err());
+        assert_eq!(cursor.position(), VARNUM_MAX_BYTES_U32 as u64);
+    }
+    buf.clear();
 }
 
 // ---------- Writing
@@ -759,6 +759,89 @@ impl<T> UserExtensibleData<T> {
             + unsigned_long_instances.len()
     }
 }
+impl UserExtensibleData<FilesContaining> {
+    /// Merge the statistics of `other` into `self`.
+    ///
+    /// Values appearing in either `self` or `other` are kept, and the number of
+    /// files containing each value is the sum of both numbers. Use this to update
+    /// a dictionary that is maintained over time with a newly sampled set of files.
+    pub fn merge(&mut self, other: &Self) {
+        // Make sure that we don't forget a field.
+        let UserExtensibleData {
+            ref identifier_name_instances,
+            ref property_key_instances,
+            ref interface_name_instances,
+            ref string_literal_instances,
+            ref string_enum_instances,
+            ref list_length_instances,
+            ref float_instances,
+            ref unsigned_long_instances,
+        } = *other;
+        Self::merge_table(
+            &mut self.identifier_name_instances,
+            identifier_name_instances,
+        );
+        Self::merge_table(&mut self.property_key_instances, property_key_instances);
+        Self::merge_table(&mut self.interface_name_instances, interface_name_instances);
+        Self::merge_table(&mut self.string_literal_instances, string_literal_instances);
+        Self::merge_table(&mut self.string_enum_instances, string_enum_instances);
+        Self::merge_table(&mut self.list_length_instances, list_length_instances);
+        Self::merge_table(&mut self.float_instances, float_instances);
+        Self::merge_table(&mut self.unsigned_long_instances, unsigned_long_instances);
+    }
+
+    /// Keep only the `max_len` most frequent values of each table.
+    ///
+    /// Values are sorted by decreasing number of files containing them. Ties are
+    /// broken by value, to ensure that the result remains stable across process restarts.
+    pub fn retain_most_frequent(&mut self, max_len: usize) {
+        // Make sure that we don't forget a field.
+        let UserExtensibleData {
+            ref mut identifier_name_instances,
+            ref mut property_key_instances,
+            ref mut interface_name_instances,
+            ref mut string_literal_instances,
+            ref mut string_enum_instances,
+            ref mut list_length_instances,
+            ref mut float_instances,
+            ref mut unsigned_long_instances,
+        } = *self;
+        Self::retain_most_frequent_in_table(identifier_name_instances, max_len);
+        Self::retain_most_frequent_in_table(property_key_instances, max_len);
+        Self::retain_most_frequent_in_table(interface_name_instances, max_len);
+        Self::retain_most_frequent_in_table(string_literal_instances, max_len);
+        Self::retain_most_frequent_in_table(string_enum_instances, max_len);
+        Self::retain_most_frequent_in_table(list_length_instances, max_len);
+        Self::retain_most_frequent_in_table(float_instances, max_len);
+        Self::retain_most_frequent_in_table(unsigned_long_instances, max_len);
+    }
+
+    fn merge_table<K>(table: &mut HashMap<K, FilesContaining>, other: &HashMap<K, FilesContaining>)
+    where
+        K: Eq + std::hash::Hash + Clone,
+    {
+        for (value, files) in other {
+            *table.entry(value.clone()).or_insert(FilesContaining(0)) += *files;
+        }
+    }
+
+    fn retain_most_frequent_in_table<K>(table: &mut HashMap<K, FilesContaining>, max_len: usize)
+    where
+        K: Eq + std::hash::Hash + Ord,
+    {
+        if table.len() <= max_len {
+            return;
+        }
+        let retained = table
+            .drain()
+            .sorted_by(|(value_1, files_1), (value_2, files_2)| {
+                Ord::cmp(files_2, files_1).then_with(|| Ord::cmp(value_1, value_2))
+            })
+            .take(max_len)
+            .collect();
+        *table = retained;
+    }
+}
 
 impl<K> InstancesToProbabilities for HashMap<K, FilesContaining>
 where
@@ -70,6 +70,17 @@ pub struct Options {
     ///   all probabilities are equal.
     dictionaries: DictionaryFamily<SymbolInfo>,
 
+    /// The dictionary derived from the grammar, used as a fallback.
+    baseline: Rc<Dictionary<Instances>>,
+
+    /// The family of dictionaries from which `dictionaries` is derived,
+    /// as loaded e.g. from disk.
+    model: Rc<DictionaryFamily<Instances>>,
+
+    /// If `true`, when compressing a file, also write `model` into the file,
+    /// so that it may be decoded without the model.
+    embed_model: bool,
+
     /// Statistics obtained while writing: number of bytes written.
     /// If several files are written with the same options, we accumulate
     /// statistics.
@@ -94,28 +105,14 @@ impl Options {
     /// Create a fresh `Options` to encode/decode in a given grammar spec with
     /// a family of dictionaries.
     ///
-    /// If `dictionaries` is empty, a default dictionary is insertred.
-    pub fn new(
-        spec: &binjs_meta::spec::Spec,
-        mut dictionaries: DictionaryFamily<Instances>,
-    ) -> Self {
-        use entropy::probabilities::InstancesToProbabilities;
-
-        // Introduce the fallback dictionary.
-        let baseline = baseline::build(spec);
-        for dictionary in dictionaries.values_mut() {
-            *dictionary = dictionary.with_grammar_fallback(baseline.clone());
-        }
-        assert!(
-            !dictionaries.insert_baseline(baseline),
-            "The dictionary family MUST start without a definition for dictionary `*`"
-        );
-        let mut dictionaries = dictionaries.instances_to_probabilities("dictionary");
-        dictionaries
-            .enter_existing(&SharedString::from_str(""))
-            .unwrap(); // We just created this dictionary with the call to `insert_baseline`.
+    /// If `model` is empty, a default dictionary is inserted.
+    pub fn new(spec: &binjs_meta::spec::Spec, model: DictionaryFamily<Instances>) -> Self {
+        let baseline = Rc::new(baseline::build(spec));
         Options {
-            dictionaries,
+            dictionaries: Self::probabilities(&baseline, &model),
+            baseline,
+            model: Rc::new(model),
+            embed_model: false,
             content_lengths: Rc::new(RefCell::new(PerUserExtensibleKind::default())),
             content_instances: Rc::new(RefCell::new(PerUserExtensibleKind::default())),
             probability_stats: Rc::new(RefCell::new(PerStaticKind::default())),
@@ -131,6 +128,49 @@ impl Options {
         }
     }
 
+    /// A copy of these `Options`, using another family of dictionaries,
+    /// e.g. one embedded in a file.
+    ///
+    /// The dictionaries of `self` are not copied, only the model of the file
+    /// is converted.
+    fn with_model(&self, model: DictionaryFamily<Instances>) -> Self {
+        Options {
+            dictionaries: Self::probabilities(&self.baseline, &model),
+            baseline: self.baseline.clone(),
+            model: Rc::new(model),
+            embed_model: self.embed_model,
+            content_lengths: self.content_lengths.clone(),
+            content_instances: self.content_instances.clone(),
+            probability_stats: self.probability_stats.clone(),
+            content_window_len: self.content_window_len.clone(),
+            split_streams: self.split_streams,
+        }
+    }
+
+    /// Convert a family of dictionaries to probabilities, falling back to
+    /// `baseline` for anything that does not appear in the family.
+    fn probabilities(
+        baseline: &Dictionary<Instances>,
+        model: &DictionaryFamily<Instances>,
+    ) -> DictionaryFamily<SymbolInfo> {
+        use entropy::probabilities::InstancesToProbabilities;
+
+        // Introduce the fallback dictionary.
+        let mut dictionaries = model.clone();
+        for dictionary in dictionaries.values_mut() {
+            *dictionary = dictionary.with_grammar_fallback(baseline.clone());
+        }
+        assert!(
+            !dictionaries.insert_baseline(baseline.clone()),
+            "The dictionary family MUST start without a definition for dictionary `*`"
+        );
+        let mut dictionaries = dictionaries.instances_to_probabilities("dictionary");
+        dictionaries
+            .enter_existing(&SharedString::from_str(""))
+            .unwrap(); // We just created this dictionary with the call to `insert_baseline`.
+        dictionaries
+    }
+
     /// Return the statistics as (number of instances, number of bytes).
     pub fn statistics_for_write(&self) -> impl std::fmt::Display {
         let per_user_extensible_kind = {
@@ -195,6 +235,17 @@ impl Options {
         self.split_streams = value;
         self
     }
+
+    /// Configure the `embed-model` option.
+    ///
+    /// If `value == true`, write the family of dictionaries into each file,
+    /// so that files are self-contained, even if the family of dictionaries
+    /// is available separately. Decoders use the embedded family rather than
+    /// their own.
+    pub fn with_embed_model(&mut self, value: bool) -> &mut Self {
+        self.embed_model = value;
+        self
+    }
 }
 
 /// Command-line management.
@@ -219,6 +270,11 @@ impl ::FormatProvider for FormatProvider {
                 .takes_value(true)
                 .required(false)
             )
+            .arg(Arg::with_name("embed-model")
+                .long("embed-model")
+                .help("If specified, write the dictionary into the file, even if it is available separately, to produce self-contained files.")
+                .takes_value(false)
+            )
             .arg(Arg::with_name("split-streams")
                 .long("split-streams")
                 .help("If specified, dump all streams to the disk, for forensics purposes.")
@@ -287,6 +343,7 @@ impl ::FormatProvider for FormatProvider {
         };
 
         let split_streams = matches.is_present("split-streams");
+        let embed_model = matches.is_present("embed-model");
 
         let convert_usize = |key: &str| {
             usize::from_str_radix(&matches.value_of(key).unwrap(), 10)
@@ -307,6 +364,7 @@ impl ::FormatProvider for FormatProvider {
                 content_lengths: Rc::new(RefCell::new(PerUserExtensibleKind::default())),
                 content_instances: Rc::new(RefCell::new(PerUserExtensibleKind::default())),
                 split_streams,
+                embed_model,
                 content_window_len,
                 ..Options::new(spec, dictionaries)
             },
@@ -5,21 +5,43 @@ mod prelude_decoders;
 use self::content_decoders::*;
 use self::prelude_decoders::*;
 
+use super::dictionary::DictionaryFamily;
 use super::probabilities::SymbolIndex;
 use super::rw::*;
 use super::util::*;
 
+use bytes::compress::Compression;
 use bytes::decoders::*;
+use bytes::serialize::Deserializer;
 use io::{FileStructurePrinter, Path, TokenReader};
-use statistics::PerUserExtensibleKind;
+use statistics::{Instances, PerUserExtensibleKind};
 use TokenReaderError;
 
 use binjs_shared::{FieldName, IdentifierName, InterfaceName, PropertyKey, SharedString, F64};
 
-use std::io::{Cursor, Read};
+use std::borrow::Cow;
+use std::io::{Cursor, Read, Seek, SeekFrom};
 
 use range_encoding::opus;
 
+/// Deserialize the family of dictionaries embedded in a file.
+///
+/// The lengths read by `bincode` are untrusted, so it may not read past the
+/// end of the section.
+struct ModelDeserializer;
+impl Deserializer for ModelDeserializer {
+    type Target = DictionaryFamily<Instances>;
+    fn read<R: Read + Seek>(&self, inp: &mut R) -> Result<Self::Target, std::io::Error> {
+        let start = inp.seek(SeekFrom::Current(0))?;
+        let end = inp.seek(SeekFrom::End(0))?;
+        inp.seek(SeekFrom::Start(start))?;
+        bincode::config()
+            .limit(end - start)
+            .deserialize_from(inp)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+}
+
 /// An entropy decoder, based on the Opus bit-level entropy coding.
 pub struct Decoder {
     /// The main stream, compressed using entropy and the dictionary
@@ -52,12 +74,35 @@ impl Decoder {
             .expect(GLOBAL_HEADER_START)
             .map_err(TokenReaderError::ReadError)?;
 
-        // 2. Read the prelude
-        debug!(target: "read", "Decoder::new: Reading prelude");
-        let mut prelude_data: PreludeStreams<Option<Vec<u8>>> = PreludeStreams::with(|_| None);
+        // 2. Read the model, if any. It takes precedence over `options`.
+        let mut header = vec![0; SECTION_MODEL.len()];
         input
-            .expect(SECTION_PRELUDE)
+            .read_exact(&mut header)
             .map_err(TokenReaderError::ReadError)?;
+        let options = if header == SECTION_MODEL {
+            debug!(target: "read", "Decoder::new: Reading model");
+            let model = Compression::decompress(&mut input, &ModelDeserializer)
+                .map_err(TokenReaderError::ReadError)?;
+            input
+                .expect(SECTION_PRELUDE)
+                .map_err(TokenReaderError::ReadError)?;
+            Cow::Owned(options.with_model(model))
+        } else {
+            // No model, so this must be the start of the prelude.
+            let mut rest = vec![0; SECTION_PRELUDE.len() - header.len()];
+            input
+                .read_exact(&mut rest)
+                .map_err(TokenReaderError::ReadError)?;
+            header.extend(rest);
+            if header != SECTION_PRELUDE {
+                return Err(TokenReaderError::BadHeaderName(header));
+            }
+            Cow::Borrowed(options)
+        };
+
+        // 3. Read the prelude
+        debug!(target: "read", "Decoder::new: Reading prelude");
+        let mut prelude_data: PreludeStreams<Option<Vec<u8>>> = PreludeStreams::with(|_| None);
 
         let mut decoder = SectionDecoder::new(input);
         for item in &mut decoder {
@@ -84,7 +129,7 @@ impl Decoder {
         // Quick verification of what comes next.
         input = Self::check_upcoming_section(decoder, &SECTION_CONTENT_WITHOUT_BRACKETS)?;
 
-        // 3. Decode prelude (could be made lazy/backgrounded)
+        // 4. Decode prelude (could be made lazy/backgrounded)
         debug!(target: "read", "Decoder::new: Decoding prelude");
         let prelude_identifier_names = {
             let mut result = Vec::new();
@@ -153,7 +198,7 @@ impl Decoder {
             result
         };
 
-        // 4. Read byte-compressed streams
+        // 5. Read byte-compressed streams
         let mut content_data: PerUserExtensibleKind<Option<Vec<u8>>> =
             PerUserExtensibleKind::with(|_| None);
         let mut decoder = SectionDecoder::new(input);
@@ -180,7 +225,7 @@ impl Decoder {
 
         input = Self::check_upcoming_section(decoder, &SECTION_MAIN_WITHOUT_BRACKETS)?;
 
-        // 5. Decode byte-compressed streams (could be made lazy/backgrounded)
+        // 6. Decode byte-compressed streams (could be made lazy/backgrounded)
         // FIXME: copying all these probability tables is a waste of time,
         // it wouldn't be too hard to keep a single copy in memory
         let stream_floats = DictionaryStreamDecoder::try_new(
@@ -244,7 +289,7 @@ impl Decoder {
             content_data.list_lengths,
         )?;
 
-        // 6. Ready to read and decode main stream.
+        // 7. Ready to read and decode main stream.
         input
             .expect(FORMAT_ENTROPY_0)
             .map_err(TokenReaderError::ReadError)?;
@@ -257,7 +302,9 @@ impl Decoder {
         let stream_main =
             opus::Reader::new(Cursor::new(data)).map_err(TokenReaderError::ReadError)?;
         let result = Decoder {
-            options: (*options).clone(), // No sharing here.
+            // No sharing here, as dictionaries keep track of the current one.
+            // The options of an embedded model are ours already.
+            options: options.into_owned(),
             stream_floats,
             stream_identifier_names,
             stream_list_lengths,
@@ -18,6 +18,10 @@ pub const GLOBAL_HEADER_START: &'static [u8; 8] = b"\x89BJS\r\n\0\n";
 pub const SECTION_MAIN: &'static [u8] = b"[[main]]";
 pub const SECTION_MAIN_WITHOUT_BRACKETS: &'static [u8] = b"main";
 
+/// The optional model section, containing the family of dictionaries
+/// used to encode the file, encoded using e.g. brotli.
+pub const SECTION_MODEL: &'static [u8] = b"[[model]]";
+
 /// The prelude section, containing the dictionary extensions,
 /// encoded using e.g. brotli.
 pub const SECTION_PRELUDE: &'static [u8] = b"[[prelude]]";
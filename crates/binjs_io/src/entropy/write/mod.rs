@@ -5,6 +5,7 @@ mod lazy_stream;
 use self::lazy_stream::*;
 use super::dictionary::{Fetch, LinearTable, TableRef};
 use super::rw::*;
+use bytes::compress::Compression;
 use bytes::lengthwriter::LengthWriter;
 use bytes::varnum::WriteVarNum;
 use io::statistics::{Bytes, Instances, PerUserExtensibleKind, Rational};
@@ -417,6 +418,20 @@ impl TokenWriter for Encoder {
 
         // FIXME: Write additional headers.
 
+        // Write the family of dictionaries, if requested.
+        if self.options.embed_model {
+            data.extend(SECTION_MODEL);
+            let model = bincode::serialize(&*self.options.model).map_err(|err| {
+                TokenWriterError::WriteError(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    err,
+                ))
+            })?;
+            Compression::Brotli
+                .compress(&model, &mut data)
+                .map_err(TokenWriterError::WriteError)?;
+        }
+
         // Write prelude compressed streams, containing dictionaries.
         data.extend(SECTION_PRELUDE);
         for (name, stream) in self.prelude_streams.into_iter().sorted_by_key(|kv| kv.0) {
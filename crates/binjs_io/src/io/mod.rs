@@ -10,7 +10,7 @@
 use binjs_shared::ast::Node;
 use binjs_shared::{self, FieldName, IdentifierName, InterfaceName, PropertyKey, SharedString};
 
-use {TokenReaderError, TokenWriterError};
+use {DecodeWarning, TokenReaderError, TokenWriterError};
 
 use std::rc::Rc;
 
@@ -172,6 +172,13 @@ where
         // Do nothing.
     }
 
+    /// Non-fatal observations made while reading, e.g. skipped sections.
+    ///
+    /// The default implementation reports none.
+    fn warnings(&self) -> &[DecodeWarning] {
+        &[]
+    }
+
     /// Read a single UTF-8 string.
     ///
     /// The returned string MUST be valid UTF-8.
@@ -224,6 +231,28 @@ where
     /// Read a single number of bytes.
     fn offset_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError>;
 
+    /// Skip the contents of a lazy field, i.e. the `byte_len` bytes announced
+    /// by the latest call to `offset_at`.
+    ///
+    /// Returns the position at which the skipped contents start, for use with
+    /// `seek_lazy_at`, or `None` if this reader doesn't support skipping, in
+    /// which case nothing is skipped and the contents must be read as usual.
+    fn skip_lazy_at(
+        &mut self,
+        _byte_len: u32,
+        _path: &Path,
+    ) -> Result<Option<u64>, TokenReaderError> {
+        Ok(None)
+    }
+
+    /// Resume reading the contents of a lazy field previously skipped with
+    /// `skip_lazy_at`.
+    fn seek_lazy_at(&mut self, _position: u64, _path: &Path) -> Result<(), TokenReaderError> {
+        Err(TokenReaderError::GenericError(
+            "This reader doesn't support lazy fields".to_string(),
+        ))
+    }
+
     /// Start reading a list.
     fn enter_list_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError>;
 
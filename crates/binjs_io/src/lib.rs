@@ -20,7 +20,9 @@ extern crate rand;
 extern crate range_encoding;
 #[macro_use]
 extern crate serde;
+extern crate sha2;
 extern crate smallvec;
+extern crate twox_hash;
 
 extern crate vec_map;
 extern crate xml as xml_rs;
@@ -30,6 +32,8 @@ use binjs_shared::SharedString;
 use std::cell::RefCell;
 use std::fmt::{Debug, Formatter};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use rand::distributions::{Distribution, Standard};
 use rand::seq::SliceRandom;
@@ -44,6 +48,13 @@ pub enum TokenWriterError {
     NotInDictionary(String),
     WriteError(std::io::Error),
     DictionarySwitchingError(SharedString),
+    /// The container would exceed `multipart::Options::max_output_bytes`.
+    OutputBudgetExceeded {
+        max: usize,
+        projected: usize,
+    },
+    /// The operation was cancelled through a `CancellationToken`.
+    Cancelled,
 }
 
 #[derive(Debug)]
@@ -59,6 +70,9 @@ pub enum TokenReaderError {
     BadHeader,
     BadHeaderName(Vec<u8>),
     BadCompression(std::io::Error),
+    /// The container failed its integrity check (e.g. the container-wide
+    /// hash stored in the footer does not match its contents).
+    ContainerCorrupt(String),
     EndOffsetError {
         start: u64,
         expected: u64,
@@ -66,6 +80,7 @@ pub enum TokenReaderError {
         description: String,
     },
     BadStringIndex(u32),
+    BadNumberIndex(u32),
     BadDictionaryIndex {
         index: u32,
         dictionary: SharedString,
@@ -84,6 +99,14 @@ pub enum TokenReaderError {
     BadEnumVariant,
     GenericError(String),
     DictionarySwitchingError(SharedString),
+    /// The input exceeds a limit set by the reader, e.g. on the number
+    /// of sections of a container.
+    LimitExceeded(String),
+    /// The container format version, written after `"BINJS"`, is more recent
+    /// than the versions known to the reader, which could otherwise misread it.
+    UnsupportedContainerVersion(u32),
+    /// The operation was cancelled through a `CancellationToken`.
+    Cancelled,
 }
 impl TokenReaderError {
     pub fn invalid_value<T: std::fmt::Debug>(value: &T) -> Self {
@@ -92,6 +115,162 @@ impl TokenReaderError {
     }
 }
 
+/// An error reported by the public encoding/decoding API, sorted by
+/// category, so that callers do not need to know the internals of each
+/// format to decide how to react.
+#[derive(Debug)]
+pub enum BinjsError {
+    /// The underlying stream could not be read or written, e.g. disk failure.
+    Io(std::io::Error),
+
+    /// The input is malformed, e.g. truncated, corrupted or with an
+    /// invalid compression header.
+    Format(String),
+
+    /// The input is well-formed but does not match the grammar.
+    Grammar(String),
+
+    /// The operation is not supported, e.g. decoding from a write-only format.
+    Unsupported(String),
+
+    /// The input exceeds a limit set on resources.
+    Limit(String),
+
+    /// The operation was cancelled through a `CancellationToken`.
+    Cancelled,
+}
+impl BinjsError {
+    /// `true` if `error` may only have been caused by malformed data,
+    /// rather than by the underlying stream.
+    fn is_format_error(error: &std::io::Error) -> bool {
+        use std::io::ErrorKind::*;
+        match error.kind() {
+            InvalidData | InvalidInput | UnexpectedEof => true,
+            _ => false,
+        }
+    }
+}
+impl std::fmt::Display for BinjsError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            BinjsError::Io(ref err) => write!(f, "I/O error: {}", err),
+            BinjsError::Format(ref msg) => write!(f, "Malformed input: {}", msg),
+            BinjsError::Grammar(ref msg) => write!(f, "Input does not match the grammar: {}", msg),
+            BinjsError::Unsupported(ref msg) => write!(f, "Unsupported: {}", msg),
+            BinjsError::Limit(ref msg) => write!(f, "Limit exceeded: {}", msg),
+            BinjsError::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+impl std::error::Error for BinjsError {}
+
+/// A flag shared with a long encoding or decoding, e.g. by a server, to
+/// abort it once its result is not needed anymore, e.g. if the client has
+/// disconnected.
+///
+/// Cancellation is cooperative: the flag is checked between sections and
+/// as nodes are written or read, and the operation then fails with `Cancelled`.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request the cancellation of all operations sharing this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Fail with `cancelled`, e.g. `TokenReaderError::Cancelled`, if
+    /// cancellation has been requested.
+    pub fn check<E>(&self, cancelled: E) -> Result<(), E> {
+        if self.is_cancelled() {
+            Err(cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A non-fatal observation made while decoding, e.g. to display it to users.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeWarning {
+    /// The file uses a deprecated version of its format, still readable.
+    DeprecatedVersion(u32),
+
+    /// A section was skipped, e.g. an optional section unknown to this
+    /// decoder, or compressed with an unknown compression format.
+    SkippedSection(String),
+
+    /// A value of a string enum unknown to the grammar was decoded as
+    /// the `Unknown` variant of the enum, with lenient enums.
+    UnknownEnumValue { name: String, value: String },
+}
+impl std::fmt::Display for DecodeWarning {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            DecodeWarning::DeprecatedVersion(version) => {
+                write!(f, "Deprecated format version {}", version)
+            }
+            DecodeWarning::SkippedSection(ref name) => write!(f, "Skipped section {}", name),
+            DecodeWarning::UnknownEnumValue {
+                ref name,
+                ref value,
+            } => write!(f, "Unknown value {:?} of {}", value, name),
+        }
+    }
+}
+impl From<std::io::Error> for BinjsError {
+    fn from(error: std::io::Error) -> Self {
+        BinjsError::Io(error)
+    }
+}
+impl From<TokenReaderError> for BinjsError {
+    fn from(error: TokenReaderError) -> Self {
+        use TokenReaderError::*;
+        match error {
+            ReadError(err) | BadCompression(err) => {
+                if Self::is_format_error(&err) {
+                    BinjsError::Format(format!("{}", err))
+                } else {
+                    BinjsError::Io(err)
+                }
+            }
+            BadEnumVariant | EmptyVariant | EmptyBool | EmptyString | EmptyList | EmptyNumber
+            | EmptyNodeName | EmptyFieldName | InvalidValue => {
+                BinjsError::Grammar(format!("{:?}", error))
+            }
+            LimitExceeded(msg) => BinjsError::Limit(msg),
+            Cancelled => BinjsError::Cancelled,
+            UnsupportedContainerVersion(version) => {
+                BinjsError::Unsupported(format!("Container format version {}", version))
+            }
+            _ => BinjsError::Format(format!("{:?}", error)),
+        }
+    }
+}
+impl From<TokenWriterError> for BinjsError {
+    fn from(error: TokenWriterError) -> Self {
+        match error {
+            TokenWriterError::WriteError(err) => BinjsError::Io(err),
+            TokenWriterError::InvalidOffsetField => BinjsError::Grammar(format!("{:?}", error)),
+            TokenWriterError::Cancelled => BinjsError::Cancelled,
+            TokenWriterError::OutputBudgetExceeded { max, projected } => {
+                BinjsError::Limit(format!(
+                    "Output of {} bytes exceeds budget of {} bytes",
+                    projected, max
+                ))
+            }
+            _ => BinjsError::Unsupported(format!("{:?}", error)),
+        }
+    }
+}
+
 /// Byte-level utilities for writing token readers/writers.
 pub mod bytes;
 
@@ -120,6 +299,9 @@ mod util;
 
 pub mod escaped_wtf8;
 
+/// Timing metrics, reported to a user-provided sink.
+pub mod metrics;
+
 /// An encoding using per-context Huffman tables.
 pub mod context;
 
@@ -149,6 +331,20 @@ enum Compressing {
 pub struct CompressionTarget {
     data: Compressing,
     format: bytes::compress::Compression,
+    metrics: Option<Rc<dyn metrics::MetricsSink>>,
+
+    /// If `true`, a section that cannot be compressed is stored
+    /// uncompressed instead, with a warning.
+    fallback_on_codec_error: bool,
+
+    /// If `true`, store the decompressed byte length of the section.
+    size_hint: bool,
+
+    /// If `true`, write an empty section as a single byte.
+    empty_marker: bool,
+
+    /// The quality of brotli, if not the default one.
+    brotli_quality: Option<u32>,
 }
 impl Debug for CompressionTarget {
     fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
@@ -160,11 +356,110 @@ impl CompressionTarget {
         Self {
             data: Compressing::Uncompressed(Rc::new(RefCell::new(vec![]))),
             format,
+            metrics: None,
+            fallback_on_codec_error: false,
+            size_hint: false,
+            empty_marker: false,
+            brotli_quality: None,
         }
     }
+
+    /// A new target compressing with `format`, with the other settings of
+    /// this one, e.g. size hints.
+    pub fn with_compression(&self, format: bytes::compress::Compression) -> Self {
+        Self {
+            metrics: self.metrics.clone(),
+            fallback_on_codec_error: self.fallback_on_codec_error,
+            size_hint: self.size_hint,
+            empty_marker: self.empty_marker,
+            brotli_quality: self.brotli_quality,
+            ..Self::new(format)
+        }
+    }
+
+    /// The mechanism used to compress this section.
+    pub fn compression(&self) -> &bytes::compress::Compression {
+        &self.format
+    }
+
+    /// Replace the mechanism used to compress this section by the result of
+    /// `choose`, called with the data written so far.
+    ///
+    /// Has no effect once the section has been compressed by `done`.
+    pub fn choose_compression<F>(&mut self, choose: F)
+    where
+        F: FnOnce(&[u8]) -> bytes::compress::Compression,
+    {
+        if let Compressing::Uncompressed(ref data) = self.data {
+            self.format = choose(data.borrow().as_ref());
+        }
+    }
+
+    /// Report the time spent compressing to `sink`.
+    pub fn set_metrics(&mut self, sink: Option<Rc<dyn metrics::MetricsSink>>) {
+        self.metrics = sink;
+    }
+
+    /// If `fallback` is `true`, when compressing fails, e.g. because of a bug
+    /// of the codec triggered by pathological data, store the section
+    /// uncompressed rather than failing.
+    pub fn set_fallback_on_codec_error(&mut self, fallback: bool) {
+        self.fallback_on_codec_error = fallback;
+    }
+
+    /// If `size_hint` is `true`, store the decompressed byte length of the
+    /// section, so that decoders may allocate it exactly once.
+    ///
+    /// See `bytes::compress::CompressionState::set_size_hint`.
+    pub fn set_size_hint(&mut self, size_hint: bool) {
+        self.size_hint = size_hint;
+    }
+
+    /// If `empty_marker` is `true`, write the section as a single byte if
+    /// it is empty, rather than as a frame of its codec.
+    ///
+    /// See `bytes::compress::CompressionState::set_empty_marker`.
+    pub fn set_empty_marker(&mut self, empty_marker: bool) {
+        self.empty_marker = empty_marker;
+    }
+
+    /// Compress the section with brotli at `quality`, from `0`, the fastest,
+    /// to `11`, the smallest and the default.
+    ///
+    /// See `bytes::compress::CompressionState::set_brotli_quality`.
+    pub fn set_brotli_quality(&mut self, quality: u32) {
+        self.brotli_quality = Some(quality);
+    }
+
     pub fn done(
         &mut self,
     ) -> std::result::Result<(Rc<Vec<u8>>, bytes::compress::CompressionResult), std::io::Error>
+    {
+        let size_hint = self.size_hint;
+        let empty_marker = self.empty_marker;
+        let brotli_quality = self.brotli_quality;
+        self.done_with(|format, data, buf| {
+            let mut state = bytes::compress::CompressionState::default();
+            state.set_size_hint(size_hint);
+            state.set_empty_marker(empty_marker);
+            if let Some(quality) = brotli_quality {
+                state.set_brotli_quality(quality);
+            }
+            format.compress_with_state(data, buf, &mut state)
+        })
+    }
+
+    /// Implementation of `done`, compressing with `compress`.
+    fn done_with<F>(
+        &mut self,
+        compress: F,
+    ) -> std::result::Result<(Rc<Vec<u8>>, bytes::compress::CompressionResult), std::io::Error>
+    where
+        F: Fn(
+            &bytes::compress::Compression,
+            &[u8],
+            &mut Vec<u8>,
+        ) -> std::result::Result<bytes::compress::CompressionResult, std::io::Error>,
     {
         let (data, result) = match self.data {
             Compressing::Compressed {
@@ -173,7 +468,25 @@ impl CompressionTarget {
             } => return Ok((data.clone(), result.clone())),
             Compressing::Uncompressed(ref data) => {
                 let mut buf = vec![];
-                let result = self.format.compress(&data.borrow().as_ref(), &mut buf)?;
+                let stopwatch = metrics::Stopwatch::start(metrics::Phase::Compress);
+                let data = data.borrow();
+                let result = match compress(&self.format, data.as_ref(), &mut buf) {
+                    Ok(result) => result,
+                    Err(err) if self.fallback_on_codec_error => {
+                        warn!(
+                            target: "compression",
+                            "Could not compress section with {:?}, storing it uncompressed: {}",
+                            self.format,
+                            err
+                        );
+                        buf.clear();
+                        bytes::compress::Compression::Identity.compress(data.as_ref(), &mut buf)?
+                    }
+                    Err(err) => return Err(err),
+                };
+                if let Some(ref sink) = self.metrics {
+                    sink.record(stopwatch.stop(Some(result.after_bytes)));
+                }
                 (Rc::new(buf), result)
             }
         };
@@ -240,6 +553,7 @@ pub enum Format {
     Simple,
     Multipart {
         targets: multipart::Targets,
+        options: multipart::Options,
         stats: Rc<RefCell<multipart::Statistics>>,
     },
     XML,
@@ -262,9 +576,11 @@ impl Distribution<Format> for Standard {
                 Format::Multipart {
                     targets: Targets {
                         strings_table: rng.gen(),
+                        numbers_table: rng.gen(),
                         grammar_table: rng.gen(),
                         tree: rng.gen(),
                     },
+                    options: multipart::Options::default(),
                     stats,
                 }
             }),
@@ -288,12 +604,14 @@ impl Format {
             Format::Simple => Format::Simple,
             Format::XML => Format::XML,
             Format::JSON => Format::JSON,
-            Format::Multipart { stats, .. } => Format::Multipart {
+            Format::Multipart { options, stats, .. } => Format::Multipart {
                 targets: multipart::Targets {
                     strings_table: rng.gen(),
+                    numbers_table: rng.gen(),
                     grammar_table: rng.gen(),
                     tree: rng.gen(),
                 },
+                options,
                 stats,
             },
             Format::Entropy { .. } => unimplemented!(),
@@ -311,6 +629,38 @@ impl Format {
         }
     }
 
+    /// Call `f` with all the sections of this format compressed with
+    /// `compression`, keeping the other settings of each section, then
+    /// restore the previous compression of each section.
+    ///
+    /// Returns `None`, without calling `f`, unless the format is multipart.
+    pub fn with_compression<F, T>(
+        &mut self,
+        compression: &bytes::compress::Compression,
+        f: F,
+    ) -> Option<T>
+    where
+        F: FnOnce(&mut Format) -> T,
+    {
+        let previous = match *self {
+            Format::Multipart {
+                ref mut targets, ..
+            } => {
+                let replacement = targets.with_compression(compression);
+                std::mem::replace(targets, replacement)
+            }
+            _ => return None,
+        };
+        let result = f(self);
+        if let Format::Multipart {
+            ref mut targets, ..
+        } = *self
+        {
+            *targets = previous;
+        }
+        Some(result)
+    }
+
     pub fn with_sections<F, E>(&mut self, mut f: F) -> Result<(), E>
     where
         F: FnMut(&mut CompressionTarget, &str) -> Result<(), E>,
@@ -333,12 +683,17 @@ impl Format {
                     multipart::Targets {
                         ref mut grammar_table,
                         ref mut strings_table,
+                        ref mut numbers_table,
                         ref mut tree,
                     },
+                ref options,
                 ..
             } => {
                 f(grammar_table, "grammar")?;
                 f(strings_table, "strings")?;
+                if options.numbers_table {
+                    f(numbers_table, "numbers")?;
+                }
                 f(tree, "tree")?;
                 Ok(())
             }
@@ -390,4 +745,63 @@ impl Format {
         }
         Self::default_provider().handle_subcommand(spec, None)
     }
+
+    /// Create a Format from the name of its subcommand, e.g. `"multipart"`
+    /// or `"entropy"`, parsing `args` as the arguments of this subcommand.
+    ///
+    /// Used by tools that need several formats at once, hence cannot
+    /// rely upon `from_matches`.
+    pub fn from_name(
+        spec: &binjs_meta::spec::Spec,
+        name: &str,
+        args: &[&str],
+    ) -> Result<Self, std::io::Error> {
+        for provider in Self::providers().into_iter() {
+            let subcommand = provider.subcommand();
+            if subcommand.get_name() != name {
+                continue;
+            }
+            let matches = subcommand
+                .get_matches_from_safe(std::iter::once(name).chain(args.iter().cloned()))
+                .map_err(|err| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{}", err))
+                })?;
+            return provider.handle_subcommand(spec, Some(&matches));
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Unknown format {}", name),
+        ))
+    }
+}
+
+#[test]
+fn test_compression_target_fallback() {
+    use bytes::compress::Compression;
+
+    use std::io::Write;
+
+    let failing = |_: &Compression, _: &[u8], _: &mut Vec<u8>| {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "Injected codec error",
+        ))
+    };
+
+    println!("* Without fallback, codec errors abort");
+    let mut target = CompressionTarget::new(Compression::Brotli);
+    target.write_all(b"some data").unwrap();
+    assert!(target.done_with(failing).is_err());
+
+    println!("* With fallback, the section is stored as identity");
+    let mut target = CompressionTarget::new(Compression::Brotli);
+    target.set_fallback_on_codec_error(true);
+    target.write_all(b"some data").unwrap();
+    let (data, result) = target.done_with(failing).expect("Fallback should succeed");
+    assert!(result.algorithms.contains(&Compression::Identity));
+    let mut expected = vec![];
+    Compression::Identity
+        .compress(b"some data", &mut expected)
+        .unwrap();
+    assert_eq!(*data, expected);
 }
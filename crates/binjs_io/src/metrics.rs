@@ -0,0 +1,73 @@
+//! Timing metrics for encoding, as well as decode cache metrics, for
+//! embedders that wish to forward them to their own monitoring system.
+
+use std::time::{Duration, Instant};
+
+/// A phase of encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Parsing the source text into an AST.
+    ///
+    /// Reported by encoders which parse the source themselves, e.g.
+    /// `binjs::batch::BatchEncoder`, as `Encoder` receives an AST.
+    Parse,
+
+    /// Walking the AST and turning it into tokens.
+    Tokenize,
+
+    /// Compressing a single section of the file.
+    ///
+    /// Formats that compress several sections independently report
+    /// one `Compress` per section.
+    Compress,
+
+    /// Assembling the final file, including any compression.
+    Write,
+}
+
+/// Measurements for a single phase.
+#[derive(Clone, Debug)]
+pub struct PhaseMetrics {
+    pub phase: Phase,
+
+    /// Time spent in the phase.
+    pub duration: Duration,
+
+    /// Number of bytes consumed or produced by the phase, if meaningful:
+    /// source bytes for `Parse`, compressed bytes for `Compress`, file
+    /// bytes for `Write`.
+    pub bytes: Option<usize>,
+}
+
+/// A destination for metrics.
+///
+/// Methods are called at the end of each phase.
+pub trait MetricsSink {
+    fn record(&self, metrics: PhaseMetrics);
+
+    /// Called whenever a decoder with a cache looks up a file, with
+    /// `hit == true` if the file was found in the cache.
+    fn record_cache_lookup(&self, _hit: bool) {}
+}
+
+/// A utility to measure the duration of a phase.
+pub struct Stopwatch {
+    phase: Phase,
+    start: Instant,
+}
+impl Stopwatch {
+    pub fn start(phase: Phase) -> Self {
+        Stopwatch {
+            phase,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn stop(self, bytes: Option<usize>) -> PhaseMetrics {
+        PhaseMetrics {
+            phase: self.phase,
+            duration: self.start.elapsed(),
+            bytes,
+        }
+    }
+}
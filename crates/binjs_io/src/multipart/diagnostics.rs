@@ -0,0 +1,104 @@
+//! Diagnostics on the source of a multipart container.
+//!
+//! Diagnostics are non-fatal observations made while encoding, e.g. the
+//! use of `with` or `eval`, stored so that tools can read them without
+//! re-parsing the source. They are ignored when decoding the AST.
+//!
+//! Format:
+//!
+//! - the characters `"[?DIAGNOSTICS]"`, an optional section (see module `multipart`), so that
+//!   readers which do not know the diagnostics skip them;
+//! - a `prefix` identifying the compression format used for the diagnostics (currently always "identity;");
+//! - the number of compressed bytes (`varnum`);
+//! - compressed in the format identified by `prefix`:
+//!    - the number of diagnostics (`varnum`);
+//!    - for each diagnostic,
+//!      - byte length of kind (`varnum`);
+//!      - kind (utf-8 encoded, no terminator);
+//!      - byte length of path (`varnum`);
+//!      - path (utf-8 encoded, no terminator).
+
+use bytes::frame::read_exactly;
+use bytes::serialize::Deserializer;
+use bytes::varnum::*;
+
+use std;
+use std::io::{Read, Seek, Write};
+
+/// A non-fatal observation on the source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The kind of observation, e.g. `"with"` or `"eval"`.
+    pub kind: String,
+
+    /// The path to the node in the AST, for display purposes.
+    pub path: String,
+}
+
+/// Write a list of diagnostics, without compression.
+pub fn write_diagnostics<W: Write>(
+    diagnostics: &[Diagnostic],
+    out: &mut W,
+) -> Result<usize, std::io::Error> {
+    let mut total = out.write_varnum(diagnostics.len() as u32)?;
+    for diagnostic in diagnostics {
+        for string in &[&diagnostic.kind, &diagnostic.path] {
+            total += out.write_varnum(string.len() as u32)?;
+            out.write_all(string.as_bytes())?;
+            total += string.len();
+        }
+    }
+    Ok(total)
+}
+
+/// Deserialize a list of `Diagnostic`.
+pub struct DiagnosticsDeserializer;
+impl Deserializer for DiagnosticsDeserializer {
+    type Target = Vec<Diagnostic>;
+    fn read<R: Read + Seek>(&self, inp: &mut R) -> Result<Vec<Diagnostic>, std::io::Error> {
+        let read_string = |inp: &mut R| -> Result<String, std::io::Error> {
+            let byte_len = inp.read_varnum()?;
+            // `byte_len` is untrusted, don't allocate it before reading.
+            let bytes = read_exactly(inp, byte_len as usize)?;
+            String::from_utf8(bytes)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+        };
+
+        let number_of_diagnostics = inp.read_varnum()?;
+        let mut diagnostics = vec![];
+        for _ in 0..number_of_diagnostics {
+            let kind = read_string(inp)?;
+            let path = read_string(inp)?;
+            diagnostics.push(Diagnostic { kind, path });
+        }
+        Ok(diagnostics)
+    }
+}
+
+#[test]
+fn test_diagnostics_bad_length() {
+    use std::io::Cursor;
+
+    let diagnostics = vec![Diagnostic {
+        kind: "with".to_string(),
+        path: "Script.statements[0]".to_string(),
+    }];
+    let mut data = vec![];
+    write_diagnostics(&diagnostics, &mut data).unwrap();
+    assert_eq!(
+        DiagnosticsDeserializer
+            .read(&mut Cursor::new(&data))
+            .unwrap(),
+        diagnostics
+    );
+
+    println!("* A string length past the end of the section is rejected");
+    let mut data = vec![];
+    data.write_varnum(1).unwrap();
+    data.write_varnum(std::u32::MAX).unwrap();
+    data.write_all(b"with").unwrap();
+    let err = DiagnosticsDeserializer
+        .read(&mut Cursor::new(&data))
+        .expect_err("Diagnostics should be rejected");
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
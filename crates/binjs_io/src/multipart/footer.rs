@@ -0,0 +1,431 @@
+//! The footer closing a multipart container.
+//!
+//! The footer holds a hash of the entire container, so that clients can check the
+//! integrity of a complete download before starting to decode anything.
+//!
+//! Format:
+//!
+//! - the characters `"[FOOTER]"`;
+//! - a `prefix` identifying the hash algorithm (one of "xxh64;", "sha256;");
+//! - the number of bytes in the digest (`varnum`);
+//! - the digest of all the bytes of the container preceding the footer;
+//! - the byte offset of the footer, from the start of the container (`u64`, little-endian);
+//! - the characters `"BINJSEND"`.
+//!
+//! The last two items have a fixed length, which lets readers find the footer by seeking
+//! from the end of the container.
+//! Streams that cannot seek, e.g. HTTP bodies, are buffered in memory first, and hashed
+//! as they are received (see `TreeTokenReader::from_stream` and `StreamHasher`).
+
+use bytes::varnum::*;
+use multipart::HEADER_FOOTER;
+use TokenReaderError;
+
+use sha2::{Digest, Sha256};
+use twox_hash::XxHash64;
+
+use std;
+use std::hash::Hasher;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+/// The characters closing a container.
+const MAGIC_TRAILER: &[u8; 8] = b"BINJSEND";
+
+/// Byte length of the trailer: offset of the footer + `MAGIC_TRAILER`.
+const TRAILER_LEN: u64 = 8 + 8;
+
+/// Maximal byte length of a footer, excluding the trailer.
+///
+/// Anything larger is certainly the result of a corrupted footer offset.
+const MAX_FOOTER_LEN: u64 = 256;
+
+/// Size of the chunks used to hash the container while reading.
+const HASH_BUFFER_SIZE: usize = 4096;
+
+/// The algorithms available to hash a container.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ContainerHash {
+    /// 64-bit xxHash, seed 0 (`xxh64;`). Fast, but not cryptographic.
+    XxHash64,
+    /// SHA-256 (`sha256;`). Slower, but suitable for archival.
+    Sha256,
+}
+impl Default for ContainerHash {
+    fn default() -> Self {
+        ContainerHash::XxHash64
+    }
+}
+
+impl ContainerHash {
+    pub fn code(&self) -> &str {
+        match *self {
+            ContainerHash::XxHash64 => "xxh64",
+            ContainerHash::Sha256 => "sha256",
+        }
+    }
+
+    pub fn parse(name: Option<&str>) -> Option<ContainerHash> {
+        let result = match name {
+            None | Some("xxh64") => ContainerHash::XxHash64,
+            Some("sha256") => ContainerHash::Sha256,
+            Some(_) => {
+                return None;
+            }
+        };
+        Some(result)
+    }
+
+    /// Hash `data` in one go.
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        let mut hasher = self.hasher();
+        hasher.input(data);
+        hasher.result()
+    }
+
+    fn hasher(&self) -> ContainerHasher {
+        match *self {
+            ContainerHash::XxHash64 => ContainerHasher::XxHash64(XxHash64::with_seed(0)),
+            ContainerHash::Sha256 => ContainerHasher::Sha256(Sha256::new()),
+        }
+    }
+}
+
+/// An ongoing computation of a `ContainerHash`.
+enum ContainerHasher {
+    XxHash64(XxHash64),
+    Sha256(Sha256),
+}
+impl ContainerHasher {
+    fn input(&mut self, data: &[u8]) {
+        match *self {
+            ContainerHasher::XxHash64(ref mut hasher) => hasher.write(data),
+            ContainerHasher::Sha256(ref mut hasher) => hasher.input(data),
+        }
+    }
+
+    fn result(self) -> Vec<u8> {
+        match self {
+            ContainerHasher::XxHash64(hasher) => hasher.finish().to_le_bytes().to_vec(),
+            ContainerHasher::Sha256(hasher) => hasher.result().to_vec(),
+        }
+    }
+}
+
+/// Append a footer to a container, hashing everything written so far.
+///
+/// Returns the number of bytes written.
+pub fn write_footer(hash: &ContainerHash, data: &mut Vec<u8>) -> Result<usize, std::io::Error> {
+    let footer_offset = data.len();
+    let digest = hash.digest(data);
+    write_footer_with_digest(hash, &digest, footer_offset, data)
+}
+
+/// Write the footer of a container whose first `footer_offset` bytes hash
+/// to `digest`.
+///
+/// Returns the number of bytes written.
+fn write_footer_with_digest(
+    hash: &ContainerHash,
+    digest: &[u8],
+    footer_offset: usize,
+    out: &mut Vec<u8>,
+) -> Result<usize, std::io::Error> {
+    let start = out.len();
+    out.write_all(HEADER_FOOTER.as_bytes())?;
+    out.write_all(hash.code().as_bytes())?;
+    out.write_all(b";")?;
+    out.write_varnum(digest.len() as u32)?;
+    out.write_all(digest)?;
+    out.write_all(&(footer_offset as u64).to_le_bytes())?;
+    out.write_all(MAGIC_TRAILER)?;
+    Ok(out.len() - start)
+}
+
+/// A `Write` forwarding a container to a sink as it is produced, while
+/// hashing it for the footer.
+///
+/// The sink may accept fewer bytes than requested: in `write_all`, the
+/// remaining bytes are written in further calls. Errors of the sink,
+/// including `WouldBlock` for a non-blocking sink that is not ready, are
+/// returned to the caller, with `bytes_written()` accounting for the bytes
+/// accepted until then.
+///
+/// The container is only complete once `finish()` has written the footer.
+/// A writer dropped before that leaves a container that readers reject, and
+/// logs a warning, unless given up on with `abandon()`.
+pub struct HashingWriter<W: Write> {
+    /// `None` once `finish()` has returned the sink.
+    sink: Option<W>,
+    hash: ContainerHash,
+    hasher: ContainerHasher,
+
+    /// The number of bytes accepted by `sink` so far.
+    len: usize,
+}
+impl<W: Write> HashingWriter<W> {
+    pub fn new(hash: &ContainerHash, sink: W) -> Self {
+        HashingWriter {
+            sink: Some(sink),
+            hash: hash.clone(),
+            hasher: hash.hasher(),
+            len: 0,
+        }
+    }
+
+    /// The number of bytes accepted by the sink so far.
+    pub fn bytes_written(&self) -> usize {
+        self.len
+    }
+
+    /// The byte length of the footer that `finish` will write.
+    pub fn footer_len(&self) -> usize {
+        let mut footer = Vec::with_capacity(64);
+        // The length of the footer does not depend on the hashed data.
+        write_footer_with_digest(&self.hash, &self.hash.digest(&[]), self.len, &mut footer).unwrap()
+        // Writing to a `Vec` can't fail.
+    }
+
+    /// Write the footer, hashing everything written so far, then flush.
+    ///
+    /// Returns the sink and the total number of bytes written.
+    pub fn finish(mut self) -> Result<(W, usize), std::io::Error> {
+        // The footer is not part of the hashed data.
+        let hasher = std::mem::replace(&mut self.hasher, self.hash.hasher());
+        let mut footer = Vec::with_capacity(64);
+        write_footer_with_digest(&self.hash, &hasher.result(), self.len, &mut footer)?;
+        self.write_all(&footer)?;
+        self.flush()?;
+        let sink = self.sink.take().unwrap(); // Only taken here.
+        Ok((sink, self.len))
+    }
+
+    /// Give up on the container, e.g. once encoding has been cancelled,
+    /// without writing the footer or logging a warning.
+    ///
+    /// Returns the sink and the number of bytes written.
+    pub fn abandon(mut self) -> (W, usize) {
+        let sink = self.sink.take().expect("HashingWriter used after finish()");
+        (sink, self.len)
+    }
+
+    fn sink(&mut self) -> &mut W {
+        self.sink
+            .as_mut()
+            .expect("HashingWriter used after finish()")
+    }
+}
+impl<W: Write> Drop for HashingWriter<W> {
+    fn drop(&mut self) {
+        if self.sink.is_some() {
+            warn!(
+                target: "multipart",
+                "Container dropped before finish(), after {} bytes, without its footer",
+                self.len
+            );
+        }
+    }
+}
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        let written = self.sink().write(buf)?;
+        self.hasher.input(&buf[..written]);
+        self.len += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.sink().flush()
+    }
+}
+
+/// Check the hash stored in the footer of a container against its contents.
+///
+/// On success, `reader` is left at the start of the container.
+pub fn verify_footer<R: Read + Seek>(reader: &mut R) -> Result<(), TokenReaderError> {
+    verified_digest(reader)?;
+    Ok(())
+}
+
+/// Find the footer of a container, using the offset stored in its trailer.
+///
+/// Returns the offset of the footer and its byte length, including the trailer.
+pub fn footer_position<R: Read + Seek>(reader: &mut R) -> Result<(u64, u64), TokenReaderError> {
+    let corrupt = |detail: &str| TokenReaderError::ContainerCorrupt(detail.to_string());
+
+    let total_len = reader
+        .seek(SeekFrom::End(0))
+        .map_err(TokenReaderError::ReadError)?;
+    if total_len < TRAILER_LEN {
+        return Err(corrupt("Container is too short to hold a footer"));
+    }
+    let trailer_offset = total_len - TRAILER_LEN;
+    reader
+        .seek(SeekFrom::Start(trailer_offset))
+        .map_err(TokenReaderError::ReadError)?;
+    let mut trailer = [0; TRAILER_LEN as usize];
+    reader
+        .read_exact(&mut trailer)
+        .map_err(TokenReaderError::ReadError)?;
+    if &trailer[8..] != MAGIC_TRAILER {
+        return Err(corrupt("Missing end of container"));
+    }
+    let mut offset_bytes = [0; 8];
+    offset_bytes.copy_from_slice(&trailer[..8]);
+    let footer_offset = u64::from_le_bytes(offset_bytes);
+    if footer_offset > trailer_offset || trailer_offset - footer_offset > MAX_FOOTER_LEN {
+        return Err(corrupt("Invalid footer offset"));
+    }
+    Ok((footer_offset, total_len - footer_offset))
+}
+
+/// Read the footer of a container, returning its offset, the hash algorithm
+/// and the digest it stores.
+fn read_footer<R: Read + Seek>(
+    reader: &mut R,
+) -> Result<(u64, ContainerHash, Vec<u8>), TokenReaderError> {
+    let corrupt = |detail: &str| TokenReaderError::ContainerCorrupt(detail.to_string());
+
+    // Find the footer.
+    let (footer_offset, footer_len) = footer_position(reader)?;
+
+    // Read the footer.
+    let mut footer = vec![0; (footer_len - TRAILER_LEN) as usize];
+    reader
+        .seek(SeekFrom::Start(footer_offset))
+        .map_err(TokenReaderError::ReadError)?;
+    reader
+        .read_exact(&mut footer)
+        .map_err(TokenReaderError::ReadError)?;
+    if !footer.starts_with(HEADER_FOOTER.as_bytes()) {
+        return Err(corrupt("Missing footer header"));
+    }
+    let footer = &footer[HEADER_FOOTER.len()..];
+    let separator = footer
+        .iter()
+        .position(|byte| *byte == b';')
+        .ok_or_else(|| corrupt("Invalid hash header"))?;
+    let hash = std::str::from_utf8(&footer[..separator])
+        .ok()
+        .and_then(|name| ContainerHash::parse(Some(name)))
+        .ok_or_else(|| corrupt("Unknown hash algorithm"))?;
+    let mut digest_reader = Cursor::new(&footer[separator + 1..]);
+    let digest_len = digest_reader
+        .read_varnum()
+        .map_err(|_| corrupt("Invalid digest length"))?;
+    let mut expected = Vec::new();
+    digest_reader
+        .read_to_end(&mut expected)
+        .map_err(TokenReaderError::ReadError)?;
+    if expected.len() != digest_len as usize {
+        return Err(corrupt("Invalid digest length"));
+    }
+    Ok((footer_offset, hash, expected))
+}
+
+/// Check the hash stored in the footer of a container against its contents,
+/// then return the hash, e.g. to identify the container.
+///
+/// On success, `reader` is left at the start of the container.
+pub fn verified_digest<R: Read + Seek>(
+    reader: &mut R,
+) -> Result<(ContainerHash, Vec<u8>), TokenReaderError> {
+    let (footer_offset, hash, expected) = read_footer(reader)?;
+
+    // Hash everything before the footer.
+    reader
+        .seek(SeekFrom::Start(0))
+        .map_err(TokenReaderError::ReadError)?;
+    let mut hasher = hash.hasher();
+    let mut buf = [0; HASH_BUFFER_SIZE];
+    let mut remaining = footer_offset;
+    while remaining > 0 {
+        let chunk_len = std::cmp::min(remaining, HASH_BUFFER_SIZE as u64) as usize;
+        reader
+            .read_exact(&mut buf[..chunk_len])
+            .map_err(TokenReaderError::ReadError)?;
+        hasher.input(&buf[..chunk_len]);
+        remaining -= chunk_len as u64;
+    }
+    if hasher.result() != expected {
+        return Err(TokenReaderError::ContainerCorrupt(
+            "Container hash mismatch".to_string(),
+        ));
+    }
+
+    reader
+        .seek(SeekFrom::Start(0))
+        .map_err(TokenReaderError::ReadError)?;
+    Ok((hash, expected))
+}
+
+/// Hashing a container as it is received, e.g. from a stream that cannot
+/// seek, so that its footer is checked once the container is complete,
+/// without another pass over the container.
+///
+/// As the footer is only found from the end, the last bytes received, which
+/// may belong to the footer, are only hashed by `verify`.
+pub struct StreamHasher {
+    hash: ContainerHash,
+    hasher: ContainerHasher,
+
+    /// The number of bytes hashed so far.
+    hashed: usize,
+}
+impl StreamHasher {
+    pub fn new(hash: &ContainerHash) -> Self {
+        StreamHasher {
+            hash: hash.clone(),
+            hasher: hash.hasher(),
+            hashed: 0,
+        }
+    }
+
+    /// Hash the bytes of `data`, the container received so far, that cannot
+    /// belong to its footer.
+    pub fn update(&mut self, data: &[u8]) {
+        let end = data
+            .len()
+            .saturating_sub((MAX_FOOTER_LEN + TRAILER_LEN) as usize);
+        if end > self.hashed {
+            self.hasher.input(&data[self.hashed..end]);
+            self.hashed = end;
+        }
+    }
+
+    /// Check the hash stored in the footer of the complete container, as
+    /// `verify_footer`, hashing only the bytes not hashed by `update` yet.
+    ///
+    /// A container hashed with another algorithm is hashed again entirely.
+    pub fn verify<R: Read + Seek>(self, reader: &mut R) -> Result<(), TokenReaderError> {
+        let (footer_offset, hash, expected) = read_footer(reader)?;
+        if hash != self.hash {
+            debug!(target: "multipart", "Container hashed with {}, hashing it again", hash.code());
+            return verify_footer(reader);
+        }
+
+        // `update` left out at least the longest footer.
+        let hashed = self.hashed as u64;
+        debug_assert!(hashed <= footer_offset);
+        let mut rest = vec![];
+        reader
+            .seek(SeekFrom::Start(hashed))
+            .and_then(|_| {
+                (&mut *reader)
+                    .take(footer_offset - hashed)
+                    .read_to_end(&mut rest)
+            })
+            .map_err(TokenReaderError::ReadError)?;
+        let mut hasher = self.hasher;
+        hasher.input(&rest);
+        if rest.len() as u64 != footer_offset - hashed || hasher.result() != expected {
+            return Err(TokenReaderError::ContainerCorrupt(
+                "Container hash mismatch".to_string(),
+            ));
+        }
+
+        reader
+            .seek(SeekFrom::Start(0))
+            .map_err(TokenReaderError::ReadError)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,343 @@
+//! Describing the layout of a multipart container, for debugging purposes,
+//! and checking its structure.
+//!
+//! These are structural walks of the container, which do not decode the
+//! contents of sections. `Layout::read` decompresses sections to find their
+//! length, `table_of_contents` and `verify_structure` do not decompress anything,
+//! while `lazy_sections` decompresses each section on first access only.
+
+use bytes::compress::Compression;
+use bytes::serialize::{BytesDeserializer, Deserializer};
+use bytes::varnum::*;
+use multipart::footer::{footer_position, verify_footer};
+use multipart::{read_section_header, FORMAT_VERSION_WITHOUT_FOOTER};
+use util::ReadConst;
+use {BinjsError, TokenReaderError};
+
+use std;
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+/// Deserialize only the byte length of a decompressed section.
+struct LengthDeserializer;
+impl Deserializer for LengthDeserializer {
+    type Target = usize;
+    fn read<R: Read + Seek>(&self, inp: &mut R) -> Result<usize, std::io::Error> {
+        let mut buf = Vec::new();
+        inp.read_to_end(&mut buf)
+    }
+}
+
+/// Read the magic header and version number, returning the version.
+fn read_version<R: Read + Seek>(reader: &mut R) -> Result<u32, TokenReaderError> {
+    reader
+        .seek(SeekFrom::Start(0))
+        .map_err(TokenReaderError::ReadError)?;
+    reader
+        .read_const(b"BINJS")
+        .map_err(|_| TokenReaderError::BadHeader)?;
+    reader.read_varnum().map_err(TokenReaderError::ReadError)
+}
+
+/// Skip the magic header and version number, returning the offset of the
+/// first section.
+fn skip_prologue<R: Read + Seek>(reader: &mut R) -> Result<u64, TokenReaderError> {
+    read_version(reader)?;
+    reader
+        .seek(SeekFrom::Current(0))
+        .map_err(TokenReaderError::ReadError)
+}
+
+/// Find the footer of a container, as `footer_position`.
+///
+/// Containers predating the footer are treated as ending with an empty
+/// footer.
+fn find_footer<R: Read + Seek>(reader: &mut R) -> Result<(u64, u64), TokenReaderError> {
+    if read_version(reader)? > FORMAT_VERSION_WITHOUT_FOOTER {
+        return footer_position(reader);
+    }
+    let total_len = reader
+        .seek(SeekFrom::End(0))
+        .map_err(TokenReaderError::ReadError)?;
+    Ok((total_len, 0))
+}
+
+/// Check that a container is structurally well-formed, without decompressing
+/// or decoding anything: the footer pointer and hash match the container, and
+/// sections, each with a known compression format and a byte length, exactly
+/// fill the container between the prologue and the footer.
+///
+/// Containers predating the footer have no hash to check.
+///
+/// This is much cheaper than decoding, e.g. to sweep a store for corruption,
+/// but does not detect invalid contents, e.g. a corrupted tree stored with a
+/// hash computed after the corruption.
+pub fn verify_structure<R: Read + Seek>(inp: &mut R) -> Result<(), BinjsError> {
+    let (footer_offset, footer_len) = find_footer(inp)?;
+    if footer_len != 0 {
+        verify_footer(inp)?;
+    }
+
+    let mut offset = skip_prologue(inp)?;
+    while offset < footer_offset {
+        read_section_header(inp)?;
+        let (_, byte_len) =
+            Compression::probe_with_len(inp).map_err(TokenReaderError::BadCompression)?;
+        offset = inp
+            .seek(SeekFrom::Current(byte_len as i64))
+            .map_err(TokenReaderError::ReadError)?;
+    }
+    if offset != footer_offset {
+        return Err(
+            TokenReaderError::ContainerCorrupt("Section exceeds container".to_string()).into(),
+        );
+    }
+    Ok(())
+}
+
+/// An entry of the table of contents of a container.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SectionEntry {
+    /// E.g. `"[GRAMMAR]"`.
+    pub name: String,
+
+    /// Offset of the section, from the start of the container.
+    pub offset: u64,
+
+    /// Byte length of the section, including its header, compression prefix
+    /// and byte length.
+    pub byte_len: u64,
+
+    pub compression: Compression,
+    pub compressed_bytes: usize,
+}
+
+/// List the sections of a container, reading only their headers, compression
+/// prefixes and byte lengths, and seeking over their contents.
+///
+/// Unlike `Layout::read`, this decompresses nothing, so it is cheap enough to
+/// e.g. list the contents of many files in a user interface.
+pub fn table_of_contents<R: Read + Seek>(
+    reader: &mut R,
+) -> Result<Vec<SectionEntry>, TokenReaderError> {
+    let (footer_offset, _) = find_footer(reader)?;
+
+    let mut sections = vec![];
+    let mut offset = skip_prologue(reader)?;
+    while offset < footer_offset {
+        let name = read_section_header(reader)?;
+        let (compression, compressed_bytes) =
+            Compression::probe_with_len(reader).map_err(TokenReaderError::BadCompression)?;
+        let end = reader
+            .seek(SeekFrom::Current(compressed_bytes as i64))
+            .map_err(TokenReaderError::ReadError)?;
+        if end > footer_offset {
+            return Err(TokenReaderError::ContainerCorrupt(
+                "Section exceeds container".to_string(),
+            ));
+        }
+        sections.push(SectionEntry {
+            name,
+            offset,
+            byte_len: end - offset,
+            compression,
+            compressed_bytes,
+        });
+        offset = end;
+    }
+    Ok(sections)
+}
+
+/// A section of a container in memory, decompressed on first access.
+pub struct LazySection<'a> {
+    /// E.g. `"[GRAMMAR]"`.
+    pub name: String,
+
+    pub compression: Compression,
+
+    /// The compressed block, following the header.
+    block: &'a [u8],
+
+    /// The maximal byte length of the section, once decompressed.
+    max_bytes: usize,
+
+    /// The decompressed bytes, once accessed.
+    bytes: OnceCell<Cow<'a, [u8]>>,
+}
+impl<'a> LazySection<'a> {
+    /// The decompressed bytes of the section, decompressing them on the first
+    /// call only. Uncompressed sections borrow the container.
+    pub fn get(&self) -> Result<&Cow<'a, [u8]>, TokenReaderError> {
+        if let Some(bytes) = self.bytes.get() {
+            return Ok(bytes);
+        }
+        let bytes = Compression::decompress_borrowed(
+            &mut Cursor::new(self.block),
+            &BytesDeserializer,
+            self.max_bytes,
+        )
+        .map_err(TokenReaderError::BadCompression)?;
+        Ok(self.bytes.get_or_init(|| bytes))
+    }
+
+    /// `true` once the section has been decompressed by `get`.
+    pub fn is_decompressed(&self) -> bool {
+        self.bytes.get().is_some()
+    }
+}
+impl<'a> std::fmt::Debug for LazySection<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.debug_struct("LazySection")
+            .field("name", &self.name)
+            .field("compression", &self.compression)
+            .field("decompressed", &self.is_decompressed())
+            .finish()
+    }
+}
+
+/// List the sections of a container in memory, as `table_of_contents`, each
+/// decompressed only once its bytes are accessed, e.g. for tools which only
+/// need some sections, and rejected if it exceeds `max_section_bytes` once
+/// decompressed.
+pub fn lazy_sections<'a>(
+    data: &'a [u8],
+    max_section_bytes: usize,
+) -> Result<Vec<LazySection<'a>>, TokenReaderError> {
+    let entries = table_of_contents(&mut Cursor::new(data))?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| {
+            let start = entry.offset as usize + entry.name.len();
+            let end = (entry.offset + entry.byte_len) as usize;
+            LazySection {
+                block: &data[start..end],
+                name: entry.name,
+                compression: entry.compression,
+                max_bytes: max_section_bytes,
+                bytes: OnceCell::new(),
+            }
+        })
+        .collect())
+}
+
+/// The layout of a section.
+#[derive(Clone, Debug)]
+pub struct SectionLayout {
+    /// E.g. `"[GRAMMAR]"`.
+    pub name: String,
+
+    /// Offset of the section, from the start of the container.
+    pub offset: u64,
+
+    /// Byte length of the section, including its header, compression prefix
+    /// and byte length.
+    pub byte_len: u64,
+
+    pub compression: Compression,
+    pub compressed_bytes: usize,
+    pub decompressed_bytes: usize,
+}
+
+/// The layout of a container.
+#[derive(Clone, Debug)]
+pub struct Layout {
+    /// Byte length of the magic header and version number.
+    pub prologue_len: u64,
+
+    pub sections: Vec<SectionLayout>,
+
+    /// Offset of the footer, from the start of the container.
+    pub footer_offset: u64,
+
+    /// Byte length of the footer, including the trailer, 0 if the container
+    /// predates the footer.
+    pub footer_len: u64,
+}
+impl Layout {
+    /// Walk the sections of a container.
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Self, TokenReaderError> {
+        let corrupt = |detail: &str| TokenReaderError::ContainerCorrupt(detail.to_string());
+        let (footer_offset, footer_len) = find_footer(reader)?;
+
+        let prologue_len = skip_prologue(reader)?;
+
+        let mut sections = vec![];
+        let mut offset = prologue_len;
+        while offset < footer_offset {
+            let name = read_section_header(reader)?;
+            let block_offset = reader
+                .seek(SeekFrom::Current(0))
+                .map_err(TokenReaderError::ReadError)?;
+
+            let (compression, compressed_bytes) =
+                Compression::probe_with_len(reader).map_err(TokenReaderError::ReadError)?;
+
+            reader
+                .seek(SeekFrom::Start(block_offset))
+                .map_err(TokenReaderError::ReadError)?;
+            let decompressed_bytes = Compression::decompress(reader, &LengthDeserializer)
+                .map_err(TokenReaderError::ReadError)?;
+
+            let end = reader
+                .seek(SeekFrom::Current(0))
+                .map_err(TokenReaderError::ReadError)?;
+            if end > footer_offset {
+                return Err(corrupt("Section exceeds container"));
+            }
+            sections.push(SectionLayout {
+                name,
+                offset,
+                byte_len: end - offset,
+                compression,
+                compressed_bytes,
+                decompressed_bytes,
+            });
+            offset = end;
+        }
+
+        Ok(Layout {
+            prologue_len,
+            sections,
+            footer_offset,
+            footer_len,
+        })
+    }
+
+    /// The byte length of the container.
+    pub fn total_len(&self) -> u64 {
+        self.footer_offset + self.footer_len
+    }
+}
+
+impl std::fmt::Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        writeln!(
+            f,
+            "{name:<16} offset {offset:>8} length {len:>8}",
+            name = "(prologue)",
+            offset = 0,
+            len = self.prologue_len
+        )?;
+        for section in &self.sections {
+            writeln!(
+                f,
+                "{name:<16} offset {offset:>8} length {len:>8} codec {codec:<8} compressed {compressed:>8} decompressed {decompressed:>8}",
+                name = section.name,
+                offset = section.offset,
+                len = section.byte_len,
+                codec = section.compression.code(),
+                compressed = section.compressed_bytes,
+                decompressed = section.decompressed_bytes
+            )?;
+        }
+        writeln!(
+            f,
+            "{name:<16} offset {offset:>8} length {len:>8}",
+            name = "[FOOTER]",
+            offset = self.footer_offset,
+            len = self.footer_len
+        )?;
+        write!(f, "Total: {} bytes", self.total_len())
+    }
+}
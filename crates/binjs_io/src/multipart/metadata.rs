@@ -0,0 +1,248 @@
+//! Metadata on the source of a multipart container.
+//!
+//! Metadata is not part of the AST, but is needed to reconstruct a source
+//! that behaves as the original, e.g. a leading `#!` line that lets
+//! the file be used as an executable script.
+//!
+//! Format:
+//!
+//! - the characters `"[METADATA]"`;
+//! - a `prefix` identifying the compression format used for the metadata (always "identity;",
+//!   so that readers may fetch the metadata without setting up a decompressor, see `Metadata::read`);
+//! - the number of compressed bytes (`varnum`);
+//! - compressed in the format identified by `prefix`:
+//!    - the number of entries (`varnum`);
+//!    - for each entry,
+//!      - byte length of key (`varnum`);
+//!      - key (utf-8 encoded, no terminator);
+//!      - byte length of value (`varnum`);
+//!      - value (utf-8 encoded, no terminator).
+//!
+//! Keys starting with `"user."` hold user metadata, e.g. build information, under the rest
+//! of the key, in the order of their keys. Readers ignore entries with other unknown keys.
+
+use bytes::compress::Compression;
+use bytes::frame::{read_exactly, read_length_prefixed};
+use bytes::serialize::Deserializer;
+use bytes::varnum::*;
+use multipart::parens::Parens;
+use multipart::read::skip_section;
+use multipart::{HEADER_METADATA, HEADER_PADDING};
+use util::ReadConst;
+use TokenReaderError;
+
+use std;
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+/// The UTF-8 byte order mark.
+const BOM: char = '\u{FEFF}';
+
+/// Key of the entry holding the shebang.
+const KEY_SHEBANG: &str = "shebang";
+
+/// Key of the entry present if the source started with a byte order mark.
+const KEY_BOM: &str = "bom";
+
+/// Prefix of the keys of the entries holding user metadata.
+const KEY_USER_PREFIX: &str = "user.";
+
+/// Maximal byte length of the metadata section.
+///
+/// Anything larger is certainly the result of a corrupted length.
+const MAX_METADATA_LEN: usize = 1 << 20;
+
+/// Metadata on the source of a container.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// The leading `#!` line of the source, without its line terminator.
+    pub shebang: Option<String>,
+
+    /// If `true`, the source started with a UTF-8 byte order mark.
+    pub bom: bool,
+
+    /// The parentheses written around expressions of the source.
+    ///
+    /// Stored in a section of their own rather than in the metadata
+    /// section (see module `parens`).
+    pub parens: Parens,
+
+    /// Arbitrary metadata, e.g. the commit a file was built from.
+    ///
+    /// Written in the order of keys, so that the same metadata always
+    /// produces the same bytes.
+    pub user: BTreeMap<String, String>,
+}
+impl Metadata {
+    /// `true` if there is no metadata to store in the metadata section, in
+    /// which case it is omitted. Parenthesization hints are not counted.
+    pub fn is_empty(&self) -> bool {
+        self.shebang.is_none() && !self.bom && self.user.is_empty()
+    }
+
+    /// Split the prologue (byte order mark, shebang) of a source, which the
+    /// JavaScript parser does not accept, from the rest of the source.
+    pub fn from_source(source: &str) -> (Self, &str) {
+        let mut metadata = Metadata::default();
+        let mut source = source;
+        if source.starts_with(BOM) {
+            metadata.bom = true;
+            source = &source[BOM.len_utf8()..];
+        }
+        if source.starts_with("#!") {
+            let end = source
+                .find(|c| c == '\n' || c == '\r' || c == '\u{2028}' || c == '\u{2029}')
+                .unwrap_or_else(|| source.len());
+            metadata.shebang = Some(source[..end].to_string());
+            source = &source[end..];
+            if source.starts_with("\r\n") {
+                source = &source[2..];
+            } else if let Some(c) = source.chars().next() {
+                source = &source[c.len_utf8()..];
+            }
+        }
+        (metadata, source)
+    }
+
+    /// Reattach the prologue to a source, e.g. one pretty-printed from the
+    /// decoded AST.
+    pub fn restore(&self, source: &str) -> String {
+        let mut result = String::with_capacity(source.len() + 256);
+        if self.bom {
+            result.push(BOM);
+        }
+        if let Some(ref shebang) = self.shebang {
+            result.push_str(shebang);
+            result.push('\n');
+        }
+        result.push_str(source);
+        result
+    }
+
+    /// Read the metadata of a container, without reading any other section.
+    ///
+    /// This neither decompresses anything, as the metadata section is never
+    /// compressed, nor checks the hash of the container, so it is cheap
+    /// even for large containers. Returns empty metadata if the container
+    /// has none. Parenthesization hints, which are stored in a section of
+    /// their own, are not read.
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Self, TokenReaderError> {
+        reader
+            .seek(SeekFrom::Start(0))
+            .map_err(TokenReaderError::ReadError)?;
+        reader
+            .read_const(b"BINJS")
+            .map_err(|_| TokenReaderError::BadHeader)?;
+        reader.read_varnum().map_err(TokenReaderError::ReadError)?;
+        // With `Options::align`, the metadata section may be padded.
+        loop {
+            let start = reader
+                .seek(SeekFrom::Current(0))
+                .map_err(TokenReaderError::ReadError)?;
+            if reader.read_const(HEADER_PADDING.as_bytes()).is_err() {
+                reader
+                    .seek(SeekFrom::Start(start))
+                    .map_err(TokenReaderError::ReadError)?;
+                break;
+            }
+            skip_section(reader)?;
+        }
+        if reader.read_const(HEADER_METADATA.as_bytes()).is_err() {
+            return Ok(Metadata::default());
+        }
+        match Compression::probe(reader).map_err(TokenReaderError::BadCompression)? {
+            Compression::Identity => {}
+            _ => {
+                return Err(TokenReaderError::ContainerCorrupt(
+                    "Compressed metadata section".to_string(),
+                ));
+            }
+        }
+        let bytes =
+            read_length_prefixed(reader, MAX_METADATA_LEN).map_err(TokenReaderError::ReadError)?;
+        MetadataDeserializer
+            .read(&mut Cursor::new(bytes))
+            .map_err(TokenReaderError::ReadError)
+    }
+
+    /// Write the metadata, without compression.
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<usize, std::io::Error> {
+        let mut entries = vec![];
+        if let Some(ref shebang) = self.shebang {
+            entries.push((KEY_SHEBANG.to_string(), shebang.as_str()));
+        }
+        if self.bom {
+            entries.push((KEY_BOM.to_string(), ""));
+        }
+        for (key, value) in &self.user {
+            entries.push((format!("{}{}", KEY_USER_PREFIX, key), value.as_str()));
+        }
+
+        let mut total = out.write_varnum(entries.len() as u32)?;
+        for (key, value) in entries {
+            for string in &[key.as_str(), value] {
+                total += out.write_varnum(string.len() as u32)?;
+                out.write_all(string.as_bytes())?;
+                total += string.len();
+            }
+        }
+        Ok(total)
+    }
+}
+
+/// Deserialize a `Metadata`.
+pub struct MetadataDeserializer;
+impl Deserializer for MetadataDeserializer {
+    type Target = Metadata;
+    fn read<R: Read + Seek>(&self, inp: &mut R) -> Result<Metadata, std::io::Error> {
+        let read_string = |inp: &mut R| -> Result<String, std::io::Error> {
+            let byte_len = inp.read_varnum()?;
+            let bytes = read_exactly(inp, byte_len as usize)?;
+            String::from_utf8(bytes)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+        };
+
+        let mut metadata = Metadata::default();
+        let number_of_entries = inp.read_varnum()?;
+        for _ in 0..number_of_entries {
+            let key = read_string(inp)?;
+            let value = read_string(inp)?;
+            match key.as_str() {
+                KEY_SHEBANG => metadata.shebang = Some(value),
+                KEY_BOM => metadata.bom = true,
+                _ if key.starts_with(KEY_USER_PREFIX) => {
+                    metadata
+                        .user
+                        .insert(key[KEY_USER_PREFIX.len()..].to_string(), value);
+                }
+                _ => {
+                    // Written by a more recent encoder, ignore.
+                }
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+#[test]
+fn test_metadata_from_source() {
+    let (metadata, rest) = Metadata::from_source("\u{FEFF}#!/usr/bin/env node\r\nfoo();");
+    assert_eq!(
+        metadata,
+        Metadata {
+            shebang: Some("#!/usr/bin/env node".to_string()),
+            bom: true,
+            parens: Parens::default(),
+            user: BTreeMap::new(),
+        }
+    );
+    assert_eq!(rest, "foo();");
+    assert_eq!(
+        metadata.restore(rest),
+        "\u{FEFF}#!/usr/bin/env node\nfoo();"
+    );
+
+    let (metadata, rest) = Metadata::from_source("foo(); // #!");
+    assert!(metadata.is_empty());
+    assert_eq!(rest, "foo(); // #!");
+}
@@ -11,9 +11,14 @@
 //!
 //! The sections are:
 //!
-//! 1. the grammar table;
-//! 2. the strings table (which contains both strings and identifiers);
-//! 3. the representation of the tree.
+//! 1. optionally, the metadata (see module `metadata`);
+//! 2. optionally, the diagnostics (see module `diagnostics`);
+//! 3. optionally, the parenthesization hints (see module `parens`);
+//! 4. the grammar table;
+//! 5. the strings table (which contains both strings and identifiers) or, if strings are inline, an empty
+//!    `[STRINGS-INLINE]` section;
+//! 6. optionally, the numbers table;
+//! 7. the representation of the tree.
 //!
 //! The grammar table lists the AST nodes used in the file. Its primary role is to serve as a lightweight
 //! versioning mechanism - for instance, older versions of JS may define a node `Function` with three fields
@@ -27,7 +32,13 @@
 //!
 //! The strings table lists all strings (including identifiers) in the file. Its primary role is to speed
 //! up parsing by making sure that each string only needs to be parsed/checked/atomized once during parsing.
-//! Its secondary role is compression.
+//! Its secondary role is compression. For tiny files, the table may cost more than it saves, so
+//! strings may instead be stored inline in the tree, in which case the strings table is replaced with
+//! an empty `[STRINGS-INLINE]` section, so that readers need not guess the layout of the tree.
+//!
+//! The numbers table, if present, lists all number literals in the file. Gathering numbers in a
+//! section of their own lets compressors take advantage of their distribution (typically, many
+//! small integers), instead of having them interleaved with the tree.
 //!
 //! In the current version, the tree is a sequence of tokens. All these tokens are ambiguous and a stream may
 //! only be tokenized by a client that knows both the grammar and the grammar table. Specific tokens (lists)
@@ -39,10 +50,48 @@
 //! The entire file is formatted as:
 //!
 //! - the characters `"BINJS"`;
-//! - a container version number (`varnum`, currently `0`);
+//! - a container version number (`varnum`, currently `2`, see `FORMAT_VERSION`; readers also
+//!   accept the deprecated version `1`, without a footer, with a `DecodeWarning`);
+//! - optionally, the metadata (see module `metadata`);
+//! - optionally, the diagnostics (see module `diagnostics`);
+//! - optionally, the parenthesization hints (see module `parens`);
 //! - the compressed grammar table (see below);
-//! - the compressed strings table (see below);
-//! - the compressed tree (see below).
+//! - the compressed strings table (see below), or an empty `[STRINGS-INLINE]` section;
+//! - optionally, the compressed numbers table (see below);
+//! - optionally, the node indices (see below);
+//! - the compressed tree, in pre-order or in breadth-first order (see below);
+//! - the footer (see module `footer`), except in containers of version `1` or less.
+//!
+//! ## Optional sections
+//!
+//! More recent encoders may add sections of their own. A section whose header starts with
+//! `"[?"`, e.g. `"[?FUTURE]"`, is optional: readers that do not know it skip it, using its
+//! byte length. Readers reject the file if it contains any other unknown section. Likewise,
+//! readers skip the metadata, the diagnostics and the parenthesization hints if they are
+//! compressed with an unknown compression format, but reject the file if any other section is.
+//!
+//! ## Padding
+//!
+//! Sections may be preceded by an optional section `"[?PADDING]"` of uncompressed zeros, so
+//! that their compressed bytes, after their header, compression prefix, byte length and size
+//! hint, start at a multiple of some number of bytes, from the start of the file (see
+//! `Options::align`). Readers skip it, and do not count it towards `ReadLimits::max_sections`.
+//!
+//! ## Empty sections
+//!
+//! Instead of a `prefix`, a number of compressed bytes and compressed bytes, an empty section
+//! may be written as the single character `";"`, i.e. an empty `prefix` (see
+//! `Options::empty_section_marker`). A table without entries may be written as an empty section.
+//!
+//! ## Size hints
+//!
+//! If the `prefix` of a section ends with `+size`, e.g. `"br+size;"`, its compressed bytes are
+//! preceded by the number of decompressed bytes (`varnum`), counted in the number of compressed
+//! bytes, so that readers may allocate the decompressed section at once (see `Options::size_hints`).
+//!
+//! Likewise, the `"compress;"` prefix may be flagged by `+msb`, e.g. `"compress+msb;"` or
+//! `"compress+msb+size;"`, if its lzw codes are packed most significant bit first, as by
+//! `compress(1)`, rather than least significant bit first.
 //!
 //! ## Grammar table
 //!
@@ -59,6 +108,15 @@
 //!        - the invalid strings [255, 0] (representing the null interface, only valid if byte length is 2);
 //!        - a utf-8 encoded string (utf-8 encoded, `bytelen` bytes, no terminator).
 //!
+//! ### Omitted fields
+//!
+//! Alternatively, fields holding their default value may be omitted from the tree (see
+//! `Options::omit_defaults`). The grammar table is then introduced by the characters
+//! `"[GRAMMAR-PRESENT-FIELDS]"`, and each tagged tuple of the tree by the bitmask of its
+//! fields that are present (`varnum`), bit `i` standing for field `i`. Fields beyond the
+//! 32nd are always present. An omitted field holds `false`, a null string or number, or
+//! an empty list, depending on its type.
+//!
 //! ## Strings table
 //!
 //! The grammar table serves to map tagged tuple indices to strings.
@@ -74,6 +132,44 @@
 //!        - the invalid strings [255, 0] (representing the null string, only valid if byte length is 2);
 //!        - a utf-8 encoded string (utf-8 encoded, `bytelen` bytes, no terminator).
 //!
+//! ### Partially interned strings
+//!
+//! Alternatively, only some strings may be interned (see `Options::intern_min_count`). The
+//! strings table is then introduced by the characters `"[STRINGS-MIXED]"`, and each string
+//! of the tree is flagged by the lowest bit of a `varnum`:
+//!
+//! - `index << 1`, for a string of the strings table;
+//! - `(byte_len << 1) | 1`, followed by the `byte_len` bytes of the string, for an inline string.
+//!
+//! ## Numbers table
+//!
+//! The numbers table serves to map number indices to floating point values.
+//!
+//! - the characters `"[NUMBERS]"`;
+//! - a `prefix` identifying the compression format used for the numbers (one of "identity;", "br;", "gzip;", "compress;", "deflate;").
+//! - the number of compressed bytes (`varnum`);
+//! - compressed in the format identified by `prefix`;
+//!    - the number of entries (`varnum`);
+//!    - for each entry,
+//!      - a low-endian IEEE764 64-bit floating point value (8 bytes, signalling NaN for null).
+//!
+//! ## Node indices
+//!
+//! Optionally (see `Options::entropy_node_indices`), the entries in the grammar table of tagged
+//! tuples are not written in the tree, but range-encoded in a section of their own, just before
+//! the tree.
+//!
+//! - the characters `"[NODE-INDICES]"`;
+//! - a `prefix` identifying the compression format used for the node indices (always "identity;").
+//! - the number of compressed bytes (`varnum`);
+//! - compressed in the format identified by `prefix`;
+//!    - the number of entries of the grammar table (`varnum`);
+//!    - for each entry, its frequency (`varnum`, non-zero);
+//!    - up to the end of the section, the entry of each tagged tuple of the tree, in pre-order,
+//!      range-encoded (see `range_encoding::opus`) with these frequencies.
+//!
+//! As these entries are read in order, lazy fields cannot be skipped.
+//!
 //! ## The tree
 //!
 //! This contains the actual tree for a specific grammar. The file does not contain all the information
@@ -85,6 +181,27 @@
 //! - compressed in the format identified by `prefix`:
 //!   - one tree token.
 //!
+//! ### Breadth-first tree
+//!
+//! Alternatively, the tree may be written in breadth-first order (see `TreeOrder`). Each tagged
+//! tuple is then cut from its parent, leaving a *segment*: the tokens of the tagged tuple, minus
+//! its tagged tuple descendants, which are themselves cut into segments. The bytes outside of any
+//! tagged tuple form the root segment. Segments are numbered in breadth-first order, starting
+//! from the root segment, so the children of a segment are numbered consecutively, in the order
+//! in which the pre-order tree would contain them.
+//!
+//! - the characters `"[TREE-BFS]"`;
+//! - a `prefix` identifying the compression format used for the grammar (one of "identity;", "br;", "gzip;", "compress;", "deflate;").
+//! - the number of compressed bytes (`varnum`);
+//! - compressed in the format identified by `prefix`:
+//!   - the number of segments (`varnum`);
+//!   - for each segment, in order,
+//!     - its byte length (`varnum`);
+//!     - its number of children (`varnum`);
+//!   - the bytes of all segments, in order.
+//!
+//! Offsets are those of the pre-order tree, so lazy fields cannot be skipped.
+//!
 //! ### Tree token
 //!
 //!  A tree token is defined as one of
@@ -95,40 +212,184 @@
 //!     - a low-endian IEEE764 64-bit floating point value signalling NaN (8 bytes),
 //!   - a non-null float, represented as:
 //!     - a low-endian IEEE764 64-bit floating point value non-signalling NaN (8 bytes),
+//!   - if the file contains a numbers table, a float or null float, instead represented as:
+//!     - an entry in the table of numbers (`varnum`);
 //!   - a null boolean, represented as:
 //!     -  a single byte with value `2` (one byte);
 //!   - a non-null boolean, represented as:
 //!     -  a single byte with value `0` (false) or `1` (true) (one byte);
 //!   - a string, representing as
 //!     - an entry in the table of strings (`varnum`);
+//!   - if the file contains a `[STRINGS-INLINE]` section, a string, instead represented as:
+//!     - byte length of string (`varnum`);
+//!     - one of
+//!       - the invalid strings [255, 0] (representing the null string, only valid if byte length is 2);
+//!       - a utf-8 encoded string (utf-8 encoded, `bytelen` bytes, no terminator).
 //!   - a list, represented as
 //!       - number of items (`varnum`);
 //!       - for each item
 //!          - the token;
 //!   - a tagged tuple, represented as
-//!     - an entry in the grammar table (`varnum`);
-//!     - for each field
+//!     - an entry in the grammar table (`varnum`), unless the file contains node indices;
+//!     - if fields may be omitted, the bitmask of the fields that are present (`varnum`);
+//!     - for each field, unless omitted
 //!       - the token
 
-use binjs_shared::SharedString;
+use entropy::dictionary::{FilesContaining, UserExtensibleData};
+
+use binjs_shared::{SharedString, F64};
 
 use clap;
+use TokenReaderError;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Read;
+use std::rc::Rc;
+
+/// Non-fatal observations on the source, e.g. use of `eval`.
+mod diagnostics;
+
+/// The footer, holding a container-wide hash.
+mod footer;
+
+/// Describing the sections of a container, for debugging purposes.
+mod layout;
+
+/// Metadata on the source, e.g. a shebang.
+mod metadata;
+
+/// The parentheses written around expressions of the source.
+mod parens;
+
+/// Dumping sections before compression.
+mod raw;
 
 /// Implementation of the token reader.
 mod read;
 
+/// Storing sections in a content-addressed store, shared between containers.
+mod store;
+
+/// Embedding containers in text.
+mod text;
+
 /// Implementation of the token writer.
 mod write;
 
+/// The version of the container format, written after the characters `"BINJS"`.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// The last version of the container format without a footer.
+///
+/// Readers still accept such containers, without checking their integrity,
+/// with a `DecodeWarning`.
+const FORMAT_VERSION_WITHOUT_FOOTER: u32 = 1;
+
+/// The header of the metadata section.
+const HEADER_METADATA: &str = "[METADATA]";
+
+/// The header of the diagnostics section.
+const HEADER_DIAGNOSTICS: &str = "[?DIAGNOSTICS]";
+
+/// The header of the parenthesization hints section.
+const HEADER_PARENS: &str = "[?PARENS]";
+
 /// The header of the strings table section.
 const HEADER_STRINGS_TABLE: &str = "[STRINGS]";
 
+/// The header of the strings table section, if only some strings are
+/// interned (see `Options::intern_min_count`).
+const HEADER_STRINGS_TABLE_MIXED: &str = "[STRINGS-MIXED]";
+
+/// The header of the empty section written instead of the strings table,
+/// if all strings are stored inline in the tree (see `Options::inline_strings`).
+const HEADER_STRINGS_INLINE: &str = "[STRINGS-INLINE]";
+
+/// The header of the numbers table section.
+const HEADER_NUMBERS_TABLE: &str = "[NUMBERS]";
+
 /// The header of the grammars table section.
 const HEADER_GRAMMAR_TABLE: &str = "[GRAMMAR]";
 
+/// The header of the grammars table section, if tagged tuples of the tree
+/// are followed by the bitmask of their fields that are present (see
+/// `Options::omit_defaults`).
+const HEADER_GRAMMAR_TABLE_PRESENT_FIELDS: &str = "[GRAMMAR-PRESENT-FIELDS]";
+
+/// The number of fields of a tagged tuple that may be omitted, i.e. the bits
+/// of its bitmask of present fields (see `Options::omit_defaults`). Further
+/// fields are always present.
+const MAX_OMITTED_FIELDS: usize = 32;
+
+/// The header of the node indices section (see `Options::entropy_node_indices`).
+const HEADER_NODE_INDICES: &str = "[NODE-INDICES]";
+
+/// The sum of the frequencies of node indices, before giving unused entries
+/// of the grammar table a frequency of 1 (see `Options::entropy_node_indices`).
+const MAX_NODE_INDICES_FREQUENCY: u32 = 1 << 15;
+
+/// The header of the table of contents of lazy fields referenced from the
+/// tree (see `Options::dedup_bodies`).
+const HEADER_BODIES: &str = "[BODIES]";
+
 /// The header of the tree section.
 const HEADER_TREE: &str = "[TREE]";
 
+/// The header of the tree section, if the tree is written in breadth-first
+/// order (see `TreeOrder`).
+const HEADER_TREE_BREADTH_FIRST: &str = "[TREE-BFS]";
+
+/// The header of the footer.
+const HEADER_FOOTER: &str = "[FOOTER]";
+
+/// The header of the sections padding the container (see `Options::align`).
+///
+/// Optional, so that decoders predating padding skip it.
+const HEADER_PADDING: &str = "[?PADDING]";
+
+/// Byte length of the sources below which encoders store strings inline by
+/// default, as the strings table generally costs more than it saves.
+pub const INLINE_STRINGS_MAX_SOURCE_BYTES: usize = 128;
+
+/// `true` if encoders store the strings of a source of `source_len` bytes
+/// inline by default (see `INLINE_STRINGS_MAX_SOURCE_BYTES`).
+pub fn inline_strings_by_default(source_len: usize) -> bool {
+    source_len < INLINE_STRINGS_MAX_SOURCE_BYTES
+}
+
+/// The prefix of the headers of optional sections, e.g. `"[?FUTURE]"`.
+///
+/// Readers skip optional sections they do not know, but reject other
+/// unknown sections.
+const OPTIONAL_SECTION_PREFIX: &str = "[?";
+
+/// Maximal byte length of a section header, e.g. `"[GRAMMAR]"`.
+const MAX_HEADER_LEN: usize = 32;
+
+/// Read the header of a section, e.g. `"[GRAMMAR]"`.
+fn read_section_header<R: Read>(reader: &mut R) -> Result<String, TokenReaderError> {
+    let corrupt = || TokenReaderError::ContainerCorrupt("Invalid section header".to_string());
+    let mut name = vec![];
+    loop {
+        let mut buf = [0];
+        reader
+            .read_exact(&mut buf)
+            .map_err(TokenReaderError::ReadError)?;
+        name.push(buf[0]);
+        if buf[0] == b']' {
+            break;
+        }
+        if name.len() >= MAX_HEADER_LEN {
+            return Err(corrupt());
+        }
+    }
+    if name[0] != b'[' {
+        return Err(corrupt());
+    }
+    String::from_utf8(name).map_err(|_| corrupt())
+}
+
 /// A trait specifying whether a piece of data needs the addition of a length index.
 trait FormatInTable {
     const HAS_LENGTH_INDEX: bool;
@@ -138,9 +399,351 @@ impl FormatInTable for Option<SharedString> {
     const HAS_LENGTH_INDEX: bool = false;
 }
 
-pub use self::read::TreeTokenReader;
+impl FormatInTable for Option<F64> {
+    const HAS_LENGTH_INDEX: bool = false;
+}
+
+pub use self::diagnostics::Diagnostic;
+pub use self::footer::{verified_digest, write_footer, ContainerHash};
+pub use self::layout::{
+    lazy_sections, table_of_contents, verify_structure, Layout, LazySection, SectionEntry,
+    SectionLayout,
+};
+pub use self::metadata::Metadata;
+pub use self::parens::Parens;
+pub use self::raw::{read_raw_sections, write_raw_header, write_raw_section, RAW_PREAMBLE};
+pub use self::read::{ReadLimits, TreeTokenReader, DEFAULT_MAX_SECTIONS};
+pub use self::store::{
+    resolve_sections, split_chunks, split_sections, store_chunks, store_sections,
+};
+pub use self::text::{binary_form, from_text, is_text, to_text, TEXT_PREAMBLE};
 pub use self::write::{Statistics, Targets, TreeTokenWriter};
 
+/// A callback invoked once for each unique string of the strings table,
+/// along with its number of instances in the file.
+///
+/// Null strings are not reported.
+#[derive(Clone)]
+pub struct OnIntern(pub Rc<dyn Fn(&str, usize)>);
+impl ::std::fmt::Debug for OnIntern {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "OnIntern")
+    }
+}
+
+/// A callback invoked with the header and the serialized bytes of each
+/// section, before compression.
+#[derive(Clone)]
+pub struct OnSection(pub Rc<dyn Fn(&str, &[u8])>);
+impl ::std::fmt::Debug for OnSection {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "OnSection")
+    }
+}
+
+/// A callback picking the compression of a section from its header and its
+/// serialized bytes, before compression, e.g. to leave small sections
+/// uncompressed.
+#[derive(Clone)]
+pub struct ChooseCompression(pub Rc<dyn Fn(&str, &[u8]) -> ::bytes::compress::Compression>);
+impl ::std::fmt::Debug for ChooseCompression {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "ChooseCompression")
+    }
+}
+
+/// The order in which the nodes of the tree are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TreeOrder {
+    /// Each node is immediately followed by its fields, recursively.
+    PreOrder,
+
+    /// Nodes are written depth by depth, so that nodes of the same depth,
+    /// which tend to be similar, are adjacent. Lazy fields cannot be skipped.
+    BreadthFirst,
+}
+impl Default for TreeOrder {
+    fn default() -> Self {
+        TreeOrder::PreOrder
+    }
+}
+impl TreeOrder {
+    pub fn parse(name: Option<&str>) -> Option<TreeOrder> {
+        let result = match name {
+            None | Some("pre-order") => TreeOrder::PreOrder,
+            Some("breadth-first") => TreeOrder::BreadthFirst,
+            Some(_) => {
+                return None;
+            }
+        };
+        Some(result)
+    }
+
+    /// The header of the tree section written in this order.
+    fn header(&self) -> &'static str {
+        match *self {
+            TreeOrder::PreOrder => HEADER_TREE,
+            TreeOrder::BreadthFirst => HEADER_TREE_BREADTH_FIRST,
+        }
+    }
+}
+
+/// Options for writing a multipart container.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// The algorithm used to hash the entire container.
+    ///
+    /// The hash is stored in the footer and checked by readers
+    /// before decoding anything.
+    pub container_hash: ContainerHash,
+
+    /// If `true`, store numbers in a numbers table, referenced
+    /// by index from the tree, rather than inline in the tree.
+    pub numbers_table: bool,
+
+    /// If `true`, store strings inline in the tree, rather than in a
+    /// strings table referenced by index from the tree.
+    ///
+    /// This is smaller for tiny files, in which few strings are repeated.
+    /// See `INLINE_STRINGS_MAX_SOURCE_BYTES`.
+    pub inline_strings: bool,
+
+    /// If greater than 1, only intern the strings used at least this many
+    /// times in the file, storing other strings inline in the tree.
+    ///
+    /// Ignored if `inline_strings` is `true`.
+    pub intern_min_count: u32,
+
+    /// If specified, the number of files containing each string,
+    /// across a corpus.
+    ///
+    /// Entries of the strings table are then ordered by decreasing
+    /// global frequency, rather than by decreasing local frequency, so
+    /// that common strings consistently get small indices across files.
+    pub global_string_frequencies: Option<Rc<HashMap<Option<SharedString>, usize>>>,
+
+    /// Metadata on the source, stored alongside the AST.
+    pub metadata: Metadata,
+
+    /// Non-fatal observations on the source, stored alongside the AST.
+    ///
+    /// If empty, the diagnostics section is omitted.
+    pub diagnostics: Vec<Diagnostic>,
+
+    /// If specified, called for each string of the strings table, once the
+    /// table is complete, e.g. to accumulate statistics across files.
+    pub on_intern: Option<OnIntern>,
+
+    /// If specified, called for each section, in the order of the container,
+    /// with its bytes before compression, e.g. to analyze them with external
+    /// tools.
+    pub on_section: Option<OnSection>,
+
+    /// If specified, called for the grammar table, the strings table, the
+    /// numbers table and the tree to pick their compression, overriding the
+    /// targets. Other sections are never compressed.
+    pub choose_compression: Option<ChooseCompression>,
+
+    /// If specified, the statistics of each file are added to these
+    /// statistics once it is written, e.g. to accumulate statistics
+    /// across files.
+    pub statistics: Option<Rc<RefCell<Statistics>>>,
+
+    /// If `true`, a section that cannot be compressed, e.g. because of a bug
+    /// of the codec, is stored uncompressed, with a warning, rather than
+    /// failing the encoding of the whole file.
+    pub fallback_on_codec_error: bool,
+
+    /// If specified, the maximal byte length of the container, including the
+    /// section headers and the footer (and, with `text`, once encoded).
+    ///
+    /// Encoding fails with `TokenWriterError::OutputBudgetExceeded` as soon
+    /// as the bytes written so far, the next section and the footer exceed
+    /// this budget, before writing the section that crosses it.
+    pub max_output_bytes: Option<usize>,
+
+    /// If specified, encoding fails with `TokenWriterError::Cancelled` at
+    /// the next node or section once the token is cancelled.
+    pub cancellation: Option<::CancellationToken>,
+
+    /// If `true`, store the decompressed byte length of each compressed
+    /// section, so that decoders may allocate it exactly once rather than
+    /// growing a buffer.
+    ///
+    /// Flagged in the compression header of the section, e.g. `br+size;`,
+    /// so files without size hints remain readable.
+    pub size_hints: bool,
+
+    /// If `true`, store all NaNs with the same bit pattern, `std::f64::NAN`,
+    /// e.g. to improve deduplication in the numbers table.
+    ///
+    /// Otherwise, floats are stored with their exact bits, including the
+    /// payload of NaNs. In either case, `-0` remains distinct from `0`.
+    pub canonicalize_nan: bool,
+
+    /// If `true`, write empty sections, including tables without entries,
+    /// as a single byte, `;`, rather than as a frame of their codec.
+    ///
+    /// Smaller, but not readable by decoders predating the marker.
+    pub empty_section_marker: bool,
+
+    /// The order in which the nodes of the tree are written.
+    ///
+    /// Recorded in the header of the tree section, so decoders need not
+    /// be told.
+    pub tree_order: TreeOrder,
+
+    /// If `true`, range-encode the indices of tagged tuples into the grammar
+    /// table in a section of their own, `[NODE-INDICES]`, with their
+    /// frequencies in this file, rather than writing them in the tree as
+    /// `varnum`s.
+    ///
+    /// Generally smaller, but prevents skipping lazy functions.
+    ///
+    /// The bytes of the section are attributed to the statistics of each
+    /// node kind in proportion to the information content of its indices,
+    /// but not to the `total_bytes` of enclosing nodes, which only count the
+    /// bytes of the tree.
+    pub entropy_node_indices: bool,
+
+    /// If greater than 1, pad the container with `[?PADDING]` sections so
+    /// that the compressed bytes of every other section start at a multiple
+    /// of `align` bytes, from the start of the container, e.g. to access
+    /// sections of a memory-mapped file without misaligned loads.
+    pub align: usize,
+
+    /// If `true`, write the container in text form, i.e. in base64 after
+    /// `TEXT_PREAMBLE`, e.g. to embed it in a text protocol. Readers detect
+    /// this form automatically.
+    pub text: bool,
+
+    /// If `true`, write identical contents of lazy fields, e.g. function
+    /// bodies generated from a template, once, and replace the others by
+    /// references into a `[BODIES]` table.
+    ///
+    /// Ignored with `TreeOrder::BreadthFirst` or `entropy_node_indices`, as
+    /// contents are then not contiguous in the tree.
+    pub dedup_bodies: bool,
+
+    /// If `true`, omit the fields of tagged tuples that hold their default
+    /// value, i.e. `false`, a null string or number, or an empty list, from
+    /// the tree. Each tagged tuple is then followed by the bitmask of its
+    /// fields that are present, and decoders restore the others.
+    ///
+    /// Lazy fields are always present, so that they can still be skipped.
+    pub omit_defaults: bool,
+}
+impl Options {
+    /// Order the strings table using the frequencies collected while
+    /// building a dictionary.
+    ///
+    /// As identifiers, property keys, string literals and string enums
+    /// share the strings table, their frequencies are added up.
+    pub fn with_global_string_frequencies(
+        mut self,
+        dictionary: &UserExtensibleData<FilesContaining>,
+    ) -> Self {
+        let mut frequencies = HashMap::new();
+        {
+            let mut add = |string: Option<&SharedString>, instances: &FilesContaining| {
+                *frequencies.entry(string.cloned()).or_insert(0) += instances.0;
+            };
+            for (name, instances) in &dictionary.identifier_name_instances {
+                add(name.as_ref().map(|name| name.as_shared_string()), instances);
+            }
+            for (key, instances) in &dictionary.property_key_instances {
+                add(key.as_ref().map(|key| key.as_shared_string()), instances);
+            }
+            for (literal, instances) in &dictionary.string_literal_instances {
+                add(literal.as_ref(), instances);
+            }
+            for (string, instances) in &dictionary.string_enum_instances {
+                add(Some(string), instances);
+            }
+        }
+        self.global_string_frequencies = Some(Rc::new(frequencies));
+        self
+    }
+}
+
+/// A coherent combination of targets and options, trading encoding speed
+/// for size, so that callers need not tune each of them.
+///
+/// `balanced` and `smallest` write sections that older decoders cannot
+/// read, i.e. `[BODIES]` and, for `smallest`, `[GRAMMAR-PRESENT-FIELDS]`.
+///
+/// No preset uses a dictionary: the only one, `global_string_frequencies`,
+/// must be built from a corpus, which a preset cannot provide.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+    /// Nothing is compressed (`fastest`).
+    Fastest,
+
+    /// The tree is left uncompressed, as `Targets::fast_tree`, the other
+    /// sections are compressed with brotli at quality 9, much faster than
+    /// the maximal 11, and identical lazy contents are written once
+    /// (`balanced`).
+    Balanced,
+
+    /// Everything is compressed with brotli at its maximal quality,
+    /// identical lazy contents are written once and fields holding their
+    /// default value are omitted (`smallest`).
+    Smallest,
+}
+impl Preset {
+    pub fn parse(name: &str) -> Option<Preset> {
+        let result = match name {
+            "fastest" => Preset::Fastest,
+            "balanced" => Preset::Balanced,
+            "smallest" => Preset::Smallest,
+            _ => {
+                return None;
+            }
+        };
+        Some(result)
+    }
+
+    /// The compression of each section.
+    pub fn targets(&self) -> Targets {
+        use bytes::compress::Compression;
+        match *self {
+            Preset::Fastest => Targets::uniform(Compression::Identity),
+            Preset::Balanced => {
+                let mut targets = Targets::fast_tree();
+                targets.set_brotli_quality(9);
+                targets
+            }
+            Preset::Smallest => Targets::uniform(Compression::Brotli),
+        }
+    }
+
+    /// The options, other options holding their default value.
+    pub fn options(&self) -> Options {
+        Options {
+            dedup_bodies: *self != Preset::Fastest,
+            omit_defaults: *self == Preset::Smallest,
+            ..Options::default()
+        }
+    }
+}
+
+/// Parse the value of command-line argument `name`, if specified, failing
+/// with `InvalidInput` if it is malformed.
+fn parse_arg<T: std::str::FromStr>(
+    matches: Option<&clap::ArgMatches>,
+    name: &str,
+) -> Result<Option<T>, std::io::Error> {
+    match matches.and_then(|matches| matches.value_of(name)) {
+        None => Ok(None),
+        Some(value) => value.parse::<T>().map(Some).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Could not parse {} {:?}", name, value),
+            )
+        }),
+    }
+}
+
 /// Command-line management.
 pub struct FormatProvider;
 impl ::FormatProvider for FormatProvider {
@@ -152,12 +755,104 @@ impl ::FormatProvider for FormatProvider {
                 .help("(EXPERIMENTAL) Apply a secondary compression *inside* the file. Used only when compressing.")
                 .long("x-inner-compression")
                 .takes_value(true)
-                .possible_values(&["identity", "gzip", "deflate", "br", "lzw"])
+                .possible_values(&["identity", "gzip", "deflate", "br", "br-large", "lzw", "lzw-msb"])
+            )
+            .arg(Arg::with_name("profile")
+                .help("A preset compression for each section. `fast-tree` leaves the tree uncompressed and compresses other sections with brotli. Used only when compressing.")
+                .long("profile")
+                .takes_value(true)
+                .possible_values(&["fast-tree"])
+                .conflicts_with("x-inner-compression")
+            )
+            .arg(Arg::with_name("preset")
+                .help("A coherent combination of compressions and options, from the fastest to encode to the smallest. Options specified explicitly are enabled in addition. Used only when compressing.")
+                .long("preset")
+                .takes_value(true)
+                .possible_values(&["fastest", "balanced", "smallest"])
+                .conflicts_with_all(&["x-inner-compression", "profile"])
             )
             .arg(Arg::with_name("x-dump-sections")
                 .help("(EXPERIMENTAL) Export sections to individual files. Used only when compressing.")
                 .long("x-dump-sections")
             )
+            .arg(Arg::with_name("container-hash")
+                .help("The algorithm used to hash the entire file, to check its integrity when decoding. Used only when compressing.")
+                .long("container-hash")
+                .takes_value(true)
+                .possible_values(&["xxh64", "sha256"])
+            )
+            .arg(Arg::with_name("fallback-on-codec-error")
+                .help("If a section cannot be compressed, store it uncompressed rather than failing. Used only when compressing.")
+                .long("fallback-on-codec-error")
+            )
+            .arg(Arg::with_name("inline-strings")
+                .help("Store strings inline in the tree, rather than in a strings table. Smaller for tiny files. Used only when compressing.")
+                .long("inline-strings")
+            )
+            .arg(Arg::with_name("intern-min-count")
+                .help("Only store in the strings table the strings used at least this many times, storing other strings inline in the tree. Used only when compressing.")
+                .long("intern-min-count")
+                .takes_value(true)
+                .value_name("COUNT")
+            )
+            .arg(Arg::with_name("x-numbers-table")
+                .help("(EXPERIMENTAL) Store numbers in a table of their own, rather than inline in the tree. Used only when compressing.")
+                .long("x-numbers-table")
+            )
+            .arg(Arg::with_name("size-hints")
+                .help("Store the decompressed byte length of each section, so that decoders may allocate it at once. Used only when compressing.")
+                .long("size-hints")
+            )
+            .arg(Arg::with_name("canonicalize-nan")
+                .help("Store all NaNs with the same bit pattern, rather than with their exact bits. Used only when compressing.")
+                .long("canonicalize-nan")
+            )
+            .arg(Arg::with_name("empty-section-marker")
+                .help("Store empty sections, e.g. a strings table without strings, as a single byte. Used only when compressing.")
+                .long("empty-section-marker")
+            )
+            .arg(Arg::with_name("tree-order")
+                .help("The order in which the nodes of the tree are written. `breadth-first` groups nodes by depth, which may compress better, but prevents skipping lazy functions. Used only when compressing.")
+                .long("tree-order")
+                .takes_value(true)
+                .possible_values(&["pre-order", "breadth-first"])
+            )
+            .arg(Arg::with_name("entropy-node-indices")
+                .help("Range-encode the kinds of nodes in a section of their own, rather than in the tree. Generally smaller, but prevents skipping lazy functions. Used only when compressing.")
+                .long("entropy-node-indices")
+            )
+            .arg(Arg::with_name("dedup-bodies")
+                .help("Write identical bodies of lazy functions once, the others as references. Ignored with --tree-order breadth-first or --entropy-node-indices. Used only when compressing.")
+                .long("dedup-bodies")
+            )
+            .arg(Arg::with_name("omit-defaults")
+                .help("Omit fields holding their default value, i.e. false, null or an empty list, storing which fields are present in each node. Used only when compressing.")
+                .long("omit-defaults")
+            )
+            .arg(Arg::with_name("align")
+                .help("Pad the file so that the bytes of each section start at a multiple of this number of bytes, e.g. for memory-mapped reads. Used only when compressing.")
+                .long("align")
+                .takes_value(true)
+                .value_name("BYTES")
+            )
+            .arg(Arg::with_name("user-metadata")
+                .help("Store arbitrary metadata, e.g. build information, as KEY=VALUE. May be repeated. Used only when compressing.")
+                .long("user-metadata")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("KEY=VALUE")
+            )
+            .arg(Arg::with_name("text")
+                .help("Write the file in base64, after preamble `BINJS64:`, e.g. to embed it in a text protocol. Decoders detect this form automatically. Used only when compressing.")
+                .long("text")
+            )
+            .arg(Arg::with_name("max-output-bytes")
+                .help("Fail to encode a file if its output, including headers and footer, would exceed this number of bytes. Used only when compressing.")
+                .long("max-output-bytes")
+                .takes_value(true)
+                .value_name("BYTES")
+            )
     }
 
     fn handle_subcommand(
@@ -170,17 +865,115 @@ impl ::FormatProvider for FormatProvider {
         use std::cell::RefCell;
         use std::rc::Rc;
         let stats = Rc::new(RefCell::new(Statistics::default().with_source_bytes(0)));
-        let compression = matches
+        let preset = matches
+            .and_then(|matches| matches.value_of("preset"))
+            .map(|name| Preset::parse(name).expect("Could not parse preset"));
+        let defaults = preset.map(|preset| preset.options()).unwrap_or_default();
+        let profile = matches.and_then(|matches| matches.value_of("profile"));
+        let targets = match (profile, preset) {
+            (Some("fast-tree"), _) => Targets::fast_tree(),
+            (_, Some(preset)) => preset.targets(),
+            _ => {
+                let compression = matches
+                    .map(|matches| {
+                        Compression::parse(matches.value_of("x-inner-compression"))
+                            .expect("Could not parse x-inner-compression")
+                    })
+                    .unwrap_or(Compression::Identity);
+                Targets::uniform(compression)
+            }
+        };
+        let container_hash = matches
+            .map(|matches| {
+                ContainerHash::parse(matches.value_of("container-hash"))
+                    .expect("Could not parse container-hash")
+            })
+            .unwrap_or_default();
+        let numbers_table = matches
+            .map(|matches| matches.is_present("x-numbers-table"))
+            .unwrap_or(false);
+        let inline_strings = matches
+            .map(|matches| matches.is_present("inline-strings"))
+            .unwrap_or(false);
+        let intern_min_count = parse_arg(matches, "intern-min-count")?.unwrap_or(0);
+        let fallback_on_codec_error = matches
+            .map(|matches| matches.is_present("fallback-on-codec-error"))
+            .unwrap_or(false);
+        let size_hints = matches
+            .map(|matches| matches.is_present("size-hints"))
+            .unwrap_or(false);
+        let canonicalize_nan = matches
+            .map(|matches| matches.is_present("canonicalize-nan"))
+            .unwrap_or(false);
+        let empty_section_marker = matches
+            .map(|matches| matches.is_present("empty-section-marker"))
+            .unwrap_or(false);
+        let tree_order = matches
             .map(|matches| {
-                Compression::parse(matches.value_of("x-inner-compression"))
-                    .expect("Could not parse x-inner-compression")
+                TreeOrder::parse(matches.value_of("tree-order"))
+                    .expect("Could not parse tree-order")
+            })
+            .unwrap_or_default();
+        let entropy_node_indices = matches
+            .map(|matches| matches.is_present("entropy-node-indices"))
+            .unwrap_or(false);
+        let dedup_bodies = matches
+            .map(|matches| matches.is_present("dedup-bodies"))
+            .unwrap_or(false)
+            || defaults.dedup_bodies;
+        let omit_defaults = matches
+            .map(|matches| matches.is_present("omit-defaults"))
+            .unwrap_or(false)
+            || defaults.omit_defaults;
+        let align = parse_arg(matches, "align")?.unwrap_or(0);
+        let user_metadata = matches
+            .and_then(|matches| matches.values_of("user-metadata"))
+            .map(|entries| {
+                entries
+                    .map(|entry| {
+                        let mut split = entry.splitn(2, '=');
+                        let key = split.next().unwrap(); // `splitn` yields at least one item.
+                        match split.next() {
+                            Some(value) => Ok((key.to_string(), value.to_string())),
+                            None => Err(::std::io::Error::new(
+                                ::std::io::ErrorKind::InvalidInput,
+                                format!(
+                                    "Could not parse user-metadata {:?}, expected KEY=VALUE",
+                                    entry
+                                ),
+                            )),
+                        }
+                    })
+                    .collect::<Result<_, _>>()
             })
-            .unwrap_or(Compression::Identity);
+            .unwrap_or_else(|| Ok(Default::default()))?;
+        let text = matches
+            .map(|matches| matches.is_present("text"))
+            .unwrap_or(false);
+        let max_output_bytes = parse_arg(matches, "max-output-bytes")?;
         Ok(::Format::Multipart {
-            targets: Targets {
-                strings_table: ::CompressionTarget::new(compression.clone()),
-                grammar_table: ::CompressionTarget::new(compression.clone()),
-                tree: ::CompressionTarget::new(compression.clone()),
+            targets,
+            options: Options {
+                container_hash,
+                numbers_table,
+                inline_strings,
+                intern_min_count,
+                fallback_on_codec_error,
+                max_output_bytes,
+                size_hints,
+                canonicalize_nan,
+                empty_section_marker,
+                tree_order,
+                entropy_node_indices,
+                align,
+                text,
+                dedup_bodies,
+                omit_defaults,
+                metadata: Metadata {
+                    user: user_metadata,
+                    ..Metadata::default()
+                },
+                ..Options::default()
             },
             stats,
         })
@@ -223,6 +1016,7 @@ fn test_multipart_io() {
                     vec.push(Targets {
                         grammar_table: CompressionTarget::new(grammar_table.clone()),
                         strings_table: CompressionTarget::new(strings_table.clone()),
+                        numbers_table: CompressionTarget::new(strings_table.clone()),
                         tree: CompressionTarget::new(tree.clone()),
                     });
                 }
@@ -418,3 +1212,1790 @@ fn test_multipart_io() {
         }
     }
 }
+
+#[test]
+fn test_multipart_footer_hash() {
+    use binjs_shared::ast::Path;
+    use binjs_shared::SharedString;
+
+    use bytes::compress::Compression;
+    use io::{TokenReader, TokenWriterWithTree};
+    use TokenReaderError;
+
+    use std::io::Cursor;
+
+    for container_hash in &[ContainerHash::XxHash64, ContainerHash::Sha256] {
+        let mut writer = TreeTokenWriter::with_options(
+            Targets::uniform(Compression::Identity),
+            Options {
+                container_hash: container_hash.clone(),
+                ..Options::default()
+            },
+        );
+        writer
+            .string(Some(&SharedString::from_str("simple string")))
+            .expect("Writing simple string");
+        let output = writer.done().expect("Finalizing data");
+
+        // The untouched container decodes.
+        let mut reader = TreeTokenReader::new(Cursor::new(&output)).expect("Creating reader");
+        let simple_string = reader
+            .string_at(&Path::new())
+            .expect("Reading simple string")
+            .expect("Non-null string");
+        assert_eq!(&simple_string, "simple string");
+
+        // Flipping any byte is detected before decoding.
+        for i in 0..output.len() {
+            let mut corrupted = output.to_vec();
+            corrupted[i] ^= 0xFF;
+            match TreeTokenReader::new(Cursor::new(&corrupted)) {
+                Err(TokenReaderError::ContainerCorrupt(_)) => {}
+                Err(err) => panic!("Byte {}: unexpected error {:?}", i, err),
+                Ok(_) => panic!("Byte {}: corruption not detected", i),
+            }
+        }
+
+        // Truncating the container is detected, too.
+        match TreeTokenReader::new(Cursor::new(&output[..output.len() - 1])) {
+            Err(TokenReaderError::ContainerCorrupt(_)) => {}
+            _ => panic!("Truncation not detected"),
+        }
+
+        // Containers predating the footer are still read, with a warning.
+        let (footer_offset, _) = footer::footer_position(&mut Cursor::new(&output)).unwrap();
+        let mut old = output[..footer_offset as usize].to_vec();
+        assert_eq!(old[b"BINJS".len()], FORMAT_VERSION as u8);
+        old[b"BINJS".len()] = FORMAT_VERSION_WITHOUT_FOOTER as u8;
+        let mut reader = TreeTokenReader::new(Cursor::new(&old)).expect("Reading old container");
+        assert_eq!(
+            reader.warnings(),
+            &[::DecodeWarning::DeprecatedVersion(
+                FORMAT_VERSION_WITHOUT_FOOTER
+            )]
+        );
+        let simple_string = reader
+            .string_at(&Path::new())
+            .expect("Reading simple string")
+            .expect("Non-null string");
+        assert_eq!(&simple_string, "simple string");
+        assert_eq!(
+            table_of_contents(&mut Cursor::new(&old)).expect("Listing old container"),
+            table_of_contents(&mut Cursor::new(&output)).expect("Listing container")
+        );
+        verify_structure(&mut Cursor::new(&old)).expect("Checking old container");
+    }
+}
+
+#[test]
+fn test_multipart_numbers_table() {
+    use binjs_shared::ast::Path;
+
+    use bytes::compress::Compression;
+    use io::{TokenReader, TokenWriterWithTree};
+
+    use std::io::Cursor;
+
+    // Many numbers, mostly small integers, as in numeric-heavy code.
+    let numbers: Vec<_> = (0..1000)
+        .map(|i| match i % 7 {
+            0 => None,
+            1 => Some(3.1415),
+            _ => Some((i % 10) as f64),
+        })
+        .collect();
+
+    let encode = |numbers_table: bool| {
+        let mut writer = TreeTokenWriter::with_options(
+            Targets::uniform(Compression::Identity),
+            Options {
+                numbers_table,
+                ..Options::default()
+            },
+        );
+        let items = numbers
+            .iter()
+            .map(|value| writer.float(*value).expect("Writing float"))
+            .collect();
+        writer.list(items).expect("Writing list");
+        writer.done().expect("Finalizing data")
+    };
+
+    let interleaved = encode(false);
+    let separate = encode(true);
+
+    for output in &[&interleaved, &separate] {
+        let path = Path::new();
+        let mut reader = TreeTokenReader::new(Cursor::new(output)).expect("Creating reader");
+        let len = reader.enter_list_at(&path).expect("Reading list");
+        assert_eq!(len as usize, numbers.len());
+        for expected in &numbers {
+            let found = reader.float_at(&path).expect("Reading float");
+            assert_eq!(found, *expected);
+        }
+        reader.exit_list_at(&path).expect("List read properly");
+    }
+
+    // With few distinct values, indices are much smaller than inline floats.
+    assert!(
+        separate.len() < interleaved.len() / 2,
+        "Numbers table should shrink the file: {} vs. {}",
+        separate.len(),
+        interleaved.len()
+    );
+}
+
+#[test]
+fn test_multipart_global_string_order() {
+    use bytes::compress::Compression;
+    use io::TokenWriterWithTree;
+
+    let position = |output: &[u8], needle: &str| {
+        output
+            .windows(needle.len())
+            .position(|window| window == needle.as_bytes())
+            .expect("String not found")
+    };
+
+    let encode = |options: Options| {
+        let mut writer =
+            TreeTokenWriter::with_options(Targets::uniform(Compression::Identity), options);
+        let mut items = vec![];
+        for _ in 0..3 {
+            items.push(
+                writer
+                    .string(Some(&SharedString::from_str("local")))
+                    .expect("Writing string"),
+            );
+        }
+        items.push(
+            writer
+                .string(Some(&SharedString::from_str("global")))
+                .expect("Writing string"),
+        );
+        writer.list(items).expect("Writing list");
+        writer.done().expect("Finalizing data")
+    };
+
+    // Without a dictionary, the most locally-common string comes first.
+    let output = encode(Options::default());
+    assert!(position(&output, "local") < position(&output, "global"));
+
+    // With a dictionary, the most globally-common string comes first.
+    let mut dictionary = UserExtensibleData::default();
+    dictionary
+        .string_literal_instances
+        .insert(Some(SharedString::from_str("global")), FilesContaining(10));
+    dictionary
+        .string_literal_instances
+        .insert(Some(SharedString::from_str("local")), FilesContaining(1));
+    let output = encode(Options::default().with_global_string_frequencies(&dictionary));
+    assert!(position(&output, "global") < position(&output, "local"));
+}
+
+#[test]
+fn test_multipart_bad_compression_is_format_error() {
+    use binjs_shared::SharedString;
+
+    use bytes::compress::Compression;
+    use io::TokenWriterWithTree;
+    use BinjsError;
+    use TokenReaderError;
+
+    use std::io::Cursor;
+
+    let mut writer = TreeTokenWriter::new(Targets::uniform(Compression::Identity));
+    writer
+        .string(Some(&SharedString::from_str("simple string")))
+        .expect("Writing simple string");
+    let output = writer.done().expect("Finalizing data");
+
+    // Replace the compression prefix of the grammar table with garbage, then
+    // rehash, so that the corruption goes past the footer check.
+    let mut footer_offset = [0; 8];
+    footer_offset.copy_from_slice(&output[output.len() - 16..output.len() - 8]);
+    let mut corrupted = output[..u64::from_le_bytes(footer_offset) as usize].to_vec();
+    let prefix = corrupted
+        .windows(HEADER_GRAMMAR_TABLE.len())
+        .position(|window| window == HEADER_GRAMMAR_TABLE.as_bytes())
+        .expect("Finding the grammar table")
+        + HEADER_GRAMMAR_TABLE.len();
+    corrupted[prefix] = b'!';
+    footer::write_footer(&ContainerHash::default(), &mut corrupted).expect("Writing footer");
+
+    match TreeTokenReader::new(Cursor::new(&corrupted)) {
+        Err(err @ TokenReaderError::BadCompression(_)) => match BinjsError::from(err) {
+            BinjsError::Format(_) => {}
+            err => panic!("Unexpected category {:?}", err),
+        },
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Bad compression header not detected"),
+    }
+
+    // Failures of the underlying stream remain I/O errors.
+    let err = TokenReaderError::ReadError(std::io::Error::new(std::io::ErrorKind::Other, "disk"));
+    match BinjsError::from(err) {
+        BinjsError::Io(_) => {}
+        err => panic!("Unexpected category {:?}", err),
+    }
+}
+
+#[test]
+fn test_multipart_on_intern() {
+    use bytes::compress::Compression;
+    use io::TokenWriterWithTree;
+
+    use std::cell::RefCell;
+
+    let reported = Rc::new(RefCell::new(HashMap::new()));
+    let options = Options {
+        on_intern: Some(OnIntern({
+            let reported = reported.clone();
+            Rc::new(move |string: &str, count: usize| {
+                let previous = reported.borrow_mut().insert(string.to_string(), count);
+                assert!(previous.is_none(), "String {} reported twice", string);
+            })
+        })),
+        ..Options::default()
+    };
+    let mut writer =
+        TreeTokenWriter::with_options(Targets::uniform(Compression::Identity), options);
+    let mut items = vec![];
+    for string in &[Some("x"), Some("y"), Some("x"), None, Some("x"), None] {
+        let string = string.map(SharedString::from_str);
+        items.push(writer.string(string.as_ref()).expect("Writing string"));
+    }
+    writer.list(items).expect("Writing list");
+    writer.done().expect("Finalizing data");
+
+    let reported = reported.borrow();
+    assert_eq!(reported.len(), 2);
+    assert_eq!(reported["x"], 3);
+    assert_eq!(reported["y"], 1);
+}
+
+#[test]
+fn test_multipart_done_to_slow_sink() {
+    use bytes::compress::Compression;
+    use io::TokenWriterWithTree;
+    use TokenWriterError;
+
+    use std::io::{ErrorKind, Write};
+
+    /// A sink accepting at most `max` bytes per call, and reporting that it is
+    /// not ready once it holds `ready_until` bytes.
+    struct SlowSink {
+        data: Vec<u8>,
+        max: usize,
+        ready_until: usize,
+    }
+    impl Write for SlowSink {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+            if self.data.len() >= self.ready_until {
+                return Err(std::io::Error::new(ErrorKind::WouldBlock, "Not ready"));
+            }
+            let len = std::cmp::min(buf.len(), self.max);
+            self.data.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+        fn flush(&mut self) -> Result<(), std::io::Error> {
+            Ok(())
+        }
+    }
+
+    let writer = || {
+        let mut writer = TreeTokenWriter::new(Targets::uniform(Compression::Identity));
+        let mut items = vec![];
+        for string in &["foo", "bar", "foo", "a somewhat longer string"] {
+            let string = SharedString::from_str(string);
+            items.push(writer.string(Some(&string)).expect("Writing string"));
+        }
+        items.push(writer.float(Some(1.5)).expect("Writing float"));
+        writer.list(items).expect("Writing list");
+        writer
+    };
+
+    let expected = writer().done().expect("Finalizing data");
+
+    println!("* Short writes are continued");
+    let mut sink = SlowSink {
+        data: vec![],
+        max: 3,
+        ready_until: std::usize::MAX,
+    };
+    let written = writer().done_to(&mut sink).expect("Writing to slow sink");
+    assert_eq!(written, expected.len());
+    assert_eq!(sink.data, expected.as_ref());
+
+    TreeTokenReader::new(std::io::Cursor::new(sink.data)).expect("Reading container");
+
+    println!("* WouldBlock is returned to the caller");
+    let mut sink = SlowSink {
+        data: vec![],
+        max: 3,
+        ready_until: 10,
+    };
+    match writer().done_to(&mut sink) {
+        Err(TokenWriterError::WriteError(ref err)) if err.kind() == ErrorKind::WouldBlock => {}
+        other => panic!("Expected WouldBlock, got {:?}", other),
+    }
+    assert_eq!(sink.data, &expected[..sink.data.len()]);
+}
+
+#[test]
+fn test_multipart_verify_structure() {
+    use bytes::compress::Compression;
+    use io::TokenWriterWithTree;
+    use multipart::footer::write_footer;
+    use BinjsError;
+
+    use std::io::Cursor;
+
+    let mut writer = TreeTokenWriter::new(Targets::uniform(Compression::Identity));
+    let items = vec![
+        writer
+            .string(Some(&SharedString::from_str("foo")))
+            .expect("Writing string"),
+        writer.float(Some(1.5)).expect("Writing float"),
+    ];
+    writer.list(items).expect("Writing list");
+    let output = writer.done().expect("Finalizing data").to_vec();
+
+    verify_structure(&mut Cursor::new(&output)).expect("Valid container");
+
+    // The byte length of the strings table, right after its compression prefix.
+    let position = output
+        .windows(HEADER_STRINGS_TABLE.len())
+        .position(|window| window == HEADER_STRINGS_TABLE.as_bytes())
+        .expect("Finding strings table");
+    let length_offset = position
+        + output[position..]
+            .iter()
+            .position(|byte| *byte == b';')
+            .unwrap()
+        + 1;
+    let (footer_offset, _) = footer::footer_position(&mut Cursor::new(&output)).unwrap();
+
+    // A corrupted section length is detected, even with a matching hash.
+    let mut corrupted = output[..footer_offset as usize].to_vec();
+    corrupted[length_offset] += 1;
+    write_footer(&ContainerHash::default(), &mut corrupted).unwrap();
+    match verify_structure(&mut Cursor::new(&corrupted)) {
+        Err(BinjsError::Format(_)) => {}
+        other => panic!("Corrupted section length not detected: {:?}", other),
+    }
+
+    // A corrupted footer is detected.
+    let mut corrupted = output.clone();
+    let digest_offset = output.len() - 17;
+    corrupted[digest_offset] ^= 1;
+    match verify_structure(&mut Cursor::new(&corrupted)) {
+        Err(BinjsError::Format(_)) => {}
+        other => panic!("Corrupted footer not detected: {:?}", other),
+    }
+    let mut corrupted = output.clone();
+    let pointer_offset = output.len() - 16;
+    corrupted[pointer_offset] ^= 1;
+    match verify_structure(&mut Cursor::new(&corrupted)) {
+        Err(BinjsError::Format(_)) => {}
+        other => panic!("Corrupted footer pointer not detected: {:?}", other),
+    }
+}
+
+#[test]
+fn test_multipart_metadata_without_decompression() {
+    use bytes::compress::Compression;
+    use bytes::varnum::WriteVarNum;
+    use io::TokenWriterWithTree;
+
+    use std::io::{Cursor, Write};
+
+    let metadata = Metadata {
+        shebang: Some("#!/usr/bin/env node".to_string()),
+        bom: false,
+        ..Metadata::default()
+    };
+    let mut writer = TreeTokenWriter::with_options(
+        Targets::uniform(Compression::Brotli),
+        Options {
+            metadata: metadata.clone(),
+            ..Options::default()
+        },
+    );
+    let item = writer
+        .string(Some(&SharedString::from_str("foo")))
+        .expect("Writing string");
+    writer.list(vec![item]).expect("Writing list");
+    let output = writer.done().expect("Finalizing data").to_vec();
+
+    // Whatever the targets, the metadata section is not compressed.
+    let layout = Layout::read(&mut Cursor::new(&output)).expect("Reading layout");
+    assert_eq!(layout.sections[0].name, HEADER_METADATA);
+    for section in &layout.sections {
+        let expected = if section.name == HEADER_METADATA {
+            Compression::Identity
+        } else {
+            Compression::Brotli
+        };
+        assert_eq!(section.compression, expected, "Codec of {}", section.name);
+    }
+
+    // Reading the metadata does not touch the brotli sections, so it
+    // succeeds even if they are garbage.
+    let mut corrupted = output.clone();
+    for byte in &mut corrupted[layout.sections[1].offset as usize..] {
+        *byte = 0;
+    }
+    assert_eq!(
+        Metadata::read(&mut Cursor::new(&corrupted)).expect("Reading metadata"),
+        metadata
+    );
+    assert!(TreeTokenReader::new(Cursor::new(&corrupted)).is_err());
+
+    // Compressed metadata are rejected rather than decompressed.
+    let mut compressed = b"BINJS".to_vec();
+    compressed.write_varnum(FORMAT_VERSION).unwrap();
+    compressed.write_all(HEADER_METADATA.as_bytes()).unwrap();
+    let mut uncompressed = vec![];
+    metadata.write(&mut uncompressed).unwrap();
+    Compression::Brotli
+        .compress(&uncompressed, &mut compressed)
+        .unwrap();
+    assert!(Metadata::read(&mut Cursor::new(&compressed)).is_err());
+}
+
+#[test]
+fn test_multipart_skip_unknown_optional_sections() {
+    use binjs_shared::ast::Path;
+    use bytes::compress::Compression;
+    use bytes::varnum::WriteVarNum;
+    use io::{TokenReader, TokenWriterWithTree};
+    use multipart::footer::write_footer;
+
+    use std::io::{Cursor, Write};
+
+    let mut writer = TreeTokenWriter::new(Targets::uniform(Compression::Identity));
+    let item = writer
+        .string(Some(&SharedString::from_str("foo")))
+        .expect("Writing string");
+    writer.list(vec![item]).expect("Writing list");
+    let output = writer.done().expect("Finalizing data").to_vec();
+
+    // Insert a section, compressed with a codec we do not know, before the grammar table.
+    let with_section = |header: &str| {
+        let position = output
+            .windows(HEADER_GRAMMAR_TABLE.len())
+            .position(|window| window == HEADER_GRAMMAR_TABLE.as_bytes())
+            .expect("Finding grammar table");
+        let (footer_offset, _) = footer::footer_position(&mut Cursor::new(&output)).unwrap();
+        let mut data = output[..position].to_vec();
+        data.write_all(header.as_bytes()).unwrap();
+        data.write_all(b"zstd;").unwrap();
+        data.write_varnum(3).unwrap();
+        data.write_all(b"xyz").unwrap();
+        data.write_all(&output[position..footer_offset as usize])
+            .unwrap();
+        write_footer(&ContainerHash::default(), &mut data).unwrap();
+        data
+    };
+
+    // Unknown optional sections are skipped, and so are known optional
+    // sections with an unknown codec.
+    for header in &["[?FUTURE]", HEADER_DIAGNOSTICS, HEADER_PARENS] {
+        let path = Path::new();
+        let mut reader =
+            TreeTokenReader::new(Cursor::new(with_section(header))).expect("Reading container");
+        assert!(reader.diagnostics().is_empty());
+        let len = reader.enter_list_at(&path).expect("Reading list");
+        assert_eq!(len, 1);
+        let found = reader
+            .string_at(&path)
+            .expect("Reading string")
+            .expect("Non-null string");
+        assert_eq!(&found, "foo");
+        reader.exit_list_at(&path).expect("List read properly");
+    }
+
+    // Unknown required sections are rejected.
+    match TreeTokenReader::new(Cursor::new(with_section("[FUTURE]"))) {
+        Err(TokenReaderError::BadHeaderName(name)) => assert_eq!(name, b"[FUTURE]".to_vec()),
+        other => panic!("Unknown required section not rejected: {:?}", other.err()),
+    }
+}
+
+#[test]
+fn test_multipart_compressed_bytes_per_kind() {
+    use binjs_shared::{FieldName, InterfaceName};
+    use bytes::compress::Compression;
+    use io::TokenWriterWithTree;
+
+    let statistics = Rc::new(RefCell::new(Statistics::default()));
+    let mut writer = TreeTokenWriter::with_options(
+        Targets::uniform(Compression::Brotli),
+        Options {
+            statistics: Some(statistics.clone()),
+            ..Options::default()
+        },
+    );
+    let mut items = vec![];
+    for i in 0..1000 {
+        let value = writer.float(Some(i as f64 * 1.7)).expect("Writing float");
+        items.push(
+            writer
+                .tagged_tuple(
+                    &InterfaceName::from_str("Literal"),
+                    &[(&FieldName::from_str("value"), value)],
+                )
+                .expect("Writing tagged tuple"),
+        );
+        if i % 10 == 0 {
+            let name = writer
+                .string(Some(&SharedString::from_str("x")))
+                .expect("Writing string");
+            items.push(
+                writer
+                    .tagged_tuple(
+                        &InterfaceName::from_str("Identifier"),
+                        &[(&FieldName::from_str("name"), name)],
+                    )
+                    .expect("Writing tagged tuple"),
+            );
+        }
+    }
+    let list = writer.list(items).expect("Writing list");
+    writer
+        .tagged_tuple(
+            &InterfaceName::from_str("Script"),
+            &[(&FieldName::from_str("statements"), list)],
+        )
+        .expect("Writing tagged tuple");
+    writer.done().expect("Finalizing data");
+
+    let statistics = statistics.borrow();
+    let compressed_bytes = |name: &str| {
+        statistics
+            .per_kind_name
+            .get(&InterfaceName::from_str(name))
+            .expect("Missing node kind")
+            .compressed_bytes
+    };
+
+    // Every byte of the tree is within a tagged tuple, so the estimates add
+    // up to the compressed tree, up to rounding.
+    let attributed: usize = statistics
+        .per_kind_name
+        .values()
+        .map(|stats| stats.compressed_bytes)
+        .sum();
+    let expected = statistics.tree.compression.after_bytes;
+    assert!(
+        attributed + statistics.per_kind_name.len() >= expected
+            && attributed <= expected + statistics.per_kind_name.len(),
+        "Attributed {} bytes, expected {}",
+        attributed,
+        expected
+    );
+
+    // Floats are much more costly than the repeated string index.
+    assert!(compressed_bytes("Literal") > 10 * compressed_bytes("Identifier"));
+    assert!(format!("{}", statistics).contains("Compressed bytes (estimate)"));
+}
+
+#[test]
+fn test_multipart_max_sections() {
+    use bytes::compress::Compression;
+    use bytes::varnum::WriteVarNum;
+    use io::TokenWriterWithTree;
+    use multipart::footer::write_footer;
+    use BinjsError;
+
+    use std::io::{Cursor, Write};
+
+    // A container declaring an absurd number of empty optional sections.
+    let mut data = b"BINJS".to_vec();
+    data.write_varnum(FORMAT_VERSION).unwrap();
+    for _ in 0..100_000 {
+        data.write_all(b"[?EMPTY]identity;").unwrap();
+        data.write_varnum(0).unwrap();
+    }
+    write_footer(&ContainerHash::default(), &mut data).unwrap();
+    match TreeTokenReader::new(Cursor::new(&data)) {
+        Err(TokenReaderError::LimitExceeded(_)) => {}
+        other => panic!("Section count not limited: {:?}", other.err()),
+    }
+
+    // The limit is configurable, and reported as such.
+    let mut writer = TreeTokenWriter::new(Targets::uniform(Compression::Identity));
+    writer
+        .string(Some(&SharedString::from_str("foo")))
+        .expect("Writing string");
+    let output = writer.done().expect("Finalizing data");
+    TreeTokenReader::with_max_sections(Cursor::new(&output), 3).expect("Reading 3 sections");
+    match TreeTokenReader::with_max_sections(Cursor::new(&output), 2) {
+        Err(error) => match BinjsError::from(error) {
+            BinjsError::Limit(_) => {}
+            other => panic!("Unexpected error {:?}", other),
+        },
+        Ok(_) => panic!("Section count not limited"),
+    }
+}
+
+#[test]
+fn test_multipart_on_section() {
+    use binjs_shared::ast::Path;
+    use bytes::compress::Compression;
+    use bytes::varnum::WriteVarNum;
+    use io::{TokenReader, TokenWriterWithTree};
+    use multipart::footer::write_footer;
+
+    use std::io::{Cursor, Write};
+
+    // Dump the sections as `--emit-raw` does.
+    let raw = Rc::new(RefCell::new(vec![]));
+    write_raw_header(&mut *raw.borrow_mut()).unwrap();
+    let mut writer = TreeTokenWriter::with_options(
+        Targets::uniform(Compression::Brotli),
+        Options {
+            metadata: Metadata {
+                shebang: Some("#!/usr/bin/env node".to_string()),
+                bom: false,
+                ..Metadata::default()
+            },
+            on_section: Some(OnSection({
+                let raw = raw.clone();
+                Rc::new(move |header: &str, bytes: &[u8]| {
+                    write_raw_section(&mut *raw.borrow_mut(), header, bytes).unwrap();
+                })
+            })),
+            ..Options::default()
+        },
+    );
+    let items = vec![
+        writer
+            .string(Some(&SharedString::from_str("foo")))
+            .expect("Writing string"),
+        writer.float(Some(1.5)).expect("Writing float"),
+    ];
+    writer.list(items).expect("Writing list");
+    writer.done().expect("Finalizing data");
+
+    let sections =
+        read_raw_sections(&mut Cursor::new(&*raw.borrow())).expect("Reading raw sections");
+    let headers: Vec<_> = sections
+        .iter()
+        .map(|&(ref header, _)| header.as_str())
+        .collect();
+    assert_eq!(
+        headers,
+        vec![
+            HEADER_METADATA,
+            HEADER_GRAMMAR_TABLE,
+            HEADER_STRINGS_TABLE,
+            HEADER_TREE
+        ]
+    );
+
+    // The uncompressed sections, read back from the dump and stored with
+    // `Compression::Identity`, make up a container with the same contents.
+    let mut data = b"BINJS".to_vec();
+    data.write_varnum(FORMAT_VERSION).unwrap();
+    for &(ref header, ref bytes) in sections.iter() {
+        data.write_all(header.as_bytes()).unwrap();
+        Compression::Identity.compress(bytes, &mut data).unwrap();
+    }
+    write_footer(&ContainerHash::default(), &mut data).unwrap();
+
+    let path = Path::new();
+    let mut reader = TreeTokenReader::new(Cursor::new(&data)).expect("Reading container");
+    assert_eq!(
+        reader.metadata().shebang,
+        Some("#!/usr/bin/env node".to_string())
+    );
+    let len = reader.enter_list_at(&path).expect("Reading list");
+    assert_eq!(len, 2);
+    let found = reader
+        .string_at(&path)
+        .expect("Reading string")
+        .expect("Non-null string");
+    assert_eq!(&found, "foo");
+    assert_eq!(reader.float_at(&path).expect("Reading float"), Some(1.5));
+    reader.exit_list_at(&path).expect("List read properly");
+}
+
+#[test]
+fn test_multipart_section_codec() {
+    use bytes::compress::Compression;
+    use io::TokenWriterWithTree;
+
+    use std::io::Cursor;
+
+    let mut writer = TreeTokenWriter::with_options(
+        Targets {
+            grammar_table: ::CompressionTarget::new(Compression::Identity),
+            strings_table: ::CompressionTarget::new(Compression::Brotli),
+            numbers_table: ::CompressionTarget::new(Compression::Deflate),
+            tree: ::CompressionTarget::new(Compression::Gzip),
+        },
+        Options {
+            numbers_table: true,
+            ..Options::default()
+        },
+    );
+    let items = vec![
+        writer
+            .string(Some(&SharedString::from_str("foo")))
+            .expect("Writing string"),
+        writer.float(Some(1.5)).expect("Writing float"),
+    ];
+    writer.list(items).expect("Writing list");
+    let output = writer.done().expect("Finalizing data");
+
+    let reader = TreeTokenReader::new(Cursor::new(&output)).expect("Reading container");
+    assert_eq!(
+        reader.section_codec(HEADER_GRAMMAR_TABLE),
+        Some(Compression::Identity)
+    );
+    assert_eq!(
+        reader.section_codec(HEADER_STRINGS_TABLE),
+        Some(Compression::Brotli)
+    );
+    assert_eq!(
+        reader.section_codec(HEADER_NUMBERS_TABLE),
+        Some(Compression::Deflate)
+    );
+    assert_eq!(reader.section_codec(HEADER_TREE), Some(Compression::Gzip));
+    assert_eq!(reader.section_codec("[NOPE]"), None);
+}
+
+#[test]
+fn test_multipart_max_output_bytes() {
+    use bytes::compress::Compression;
+    use io::TokenWriterWithTree;
+    use {BinjsError, TokenWriterError};
+
+    let encode = |strings: usize, max_output_bytes: usize| {
+        let mut writer = TreeTokenWriter::with_options(
+            Targets::uniform(Compression::Identity),
+            Options {
+                max_output_bytes: Some(max_output_bytes),
+                ..Options::default()
+            },
+        );
+        let items = (0..strings)
+            .map(|i| {
+                writer
+                    .string(Some(&SharedString::from_string(format!("string {}", i))))
+                    .expect("Writing string")
+            })
+            .collect();
+        writer.list(items).expect("Writing list");
+        writer.done()
+    };
+
+    println!("* A large file exceeds a tiny budget");
+    match encode(1_000, 64) {
+        Err(TokenWriterError::OutputBudgetExceeded { max, projected }) => {
+            assert_eq!(max, 64);
+            assert!(projected > max);
+        }
+        other => panic!("Output not limited: {:?}", other.map(|data| data.len())),
+    }
+    match BinjsError::from(encode(1_000, 64).unwrap_err()) {
+        BinjsError::Limit(_) => {}
+        other => panic!("Unexpected error {:?}", other),
+    }
+
+    println!("* A small file fits in its budget");
+    let data = encode(1, 1_024).expect("Encoding within budget");
+    assert!(data.len() <= 1_024);
+
+    println!("* The budget counts all the bytes of the container");
+    let len = encode(10, std::usize::MAX).expect("Encoding").len();
+    assert_eq!(encode(10, len).expect("Encoding within budget").len(), len);
+    match encode(10, len - 1) {
+        Err(TokenWriterError::OutputBudgetExceeded { projected, .. }) => {
+            assert_eq!(projected, len);
+        }
+        other => panic!("Output not limited: {:?}", other.map(|data| data.len())),
+    }
+}
+
+#[test]
+fn test_multipart_size_hints() {
+    use binjs_shared::ast::Path;
+    use bytes::compress::Compression;
+    use io::{TokenReader, TokenWriterWithTree};
+
+    use std::io::Cursor;
+
+    for size_hints in &[false, true] {
+        println!("* Size hints: {}", size_hints);
+        let mut writer = TreeTokenWriter::with_options(
+            Targets::uniform(Compression::Gzip),
+            Options {
+                size_hints: *size_hints,
+                ..Options::default()
+            },
+        );
+        let items = vec![
+            writer
+                .string(Some(&SharedString::from_str("foo")))
+                .expect("Writing string"),
+            writer.float(Some(1.5)).expect("Writing float"),
+        ];
+        writer.list(items).expect("Writing list");
+        let output = writer.done().expect("Finalizing data");
+
+        verify_structure(&mut Cursor::new(&output)).expect("Verifying structure");
+        let path = Path::new();
+        let mut reader = TreeTokenReader::new(Cursor::new(&output)).expect("Reading container");
+        assert_eq!(reader.section_codec(HEADER_TREE), Some(Compression::Gzip));
+        let len = reader.enter_list_at(&path).expect("Reading list");
+        assert_eq!(len, 2);
+        let found = reader
+            .string_at(&path)
+            .expect("Reading string")
+            .expect("Non-null string");
+        assert_eq!(&found, "foo");
+        assert_eq!(reader.float_at(&path).expect("Reading float"), Some(1.5));
+        reader.exit_list_at(&path).expect("List read properly");
+    }
+}
+
+#[test]
+fn test_multipart_decompression_options() {
+    use binjs_shared::ast::Path;
+    use bytes::compress::{Compression, DecompressionOptions, DEFAULT_READ_BUFFER_SIZE};
+    use io::{TokenReader, TokenWriterWithTree};
+
+    use std::io::Cursor;
+
+    let mut writer = TreeTokenWriter::new(Targets::uniform(Compression::Brotli));
+    let items = (0..100)
+        .map(|i| {
+            writer
+                .string(Some(&SharedString::from_string(format!("string {}", i))))
+                .expect("Writing string")
+        })
+        .collect();
+    writer.list(items).expect("Writing list");
+    let output = writer.done().expect("Finalizing data");
+
+    let path = Path::new();
+    for &buffer_size in &[1, 4096, DEFAULT_READ_BUFFER_SIZE] {
+        println!("* Buffers of {} bytes", buffer_size);
+        let limits = ReadLimits {
+            decompression: DecompressionOptions {
+                buffer_size,
+                ..DecompressionOptions::default()
+            },
+            ..ReadLimits::default()
+        };
+        let copied = TreeTokenReader::with_limits(Cursor::new(&output), limits.clone())
+            .expect("Reading container");
+        let shared = TreeTokenReader::from_shared(Rc::new(output.to_vec()), limits)
+            .expect("Reading container");
+        for mut reader in vec![copied, shared] {
+            assert_eq!(reader.enter_list_at(&path).expect("Reading list"), 100);
+            for i in 0..100 {
+                let found = reader
+                    .string_at(&path)
+                    .expect("Reading string")
+                    .expect("Non-null string");
+                assert_eq!(found.as_str(), format!("string {}", i));
+            }
+            reader.exit_list_at(&path).expect("List read properly");
+        }
+    }
+
+    println!("* Sections are limited by the options too");
+    let limits = ReadLimits {
+        decompression: DecompressionOptions::with_max_bytes(16),
+        ..ReadLimits::default()
+    };
+    match TreeTokenReader::with_limits(Cursor::new(&output), limits) {
+        Err(TokenReaderError::BadCompression(_)) => {}
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Section over the limit was accepted"),
+    }
+}
+
+#[test]
+fn test_targets_with_compression() {
+    use bytes::compress::Compression;
+
+    use std::io::Write;
+
+    let mut targets = Targets::fast_tree();
+    targets.set_size_hint(true);
+    let mut replaced = targets.with_compression(&Compression::Gzip);
+    for target in &[
+        &replaced.grammar_table,
+        &replaced.strings_table,
+        &replaced.numbers_table,
+        &replaced.tree,
+    ] {
+        assert_eq!(target.compression(), &Compression::Gzip);
+    }
+
+    println!("* The other settings of each section are kept");
+    replaced.tree.write_all(b"foo").expect("Writing section");
+    let (data, _) = replaced.tree.done().expect("Compressing section");
+    let header = data
+        .iter()
+        .position(|byte| *byte == b';')
+        .expect("Finding the compression header");
+    assert!(data[..header].ends_with(b"+size"));
+
+    println!("* The original targets are unchanged");
+    assert_eq!(targets.tree.compression(), &Compression::Identity);
+    assert_eq!(targets.grammar_table.compression(), &Compression::Brotli);
+}
+
+#[test]
+fn test_multipart_empty_section_marker() {
+    use binjs_shared::ast::Path;
+    use bytes::compress::Compression;
+    use io::{TokenReader, TokenWriterWithTree};
+
+    use std::io::Cursor;
+
+    // Without strings or tagged tuples, all tables are empty.
+    let mut sizes = vec![];
+    for empty_section_marker in &[false, true] {
+        println!("* Empty section marker: {}", empty_section_marker);
+        let mut writer = TreeTokenWriter::with_options(
+            Targets::uniform(Compression::Brotli),
+            Options {
+                numbers_table: true,
+                empty_section_marker: *empty_section_marker,
+                ..Options::default()
+            },
+        );
+        writer.bool(Some(true)).expect("Writing bool");
+        let output = writer.done().expect("Finalizing data");
+        sizes.push(output.len());
+
+        verify_structure(&mut Cursor::new(&output)).expect("Verifying structure");
+        let codec = if *empty_section_marker {
+            Compression::Identity
+        } else {
+            Compression::Brotli
+        };
+        let layout = Layout::read(&mut Cursor::new(&output)).expect("Reading layout");
+        for section in &layout.sections {
+            if section.name != HEADER_TREE {
+                assert_eq!(section.compression, codec);
+                assert_eq!(section.decompressed_bytes == 0, *empty_section_marker);
+            }
+        }
+
+        let mut reader = TreeTokenReader::new(Cursor::new(&output)).expect("Reading container");
+        assert_eq!(reader.section_codec(HEADER_TREE), Some(Compression::Brotli));
+        assert_eq!(
+            reader.bool_at(&Path::new()).expect("Reading bool"),
+            Some(true)
+        );
+    }
+    // Each of the three tables saves more than a byte.
+    assert!(sizes[1] + 3 < sizes[0]);
+}
+
+#[test]
+fn test_multipart_table_of_contents() {
+    use bytes::compress::Compression;
+    use io::TokenWriterWithTree;
+    use multipart::footer::{footer_position, write_footer};
+
+    use std::io::Cursor;
+
+    let mut writer = TreeTokenWriter::new(Targets::uniform(Compression::Gzip));
+    let items = (0..100)
+        .map(|i| {
+            writer
+                .string(Some(&SharedString::from_string(format!("string {}", i))))
+                .expect("Writing string")
+        })
+        .collect();
+    writer.list(items).expect("Writing list");
+    let output = writer.done().expect("Finalizing data");
+
+    println!("* The table of contents matches the layout");
+    let toc = table_of_contents(&mut Cursor::new(&output)).expect("Reading table of contents");
+    let layout = Layout::read(&mut Cursor::new(&output)).expect("Reading layout");
+    let names: Vec<_> = toc.iter().map(|entry| entry.name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec![HEADER_GRAMMAR_TABLE, HEADER_STRINGS_TABLE, HEADER_TREE]
+    );
+    assert_eq!(toc.len(), layout.sections.len());
+    for (entry, section) in toc.iter().zip(layout.sections.iter()) {
+        assert_eq!(entry.name, section.name);
+        assert_eq!(entry.offset, section.offset);
+        assert_eq!(entry.byte_len, section.byte_len);
+        assert_eq!(entry.compression, section.compression);
+        assert_eq!(entry.compressed_bytes, section.compressed_bytes);
+    }
+
+    println!("* The table of contents does not decompress anything");
+    // Replace all compressed bytes with garbage, which cannot be decompressed.
+    let (footer_offset, _) = footer_position(&mut Cursor::new(&output)).expect("Reading footer");
+    let mut corrupted = output[..footer_offset as usize].to_vec();
+    for entry in &toc {
+        let end = (entry.offset + entry.byte_len) as usize;
+        for byte in &mut corrupted[end - entry.compressed_bytes..end] {
+            *byte = 0xFF;
+        }
+    }
+    write_footer(&ContainerHash::default(), &mut corrupted).unwrap();
+    assert!(Layout::read(&mut Cursor::new(&corrupted)).is_err());
+    assert_eq!(
+        table_of_contents(&mut Cursor::new(&corrupted)).expect("Reading table of contents"),
+        toc
+    );
+}
+
+#[test]
+fn test_multipart_canonicalize_nan() {
+    use binjs_shared::ast::Path;
+    use bytes::compress::Compression;
+    use io::{TokenReader, TokenWriterWithTree};
+
+    use std::f64::NAN;
+    use std::io::Cursor;
+
+    // A NaN with a payload, distinct from `std::f64::NAN`.
+    let nan = f64::from_bits(0x7FF8_0000_0000_1234);
+    assert!(nan.is_nan());
+    assert_ne!(nan.to_bits(), NAN.to_bits());
+
+    let values = [nan, NAN, -0., 0.];
+    for &canonicalize_nan in &[false, true] {
+        for &numbers_table in &[false, true] {
+            println!(
+                "* canonicalize_nan: {}, numbers_table: {}",
+                canonicalize_nan, numbers_table
+            );
+            let mut writer = TreeTokenWriter::with_options(
+                Targets::uniform(Compression::Identity),
+                Options {
+                    canonicalize_nan,
+                    numbers_table,
+                    ..Options::default()
+                },
+            );
+            let items = values
+                .iter()
+                .map(|value| writer.float(Some(*value)).expect("Writing float"))
+                .collect();
+            writer.list(items).expect("Writing list");
+            let output = writer.done().expect("Finalizing data");
+
+            let path = Path::new();
+            let mut reader = TreeTokenReader::new(Cursor::new(&output)).expect("Reading container");
+            let len = reader.enter_list_at(&path).expect("Reading list");
+            assert_eq!(len as usize, values.len());
+            let found: Vec<u64> = values
+                .iter()
+                .map(|_| {
+                    reader
+                        .float_at(&path)
+                        .expect("Reading float")
+                        .expect("Non-null float")
+                        .to_bits()
+                })
+                .collect();
+            reader.exit_list_at(&path).expect("List read properly");
+
+            let expected_nan = if canonicalize_nan {
+                NAN.to_bits()
+            } else {
+                nan.to_bits()
+            };
+            assert_eq!(
+                found,
+                vec![
+                    expected_nan,
+                    NAN.to_bits(),
+                    (-0f64).to_bits(),
+                    0f64.to_bits()
+                ]
+            );
+        }
+    }
+}
+
+#[test]
+fn test_multipart_intern_min_count() {
+    use binjs_shared::ast::Path;
+    use bytes::compress::Compression;
+    use io::{TokenReader, TokenWriterWithTree};
+
+    use std::io::Cursor;
+
+    let repeated = "repeated";
+    let once = "a long string, which is only used once";
+    let contains = |haystack: &[u8], needle: &str| {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle.as_bytes())
+    };
+
+    let mut sizes = vec![];
+    for intern_min_count in &[0, 2] {
+        println!("* Intern min count: {}", intern_min_count);
+        let strings = Rc::new(RefCell::new(vec![]));
+        let mut writer = TreeTokenWriter::with_options(
+            Targets::uniform(Compression::Identity),
+            Options {
+                intern_min_count: *intern_min_count,
+                on_section: Some(OnSection({
+                    let strings = strings.clone();
+                    Rc::new(move |header: &str, bytes: &[u8]| {
+                        if header == HEADER_STRINGS_TABLE || header == HEADER_STRINGS_TABLE_MIXED {
+                            strings.borrow_mut().extend_from_slice(bytes);
+                        }
+                    })
+                })),
+                ..Options::default()
+            },
+        );
+        let mut items = vec![];
+        for string in &[repeated, once, repeated] {
+            items.push(
+                writer
+                    .string(Some(&SharedString::from_str(string)))
+                    .expect("Writing string"),
+            );
+        }
+        writer.list(items).expect("Writing list");
+        let output = writer.done().expect("Finalizing data");
+        sizes.push(output.len());
+
+        // The repeated string is always interned, the other one only by default.
+        let strings = strings.borrow();
+        assert!(contains(&strings, repeated));
+        assert_eq!(contains(&strings, once), *intern_min_count == 0);
+
+        let path = Path::new();
+        let mut reader = TreeTokenReader::new(Cursor::new(&output)).expect("Reading container");
+        assert_eq!(reader.enter_list_at(&path).expect("Reading list"), 3);
+        for string in &[repeated, once, repeated] {
+            let found = reader
+                .string_at(&path)
+                .expect("Reading string")
+                .expect("Non-null string");
+            assert_eq!(&found, *string);
+        }
+        reader.exit_list_at(&path).expect("List read properly");
+
+        if *intern_min_count == 0 {
+            continue;
+        }
+        println!("* Inline strings longer than the tree are rejected");
+        let mut footer_offset = [0; 8];
+        footer_offset.copy_from_slice(&output[output.len() - 16..output.len() - 8]);
+        let mut corrupted = output[..u64::from_le_bytes(footer_offset) as usize].to_vec();
+        let start = corrupted
+            .windows(once.len())
+            .position(|window| window == once.as_bytes())
+            .expect("Finding the inline string");
+        // The flagged byte length of the string, replaced with `(63 << 1) | 1`,
+        // on a single byte.
+        assert_eq!(corrupted[start - 1], (((once.len() << 1) | 1) << 1) as u8);
+        corrupted[start - 1] = 0xFE;
+        footer::write_footer(&ContainerHash::default(), &mut corrupted).expect("Writing footer");
+        let mut reader = TreeTokenReader::new(Cursor::new(&corrupted)).expect("Reading container");
+        assert_eq!(reader.enter_list_at(&path).expect("Reading list"), 3);
+        reader.string_at(&path).expect("Reading string");
+        assert!(reader.string_at(&path).is_err());
+    }
+    assert!(sizes[1] < sizes[0], "Sizes: {:?}", sizes);
+}
+
+#[test]
+fn test_multipart_finish() {
+    use bytes::compress::Compression;
+    use io::TokenWriterWithTree;
+
+    use std::io::{Cursor, Write};
+
+    let mut writer = TreeTokenWriter::new(Targets::uniform(Compression::Identity));
+    writer
+        .string(Some(&SharedString::from_str("simple string")))
+        .expect("Writing simple string");
+    let output = writer.done().expect("Finalizing data");
+
+    // The footer starts at the offset stored in the trailer.
+    let mut offset = [0; 8];
+    offset.copy_from_slice(&output[output.len() - 16..output.len() - 8]);
+    let body = &output[..u64::from_le_bytes(offset) as usize];
+
+    println!("* Dropped before finish()");
+    let mut dropped = vec![];
+    {
+        let mut out = footer::HashingWriter::new(&ContainerHash::default(), &mut dropped);
+        out.write_all(body).expect("Writing body");
+    }
+    assert_eq!(dropped, body);
+    assert!(TreeTokenReader::new(Cursor::new(&dropped)).is_err());
+
+    println!("* Abandoned");
+    let mut abandoned = vec![];
+    let written = {
+        let mut out = footer::HashingWriter::new(&ContainerHash::default(), &mut abandoned);
+        out.write_all(body).expect("Writing body");
+        let (_, written) = out.abandon();
+        written
+    };
+    assert_eq!(written, body.len());
+    assert_eq!(abandoned, body);
+
+    println!("* Finished");
+    let mut finished = vec![];
+    let written = {
+        let mut out = footer::HashingWriter::new(&ContainerHash::default(), &mut finished);
+        out.write_all(body).expect("Writing body");
+        let (_, written) = out.finish().expect("Finishing container");
+        written
+    };
+    assert_eq!(written, finished.len());
+    assert_eq!(finished, output);
+    TreeTokenReader::new(Cursor::new(&finished)).expect("Reading finished container");
+}
+
+#[test]
+fn test_multipart_align() {
+    use binjs_shared::ast::Path;
+    use bytes::compress::Compression;
+    use io::{TokenReader, TokenWriterWithTree};
+
+    use std::io::Cursor;
+
+    for &(align, size_hint) in &[(0, false), (3, false), (8, false), (4096, false), (8, true)] {
+        println!("* Align: {}, size hint: {}", align, size_hint);
+        let mut targets = Targets::uniform(Compression::Identity);
+        targets.set_size_hint(size_hint);
+        let mut writer = TreeTokenWriter::with_options(
+            targets,
+            Options {
+                align,
+                ..Options::default()
+            },
+        );
+        let item = writer
+            .string(Some(&SharedString::from_str("simple string")))
+            .expect("Writing simple string");
+        writer.list(vec![item]).expect("Writing list");
+        let output = writer.done().expect("Finalizing data");
+
+        let sections = table_of_contents(&mut Cursor::new(&output)).expect("Listing sections");
+        let padded = sections
+            .iter()
+            .filter(|section| section.name == HEADER_PADDING)
+            .count();
+        assert_eq!(padded > 0, align > 1);
+        for section in &sections {
+            if align > 1 && section.name != HEADER_PADDING {
+                // The bytes after the header, compression prefix, byte
+                // length and size hint are aligned, not the header.
+                let header_end = section.offset as usize + section.name.len();
+                let body_offset = header_end
+                    + Compression::body_offset(&output[header_end..])
+                        .expect("Reading compression prefix");
+                assert_eq!(body_offset % align, 0, "{:?}", section);
+            }
+        }
+
+        // Padding does not count towards the limit on sections.
+        let sections = sections.len() - padded;
+        TreeTokenReader::with_max_sections(Cursor::new(&output), sections)
+            .expect("Reading with padding");
+        assert!(TreeTokenReader::with_max_sections(Cursor::new(&output), sections - 1).is_err());
+
+        // Padding is skipped silently.
+        let path = Path::new();
+        let mut reader = TreeTokenReader::new(Cursor::new(&output)).expect("Reading container");
+        assert!(reader.warnings().is_empty());
+        assert_eq!(reader.enter_list_at(&path).expect("Reading list"), 1);
+        let string = reader
+            .string_at(&path)
+            .expect("Reading string")
+            .expect("Non-null string");
+        assert_eq!(&string, "simple string");
+        reader.exit_list_at(&path).expect("List read properly");
+    }
+}
+
+#[test]
+fn test_multipart_from_shared() {
+    use binjs_shared::ast::Path;
+    use bytes::compress::Compression;
+    use io::{TokenReader, TokenWriterWithTree};
+
+    for &(ref compression, shared) in &[(Compression::Identity, true), (Compression::Brotli, false)]
+    {
+        println!("* Tree compressed with {:?}", compression);
+        let mut writer = TreeTokenWriter::new(Targets {
+            tree: ::CompressionTarget::new(compression.clone()),
+            ..Targets::uniform(Compression::Brotli)
+        });
+        let item = writer
+            .string(Some(&SharedString::from_str("simple string")))
+            .expect("Writing simple string");
+        writer.list(vec![item]).expect("Writing list");
+        let data = Rc::new(writer.done().expect("Finalizing data").to_vec());
+
+        // Only an uncompressed tree is read in place.
+        let path = Path::new();
+        let mut reader =
+            TreeTokenReader::from_shared(data.clone(), ReadLimits::default()).expect("Reading");
+        assert_eq!(Rc::strong_count(&data) > 1, shared);
+        assert_eq!(reader.enter_list_at(&path).expect("Reading list"), 1);
+        let string = reader
+            .string_at(&path)
+            .expect("Reading string")
+            .expect("Non-null string");
+        assert_eq!(&string, "simple string");
+        reader.exit_list_at(&path).expect("List read properly");
+        assert_eq!(reader.remaining_tree_bytes().expect("Tree read"), 0);
+        drop(reader);
+        assert_eq!(Rc::strong_count(&data), 1);
+    }
+}
+
+#[test]
+fn test_multipart_choose_compression() {
+    use binjs_shared::ast::Path;
+    use bytes::compress::Compression;
+    use io::{TokenReader, TokenWriterWithTree};
+
+    use std::io::Cursor;
+
+    let long_string = "x".repeat(2000);
+    let chosen = Rc::new(RefCell::new(vec![]));
+    let mut writer = TreeTokenWriter::with_options(
+        Targets::uniform(Compression::Gzip),
+        Options {
+            choose_compression: Some(ChooseCompression({
+                let chosen = chosen.clone();
+                Rc::new(move |header: &str, uncompressed: &[u8]| {
+                    let compression = if uncompressed.len() > 1024 {
+                        Compression::Brotli
+                    } else {
+                        Compression::Identity
+                    };
+                    chosen
+                        .borrow_mut()
+                        .push((header.to_string(), compression.clone()));
+                    compression
+                })
+            })),
+            ..Options::default()
+        },
+    );
+    let item = writer
+        .string(Some(&SharedString::from_string(long_string.clone())))
+        .expect("Writing string");
+    writer.list(vec![item]).expect("Writing list");
+    let output = writer.done().expect("Finalizing data");
+
+    println!("* The callback is called for each compressed section");
+    let expected = vec![
+        (HEADER_GRAMMAR_TABLE.to_string(), Compression::Identity),
+        (HEADER_STRINGS_TABLE.to_string(), Compression::Brotli),
+        (HEADER_TREE.to_string(), Compression::Identity),
+    ];
+    assert_eq!(*chosen.borrow(), expected);
+
+    println!("* Its choices override the targets");
+    let sections = table_of_contents(&mut Cursor::new(&output)).expect("Listing sections");
+    let compressions: Vec<_> = sections
+        .iter()
+        .map(|section| (section.name.clone(), section.compression.clone()))
+        .collect();
+    assert_eq!(compressions, expected);
+
+    println!("* The container decodes");
+    let path = Path::new();
+    let mut reader = TreeTokenReader::new(Cursor::new(&output)).expect("Reading container");
+    assert_eq!(reader.enter_list_at(&path).expect("Reading list"), 1);
+    let string = reader
+        .string_at(&path)
+        .expect("Reading string")
+        .expect("Non-null string");
+    assert_eq!(string.as_str(), long_string);
+    reader.exit_list_at(&path).expect("List read properly");
+}
+
+#[test]
+fn test_multipart_user_metadata() {
+    use bytes::compress::Compression;
+    use io::TokenWriterWithTree;
+
+    use std::collections::BTreeMap;
+    use std::io::Cursor;
+
+    let entries = [
+        ("commit", "0123456789abcdef"),
+        ("build-time", "2019-01-01T00:00:00Z"),
+        ("tool", "webpack 4.28.0"),
+        ("empty", ""),
+    ];
+    let encode = |entries: &[(&str, &str)], align: usize| {
+        let mut user = BTreeMap::new();
+        for &(key, value) in entries {
+            user.insert(key.to_string(), value.to_string());
+        }
+        let mut writer = TreeTokenWriter::with_options(
+            Targets::uniform(Compression::Brotli),
+            Options {
+                metadata: Metadata {
+                    user: user.clone(),
+                    ..Metadata::default()
+                },
+                align,
+                ..Options::default()
+            },
+        );
+        let item = writer
+            .string(Some(&SharedString::from_str("foo")))
+            .expect("Writing string");
+        writer.list(vec![item]).expect("Writing list");
+        (user, writer.done().expect("Finalizing data").to_vec())
+    };
+
+    println!("* Entries round-trip");
+    let (user, output) = encode(&entries, 1);
+    let reader = TreeTokenReader::new(Cursor::new(&output)).expect("Reading container");
+    assert_eq!(reader.user_metadata(), &user);
+    assert_eq!(reader.user_metadata().len(), entries.len());
+    let metadata = Metadata::read(&mut Cursor::new(&output)).expect("Reading metadata");
+    assert_eq!(metadata.user, user);
+
+    println!("* Output does not depend on insertion order");
+    let mut reversed = entries.to_vec();
+    reversed.reverse();
+    let (_, reversed_output) = encode(&reversed, 1);
+    assert_eq!(reversed_output, output);
+
+    println!("* Without entries, there is no metadata section");
+    let (_, empty_output) = encode(&[], 1);
+    let layout = Layout::read(&mut Cursor::new(&empty_output)).expect("Reading layout");
+    assert!(layout
+        .sections
+        .iter()
+        .all(|section| section.name != HEADER_METADATA));
+
+    println!("* Entries survive the padding of aligned sections");
+    let (user, aligned_output) = encode(&entries, 64);
+    let sections = table_of_contents(&mut Cursor::new(&aligned_output)).expect("Listing sections");
+    assert_eq!(sections[0].name, HEADER_PADDING);
+    assert_eq!(sections[1].name, HEADER_METADATA);
+    let reader = TreeTokenReader::new(Cursor::new(&aligned_output)).expect("Reading container");
+    assert_eq!(reader.user_metadata(), &user);
+    let metadata = Metadata::read(&mut Cursor::new(&aligned_output)).expect("Reading metadata");
+    assert_eq!(metadata.user, user);
+}
+
+#[test]
+fn test_multipart_from_stream() {
+    use binjs_shared::ast::Path;
+
+    use bytes::compress::Compression;
+    use io::{TokenReader, TokenWriterWithTree};
+
+    use std::io::Cursor;
+
+    /// A stream that cannot seek, returning a few bytes at a time, like a pipe.
+    struct Pipe<'a>(&'a [u8]);
+    impl<'a> Read for Pipe<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+            let len = std::cmp::min(std::cmp::min(buf.len(), self.0.len()), 7);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    let mut writer = TreeTokenWriter::new(Targets::uniform(Compression::Brotli));
+    writer
+        .string(Some(&SharedString::from_str("simple string")))
+        .expect("Writing simple string");
+    let output = writer.done().expect("Finalizing data");
+
+    println!("* A stream is decoded like a seekable reader");
+    let mut reader =
+        TreeTokenReader::from_stream(Pipe(&output), output.len(), ReadLimits::default())
+            .expect("Reading stream");
+    let simple_string = reader
+        .string_at(&Path::new())
+        .expect("Reading simple string")
+        .expect("Non-null string");
+    assert_eq!(&simple_string, "simple string");
+
+    println!("* A stream longer than the limit is rejected");
+    match TreeTokenReader::from_stream(Pipe(&output), output.len() - 1, ReadLimits::default()) {
+        Err(TokenReaderError::LimitExceeded(_)) => {}
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Stream longer than the limit was accepted"),
+    }
+
+    println!("* A truncated stream is rejected");
+    assert!(TreeTokenReader::from_stream(
+        Pipe(&output[..output.len() - 1]),
+        output.len(),
+        ReadLimits::default()
+    )
+    .is_err());
+
+    let large = |container_hash: ContainerHash| {
+        let mut writer = TreeTokenWriter::with_options(
+            Targets::uniform(Compression::Brotli),
+            Options {
+                container_hash,
+                ..Options::default()
+            },
+        );
+        let items: Vec<_> = (0..10_000)
+            .map(|i| {
+                writer
+                    .string(Some(&SharedString::from_string(format!("string {}", i))))
+                    .expect("Writing string")
+            })
+            .collect();
+        writer.list(items).expect("Writing list");
+        writer.done().expect("Finalizing data").to_vec()
+    };
+    let read = |data: &[u8], stream_hash: ContainerHash| {
+        TreeTokenReader::from_stream(
+            Pipe(data),
+            data.len(),
+            ReadLimits {
+                stream_hash,
+                ..ReadLimits::default()
+            },
+        )
+    };
+
+    println!("* Large streams are checked as they are received, whatever their hash");
+    for container_hash in &[ContainerHash::XxHash64, ContainerHash::Sha256] {
+        for stream_hash in &[ContainerHash::XxHash64, ContainerHash::Sha256] {
+            let output = large(container_hash.clone());
+            let mut reader = read(&output, stream_hash.clone()).expect("Reading stream");
+            assert_eq!(
+                reader.enter_list_at(&Path::new()).expect("Reading list"),
+                10_000
+            );
+        }
+    }
+
+    println!("* A corrupted large stream is rejected at the end of the stream");
+    let output = large(ContainerHash::default());
+    let (footer_offset, _) = footer::footer_position(&mut Cursor::new(&output)).unwrap();
+    // Bytes hashed as they are received, then with the footer.
+    for &position in &[output.len() / 2, footer_offset as usize - 1] {
+        for stream_hash in &[ContainerHash::XxHash64, ContainerHash::Sha256] {
+            let mut corrupted = output.clone();
+            corrupted[position] ^= 1;
+            match read(&corrupted, stream_hash.clone()) {
+                Err(TokenReaderError::ContainerCorrupt(_)) => {}
+                Err(err) => panic!("Unexpected error {:?}", err),
+                Ok(_) => panic!("Corrupted stream was accepted"),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_multipart_lazy_sections() {
+    use bytes::compress::Compression;
+    use io::TokenWriterWithTree;
+
+    use std::borrow::Cow;
+
+    let encode = |compression: Compression| {
+        let uncompressed = Rc::new(RefCell::new(HashMap::new()));
+        let mut writer = TreeTokenWriter::with_options(
+            Targets::uniform(compression),
+            Options {
+                on_section: Some(OnSection({
+                    let uncompressed = uncompressed.clone();
+                    Rc::new(move |header: &str, bytes: &[u8]| {
+                        uncompressed
+                            .borrow_mut()
+                            .insert(header.to_string(), bytes.to_vec());
+                    })
+                })),
+                ..Options::default()
+            },
+        );
+        let item = writer
+            .string(Some(&SharedString::from_str("simple string")))
+            .expect("Writing string");
+        writer.list(vec![item]).expect("Writing list");
+        let output = writer.done().expect("Finalizing data").to_vec();
+        let uncompressed = uncompressed.borrow().clone();
+        (output, uncompressed)
+    };
+
+    println!("* Only the sections accessed are decompressed");
+    let (output, uncompressed) = encode(Compression::Brotli);
+    let sections = lazy_sections(&output, std::usize::MAX).expect("Listing sections");
+    assert_eq!(sections.len(), 3);
+    assert!(sections.iter().all(|section| !section.is_decompressed()));
+    let strings = sections
+        .iter()
+        .find(|section| section.name == HEADER_STRINGS_TABLE)
+        .expect("Finding strings table");
+    assert_eq!(strings.compression, Compression::Brotli);
+    let bytes = strings.get().expect("Decompressing strings table");
+    assert_eq!(
+        bytes.as_ref(),
+        uncompressed[HEADER_STRINGS_TABLE].as_slice()
+    );
+    for section in &sections {
+        assert_eq!(
+            section.is_decompressed(),
+            section.name == HEADER_STRINGS_TABLE,
+            "{:?}",
+            section
+        );
+    }
+
+    println!("* Decompressed bytes are cached");
+    let again = strings.get().expect("Accessing strings table again");
+    assert!(std::ptr::eq(again, bytes));
+
+    println!("* Uncompressed sections borrow the container");
+    let (output, uncompressed) = encode(Compression::Identity);
+    for section in &lazy_sections(&output, std::usize::MAX).expect("Listing sections") {
+        match *section.get().expect("Reading section") {
+            Cow::Borrowed(bytes) => assert_eq!(bytes, uncompressed[&section.name].as_slice()),
+            Cow::Owned(_) => panic!("Section {} was copied", section.name),
+        }
+    }
+
+    println!("* Sections larger than the limit are rejected on access");
+    let (output, _) = encode(Compression::Brotli);
+    let sections = lazy_sections(&output, 1).expect("Listing sections");
+    assert!(sections.iter().any(|section| section.get().is_err()));
+}
+
+#[test]
+fn test_multipart_omit_defaults() {
+    use binjs_shared::ast::Path;
+    use binjs_shared::{FieldName, InterfaceName};
+    use bytes::compress::Compression;
+    use io::{TokenReader, TokenWriterWithTree};
+
+    use std::io::Cursor;
+
+    let name = InterfaceName::from_str("Node");
+    let fields = [
+        FieldName::from_str("flag"),
+        FieldName::from_str("label"),
+        FieldName::from_str("items"),
+        FieldName::from_str("value"),
+    ];
+
+    // Write a node, with all fields holding their default value or none,
+    // returning the container and its uncompressed tree.
+    let encode = |defaults: bool| {
+        let tree = Rc::new(RefCell::new(vec![]));
+        let mut writer = TreeTokenWriter::with_options(
+            Targets::uniform(Compression::Identity),
+            Options {
+                omit_defaults: true,
+                on_section: Some(OnSection({
+                    let tree = tree.clone();
+                    Rc::new(move |header: &str, bytes: &[u8]| {
+                        if header == HEADER_TREE {
+                            tree.borrow_mut().extend_from_slice(bytes);
+                        }
+                    })
+                })),
+                ..Options::default()
+            },
+        );
+        let children = if defaults {
+            vec![
+                writer.bool(Some(false)),
+                writer.string(None),
+                writer.list(vec![]),
+                writer.float(None),
+            ]
+        } else {
+            let item = writer.bool(Some(true)).expect("Writing item");
+            vec![
+                writer.bool(Some(true)),
+                writer.string(Some(&SharedString::from_str("label"))),
+                writer.list(vec![item]),
+                writer.float(Some(1.5)),
+            ]
+        };
+        let children: Vec<_> = fields
+            .iter()
+            .zip(children)
+            .map(|(field, child)| (field, child.expect("Writing field")))
+            .collect();
+        writer.tagged_tuple(&name, &children).expect("Writing node");
+        let output = writer.done().expect("Finalizing data");
+        let tree = tree.borrow().clone();
+        (output, tree)
+    };
+
+    let decode = |output: &[u8]| {
+        let mut reader = TreeTokenReader::new(Cursor::new(output)).expect("Reading container");
+        let mut path = Path::new();
+        let (kind, _) = reader.enter_tagged_tuple_at(&path).expect("Reading node");
+        assert_eq!(kind, name);
+        path.enter_interface(name.clone());
+        let field = |index: usize| (index, fields[index].clone());
+
+        path.enter_field(field(0));
+        let flag = reader.bool_at(&path).expect("Reading flag");
+        path.exit_field(field(0));
+
+        path.enter_field(field(1));
+        let label = reader.string_at(&path).expect("Reading label");
+        path.exit_field(field(1));
+
+        path.enter_field(field(2));
+        let len = reader.enter_list_at(&path).expect("Reading items");
+        let mut items = vec![];
+        for _ in 0..len {
+            items.push(reader.bool_at(&path).expect("Reading item"));
+        }
+        reader.exit_list_at(&path).expect("List read properly");
+        path.exit_field(field(2));
+
+        path.enter_field(field(3));
+        let value = reader.float_at(&path).expect("Reading value");
+        path.exit_field(field(3));
+
+        path.exit_interface(name.clone());
+        reader
+            .exit_tagged_tuple_at(&path)
+            .expect("Node read properly");
+        (flag, label, items, value)
+    };
+
+    println!("* A node with all-default fields is its kind and an empty bitmask");
+    let (output, tree) = encode(true);
+    assert_eq!(tree, vec![0, 0]);
+    assert_eq!(decode(&output), (Some(false), None, vec![], None));
+
+    println!("* Other fields are present");
+    let (output, tree) = encode(false);
+    assert!(tree.len() > 2);
+    assert_eq!(
+        decode(&output),
+        (
+            Some(true),
+            Some(SharedString::from_str("label")),
+            vec![Some(true)],
+            Some(1.5)
+        )
+    );
+}
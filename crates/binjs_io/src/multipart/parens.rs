@@ -0,0 +1,114 @@
+//! Parenthesization hints on the source of a multipart container.
+//!
+//! Hints record the parentheses written around expressions of the source,
+//! including those that precedence does not require, so that the decoder
+//! may reproduce them. They are ignored when decoding the AST.
+//!
+//! Format:
+//!
+//! - the characters `"[?PARENS]"`, an optional section (see module `multipart`), so that readers
+//!   which do not know the hints skip them;
+//! - a `prefix` identifying the compression format used for the hints (currently always "identity;");
+//! - the number of compressed bytes (`varnum`);
+//! - compressed in the format identified by `prefix`:
+//!    - the number of expressions of the AST (`varnum`);
+//!    - the number of hints (`varnum`);
+//!    - for each hint, by increasing index,
+//!      - the index of the expression (`varnum`);
+//!      - the number of parentheses (`varnum`).
+
+use bytes::serialize::Deserializer;
+use bytes::varnum::*;
+
+use std;
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, Write};
+
+/// The parentheses written around expressions of a source, other than
+/// those of the enclosing construct, e.g. the condition of an `if` (see
+/// `Shift::parse_str_tolerant_with_parens`).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Parens {
+    /// The number of expressions of the AST the hints were recorded on,
+    /// so that hints are not applied to a different AST.
+    pub expressions: u32,
+
+    /// The number of parentheses, by index of the expression.
+    pub hints: BTreeMap<u32, u32>,
+}
+impl Parens {
+    /// `true` if there are no hints, in which case the section is omitted.
+    pub fn is_empty(&self) -> bool {
+        self.hints.is_empty()
+    }
+}
+
+/// Write parenthesization hints, without compression.
+pub fn write_parens<W: Write>(parens: &Parens, out: &mut W) -> Result<usize, std::io::Error> {
+    let mut total = out.write_varnum(parens.expressions)?;
+    total += out.write_varnum(parens.hints.len() as u32)?;
+    for (index, count) in &parens.hints {
+        total += out.write_varnum(*index)?;
+        total += out.write_varnum(*count)?;
+    }
+    Ok(total)
+}
+
+/// Deserialize a `Parens`.
+pub struct ParensDeserializer;
+impl Deserializer for ParensDeserializer {
+    type Target = Parens;
+    fn read<R: Read + Seek>(&self, inp: &mut R) -> Result<Parens, std::io::Error> {
+        let invalid = |message: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid parenthesization hints: {}", message),
+            )
+        };
+
+        let expressions = inp.read_varnum()?;
+        let number_of_hints = inp.read_varnum()?;
+        if number_of_hints > expressions {
+            return Err(invalid("more hints than expressions"));
+        }
+        let mut hints = BTreeMap::new();
+        let mut next_index = 0;
+        for _ in 0..number_of_hints {
+            let index = inp.read_varnum()?;
+            let count = inp.read_varnum()?;
+            if index < next_index || index >= expressions {
+                return Err(invalid("index out of order or out of bounds"));
+            }
+            next_index = index + 1;
+            hints.insert(index, count);
+        }
+        Ok(Parens { expressions, hints })
+    }
+}
+
+#[test]
+fn test_parens_roundtrip() {
+    use std::io::Cursor;
+
+    let mut parens = Parens {
+        expressions: 10,
+        hints: BTreeMap::new(),
+    };
+    parens.hints.insert(0, 1);
+    parens.hints.insert(9, 2);
+    let mut data = vec![];
+    write_parens(&parens, &mut data).unwrap();
+    assert_eq!(
+        ParensDeserializer.read(&mut Cursor::new(&data)).unwrap(),
+        parens
+    );
+
+    println!("* Hints past the last expression are rejected");
+    parens.expressions = 9;
+    let mut data = vec![];
+    write_parens(&parens, &mut data).unwrap();
+    let err = ParensDeserializer
+        .read(&mut Cursor::new(&data))
+        .expect_err("Hints should be rejected");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
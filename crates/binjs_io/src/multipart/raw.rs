@@ -0,0 +1,98 @@
+//! Dumping the sections of containers before compression, e.g. to analyze
+//! them with external tools (see `Options::on_section`).
+//!
+//! Format:
+//!
+//! - the characters `"BINJS-RAW"`;
+//! - the version of the dump format (`varnum`);
+//! - for each section, in the order of the containers:
+//!   - the header of the section, e.g. `"[TREE]"`, as a frame (see module `bytes::frame`);
+//!   - the bytes of the section before compression, as a frame.
+//!
+//! The sections of each container end with its tree, so a dump of several
+//! containers is split after each `"[TREE]"` or `"[TREE-BFS]"` section.
+
+use bytes::frame::{read_length_prefixed, write_length_prefixed};
+use bytes::varnum::*;
+use util::ReadConst;
+
+use std;
+use std::io::{Read, Write};
+
+/// The characters starting a dump of sections.
+pub const RAW_PREAMBLE: &[u8; 9] = b"BINJS-RAW";
+
+/// The version of the dump format, written after `RAW_PREAMBLE`.
+const RAW_VERSION: u32 = 1;
+
+/// Write the preamble and version of a dump of sections, before any section.
+pub fn write_raw_header<W: Write>(out: &mut W) -> Result<(), std::io::Error> {
+    out.write_all(RAW_PREAMBLE)?;
+    out.write_varnum(RAW_VERSION)?;
+    Ok(())
+}
+
+/// Write a section of a dump, as passed to `Options::on_section`.
+pub fn write_raw_section<W: Write>(
+    out: &mut W,
+    header: &str,
+    bytes: &[u8],
+) -> Result<(), std::io::Error> {
+    write_length_prefixed(out, header.as_bytes())?;
+    write_length_prefixed(out, bytes)?;
+    Ok(())
+}
+
+/// Read a dump of sections, as the pairs of their header and bytes.
+pub fn read_raw_sections<R: Read>(inp: &mut R) -> Result<Vec<(String, Vec<u8>)>, std::io::Error> {
+    inp.read_const(RAW_PREAMBLE)?;
+    let version = inp.read_varnum()?;
+    if version != RAW_VERSION {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Unsupported dump version {}", version),
+        ));
+    }
+    let mut sections = vec![];
+    loop {
+        // The dump ends between sections.
+        let mut first = [0];
+        if inp.read(&mut first)? == 0 {
+            break;
+        }
+        let header = read_length_prefixed(&mut (&first[..]).chain(inp.by_ref()), std::usize::MAX)?;
+        let header = String::from_utf8(header).map_err(|err| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, err.utf8_error())
+        })?;
+        let bytes = read_length_prefixed(inp, std::usize::MAX)?;
+        sections.push((header, bytes));
+    }
+    Ok(sections)
+}
+
+#[test]
+fn test_raw_sections() {
+    use std::io::Cursor;
+
+    println!("* Sections round-trip");
+    let sections = vec![
+        ("[GRAMMAR]".to_string(), vec![1, 2, 3]),
+        ("[STRINGS]".to_string(), vec![]),
+        ("[TREE]".to_string(), vec![42; 300]),
+    ];
+    let mut data = vec![];
+    write_raw_header(&mut data).expect("Writing header");
+    for &(ref header, ref bytes) in &sections {
+        write_raw_section(&mut data, header, bytes).expect("Writing section");
+    }
+    let found = read_raw_sections(&mut Cursor::new(&data)).expect("Reading sections");
+    assert_eq!(found, sections);
+
+    println!("* Truncated dumps are rejected");
+    for len in &[0, RAW_PREAMBLE.len(), data.len() - 1] {
+        assert!(read_raw_sections(&mut Cursor::new(&data[..*len])).is_err());
+    }
+
+    println!("* Other files are rejected");
+    assert!(read_raw_sections(&mut Cursor::new(b"BINJS\x02")).is_err());
+}
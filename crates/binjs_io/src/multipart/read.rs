@@ -1,5 +1,7 @@
 use std;
+use std::borrow::Cow;
 use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::rc::Rc;
 
@@ -11,11 +13,25 @@ use bytes::serialize::*;
 use bytes::varnum::*;
 use escaped_wtf8;
 use io::*;
-use multipart::{FormatInTable, HEADER_GRAMMAR_TABLE, HEADER_STRINGS_TABLE, HEADER_TREE};
+use multipart::diagnostics::{Diagnostic, DiagnosticsDeserializer};
+use multipart::footer::{footer_position, verify_footer, ContainerHash, StreamHasher};
+use multipart::metadata::{Metadata, MetadataDeserializer};
+use multipart::parens::{Parens, ParensDeserializer};
+use multipart::text::{from_text, is_text, TEXT_PREAMBLE};
+use multipart::{
+    read_section_header, FormatInTable, FORMAT_VERSION, FORMAT_VERSION_WITHOUT_FOOTER,
+    HEADER_BODIES, HEADER_DIAGNOSTICS, HEADER_GRAMMAR_TABLE, HEADER_GRAMMAR_TABLE_PRESENT_FIELDS,
+    HEADER_METADATA, HEADER_NODE_INDICES, HEADER_NUMBERS_TABLE, HEADER_PADDING, HEADER_PARENS,
+    HEADER_STRINGS_INLINE, HEADER_STRINGS_TABLE, HEADER_STRINGS_TABLE_MIXED, HEADER_TREE,
+    HEADER_TREE_BREADTH_FIRST, MAX_NODE_INDICES_FREQUENCY, MAX_OMITTED_FIELDS,
+    OPTIONAL_SECTION_PREFIX,
+};
 use util::{PoisonLock, Pos, ReadConst};
-use TokenReaderError;
+use {CancellationToken, DecodeWarning, TokenReaderError};
 
-use binjs_shared::{FieldName, InterfaceName, SharedString};
+use binjs_shared::{FieldName, InterfaceName, SharedString, F64};
+
+use range_encoding::{opus, CumulativeDistributionFrequency};
 
 impl Into<std::io::Error> for TokenReaderError {
     fn into(self) -> std::io::Error {
@@ -29,12 +45,7 @@ impl Deserializer for BufDeserializer {
     type Target = Vec<u8>;
     fn read<R: Read + Seek>(&self, reader: &mut R) -> Result<Self::Target, std::io::Error> {
         let size = reader.size();
-        let mut buf = Vec::with_capacity(size);
-        unsafe {
-            buf.set_len(size);
-        }
-        reader.read_exact(&mut buf)?;
-        Ok(buf)
+        bytes::frame::read_exactly(reader, size)
     }
 }
 
@@ -43,20 +54,36 @@ impl Deserializer for Option<SharedString> {
     type Target = Self;
     fn read<R: Read>(&self, inp: &mut R) -> Result<Self, std::io::Error> {
         let byte_len = inp.read_varnum()?;
-        let mut bytes = Vec::with_capacity(byte_len as usize);
-        unsafe {
-            bytes.set_len(byte_len as usize);
-        }
-        inp.read_exact(&mut bytes)?;
-        if &bytes == &[255, 0] {
-            Ok(None)
-        } else {
-            let escaped = escaped_wtf8::escape(bytes);
-            String::from_utf8(escaped)
-                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
-                .map(SharedString::from_string)
-                .map(Some)
-        }
+        read_string_bytes(inp, byte_len)
+    }
+}
+
+/// Read a String|null of `byte_len` bytes, without its byte length.
+fn read_string_bytes<R: Read>(
+    inp: &mut R,
+    byte_len: u32,
+) -> Result<Option<SharedString>, std::io::Error> {
+    // The length comes from the file, so the buffer only grows with the
+    // bytes actually read.
+    let bytes = bytes::frame::read_exactly(inp, byte_len as usize)?;
+    if &bytes == &[255, 0] {
+        Ok(None)
+    } else {
+        let escaped = escaped_wtf8::escape(bytes);
+        String::from_utf8(escaped)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+            .map(SharedString::from_string)
+            .map(Some)
+    }
+}
+
+/// Deserialize a Float|null
+impl Deserializer for Option<F64> {
+    type Target = Self;
+    fn read<R: Read>(&self, inp: &mut R) -> Result<Self, std::io::Error> {
+        let mut buf = [0; 8];
+        inp.read_exact(&mut buf)?;
+        Ok(bytes::float::float_of_bytes(&buf).map(F64::from))
     }
 }
 
@@ -86,6 +113,11 @@ where
 {
     type Target = Table<D::Target>;
     fn read<R: Read + Seek>(&self, inp: &mut R) -> Result<Self::Target, std::io::Error> {
+        // An empty section is an empty table (see `Options::empty_section_marker`).
+        if inp.size() == 0 {
+            return Ok(Table { map: VecMap::new() });
+        }
+
         // Get number of entries.
         let number_of_entries = inp.read_varnum()?;
 
@@ -125,6 +157,75 @@ where
     }
 }
 
+/// Maximal byte length of a compression prefix, including `;`.
+const MAX_PREFIX_LEN: usize = 32;
+
+/// Skip the contents of a section, whatever its compression format,
+/// using its byte length.
+pub fn skip_section<R: Read + Seek>(reader: &mut R) -> Result<(), TokenReaderError> {
+    let mut prefix_len = 0;
+    loop {
+        let mut buf = [0];
+        reader
+            .read_exact(&mut buf)
+            .map_err(TokenReaderError::ReadError)?;
+        prefix_len += 1;
+        if buf[0] == b';' {
+            break;
+        }
+        if prefix_len >= MAX_PREFIX_LEN {
+            return Err(TokenReaderError::ContainerCorrupt(
+                "Invalid compression header".to_string(),
+            ));
+        }
+    }
+    // An empty section is a lone `;`, without byte length.
+    if prefix_len == 1 {
+        return Ok(());
+    }
+    let byte_len = reader.read_varnum().map_err(TokenReaderError::ReadError)?;
+    reader
+        .seek(SeekFrom::Current(byte_len as i64))
+        .map_err(TokenReaderError::ReadError)?;
+    Ok(())
+}
+
+/// Read the compression format of a section, without moving `reader`.
+///
+/// Returns `None` if the compression format is unknown, e.g. introduced by a
+/// more recent encoder.
+fn probe_section<R: Read + Seek>(reader: &mut R) -> Result<Option<Compression>, TokenReaderError> {
+    let start = reader
+        .seek(SeekFrom::Current(0))
+        .map_err(TokenReaderError::ReadError)?;
+    let compression = Compression::probe(reader).ok();
+    reader
+        .seek(SeekFrom::Start(start))
+        .map_err(TokenReaderError::ReadError)?;
+    Ok(compression)
+}
+
+/// Decompress an optional section, or skip it if its compression format is
+/// unknown.
+fn read_optional_section<R, D>(
+    reader: &mut R,
+    compression: &Option<Compression>,
+    deserializer: &D,
+    options: &DecompressionOptions,
+) -> Result<Option<D::Target>, TokenReaderError>
+where
+    R: Read + Seek,
+    D: Deserializer,
+{
+    if compression.is_none() {
+        skip_section(reader)?;
+        return Ok(None);
+    }
+    Compression::decompress_with_options(reader, deserializer, options)
+        .map(Some)
+        .map_err(TokenReaderError::BadCompression)
+}
+
 /// Description of a node in the table.
 #[derive(Debug)]
 pub struct NodeDescription {
@@ -154,16 +255,31 @@ impl Deserializer for NodeDescriptionDeserializer {
     }
 }
 
+/// The bytes of the tree, decompressed or, if uncompressed, shared with the
+/// container (see `TreeTokenReader::from_shared`).
+enum TreeBytes {
+    Owned(Vec<u8>),
+    Shared(Rc<Vec<u8>>, std::ops::Range<usize>),
+}
+impl AsRef<[u8]> for TreeBytes {
+    fn as_ref(&self) -> &[u8] {
+        match *self {
+            TreeBytes::Owned(ref bytes) => bytes,
+            TreeBytes::Shared(ref container, ref range) => &container[range.clone()],
+        }
+    }
+}
+
 /// A wrapper of Cursor which prints the the binary representation and
 /// handles printing structural interpretation.
 /// The underlying implementation for FileStructurePrinter for TreeTokenReader.
 struct DumpCursor {
-    reader: Cursor<Vec<u8>>,
+    reader: Cursor<TreeBytes>,
     file_format_print_enabled: bool,
     newline: bool,
 }
 impl DumpCursor {
-    fn new(buf: Vec<u8>) -> DumpCursor {
+    fn new(buf: TreeBytes) -> DumpCursor {
         DumpCursor {
             reader: Cursor::new(buf),
             file_format_print_enabled: false,
@@ -227,115 +343,1053 @@ impl std::io::Seek for DumpCursor {
     }
 }
 
+/// A segment of a tree written in breadth-first order, being read.
+struct SegmentCursor {
+    /// The number of the segment.
+    segment: usize,
+
+    /// The position at which to resume reading the parent segment.
+    resume: u64,
+
+    /// The number of children of the segment entered so far.
+    entered: u32,
+}
+
+/// The segments of a tree written in breadth-first order (see `TreeOrder`).
+struct Segments {
+    /// For each segment, in order, the positions of its first byte and past
+    /// its last byte in the tree.
+    bounds: Vec<(u64, u64)>,
+
+    /// For each segment, in order, the number of its first child and its
+    /// number of children.
+    children: Vec<(usize, u32)>,
+
+    /// The segments being read, innermost last, starting with the root segment.
+    stack: Vec<SegmentCursor>,
+
+    /// The number of bytes of the segments exited or never entered.
+    unread_bytes: u64,
+}
+impl Segments {
+    /// Read the table of segments, leaving `reader` at the start of the
+    /// root segment.
+    fn read(reader: &mut Cursor<TreeBytes>) -> Result<Self, TokenReaderError> {
+        let corrupt = || TokenReaderError::ContainerCorrupt("Invalid tree segments".to_string());
+        let total_bytes = reader.get_ref().as_ref().len() as u64;
+        let number_of_segments = reader.read_varnum().map_err(TokenReaderError::ReadError)?;
+        // Each segment takes at least two bytes of the table.
+        if number_of_segments == 0 || number_of_segments as u64 > total_bytes / 2 {
+            return Err(corrupt());
+        }
+        let mut lengths = Vec::with_capacity(number_of_segments as usize);
+        let mut children = Vec::with_capacity(number_of_segments as usize);
+        // The root segment is not the child of any segment.
+        let mut next_child = 1;
+        for _ in 0..number_of_segments {
+            lengths.push(reader.read_varnum().map_err(TokenReaderError::ReadError)? as u64);
+            let number_of_children = reader.read_varnum().map_err(TokenReaderError::ReadError)?;
+            children.push((next_child, number_of_children));
+            next_child += number_of_children as usize;
+        }
+        if next_child != number_of_segments as usize {
+            return Err(corrupt());
+        }
+
+        let mut bounds = Vec::with_capacity(number_of_segments as usize);
+        let mut start = reader.position();
+        for byte_len in lengths {
+            let end = start.checked_add(byte_len).ok_or_else(corrupt)?;
+            bounds.push((start, end));
+            start = end;
+        }
+        if start != total_bytes {
+            return Err(corrupt());
+        }
+        let unread_bytes = total_bytes - bounds[0].0;
+        Ok(Segments {
+            bounds,
+            children,
+            stack: vec![SegmentCursor {
+                segment: 0,
+                resume: 0,
+                entered: 0,
+            }],
+            unread_bytes,
+        })
+    }
+
+    /// Enter the next child of the current segment, left at `position`.
+    ///
+    /// Returns the position of the first byte of the child.
+    fn enter(&mut self, position: u64) -> Result<u64, TokenReaderError> {
+        let child = {
+            let current = self.stack.last_mut().unwrap(); // We never exit the root segment.
+            let (first_child, number_of_children) = self.children[current.segment];
+            if current.entered >= number_of_children {
+                return Err(TokenReaderError::ContainerCorrupt(format!(
+                    "Tree segment {} has only {} children",
+                    current.segment, number_of_children
+                )));
+            }
+            current.entered += 1;
+            first_child + current.entered as usize - 1
+        };
+        self.stack.push(SegmentCursor {
+            segment: child,
+            resume: position,
+            entered: 0,
+        });
+        Ok(self.bounds[child].0)
+    }
+
+    /// Exit the current segment, left at `position`.
+    ///
+    /// Returns the position at which to resume reading the parent segment.
+    fn exit(&mut self, position: u64) -> Result<u64, TokenReaderError> {
+        if self.stack.len() <= 1 {
+            return Ok(position);
+        }
+        let current = self.stack.pop().unwrap();
+        let (start, end) = self.bounds[current.segment];
+        if position < start || position > end {
+            return Err(TokenReaderError::ContainerCorrupt(format!(
+                "Read past the end of tree segment {}",
+                current.segment
+            )));
+        }
+        self.unread_bytes -= position - start;
+        Ok(current.resume)
+    }
+
+    /// The number of bytes of all segments not read yet, if the current
+    /// segment is left at `position`.
+    fn remaining_bytes(&self, position: u64) -> u64 {
+        let current = self.stack.last().unwrap();
+        let start = self.bounds[current.segment].0;
+        self.unread_bytes
+            .saturating_sub(position.saturating_sub(start))
+    }
+}
+
+/// The indices of tagged tuples into the grammar table, range-encoded in a
+/// section of their own (see `Options::entropy_node_indices`).
+struct NodeIndices {
+    reader: opus::Reader<Cursor<Vec<u8>>>,
+    distribution: CumulativeDistributionFrequency,
+}
+impl NodeIndices {
+    /// Read the frequencies, leaving the range-encoded indices to `next`.
+    fn read(mut data: Vec<u8>) -> Result<Self, TokenReaderError> {
+        let corrupt = || TokenReaderError::ContainerCorrupt("Invalid node indices".to_string());
+        let (frequencies, position) = {
+            let mut reader = Cursor::new(&data);
+            let number_of_kinds = reader.read_varnum().map_err(TokenReaderError::ReadError)?;
+            // Each frequency takes at least one byte.
+            if number_of_kinds as u64 > data.len() as u64 {
+                return Err(corrupt());
+            }
+            let mut frequencies = Vec::with_capacity(number_of_kinds as usize);
+            let mut total = 0u64;
+            for _ in 0..number_of_kinds {
+                let frequency = reader.read_varnum().map_err(TokenReaderError::ReadError)?;
+                if frequency == 0 {
+                    return Err(corrupt());
+                }
+                total += frequency as u64;
+                frequencies.push(frequency);
+            }
+            // See `write_node_indices`.
+            if total > MAX_NODE_INDICES_FREQUENCY as u64 + number_of_kinds as u64 {
+                return Err(corrupt());
+            }
+            (frequencies, reader.position() as usize)
+        };
+        let encoded = data.split_off(position);
+        let reader =
+            opus::Reader::new(Cursor::new(encoded)).map_err(TokenReaderError::ReadError)?;
+        Ok(NodeIndices {
+            reader,
+            distribution: CumulativeDistributionFrequency::new(frequencies),
+        })
+    }
+
+    /// Read the index of the next tagged tuple, in pre-order.
+    fn next(&mut self) -> Result<u32, TokenReaderError> {
+        self.reader
+            .symbol(&mut self.distribution)
+            .map_err(TokenReaderError::ReadError)
+    }
+}
+
+/// The contents of lazy fields referenced from the tree, and the references
+/// being followed (see `Options::dedup_bodies`).
+struct Bodies {
+    /// The position in the tree and byte length of each contents, by id.
+    table: Vec<(u64, u32)>,
+
+    /// The position following the reference just read, until the tagged
+    /// tuple starting its contents is entered.
+    pending: Option<u64>,
+
+    /// For each contents being read through a reference, the depth of the
+    /// tagged tuple starting them, and the position following the reference.
+    returns: Vec<(usize, u64)>,
+}
+impl Bodies {
+    fn read(data: &[u8]) -> Result<Self, TokenReaderError> {
+        let mut reader = Cursor::new(data);
+        let number_of_bodies = reader.read_varnum().map_err(TokenReaderError::ReadError)?;
+        // Each entry takes at least two bytes.
+        if number_of_bodies as u64 > data.len() as u64 / 2 {
+            return Err(TokenReaderError::ContainerCorrupt(
+                "Invalid table of bodies".to_string(),
+            ));
+        }
+        let mut table = Vec::with_capacity(number_of_bodies as usize);
+        for _ in 0..number_of_bodies {
+            let position = reader.read_varnum().map_err(TokenReaderError::ReadError)?;
+            let byte_len = reader.read_varnum().map_err(TokenReaderError::ReadError)?;
+            table.push((position as u64, byte_len));
+        }
+        Ok(Bodies {
+            table,
+            pending: None,
+            returns: vec![],
+        })
+    }
+}
+
 /// The state of the `TreeTokenReader`.
 ///
 /// Use a `PoisonLock` to access this state.
 pub struct ReaderState {
     reader: DumpCursor,
-    pub strings_table: Table<Option<SharedString>>,
+    /// If `None`, strings are stored inline in the tree.
+    pub strings_table: Option<Table<Option<SharedString>>>,
+    /// If `true`, each string of the tree is flagged as either an index
+    /// into the strings table or an inline string.
+    mixed_strings: bool,
+    /// If specified, floats are stored in this table and the tree only contains indices.
+    pub numbers_table: Option<Table<Option<F64>>>,
     pub grammar_table: Table<NodeDescription>,
+
+    /// If specified, the tree is written in breadth-first order.
+    segments: Option<Segments>,
+
+    /// If specified, the indices of tagged tuples are not in the tree.
+    node_indices: Option<NodeIndices>,
+
+    /// If specified, the contents of some lazy fields are references.
+    bodies: Option<Bodies>,
+
+    /// The number of tagged tuples and lists being read.
+    depth: usize,
+
+    /// See `ReadLimits::max_depth`.
+    max_depth: usize,
+
+    /// See `ReadLimits::cancellation`, checked as tagged tuples and lists
+    /// are entered.
+    cancellation: CancellationToken,
+
+    /// If specified, the number of tagged tuples read so far, by kind.
+    node_kinds: Option<BTreeMap<SharedString, usize>>,
+
+    /// If specified, the values read so far from string enums, by interface
+    /// and field.
+    string_enums: Option<BTreeMap<(InterfaceName, FieldName), BTreeSet<SharedString>>>,
+
+    /// If specified, fields holding their default value may be omitted (see
+    /// `Options::omit_defaults`). For each tagged tuple and list being read,
+    /// innermost last, the bitmask of the fields of the tagged tuple that are
+    /// present, `None` for lists.
+    present_fields: Option<Vec<Option<u32>>>,
+}
+impl ReaderState {
+    /// `true` if the field described by the last item of `path` is omitted
+    /// from the tree, hence holds its default value.
+    ///
+    /// Items of lists are never omitted.
+    fn is_omitted(&self, path: &Path) -> bool {
+        let present_fields = match self.present_fields {
+            Some(ref stack) => stack.last(),
+            None => return false,
+        };
+        match (present_fields, path.get(0)) {
+            (Some(&Some(present_fields)), Some(item)) => {
+                let index = item.field().0;
+                index < MAX_OMITTED_FIELDS && present_fields & (1 << index) == 0
+            }
+            _ => false,
+        }
+    }
+
+    fn push_present_fields(&mut self, present_fields: Option<u32>) {
+        if let Some(ref mut stack) = self.present_fields {
+            stack.push(present_fields);
+        }
+    }
+
+    fn pop_present_fields(&mut self) {
+        if let Some(ref mut stack) = self.present_fields {
+            stack.pop();
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), TokenReaderError> {
+        self.cancellation.check(TokenReaderError::Cancelled)?;
+        if self.depth >= self.max_depth {
+            return Err(TokenReaderError::LimitExceeded(format!(
+                "More than {} nested nodes and lists",
+                self.max_depth
+            )));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+    fn exit(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
 }
 
 pub struct TreeTokenReader {
     // Shared with all children.
     owner: Rc<RefCell<PoisonLock<ReaderState>>>,
+
+    /// Metadata on the source, empty if the container has none.
+    metadata: Metadata,
+
+    /// Diagnostics on the source, empty if the container has none.
+    diagnostics: Vec<Diagnostic>,
+
+    /// The header and compression format of each section, in the order of
+    /// the container, omitting sections with an unknown compression format.
+    section_codecs: Vec<(String, Compression)>,
+
+    /// Non-fatal observations made while reading the sections.
+    warnings: Vec<DecodeWarning>,
+}
+
+/// The default maximal number of sections in a container, counting both
+/// known and skipped sections.
+///
+/// Current encoders write at most 9 sections, besides padding: metadata,
+/// diagnostics, parenthesization hints, grammar, strings, numbers, node
+/// indices, bodies and tree.
+pub const DEFAULT_MAX_SECTIONS: usize = 256;
+
+/// Limits on the resources used to read a container, e.g. one uploaded by
+/// an untrusted user.
+///
+/// Strings are always checked to be valid UTF-8 and varnums to fit in 32 bits.
+#[derive(Clone, Debug)]
+pub struct ReadLimits {
+    /// The maximal number of sections, counting both known and skipped
+    /// sections, but not `[?PADDING]` sections.
+    pub max_sections: usize,
+
+    /// The maximal byte length of each section, once decompressed.
+    pub max_section_bytes: usize,
+
+    /// The maximal number of tagged tuples and lists nested in one another.
+    pub max_depth: usize,
+
+    /// If `true`, reject containers with bytes between the tree and the footer.
+    pub reject_trailing_bytes: bool,
+
+    /// If specified, reading fails with `TokenReaderError::Cancelled` at
+    /// the next section, tagged tuple or list once the token is cancelled.
+    pub cancellation: Option<CancellationToken>,
+
+    /// The options of the decoders of sections, e.g. the size of their
+    /// buffers. Sections are limited to the lower of their `max_bytes` and
+    /// of `max_section_bytes`.
+    pub decompression: DecompressionOptions,
+
+    /// The hash computed by `TreeTokenReader::from_stream` as bytes are
+    /// received, so that the footer of containers hashed with it is checked
+    /// without another pass. Other containers are hashed again once
+    /// received entirely.
+    pub stream_hash: ContainerHash,
+}
+impl Default for ReadLimits {
+    /// Permissive limits, for trusted input.
+    fn default() -> Self {
+        ReadLimits {
+            max_sections: DEFAULT_MAX_SECTIONS,
+            max_section_bytes: std::usize::MAX,
+            max_depth: std::usize::MAX,
+            reject_trailing_bytes: false,
+            cancellation: None,
+            decompression: DecompressionOptions::default(),
+            stream_hash: ContainerHash::default(),
+        }
+    }
+}
+impl ReadLimits {
+    /// Conservative limits, for untrusted input.
+    ///
+    /// Each level of nesting takes several frames of the deserializer, so
+    /// `max_depth` is low enough to decode within the default stack of a
+    /// thread.
+    pub fn untrusted() -> Self {
+        ReadLimits {
+            max_sections: 16,
+            max_section_bytes: 32 * 1024 * 1024,
+            max_depth: 128,
+            reject_trailing_bytes: true,
+            cancellation: None,
+            decompression: DecompressionOptions::default(),
+            stream_hash: ContainerHash::default(),
+        }
+    }
+
+    /// The options of the decoders of sections, within `max_section_bytes`.
+    fn decompression_options(&self) -> DecompressionOptions {
+        DecompressionOptions {
+            max_bytes: std::cmp::min(self.decompression.max_bytes, self.max_section_bytes),
+            ..self.decompression.clone()
+        }
+    }
 }
 
 impl TreeTokenReader {
-    pub fn new<R: Read + Seek>(mut reader: R) -> Result<Self, TokenReaderError> {
-        // Check magic headers.
+    pub fn new<R: Read + Seek>(reader: R) -> Result<Self, TokenReaderError> {
+        Self::with_limits(reader, ReadLimits::default())
+    }
+
+    /// Read a container, rejecting it with `LimitExceeded` if it has more
+    /// than `max_sections` sections before the tree, e.g. a malicious
+    /// container declaring millions of empty optional sections.
+    pub fn with_max_sections<R: Read + Seek>(
+        reader: R,
+        max_sections: usize,
+    ) -> Result<Self, TokenReaderError> {
+        Self::with_limits(
+            reader,
+            ReadLimits {
+                max_sections,
+                ..ReadLimits::default()
+            },
+        )
+    }
+
+    /// Read a container from a stream that cannot seek, e.g. an HTTP body
+    /// or a pipe, rejecting it with `LimitExceeded` if it is longer than
+    /// `max_input_bytes`, or if it exceeds `limits`.
+    ///
+    /// The footer, which holds the hash of the container, is found from the
+    /// end, so the stream is buffered in memory before anything is decoded.
+    /// An index at the start of the container would let readers decode as
+    /// bytes arrive, but the hash could then only be checked at the end,
+    /// after decoding possibly corrupted data. As the reader keeps all the
+    /// sections in memory anyway, buffering at most doubles its footprint,
+    /// and an uncompressed tree is read in place (see `from_shared`).
+    ///
+    /// The stream is hashed with `limits.stream_hash` as it is received, so
+    /// that checking the footer at the end of the stream takes no other pass
+    /// over the container, unless it is hashed with another algorithm.
+    pub fn from_stream<R: Read>(
+        reader: R,
+        max_input_bytes: usize,
+        limits: ReadLimits,
+    ) -> Result<Self, TokenReaderError> {
+        let mut reader = reader.take((max_input_bytes as u64).saturating_add(1));
+        let mut hasher = StreamHasher::new(&limits.stream_hash);
+        let mut data = vec![];
+        let mut buf = vec![0; DEFAULT_READ_BUFFER_SIZE];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => {
+                    data.extend_from_slice(&buf[..len]);
+                    hasher.update(&data);
+                }
+                Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(TokenReaderError::ReadError(err)),
+            }
+        }
+        if data.len() > max_input_bytes {
+            return Err(TokenReaderError::LimitExceeded(format!(
+                "Input exceeds {} bytes",
+                max_input_bytes
+            )));
+        }
+        Self::read_shared(Rc::new(data), limits, Some(hasher))
+    }
+
+    /// Read a container held in memory, e.g. a file cached by a server,
+    /// rejecting it if it exceeds `limits`.
+    ///
+    /// If the tree is uncompressed, e.g. with `Targets::fast_tree`, it is
+    /// read in place, sharing `data`, rather than copied. Other sections
+    /// are decoded as by `with_limits`.
+    pub fn from_shared(data: Rc<Vec<u8>>, limits: ReadLimits) -> Result<Self, TokenReaderError> {
+        Self::read_shared(data, limits, None)
+    }
+
+    /// Implementation of `from_shared`, checking the footer with `hasher`
+    /// if specified.
+    fn read_shared(
+        data: Rc<Vec<u8>>,
+        limits: ReadLimits,
+        hasher: Option<StreamHasher>,
+    ) -> Result<Self, TokenReaderError> {
+        Self::read_with(Cursor::new(&data[..]), limits, hasher, |reader, options| {
+            let tree =
+                Compression::decompress_borrowed_with_options(reader, &BytesDeserializer, options)?;
+            Ok(match tree {
+                Cow::Borrowed(bytes) => {
+                    // An uncompressed tree ends with its block.
+                    let end = reader.position() as usize;
+                    TreeBytes::Shared(data.clone(), end - bytes.len()..end)
+                }
+                Cow::Owned(bytes) => TreeBytes::Owned(bytes),
+            })
+        })
+    }
+
+    /// Read a container, rejecting it if it exceeds `limits`.
+    ///
+    /// Exceeding `max_sections` or `max_depth` is reported as `LimitExceeded`,
+    /// an oversized section as `BadCompression` and trailing bytes as
+    /// `ContainerCorrupt`. The depth is only checked as the tree is read.
+    /// A container of a more recent format version than `FORMAT_VERSION` is
+    /// reported as `UnsupportedContainerVersion`.
+    ///
+    /// Containers in text form, see `Options::text`, are detected and
+    /// decoded to the binary form first.
+    pub fn with_limits<R: Read + Seek>(
+        reader: R,
+        limits: ReadLimits,
+    ) -> Result<Self, TokenReaderError> {
+        Self::read_with(reader, limits, None, |reader, options| {
+            Compression::decompress_with_options(reader, &BufDeserializer, options)
+                .map(TreeBytes::Owned)
+        })
+    }
+
+    /// Implementation of `with_limits`, reading the tree section with
+    /// `read_tree`, from its compression prefix, with the options of the
+    /// decoders of sections, and checking the footer with `hasher`, if
+    /// specified, which has hashed the start of the container already.
+    fn read_with<R, F>(
+        mut reader: R,
+        limits: ReadLimits,
+        hasher: Option<StreamHasher>,
+        mut read_tree: F,
+    ) -> Result<Self, TokenReaderError>
+    where
+        R: Read + Seek,
+        F: FnMut(&mut R, &DecompressionOptions) -> Result<TreeBytes, std::io::Error>,
+    {
+        let mut preamble = Vec::with_capacity(TEXT_PREAMBLE.len());
+        reader
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| {
+                (&mut reader)
+                    .take(TEXT_PREAMBLE.len() as u64)
+                    .read_to_end(&mut preamble)
+            })
+            .and_then(|_| reader.seek(SeekFrom::Start(0)))
+            .map_err(TokenReaderError::ReadError)?;
+        if is_text(&preamble) {
+            let mut text = vec![];
+            reader
+                .read_to_end(&mut text)
+                .map_err(TokenReaderError::ReadError)?;
+            return Self::with_limits(Cursor::new(from_text(&text)?), limits);
+        }
+
+        let max_sections = limits.max_sections;
+        let options = limits.decompression_options();
+        let cancellation = limits.cancellation.clone().unwrap_or_default();
+
         const MAGIC_HEADER: &'static [u8; 5] = b"BINJS";
-        const FORMAT_VERSION: u32 = 1;
 
+        // Older versions, still read, with a warning.
+        const DEPRECATED_FORMAT_VERSIONS: &[u32] = &[FORMAT_VERSION_WITHOUT_FOOTER];
+
+        // Check the integrity of the entire container before decoding anything,
+        // unless it predates the footer. A corrupted header is reported by
+        // the footer check, as any other corruption.
+        let version = reader
+            .read_const(MAGIC_HEADER)
+            .and_then(|_| reader.read_varnum())
+            .ok();
+        // A more recent format may lay out its footer differently, so it
+        // is rejected before checking the footer.
+        if let Some(version) = version {
+            if version > FORMAT_VERSION {
+                return Err(TokenReaderError::UnsupportedContainerVersion(version));
+            }
+        }
+        let has_footer = match version {
+            Some(version) => version > FORMAT_VERSION_WITHOUT_FOOTER,
+            None => true,
+        };
+        cancellation.check(TokenReaderError::Cancelled)?;
+        if has_footer {
+            match hasher {
+                Some(hasher) => hasher.verify(&mut reader)?,
+                None => verify_footer(&mut reader)?,
+            }
+        } else {
+            reader
+                .seek(SeekFrom::Start(0))
+                .map_err(TokenReaderError::ReadError)?;
+        }
+
+        // Check magic headers.
         reader
             .read_const(MAGIC_HEADER)
             .map_err(TokenReaderError::ReadError)?;
 
         let version = reader.read_varnum().map_err(TokenReaderError::ReadError)?;
 
-        if version != FORMAT_VERSION {
+        let mut warnings = vec![];
+        if DEPRECATED_FORMAT_VERSIONS.contains(&version) {
+            warnings.push(DecodeWarning::DeprecatedVersion(version));
+        } else if version != FORMAT_VERSION {
             return Err(TokenReaderError::BadHeader);
         }
 
-        // At this stage, we could start parallelizing reads between grammar table and strings table, possibly even the tree.
-        reader
-            .read_const(HEADER_GRAMMAR_TABLE.as_bytes())
-            .map_err(TokenReaderError::ReadError)?;
-
-        // Read grammar table
-        let grammar_deserializer = TableDeserializer {
-            deserializer: NodeDescriptionDeserializer,
+        // Read sections up to the tree. Sections may be omitted or added by
+        // more recent encoders, so we only require the ones we cannot do without.
+        let mut metadata = Metadata::default();
+        let mut diagnostics = vec![];
+        let mut parens = Parens::default();
+        let mut grammar_table = None;
+        // Without a strings (resp. numbers) table, strings (resp. floats) are stored inline
+        // in the tree.
+        let mut strings_table = None;
+        let mut mixed_strings = false;
+        let mut inline_strings = false;
+        let mut numbers_table = None;
+        let mut node_indices = None;
+        let mut bodies = None;
+        let mut present_fields = None;
+        let mut section_codecs = vec![];
+        let mut number_of_sections = 0;
+        let (decompressed_tree, breadth_first) = loop {
+            cancellation.check(TokenReaderError::Cancelled)?;
+            let header = read_section_header(&mut reader)?;
+            // Padding depends on the alignment, not on the contents.
+            if header != HEADER_PADDING {
+                number_of_sections += 1;
+                if number_of_sections > max_sections {
+                    return Err(TokenReaderError::LimitExceeded(format!(
+                        "More than {} sections",
+                        max_sections
+                    )));
+                }
+            }
+            let compression = probe_section(&mut reader)?;
+            if let Some(ref compression) = compression {
+                section_codecs.push((header.clone(), compression.clone()));
+            }
+            match header.as_str() {
+                HEADER_METADATA => {
+                    match read_optional_section(
+                        &mut reader,
+                        &compression,
+                        &MetadataDeserializer,
+                        &options,
+                    )? {
+                        Some(value) => metadata = value,
+                        None => warnings.push(DecodeWarning::SkippedSection(header.clone())),
+                    }
+                }
+                HEADER_DIAGNOSTICS => {
+                    match read_optional_section(
+                        &mut reader,
+                        &compression,
+                        &DiagnosticsDeserializer,
+                        &options,
+                    )? {
+                        Some(value) => diagnostics = value,
+                        None => warnings.push(DecodeWarning::SkippedSection(header.clone())),
+                    }
+                }
+                HEADER_PARENS => {
+                    match read_optional_section(
+                        &mut reader,
+                        &compression,
+                        &ParensDeserializer,
+                        &options,
+                    )? {
+                        Some(value) => parens = value,
+                        None => warnings.push(DecodeWarning::SkippedSection(header.clone())),
+                    }
+                }
+                HEADER_GRAMMAR_TABLE | HEADER_GRAMMAR_TABLE_PRESENT_FIELDS => {
+                    let grammar_deserializer = TableDeserializer {
+                        deserializer: NodeDescriptionDeserializer,
+                    };
+                    let table = Compression::decompress_with_options(
+                        &mut reader,
+                        &grammar_deserializer,
+                        &options,
+                    )
+                    .map_err(TokenReaderError::BadCompression)?;
+                    debug!(target: "multipart", "Grammar table: {:?}", table.map);
+                    grammar_table = Some(table);
+                    present_fields = if header == HEADER_GRAMMAR_TABLE_PRESENT_FIELDS {
+                        Some(vec![])
+                    } else {
+                        None
+                    };
+                }
+                HEADER_STRINGS_TABLE | HEADER_STRINGS_TABLE_MIXED => {
+                    let strings_deserializer = TableDeserializer {
+                        deserializer: None::<SharedString>,
+                    };
+                    let table = Compression::decompress_with_options(
+                        &mut reader,
+                        &strings_deserializer,
+                        &options,
+                    )
+                    .map_err(TokenReaderError::BadCompression)?;
+                    strings_table = Some(table);
+                    mixed_strings = header == HEADER_STRINGS_TABLE_MIXED;
+                }
+                HEADER_STRINGS_INLINE => {
+                    skip_section(&mut reader)?;
+                    inline_strings = true;
+                }
+                HEADER_NUMBERS_TABLE => {
+                    let numbers_deserializer = TableDeserializer {
+                        deserializer: None::<F64>,
+                    };
+                    let table = Compression::decompress_with_options(
+                        &mut reader,
+                        &numbers_deserializer,
+                        &options,
+                    )
+                    .map_err(TokenReaderError::BadCompression)?;
+                    numbers_table = Some(table);
+                }
+                HEADER_NODE_INDICES => {
+                    let data = Compression::decompress_with_options(
+                        &mut reader,
+                        &BufDeserializer,
+                        &options,
+                    )
+                    .map_err(TokenReaderError::BadCompression)?;
+                    node_indices = Some(NodeIndices::read(data)?);
+                }
+                HEADER_BODIES => {
+                    let data = Compression::decompress_with_options(
+                        &mut reader,
+                        &BufDeserializer,
+                        &options,
+                    )
+                    .map_err(TokenReaderError::BadCompression)?;
+                    bodies = Some(Bodies::read(&data)?);
+                }
+                HEADER_TREE | HEADER_TREE_BREADTH_FIRST => {
+                    // Decompress tree section to memory (we could as well stream it)
+                    let tree = read_tree(&mut reader, &options)
+                        .map_err(TokenReaderError::BadCompression)?;
+                    break (tree, header == HEADER_TREE_BREADTH_FIRST);
+                }
+                HEADER_PADDING => {
+                    skip_section(&mut reader)?;
+                }
+                _ if header.starts_with(OPTIONAL_SECTION_PREFIX) => {
+                    debug!(target: "multipart", "Skipping unknown section {}", header);
+                    skip_section(&mut reader)?;
+                    warnings.push(DecodeWarning::SkippedSection(header.clone()));
+                }
+                _ => return Err(TokenReaderError::BadHeaderName(header.into_bytes())),
+            }
         };
-        let grammar_table = Compression::decompress(&mut reader, &grammar_deserializer)
-            .map_err(TokenReaderError::BadCompression)?;
-        debug!(target: "multipart", "Grammar table: {:?}",
-            grammar_table.map);
+        let grammar_table = grammar_table.ok_or_else(|| {
+            TokenReaderError::ContainerCorrupt("Missing grammar table".to_string())
+        })?;
+        if strings_table.is_some() == inline_strings {
+            return Err(TokenReaderError::ContainerCorrupt(
+                "Expected either a strings table or inline strings".to_string(),
+            ));
+        }
+        if limits.reject_trailing_bytes {
+            let end_of_tree = reader
+                .seek(SeekFrom::Current(0))
+                .map_err(TokenReaderError::ReadError)?;
+            let end_of_sections = if has_footer {
+                footer_position(&mut reader)?.0
+            } else {
+                reader
+                    .seek(SeekFrom::End(0))
+                    .map_err(TokenReaderError::ReadError)?
+            };
+            if end_of_tree != end_of_sections {
+                return Err(TokenReaderError::ContainerCorrupt(
+                    "Unexpected bytes after the tree".to_string(),
+                ));
+            }
+        }
 
-        // Read strings table
-        reader
-            .read_const(HEADER_STRINGS_TABLE.as_bytes())
-            .map_err(TokenReaderError::ReadError)?;
-        let strings_deserializer = TableDeserializer {
-            deserializer: None, /* Option<SharedString> */
+        let mut tree = DumpCursor::new(decompressed_tree);
+        let segments = if breadth_first {
+            Some(Segments::read(&mut tree.reader)?)
+        } else {
+            None
         };
-        let strings_table = Compression::decompress(&mut reader, &strings_deserializer)
-            .map_err(TokenReaderError::BadCompression)?;
+        // References are positions in a pre-order tree, with node indices.
+        if bodies.is_some() && (segments.is_some() || node_indices.is_some()) {
+            return Err(TokenReaderError::ContainerCorrupt(
+                "Unexpected table of bodies".to_string(),
+            ));
+        }
 
-        // Decompress tree section to memory (we could as well stream it)
-        reader
-            .read_const(HEADER_TREE.as_bytes())
-            .map_err(TokenReaderError::ReadError)?;
-        let decompressed_tree = Compression::decompress(&mut reader, &BufDeserializer)
-            .map_err(TokenReaderError::BadCompression)?;
         let implem = ReaderState {
             strings_table,
+            mixed_strings,
+            numbers_table,
             grammar_table,
-            reader: DumpCursor::new(decompressed_tree),
+            segments,
+            node_indices,
+            bodies,
+            reader: tree,
+            depth: 0,
+            max_depth: limits.max_depth,
+            cancellation,
+            node_kinds: None,
+            string_enums: None,
+            present_fields,
         };
 
+        // Stored in a section of their own, carried along the metadata.
+        metadata.parens = parens;
         Ok(TreeTokenReader {
             owner: Rc::new(RefCell::new(PoisonLock::new(implem))),
+            metadata,
+            diagnostics,
+            section_codecs,
+            warnings,
+        })
+    }
+
+    /// The compression format of a section, e.g. `"[STRINGS]"`, as
+    /// announced by its compression prefix.
+    ///
+    /// Returns `None` if the container has no such section, or if its
+    /// compression format is unknown.
+    pub fn section_codec(&self, name: &str) -> Option<Compression> {
+        self.section_codecs
+            .iter()
+            .find(|&&(ref header, _)| header == name)
+            .map(|&(_, ref compression)| compression.clone())
+    }
+
+    /// Metadata on the source, e.g. a shebang.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Arbitrary metadata stored by the encoder, e.g. build information,
+    /// in the order of keys.
+    pub fn user_metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata.user
+    }
+
+    /// Non-fatal observations on the source, e.g. use of `eval`.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Non-fatal observations made while reading the sections, e.g. an
+    /// optional section skipped as unknown.
+    pub fn warnings(&self) -> &[DecodeWarning] {
+        &self.warnings
+    }
+
+    /// Start counting the tagged tuples read, by kind, see `node_kinds`,
+    /// and collecting the values of string enums, see `string_enums`.
+    pub fn count_node_kinds(&mut self) {
+        let _: Result<(), ()> = self.owner.borrow_mut().try(|state| {
+            state.node_kinds.get_or_insert_with(BTreeMap::new);
+            state.string_enums.get_or_insert_with(BTreeMap::new);
+            Ok(())
+        });
+    }
+
+    /// The number of tagged tuples read since `count_node_kinds`, by kind,
+    /// e.g. to find out which parts of the grammar a corpus uses.
+    ///
+    /// Kinds which were never read, including nodes skipped as lazy, are
+    /// absent. Null interfaces are counted under the null name of the spec.
+    pub fn node_kinds(&self) -> BTreeMap<SharedString, usize> {
+        let result: Result<_, ()> = self
+            .owner
+            .borrow_mut()
+            .try(|state| Ok(state.node_kinds.clone().unwrap_or_default()));
+        result.unwrap_or_default()
+    }
+
+    /// The values read from string enums since `count_node_kinds`, by
+    /// interface and field.
+    ///
+    /// As for `node_kinds`, values only present in nodes skipped as lazy
+    /// are absent.
+    pub fn string_enums(&self) -> BTreeMap<(InterfaceName, FieldName), BTreeSet<SharedString>> {
+        let result: Result<_, ()> = self
+            .owner
+            .borrow_mut()
+            .try(|state| Ok(state.string_enums.clone().unwrap_or_default()));
+        result.unwrap_or_default()
+    }
+
+    /// The number of bytes of the tree not read yet.
+    ///
+    /// Once the entire AST has been read, anything but 0 denotes trailing
+    /// bytes, e.g. a tree that is longer than its AST.
+    ///
+    /// Fails if reading the tree has failed, as the position in the tree is
+    /// then meaningless, or if the tree has been read past its end.
+    pub fn remaining_tree_bytes(&self) -> Result<u64, TokenReaderError> {
+        let mut owner = self.owner.borrow_mut();
+        if owner.is_poisoned() {
+            return Err(TokenReaderError::GenericError(
+                "The tree could not be read".to_string(),
+            ));
+        }
+        owner.try(|state| {
+            let reader = &state.reader.reader;
+            if let Some(ref segments) = state.segments {
+                return Ok(segments.remaining_bytes(reader.position()));
+            }
+            let len = reader.get_ref().as_ref().len() as u64;
+            if reader.position() > len {
+                return Err(TokenReaderError::UnexpectedEndOfStream(
+                    "The tree was read past its end".to_string(),
+                ));
+            }
+            Ok(len - reader.position())
         })
     }
 }
 
 impl TokenReader for TreeTokenReader {
+    fn warnings(&self) -> &[DecodeWarning] {
+        &self.warnings
+    }
+
     fn poison(&mut self) {
         self.owner.borrow_mut().poison();
     }
 
-    fn string_at(&mut self, _path: &Path) -> Result<Option<SharedString>, TokenReaderError> {
+    fn string_at(&mut self, path: &Path) -> Result<Option<SharedString>, TokenReaderError> {
         self.owner.borrow_mut().try(|state| {
-            let index = state
-                .reader
-                .read_varnum()
-                .map_err(TokenReaderError::ReadError)?;
-            match state.strings_table.get(index) {
-                Some(result) => {
-                    debug!(target: "multipart", "Reading string {:?} => {:?}", index, result);
-                    match result {
-                        Some(s) => {
-                            print_file_structure!(
-                                state.reader,
-                                "string=\"{}\"",
-                                escaped_wtf8::to_unicode_escape(s.to_string())
-                            );
-                            Ok(Some(s.clone()))
-                        }
-                        None => {
-                            print_file_structure!(state.reader, "string=None");
-                            Ok(None)
-                        }
+            if state.is_omitted(path) {
+                print_file_structure!(state.reader, "string=None (omitted)");
+                return Ok(None);
+            }
+            let result = match state.strings_table {
+                Some(ref strings_table) if state.mixed_strings => {
+                    let flagged = state
+                        .reader
+                        .read_varnum()
+                        .map_err(TokenReaderError::ReadError)?;
+                    if flagged & 1 == 0 {
+                        let index = flagged >> 1;
+                        let result = strings_table
+                            .get(index)
+                            .ok_or(TokenReaderError::BadStringIndex(index))?
+                            .clone();
+                        debug!(target: "multipart", "Reading string {:?} => {:?}", index, result);
+                        result
+                    } else {
+                        let result = read_string_bytes(&mut state.reader, flagged >> 1)
+                            .map_err(TokenReaderError::ReadError)?;
+                        debug!(target: "multipart", "Reading inline string {:?}", result);
+                        result
                     }
                 }
-                None => Err(TokenReaderError::BadStringIndex(index)),
+                Some(ref strings_table) => {
+                    let index = state
+                        .reader
+                        .read_varnum()
+                        .map_err(TokenReaderError::ReadError)?;
+                    let result = strings_table
+                        .get(index)
+                        .ok_or(TokenReaderError::BadStringIndex(index))?
+                        .clone();
+                    debug!(target: "multipart", "Reading string {:?} => {:?}", index, result);
+                    result
+                }
+                None => {
+                    // No strings table, the string is stored inline.
+                    let result = None::<SharedString>
+                        .read(&mut state.reader)
+                        .map_err(TokenReaderError::ReadError)?;
+                    debug!(target: "multipart", "Reading inline string {:?}", result);
+                    result
+                }
+            };
+            match result {
+                Some(ref s) => {
+                    print_file_structure!(
+                        state.reader,
+                        "string=\"{}\"",
+                        escaped_wtf8::to_unicode_escape(s.to_string())
+                    );
+                }
+                None => {
+                    print_file_structure!(state.reader, "string=None");
+                }
             }
+            Ok(result)
         })
     }
 
+    fn string_enum_at(&mut self, path: &Path) -> Result<SharedString, TokenReaderError> {
+        let value = self
+            .string_at(path)?
+            .ok_or(TokenReaderError::EmptyVariant)?;
+        if let Some(item) = path.get(0) {
+            let _: Result<(), ()> = self.owner.borrow_mut().try(|state| {
+                if let Some(ref mut string_enums) = state.string_enums {
+                    string_enums
+                        .entry((item.interface().clone(), item.field().1.clone()))
+                        .or_insert_with(BTreeSet::new)
+                        .insert(value.clone());
+                }
+                Ok(())
+            });
+        }
+        Ok(value)
+    }
+
     /// Read a single `f64`. Note that all numbers are `f64`.
-    fn float_at(&mut self, _path: &Path) -> Result<Option<f64>, TokenReaderError> {
+    fn float_at(&mut self, path: &Path) -> Result<Option<f64>, TokenReaderError> {
         self.owner.borrow_mut().try(|state| {
+            if state.is_omitted(path) {
+                print_file_structure!(state.reader, "float=None (omitted)");
+                return Ok(None);
+            }
+            if let Some(ref numbers_table) = state.numbers_table {
+                let index = state
+                    .reader
+                    .read_varnum()
+                    .map_err(TokenReaderError::ReadError)?;
+                let result = numbers_table
+                    .get(index)
+                    .ok_or(TokenReaderError::BadNumberIndex(index))?
+                    .map(Into::<f64>::into);
+                debug!(target: "multipart", "Reading float {:?} => {:?}", index, result);
+                match result {
+                    Some(f) => {
+                        print_file_structure!(state.reader, "float={}", f);
+                    }
+                    None => {
+                        print_file_structure!(state.reader, "float=None");
+                    }
+                };
+                return Ok(result);
+            }
             let mut buf: [u8; 8] = unsafe { std::mem::uninitialized() };
             state
                 .reader
@@ -368,8 +1422,12 @@ impl TokenReader for TreeTokenReader {
     }
 
     /// Read a single `bool`.
-    fn bool_at(&mut self, _path: &Path) -> Result<Option<bool>, TokenReaderError> {
+    fn bool_at(&mut self, path: &Path) -> Result<Option<bool>, TokenReaderError> {
         self.owner.borrow_mut().try(|state| {
+            if state.is_omitted(path) {
+                print_file_structure!(state.reader, "bool=false (omitted)");
+                return Ok(Some(false));
+            }
             let mut buf: [u8; 1] = unsafe { std::mem::uninitialized() };
             state
                 .reader
@@ -394,10 +1452,40 @@ impl TokenReader for TreeTokenReader {
     /// Read a single `u32`.
     fn offset_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError> {
         self.owner.borrow_mut().try(|state| {
+            let start = state.reader.reader.position();
             let byte_len = state
                 .reader
                 .read_varnum()
                 .map_err(TokenReaderError::ReadError)?;
+            if let (0, Some(bodies)) = (byte_len, state.bodies.as_mut()) {
+                // A reference to identical contents, earlier in the tree.
+                let id = state
+                    .reader
+                    .read_varnum()
+                    .map_err(TokenReaderError::ReadError)?;
+                let (position, byte_len) = match bodies.table.get(id as usize) {
+                    // Pointing backwards, references cannot form cycles.
+                    Some(&(position, byte_len)) if position + byte_len as u64 <= start => {
+                        (position, byte_len)
+                    }
+                    _ => {
+                        return Err(TokenReaderError::ContainerCorrupt(format!(
+                            "Invalid reference to body {}",
+                            id
+                        )))
+                    }
+                };
+                bodies.pending = Some(state.reader.reader.position());
+                state.reader.reader.set_position(position);
+                print_file_structure!(
+                    state.reader,
+                    "offset=body #{} +{} ({})",
+                    id,
+                    byte_len,
+                    position
+                );
+                return Ok(byte_len);
+            }
             let offset = state
                 .reader
                 .seek(SeekFrom::Current(0))
@@ -412,13 +1500,65 @@ impl TokenReader for TreeTokenReader {
         })
     }
 
+    fn skip_lazy_at(
+        &mut self,
+        byte_len: u32,
+        _path: &Path,
+    ) -> Result<Option<u64>, TokenReaderError> {
+        self.owner.borrow_mut().try(|state| {
+            // Offsets are those of the pre-order tree, and node indices
+            // are read in order.
+            if state.segments.is_some() || state.node_indices.is_some() {
+                return Ok(None);
+            }
+            let position = state
+                .reader
+                .seek(SeekFrom::Current(0))
+                .map_err(TokenReaderError::ReadError)?;
+            // Contents reached through a reference are skipped by resuming
+            // after the reference.
+            if let Some(resume) = state
+                .bodies
+                .as_mut()
+                .and_then(|bodies| bodies.pending.take())
+            {
+                state.reader.reader.set_position(resume);
+                print_file_structure!(state.reader, "skipped=body");
+                return Ok(Some(position));
+            }
+            state
+                .reader
+                .seek(SeekFrom::Current(byte_len as i64))
+                .map_err(TokenReaderError::ReadError)?;
+            print_file_structure!(state.reader, "skipped=+{}", byte_len);
+            Ok(Some(position))
+        })
+    }
+
+    fn seek_lazy_at(&mut self, position: u64, _path: &Path) -> Result<(), TokenReaderError> {
+        self.owner.borrow_mut().try(|state| {
+            state
+                .reader
+                .seek(SeekFrom::Start(position))
+                .map_err(TokenReaderError::ReadError)?;
+            Ok(())
+        })
+    }
+
     /// Start reading a list.
     ///
     /// Returns an extractor for that list and the number of elements
     /// in the list. Before dropping the sub-extractor, callers MUST
     /// either reach the end of the list or call `skip()`.
-    fn enter_list_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError> {
+    fn enter_list_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
         self.owner.borrow_mut().try(move |state| {
+            state.enter()?;
+            let omitted = state.is_omitted(path);
+            state.push_present_fields(None);
+            if omitted {
+                debug!(target: "multipart", "Reading omitted list");
+                return Ok(0);
+            }
             let list_len = state
                 .reader
                 .read_varnum()
@@ -428,6 +1568,14 @@ impl TokenReader for TreeTokenReader {
         })
     }
 
+    fn exit_list_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        self.owner.borrow_mut().try(|state| {
+            state.pop_present_fields();
+            state.exit();
+            Ok(())
+        })
+    }
+
     /// Start reading a tagged tuple.
     ///
     /// Returns the tag name, `None` for fields and a
@@ -438,21 +1586,64 @@ impl TokenReader for TreeTokenReader {
         _path: &Path,
     ) -> Result<(InterfaceName, Option<Rc<Box<[FieldName]>>>), TokenReaderError> {
         self.owner.borrow_mut().try(|state| {
-            let index = state
-                .reader
-                .read_varnum()
-                .map_err(TokenReaderError::ReadError)?;
+            state.enter()?;
+            if let Some(ref mut bodies) = state.bodies {
+                if let Some(resume) = bodies.pending.take() {
+                    bodies.returns.push((state.depth, resume));
+                }
+            }
+            if let Some(ref mut segments) = state.segments {
+                let position = state.reader.reader.position();
+                let start = segments.enter(position)?;
+                state.reader.reader.set_position(start);
+            }
+            let index = match state.node_indices {
+                Some(ref mut node_indices) => node_indices.next()?,
+                None => state
+                    .reader
+                    .read_varnum()
+                    .map_err(TokenReaderError::ReadError)?,
+            };
             let description = state
                 .grammar_table
                 .get(index)
                 .ok_or(TokenReaderError::BadKindIndex(index))?;
 
+            if let Some(ref mut node_kinds) = state.node_kinds {
+                *node_kinds.entry(description.kind.clone()).or_insert(0) += 1;
+            }
             let tag = InterfaceName(description.kind.clone());
+            if state.present_fields.is_some() {
+                let present_fields = state
+                    .reader
+                    .read_varnum()
+                    .map_err(TokenReaderError::ReadError)?;
+                state.push_present_fields(Some(present_fields));
+            }
             debug!(target: "multipart", "Reading tagged tuple with kind \"{}\"",
                 tag.as_shared_string());
             Ok((tag, None))
         })
     }
+
+    fn exit_tagged_tuple_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        self.owner.borrow_mut().try(|state| {
+            if let Some(ref mut segments) = state.segments {
+                let position = state.reader.reader.position();
+                let resume = segments.exit(position)?;
+                state.reader.reader.set_position(resume);
+            }
+            if let Some(ref mut bodies) = state.bodies {
+                if bodies.returns.last().map(|&(depth, _)| depth) == Some(state.depth) {
+                    let (_, resume) = bodies.returns.pop().unwrap();
+                    state.reader.reader.set_position(resume);
+                }
+            }
+            state.pop_present_fields();
+            state.exit();
+            Ok(())
+        })
+    }
 }
 
 impl FileStructurePrinter for TreeTokenReader {
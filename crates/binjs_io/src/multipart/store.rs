@@ -0,0 +1,382 @@
+//! Storing the sections of multipart containers in a content-addressed store.
+//!
+//! Each large section (grammar table, strings table, numbers table, tree) is
+//! stored as a file of its own, named by the SHA-256 of its contents, so that
+//! identical sections across containers (e.g. the same grammar table) are
+//! stored once, as immutable files.
+//!
+//! The container itself becomes a manifest, in which each such section is
+//! replaced with a reference:
+//!
+//! - the header of the section (e.g. `"[GRAMMAR]"`), unchanged;
+//! - the characters `"ref;"`, in place of the compression prefix;
+//! - the byte length of the name (`varnum`);
+//! - the name of the file holding the section (hex-encoded SHA-256).
+//!
+//! The file holds the section as it appears in the container, i.e. compression
+//! prefix, byte length and compressed bytes. Smaller sections (metadata,
+//! diagnostics) remain in the manifest. The manifest has a footer of its own.
+//!
+//! As `"ref"` is not a known compression format, decoding a manifest without
+//! resolving its references first fails.
+//!
+//! # Chunk store
+//!
+//! Alternatively, each large section may be split into content-defined chunks
+//! (see `bytes::chunk`), each stored as a file of its own, named by its SHA-256,
+//! so that chunks shared between sections that differ elsewhere (e.g. the tree
+//! of two versions of a script) are also stored once. In the manifest, such a
+//! section is replaced with:
+//!
+//! - the header of the section (e.g. `"[TREE]"`), unchanged;
+//! - the characters `"chunks;"`, in place of the compression prefix;
+//! - the byte length of the list of chunks (`varnum`);
+//! - the number of chunks (`varnum`);
+//! - for each chunk, in order, the byte length of its name (`varnum`) and its
+//!   name (hex-encoded SHA-256).
+//!
+//! Concatenating the chunks yields the section as it appears in the container.
+//!
+//! Chunks are cut from the section as it appears in the container, so they
+//! are only shared if the section is uncompressed: a change anywhere in the
+//! input of a compressor changes much of its output. Chunking therefore
+//! requires stored sections to be compressed with `Compression::Identity`.
+
+use bytes::chunk::Chunker;
+use bytes::compress::Compression;
+use bytes::varnum::*;
+use multipart::footer::{verified_digest, write_footer, ContainerHash};
+use multipart::{
+    HEADER_FOOTER, HEADER_GRAMMAR_TABLE, HEADER_GRAMMAR_TABLE_PRESENT_FIELDS, HEADER_NODE_INDICES,
+    HEADER_NUMBERS_TABLE, HEADER_STRINGS_TABLE, HEADER_STRINGS_TABLE_MIXED, HEADER_TREE,
+    HEADER_TREE_BREADTH_FIRST,
+};
+use TokenReaderError;
+
+use sha2::{Digest, Sha256};
+
+use std;
+use std::fs;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// The prefix replacing the compression prefix of stored sections.
+const PREFIX_REF: &[u8] = b"ref;";
+
+/// The prefix replacing the compression prefix of sections stored as chunks.
+const PREFIX_CHUNKS: &[u8] = b"chunks;";
+
+/// Maximal byte length of a compression prefix, including `;`.
+const MAX_PREFIX_LEN: usize = 32;
+
+/// A section, as it appears in a container.
+struct RawSection<'a> {
+    /// E.g. `"[GRAMMAR]"`.
+    header: &'a [u8],
+
+    /// Compression prefix, byte length and compressed bytes.
+    block: &'a [u8],
+}
+impl<'a> RawSection<'a> {
+    fn is_stored(&self) -> bool {
+        [
+            HEADER_GRAMMAR_TABLE,
+            HEADER_GRAMMAR_TABLE_PRESENT_FIELDS,
+            HEADER_STRINGS_TABLE,
+            HEADER_STRINGS_TABLE_MIXED,
+            HEADER_NUMBERS_TABLE,
+            HEADER_NODE_INDICES,
+            HEADER_TREE,
+            HEADER_TREE_BREADTH_FIRST,
+        ]
+        .iter()
+        .any(|header| header.as_bytes() == self.header)
+    }
+
+    /// If this section is a reference, the name of the file it references.
+    fn reference(&self) -> Option<&'a [u8]> {
+        if !self.block.starts_with(PREFIX_REF) {
+            return None;
+        }
+        let mut cursor = Cursor::new(&self.block[PREFIX_REF.len()..]);
+        cursor.read_varnum().ok()?;
+        Some(&self.block[PREFIX_REF.len() + cursor.position() as usize..])
+    }
+
+    /// If this section is stored as chunks, the names of the files holding
+    /// its chunks, in order.
+    fn chunk_references(&self) -> Result<Option<Vec<&'a [u8]>>, TokenReaderError> {
+        if !self.block.starts_with(PREFIX_CHUNKS) {
+            return Ok(None);
+        }
+        let corrupt = || TokenReaderError::ContainerCorrupt("Invalid chunk list".to_string());
+        let list = &self.block[PREFIX_CHUNKS.len()..];
+        let mut cursor = Cursor::new(list);
+        cursor.read_varnum().map_err(TokenReaderError::ReadError)?;
+        let count = cursor.read_varnum().map_err(TokenReaderError::ReadError)?;
+        let mut names = vec![];
+        for _ in 0..count {
+            let len = cursor.read_varnum().map_err(TokenReaderError::ReadError)? as usize;
+            let start = cursor.position() as usize;
+            if start + len > list.len() {
+                return Err(corrupt());
+            }
+            names.push(&list[start..start + len]);
+            cursor.set_position((start + len) as u64);
+        }
+        if cursor.position() as usize != list.len() {
+            return Err(corrupt());
+        }
+        Ok(Some(names))
+    }
+}
+
+/// A container, split into sections.
+struct RawContainer<'a> {
+    /// Magic header and version number.
+    prologue: &'a [u8],
+    sections: Vec<RawSection<'a>>,
+    hash: ContainerHash,
+}
+impl<'a> RawContainer<'a> {
+    fn parse(container: &'a [u8]) -> Result<Self, TokenReaderError> {
+        let corrupt = |detail: &str| TokenReaderError::ContainerCorrupt(detail.to_string());
+        let (hash, _) = verified_digest(&mut Cursor::new(container))?;
+
+        // The footer starts at the offset stored just before the trailer.
+        let mut footer_offset = [0; 8];
+        footer_offset.copy_from_slice(&container[container.len() - 16..container.len() - 8]);
+        let body = &container[..u64::from_le_bytes(footer_offset) as usize];
+
+        let mut cursor = Cursor::new(body);
+        cursor
+            .seek(SeekFrom::Start(b"BINJS".len() as u64))
+            .map_err(TokenReaderError::ReadError)?;
+        cursor.read_varnum().map_err(TokenReaderError::ReadError)?;
+        let mut start = cursor.position() as usize;
+        let prologue = &body[..start];
+
+        let mut sections = vec![];
+        while start < body.len() {
+            let header_len = body[start..]
+                .iter()
+                .position(|byte| *byte == b']')
+                .ok_or_else(|| corrupt("Invalid section header"))?
+                + 1;
+            let header = &body[start..start + header_len];
+            if header.first() != Some(&b'[') || header == HEADER_FOOTER.as_bytes() {
+                return Err(corrupt("Invalid section header"));
+            }
+            let block_start = start + header_len;
+            let prefix_len = body[block_start..]
+                .iter()
+                .take(MAX_PREFIX_LEN)
+                .position(|byte| *byte == b';')
+                .ok_or_else(|| corrupt("Invalid compression header"))?
+                + 1;
+            cursor.set_position((block_start + prefix_len) as u64);
+            // An empty section is a lone `;`, without byte length.
+            let byte_len = if prefix_len == 1 {
+                0
+            } else {
+                cursor.read_varnum().map_err(TokenReaderError::ReadError)? as usize
+            };
+            let end = cursor.position() as usize + byte_len;
+            if end > body.len() {
+                return Err(corrupt("Section exceeds container"));
+            }
+            sections.push(RawSection {
+                header,
+                block: &body[block_start..end],
+            });
+            start = end;
+        }
+        Ok(RawContainer {
+            prologue,
+            sections,
+            hash,
+        })
+    }
+
+    /// Reassemble a container, replacing blocks with `replace`.
+    fn rebuild<F>(&self, mut replace: F) -> Result<Vec<u8>, TokenReaderError>
+    where
+        F: FnMut(&RawSection<'a>) -> Result<Option<Vec<u8>>, TokenReaderError>,
+    {
+        let mut result = self.prologue.to_vec();
+        for section in &self.sections {
+            result.extend_from_slice(section.header);
+            match replace(section)? {
+                Some(block) => result.extend_from_slice(&block),
+                None => result.extend_from_slice(section.block),
+            }
+        }
+        write_footer(&self.hash, &mut result).map_err(TokenReaderError::ReadError)?;
+        Ok(result)
+    }
+}
+
+/// The name of the file holding `block`.
+fn name_of(block: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(block);
+    hasher
+        .result()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Split `container` into a manifest and the sections it references,
+/// as `(name, contents)`.
+pub fn split_sections(
+    container: &[u8],
+) -> Result<(Vec<u8>, Vec<(String, Vec<u8>)>), TokenReaderError> {
+    let container = RawContainer::parse(container)?;
+    let mut stored = vec![];
+    let manifest = container.rebuild(|section| {
+        if !section.is_stored() {
+            return Ok(None);
+        }
+        let name = name_of(section.block);
+        let mut block = PREFIX_REF.to_vec();
+        block
+            .write_varnum(name.len() as u32)
+            .map_err(TokenReaderError::ReadError)?;
+        block.extend_from_slice(name.as_bytes());
+        stored.push((name, section.block.to_vec()));
+        Ok(Some(block))
+    })?;
+    Ok((manifest, stored))
+}
+
+/// Split `container` into a manifest and the chunks of the sections it
+/// references, cut by `chunker`, as `(name, contents)`.
+///
+/// A chunk appearing several times is returned each time.
+///
+/// Fails with `TokenReaderError::BadCompression` if a section to store is
+/// compressed, i.e. not stored with `Compression::Identity`.
+pub fn split_chunks(
+    container: &[u8],
+    chunker: &Chunker,
+) -> Result<(Vec<u8>, Vec<(String, Vec<u8>)>), TokenReaderError> {
+    let container = RawContainer::parse(container)?;
+    let mut stored = vec![];
+    let manifest = container.rebuild(|section| {
+        if !section.is_stored() {
+            return Ok(None);
+        }
+        match Compression::sniff(section.block) {
+            Some((Compression::Identity, _)) => {}
+            other => {
+                return Err(TokenReaderError::BadCompression(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "Cannot chunk section {} compressed with {:?}, chunks require uncompressed sections",
+                        String::from_utf8_lossy(section.header),
+                        other.map(|(compression, _)| compression)
+                    ),
+                )));
+            }
+        }
+        let chunks = chunker.chunks(section.block);
+        let mut list = vec![];
+        list.write_varnum(chunks.len() as u32)
+            .map_err(TokenReaderError::ReadError)?;
+        for chunk in chunks {
+            let name = name_of(chunk);
+            list.write_varnum(name.len() as u32)
+                .map_err(TokenReaderError::ReadError)?;
+            list.extend_from_slice(name.as_bytes());
+            stored.push((name, chunk.to_vec()));
+        }
+        let mut block = PREFIX_CHUNKS.to_vec();
+        block
+            .write_varnum(list.len() as u32)
+            .map_err(TokenReaderError::ReadError)?;
+        block.extend_from_slice(&list);
+        Ok(Some(block))
+    })?;
+    Ok((manifest, stored))
+}
+
+/// Write `stored` to `dir`, skipping files that already exist.
+fn write_stored(stored: Vec<(String, Vec<u8>)>, dir: &Path) -> Result<(), std::io::Error> {
+    fs::create_dir_all(dir)?;
+    for (name, contents) in stored {
+        let path = dir.join(name);
+        if !path.exists() {
+            fs::File::create(path)?.write_all(&contents)?;
+        }
+    }
+    Ok(())
+}
+
+/// Split `container` into a manifest, which is returned, and sections,
+/// which are written to `dir` unless a file with the same name exists.
+pub fn store_sections(container: &[u8], dir: &Path) -> Result<Vec<u8>, std::io::Error> {
+    let (manifest, stored) = split_sections(container).map_err(Into::<std::io::Error>::into)?;
+    write_stored(stored, dir)?;
+    Ok(manifest)
+}
+
+/// Split `container` into a manifest, which is returned, and the chunks of
+/// its sections, cut by `chunker`, which are written to `dir` unless a file
+/// with the same name exists.
+///
+/// Several files may share `dir`, as long as they are resolved with
+/// `resolve_sections`.
+pub fn store_chunks(
+    container: &[u8],
+    dir: &Path,
+    chunker: &Chunker,
+) -> Result<Vec<u8>, std::io::Error> {
+    let (manifest, stored) =
+        split_chunks(container, chunker).map_err(Into::<std::io::Error>::into)?;
+    write_stored(stored, dir)?;
+    Ok(manifest)
+}
+
+/// Read the file `name` from `dir`, checking that it matches its hash.
+fn read_stored(name: &[u8], dir: &Path) -> Result<Vec<u8>, TokenReaderError> {
+    let invalid = || TokenReaderError::ContainerCorrupt("Invalid section reference".to_string());
+    let name = std::str::from_utf8(name).map_err(|_| invalid())?;
+    if name.is_empty() || !name.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let mut block = vec![];
+    fs::File::open(dir.join(name))
+        .and_then(|mut file| file.read_to_end(&mut block))
+        .map_err(TokenReaderError::ReadError)?;
+    if name_of(&block) != name {
+        return Err(TokenReaderError::ContainerCorrupt(format!(
+            "Section {} does not match its hash",
+            name
+        )));
+    }
+    Ok(block)
+}
+
+/// Rebuild a container from a manifest, reading the sections or chunks it
+/// references from `dir`.
+///
+/// Containers without references are returned unchanged.
+pub fn resolve_sections(manifest: &[u8], dir: &Path) -> Result<Vec<u8>, TokenReaderError> {
+    let container = RawContainer::parse(manifest)?;
+    container.rebuild(|section| {
+        if let Some(name) = section.reference() {
+            return read_stored(name, dir).map(Some);
+        }
+        match section.chunk_references()? {
+            None => Ok(None),
+            Some(names) => {
+                let mut block = vec![];
+                for name in names {
+                    block.extend_from_slice(&read_stored(name, dir)?);
+                }
+                Ok(Some(block))
+            }
+        }
+    })
+}
@@ -0,0 +1,91 @@
+//! Embedding a container in text, e.g. in a protocol which cannot carry
+//! binary data.
+//!
+//! Format:
+//!
+//! - the characters `"BINJS64:"`;
+//! - the container, in base64 (RFC 4648, standard alphabet, with padding).
+//!
+//! Readers ignore whitespace in the base64, e.g. line breaks added by the
+//! protocol. As the magic header of binary containers is `"BINJS"` followed
+//! by a version number, never `'6'`, both forms are told apart by their
+//! first bytes.
+
+use TokenReaderError;
+
+use std;
+use std::borrow::Cow;
+
+/// The characters starting the text form of a container.
+pub const TEXT_PREAMBLE: &[u8; 8] = b"BINJS64:";
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// The text form of a binary container.
+pub fn to_text(container: &[u8]) -> String {
+    let mut text = String::with_capacity(TEXT_PREAMBLE.len() + (container.len() + 2) / 3 * 4);
+    text.push_str(std::str::from_utf8(TEXT_PREAMBLE).unwrap());
+    for chunk in container.chunks(3) {
+        let group = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(group >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// `true` if `data` starts like the text form of a container.
+pub fn is_text(data: &[u8]) -> bool {
+    data.starts_with(TEXT_PREAMBLE)
+}
+
+/// The binary container of the text form `text`.
+pub fn from_text(text: &[u8]) -> Result<Vec<u8>, TokenReaderError> {
+    let corrupt = |detail: &str| TokenReaderError::ContainerCorrupt(detail.to_string());
+    if !is_text(text) {
+        return Err(corrupt("Missing text preamble"));
+    }
+    let digits: Vec<u8> = text[TEXT_PREAMBLE.len()..]
+        .iter()
+        .cloned()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    if digits.len() % 4 != 0 {
+        return Err(corrupt("Truncated base64"));
+    }
+    let mut container = Vec::with_capacity(digits.len() / 4 * 3);
+    for (index, quad) in digits.chunks(4).enumerate() {
+        let is_last = (index + 1) * 4 == digits.len();
+        let padding = quad.iter().rev().take_while(|&&byte| byte == b'=').count();
+        if padding > 2 || (padding > 0 && !is_last) {
+            return Err(corrupt("Invalid base64 padding"));
+        }
+        let mut group = 0u32;
+        for &byte in &quad[..4 - padding] {
+            let value = ALPHABET
+                .iter()
+                .position(|&digit| digit == byte)
+                .ok_or_else(|| corrupt("Invalid base64 digit"))?;
+            group = group << 6 | value as u32;
+        }
+        group <<= 6 * padding as u32;
+        let bytes = [(group >> 16) as u8, (group >> 8) as u8, group as u8];
+        container.extend_from_slice(&bytes[..3 - padding]);
+    }
+    Ok(container)
+}
+
+/// The binary container of `data`, decoded from the text form if needed.
+pub fn binary_form(data: &[u8]) -> Result<Cow<[u8]>, TokenReaderError> {
+    if is_text(data) {
+        Ok(Cow::Owned(from_text(data)?))
+    } else {
+        Ok(Cow::Borrowed(data))
+    }
+}
@@ -3,14 +3,21 @@ use bytes::compress::*;
 use bytes::varnum::*;
 use escaped_wtf8;
 use io::*;
+use metrics::MetricsSink;
+use multipart::diagnostics::write_diagnostics;
+use multipart::footer::HashingWriter;
+use multipart::parens::write_parens;
+use multipart::text::to_text;
 use multipart::*;
 use {CompressionTarget, TokenWriterError};
 
-use binjs_shared::{FieldName, InterfaceName, SharedString};
+use binjs_shared::{FieldName, InterfaceName, SharedString, F64};
+
+use range_encoding::{opus, CumulativeDistributionFrequency};
 
 use std;
 use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::Hash;
 use std::io::Write;
@@ -24,14 +31,90 @@ use vec_map::*;
 pub struct Targets {
     pub grammar_table: CompressionTarget,
     pub strings_table: CompressionTarget,
+    /// Used only if `Options::numbers_table` is `true`.
+    pub numbers_table: CompressionTarget,
     pub tree: CompressionTarget,
 }
 impl Targets {
+    /// Compress all sections with the same mechanism.
+    pub fn uniform(compression: Compression) -> Self {
+        Targets {
+            grammar_table: CompressionTarget::new(compression.clone()),
+            strings_table: CompressionTarget::new(compression.clone()),
+            numbers_table: CompressionTarget::new(compression.clone()),
+            tree: CompressionTarget::new(compression),
+        }
+    }
+
+    /// Compress all sections with the same mechanism, keeping the other
+    /// settings of each section, e.g. size hints.
+    pub fn with_compression(&self, compression: &Compression) -> Self {
+        Targets {
+            grammar_table: self.grammar_table.with_compression(compression.clone()),
+            strings_table: self.strings_table.with_compression(compression.clone()),
+            numbers_table: self.numbers_table.with_compression(compression.clone()),
+            tree: self.tree.with_compression(compression.clone()),
+        }
+    }
+
+    /// Leave the tree uncompressed and compress the other sections with brotli.
+    ///
+    /// The tree is generally compact already, so compressing it costs a lot
+    /// of CPU for little gain, while the size of the file is dominated by
+    /// the strings table.
+    pub fn fast_tree() -> Self {
+        Targets {
+            tree: CompressionTarget::new(Compression::Identity),
+            ..Targets::uniform(Compression::Brotli)
+        }
+    }
+
     pub fn reset(&mut self) {
         self.grammar_table.reset();
         self.strings_table.reset();
+        self.numbers_table.reset();
         self.tree.reset();
     }
+
+    /// Report the time spent compressing each section to `sink`.
+    pub fn set_metrics(&mut self, sink: Option<Rc<dyn MetricsSink>>) {
+        self.grammar_table.set_metrics(sink.clone());
+        self.strings_table.set_metrics(sink.clone());
+        self.numbers_table.set_metrics(sink.clone());
+        self.tree.set_metrics(sink);
+    }
+
+    /// Store sections that cannot be compressed uncompressed, rather than failing.
+    pub fn set_fallback_on_codec_error(&mut self, fallback: bool) {
+        self.grammar_table.set_fallback_on_codec_error(fallback);
+        self.strings_table.set_fallback_on_codec_error(fallback);
+        self.numbers_table.set_fallback_on_codec_error(fallback);
+        self.tree.set_fallback_on_codec_error(fallback);
+    }
+
+    /// Store the decompressed byte length of each section.
+    pub fn set_size_hint(&mut self, size_hint: bool) {
+        self.grammar_table.set_size_hint(size_hint);
+        self.strings_table.set_size_hint(size_hint);
+        self.numbers_table.set_size_hint(size_hint);
+        self.tree.set_size_hint(size_hint);
+    }
+
+    /// Write empty sections as a single byte.
+    pub fn set_empty_marker(&mut self, empty_marker: bool) {
+        self.grammar_table.set_empty_marker(empty_marker);
+        self.strings_table.set_empty_marker(empty_marker);
+        self.numbers_table.set_empty_marker(empty_marker);
+        self.tree.set_empty_marker(empty_marker);
+    }
+
+    /// Compress brotli sections at `quality`, from `0` to `11`.
+    pub fn set_brotli_quality(&mut self, quality: u32) {
+        self.grammar_table.set_brotli_quality(quality);
+        self.strings_table.set_brotli_quality(quality);
+        self.numbers_table.set_brotli_quality(quality);
+        self.tree.set_brotli_quality(quality);
+    }
 }
 
 /// A value that may be serialized to bytes, optionally compressed.
@@ -98,6 +181,19 @@ impl Serializable for Option<SharedString> {
     }
 }
 
+/// A `Float | null` is serialized as:
+/// - a low-endian IEEE764 64-bit floating point value (8 bytes).
+///
+/// With the following special case used to represent the null float:
+/// - a signalling NaN (8 bytes).
+impl Serializable for Option<F64> {
+    fn write<W: Write>(&self, out: &mut W) -> Result<usize, std::io::Error> {
+        let bytes = bytes::float::bytes_of_float(self.map(Into::<f64>::into));
+        out.write_all(&bytes)?;
+        Ok(bytes.len())
+    }
+}
+
 /// An entry in an WriterTable.
 ///
 /// This entry tracks the number of instances of the entry used in the table.
@@ -109,6 +205,11 @@ where
     /// Number of instances of this entry around.
     instances: RefCell<u32>,
 
+    /// Number of instances of this entry across a corpus, if known.
+    ///
+    /// Takes precedence over `instances` when ordering the table.
+    global_instances: usize,
+
     /// The actual data.
     data: T,
 
@@ -122,6 +223,7 @@ where
     fn new(data: T) -> Self {
         TableEntry {
             instances: RefCell::new(1),
+            global_instances: 0,
             index: TableIndex::new(&format!("{:?}", data)),
             data,
         }
@@ -149,7 +251,8 @@ where
     T: Clone + std::fmt::Debug + Ord,
 {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        u32::cmp(&*other.instances.borrow(), &*self.instances.borrow())
+        usize::cmp(&other.global_instances, &self.global_instances)
+            .then_with(|| u32::cmp(&*other.instances.borrow(), &*self.instances.borrow()))
             .then_with(|| self.data.cmp(&other.data))
     }
 }
@@ -295,6 +398,14 @@ enum UnresolvedTreeNode {
     /// An index into the table of strings.
     UnresolvedStringIndex(TableIndex<Option<SharedString>>),
 
+    /// A string that is stored in the table of strings if it has been
+    /// interned by the time the tree is written, or inline otherwise,
+    /// along with its WTF-8 bytes.
+    UnresolvedFlaggedString(TableIndex<Option<SharedString>>, Vec<u8>),
+
+    /// An index into the table of numbers.
+    UnresolvedNumberIndex(TableIndex<Option<F64>>),
+
     /// An index into the table of nodes.
     UnresolvedNodeIndex(TableIndex<NodeDescription>),
 
@@ -311,6 +422,82 @@ struct UnresolvedTree {
 enum ResolvedTree {
     Tuple(Vec<ResolvedTree>),
     Encoded(Vec<u8>),
+
+    /// A tagged tuple, along with its index in the grammar table,
+    /// used to attribute bytes to node kinds.
+    Tagged(u32, Box<ResolvedTree>),
+
+    /// The contents of a lazy field, along with the id of these contents
+    /// (see `Options::dedup_bodies`).
+    Body(u32, Box<ResolvedTree>),
+}
+
+/// The contents of lazy fields, to write identical contents once (see
+/// `Options::dedup_bodies`).
+#[derive(Default)]
+struct Bodies {
+    /// The id of each distinct contents, by key (see `UnresolvedTree::hash_bodies`).
+    ids: HashMap<Vec<u8>, u32>,
+
+    /// For each lazy field whose contents may be replaced, in pre-order, the
+    /// id of its contents and the number of such lazy fields they contain.
+    fields: Vec<(u32, u32)>,
+
+    /// The index in `fields` of the next lazy field to resolve.
+    next_field: usize,
+
+    /// For each id, `true` if the contents have been resolved already.
+    resolved: Vec<bool>,
+
+    /// For each id, the index of the contents in the table of bodies, if they
+    /// are referenced.
+    table_indices: Vec<Option<u32>>,
+
+    /// The number of entries in the table of bodies.
+    table_len: u32,
+}
+impl Bodies {
+    /// Assign an id to the contents of each lazy field of `root` which may be
+    /// replaced by a reference.
+    fn new(root: &UnresolvedTree) -> Self {
+        let mut bodies = Bodies::default();
+        root.hash_bodies(&mut bodies, &mut vec![]);
+        bodies.resolved = vec![false; bodies.ids.len()];
+        bodies.table_indices = vec![None; bodies.ids.len()];
+        bodies
+    }
+
+    /// The id of contents identified by `key`.
+    fn id(&mut self, key: Vec<u8>) -> u32 {
+        let next_id = self.ids.len() as u32;
+        *self.ids.entry(key).or_insert(next_id)
+    }
+
+    /// Move to the next lazy field whose contents may be replaced, returning
+    /// the id of its contents, along with their index in the table of bodies
+    /// if identical contents were resolved before.
+    ///
+    /// Only contents referenced at least once are entered in the table.
+    fn next_field(&mut self) -> (u32, Option<u32>) {
+        let (id, nested) = self.fields[self.next_field];
+        self.next_field += 1;
+        if !self.resolved[id as usize] {
+            self.resolved[id as usize] = true;
+            return (id, None);
+        }
+        // Replaced contents are not resolved, nor the lazy fields they contain.
+        self.next_field += nested as usize;
+        let table_index = match self.table_indices[id as usize] {
+            Some(table_index) => table_index,
+            None => {
+                let table_index = self.table_len;
+                self.table_len += 1;
+                self.table_indices[id as usize] = Some(table_index);
+                table_index
+            }
+        };
+        (id, Some(table_index))
+    }
 }
 
 impl ResolvedTree {
@@ -327,12 +514,301 @@ impl ResolvedTree {
                 }
                 Ok(total)
             }
+            ResolvedTree::Tagged(_, ref tree) | ResolvedTree::Body(_, ref tree) => tree.write(out),
+        }
+    }
+
+    /// Find the position and byte length of each referenced `Body`, by index
+    /// in the table of bodies, for a tree starting at `start` in pre-order.
+    ///
+    /// Returns the byte length of the tree.
+    fn locate_bodies(
+        &self,
+        start: usize,
+        bodies: &Bodies,
+        positions: &mut [(usize, usize)],
+    ) -> usize {
+        match *self {
+            ResolvedTree::Encoded(ref buf) => buf.len(),
+            ResolvedTree::Tuple(ref items) => items.iter().fold(0, |byte_len, item| {
+                byte_len + item.locate_bodies(start + byte_len, bodies, positions)
+            }),
+            ResolvedTree::Tagged(_, ref tree) => tree.locate_bodies(start, bodies, positions),
+            ResolvedTree::Body(id, ref tree) => {
+                let byte_len = tree.locate_bodies(start, bodies, positions);
+                if let Some(table_index) = bodies.table_indices[id as usize] {
+                    positions[table_index as usize] = (start, byte_len);
+                }
+                byte_len
+            }
+        }
+    }
+
+    /// Write the tree in breadth-first order, as a table of segments
+    /// followed by their bytes (see `TreeOrder::BreadthFirst`).
+    fn write_breadth_first<W: Write>(&self, out: &mut W) -> Result<usize, std::io::Error> {
+        let mut segments = vec![];
+        let mut queue = VecDeque::new();
+        queue.push_back(self);
+        while let Some(tree) = queue.pop_front() {
+            let mut bytes = vec![];
+            let mut children = vec![];
+            tree.write_segment(&mut bytes, &mut children);
+            segments.push((bytes, children.len() as u32));
+            queue.extend(children);
+        }
+
+        let mut total = out.write_varnum(segments.len() as u32)?;
+        for &(ref bytes, number_of_children) in &segments {
+            total += out.write_varnum(bytes.len() as u32)?;
+            total += out.write_varnum(number_of_children)?;
+        }
+        for &(ref bytes, _) in &segments {
+            out.write_all(bytes)?;
+            total += bytes.len();
+        }
+        Ok(total)
+    }
+
+    /// Write the bytes of this tree that are outside of any tagged tuple,
+    /// collecting the contents of the outermost tagged tuples, in order.
+    fn write_segment<'a>(&'a self, out: &mut Vec<u8>, children: &mut Vec<&'a ResolvedTree>) {
+        match *self {
+            ResolvedTree::Encoded(ref buf) => out.extend_from_slice(buf),
+            ResolvedTree::Tuple(ref items) => {
+                for item in items {
+                    item.write_segment(out, children);
+                }
+            }
+            ResolvedTree::Tagged(_, ref tree) => children.push(tree),
+            ResolvedTree::Body(_, ref tree) => tree.write_segment(out, children),
+        }
+    }
+
+    /// Count the occurrences of each byte value, per index of the innermost
+    /// tagged tuple containing it, `None` for bytes outside of any tagged tuple.
+    fn count_bytes(&self, owner: Option<u32>, counts: &mut HashMap<Option<u32>, [usize; 256]>) {
+        match *self {
+            ResolvedTree::Encoded(ref buf) => {
+                let entry = counts.entry(owner).or_insert([0; 256]);
+                for byte in buf {
+                    entry[*byte as usize] += 1;
+                }
+            }
+            ResolvedTree::Tuple(ref items) => {
+                for item in items {
+                    item.count_bytes(owner, counts);
+                }
+            }
+            ResolvedTree::Tagged(index, ref tree) => tree.count_bytes(Some(index), counts),
+            ResolvedTree::Body(_, ref tree) => tree.count_bytes(owner, counts),
+        }
+    }
+}
+
+/// Estimate the share of the compressed tree attributable to each node kind,
+/// adding it to `per_kind_index`.
+///
+/// Each byte of the tree is attributed to the innermost tagged tuple containing
+/// it, and costs its information content, i.e. `-log2(p)` where `p` is the
+/// frequency of its value across the tree. The compressed bytes are then split
+/// between node kinds in proportion to their cost. This is an order-0 estimate:
+/// it ignores that compressors also exploit context, e.g. repeated sequences.
+fn attribute_compressed_bytes(
+    tree: &ResolvedTree,
+    compressed_bytes: usize,
+    per_kind_index: &mut VecMap<NodeStatistics>,
+) {
+    let mut counts = HashMap::new();
+    tree.count_bytes(None, &mut counts);
+
+    let mut totals = [0; 256];
+    for bytes in counts.values() {
+        for (total, count) in totals.iter_mut().zip(bytes.iter()) {
+            *total += *count;
+        }
+    }
+    let total_bytes: usize = totals.iter().sum();
+    let costs: Vec<_> = counts
+        .iter()
+        .map(|(owner, bytes)| {
+            let cost: f64 = bytes
+                .iter()
+                .zip(totals.iter())
+                .filter(|&(count, _)| *count != 0)
+                .map(|(count, total)| {
+                    (*count as f64) * ((total_bytes as f64) / (*total as f64)).log2()
+                })
+                .sum();
+            let byte_len: usize = bytes.iter().sum();
+            (*owner, cost, byte_len)
+        })
+        .collect();
+
+    // If all bytes have the same value, they carry no information: fall back
+    // to their number.
+    let total_cost: f64 = costs.iter().map(|&(_, cost, _)| cost).sum();
+    for (owner, cost, byte_len) in costs {
+        let share = if total_cost > 0. {
+            cost / total_cost
+        } else {
+            (byte_len as f64) / (total_bytes as f64)
+        };
+        if let Some(stats) = owner.and_then(|index| per_kind_index.get_mut(index as usize)) {
+            stats.compressed_bytes += ((compressed_bytes as f64) * share).round() as usize;
+        }
+    }
+}
+
+/// Attribute the bytes of the `[NODE-INDICES]` section to node kinds,
+/// adding them to `per_kind_index`, as the indices are not in the tree.
+///
+/// The bytes are split between node kinds in proportion to the information
+/// content of their indices, i.e. `-log2(p)` where `p` is the frequency of
+/// the kind, which is what the range coder spends on each index.
+fn attribute_node_indices(
+    node_indices: &[u32],
+    section_bytes: usize,
+    per_kind_index: &mut VecMap<NodeStatistics>,
+) {
+    let mut counts = HashMap::new();
+    for index in node_indices {
+        *counts.entry(*index).or_insert(0usize) += 1;
+    }
+    let total = node_indices.len() as f64;
+    let costs: Vec<_> = counts
+        .iter()
+        .map(|(index, count)| (*index, (*count as f64) * (total / (*count as f64)).log2()))
+        .collect();
+
+    // With a single kind, indices carry no information: fall back to their number.
+    let total_cost: f64 = costs.iter().map(|&(_, cost)| cost).sum();
+    for (index, cost) in costs {
+        let share = if total_cost > 0. {
+            cost / total_cost
+        } else {
+            (counts[&index] as f64) / total
+        };
+        if let Some(stats) = per_kind_index.get_mut(index as usize) {
+            let bytes = ((section_bytes as f64) * share).round() as usize;
+            stats.own_bytes += bytes;
+            stats.shallow_bytes += bytes;
+            stats.total_bytes += bytes;
+            // Range-encoded bytes are not compressed any further.
+            stats.compressed_bytes += bytes;
         }
     }
 }
 
 impl UnresolvedTree {
-    fn resolve(self, stats: &mut Statistics) -> (u32, ResolvedTree) {
+    /// `true` if this tree, the contents of a lazy field, may be replaced by
+    /// a reference to identical contents.
+    ///
+    /// Only contents starting with a tagged tuple are replaced, as readers
+    /// resume after a reference once that tuple is read.
+    fn is_body(&self) -> bool {
+        match self.data {
+            UnresolvedTreeNode::Tuple(ref items) if items.len() == 1 => match items[0].nature {
+                Nature::TaggedTuple(_) => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Assign an id to the contents of each lazy field which may be replaced
+    /// by a reference, identical contents sharing an id, appending the bytes
+    /// identifying this tree to `key`.
+    ///
+    /// Contents are identified by their bytes outside of nested lazy fields
+    /// and by the ids of the contents of these, so each node is visited once.
+    fn hash_bodies(&self, bodies: &mut Bodies, key: &mut Vec<u8>) {
+        use self::UnresolvedTreeNode::*;
+        // Writing to a `Vec` can't fail.
+        match self.data {
+            UnresolvedStringIndex(ref index) => {
+                key.push(0);
+                key.write_varnum(
+                    index
+                        .index()
+                        .expect("String index should have been resolved by now."),
+                )
+                .unwrap();
+            }
+            UnresolvedFlaggedString(ref index, ref bytes) => {
+                key.push(1);
+                match index.index() {
+                    Some(index) => {
+                        key.write_varnum(index << 1).unwrap();
+                    }
+                    None => {
+                        key.write_varnum(((bytes.len() as u32) << 1) | 1).unwrap();
+                        key.extend_from_slice(bytes);
+                    }
+                }
+            }
+            UnresolvedNumberIndex(ref index) => {
+                key.push(2);
+                key.write_varnum(
+                    index
+                        .index()
+                        .expect("Number index should have been resolved by now."),
+                )
+                .unwrap();
+            }
+            UnresolvedNodeIndex(ref index) => {
+                key.push(3);
+                key.write_varnum(
+                    index
+                        .index()
+                        .expect("Node index should have been resolved by now."),
+                )
+                .unwrap();
+            }
+            UnresolvedOffset(None) => {
+                panic!("UnresolvedOffset should have children");
+            }
+            UnresolvedOffset(Some(ref child)) if child.is_body() => {
+                let field = bodies.fields.len();
+                bodies.fields.push((0, 0));
+                let mut contents = vec![];
+                child.hash_bodies(bodies, &mut contents);
+                let id = bodies.id(contents);
+                let nested = bodies.fields.len() - field - 1;
+                bodies.fields[field] = (id, nested as u32);
+                key.push(4);
+                key.write_varnum(id).unwrap();
+            }
+            UnresolvedOffset(Some(ref child)) => {
+                key.push(5);
+                child.hash_bodies(bodies, key);
+            }
+            Tuple(ref subtrees) => {
+                key.push(6);
+                key.write_varnum(subtrees.len() as u32).unwrap();
+                for tree in subtrees {
+                    tree.hash_bodies(bodies, key);
+                }
+            }
+            Encoded(ref vec) => {
+                key.push(7);
+                key.write_varnum(vec.len() as u32).unwrap();
+                key.extend_from_slice(vec);
+            }
+        }
+    }
+
+    /// Resolve the tree, collecting the indices of tagged tuples into
+    /// `node_indices`, in pre-order, rather than writing them, if specified.
+    ///
+    /// If `bodies` is specified, contents of lazy fields identical to contents
+    /// resolved before are replaced by references (see `hash_bodies`).
+    fn resolve(
+        self,
+        stats: &mut Statistics,
+        node_indices: &mut Option<Vec<u32>>,
+        bodies: &mut Option<Bodies>,
+    ) -> (u32, ResolvedTree) {
         use self::UnresolvedTreeNode::*;
         let (total_bytes, own_bytes, tree) = match self.data {
             UnresolvedStringIndex(index) => {
@@ -344,34 +820,83 @@ impl UnresolvedTree {
 
                 (byte_len as u32, byte_len as u32, ResolvedTree::Encoded(buf))
             }
-            UnresolvedNodeIndex(index) => {
-                debug!(target: "multipart", "Rewriting node '{}'", index.description);
+            UnresolvedFlaggedString(index, bytes) => {
+                let mut buf = Vec::with_capacity(4);
+                match index.index() {
+                    Some(index) => {
+                        buf.write_varnum(index << 1).unwrap(); // This operation can't fail.
+                    }
+                    None => {
+                        buf.write_varnum(((bytes.len() as u32) << 1) | 1).unwrap(); // This operation can't fail.
+                        buf.extend_from_slice(&bytes);
+                    }
+                }
+                let byte_len = buf.len() as u32;
 
+                (byte_len, byte_len, ResolvedTree::Encoded(buf))
+            }
+            UnresolvedNumberIndex(index) => {
                 let index = index
                     .index()
-                    .expect("Node index should have been resolved by now.");
+                    .expect("Number index should have been resolved by now.");
                 let mut buf = Vec::with_capacity(4);
                 let byte_len: usize = buf.write_varnum(index).unwrap(); // This operation can't fail.
 
                 (byte_len as u32, byte_len as u32, ResolvedTree::Encoded(buf))
             }
+            UnresolvedNodeIndex(index) => {
+                debug!(target: "multipart", "Rewriting node '{}'", index.description);
+
+                let index = index
+                    .index()
+                    .expect("Node index should have been resolved by now.");
+                if let Some(ref mut node_indices) = *node_indices {
+                    node_indices.push(index);
+                    (0, 0, ResolvedTree::Encoded(vec![]))
+                } else {
+                    let mut buf = Vec::with_capacity(4);
+                    let byte_len: usize = buf.write_varnum(index).unwrap(); // This operation can't fail.
+
+                    (byte_len as u32, byte_len as u32, ResolvedTree::Encoded(buf))
+                }
+            }
             UnresolvedOffset(None) => {
                 panic!("UnresolvedOffset should have children");
             }
             UnresolvedOffset(Some(child)) => {
-                let (sub_byte_len, sub_resolved) = child.resolve(stats);
+                let body = match *bodies {
+                    Some(ref mut bodies) if child.is_body() => Some(bodies.next_field()),
+                    _ => None,
+                };
                 let mut buf = Vec::with_capacity(4);
-                let offset_byte_len = buf.write_varnum(sub_byte_len).unwrap(); // This operation can't fail.
-                let offset_resolved = ResolvedTree::Encoded(buf);
-
-                (
-                    sub_byte_len + offset_byte_len as u32,
-                    offset_byte_len as u32,
-                    ResolvedTree::Tuple(vec![
-                        offset_resolved,
-                        ResolvedTree::Tuple(vec![sub_resolved]),
-                    ]),
-                )
+                match body {
+                    Some((_, Some(table_index))) => {
+                        // A byte length of 0, which no contents have, then the
+                        // index in the table of bodies.
+                        buf.write_varnum(0).unwrap(); // This operation can't fail.
+                        buf.write_varnum(table_index).unwrap(); // This operation can't fail.
+                        let byte_len = buf.len() as u32;
+
+                        (byte_len, byte_len, ResolvedTree::Encoded(buf))
+                    }
+                    _ => {
+                        let (sub_byte_len, sub_resolved) =
+                            child.resolve(stats, node_indices, bodies);
+                        let contents = ResolvedTree::Tuple(vec![sub_resolved]);
+                        let offset_byte_len = buf.write_varnum(sub_byte_len).unwrap(); // This operation can't fail.
+                        let offset_resolved = ResolvedTree::Encoded(buf);
+                        let contents = match body {
+                            Some((id, None)) => ResolvedTree::Body(id, Box::new(contents)),
+                            _ => contents,
+                        };
+
+                        (
+                            sub_byte_len + offset_byte_len as u32,
+                            offset_byte_len as u32,
+                            ResolvedTree::Tuple(vec![offset_resolved, contents]),
+                        )
+                    }
+                }
             }
             Tuple(mut subtrees) => {
                 let mut byte_len = 0;
@@ -383,7 +908,7 @@ impl UnresolvedTree {
                             std::rc::Rc::strong_count(&e)
                         )
                     });
-                    let (sub_byte_len, sub_resolved) = tree.resolve(stats);
+                    let (sub_byte_len, sub_resolved) = tree.resolve(stats, node_indices, bodies);
                     byte_len += sub_byte_len;
                     resolved.push(sub_resolved);
                 }
@@ -401,6 +926,14 @@ impl UnresolvedTree {
             }
         };
 
+        let tree = match self.nature {
+            Nature::TaggedTuple(ref index) => {
+                let key = index.index().expect("TableIndex hasn't been resolved");
+                ResolvedTree::Tagged(key, Box::new(tree))
+            }
+            _ => tree,
+        };
+
         let total = total_bytes as usize;
         let own = own_bytes as usize;
         match self.nature {
@@ -437,6 +970,7 @@ impl UnresolvedTree {
                             shallow_bytes: own,
                             total_bytes: total,
                             own_bytes: own,
+                            compressed_bytes: 0,
                         });
                     }
                 }
@@ -466,10 +1000,15 @@ impl UnresolvedTree {
                             shallow_bytes: own,
                             total_bytes: total,
                             own_bytes: own,
+                            compressed_bytes: 0,
                         });
                     }
                 }
             }
+            Nature::PresentFields => {
+                stats.tagged_header.own_bytes += own;
+                stats.tagged_header.total_bytes += total;
+            }
             Nature::List => {
                 stats.list.entries += 1;
                 stats.list.total_bytes += total;
@@ -522,10 +1061,18 @@ enum Nature {
     String(TableIndex<Option<SharedString>>),
     /// Internal data representing a number of bytes.
     Offset,
+    /// The fields of a tagged tuple that are present (see `Options::omit_defaults`).
+    PresentFields,
 }
 
 #[derive(Clone)]
-pub struct Tree(Rc<UnresolvedTree>);
+pub struct Tree {
+    tree: Rc<UnresolvedTree>,
+
+    /// If `true`, this is the default value of its field, i.e. `false`, a
+    /// null string or number, or an empty list (see `Options::omit_defaults`).
+    is_default: bool,
+}
 
 #[derive(Debug)]
 struct TableIndex<T> {
@@ -569,79 +1116,312 @@ impl<T> Serializable for TableIndex<T> {
 }
 
 impl TreeTokenWriter {
-    pub fn new(mut targets: Targets) -> Self {
+    pub fn new(targets: Targets) -> Self {
+        Self::with_options(targets, Options::default())
+    }
+
+    pub fn with_options(mut targets: Targets, options: Options) -> Self {
         targets.reset();
+        targets.set_fallback_on_codec_error(options.fallback_on_codec_error);
+        targets.set_size_hint(options.size_hints);
+        targets.set_empty_marker(options.empty_section_marker);
         TreeTokenWriter {
             grammar_table: WriterTable::new(),
             strings_table: WriterTable::new(),
+            numbers_table: WriterTable::new(),
             root: None,
-            data: Vec::with_capacity(1024),
             targets,
+            options,
             statistics: Statistics::default(),
         }
     }
 
     fn register(&mut self, data: UnresolvedTree) -> Tree {
-        let result = Rc::new(data);
-        self.root = Some(Tree(result.clone()));
-        Tree(result)
+        self.register_value(data, false)
+    }
+
+    /// As `register`, for a value that is the default value of its field
+    /// if `is_default` is `true`.
+    fn register_value(&mut self, data: UnresolvedTree, is_default: bool) -> Tree {
+        let tree = Tree {
+            tree: Rc::new(data),
+            is_default,
+        };
+        self.root = Some(tree.clone());
+        tree
+    }
+
+    pub fn done(self) -> Result<Box<[u8]>, TokenWriterError> {
+        let mut data = Vec::with_capacity(1024);
+        self.done_to(&mut data)?;
+        Ok(data.into_boxed_slice())
+    }
+
+    /// Write the container to `sink`, section by section, as they are produced,
+    /// rather than assembling it in memory first.
+    ///
+    /// Short writes are continued, so `sink` may be a slow stream, e.g. a
+    /// socket. Errors of `sink` are returned as `TokenWriterError::WriteError`,
+    /// including `WouldBlock` if it is non-blocking, rather than retried.
+    ///
+    /// Returns the number of bytes written.
+    ///
+    /// With `Options::text`, the container is assembled in memory first, to
+    /// be encoded.
+    pub fn done_to<W: Write>(mut self, mut sink: W) -> Result<usize, TokenWriterError> {
+        if self.options.text {
+            self.options.text = false;
+            let max_output_bytes = self.options.max_output_bytes;
+            let mut container = Vec::with_capacity(1024);
+            self.done_to(&mut container)?;
+            let text = to_text(&container);
+            if let Some(max) = max_output_bytes {
+                if text.len() > max {
+                    return Err(TokenWriterError::OutputBudgetExceeded {
+                        max,
+                        projected: text.len(),
+                    });
+                }
+            }
+            sink.write_all(text.as_bytes())
+                .map_err(TokenWriterError::WriteError)?;
+            return Ok(text.len());
+        }
+        let mut out = HashingWriter::new(&self.options.container_hash, sink);
+        if let Err(err) = self.write_sections(&mut out) {
+            match err {
+                // Expected failures rather than bugs, not worth a warning.
+                TokenWriterError::Cancelled | TokenWriterError::OutputBudgetExceeded { .. } => {
+                    out.abandon();
+                }
+                _ => {}
+            }
+            return Err(err);
+        }
+
+        // Write the footer, hashing everything written so far.
+        let (_, written) = out.finish().map_err(TokenWriterError::WriteError)?;
+
+        self.statistics.number_of_files = 1;
+        self.statistics.compressed_bytes = written;
+        self.statistics.uncompressed_bytes +=
+            self.statistics.grammar_table.compression.before_bytes
+                + self.statistics.strings_table.compression.before_bytes
+                + self.statistics.numbers_table.compression.before_bytes
+                + self.statistics.tree.compression.before_bytes;
+        if let Some(ref statistics) = self.options.statistics {
+            *statistics.borrow_mut() += self.statistics;
+        }
+        Ok(written)
     }
 
-    pub fn done(mut self) -> Result<Box<[u8]>, TokenWriterError> {
+    /// Implementation of `done_to`, writing everything but the footer.
+    fn write_sections<W: Write>(
+        &mut self,
+        out: &mut HashingWriter<W>,
+    ) -> Result<(), TokenWriterError> {
+        // Stop between sections if cancelled, see `Options::cancellation`.
+        let cancellation = self.options.cancellation.clone().unwrap_or_default();
+        cancellation.check(TokenWriterError::Cancelled)?;
+
         const MAGIC_HEADER: &[u8; 5] = b"BINJS";
         // Write header to byte stream
-        self.data
-            .write_all(MAGIC_HEADER)
+        out.write_all(MAGIC_HEADER)
             .map_err(TokenWriterError::WriteError)?;
         self.statistics.uncompressed_bytes += MAGIC_HEADER.len();
 
-        const FORMAT_VERSION: u32 = 1;
-        self.data
-            .write_varnum(FORMAT_VERSION)
+        out.write_varnum(FORMAT_VERSION)
             .map_err(TokenWriterError::WriteError)?;
         self.statistics.uncompressed_bytes += std::mem::size_of_val(&FORMAT_VERSION);
 
+        cancellation.check(TokenWriterError::Cancelled)?;
+
+        // Write metadata to byte stream, if any. Never compressed, whatever
+        // the targets, so that `Metadata::read` needs no decompressor.
+        let metadata = &self.options.metadata;
+        if !metadata.is_empty() {
+            let mut uncompressed = Vec::with_capacity(256);
+            self.statistics.uncompressed_bytes += metadata
+                .write(&mut uncompressed)
+                .map_err(TokenWriterError::WriteError)?;
+            if let Some(ref on_section) = self.options.on_section {
+                (on_section.0)(HEADER_METADATA, &uncompressed);
+            }
+            write_identity_section(out, &self.options, HEADER_METADATA, &uncompressed)?;
+        }
+
+        cancellation.check(TokenWriterError::Cancelled)?;
+
+        // Write diagnostics to byte stream, if any.
+        if !self.options.diagnostics.is_empty() {
+            let mut uncompressed = Vec::with_capacity(256);
+            self.statistics.uncompressed_bytes +=
+                write_diagnostics(&self.options.diagnostics, &mut uncompressed)
+                    .map_err(TokenWriterError::WriteError)?;
+            if let Some(ref on_section) = self.options.on_section {
+                (on_section.0)(HEADER_DIAGNOSTICS, &uncompressed);
+            }
+            write_identity_section(out, &self.options, HEADER_DIAGNOSTICS, &uncompressed)?;
+        }
+
+        cancellation.check(TokenWriterError::Cancelled)?;
+
+        // Write parenthesization hints to byte stream, if any.
+        if !metadata.parens.is_empty() {
+            let mut uncompressed = Vec::with_capacity(256);
+            self.statistics.uncompressed_bytes += write_parens(&metadata.parens, &mut uncompressed)
+                .map_err(TokenWriterError::WriteError)?;
+            if let Some(ref on_section) = self.options.on_section {
+                (on_section.0)(HEADER_PARENS, &uncompressed);
+            }
+            write_identity_section(out, &self.options, HEADER_PARENS, &uncompressed)?;
+        }
+
+        cancellation.check(TokenWriterError::Cancelled)?;
+
         // Write grammar table to byte stream.
-        self.data
-            .write_all(HEADER_GRAMMAR_TABLE.as_bytes())
-            .map_err(TokenWriterError::WriteError)?;
-        self.statistics.uncompressed_bytes += HEADER_GRAMMAR_TABLE.len();
+        let header = if self.options.omit_defaults {
+            HEADER_GRAMMAR_TABLE_PRESENT_FIELDS
+        } else {
+            HEADER_GRAMMAR_TABLE
+        };
+        self.statistics.uncompressed_bytes += header.len();
         {
-            self.grammar_table
-                .write(&mut self.targets.grammar_table)
-                .map_err(TokenWriterError::WriteError)?;
+            // With the marker, an empty table is written as an empty section.
+            let empty = self.options.empty_section_marker && self.grammar_table.map.is_empty();
+            if let Some(ref on_section) = self.options.on_section {
+                let mut uncompressed = vec![];
+                if !empty {
+                    self.grammar_table
+                        .write(&mut uncompressed)
+                        .map_err(TokenWriterError::WriteError)?;
+                }
+                (on_section.0)(header, &uncompressed);
+            }
+            if !empty {
+                self.grammar_table
+                    .write(&mut self.targets.grammar_table)
+                    .map_err(TokenWriterError::WriteError)?;
+            }
+            choose_compression(&self.options, header, &mut self.targets.grammar_table);
             let (data, compression) = self
                 .targets
                 .grammar_table
                 .done()
                 .map_err(TokenWriterError::WriteError)?;
-            self.data
-                .write_all(data.as_ref())
-                .map_err(TokenWriterError::WriteError)?;
+            write_section(out, &self.options, header, &data)?;
             self.statistics.grammar_table.entries = self.grammar_table.map.len();
             self.statistics.grammar_table.max_entries = self.grammar_table.map.len();
             self.statistics.grammar_table.compression = compression;
+            out.flush().map_err(TokenWriterError::WriteError)?;
         }
 
-        // Write strings table to byte stream.
-        self.data
-            .write_all(HEADER_STRINGS_TABLE.as_bytes())
-            .map_err(TokenWriterError::WriteError)?;
-        {
-            self.strings_table
-                .write(&mut self.targets.strings_table)
-                .map_err(TokenWriterError::WriteError)?;
+        if let Some(ref on_intern) = self.options.on_intern {
+            for (key, entry) in &self.strings_table.map {
+                if let Some(ref string) = *key {
+                    (on_intern.0)(string.as_str(), *entry.instances.borrow() as usize);
+                }
+            }
+        }
+
+        cancellation.check(TokenWriterError::Cancelled)?;
+
+        // Write strings table to byte stream or, if strings are inline, an
+        // empty section telling readers so.
+        if self.options.inline_strings {
+            write_identity_section(out, &self.options, HEADER_STRINGS_INLINE, &[])?;
+        } else {
+            // Strings used too rarely are left out of the table, hence inline.
+            let mixed = self.options.intern_min_count > 1;
+            let inline: HashMap<_, _> = if mixed {
+                let min_count = self.options.intern_min_count;
+                let (interned, inline) = self
+                    .strings_table
+                    .map
+                    .drain()
+                    .partition(|&(_, ref entry)| *entry.instances.borrow() >= min_count);
+                self.strings_table.map = interned;
+                inline
+            } else {
+                HashMap::new()
+            };
+            let header = if mixed {
+                HEADER_STRINGS_TABLE_MIXED
+            } else {
+                HEADER_STRINGS_TABLE
+            };
+            if let Some(ref frequencies) = self.options.global_string_frequencies {
+                for (key, entry) in self.strings_table.map.iter_mut() {
+                    entry.global_instances = frequencies.get(key).cloned().unwrap_or(0);
+                }
+            }
+            // With the marker, an empty table is written as an empty section.
+            let empty = self.options.empty_section_marker && self.strings_table.map.is_empty();
+            if let Some(ref on_section) = self.options.on_section {
+                let mut uncompressed = vec![];
+                if !empty {
+                    self.strings_table
+                        .write(&mut uncompressed)
+                        .map_err(TokenWriterError::WriteError)?;
+                }
+                (on_section.0)(header, &uncompressed);
+            }
+            if !empty {
+                self.strings_table
+                    .write(&mut self.targets.strings_table)
+                    .map_err(TokenWriterError::WriteError)?;
+            }
+            choose_compression(&self.options, header, &mut self.targets.strings_table);
             let (data, compression) = self
                 .targets
                 .strings_table
                 .done()
                 .map_err(TokenWriterError::WriteError)?;
-            self.data
-                .write_all(data.as_ref())
-                .map_err(TokenWriterError::WriteError)?;
+            write_section(out, &self.options, header, &data)?;
             self.statistics.strings_table.entries = self.strings_table.map.len();
             self.statistics.strings_table.max_entries = self.strings_table.map.len();
             self.statistics.strings_table.compression = compression;
+            out.flush().map_err(TokenWriterError::WriteError)?;
+
+            // Inline strings still count towards statistics.
+            self.strings_table.map.extend(inline);
+        }
+
+        cancellation.check(TokenWriterError::Cancelled)?;
+
+        // Write numbers table to byte stream.
+        if self.options.numbers_table {
+            // With the marker, an empty table is written as an empty section.
+            let empty = self.options.empty_section_marker && self.numbers_table.map.is_empty();
+            if let Some(ref on_section) = self.options.on_section {
+                let mut uncompressed = vec![];
+                if !empty {
+                    self.numbers_table
+                        .write(&mut uncompressed)
+                        .map_err(TokenWriterError::WriteError)?;
+                }
+                (on_section.0)(HEADER_NUMBERS_TABLE, &uncompressed);
+            }
+            if !empty {
+                self.numbers_table
+                    .write(&mut self.targets.numbers_table)
+                    .map_err(TokenWriterError::WriteError)?;
+            }
+            choose_compression(
+                &self.options,
+                HEADER_NUMBERS_TABLE,
+                &mut self.targets.numbers_table,
+            );
+            let (data, compression) = self
+                .targets
+                .numbers_table
+                .done()
+                .map_err(TokenWriterError::WriteError)?;
+            write_section(out, &self.options, HEADER_NUMBERS_TABLE, &data)?;
+            self.statistics.numbers_table.entries = self.numbers_table.map.len();
+            self.statistics.numbers_table.max_entries = self.numbers_table.map.len();
+            self.statistics.numbers_table.compression = compression;
+            out.flush().map_err(TokenWriterError::WriteError)?;
         }
 
         // Compute more statistics on strings.
@@ -675,43 +1455,111 @@ impl TreeTokenWriter {
             }
         }
 
+        cancellation.check(TokenWriterError::Cancelled)?;
+
         // Write tree itself to byte stream.
-        if let Some(root) = self.root {
+        if let Some(root) = self.root.take() {
             let mut tree_buf = Vec::with_capacity(2048);
-            let root = std::rc::Rc::try_unwrap(root.0).unwrap_or_else(|e| {
+            let root = std::rc::Rc::try_unwrap(root.tree).unwrap_or_else(|e| {
                 panic!(
                     "Could not unwrap tree, it still has {} consumers",
                     std::rc::Rc::strong_count(&e)
                 )
             });
-            let (_, resolved) = root.resolve(&mut self.statistics);
-            resolved
-                .write(&mut tree_buf)
-                .map_err(TokenWriterError::WriteError)?;
+            let mut node_indices = if self.options.entropy_node_indices {
+                Some(vec![])
+            } else {
+                None
+            };
+            let mut bodies = match self.options.tree_order {
+                TreeOrder::PreOrder if self.options.dedup_bodies && node_indices.is_none() => {
+                    Some(Bodies::new(&root))
+                }
+                _ => None,
+            };
+            let (_, resolved) = root.resolve(&mut self.statistics, &mut node_indices, &mut bodies);
+            match self.options.tree_order {
+                TreeOrder::PreOrder => resolved.write(&mut tree_buf),
+                TreeOrder::BreadthFirst => resolved.write_breadth_first(&mut tree_buf),
+            }
+            .map_err(TokenWriterError::WriteError)?;
 
-            self.data
-                .write_all(HEADER_TREE.as_bytes())
+            // Write node indices to byte stream, if they are not in the tree.
+            if let Some(ref node_indices) = node_indices {
+                let mut uncompressed = Vec::with_capacity(node_indices.len());
+                let section_bytes = write_node_indices(
+                    node_indices,
+                    self.grammar_table.map.len(),
+                    &mut uncompressed,
+                )
                 .map_err(TokenWriterError::WriteError)?;
+                self.statistics.uncompressed_bytes += section_bytes;
+                self.statistics.tagged_header.own_bytes += section_bytes;
+                self.statistics.tagged_header.total_bytes += section_bytes;
+                attribute_node_indices(
+                    node_indices,
+                    section_bytes,
+                    &mut self.statistics.per_kind_index,
+                );
+                if let Some(ref on_section) = self.options.on_section {
+                    (on_section.0)(HEADER_NODE_INDICES, &uncompressed);
+                }
+                // Range-encoded bytes do not compress any further.
+                write_identity_section(out, &self.options, HEADER_NODE_INDICES, &uncompressed)?;
+
+                cancellation.check(TokenWriterError::Cancelled)?;
+            }
+
+            // Write the table of bodies, if some are referenced.
+            if let Some(bodies) = bodies.filter(|bodies| bodies.table_len > 0) {
+                let mut positions = vec![(0, 0); bodies.table_len as usize];
+                resolved.locate_bodies(0, &bodies, &mut positions);
+                let mut uncompressed = Vec::with_capacity(positions.len() * 4);
+                uncompressed
+                    .write_varnum(positions.len() as u32)
+                    .map_err(TokenWriterError::WriteError)?;
+                for &(position, byte_len) in &positions {
+                    uncompressed
+                        .write_varnum(position as u32)
+                        .and_then(|_| uncompressed.write_varnum(byte_len as u32))
+                        .map_err(TokenWriterError::WriteError)?;
+                }
+                if let Some(ref on_section) = self.options.on_section {
+                    (on_section.0)(HEADER_BODIES, &uncompressed);
+                }
+                self.statistics.uncompressed_bytes += uncompressed.len();
+                write_identity_section(out, &self.options, HEADER_BODIES, &uncompressed)?;
+            }
+
+            let header = self.options.tree_order.header();
+            if let Some(ref on_section) = self.options.on_section {
+                (on_section.0)(header, &tree_buf);
+            }
             {
                 tree_buf
                     .write(&mut self.targets.tree)
                     .map_err(TokenWriterError::WriteError)?;
+                choose_compression(&self.options, header, &mut self.targets.tree);
                 let (data, compression) = self
                     .targets
                     .tree
                     .done()
                     .map_err(TokenWriterError::WriteError)?;
-                self.data
-                    .write_all(data.as_ref())
-                    .map_err(TokenWriterError::WriteError)?;
+                write_section(out, &self.options, header, &data)?;
                 self.statistics.tree.entries = 1;
                 self.statistics.tree.max_entries = 1;
+                attribute_compressed_bytes(
+                    &resolved,
+                    compression.after_bytes,
+                    &mut self.statistics.per_kind_index,
+                );
                 self.statistics.tree.compression = compression;
+                out.flush().map_err(TokenWriterError::WriteError)?;
             }
         }
 
         // Compute more statistics on nodes.
-        for (key, value) in self.grammar_table.map {
+        for (key, value) in self.grammar_table.map.drain() {
             let index = value
                 .index
                 .index
@@ -733,16 +1581,167 @@ impl TreeTokenWriter {
                 }
             }
         }
-        self.statistics.number_of_files = 1;
-        self.statistics.compressed_bytes = self.data.len();
-        self.statistics.uncompressed_bytes +=
-            self.statistics.grammar_table.compression.before_bytes
-                + self.statistics.strings_table.compression.before_bytes
-                + self.statistics.tree.compression.before_bytes;
-        Ok(self.data.clone().into_boxed_slice())
+        cancellation.check(TokenWriterError::Cancelled)
     }
 }
 
+/// Write the indices of tagged tuples into the grammar table, range-encoded
+/// with their frequencies, preceded by these frequencies.
+///
+/// Frequencies are scaled down to add up to about
+/// `MAX_NODE_INDICES_FREQUENCY`, as needed by the range encoder, and each
+/// entry of the grammar table is given some frequency, even if unused.
+fn write_node_indices<W: Write>(
+    node_indices: &[u32],
+    number_of_kinds: usize,
+    out: &mut W,
+) -> Result<usize, std::io::Error> {
+    let mut instances = vec![0u64; number_of_kinds];
+    for index in node_indices {
+        instances[*index as usize] += 1;
+    }
+    let total = std::cmp::max(node_indices.len() as u64, 1);
+    let max = MAX_NODE_INDICES_FREQUENCY as u64;
+    let frequencies: Vec<u32> = instances
+        .iter()
+        .map(|instances| {
+            let scaled = if total > max {
+                instances * max / total
+            } else {
+                *instances
+            };
+            std::cmp::max(scaled, 1) as u32
+        })
+        .collect();
+
+    let mut total_bytes = out.write_varnum(frequencies.len() as u32)?;
+    for frequency in &frequencies {
+        total_bytes += out.write_varnum(*frequency)?;
+    }
+
+    let distribution = CumulativeDistributionFrequency::new(frequencies);
+    let mut writer = opus::Writer::new(Vec::with_capacity(node_indices.len()));
+    for index in node_indices {
+        writer.symbol(*index, &distribution)?;
+    }
+    let encoded = writer.done()?;
+    out.write_all(&encoded)?;
+    total_bytes += encoded.len();
+    Ok(total_bytes)
+}
+
+/// A `[?PADDING]` section of zeros, such that `offset` plus its byte length
+/// is a multiple of `align`, or nothing if `offset` is already one.
+///
+/// The smallest padding section takes a few bytes, so it may end at a
+/// further multiple.
+fn padding_section(offset: usize, align: usize) -> Vec<u8> {
+    let section = |padding: usize| {
+        let mut section = HEADER_PADDING.as_bytes().to_vec();
+        Compression::Identity
+            .compress(&vec![0; padding], &mut section)
+            .unwrap(); // Writing to a `Vec` can't fail.
+        section
+    };
+    // The header and compression prefix, without the byte length.
+    let overhead = section(0).len() - 1;
+
+    let mut target = (align - offset % align) % align;
+    if target == 0 {
+        return vec![];
+    }
+    loop {
+        // The byte length of the padding takes between 1 and
+        // `VARNUM_MAX_BYTES_U32` bytes, so some targets cannot be reached,
+        // e.g. 127 bytes of padding take 128 bytes with their length, but
+        // 128 bytes of padding take 130 bytes.
+        for len_bytes in 1..=VARNUM_MAX_BYTES_U32 {
+            if let Some(padding) = target.checked_sub(overhead + len_bytes) {
+                let candidate = section(padding);
+                if candidate.len() == target {
+                    return candidate;
+                }
+            }
+        }
+        target += align;
+    }
+}
+
+/// Let `options.choose_compression`, if specified, pick the compression of
+/// section `header`, stored in `target`.
+fn choose_compression(options: &Options, header: &str, target: &mut CompressionTarget) {
+    if let Some(ref choose) = options.choose_compression {
+        target.choose_compression(|uncompressed| (choose.0)(header, uncompressed));
+    }
+}
+
+/// Fail if writing `section_bytes` more bytes to `out`, then the footer,
+/// would exceed `max_output_bytes`, if specified.
+///
+/// As the footer follows the last section, checking before writing each
+/// section bounds the length of the whole container.
+fn check_output_budget<W: Write>(
+    max_output_bytes: Option<usize>,
+    out: &HashingWriter<W>,
+    section_bytes: usize,
+) -> Result<(), TokenWriterError> {
+    let projected = out.bytes_written() + section_bytes + out.footer_len();
+    match max_output_bytes {
+        Some(max) if projected > max => {
+            Err(TokenWriterError::OutputBudgetExceeded { max, projected })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Write section `header`, with its compressed block `data`, within
+/// `options.max_output_bytes`.
+///
+/// If `options.align` is greater than 1, the section is preceded by a
+/// `[?PADDING]` section so that the compressed bytes of `data`, after the
+/// header, compression prefix, byte length and size hint, start at a
+/// multiple of `options.align`. The padding counts towards the budget.
+fn write_section<W: Write>(
+    out: &mut HashingWriter<W>,
+    options: &Options,
+    header: &str,
+    data: &[u8],
+) -> Result<(), TokenWriterError> {
+    let padding = if options.align > 1 {
+        let body_offset =
+            Compression::body_offset(data).expect("Compressed blocks start with a complete prefix");
+        padding_section(
+            out.bytes_written() + header.len() + body_offset,
+            options.align,
+        )
+    } else {
+        vec![]
+    };
+    check_output_budget(
+        options.max_output_bytes,
+        out,
+        padding.len() + header.len() + data.len(),
+    )?;
+    out.write_all(&padding)
+        .and_then(|_| out.write_all(header.as_bytes()))
+        .and_then(|_| out.write_all(data))
+        .map_err(TokenWriterError::WriteError)
+}
+
+/// As `write_section`, for a section stored with `Compression::Identity`.
+fn write_identity_section<W: Write>(
+    out: &mut HashingWriter<W>,
+    options: &Options,
+    header: &str,
+    uncompressed: &[u8],
+) -> Result<(), TokenWriterError> {
+    let mut data = Vec::with_capacity(uncompressed.len() + 16);
+    Compression::Identity
+        .compress(uncompressed, &mut data)
+        .map_err(TokenWriterError::WriteError)?;
+    write_section(out, options, header, &data)
+}
+
 impl TokenWriterWithTree for TreeTokenWriter {
     type Tree = Tree;
     type Data = Box<[u8]>;
@@ -752,15 +1751,34 @@ impl TokenWriterWithTree for TreeTokenWriter {
     }
 
     fn float(&mut self, value: Option<f64>) -> Result<Self::Tree, TokenWriterError> {
+        // `-0` is never canonicalized, as it is observably distinct from `0`.
+        let value = match value {
+            Some(value) if self.options.canonicalize_nan && value.is_nan() => Some(std::f64::NAN),
+            _ => value,
+        };
+        if self.options.numbers_table {
+            let index = self.numbers_table.insert(value.map(F64::from));
+            debug!(target: "multipart", "writing float {:?} => {:?}", value, index);
+            return Ok(self.register_value(
+                UnresolvedTree {
+                    data: UnresolvedTreeNode::UnresolvedNumberIndex(index),
+                    nature: Nature::Float,
+                },
+                value.is_none(),
+            ));
+        }
         let bytes: Vec<_> = bytes::float::bytes_of_float(value)
             .iter()
             .cloned()
             .collect();
         debug!(target: "multipart", "writing float {:?} => {:?}", value, bytes);
-        Ok(self.register(UnresolvedTree {
-            nature: Nature::Float,
-            data: UnresolvedTreeNode::Encoded(bytes),
-        }))
+        Ok(self.register_value(
+            UnresolvedTree {
+                nature: Nature::Float,
+                data: UnresolvedTreeNode::Encoded(bytes),
+            },
+            value.is_none(),
+        ))
     }
 
     fn unsigned_long(&mut self, value: u32) -> Result<Self::Tree, TokenWriterError> {
@@ -778,10 +1796,13 @@ impl TokenWriterWithTree for TreeTokenWriter {
     fn bool(&mut self, data: Option<bool>) -> Result<Self::Tree, TokenWriterError> {
         let bytes = bytes::bool::bytes_of_bool(data).iter().cloned().collect();
         debug!(target: "multipart", "writing bool {:?} => {:?}", data, bytes);
-        Ok(self.register(UnresolvedTree {
-            nature: Nature::Bool,
-            data: UnresolvedTreeNode::Encoded(bytes),
-        }))
+        Ok(self.register_value(
+            UnresolvedTree {
+                nature: Nature::Bool,
+                data: UnresolvedTreeNode::Encoded(bytes),
+            },
+            data == Some(false),
+        ))
     }
 
     fn offset(&mut self) -> Result<Self::Tree, TokenWriterError> {
@@ -793,23 +1814,62 @@ impl TokenWriterWithTree for TreeTokenWriter {
 
     fn string(&mut self, data: Option<&SharedString>) -> Result<Self::Tree, TokenWriterError> {
         let key = data.map(Clone::clone);
+        let is_default = data.is_none();
+        if self.options.inline_strings {
+            // Still record the string, for statistics.
+            let index = self.strings_table.insert(key.clone());
+            let mut bytes = Vec::with_capacity(16);
+            key.write(&mut bytes)
+                .map_err(TokenWriterError::WriteError)?;
+            debug!(target: "multipart", "writing string {:?} => {:?}", data, bytes);
+            return Ok(self.register_value(
+                UnresolvedTree {
+                    data: UnresolvedTreeNode::Encoded(bytes),
+                    nature: Nature::String(index),
+                },
+                is_default,
+            ));
+        }
+        if self.options.intern_min_count > 1 {
+            // Whether the string is interned is only known once all strings
+            // have been counted, so keep its bytes around.
+            let index = self.strings_table.insert(key.clone());
+            let bytes = match key {
+                None => vec![255, 0],
+                Some(ref string) => escaped_wtf8::unescape(string.as_bytes()).into_owned(),
+            };
+            debug!(target: "multipart", "writing string {:?} => {:?}", data, bytes);
+            return Ok(self.register_value(
+                UnresolvedTree {
+                    data: UnresolvedTreeNode::UnresolvedFlaggedString(index.clone(), bytes),
+                    nature: Nature::String(index),
+                },
+                is_default,
+            ));
+        }
         let index = self
             .strings_table
             .get(&key)
             .map(|entry| entry.index.clone());
 
         if let Some(index) = index {
-            return Ok(self.register(UnresolvedTree {
-                data: UnresolvedTreeNode::UnresolvedStringIndex(index.clone()),
-                nature: Nature::String(index),
-            }));
+            return Ok(self.register_value(
+                UnresolvedTree {
+                    data: UnresolvedTreeNode::UnresolvedStringIndex(index.clone()),
+                    nature: Nature::String(index),
+                },
+                is_default,
+            ));
         }
         let index = self.strings_table.insert(key);
         debug!(target: "multipart", "writing string {:?} => {:?}", data, index);
-        Ok(self.register(UnresolvedTree {
-            data: UnresolvedTreeNode::UnresolvedStringIndex(index.clone()),
-            nature: Nature::String(index),
-        }))
+        Ok(self.register_value(
+            UnresolvedTree {
+                data: UnresolvedTreeNode::UnresolvedStringIndex(index.clone()),
+                nature: Nature::String(index),
+            },
+            is_default,
+        ))
     }
     fn list(&mut self, mut children: Vec<Self::Tree>) -> Result<Self::Tree, TokenWriterError> {
         let mut items = Vec::with_capacity(children.len() + 1);
@@ -824,14 +1884,18 @@ impl TokenWriterWithTree for TreeTokenWriter {
         }));
 
         let len = children.len();
+        let is_default = len == 0;
         // Next, move in the children.
-        let children: Vec<_> = children.drain(..).map(|tree| tree.0.clone()).collect();
+        let children: Vec<_> = children.drain(..).map(|tree| tree.tree.clone()).collect();
         items.extend(children);
         debug!(target: "multipart", "writing list with {} => {} items", len, items.len());
-        Ok(self.register(UnresolvedTree {
-            data: UnresolvedTreeNode::Tuple(items),
-            nature: Nature::List,
-        }))
+        Ok(self.register_value(
+            UnresolvedTree {
+                data: UnresolvedTreeNode::Tuple(items),
+                nature: Nature::List,
+            },
+            is_default,
+        ))
     }
 
     // Tagged tuple:
@@ -839,13 +1903,17 @@ impl TokenWriterWithTree for TreeTokenWriter {
     // All tagged tuples with the same `tag` are written with the fields in the same order.
     //
     // - index in the grammar table (varnum);
-    // - for each item, in the order specified
+    // - if `Options::omit_defaults`, the bitmask of the fields that are present (varnum);
+    // - for each item, in the order specified, unless omitted
     //    - the item (see item)
     fn tagged_tuple(
         &mut self,
         name: &InterfaceName,
         children: &[(&FieldName, Self::Tree)],
     ) -> Result<Self::Tree, TokenWriterError> {
+        if let Some(ref token) = self.options.cancellation {
+            token.check(TokenWriterError::Cancelled)?;
+        }
         let data;
         let description = NodeDescription { kind: name.clone() };
         debug!(target: "multipart", "writing tagged tuple {:?} with {} children as {:?}",
@@ -876,8 +1944,16 @@ impl TokenWriterWithTree for TreeTokenWriter {
             //
             // `is_lazy_field` is used to detect the child after Nature::Offset
             let mut is_lazy_field = false;
-            for child in children.iter() {
-                let child_data = (child.1).0.clone();
+            let mut present_fields = 0;
+            for (field_index, child) in children.iter().enumerate() {
+                let child_data = (child.1).tree.clone();
+                if self.options.omit_defaults && field_index < MAX_OMITTED_FIELDS {
+                    // Lazy fields are always present, so that they can be skipped.
+                    if (child.1).is_default && !is_lazy_field {
+                        continue;
+                    }
+                    present_fields |= 1 << field_index;
+                }
                 match *child_data {
                     UnresolvedTree {
                         data: UnresolvedTreeNode::UnresolvedOffset(ref v),
@@ -920,7 +1996,19 @@ impl TokenWriterWithTree for TreeTokenWriter {
                 nature: Nature::TaggedTupleHeader(index.clone()),
             });
 
-            data = vec![prefix, Rc::new(children)];
+            data = if self.options.omit_defaults {
+                let mut encoded_present_fields = Vec::with_capacity(4);
+                encoded_present_fields
+                    .write_varnum(present_fields)
+                    .map_err(TokenWriterError::WriteError)?;
+                let present_fields = Rc::new(UnresolvedTree {
+                    data: UnresolvedTreeNode::Encoded(encoded_present_fields),
+                    nature: Nature::PresentFields,
+                });
+                vec![prefix, present_fields, Rc::new(children)]
+            } else {
+                vec![prefix, Rc::new(children)]
+            };
         }
         Ok(self.register(UnresolvedTree {
             data: UnresolvedTreeNode::Tuple(data),
@@ -937,12 +2025,15 @@ pub struct TreeTokenWriter {
     /// The strings used in the binary.
     strings_table: WriterTable<Option<SharedString>>,
 
-    root: Option<Tree>,
+    /// The numbers used in the binary, if `options.numbers_table` is `true`.
+    numbers_table: WriterTable<Option<F64>>,
 
-    data: Vec<u8>,
+    root: Option<Tree>,
 
     targets: Targets,
 
+    options: Options,
+
     statistics: Statistics,
 }
 
@@ -967,6 +2058,7 @@ impl Default for SectionStatistics {
                 before_bytes: 0,
                 after_bytes: 0,
                 algorithms: HashSet::new(),
+                input_hash: None,
             },
         }
     }
@@ -977,6 +2069,7 @@ impl AddAssign for CompressionResult {
         self.before_bytes += rhs.before_bytes;
         self.after_bytes += rhs.after_bytes;
         self.algorithms.extend(rhs.algorithms.drain());
+        self.input_hash = None;
     }
 }
 
@@ -1009,6 +2102,12 @@ pub struct NodeStatistics {
 
     /// Number of bytes used to represent the node, including all subnodes.
     pub total_bytes: usize,
+
+    /// Estimated number of bytes of the compressed tree attributable to the
+    /// node, including primitive subnodes but not tagged subnodes.
+    ///
+    /// Only available for node kinds.
+    pub compressed_bytes: usize,
 }
 
 impl AddAssign for NodeStatistics {
@@ -1020,6 +2119,7 @@ impl AddAssign for NodeStatistics {
         self.own_bytes += rhs.own_bytes;
         self.shallow_bytes += rhs.shallow_bytes;
         self.total_bytes += rhs.total_bytes;
+        self.compressed_bytes += rhs.compressed_bytes;
     }
 }
 
@@ -1027,6 +2127,7 @@ impl AddAssign for NodeStatistics {
 pub struct Statistics {
     pub grammar_table: SectionStatistics,
     pub strings_table: SectionStatistics,
+    pub numbers_table: SectionStatistics,
     pub tree: SectionStatistics,
 
     pub per_kind_index: VecMap<NodeStatistics>,
@@ -1068,6 +2169,7 @@ impl Add for Statistics {
     fn add(mut self, mut rhs: Self) -> Self {
         self.grammar_table += rhs.grammar_table;
         self.strings_table += rhs.strings_table;
+        self.numbers_table += rhs.numbers_table;
         self.tree += rhs.tree;
 
         for (key, value) in rhs.per_kind_index.drain() {
@@ -1215,6 +2317,7 @@ struct NodeAndStatistics<'a> {
     stats: &'a NodeStatistics,
     header_bytes: usize,
     total_uncompressed_bytes: usize,
+    total_compressed_bytes: usize,
     total_number_of_entries: usize,
 }
 impl<'a> Display for NodeAndStatistics<'a> {
@@ -1263,12 +2366,21 @@ impl<'a> Display for NodeAndStatistics<'a> {
                 100. * (self.stats.total_bytes as f64) / (self.total_uncompressed_bytes as f64)
             )?;
         }
+        if self.stats.compressed_bytes != 0 {
+            write!(
+                f,
+                "\t\t\tCompressed bytes (estimate): {} ({:.2}%)\n",
+                self.stats.compressed_bytes,
+                100. * (self.stats.compressed_bytes as f64) / (self.total_compressed_bytes as f64)
+            )?;
+        }
         Ok(())
     }
 }
 
 struct NodeNameAndStatistics {
     total_uncompressed_bytes: usize,
+    total_compressed_bytes: usize,
     nodes: Vec<(InterfaceName, NodeStatistics)>,
 }
 
@@ -1280,6 +2392,7 @@ impl Display for NodeNameAndStatistics {
             let for_display = NodeAndStatistics {
                 header_bytes: 0,
                 total_uncompressed_bytes: self.total_uncompressed_bytes,
+                total_compressed_bytes: self.total_compressed_bytes,
                 total_number_of_entries,
                 name: name.as_str(),
                 stats,
@@ -1370,6 +2483,8 @@ Statistics
 {section_grammar}
 \t\tStrings:
 {section_strings}
+\t\tNumbers:
+{section_numbers}
 \t\tTree:
 {section_tree}
 \tNodes:
@@ -1415,6 +2530,11 @@ Statistics
                 total_compressed_bytes: self.compressed_bytes,
                 section: &self.strings_table,
             },
+            section_numbers = SectionAndStatistics {
+                total_uncompressed_bytes: self.uncompressed_bytes,
+                total_compressed_bytes: self.compressed_bytes,
+                section: &self.numbers_table,
+            },
             section_tree = SectionAndStatistics {
                 total_uncompressed_bytes: self.uncompressed_bytes,
                 total_compressed_bytes: self.compressed_bytes,
@@ -1422,6 +2542,7 @@ Statistics
             },
             collapsed_nodes = NodeNameAndStatistics {
                 total_uncompressed_bytes: self.uncompressed_bytes,
+                total_compressed_bytes: self.compressed_bytes,
                 nodes: per_kind
             },
             token_bool = NodeAndStatistics {
@@ -1429,6 +2550,7 @@ Statistics
                 stats: &self.bool,
                 total_number_of_entries: total_number_of_tokens,
                 total_uncompressed_bytes: self.uncompressed_bytes,
+                total_compressed_bytes: self.compressed_bytes,
                 header_bytes: 0,
             },
             token_float = NodeAndStatistics {
@@ -1436,6 +2558,7 @@ Statistics
                 stats: &self.float,
                 total_number_of_entries: total_number_of_tokens,
                 total_uncompressed_bytes: self.uncompressed_bytes,
+                total_compressed_bytes: self.compressed_bytes,
                 header_bytes: 0,
             },
             token_unsigned_long = NodeAndStatistics {
@@ -1443,6 +2566,7 @@ Statistics
                 stats: &self.unsigned_long,
                 total_number_of_entries: total_number_of_tokens,
                 total_uncompressed_bytes: self.uncompressed_bytes,
+                total_compressed_bytes: self.compressed_bytes,
                 header_bytes: 0,
             },
             token_offset = NodeAndStatistics {
@@ -1450,6 +2574,7 @@ Statistics
                 stats: &self.offset,
                 total_number_of_entries: total_number_of_tokens,
                 total_uncompressed_bytes: self.uncompressed_bytes,
+                total_compressed_bytes: self.compressed_bytes,
                 header_bytes: 0,
             },
             token_string = NodeAndStatistics {
@@ -1457,6 +2582,7 @@ Statistics
                 stats: &self.string,
                 total_number_of_entries: total_number_of_tokens,
                 total_uncompressed_bytes: self.uncompressed_bytes,
+                total_compressed_bytes: self.compressed_bytes,
                 header_bytes: 0,
             },
             token_list = NodeAndStatistics {
@@ -1464,6 +2590,7 @@ Statistics
                 stats: &self.list,
                 total_number_of_entries: total_number_of_tokens,
                 total_uncompressed_bytes: self.uncompressed_bytes,
+                total_compressed_bytes: self.compressed_bytes,
                 header_bytes: self.list_header.own_bytes,
             },
             token_tagged_tuple = NodeAndStatistics {
@@ -1471,6 +2598,7 @@ Statistics
                 stats: &self.tagged_tuple,
                 total_number_of_entries: total_number_of_tokens,
                 total_uncompressed_bytes: self.uncompressed_bytes,
+                total_compressed_bytes: self.compressed_bytes,
                 header_bytes: self.tagged_header.own_bytes,
             },
         )
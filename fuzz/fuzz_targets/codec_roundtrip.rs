@@ -0,0 +1,46 @@
+//! Differential fuzzing of the codecs of `Compression::values()`.
+//!
+//! Each codec must decompress to the original bytes, hence all codecs
+//! must agree with each other. A codec-specific bug, e.g. a compressor
+//! ignoring short writes and truncating its output, shows up as a codec
+//! disagreeing with `identity`.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate binjs_io;
+
+use binjs_io::bytes::compress::Compression;
+
+/// Compress then decompress `data` with `compression`.
+fn roundtrip(compression: &Compression, data: &[u8]) -> Vec<u8> {
+    Compression::roundtrip(data, compression.clone())
+        .unwrap_or_else(|err| panic!("Could not round-trip with {:?}: {:?}", compression, err))
+}
+
+fuzz_target!(|data: &[u8]| {
+    let codecs = Compression::values();
+    let results: Vec<_> = codecs
+        .iter()
+        .map(|compression| roundtrip(compression, data))
+        .collect();
+    for (i, a) in codecs.iter().enumerate() {
+        for (j, b) in codecs.iter().enumerate().skip(i + 1) {
+            assert!(
+                results[i] == results[j],
+                "{:?} and {:?} disagree on {} bytes: {} vs. {} bytes",
+                a,
+                b,
+                data.len(),
+                results[i].len(),
+                results[j].len()
+            );
+        }
+        assert!(
+            results[i] == data,
+            "{:?} does not round-trip {} bytes",
+            a,
+            data.len()
+        );
+    }
+});
@@ -0,0 +1,307 @@
+//! Encoding many files in a row, e.g. all the files of a build.
+
+use binjs_es6::ast::Script;
+use binjs_es6::io::Encoder;
+use binjs_es6::{Enrich, EnrichError};
+use binjs_io::bytes::compress::Compression;
+use binjs_io::metrics::{MetricsSink, Phase, Stopwatch};
+use binjs_io::multipart::{inline_strings_by_default, Metadata};
+use binjs_io::{BinjsError, Format};
+
+use source::shift::{self, Shift};
+use source::SourceParser;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::rc::Rc;
+
+#[derive(Debug)]
+pub enum Error {
+    IOError(std::io::Error),
+    SourceError(shift::Error),
+    EnrichError(EnrichError),
+    EncodingError(BinjsError),
+
+    /// The encoded file exceeds `multipart::Options::max_output_bytes`,
+    /// see `encode_within_budget`.
+    SizeExceeded {
+        /// The byte length of the encoded file.
+        size: usize,
+
+        /// The budget, in bytes.
+        max_size: usize,
+    },
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Error::IOError(ref err) => write!(f, "I/O error: {}", err),
+            Error::SourceError(ref err) => write!(f, "Could not parse source: {:?}", err),
+            Error::EnrichError(ref err) => write!(f, "Could not enrich AST: {:?}", err),
+            Error::EncodingError(ref err) => write!(f, "Could not encode: {}", err),
+            Error::SizeExceeded { size, max_size } => write!(
+                f,
+                "Encoded size is {} bytes, exceeding the budget of {} bytes",
+                size, max_size
+            ),
+        }
+    }
+}
+
+/// The result of encoding a single file.
+pub struct EncodeResult {
+    /// The encoded file.
+    pub data: Box<[u8]>,
+
+    /// Byte length of the source.
+    pub source_len: usize,
+}
+
+/// An encoder for many files.
+///
+/// Encoding each file from scratch launches a parser and sets up the format,
+/// which dominates the cost of encoding small files. A `BatchEncoder` does
+/// this once, then shares the parser, the format (including its dictionary,
+/// if any) and the buffer in which sources are read across all files.
+pub struct BatchEncoder {
+    parser: Shift,
+    enricher: Enrich,
+    format: Format,
+    encoder: Encoder,
+
+    /// If specified, the destination for timing metrics.
+    metrics: Option<Rc<dyn MetricsSink>>,
+
+    /// If `true`, store strings inline in the tree for all files, rather
+    /// than only for tiny ones.
+    inline_strings: bool,
+
+    /// The source of the latest file, kept to reuse its allocation.
+    source: String,
+}
+
+impl BatchEncoder {
+    /// Launch a parser, then encode files to `format`.
+    pub fn try_new(format: Format, enricher: Enrich) -> Result<Self, Error> {
+        let parser = Shift::try_new().map_err(Error::SourceError)?;
+        Ok(Self::with_parser(parser, format, enricher))
+    }
+
+    /// Encode files to `format`, using an existing parser.
+    pub fn with_parser(parser: Shift, format: Format, enricher: Enrich) -> Self {
+        let inline_strings = match format {
+            Format::Multipart { ref options, .. } => options.inline_strings,
+            _ => false,
+        };
+        BatchEncoder {
+            parser,
+            enricher,
+            format,
+            encoder: Encoder::new(),
+            metrics: None,
+            inline_strings,
+            source: String::new(),
+        }
+    }
+
+    /// Report the time spent in each phase of encoding to `sink`, starting
+    /// with phase `Parse`, then the phases of `Encoder::with_metrics`.
+    pub fn with_metrics(self, sink: Rc<dyn MetricsSink>) -> Self {
+        let encoder = self.encoder.with_metrics(sink.clone());
+        BatchEncoder {
+            encoder,
+            metrics: Some(sink),
+            ..self
+        }
+    }
+
+    /// The format shared by all files, e.g. to read statistics.
+    pub fn format(&self) -> &Format {
+        &self.format
+    }
+
+    /// Read, parse, enrich and encode a single text source file.
+    pub fn encode_file<P: AsRef<Path>>(&mut self, path: P) -> Result<EncodeResult, Error> {
+        self.encode_file_aux(path, false).map(|(result, _)| result)
+    }
+
+    /// As `encode_file`, also printing the parsed AST back to JavaScript,
+    /// e.g. as a fallback for clients that do not support BinAST.
+    ///
+    /// The source is parsed once for both outputs.
+    pub fn encode_file_with_js<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<(EncodeResult, String), Error> {
+        self.encode_file_aux(path, true)
+            .map(|(result, js)| (result, js.unwrap()))
+    }
+
+    /// As `encode_file`, once for each of `codecs`, all sections being
+    /// compressed with the codec, e.g. so that a server may pick the codec
+    /// supported by each client without encoding again.
+    ///
+    /// The source is parsed once for all outputs. Multipart format only.
+    pub fn encode_file_with_codecs<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        codecs: &[Compression],
+    ) -> Result<Vec<EncodeResult>, Error> {
+        match self.format {
+            Format::Multipart { .. } => {}
+            _ => {
+                return Err(Error::EncodingError(BinjsError::Unsupported(
+                    "Encoding with several codecs requires format multipart".to_string(),
+                )));
+            }
+        }
+        let (ast, metadata, source_len, _) = self.parse_file(path, false)?;
+        let mut results = Vec::with_capacity(codecs.len());
+        for codec in codecs {
+            let encoder = &self.encoder;
+            let inline_strings = self.inline_strings;
+            let result = self
+                .format
+                .with_compression(codec, |format| {
+                    encode_ast(
+                        encoder,
+                        format,
+                        inline_strings,
+                        &ast,
+                        metadata.clone(),
+                        source_len,
+                    )
+                })
+                .expect("Format checked above");
+            results.push(result?);
+        }
+        Ok(results)
+    }
+
+    /// Implementation of `encode_file`, printing the AST if `with_js` is `true`.
+    fn encode_file_aux<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        with_js: bool,
+    ) -> Result<(EncodeResult, Option<String>), Error> {
+        let (ast, metadata, source_len, js) = self.parse_file(path, with_js)?;
+        let result = encode_ast(
+            &self.encoder,
+            &mut self.format,
+            self.inline_strings,
+            &ast,
+            metadata,
+            source_len,
+        )?;
+        Ok((result, js))
+    }
+
+    /// Read, parse and enrich a single text source file, also printing the
+    /// parsed AST back to JavaScript if `with_js` is `true`.
+    fn parse_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        with_js: bool,
+    ) -> Result<(Script, Metadata, usize, Option<String>), Error> {
+        self.source.clear();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut self.source))
+            .map_err(Error::IOError)?;
+        let source_len = self.source.len();
+
+        // The parser rejects byte order marks and shebangs, so store them aside.
+        let (metadata, text) = Metadata::from_source(&self.source);
+        let stopwatch = Stopwatch::start(Phase::Parse);
+        let mut ast = self.parser.parse_str(text).map_err(Error::SourceError)?;
+        if let Some(ref sink) = self.metrics {
+            sink.record(stopwatch.stop(Some(source_len)));
+        }
+        // Print before enriching, as printing ignores the enrichments.
+        let js = if with_js {
+            let js = self.parser.to_source(&ast).map_err(Error::SourceError)?;
+            Some(metadata.restore(&js))
+        } else {
+            None
+        };
+        self.enricher.enrich(&mut ast).map_err(Error::EnrichError)?;
+        Ok((ast, metadata, source_len, js))
+    }
+}
+
+/// Encode an enriched AST, parsed from a source of `source_len` bytes, with
+/// the settings of a `BatchEncoder`.
+fn encode_ast(
+    encoder: &Encoder,
+    format: &mut Format,
+    inline_strings: bool,
+    ast: &Script,
+    metadata: Metadata,
+    source_len: usize,
+) -> Result<EncodeResult, Error> {
+    if let Format::Multipart {
+        ref mut options, ..
+    } = *format
+    {
+        // Keep the user metadata of the format, e.g. `--user-metadata`.
+        options.metadata = Metadata {
+            user: options.metadata.user.clone(),
+            ..metadata
+        };
+        options.inline_strings = inline_strings || inline_strings_by_default(source_len);
+    }
+    let data = encode_within_budget(encoder, None, format, ast)?;
+
+    Ok(EncodeResult { data, source_len })
+}
+
+/// Encode `ast` to `format`, as `Encoder::encode`, reporting files over the
+/// budget of `multipart::Options::max_output_bytes` with their actual size,
+/// e.g. to catch size regressions in continuous integration.
+///
+/// As encoding stops at the first section over the budget, such files are
+/// encoded again, without budget, to measure them.
+pub fn encode_within_budget(
+    encoder: &Encoder,
+    path: Option<&Path>,
+    format: &mut Format,
+    ast: &Script,
+) -> Result<Box<[u8]>, Error> {
+    let result = encoder.encode(path, format, ast);
+    let max_size = match (&result, &mut *format) {
+        (
+            &Err(BinjsError::Limit(_)),
+            &mut Format::Multipart {
+                ref mut options, ..
+            },
+        ) => options.max_output_bytes.take(),
+        _ => None,
+    };
+    let max_size = match max_size {
+        Some(max_size) => max_size,
+        None => return result.map_err(Error::EncodingError),
+    };
+    let measured = encoder.encode(None, format, ast);
+    if let Format::Multipart {
+        ref mut options, ..
+    } = *format
+    {
+        options.max_output_bytes = Some(max_size);
+    }
+    let size = measured.map_err(Error::EncodingError)?.len();
+    if size <= max_size {
+        // Another limit.
+        return result.map_err(Error::EncodingError);
+    }
+    Err(Error::SizeExceeded { size, max_size })
+}
+
+/// The file extension of files whose sections are all compressed with
+/// `codec`, e.g. `"br.binjs"`, or `"binjs"` for uncompressed files.
+pub fn codec_extension(codec: &Compression) -> String {
+    match *codec {
+        Compression::Identity => "binjs".to_string(),
+        Compression::Gzip => "gz.binjs".to_string(),
+        _ => format!("{}.binjs", codec.code()),
+    }
+}
@@ -0,0 +1,215 @@
+//! Comparing encoding configurations on a corpus of source files.
+//!
+//! A configuration is a TOML file describing a format and its options, e.g.
+//!
+//! ```toml
+//! # Defaults to the name of the file.
+//! name = "brotli"
+//!
+//! # Defaults to "multipart".
+//! format = "multipart"
+//!
+//! # Shorthands for `--x-inner-compression`, `--profile` and `--dictionary`.
+//! compression = "br"
+//! profile = "fast-tree"
+//! dictionary = "path/to/dictionary"
+//!
+//! # Any further arguments of the format subcommand.
+//! args = ["--size-hints"]
+//! ```
+
+use batch::{self, BatchEncoder};
+
+use binjs_es6::Enrich;
+use binjs_io::bytes::compress::CompressionResult;
+use binjs_io::Format;
+
+use serde::Deserialize;
+
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub enum Error {
+    IOError(std::io::Error),
+    ConfigError(toml::de::Error),
+    FormatError(std::io::Error),
+    BatchError(batch::Error),
+    EncodingError(PathBuf, batch::Error),
+}
+
+/// The contents of a configuration file.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    name: Option<String>,
+    format: Option<String>,
+    compression: Option<String>,
+    profile: Option<String>,
+    dictionary: Option<String>,
+    args: Vec<String>,
+}
+
+/// An encoding configuration.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The name under which this configuration is reported.
+    pub name: String,
+
+    /// The name of the format subcommand, e.g. `"multipart"`.
+    pub format: String,
+
+    /// The arguments of the format subcommand.
+    pub args: Vec<String>,
+}
+
+impl Config {
+    /// Parse a configuration, named `name` unless it specifies its own name.
+    pub fn from_toml(name: &str, text: &str) -> Result<Self, Error> {
+        let file: ConfigFile = toml::from_str(text).map_err(Error::ConfigError)?;
+        let mut args = vec![];
+        for (flag, value) in &[
+            ("--x-inner-compression", &file.compression),
+            ("--profile", &file.profile),
+            ("--dictionary", &file.dictionary),
+        ] {
+            if let Some(ref value) = **value {
+                args.push(flag.to_string());
+                args.push(value.clone());
+            }
+        }
+        args.extend(file.args);
+        Ok(Config {
+            name: file.name.unwrap_or_else(|| name.to_string()),
+            format: file.format.unwrap_or_else(|| "multipart".to_string()),
+            args,
+        })
+    }
+
+    /// Read a configuration file, named after the file unless it specifies
+    /// its own name.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(Error::IOError)?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self::from_toml(&name, &text)
+    }
+
+    /// Create a fresh format for this configuration.
+    pub fn format(&self) -> Result<Format, Error> {
+        let spec = binjs_generic::es6::Library::spec();
+        let args: Vec<&str> = self.args.iter().map(String::as_str).collect();
+        Format::from_name(&spec, &self.format, &args).map_err(Error::FormatError)
+    }
+}
+
+/// The results of encoding a corpus with a configuration.
+#[derive(Clone, Debug)]
+pub struct Totals {
+    /// The name of the configuration.
+    pub name: String,
+
+    /// Number of files encoded.
+    pub files: usize,
+
+    /// Byte length of all sources.
+    pub source_bytes: usize,
+
+    /// Byte length of all encoded files.
+    pub encoded_bytes: usize,
+
+    /// Time spent reading, parsing and encoding all files.
+    pub duration: Duration,
+
+    /// The compression of all sections of all files, added up.
+    ///
+    /// `None` if the format does not report compression statistics.
+    pub compression: Option<CompressionResult>,
+}
+
+/// List the JavaScript files of a directory and its subdirectories, sorted.
+pub fn corpus_files<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>, Error> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.as_ref().to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).map_err(Error::IOError)? {
+            let path = entry.map_err(Error::IOError)?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().map_or(false, |ext| ext == "js") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Encode all `files` with `config`.
+pub fn run(config: &Config, files: &[PathBuf]) -> Result<Totals, Error> {
+    let mut encoder =
+        BatchEncoder::try_new(config.format()?, Enrich::default()).map_err(Error::BatchError)?;
+    let mut totals = Totals {
+        name: config.name.clone(),
+        files: 0,
+        source_bytes: 0,
+        encoded_bytes: 0,
+        duration: Duration::default(),
+        compression: None,
+    };
+    for path in files {
+        let start = Instant::now();
+        let result = encoder
+            .encode_file(path)
+            .map_err(|err| Error::EncodingError(path.clone(), err))?;
+        totals.duration += start.elapsed();
+        totals.files += 1;
+        totals.source_bytes += result.source_len;
+        totals.encoded_bytes += result.data.len();
+    }
+
+    // Statistics are shared by all files encoded with the format.
+    if let Format::Multipart { ref stats, .. } = *encoder.format() {
+        let stats = stats.borrow();
+        let mut compression = stats.grammar_table.compression.clone();
+        for section in &[&stats.strings_table, &stats.numbers_table, &stats.tree] {
+            compression += section.compression.clone();
+        }
+        totals.compression = Some(compression);
+    }
+    Ok(totals)
+}
+
+/// The configuration producing the smallest files, if any.
+///
+/// In case of a tie, the first one wins.
+pub fn smallest(all_totals: &[Totals]) -> Option<&Totals> {
+    all_totals.iter().min_by_key(|totals| totals.encoded_bytes)
+}
+
+/// A human-readable report, one line per configuration, then the winner.
+pub fn report(all_totals: &[Totals]) -> String {
+    let mut report = String::new();
+    for totals in all_totals {
+        writeln!(
+            report,
+            "{name}: {files} files, {source} source bytes, {encoded} encoded bytes ({ratio:.3}), {ms} ms",
+            name = totals.name,
+            files = totals.files,
+            source = totals.source_bytes,
+            encoded = totals.encoded_bytes,
+            ratio = totals.encoded_bytes as f64 / totals.source_bytes as f64,
+            ms = totals.duration.as_millis(),
+        )
+        .unwrap();
+    }
+    if let Some(winner) = smallest(all_totals) {
+        writeln!(report, "Winner on size: {}", winner.name).unwrap();
+    }
+    report
+}
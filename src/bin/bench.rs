@@ -0,0 +1,61 @@
+//! Encode a corpus of JS source files with several configurations, then
+//! report which one produces the smallest files.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+
+use binjs::bench::{self, Config};
+
+use std::thread;
+
+use clap::*;
+
+fn main() {
+    thread::Builder::new()
+        .name("large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main_aux();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main_aux() {
+    env_logger::init();
+
+    let matches = App::new("BinJS benchmark")
+        .about("Encode all the JS files of a corpus with each configuration, report total sizes and times, and declare which configuration wins on size.")
+        .args(&[
+            Arg::with_name("CORPUS")
+                .required(true)
+                .help("Directory containing the JS files to encode, including in subdirectories."),
+            Arg::with_name("configs")
+                .long("configs")
+                .takes_value(true)
+                .use_delimiter(true)
+                .required(true)
+                .help("Comma-separated list of TOML configuration files. See `binjs::bench` for their contents."),
+        ])
+        .get_matches();
+
+    let configs: Vec<_> = matches
+        .values_of("configs")
+        .unwrap()
+        .map(|path| Config::from_file(path).expect("Could not read configuration"))
+        .collect();
+    let files =
+        bench::corpus_files(matches.value_of("CORPUS").unwrap()).expect("Could not list corpus");
+    eprintln!("Encoding {} files.", files.len());
+
+    let all_totals: Vec<_> = configs
+        .iter()
+        .map(|config| {
+            eprintln!("Encoding with {}.", config.name);
+            bench::run(config, &files).expect("Could not encode corpus")
+        })
+        .collect();
+    print!("{}", bench::report(&all_totals));
+}
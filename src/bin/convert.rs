@@ -0,0 +1,132 @@
+//! Convert a BinAST file between two BinAST formats, without re-parsing
+//! the text source.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+
+use binjs::generic::Offset;
+use binjs::specialized::es6::ast::{Script, Visitor, WalkPath, Walker};
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use std::fs;
+use std::io::Cursor;
+use std::thread;
+
+use clap::*;
+
+macro_rules! progress {
+    ($quiet:expr, $($args:tt)*) => {
+        if !$quiet {
+            println!($($args)*);
+        }
+    }
+}
+
+/// A visitor designed to reset offsets to 0.
+///
+/// Offsets depend on the format, so they must be ignored when comparing
+/// ASTs decoded from distinct formats.
+struct OffsetCleanerVisitor;
+impl Visitor<()> for OffsetCleanerVisitor {
+    fn visit_offset(&mut self, _path: &WalkPath, node: &mut Offset) -> Result<(), ()> {
+        *node = Offset(0);
+        Ok(())
+    }
+}
+
+fn main() {
+    thread::Builder::new()
+        .name("large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main_aux();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main_aux() {
+    env_logger::init();
+
+    let format_names = ["multipart", "entropy", "expanded", "json"];
+    let matches = App::new("BinJS converter")
+        .about("Convert a BinAST file between two BinAST formats, by decoding it to an AST and re-encoding the AST.")
+        .args(&[
+            Arg::with_name("INPUT")
+                .required(true)
+                .help("Input file to use. Must be a BinAST file in format `--from`."),
+            Arg::with_name("OUTPUT")
+                .required(true)
+                .help("Output file to use. Will be overwritten."),
+            Arg::with_name("from")
+                .long("from")
+                .takes_value(true)
+                .possible_values(&format_names)
+                .default_value("multipart")
+                .help("The format of INPUT."),
+            Arg::with_name("to")
+                .long("to")
+                .takes_value(true)
+                .possible_values(&format_names)
+                .required(true)
+                .help("The format of OUTPUT."),
+            Arg::with_name("dictionary")
+                .long("dictionary")
+                .takes_value(true)
+                .help("Path to the dictionary, if either format is `entropy`."),
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Do not print progress"),
+        ])
+        .get_matches();
+
+    let spec = binjs::generic::es6::Library::spec();
+    let quiet = matches.is_present("quiet");
+
+    let format = |name: &str| {
+        let mut args = vec![];
+        if name == "entropy" {
+            if let Some(path) = matches.value_of("dictionary") {
+                args.push("--dictionary");
+                args.push(path);
+            }
+        }
+        binjs::io::Format::from_name(&spec, name, &args).expect("Could not parse format")
+    };
+    let mut source_format = format(matches.value_of("from").unwrap());
+    let mut dest_format = format(matches.value_of("to").unwrap());
+
+    progress!(
+        quiet,
+        "Converting from {} to {}.",
+        source_format.name(),
+        dest_format.name()
+    );
+
+    progress!(quiet, "Decoding.");
+    let source = fs::read(matches.value_of("INPUT").unwrap()).expect("Could not read source");
+    let mut ast: Script = Decoder::new()
+        .decode(&mut source_format, Cursor::new(source))
+        .expect("Could not decode source");
+
+    progress!(quiet, "Encoding.");
+    let data = Encoder::new()
+        .encode(None, &mut dest_format, &ast)
+        .expect("Could not encode");
+
+    progress!(quiet, "Checking round-trip.");
+    let mut decoded: Script = Decoder::new()
+        .decode(&mut dest_format, Cursor::new(&data))
+        .expect("Could not decode converted file");
+    for tree in &mut [&mut ast, &mut decoded] {
+        tree.walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+            .expect("Could not cleanup offsets");
+    }
+    assert_eq!(ast, decoded, "Conversion is not lossless");
+
+    progress!(quiet, "Writing.");
+    fs::write(matches.value_of("OUTPUT").unwrap(), data).expect("Could not write destination");
+}
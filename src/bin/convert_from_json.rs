@@ -183,9 +183,14 @@ fn main_aux() {
                 .expect("Could not write to stdout");
         }
         binjs::io::Format::Multipart {
-            ref mut targets, ..
+            ref mut targets,
+            ref options,
+            ..
         } => {
-            let w = binjs::io::multipart::TreeTokenWriter::new(targets.clone());
+            let w = binjs::io::multipart::TreeTokenWriter::with_options(
+                targets.clone(),
+                options.clone(),
+            );
             let mut writer = binjs::io::TokenWriterTreeAdapter::new(w);
             convert(&mut reader, &mut writer);
             let data = writer.done().expect("Error");
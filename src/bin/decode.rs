@@ -4,11 +4,13 @@ extern crate binjs;
 extern crate clap;
 extern crate env_logger;
 
+use binjs::io::multipart::{resolve_sections, Metadata};
 use binjs::source::Shift;
 use binjs::specialized::es6::io::Decoder;
 
 use std::fs::{self, File};
 use std::io::*;
+use std::path::Path;
 use std::thread;
 
 use clap::*;
@@ -55,10 +57,10 @@ fn main_aux() {
         .about("Decode a JavaScript BinJS source to a JavaScript text source.")
         .args(&[
             Arg::with_name("INPUT").help(
-                "Input file to use. Must be a BinJS source file. If not specified, stdin is used",
+                "Input file to use. Must be a BinJS source file. If not specified or `-`, stdin is used",
             ),
             Arg::with_name("OUTPUT")
-                .help("Output file to use. Will be overwritten. If not specified, stdout is used"),
+                .help("Output file to use. Will be overwritten. If not specified or `-`, stdout is used"),
             Arg::with_name("dump")
                 .long("dump")
                 .takes_value(false)
@@ -70,6 +72,13 @@ fn main_aux() {
             Arg::with_name("print-json")
                 .long("print-json")
                 .help("Print JSON of parse tree"),
+            Arg::with_name("preserve-parens")
+                .long("preserve-parens")
+                .help("Reproduce the parentheses written around expressions of the original source, including those precedence does not require, if stored by `binjs_encode --preserve-parens`."),
+            Arg::with_name("section-store")
+                .long("section-store")
+                .takes_value(true)
+                .help("Read the sections or chunks referenced by the input file from this directory, as written by `encode --section-store` or `encode --chunk-store`."),
         ])
         .subcommand(binjs::io::Format::subcommand())
         .get_matches();
@@ -78,8 +87,9 @@ fn main_aux() {
     let spec = binjs::generic::es6::Library::spec();
 
     // Common options.
-    let source_path = matches.value_of("INPUT");
-    let dest_path = matches.value_of("OUTPUT");
+    // `-` means stdin/stdout, which are also the defaults.
+    let source_path = matches.value_of("INPUT").filter(|path| *path != "-");
+    let dest_path = matches.value_of("OUTPUT").filter(|path| *path != "-");
     let quiet = matches.is_present("quiet") || dest_path.is_none();
 
     // Format options.
@@ -95,16 +105,25 @@ fn main_aux() {
     };
 
     progress!(quiet, "Reading.");
-    let mut tree = match source_path {
-        Some(path) => parse_tree(
+    let (mut tree, metadata) = match (source_path, matches.value_of("section-store")) {
+        (Some(path), None) => parse_tree(
             &|| BufReader::new(File::open(path).expect("Could not open source")),
             &mut options,
         ),
-        None => {
+        (source_path, section_store) => {
             let mut buffer = Vec::new();
-            stdin()
-                .read_to_end(&mut buffer)
-                .expect("Failed to read from stdin");
+            match source_path {
+                Some(path) => File::open(path)
+                    .and_then(|mut file| file.read_to_end(&mut buffer))
+                    .expect("Could not read source"),
+                None => stdin()
+                    .read_to_end(&mut buffer)
+                    .expect("Failed to read from stdin"),
+            };
+            if let Some(dir) = section_store {
+                buffer =
+                    resolve_sections(&buffer, Path::new(dir)).expect("Could not resolve sections");
+            }
 
             parse_tree(&|| Cursor::new(&buffer), &mut options)
         }
@@ -124,7 +143,13 @@ fn main_aux() {
 
     progress!(quiet, "Pretty-printing");
     let printer = Shift::try_new().expect("Could not launch Shift");
-    let source = printer.to_source(&tree).expect("Could not pretty-print");
+    let source = if matches.is_present("preserve-parens") {
+        printer.to_source_with_parens(&tree, &metadata.parens)
+    } else {
+        printer.to_source(&tree)
+    }
+    .expect("Could not pretty-print");
+    let source = metadata.restore(&source);
 
     progress!(quiet, "Writing.");
     match options.dest_path {
@@ -142,9 +167,9 @@ fn main_aux() {
 fn parse_tree<R: Read + Seek>(
     get_stream: &dyn Fn() -> R,
     options: &mut Options,
-) -> binjs::specialized::es6::ast::Script {
+) -> (binjs::specialized::es6::ast::Script, Metadata) {
     let decoder = Decoder::new();
     decoder
-        .decode(&mut options.format, get_stream())
+        .decode_with_metadata(&mut options.format, get_stream())
         .expect("Could not decode")
 }
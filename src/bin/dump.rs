@@ -3,6 +3,7 @@
 extern crate binjs;
 extern crate clap;
 extern crate env_logger;
+extern crate serde_json;
 
 use binjs::io::Deserialization;
 use binjs::io::FileStructurePrinter;
@@ -21,19 +22,80 @@ fn main() {
              David Teller, <dteller@mozilla.com>",
         )
         .about("Dump a JavaScript BinJS file structure to stdout.")
-        .args(&[Arg::with_name("INPUT")
-            .required(true)
-            .help("Input file to use. Must be a BinJS source file.")])
+        .args(&[
+            Arg::with_name("INPUT")
+                .required_unless("grammar")
+                .help("Input file to use. Must be a BinJS source file. Use `-` for stdin."),
+            Arg::with_name("grammar")
+                .long("grammar")
+                .conflicts_with("INPUT")
+                .help("Instead of dumping a file, dump the grammar used by the encoder and decoder, as JSON."),
+            Arg::with_name("layout")
+                .long("layout")
+                .help("Instead of dumping the file structure, print the offset, codec and length of each section."),
+            Arg::with_name("node-kinds")
+                .long("node-kinds")
+                .conflicts_with("layout")
+                .help("Instead of dumping the file structure, print each node kind present in the file, with its number of nodes."),
+        ])
         .get_matches();
 
+    if matches.is_present("grammar") {
+        let spec = binjs::generic::es6::Library::spec();
+        serde_json::to_writer_pretty(stdout(), &binjs::generic::schema::Schema::to_json(&spec))
+            .expect("Could not write grammar");
+        println!();
+        return;
+    }
+
     let source_path = matches.value_of("INPUT").expect("Expected input file");
 
+    if matches.is_present("layout") || matches.is_present("node-kinds") {
+        let mut buffer = Vec::new();
+        if source_path == "-" {
+            stdin()
+                .read_to_end(&mut buffer)
+                .expect("Failed to read from stdin");
+        } else {
+            File::open(source_path)
+                .and_then(|mut file| file.read_to_end(&mut buffer))
+                .expect("Could not read source");
+        }
+        if matches.is_present("node-kinds") {
+            let mut reader = binjs::io::multipart::TreeTokenReader::new(Cursor::new(buffer))
+                .expect("Could not read file");
+            reader.count_node_kinds();
+            let mut deserializer = binjs::specialized::es6::io::Deserializer::new(reader);
+            let _tree: binjs::specialized::es6::ast::Script = deserializer
+                .deserialize(&mut binjs::specialized::es6::ast::IOPath::new())
+                .expect("Could not decode");
+            for (kind, count) in deserializer.reader.node_kinds() {
+                println!("{} {}", kind, count);
+            }
+            return;
+        }
+        let layout = binjs::io::multipart::Layout::read(&mut Cursor::new(buffer))
+            .expect("Could not read layout");
+        println!("{}", layout);
+        return;
+    }
+
     println!("Reading.");
-    let file = File::open(source_path).expect("Could not open source");
-    let stream = BufReader::new(file);
+    let result = if source_path == "-" {
+        // Stdin may not be seekable, so buffer it.
+        let mut buffer = Vec::new();
+        stdin()
+            .read_to_end(&mut buffer)
+            .expect("Failed to read from stdin");
+        println!("Attempting to decode as multipart.");
+        binjs::io::multipart::TreeTokenReader::new(Cursor::new(buffer))
+    } else {
+        let file = File::open(source_path).expect("Could not open source");
+        println!("Attempting to decode as multipart.");
+        binjs::io::multipart::TreeTokenReader::new(BufReader::new(file))
+    };
 
-    println!("Attempting to decode as multipart.");
-    if let Ok(mut reader) = binjs::io::multipart::TreeTokenReader::new(stream) {
+    if let Ok(mut reader) = result {
         reader.enable_file_structure_print();
         let mut deserializer = binjs::specialized::es6::io::Deserializer::new(reader);
         let _tree: binjs::specialized::es6::ast::Script = deserializer
@@ -4,14 +4,25 @@ extern crate binjs;
 extern crate clap;
 extern crate env_logger;
 
+use binjs::batch::{codec_extension, encode_within_budget, Error as BatchError};
+use binjs::io::bytes::chunk::Chunker;
+use binjs::io::bytes::compress::{input_hash, Compression};
+use binjs::io::multipart::{
+    inline_strings_by_default, store_chunks, store_sections, write_raw_header, write_raw_section,
+    Metadata, OnSection,
+};
 use binjs::io::{CompressionTarget, Format};
-use binjs::source::{Shift, SourceParser};
+use binjs::source::{strip_types, Shift};
+use binjs::specialized::es6::diagnostics::DiagnosticsVisitor;
 use binjs::specialized::es6::io::Encoder;
 use binjs::specialized::es6::Enrich;
 
+use std::cell::RefCell;
 use std::fs;
 use std::io::*;
 use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
 use std::thread;
 
 use clap::*;
@@ -37,6 +48,39 @@ struct Options<'a> {
     dest_dir: Option<PathBuf>,
     enricher: Enrich,
     show_ast: bool,
+    diagnostics: bool,
+    /// If `true`, encode the parts of the source that can be parsed,
+    /// rather than failing on syntax errors.
+    skip_parse_errors: bool,
+    /// If `true`, strip TypeScript-specific syntax before parsing, and
+    /// accept `.ts` files.
+    strip_types: bool,
+    /// If `true`, store the parentheses written around expressions of the
+    /// source, for `binjs_decode --preserve-parens`.
+    /// Multipart format only.
+    preserve_parens: bool,
+    /// If `true`, store strings inline in all files, not just tiny ones.
+    /// Multipart format only.
+    inline_strings: bool,
+    /// If specified, store the sections of multipart files in this
+    /// directory, named by their hash, and write manifests instead.
+    section_store: Option<PathBuf>,
+    /// If specified, store the sections of multipart files in this
+    /// directory, as content-defined chunks named by their hash, and
+    /// write manifests instead.
+    chunk_store: Option<PathBuf>,
+    /// If specified, also print the parsed AST to this JavaScript file,
+    /// e.g. as a fallback for clients that do not support BinAST.
+    also_js: Option<PathBuf>,
+    /// If non-empty, write one file per codec, all sections compressed
+    /// with the codec, instead of a single file. Multipart format only.
+    codecs: Vec<Compression>,
+    /// If `true`, report source files modified while they are encoded,
+    /// hashing each of them twice, rather than writing their output.
+    check_unchanged: bool,
+    /// `true` once a file has failed a check, e.g. exceeded the budget of
+    /// `--max-output-bytes`.
+    failed: bool,
     quiet: bool,
 }
 
@@ -75,11 +119,13 @@ fn handle_path<'a>(options: &mut Options<'a>, source_path: &Path, sub_dir: &Path
         }
         return;
     }
-    if let Some(Some("js")) = source_path.extension().map(std::ffi::OsStr::to_str) {
-        // Proceed
-    } else {
-        progress!(options.quiet, "Skipping {:?}", source_path);
-        return;
+    match source_path.extension().map(std::ffi::OsStr::to_str) {
+        Some(Some("js")) => {}
+        Some(Some("ts")) if options.strip_types => {}
+        _ => {
+            progress!(options.quiet, "Skipping {:?}", source_path);
+            return;
+        }
     }
     let (dest_txt_path, dest_bin_path) = match options.dest_dir {
         None => (None, None), // Use stdout
@@ -120,24 +166,95 @@ fn handle_path<'a>(options: &mut Options<'a>, source_path: &Path, sub_dir: &Path
 }
 
 fn handle_path_or_text<'a>(options: &mut Options<'a>, params: EncodeParams) {
-    let (source_path, source_len, mut ast) = match params.source {
+    let (source_path, text) = match params.source {
         Source::FromFile { path } => (
             Some(path),
-            fs::metadata(path).expect("Could not open source").len(),
-            options
-                .parser
-                .parse_file(path)
-                .expect("Could not parse source"),
-        ),
-        Source::FromStdin { text } => (
-            None,
-            text.len() as u64,
-            options
-                .parser
-                .parse_str(text.as_str())
-                .expect("Could not parse source"),
+            fs::read_to_string(path).expect("Could not open source"),
         ),
+        Source::FromStdin { text } => (None, text),
+    };
+    let source_len = text.len() as u64;
+    let source_hash = if options.check_unchanged {
+        Some(input_hash(text.as_bytes()))
+    } else {
+        None
+    };
+
+    // The parser rejects byte order marks and shebangs, so store them aside.
+    let (mut metadata, text) = Metadata::from_source(&text);
+
+    // Report lines of the original source, including any shebang.
+    let first_line = if metadata.shebang.is_some() { 2 } else { 1 };
+
+    // Stripping preserves positions, so errors still refer to the original source.
+    let stripped = if options.strip_types {
+        Some(strip_types(text).unwrap_or_else(|error| {
+            panic!(
+                "Could not strip types:\n{}:{}: {}",
+                error.line + first_line - 1,
+                error.column,
+                error.message
+            )
+        }))
+    } else {
+        None
     };
+    let text = stripped.as_ref().map_or(text, String::as_str);
+
+    let (mut ast, errors, parens) = if options.preserve_parens {
+        options.parser.parse_str_tolerant_with_parens(text)
+    } else {
+        options
+            .parser
+            .parse_str_tolerant(text)
+            .map(|(ast, errors)| (ast, errors, Default::default()))
+    }
+    .expect("Could not parse source");
+    metadata.parens = parens;
+    if !errors.is_empty() {
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| {
+                format!(
+                    "{}:{}: {}",
+                    error.line + first_line - 1,
+                    error.column,
+                    error.message
+                )
+            })
+            .collect();
+        if options.skip_parse_errors {
+            eprintln!(
+                "Skipping unparseable regions of {:?}:\n{}",
+                source_path.unwrap_or_else(|| Path::new("-")),
+                errors.join("\n")
+            );
+        } else {
+            panic!("Could not parse source:\n{}", errors.join("\n"));
+        }
+    }
+    if let Some(ref js_path) = options.also_js {
+        progress!(options.quiet, "Printing JavaScript.");
+        let js = options
+            .parser
+            .to_source_with_parens(&ast, &metadata.parens)
+            .expect("Could not pretty-print");
+        fs::write(js_path, metadata.restore(&js))
+            .unwrap_or_else(|e| panic!("Could not write JavaScript file {:?}: {:?}", js_path, e));
+    }
+    if let Format::Multipart {
+        options: ref mut multipart_options,
+        ..
+    } = options.format
+    {
+        // Keep the user metadata of the format, e.g. `--user-metadata`.
+        multipart_options.metadata = Metadata {
+            user: multipart_options.metadata.user.clone(),
+            ..metadata
+        };
+        multipart_options.inline_strings =
+            options.inline_strings || inline_strings_by_default(source_len as usize);
+    }
     let dest_bin_path = params.dest_bin_path;
     let dest_txt_path = params.dest_txt_path;
 
@@ -146,6 +263,25 @@ fn handle_path_or_text<'a>(options: &mut Options<'a>, params: EncodeParams) {
         .enrich(&mut ast)
         .expect("Could not enrich AST");
 
+    if options.diagnostics {
+        progress!(options.quiet, "Collecting diagnostics.");
+        let diagnostics = DiagnosticsVisitor::collect(&mut ast);
+        match options.format {
+            Format::Multipart {
+                options: ref mut multipart_options,
+                ..
+            } => {
+                multipart_options.diagnostics = diagnostics;
+            }
+            _ => {
+                progress!(
+                    options.quiet,
+                    "Diagnostics are only stored by format multipart"
+                );
+            }
+        }
+    }
+
     if options.show_ast {
         serde_json::to_writer_pretty(std::io::stdout(), &ast).unwrap();
         println!();
@@ -153,32 +289,91 @@ fn handle_path_or_text<'a>(options: &mut Options<'a>, params: EncodeParams) {
 
     progress!(options.quiet, "Encoding.");
     let encoder = Encoder::new();
-    let encoding_path = match dest_bin_path {
-        None => None,
-        Some(ref buf) => Some(buf.as_path()),
-    };
-    let data = encoder
-        .encode(encoding_path, &mut options.format, &ast)
-        .expect("Could not encode");
-    if dest_txt_path.is_some() {
-        options
-            .format
-            .with_sections::<_, ()>(|contents, name| {
-                export_section(&dest_bin_path, contents, name);
-                Ok(())
-            })
-            .expect("Could not write sections");
+    let outputs = if options.codecs.is_empty() {
+        let encoding_path = match dest_bin_path {
+            None => None,
+            Some(ref buf) => Some(buf.as_path()),
+        };
+        let data = match encode_within_budget(&encoder, encoding_path, &mut options.format, &ast) {
+            Ok(data) => data,
+            Err(err) => return report_size_exceeded(options, source_path, err),
+        };
+        if dest_txt_path.is_some() {
+            options
+                .format
+                .with_sections::<_, ()>(|contents, name| {
+                    export_section(&dest_bin_path, contents, name);
+                    Ok(())
+                })
+                .expect("Could not write sections");
+        };
+        vec![(dest_bin_path.clone(), data)]
+    } else {
+        // One file per codec, from the same AST.
+        let dest_bin_path = dest_bin_path
+            .as_ref()
+            .expect("--codecs requires a destination directory");
+        let mut outputs = vec![];
+        for codec in options.codecs.clone() {
+            progress!(options.quiet, "Encoding with {}.", codec.code());
+            let path = dest_bin_path.with_extension(codec_extension(&codec));
+            let data = match options
+                .format
+                .with_compression(&codec, |format| {
+                    encode_within_budget(&encoder, Some(path.as_path()), format, &ast)
+                })
+                .expect("--codecs is only supported by format multipart")
+            {
+                Ok(data) => data,
+                Err(err) => return report_size_exceeded(options, source_path, err),
+            };
+            outputs.push((Some(path), data));
+        }
+        outputs
     };
-    let dest_len = data.as_ref().as_ref().len();
 
-    if let Some(ref bin_path) = dest_bin_path {
-        progress!(options.quiet, "Writing binary file.");
-        fs::write(bin_path, data.as_ref())
-            .unwrap_or_else(|e| panic!("Could not write destination file {:?}: {:?}", bin_path, e));
-    } else {
-        stdout()
-            .write((*data).as_ref())
-            .expect("Could not write to stdout");
+    // Build systems may write the source while we encode it.
+    if let (Some(path), Some(source_hash)) = (source_path, source_hash) {
+        let reread = fs::read(path).expect("Could not reopen source");
+        if input_hash(&reread) != source_hash {
+            eprintln!("{:?}: Source file changed during encoding", path);
+            options.failed = true;
+            return;
+        }
+    }
+
+    let mut dest_lens = vec![];
+    for (dest_bin_path, data) in outputs {
+        let data: Box<[u8]> = match (&options.section_store, &options.chunk_store) {
+            (&Some(ref dir), _) => store_sections(&data, dir)
+                .expect("Could not store sections")
+                .into_boxed_slice(),
+            (_, &Some(ref dir)) => match store_chunks(&data, dir, &Chunker::default()) {
+                Ok(manifest) => manifest.into_boxed_slice(),
+                Err(err) => {
+                    eprintln!(
+                        "{:?}: Could not store chunks: {}",
+                        source_path.unwrap_or_else(|| Path::new("-")),
+                        err
+                    );
+                    options.failed = true;
+                    return;
+                }
+            },
+            (&None, &None) => data,
+        };
+        dest_lens.push(data.as_ref().as_ref().len().to_string());
+
+        if let Some(ref bin_path) = dest_bin_path {
+            progress!(options.quiet, "Writing binary file {:?}.", bin_path);
+            fs::write(bin_path, data.as_ref()).unwrap_or_else(|e| {
+                panic!("Could not write destination file {:?}: {:?}", bin_path, e)
+            });
+        } else {
+            stdout()
+                .write((*data).as_ref())
+                .expect("Could not write to stdout");
+        }
     }
 
     if let Some(ref txt_path) = dest_txt_path {
@@ -191,7 +386,14 @@ fn handle_path_or_text<'a>(options: &mut Options<'a>, params: EncodeParams) {
         } else {
             progress!(options.quiet, "Copying source file.");
 
-            fs::copy(source_path.unwrap(), txt_path).expect("Could not copy source file");
+            match stripped {
+                Some(ref stripped) => {
+                    fs::write(txt_path, stripped).expect("Could not write stripped source file")
+                }
+                None => {
+                    fs::copy(source_path.unwrap(), txt_path).expect("Could not copy source file");
+                }
+            }
         }
     }
 
@@ -199,23 +401,38 @@ fn handle_path_or_text<'a>(options: &mut Options<'a>, params: EncodeParams) {
         options.quiet,
         "Successfully compressed {} bytes => {} bytes",
         source_len,
-        dest_len
+        dest_lens.join(", ")
     );
 }
 
+/// Report a file over the budget of `--max-output-bytes`, with its actual
+/// size, marking the run as failed. Other errors are fatal.
+fn report_size_exceeded(options: &mut Options, source_path: Option<&Path>, err: BatchError) {
+    match err {
+        BatchError::SizeExceeded { .. } => {
+            eprintln!(
+                "{:?}: {}",
+                source_path.unwrap_or_else(|| Path::new("-")),
+                err
+            );
+            options.failed = true;
+        }
+        _ => panic!("Could not encode: {}", err),
+    }
+}
+
 fn main() {
-    thread::Builder::new()
+    let status = thread::Builder::new()
         .name("large stack dedicated thread".to_string())
         .stack_size(20 * 1024 * 1024)
-        .spawn(|| {
-            main_aux();
-        })
+        .spawn(main_aux)
         .expect("Could not launch dedicated thread")
         .join()
         .expect("Error in dedicated thread");
+    process::exit(status);
 }
 
-fn main_aux() {
+fn main_aux() -> i32 {
     env_logger::init();
 
     let matches = App::new("BinJS encoder")
@@ -228,18 +445,66 @@ fn main_aux() {
                 .multiple(true)
                 .takes_value(true)
                 .number_of_values(1)
-                .help("Input files to use. Must be JS source file. May be specified multiple times. If not specified, stdin is used."),
+                .help("Input files to use. Must be JS source file. May be specified multiple times. If not specified or `-`, stdin is used."),
             Arg::with_name("out")
                 .long("out")
                 .short("o")
                 .takes_value(true)
-                .help("Output directory to use. Files in this directory may be overwritten. Requires --in. If not specified, stdout is used"),
+                .help("Output directory to use. Files in this directory may be overwritten. Requires --in. If not specified or `-`, stdout is used"),
             Arg::with_name("statistics")
                 .long("show-stats")
                 .help("Show statistics."),
+            Arg::with_name("stats-out")
+                .long("stats-out")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Write statistics to this file. For format multipart, these include an estimate of the compressed bytes attributable to each node kind."),
+            Arg::with_name("emit-raw")
+                .long("emit-raw")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Write the sections of all files, before compression, to this file, e.g. to analyze them with external tools. Each section is framed with its header and length, see `read_raw_sections`. Multipart format only."),
             Arg::with_name("show-ast")
                 .long("show-ast")
                 .help("Show pos-processed ast"),
+            Arg::with_name("diagnostics")
+                .long("diagnostics")
+                .help("Store non-fatal observations on the source (e.g. use of `with` or `eval`) in the file. Multipart format only."),
+            Arg::with_name("preserve-parens")
+                .long("preserve-parens")
+                .help("Store the parentheses written around expressions of the source, so that `binjs_decode --preserve-parens` reproduces them, including those precedence does not require. Multipart format only."),
+            Arg::with_name("on-parse-error")
+                .long("on-parse-error")
+                .takes_value(true)
+                .possible_values(&["fail", "skip"])
+                .default_value("fail")
+                .help("What to do with syntax errors. `fail` reports all the errors of the file, `skip` encodes the statements that could be parsed."),
+            Arg::with_name("strip-types")
+                .long("strip-types")
+                .help("Strip TypeScript type annotations, interfaces, casts, etc. before encoding. Also accepts `.ts` files. Constructs with runtime semantics, such as `enum`, are rejected."),
+            Arg::with_name("section-store")
+                .long("section-store")
+                .takes_value(true)
+                .help("Store the sections of each file in this directory, named by their hash, so that identical sections are stored once. The output files reference these sections. Multipart format only."),
+            Arg::with_name("chunk-store")
+                .long("chunk-store")
+                .takes_value(true)
+                .conflicts_with("section-store")
+                .help("Store the sections of each file in this directory, split into content-defined chunks named by their hash, so that chunks shared between files are stored once. The output files reference these chunks. Multipart format only, with uncompressed sections."),
+            Arg::with_name("also-js")
+                .long("also-js")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Also write the parsed source, printed back to JavaScript, to this file, e.g. as a fallback for clients that do not support BinAST. The source is parsed once for both outputs. Requires a single source."),
+            Arg::with_name("codecs")
+                .long("codecs")
+                .takes_value(true)
+                .use_delimiter(true)
+                .possible_values(&["identity", "br", "br-large", "gzip", "deflate", "lzw", "lzw-msb"])
+                .help("Write one file per codec, e.g. `foo.br.binjs`, `foo.gz.binjs` and `foo.binjs` for `br,gzip,identity`, with all sections compressed with the codec, so that servers may pick a codec per client. The source is parsed once for all files. Requires --out. Multipart format only."),
+            Arg::with_name("check-unchanged")
+                .long("check-unchanged")
+                .help("Fail if a source file changes while it is encoded, e.g. as a build system writes it, rather than writing its output. Each source file is hashed twice."),
             Arg::with_name("quiet")
                 .long("quiet")
                 .short("q")
@@ -253,9 +518,15 @@ fn main_aux() {
     let spec = binjs::generic::es6::Library::spec();
 
     // Common options.
+    // `-` means stdin, which is also the default.
     let sources: Vec<_> = matches
         .values_of("in")
         .map_or_else(|| Vec::new(), |input| input.map(Path::new).collect());
+    let sources = if sources == [Path::new("-")] {
+        vec![]
+    } else {
+        sources
+    };
 
     let dest_dir = if sources.len() == 0 {
         // If --in is not specified, --out is not used even if specified.
@@ -263,21 +534,62 @@ fn main_aux() {
         None
     } else {
         match matches.value_of("out") {
-            None => None,
+            None | Some("-") => None,
             Some(path) => Some(Path::new(path).to_path_buf()),
         }
     };
 
     let quiet = matches.is_present("quiet") || dest_dir.is_none();
 
+    let also_js = matches.value_of("also-js").map(PathBuf::from);
+    if also_js.is_some() && sources.len() > 1 {
+        panic!("--also-js requires a single source");
+    }
+
+    let codecs: Vec<_> = matches.values_of("codecs").map_or_else(Vec::new, |codecs| {
+        codecs
+            .map(|codec| Compression::parse(Some(codec)).expect("Could not parse codec"))
+            .collect()
+    });
+    if !codecs.is_empty() && dest_dir.is_none() {
+        panic!("--codecs requires --out");
+    }
+
     // Format options.
-    let format =
+    let mut format =
         binjs::io::Format::from_matches(&spec, &matches).expect("Could not parse encoding format");
     progress!(quiet, "Using format: {}", format.name());
 
+    let inline_strings = match format {
+        Format::Multipart { ref options, .. } => options.inline_strings,
+        _ => false,
+    };
+
+    // Collect the sections of all files before compression, if requested.
+    let raw_sections = matches.value_of("emit-raw").map(|path| {
+        let mut raw = vec![];
+        write_raw_header(&mut raw).unwrap(); // Writing to a `Vec` can't fail.
+        (PathBuf::from(path), Rc::new(RefCell::new(raw)))
+    });
+    if let Some((_, ref raw)) = raw_sections {
+        match format {
+            Format::Multipart {
+                ref mut options, ..
+            } => {
+                let raw = raw.clone();
+                options.on_section = Some(OnSection(Rc::new(move |header, bytes: &[u8]| {
+                    // Writing to a `Vec` can't fail.
+                    write_raw_section(&mut *raw.borrow_mut(), header, bytes).unwrap();
+                })));
+            }
+            _ => panic!("--emit-raw is only supported by format multipart"),
+        }
+    }
+
     let enricher = Enrich::from_matches(&matches);
 
     let show_stats = matches.is_present("statistics");
+    let stats_out = matches.value_of("stats-out").map(PathBuf::from);
 
     // Setup.
     let parser = Shift::try_new().expect("Could not launch Shift");
@@ -288,6 +600,17 @@ fn main_aux() {
         dest_dir,
         enricher,
         show_ast: matches.is_present("show-ast"),
+        diagnostics: matches.is_present("diagnostics"),
+        skip_parse_errors: matches.value_of("on-parse-error") == Some("skip"),
+        strip_types: matches.is_present("strip-types"),
+        preserve_parens: matches.is_present("preserve-parens"),
+        inline_strings,
+        section_store: matches.value_of("section-store").map(PathBuf::from),
+        chunk_store: matches.value_of("chunk-store").map(PathBuf::from),
+        also_js,
+        codecs,
+        check_unchanged: matches.is_present("check-unchanged"),
+        failed: false,
         quiet,
     };
 
@@ -312,6 +635,23 @@ fn main_aux() {
         }
     }
 
+    if let Some((ref path, ref raw)) = raw_sections {
+        fs::write(path, &*raw.borrow())
+            .unwrap_or_else(|e| panic!("Could not write raw sections {:?}: {:?}", path, e));
+    }
+
+    if let Some(ref path) = stats_out {
+        let stats = match options.format {
+            Format::Multipart { ref stats, .. } => format!("{}", stats.borrow()),
+            Format::Entropy {
+                options: ref entropy,
+            } => format!("{}", entropy.statistics_for_write()),
+            _ => "No stats available for this format".to_string(),
+        };
+        fs::write(path, stats)
+            .unwrap_or_else(|e| panic!("Could not write statistics {:?}: {:?}", path, e));
+    }
+
     if show_stats {
         match options.format {
             Format::Multipart { ref stats, .. } => {
@@ -331,4 +671,10 @@ fn main_aux() {
             }
         }
     }
+
+    if options.failed {
+        1
+    } else {
+        0
+    }
 }
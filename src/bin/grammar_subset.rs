@@ -0,0 +1,66 @@
+//! Decode a corpus of BinJS files, then dump the subset of the grammar they
+//! use, e.g. to produce a decoder which excludes the rest of the grammar.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+extern crate serde_json;
+
+use binjs::subset;
+
+use std::io::stdout;
+use std::path::Path;
+use std::thread;
+
+use clap::*;
+
+fn main() {
+    thread::Builder::new()
+        .name("large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main_aux();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main_aux() {
+    env_logger::init();
+
+    let matches = App::new("BinJS grammar subset")
+        .about("Decode BinJS files, then dump the node kinds and string enum values they use, as a grammar schema in the format of `binjs_dump --grammar`.")
+        .args(&[
+            Arg::with_name("INPUT")
+                .required(true)
+                .multiple(true)
+                .help("BinJS files in the multipart format, or directories containing such files with extension `.binjs`, including in subdirectories."),
+            Arg::with_name("counts")
+                .long("counts")
+                .help("Instead of the schema, print each node kind used by the files, with its number of nodes."),
+        ])
+        .get_matches();
+
+    let mut files = vec![];
+    for input in matches.values_of("INPUT").unwrap() {
+        if Path::new(input).is_dir() {
+            files.extend(subset::corpus_files(input).expect("Could not list corpus"));
+        } else {
+            files.push(input.into());
+        }
+    }
+    eprintln!("Decoding {} files.", files.len());
+    let usage = subset::of_files(&files).expect("Could not decode corpus");
+
+    if matches.is_present("counts") {
+        for (kind, count) in &usage.node_kinds {
+            println!("{} {}", kind, count);
+        }
+        return;
+    }
+    let spec = binjs::generic::es6::Library::spec();
+    serde_json::to_writer_pretty(stdout(), &usage.to_schema(&spec))
+        .expect("Could not write grammar subset");
+    println!();
+}
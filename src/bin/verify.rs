@@ -0,0 +1,78 @@
+//! Check that a BinAST file only uses the node kinds and fields of a grammar
+//! schema, as exported by `binjs_dump --grammar`.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+extern crate serde_json;
+
+use binjs::generic::schema::Schema;
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::Decoder;
+
+use std::fs;
+use std::io::Cursor;
+use std::process;
+use std::thread;
+
+use clap::*;
+
+fn main() {
+    let status = thread::Builder::new()
+        .name("large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(main_aux)
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+    process::exit(status);
+}
+
+fn main_aux() -> i32 {
+    env_logger::init();
+
+    let matches = App::new("BinJS verifier")
+        .about("Check that a BinAST file only uses the node kinds and fields of a grammar schema, e.g. one exported by an older version with `binjs_dump --grammar`.")
+        .args(&[
+            Arg::with_name("INPUT")
+                .required(true)
+                .help("Input file to use. Must be a BinAST file in format `--format`."),
+            Arg::with_name("grammar")
+                .long("grammar")
+                .takes_value(true)
+                .required(true)
+                .help("The grammar schema, in JSON."),
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["multipart", "expanded", "json"])
+                .default_value("multipart")
+                .help("The format of INPUT."),
+        ])
+        .get_matches();
+
+    let spec = binjs::generic::es6::Library::spec();
+
+    let schema = fs::read(matches.value_of("grammar").unwrap()).expect("Could not read schema");
+    let schema: serde_json::Value =
+        serde_json::from_slice(&schema).expect("Could not parse schema");
+
+    let mut format = binjs::io::Format::from_name(&spec, matches.value_of("format").unwrap(), &[])
+        .expect("Could not parse format");
+    let source = fs::read(matches.value_of("INPUT").unwrap()).expect("Could not read source");
+    let ast: Script = Decoder::new()
+        .decode(&mut format, Cursor::new(source))
+        .expect("Could not decode source");
+    let ast = serde_json::to_value(&ast).expect("Could not convert AST to JSON");
+
+    match Schema::check(&schema, &ast) {
+        Ok(()) => 0,
+        Err(errors) => {
+            for error in &errors {
+                eprintln!("{}", error);
+            }
+            eprintln!("{} violation(s) of the schema", errors.len());
+            1
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Extracting the source of single functions from a BinJS file, without
+//! decoding the rest of the file.
+
+use binjs_es6::ast::{Script, Statement};
+use binjs_es6::io::{Deserializer, IOPath};
+use binjs_io::{Deserialization, TokenReader, TokenReaderError};
+
+use source::shift::{self, Shift};
+use source::SourceParser;
+
+#[derive(Debug)]
+pub enum Error {
+    DecodingError(TokenReaderError),
+    SourceError(shift::Error),
+
+    /// The script has no top-level function with this name.
+    NoSuchFunction(String),
+}
+
+/// A reader decoding the top level of a script, then the contents of
+/// top-level functions on demand.
+///
+/// Only the contents of lazy functions (see `Enrich::lazy_threshold`) may be
+/// skipped. Eager functions are decoded along with the top level.
+pub struct FunctionReader<R>
+where
+    R: TokenReader,
+{
+    deserializer: Deserializer<R>,
+
+    /// The top level of the script. The contents of lazy functions are
+    /// left undecoded.
+    script: Script,
+
+    /// The number of contents of lazy functions decoded so far.
+    bodies_decoded: usize,
+}
+
+impl<R> FunctionReader<R>
+where
+    R: TokenReader,
+{
+    /// Decode the top level of a script.
+    pub fn new(reader: R) -> Result<Self, Error> {
+        let mut deserializer = Deserializer::new(reader).with_deferred_lazy_fields(true);
+        let script = deserializer
+            .deserialize(&mut IOPath::new())
+            .map_err(Error::DecodingError)?;
+        Ok(FunctionReader {
+            deserializer,
+            script,
+            bodies_decoded: 0,
+        })
+    }
+
+    /// The top level of the script, in which the contents of lazy
+    /// functions are `Default::default()`.
+    pub fn script(&self) -> &Script {
+        &self.script
+    }
+
+    /// The number of top-level lazy functions whose contents were decoded so
+    /// far, by `function_source`.
+    pub fn bodies_decoded(&self) -> usize {
+        self.bodies_decoded
+    }
+
+    /// The names of the top-level function declarations, in the order of
+    /// the script.
+    pub fn function_names(&self) -> Vec<&str> {
+        self.script
+            .statements
+            .iter()
+            .filter_map(|statement| match *statement {
+                Statement::EagerFunctionDeclaration(ref declaration) => {
+                    Some(declaration.name.name.as_str())
+                }
+                Statement::LazyFunctionDeclaration(ref declaration) => {
+                    Some(declaration.name.name.as_str())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Decode the top-level function declaration `name`, then print its source.
+    ///
+    /// The contents of a lazy function, then those of the lazy functions nested
+    /// in it, are decoded by seeking to them, without decoding the contents of
+    /// any other function. They are decoded anew on each call, and not stored
+    /// in `script()`.
+    pub fn function_source(&mut self, parser: &Shift, name: &str) -> Result<String, Error> {
+        let found = self
+            .script
+            .statements
+            .iter()
+            .find(|statement| match **statement {
+                Statement::EagerFunctionDeclaration(ref declaration) => {
+                    declaration.name.name.as_str() == name
+                }
+                Statement::LazyFunctionDeclaration(ref declaration) => {
+                    declaration.name.name.as_str() == name
+                }
+                _ => false,
+            });
+        let statement = match found {
+            Some(statement) => statement.clone(),
+            None => return Err(Error::NoSuchFunction(name.to_string())),
+        };
+        // Readers that cannot skip decode the contents along with the top level.
+        let deferred = match statement {
+            Statement::LazyFunctionDeclaration(ref declaration) => {
+                declaration.contents_position.is_some()
+            }
+            _ => false,
+        };
+
+        let mut script = Script {
+            scope: Default::default(),
+            directives: vec![],
+            statements: vec![statement],
+        };
+        self.deserializer
+            .decode_all_bodies(&mut script)
+            .map_err(Error::DecodingError)?;
+        if deferred {
+            self.bodies_decoded += 1;
+        }
+        parser.to_source(&script).map_err(Error::SourceError)
+    }
+}
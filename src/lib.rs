@@ -26,6 +26,7 @@ extern crate binjs_shared;
 
 extern crate serde;
 extern crate serde_json;
+extern crate toml;
 extern crate which;
 
 #[cfg(test)]
@@ -64,3 +65,15 @@ pub mod meta {
 
 /// Parsing source JavaScript.
 pub mod source;
+
+/// Encoding many source files with a single parser and format.
+pub mod batch;
+
+/// Comparing encoding configurations on a corpus.
+pub mod bench;
+
+/// Extracting the source of single functions from a file.
+pub mod extract;
+
+/// Computing the subset of the grammar used by a corpus.
+pub mod subset;
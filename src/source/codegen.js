@@ -1,21 +1,95 @@
 /**
  * This CLI "daemon" expects Shift AST objects (in JSON format) on the stdin
  * and will return a generated JS (as JSON strings) on stdout.
+ *
+ * Alternatively, it accepts objects `{ script, parens }`, in which case it
+ * writes the parentheses designated by the parenthesization hints `parens`
+ * (see parens.js) instead of those required by precedence.
  */
 
 'use strict';
 
 const codegen = require('shift-codegen').default;
+const { ExtensibleCodeGen } = require('shift-codegen');
+const { fromHints } = require('./parens');
 const startJSONStream = require('./start-json-stream');
+const toShift = require('./to-shift');
+
+/**
+ * Stands for a parenthesized expression when its parent is generated, so
+ * that the parent does not add parentheses of its own.
+ */
+const PARENTHESIZED = { type: 'ParenthesizedExpression' };
+
+/**
+ * A codegen writing `counts.get(node)` parentheses around each expression
+ * `node` of `counts`.
+ */
+class ParenthesizingCodeGen extends ExtensibleCodeGen {
+    constructor(counts) {
+        super();
+        this.counts = counts;
+    }
+
+    /**
+     * `node`, with its fields holding expressions of `counts` replaced
+     * by `PARENTHESIZED`.
+     */
+    hideParenthesized(node) {
+        let shown = node;
+        let hide = value => (this.counts.has(value) ? PARENTHESIZED : value);
+        for (let field of Object.keys(node)) {
+            let value = node[field];
+            let hidden = Array.isArray(value) ? value.map(hide) : hide(value);
+            let changed = Array.isArray(value)
+                ? hidden.some((item, i) => item !== value[i])
+                : hidden !== value;
+            if (changed) {
+                if (shown === node) {
+                    shown = Object.assign({}, node);
+                }
+                shown[field] = hidden;
+            }
+        }
+        return shown;
+    }
+}
+
+for (let name of Object.getOwnPropertyNames(ExtensibleCodeGen.prototype)) {
+    if (!name.startsWith('reduce')) {
+        continue;
+    }
+    let reduce = ExtensibleCodeGen.prototype[name];
+    ParenthesizingCodeGen.prototype[name] = function(node, elements) {
+        let rep = reduce.call(this, this.hideParenthesized(node), elements);
+        for (let i = this.counts.get(node) || 0; i > 0; --i) {
+            rep = this.paren(rep);
+        }
+        return rep;
+    };
+}
 
 startJSONStream({
     // `fromJSON` is a `JSON.parse` reviver callback that converts BinaryAST
     // JSON to Shift AST compatible objects on the fly.
-    fromJSON: require('./to-shift'),
+    //
+    // The object wrapping a script along with its hints is not a node.
+    fromJSON: function(key, value) {
+        if (key === '' && value.type === undefined) {
+            return value;
+        }
+        return toShift.call(this, key, value);
+    },
 
     // Mirrors the Rust side of generic value transformations.
     //
     // This one takes an AST from the Rust side as an input and applies
     // a codegen as a transform, returning generated JavaScript back.
-    transform: ast => codegen(ast)
+    transform: input =>
+        input.type === undefined
+            ? codegen(
+                  input.script,
+                  new ParenthesizingCodeGen(fromHints(input.script, input.parens))
+              )
+            : codegen(input)
 });
@@ -0,0 +1,28 @@
+/**
+ * This CLI "daemon" expects ESTree ASTs as JSON on the stdin, e.g. produced
+ * by Acorn, and will convert them to BinaryAST objects as JSON on stdout, as
+ * `{ script }`, or `{ unsupported }` with the kind of the first node that
+ * cannot be converted (see from-estree.js).
+ */
+
+'use strict';
+
+const { fromESTree, UnsupportedNodeKind } = require('./from-estree');
+const startJSONStream = require('./start-json-stream');
+
+startJSONStream({
+    transform: input => {
+        try {
+            return { script: fromESTree(input) };
+        } catch (e) {
+            if (e instanceof UnsupportedNodeKind) {
+                return { unsupported: e.kind };
+            }
+            throw e;
+        }
+    },
+
+    // As in parse_str.js, complete the conversion of the Shift AST produced
+    // by `fromESTree` to BinaryAST JSON.
+    toJSON: require('./from-shift')
+});
@@ -0,0 +1,650 @@
+'use strict';
+
+/**
+ * Conversion of ESTree ASTs, e.g. produced by Acorn, or by Babel with its
+ * `estree` plugin, to Shift AST compatible objects, on which from-shift.js
+ * then completes the conversion to BinaryAST JSON.
+ *
+ * Nodes which from-shift.js leaves alone are produced directly with their
+ * BinaryAST kinds and fields. Node kinds without a BinaryAST counterpart,
+ * e.g. modules, JSX or class fields, throw an `UnsupportedNodeKind`.
+ */
+
+/**
+ * The error thrown on ESTree nodes that cannot be converted, with the
+ * ESTree kind of the node in `kind`.
+ */
+class UnsupportedNodeKind extends Error {
+    constructor(kind) {
+        super(`Unsupported ESTree node kind ${kind}`);
+        this.kind = kind;
+    }
+}
+
+function unsupported(node) {
+    throw new UnsupportedNodeKind(node.type);
+}
+
+/**
+ * Split the statements of a program or function body into its directives,
+ * i.e. the leading statements flagged by the parser as directives, and its
+ * other statements.
+ */
+function directivesAndStatements(body) {
+    let directives = [];
+    let i = 0;
+    for (; i < body.length && typeof body[i].directive === 'string'; ++i) {
+        directives.push({ type: 'Directive', rawValue: body[i].directive });
+    }
+    return { directives, statements: body.slice(i).map(statement) };
+}
+
+function block(node) {
+    if (node.type !== 'BlockStatement') {
+        unsupported(node);
+    }
+    return { type: 'Block', statements: node.body.map(statement) };
+}
+
+function functionBody(node) {
+    let { directives, statements } = directivesAndStatements(node.body);
+    return { type: 'FunctionBody', directives, statements };
+}
+
+function formalParameters(params) {
+    let items = params;
+    let rest = null;
+    if (params.length > 0 && params[params.length - 1].type === 'RestElement') {
+        items = params.slice(0, -1);
+        rest = binding(params[params.length - 1].argument);
+    }
+    return { type: 'FormalParameters', items: items.map(parameter), rest };
+}
+
+/**
+ * A function, as the Shift node `type`, e.g. `FunctionExpression`.
+ */
+function fun(type, node) {
+    return {
+        type,
+        isAsync: !!node.async,
+        isGenerator: !!node.generator,
+        name: node.id ? bindingIdentifier(node.id) : null,
+        params: formalParameters(node.params),
+        body: functionBody(node.body)
+    };
+}
+
+/**
+ * A method, getter or setter named `key`, of kind `kind`, with the function
+ * `value`.
+ */
+function method(kind, key, computed, value) {
+    let name = propertyName(key, computed);
+    switch (kind) {
+        case 'get':
+            return { type: 'Getter', name, body: functionBody(value.body) };
+        case 'set':
+            return {
+                type: 'Setter',
+                name,
+                param: parameter(value.params[0]),
+                body: functionBody(value.body)
+            };
+        default: {
+            let result = fun('Method', value);
+            result.name = name;
+            return result;
+        }
+    }
+}
+
+function propertyName(key, computed) {
+    if (computed) {
+        return { type: 'ComputedPropertyName', expression: expression(key) };
+    }
+    switch (key.type) {
+        case 'Identifier':
+            return { type: 'LiteralPropertyName', value: key.name };
+        case 'Literal':
+            return { type: 'LiteralPropertyName', value: String(key.value) };
+        default:
+            return unsupported(key);
+    }
+}
+
+function classElements(body) {
+    return body.body.map(element => {
+        if (element.type !== 'MethodDefinition') {
+            unsupported(element);
+        }
+        return {
+            type: 'ClassElement',
+            isStatic: element.static,
+            method: method(element.kind, element.key, element.computed, element.value)
+        };
+    });
+}
+
+function bindingIdentifier(node) {
+    if (node.type !== 'Identifier') {
+        unsupported(node);
+    }
+    return { type: 'BindingIdentifier', name: node.name };
+}
+
+function binding(node) {
+    switch (node.type) {
+        case 'Identifier':
+            return bindingIdentifier(node);
+        case 'ObjectPattern':
+            return {
+                type: 'ObjectBinding',
+                properties: node.properties.map(property => {
+                    if (property.type !== 'Property') {
+                        unsupported(property);
+                    }
+                    let { value } = property;
+                    if (property.shorthand) {
+                        let init = value.type === 'AssignmentPattern' ? value.right : null;
+                        return {
+                            type: 'BindingPropertyIdentifier',
+                            binding: bindingIdentifier(property.key),
+                            init: init && expression(init)
+                        };
+                    }
+                    return {
+                        type: 'BindingPropertyProperty',
+                        name: propertyName(property.key, property.computed),
+                        binding: parameter(value)
+                    };
+                })
+            };
+        case 'ArrayPattern': {
+            let { items, rest } = withRest(node.elements);
+            return {
+                type: 'ArrayBinding',
+                elements: items.map(item => item && parameter(item)),
+                rest: rest && binding(rest)
+            };
+        }
+        default:
+            return unsupported(node);
+    }
+}
+
+/**
+ * A binding, possibly with a default value, e.g. a parameter.
+ */
+function parameter(node) {
+    if (node.type === 'AssignmentPattern') {
+        return {
+            type: 'BindingWithInitializer',
+            binding: binding(node.left),
+            init: expression(node.right)
+        };
+    }
+    return binding(node);
+}
+
+/**
+ * The elements of an array pattern, and the argument of its rest element.
+ */
+function withRest(elements) {
+    let last = elements[elements.length - 1];
+    if (last && last.type === 'RestElement') {
+        return { items: elements.slice(0, -1), rest: last.argument };
+    }
+    return { items: elements, rest: null };
+}
+
+function simpleAssignmentTarget(node) {
+    switch (node.type) {
+        case 'Identifier':
+            return { type: 'AssignmentTargetIdentifier', name: node.name };
+        case 'MemberExpression':
+            return node.computed
+                ? {
+                      type: 'ComputedMemberAssignmentTarget',
+                      object: object(node.object),
+                      expression: expression(node.property)
+                  }
+                : {
+                      type: 'StaticMemberAssignmentTarget',
+                      object: object(node.object),
+                      property: node.property.name
+                  };
+        case 'ParenthesizedExpression':
+            return simpleAssignmentTarget(node.expression);
+        default:
+            return unsupported(node);
+    }
+}
+
+function assignmentTarget(node) {
+    switch (node.type) {
+        case 'ObjectPattern':
+            return {
+                type: 'ObjectAssignmentTarget',
+                properties: node.properties.map(property => {
+                    if (property.type !== 'Property') {
+                        unsupported(property);
+                    }
+                    let { value } = property;
+                    if (property.shorthand) {
+                        let init = value.type === 'AssignmentPattern' ? value.right : null;
+                        return {
+                            type: 'AssignmentTargetPropertyIdentifier',
+                            binding: simpleAssignmentTarget(property.key),
+                            init: init && expression(init)
+                        };
+                    }
+                    return {
+                        type: 'AssignmentTargetPropertyProperty',
+                        name: propertyName(property.key, property.computed),
+                        binding: assignmentTargetWithInitializer(value)
+                    };
+                })
+            };
+        case 'ArrayPattern': {
+            let { items, rest } = withRest(node.elements);
+            return {
+                type: 'ArrayAssignmentTarget',
+                elements: items.map(item => item && assignmentTargetWithInitializer(item)),
+                rest: rest && assignmentTarget(rest)
+            };
+        }
+        default:
+            return simpleAssignmentTarget(node);
+    }
+}
+
+function assignmentTargetWithInitializer(node) {
+    if (node.type === 'AssignmentPattern') {
+        return {
+            type: 'AssignmentTargetWithInitializer',
+            binding: assignmentTarget(node.left),
+            init: expression(node.right)
+        };
+    }
+    return assignmentTarget(node);
+}
+
+/**
+ * The object of a member expression or the callee of a call, which may be
+ * `super`.
+ */
+function object(node) {
+    return node.type === 'Super' ? { type: 'Super' } : expression(node);
+}
+
+function spreadOrExpression(node) {
+    return node.type === 'SpreadElement'
+        ? { type: 'SpreadElement', expression: expression(node.argument) }
+        : expression(node);
+}
+
+function literal(node) {
+    if (node.regex) {
+        let { pattern, flags } = node.regex;
+        if (/[^gimyu]/.test(flags)) {
+            unsupported(node);
+        }
+        return {
+            type: 'LiteralRegExpExpression',
+            pattern,
+            global: flags.includes('g'),
+            ignoreCase: flags.includes('i'),
+            multiLine: flags.includes('m'),
+            sticky: flags.includes('y'),
+            unicode: flags.includes('u')
+        };
+    }
+    if (typeof node.bigint === 'string') {
+        // As written, without the `n` suffix, see literals.rs.
+        let value = typeof node.raw === 'string' ? node.raw.slice(0, -1) : node.bigint;
+        return { type: 'LiteralBigIntExpression', value: value.replace(/_/g, '') };
+    }
+    switch (typeof node.value) {
+        case 'string':
+            return { type: 'LiteralStringExpression', value: node.value };
+        case 'number':
+            return { type: 'LiteralNumericExpression', value: node.value };
+        case 'boolean':
+            return { type: 'LiteralBooleanExpression', value: node.value };
+        default:
+            if (node.value === null) {
+                return { type: 'LiteralNullExpression' };
+            }
+            return unsupported(node);
+    }
+}
+
+function templateElements(quasi) {
+    let elements = [];
+    quasi.quasis.forEach((element, i) => {
+        elements.push({ type: 'TemplateElement', rawValue: element.value.raw });
+        if (i < quasi.expressions.length) {
+            elements.push(expression(quasi.expressions[i]));
+        }
+    });
+    return elements;
+}
+
+function expression(node) {
+    switch (node.type) {
+        case 'Identifier':
+            return { type: 'IdentifierExpression', name: node.name };
+        case 'Literal':
+            return literal(node);
+        case 'ThisExpression':
+            return { type: 'ThisExpression' };
+        case 'ArrayExpression':
+            return {
+                type: 'ArrayExpression',
+                elements: node.elements.map(element => element && spreadOrExpression(element))
+            };
+        case 'ObjectExpression':
+            return {
+                type: 'ObjectExpression',
+                properties: node.properties.map(property => {
+                    if (property.type !== 'Property') {
+                        unsupported(property);
+                    }
+                    if (property.kind !== 'init' || property.method) {
+                        return method(property.kind, property.key, property.computed, property.value);
+                    }
+                    if (property.shorthand) {
+                        return {
+                            type: 'ShorthandProperty',
+                            name: { type: 'IdentifierExpression', name: property.key.name }
+                        };
+                    }
+                    return {
+                        type: 'DataProperty',
+                        name: propertyName(property.key, property.computed),
+                        expression: expression(property.value)
+                    };
+                })
+            };
+        case 'FunctionExpression':
+            return fun('FunctionExpression', node);
+        case 'ArrowFunctionExpression':
+            return {
+                type: 'ArrowExpression',
+                isAsync: !!node.async,
+                params: formalParameters(node.params),
+                body:
+                    node.body.type === 'BlockStatement'
+                        ? functionBody(node.body)
+                        : expression(node.body)
+            };
+        case 'ClassExpression':
+            return {
+                type: 'ClassExpression',
+                name: node.id ? bindingIdentifier(node.id) : null,
+                super: node.superClass ? expression(node.superClass) : null,
+                elements: classElements(node.body)
+            };
+        case 'TemplateLiteral':
+            return { type: 'TemplateExpression', tag: null, elements: templateElements(node) };
+        case 'TaggedTemplateExpression':
+            return {
+                type: 'TemplateExpression',
+                tag: expression(node.tag),
+                elements: templateElements(node.quasi)
+            };
+        case 'MemberExpression':
+            if (node.optional) {
+                return unsupported(node);
+            }
+            return node.computed
+                ? {
+                      type: 'ComputedMemberExpression',
+                      object: object(node.object),
+                      expression: expression(node.property)
+                  }
+                : {
+                      type: 'StaticMemberExpression',
+                      object: object(node.object),
+                      property: node.property.name
+                  };
+        case 'CallExpression':
+            if (node.optional) {
+                return unsupported(node);
+            }
+            return {
+                type: 'CallExpression',
+                callee: object(node.callee),
+                arguments: node.arguments.map(spreadOrExpression)
+            };
+        case 'NewExpression':
+            return {
+                type: 'NewExpression',
+                callee: expression(node.callee),
+                arguments: node.arguments.map(spreadOrExpression)
+            };
+        case 'MetaProperty':
+            if (node.meta.name !== 'new' || node.property.name !== 'target') {
+                return unsupported(node);
+            }
+            return { type: 'NewTargetExpression' };
+        case 'UpdateExpression':
+            return {
+                type: 'UpdateExpression',
+                isPrefix: node.prefix,
+                operator: node.operator,
+                operand: simpleAssignmentTarget(node.argument)
+            };
+        case 'UnaryExpression':
+            return {
+                type: 'UnaryExpression',
+                operator: node.operator,
+                operand: expression(node.argument)
+            };
+        case 'BinaryExpression':
+        case 'LogicalExpression':
+            if (node.operator === '??') {
+                return unsupported(node);
+            }
+            return {
+                type: 'BinaryExpression',
+                operator: node.operator,
+                left: expression(node.left),
+                right: expression(node.right)
+            };
+        case 'SequenceExpression':
+            // As the Shift parser, a left-nested comma operator.
+            return node.expressions
+                .map(expression)
+                .reduce((left, right) => ({ type: 'BinaryExpression', operator: ',', left, right }));
+        case 'AssignmentExpression':
+            if (node.operator === '=') {
+                return {
+                    type: 'AssignmentExpression',
+                    binding: assignmentTarget(node.left),
+                    expression: expression(node.right)
+                };
+            }
+            return {
+                type: 'CompoundAssignmentExpression',
+                operator: node.operator,
+                binding: simpleAssignmentTarget(node.left),
+                expression: expression(node.right)
+            };
+        case 'ConditionalExpression':
+            return {
+                type: 'ConditionalExpression',
+                test: expression(node.test),
+                consequent: expression(node.consequent),
+                alternate: expression(node.alternate)
+            };
+        case 'YieldExpression':
+            if (node.delegate) {
+                return { type: 'YieldStarExpression', expression: expression(node.argument) };
+            }
+            return {
+                type: 'YieldExpression',
+                expression: node.argument ? expression(node.argument) : null
+            };
+        case 'AwaitExpression':
+            return { type: 'AwaitExpression', expression: expression(node.argument) };
+        case 'ParenthesizedExpression':
+            return expression(node.expression);
+        default:
+            return unsupported(node);
+    }
+}
+
+function variableDeclaration(node) {
+    return {
+        type: 'VariableDeclaration',
+        kind: node.kind,
+        declarators: node.declarations.map(declarator => ({
+            type: 'VariableDeclarator',
+            binding: binding(declarator.id),
+            init: declarator.init ? expression(declarator.init) : null
+        }))
+    };
+}
+
+/**
+ * The left-hand side of a `for-in` or `for-of` loop.
+ */
+function forInOfLeft(node) {
+    if (node.type === 'VariableDeclaration') {
+        return {
+            type: 'ForInOfBinding',
+            kind: node.kind,
+            binding: binding(node.declarations[0].id)
+        };
+    }
+    return assignmentTarget(node);
+}
+
+function catchClause(node) {
+    if (!node.param) {
+        return unsupported(node);
+    }
+    return { type: 'CatchClause', binding: binding(node.param), body: block(node.body) };
+}
+
+function switchStatement(node) {
+    let discriminant = expression(node.discriminant);
+    let cases = node.cases.map(item =>
+        item.test
+            ? { type: 'SwitchCase', test: expression(item.test), consequent: item.consequent.map(statement) }
+            : { type: 'SwitchDefault', consequent: item.consequent.map(statement) }
+    );
+    let index = cases.findIndex(item => item.type === 'SwitchDefault');
+    if (index === -1) {
+        return { type: 'SwitchStatement', discriminant, cases };
+    }
+    return {
+        type: 'SwitchStatementWithDefault',
+        discriminant,
+        preDefaultCases: cases.slice(0, index),
+        defaultCase: cases[index],
+        postDefaultCases: cases.slice(index + 1)
+    };
+}
+
+function statement(node) {
+    switch (node.type) {
+        case 'ExpressionStatement':
+            return { type: 'ExpressionStatement', expression: expression(node.expression) };
+        case 'BlockStatement':
+            return block(node);
+        case 'EmptyStatement':
+        case 'DebuggerStatement':
+            return { type: node.type };
+        case 'WithStatement':
+            return { type: 'WithStatement', object: expression(node.object), body: statement(node.body) };
+        case 'ReturnStatement':
+            return {
+                type: 'ReturnStatement',
+                expression: node.argument ? expression(node.argument) : null
+            };
+        case 'LabeledStatement':
+            return { type: 'LabelledStatement', label: node.label.name, body: statement(node.body) };
+        case 'BreakStatement':
+        case 'ContinueStatement':
+            return { type: node.type, label: node.label ? node.label.name : null };
+        case 'IfStatement':
+            return {
+                type: 'IfStatement',
+                test: expression(node.test),
+                consequent: statement(node.consequent),
+                alternate: node.alternate ? statement(node.alternate) : null
+            };
+        case 'SwitchStatement':
+            return switchStatement(node);
+        case 'ThrowStatement':
+            return { type: 'ThrowStatement', expression: expression(node.argument) };
+        case 'TryStatement': {
+            let body = block(node.block);
+            let clause = node.handler ? catchClause(node.handler) : null;
+            if (!node.finalizer) {
+                return { type: 'TryCatchStatement', body, catchClause: clause };
+            }
+            return {
+                type: 'TryFinallyStatement',
+                body,
+                catchClause: clause,
+                finalizer: block(node.finalizer)
+            };
+        }
+        case 'WhileStatement':
+        case 'DoWhileStatement':
+            return { type: node.type, test: expression(node.test), body: statement(node.body) };
+        case 'ForStatement':
+            return {
+                type: 'ForStatement',
+                init: !node.init
+                    ? null
+                    : node.init.type === 'VariableDeclaration'
+                    ? variableDeclaration(node.init)
+                    : expression(node.init),
+                test: node.test ? expression(node.test) : null,
+                update: node.update ? expression(node.update) : null,
+                body: statement(node.body)
+            };
+        case 'ForInStatement':
+        case 'ForOfStatement':
+            if (node.await) {
+                return unsupported(node);
+            }
+            return {
+                type: node.type,
+                left: forInOfLeft(node.left),
+                right: expression(node.right),
+                body: statement(node.body)
+            };
+        case 'FunctionDeclaration':
+            return fun('FunctionDeclaration', node);
+        case 'VariableDeclaration':
+            return variableDeclaration(node);
+        case 'ClassDeclaration':
+            return {
+                type: 'ClassDeclaration',
+                name: bindingIdentifier(node.id),
+                super: node.superClass ? expression(node.superClass) : null,
+                elements: classElements(node.body)
+            };
+        default:
+            return unsupported(node);
+    }
+}
+
+/**
+ * Convert an ESTree `Program` to a Shift `Script`.
+ */
+function fromESTree(program) {
+    if (program.type !== 'Program') {
+        unsupported(program);
+    }
+    let { directives, statements } = directivesAndStatements(program.body);
+    return { type: 'Script', directives, statements };
+}
+
+module.exports = { fromESTree, UnsupportedNodeKind };
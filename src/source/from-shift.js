@@ -134,7 +134,9 @@ function createFunctionContents(obj) {
     contents.body = body;
 
     obj.contents = contents;
-    obj.directives = directives;
+    if (!isExpressionBody) {
+        obj.directives = directives;
+    }
 
     makeEager(obj);
 }
@@ -176,6 +178,13 @@ function convertObject(obj) {
             if (obj.sticky) flags += 'y';
             if (obj.unicode) flags += 'u';
             obj.flags = flags;
+            // Only `flags` is part of the grammar.
+            delete obj.global;
+            delete obj.ignoreCase;
+            delete obj.multiLine;
+            delete obj.sticky;
+            delete obj.unicode;
+            delete obj.dotAll;
             break;
         }
         case 'Script':
@@ -0,0 +1,108 @@
+//! Reading an AST already in JSON format, e.g. produced by another tool.
+//!
+//! The JSON follows either the grammar: one object per node, with its kind
+//! in field `type` and one field per attribute of the interface, as produced
+//! by `Decoder::decode_to_binast_json`; or ESTree, as produced by Acorn or
+//! Babel, which is first converted to the grammar by `ESTree` (in Node).
+
+use binjs_es6::ast::Script;
+use binjs_es6::io::Encoder;
+use binjs_generic::schema::Schema;
+use binjs_generic::validate::ValidationError;
+use binjs_io::{BinjsError, Format};
+
+use source::shift::{self, NodeConfig};
+
+use serde::Deserialize;
+use serde_json::Value as JSON;
+
+use std::io::Write;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The JSON uses node kinds or fields unknown to the grammar, with the
+    /// path to each offending node.
+    SchemaError(Vec<ValidationError>),
+
+    /// The JSON does not match the types of the grammar, e.g. a missing field.
+    JSONError(serde_json::Error),
+
+    EncodingError(BinjsError),
+
+    /// The ESTree JSON holds a node of this kind, which has no counterpart
+    /// in the grammar, e.g. `ImportDeclaration` or `JSXElement`.
+    UnsupportedNodeKind(String),
+
+    /// The ESTree JSON could not be converted by Node, e.g. it is not an AST.
+    ConversionError(shift::Error),
+
+    IOError(std::io::Error),
+}
+
+/// Convert a JSON AST into a `Script`, checking its node kinds and fields
+/// against the grammar first.
+pub fn script_from_json(value: &JSON) -> Result<Script, Error> {
+    let spec = binjs_generic::es6::Library::spec();
+    Schema::check(&Schema::to_json(&spec), value).map_err(Error::SchemaError)?;
+    Script::deserialize(value).map_err(Error::JSONError)
+}
+
+/// Encode a JSON AST to `format`, without a round-trip through the text source.
+pub fn encode_from_json(format: &mut Format, value: &JSON) -> Result<Box<[u8]>, Error> {
+    let script = script_from_json(value)?;
+    Encoder::new()
+        .encode(None, format, &script)
+        .map_err(Error::EncodingError)
+}
+
+/// Converting ESTree JSON to the grammar, through a Node.js script (see
+/// estree.js).
+pub struct ESTree {
+    estree: shift::Script,
+}
+
+impl ESTree {
+    pub fn try_new() -> Result<Self, shift::Error> {
+        let node = NodeConfig::try_new()?;
+        Ok(ESTree {
+            estree: shift::Script::try_new(&node, "estree.js")?,
+        })
+    }
+
+    /// Convert an ESTree `Program` to the JSON of a `Script`, as accepted by
+    /// `script_from_json`.
+    pub fn to_json(&self, value: &JSON) -> Result<JSON, Error> {
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(default)]
+            script: Option<JSON>,
+            #[serde(default)]
+            unsupported: Option<String>,
+        }
+
+        let response: Response = self
+            .estree
+            .transform(value)
+            .map_err(Error::ConversionError)?;
+        match (response.script, response.unsupported) {
+            (_, Some(kind)) => Err(Error::UnsupportedNodeKind(kind)),
+            (Some(script), None) => Ok(script),
+            (None, None) => Err(Error::ConversionError(shift::Error::ParsingError(
+                "No script in response".to_string(),
+            ))),
+        }
+    }
+
+    /// Encode an ESTree `Program` to `format`, writing it to `out`, without
+    /// a round-trip through the text source.
+    pub fn encode_from_estree_json<W: Write>(
+        &self,
+        format: &mut Format,
+        value: &JSON,
+        out: &mut W,
+    ) -> Result<(), Error> {
+        let value = self.to_json(value)?;
+        let data = encode_from_json(format, &value)?;
+        out.write_all(&data).map_err(Error::IOError)
+    }
+}
@@ -0,0 +1,153 @@
+//! Rewriting the literals which the parser does not support yet.
+//!
+//! - numeric separators are removed, e.g. `1_000_000` becomes `1000000`;
+//! - BigInt literals are replaced with calls, e.g. `0x1F_FFn` becomes
+//!   `__binjs_bigint__("0x1FFF")`, which parse_str.js turns back into
+//!   `LiteralBigIntExpression` nodes.
+//!
+//! Literals are only rewritten where the parser reports a syntax error, one
+//! at a time, so that text which merely looks like such a literal, e.g. in
+//! a regular expression, is left alone.
+//!
+//! Rewritten literals change length, so positions following them, e.g.
+//! those of syntax errors, are shifted.
+
+use source::shift::ParseError;
+use source::typescript::error_at;
+
+/// The function standing for BigInt literals in the source given to the parser.
+pub const BIGINT_PLACEHOLDER: &str = "__binjs_bigint__";
+
+/// A literal rewritten by `rewrite_literal_at`.
+pub struct Rewrite {
+    /// The source, with the literal rewritten.
+    pub source: String,
+
+    /// `true` if the literal was a BigInt, replaced with a call to
+    /// `BIGINT_PLACEHOLDER`.
+    pub bigint: bool,
+}
+
+/// Fail if `source` mentions `BIGINT_PLACEHOLDER` anywhere, e.g. in a
+/// comment, as calls to it are read as BigInt literals.
+pub fn check_reserved(source: &str) -> Result<(), ParseError> {
+    match source.find(BIGINT_PLACEHOLDER) {
+        Some(offset) => Err(error_at(
+            source,
+            offset,
+            &format!("Reserved identifier {}", BIGINT_PLACEHOLDER),
+        )),
+        None => Ok(()),
+    }
+}
+
+fn is_literal_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+/// Rewrite the literal of `source` at byte `offset`, or just before it,
+/// where the parser reported a syntax error, or return `None` if there is
+/// no literal with numeric separators or a BigInt suffix there.
+pub fn rewrite_literal_at(source: &str, offset: usize) -> Option<Rewrite> {
+    let bytes = source.as_bytes();
+    let offset = std::cmp::min(offset, bytes.len());
+    let mut start = offset;
+    while start > 0 && is_literal_byte(bytes[start - 1]) {
+        start -= 1;
+    }
+    let mut end = offset;
+    while end < bytes.len() && is_literal_byte(bytes[end]) {
+        end += 1;
+    }
+    if start == end || !bytes[start].is_ascii_digit() {
+        return None;
+    }
+    // Part of an identifier, e.g. `$1_0`, rather than a literal.
+    if start > 0
+        && (bytes[start - 1] == b'$' || bytes[start - 1] == b'\\' || bytes[start - 1] >= 0x80)
+    {
+        return None;
+    }
+
+    // As the parser stopped at the first unsupported character, `text`
+    // may be a part of the literal, e.g. `1_0e` in `1_0e+5`, which does
+    // not matter to separators.
+    let text = &source[start..end];
+    let bigint = text.ends_with('n');
+    let digits = if bigint {
+        &text[..text.len() - 1]
+    } else {
+        text
+    };
+    let prefixed = digits.len() > 2 && digits.starts_with('0') && "xXoObB".contains(&digits[1..2]);
+    let valid = if prefixed {
+        digits[2..]
+            .bytes()
+            .all(|byte| byte.is_ascii_hexdigit() || byte == b'_')
+    } else {
+        digits
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || byte == b'_')
+            || (!bigint
+                && digits.bytes().all(|byte| {
+                    byte.is_ascii_digit() || byte == b'_' || byte == b'e' || byte == b'E'
+                }))
+    };
+    // BigInt literals are integers, e.g. not `1.5n`.
+    let fraction = start > 0 && bytes[start - 1] == b'.';
+    if !valid || !(bigint || digits.contains('_')) || (bigint && fraction) {
+        return None;
+    }
+
+    let digits = digits.replace('_', "");
+    let literal = if bigint {
+        format!("{}(\"{}\")", BIGINT_PLACEHOLDER, digits)
+    } else {
+        digits
+    };
+    Some(Rewrite {
+        source: format!("{}{}{}", &source[..start], literal, &source[end..]),
+        bigint,
+    })
+}
+
+#[test]
+fn test_rewrite_literal_at() {
+    let rewrite = |source: &str, offset: usize| {
+        rewrite_literal_at(source, offset).map(|rewrite| (rewrite.source, rewrite.bigint))
+    };
+
+    println!("* Literals are rewritten at the offset of the error");
+    assert_eq!(
+        rewrite("x = 1_000_000;", 5),
+        Some(("x = 1000000;".to_string(), false))
+    );
+    assert_eq!(
+        rewrite("x = 0x1F_FFn + 1_0;", 8),
+        Some(("x = __binjs_bigint__(\"0x1FFF\") + 1_0;".to_string(), true))
+    );
+    assert_eq!(
+        rewrite("x = 10n;", 7),
+        Some(("x = __binjs_bigint__(\"10\");".to_string(), true))
+    );
+    assert_eq!(
+        rewrite("x = 1_0e+5;", 5),
+        Some(("x = 10e+5;".to_string(), false))
+    );
+
+    println!("* Other errors are left alone");
+    assert_eq!(rewrite("x = 1000;", 5), None);
+    assert_eq!(rewrite("x = a_b;", 5), None);
+    assert_eq!(rewrite("x = $1_0;", 6), None);
+    assert_eq!(rewrite("x = 1.5n;", 7), None);
+    assert_eq!(rewrite("x = ;", 4), None);
+
+    println!("* The placeholder is reserved");
+    assert!(check_reserved("x = 1;").is_ok());
+    assert_eq!(
+        check_reserved("// __binjs_bigint__\nx;")
+            .expect_err("Placeholder was accepted")
+            .line,
+        1
+    );
+}
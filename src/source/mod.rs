@@ -7,4 +7,16 @@ pub use self::parser::SourceParser;
 
 /// Parsing JavaScript using the Shift source parser (in Node).
 pub mod shift;
-pub use self::shift::Shift;
+pub use self::shift::{ParseError, Shift};
+
+/// Rewriting the literals which the parser does not support yet.
+pub mod literals;
+
+/// Reading an AST already in JSON format, following either the grammar or
+/// ESTree.
+pub mod json;
+pub use self::json::{encode_from_json, ESTree};
+
+/// Stripping TypeScript-specific syntax, to encode TypeScript sources.
+pub mod typescript;
+pub use self::typescript::strip_types;
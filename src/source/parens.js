@@ -0,0 +1,149 @@
+/**
+ * Parenthesization hints: the parentheses written around expressions of a
+ * source, other than those of the enclosing construct, e.g. the condition
+ * of an `if` or the single argument of a call.
+ *
+ * Hints are `{ expressions, hints }`, where `hints` are `[index, count]`
+ * pairs, `index` being the rank of the expression in the order of
+ * `forEachExpression`, and `expressions` the number of expressions of the
+ * tree. Both the parser and the codegen number expressions this way, so
+ * hints recorded while parsing designate the same expressions in the tree
+ * decoded from BinAST, provided that both trees have the same expressions,
+ * which `fromHints` checks by their number.
+ */
+
+'use strict';
+
+/**
+ * Call `callback(node, parent, key)` on each expression of `tree`, in
+ * pre-order, visiting the fields of each node in the order of their names.
+ *
+ * Nodes reachable from several fields, e.g. the `params` and `body` that
+ * to-shift.js copies out of function `contents`, are visited once.
+ */
+function forEachExpression(tree, callback) {
+    let visited = new Set();
+    let visit = (node, parent, key) => {
+        if (typeof node !== 'object' || node === null || visited.has(node)) {
+            return;
+        }
+        visited.add(node);
+        if (Array.isArray(node)) {
+            for (let item of node) {
+                visit(item, parent, key);
+            }
+            return;
+        }
+        if (typeof node.type === 'string' && node.type.endsWith('Expression')) {
+            callback(node, parent, key);
+        }
+        for (let field of Object.keys(node).sort()) {
+            visit(node[field], node, field);
+        }
+    };
+    visit(tree, null, null);
+}
+
+/**
+ * The number of parentheses the syntax of `parent` requires around its
+ * field `key`, e.g. the condition of an `if`.
+ */
+function syntacticParens(parent, key) {
+    if (parent === null) {
+        return 0;
+    }
+    switch (parent.type) {
+        case 'CallExpression':
+        case 'NewExpression':
+            return key === 'arguments' && parent.arguments.length === 1 ? 1 : 0;
+        case 'IfStatement':
+        case 'WhileStatement':
+        case 'DoWhileStatement':
+            return key === 'test' ? 1 : 0;
+        case 'SwitchStatement':
+        case 'SwitchStatementWithDefault':
+            return key === 'discriminant' ? 1 : 0;
+        case 'WithStatement':
+            return key === 'object' ? 1 : 0;
+        default:
+            return 0;
+    }
+}
+
+/**
+ * Add to `counts` the number of parentheses written around each expression
+ * of `tree`, parsed from `code` with `locations`, other than those of its
+ * parent.
+ *
+ * Only whitespace may separate the parentheses from the expression.
+ */
+function countParens(tree, locations, code, counts) {
+    let isSpace = c => /\s/.test(c);
+    forEachExpression(tree, (node, parent, key) => {
+        let location = locations.get(node);
+        if (!location) {
+            return;
+        }
+        let before = 0;
+        for (let i = location.start.offset - 1; i >= 0; --i) {
+            if (code[i] === '(') {
+                before += 1;
+            } else if (!isSpace(code[i])) {
+                break;
+            }
+        }
+        let after = 0;
+        for (let i = location.end.offset; i < code.length; ++i) {
+            if (code[i] === ')') {
+                after += 1;
+            } else if (!isSpace(code[i])) {
+                break;
+            }
+        }
+        let count = Math.min(before, after) - syntacticParens(parent, key);
+        if (count > 0) {
+            counts.set(node, count);
+        }
+    });
+}
+
+/**
+ * The hints of `tree`, from the counts of `countParens`.
+ */
+function toHints(tree, counts) {
+    let hints = [];
+    let index = 0;
+    forEachExpression(tree, node => {
+        if (counts.has(node)) {
+            hints.push([index, counts.get(node)]);
+        }
+        index += 1;
+    });
+    return { expressions: index, hints };
+}
+
+/**
+ * The counts of the expressions of `tree` designated by the hints `parens`.
+ *
+ * Fails if `tree` does not have as many expressions as the tree the hints
+ * were recorded on, as they would designate other expressions.
+ */
+function fromHints(tree, parens) {
+    let byIndex = new Map(parens.hints);
+    let counts = new Map();
+    let index = 0;
+    forEachExpression(tree, node => {
+        if (byIndex.has(index)) {
+            counts.set(node, byIndex.get(index));
+        }
+        index += 1;
+    });
+    if (index !== parens.expressions) {
+        throw new Error(
+            `Parenthesization hints were recorded on ${parens.expressions} expressions, found ${index}`
+        );
+    }
+    return counts;
+}
+
+module.exports = { countParens, toHints, fromHints };
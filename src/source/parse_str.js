@@ -1,19 +1,339 @@
 /**
  * This CLI "daemon" expects JSON strings containing JavaScript code on the
  * stdin and will parse them and produce BinaryAST objects as JSON on stdout.
+ *
+ * Alternatively, it accepts objects `{ code, bigints }`, where `bigints` is the
+ * number of BigInt literals replaced with calls to `BIGINT_PLACEHOLDER` (see
+ * literals.rs), and produces `{ script }`, or `{ error }` with the position of
+ * the first syntax error, for the Rust side to rewrite the literal there.
+ *
+ * With `tolerant: true`, it parses the code with error recovery instead, and
+ * produces `{ script, errors }`. With `parens: true`, it also produces the
+ * parenthesization hints of the code as `parens` (see parens.js).
  */
 
 'use strict';
 
-const { parseScript } = require('shift-parser');
+const { parseScript, parseScriptWithLocation } = require('shift-parser');
+const { countParens, toHints } = require('./parens');
 const startJSONStream = require('./start-json-stream');
 
+const OPTIONS = { earlyErrors: false };
+
+// The function standing for BigInt literals, which the parser does not
+// support, see literals.rs.
+const BIGINT_PLACEHOLDER = '__binjs_bigint__';
+
+/**
+ * Replace the calls to `BIGINT_PLACEHOLDER` in `tree` with BigInt literals,
+ * which keep the location of the call, if any.
+ *
+ * Returns the tree and the number of literals.
+ */
+function restoreBigInts(tree, locations) {
+    let bigints = 0;
+    let visit = node => {
+        if (typeof node !== 'object' || node === null) {
+            return node;
+        }
+        let { callee, arguments: args } = node;
+        if (
+            node.type === 'CallExpression' &&
+            callee.type === 'IdentifierExpression' &&
+            callee.name === BIGINT_PLACEHOLDER &&
+            args.length === 1 &&
+            args[0].type === 'LiteralStringExpression'
+        ) {
+            bigints += 1;
+            let literal = { type: 'LiteralBigIntExpression', value: args[0].value };
+            if (locations && locations.has(node)) {
+                locations.set(literal, locations.get(node));
+            }
+            return literal;
+        }
+        for (let key of Object.keys(node)) {
+            node[key] = visit(node[key]);
+        }
+        return node;
+    };
+    return { tree: visit(tree), bigints };
+}
+
+/**
+ * Parse `code`, restoring its BigInt literals. If `counts` is specified, add
+ * the number of parentheses around its expressions to `counts`.
+ *
+ * Returns the tree and the number of BigInt literals.
+ */
+function parse(code, counts) {
+    if (!counts) {
+        return restoreBigInts(parseScript(code, OPTIONS), null);
+    }
+    let { tree, locations } = parseScriptWithLocation(code, OPTIONS);
+    let restored = restoreBigInts(tree, locations);
+    countParens(restored.tree, locations, code, counts);
+    return restored;
+}
+
+/**
+ * Fail if more than the `bigints` placeholders inserted by the Rust side
+ * were `found`, as user code may call the placeholder too, e.g. with an
+ * escaped name.
+ */
+function checkBigInts(found, bigints) {
+    if (found > bigints) {
+        throw new Error(`Reserved identifier ${BIGINT_PLACEHOLDER}`);
+    }
+}
+
+/**
+ * Parse `code`, in which `bigints` BigInt literals were replaced with calls
+ * to `BIGINT_PLACEHOLDER`.
+ *
+ * Returns `{ script }`, or `{ error }` on a syntax error.
+ */
+function parseLiterals(code, bigints) {
+    try {
+        let { tree, bigints: found } = parse(code, null);
+        checkBigInts(found, bigints);
+        return { script: tree };
+    } catch (e) {
+        if (typeof e.index !== 'number') {
+            throw e;
+        }
+        return {
+            error: {
+                message: e.description || e.message,
+                line: e.line,
+                column: e.column,
+                index: e.index
+            }
+        };
+    }
+}
+
+/**
+ * The offsets in `code` at which a top-level statement may start: after a
+ * `;`, a `}` closing a top-level block or a line break, outside of brackets,
+ * strings, template literals, comments and regular expressions. A `}` or
+ * line break followed by a character that may continue an expression, e.g.
+ * `.` in `{ a: 1 }.a` or `+` on the next line, is not a boundary.
+ *
+ * This is only a scan, not a parse: regular expressions are told apart from
+ * divisions by the preceding character, and substitutions in template
+ * literals are not scanned. This is enough to pick places to resume after
+ * a syntax error, as every prefix is parsed before it is kept.
+ */
+function statementBoundaries(code) {
+    let boundaries = [];
+    // Boundaries after a `}` or a line break, until the next character.
+    let pending = [];
+    let depth = 0;
+    // The last character other than whitespace and comments.
+    let previous = '';
+    // Skip to the end of a literal opened at `i` and closed by `close`,
+    // stopping at line breaks unless `multiline`.
+    let skip = (i, close, multiline) => {
+        let inClass = false;
+        for (++i; i < code.length; ++i) {
+            let c = code[i];
+            if (c === '\\') {
+                ++i;
+            } else if (c === '\n' && !multiline) {
+                return i - 1;
+            } else if (close === '/' && c === '[') {
+                inClass = true;
+            } else if (close === '/' && c === ']') {
+                inClass = false;
+            } else if (c === close && !inClass) {
+                return i;
+            }
+        }
+        return code.length;
+    };
+    for (let i = 0; i < code.length; ++i) {
+        let c = code[i];
+        if (c === '/' && code[i + 1] === '/') {
+            let end = code.indexOf('\n', i);
+            i = end === -1 ? code.length : end - 1;
+            continue;
+        }
+        if (c === '/' && code[i + 1] === '*') {
+            let end = code.indexOf('*/', i + 2);
+            i = end === -1 ? code.length : end + 1;
+            continue;
+        }
+        if (c === '\n') {
+            if (depth === 0) {
+                pending.push(i + 1);
+            }
+            continue;
+        }
+        if (/\s/.test(c)) {
+            continue;
+        }
+        if (pending.length > 0) {
+            if (!'.,;:?=+-*/%<>&|^(['.includes(c)) {
+                boundaries.push(...pending);
+            }
+            pending = [];
+        }
+        if (c === '"' || c === "'") {
+            i = skip(i, c, false);
+        } else if (c === '`') {
+            i = skip(i, c, true);
+        } else if (c === '/' && (previous === '' || '(,=:[!&|?{};+-*%<>~^'.includes(previous))) {
+            i = skip(i, c, false);
+        } else if (c === '(' || c === '[' || c === '{') {
+            depth += 1;
+        } else if (c === ')' || c === ']' || c === '}') {
+            depth = Math.max(depth - 1, 0);
+            if (c === '}' && depth === 0) {
+                pending.push(i + 1);
+            }
+        } else if (c === ';' && depth === 0) {
+            boundaries.push(i + 1);
+        }
+        previous = c;
+    }
+    boundaries.push(...pending);
+    return boundaries;
+}
+
+/**
+ * The last of `boundaries` in `(start, end]`, or `start` if there is none.
+ */
+function lastBoundary(boundaries, start, end) {
+    let result = start;
+    let low = 0;
+    let high = boundaries.length;
+    while (low < high) {
+        let middle = (low + high) >> 1;
+        if (boundaries[middle] <= end) {
+            result = boundaries[middle];
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    return Math.max(result, start);
+}
+
+/**
+ * The first of `boundaries` after `index`, or `code.length` if there is none.
+ */
+function nextBoundary(boundaries, index, code) {
+    let low = 0;
+    let high = boundaries.length;
+    while (low < high) {
+        let middle = (low + high) >> 1;
+        if (boundaries[middle] <= index) {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    return low < boundaries.length ? boundaries[low] : code.length;
+}
+
+/**
+ * Parse `code`, skipping the top-level statements that cause syntax errors.
+ *
+ * On each error, the statements preceding the one of the error are kept,
+ * then parsing resumes after it, at the next statement boundary (see
+ * `statementBoundaries`), using the position of the error reported by the
+ * parser. An error inside a function or block drops the whole enclosing
+ * top-level statement, rather than hoisting the rest of its statements to
+ * the top level. Each error costs one parse of the rest of the code.
+ *
+ * Returns a Shift `Script` with all the statements that could be parsed,
+ * along with the list of errors, with their position in `code`, and the
+ * parenthesization hints of the script if `parens` is `true`.
+ *
+ * Of the `bigints` placeholders of BigInt literals in `code`, those of the
+ * statements skipped are lost.
+ */
+function parseTolerant(code, parens, bigints) {
+    let counts = parens ? new Map() : null;
+    let directives = [];
+    let statements = [];
+    let errors = [];
+    let found = 0;
+    let keep = ({ tree: script, bigints }, start) => {
+        if (start === 0) {
+            directives = script.directives;
+        }
+        statements.push(...script.statements);
+        found += bigints;
+    };
+    let errorIndex = (e, start) => {
+        if (typeof e.index !== 'number') {
+            throw e;
+        }
+        return start + e.index;
+    };
+    // Computed on the first error.
+    let boundaries = null;
+    let start = 0;
+    while (start < code.length) {
+        let index;
+        try {
+            keep(parse(code.slice(start), counts), start);
+            break;
+        } catch (e) {
+            index = errorIndex(e, start);
+            let lineStart = code.lastIndexOf('\n', index - 1) + 1;
+            errors.push({
+                message: e.description || e.message,
+                line: code.slice(0, index).split('\n').length,
+                column: index - lineStart
+            });
+        }
+        if (!boundaries) {
+            boundaries = statementBoundaries(code);
+        }
+
+        // Keep the longest prefix, ending at a statement boundary before
+        // the error, that parses. Should this prefix fail, too, its own
+        // error designates a shorter one.
+        let end = index;
+        for (;;) {
+            end = lastBoundary(boundaries, start, end);
+            if (end === start) {
+                break;
+            }
+            try {
+                keep(parse(code.slice(start, end), counts), start);
+                break;
+            } catch (e) {
+                end = Math.min(end - 1, errorIndex(e, start));
+            }
+        }
+
+        start = nextBoundary(boundaries, index, code);
+    }
+    checkBigInts(found, bigints);
+    let script = { type: 'Script', directives, statements };
+    if (!parens) {
+        return { script, errors };
+    }
+    return { script, errors, parens: toHints(script, counts) };
+}
+
 startJSONStream({
     // Mirrors the Rust side of generic value transformations.
     //
     // This one takes a JavaScript source code from the Rust side as an input
     // and parses it into a Shift AST.
-    transform: code => parseScript(code, { earlyErrors: false }),
+    transform: input => {
+        if (typeof input === 'string') {
+            return parseScript(input, OPTIONS);
+        }
+        let bigints = input.bigints || 0;
+        return input.tolerant
+            ? parseTolerant(input.code, input.parens, bigints)
+            : parseLiterals(input.code, bigints);
+    },
 
     // `toJSON` is a `JSON.stringify` replacer callback that converts Shift AST
     // to a BinaryAST JSON format on the fly.
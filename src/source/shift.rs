@@ -1,9 +1,11 @@
 //! Read the data through a call to the Shift parser
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
+use std::borrow::Cow;
 use std::env;
 use std::ffi::OsString;
+use std::fs;
 use std::io::{BufRead, BufReader, LineWriter, Lines, Write};
 use std::path::*;
 use std::process::*;
@@ -11,7 +13,9 @@ use std::sync::Mutex;
 
 use binjs_es6::ast::Script as AST;
 use binjs_io::escaped_wtf8;
+use binjs_io::multipart::Parens;
 
+use source::literals::{check_reserved, rewrite_literal_at};
 use source::parser::SourceParser;
 
 use which::which;
@@ -26,6 +30,18 @@ pub enum Error {
     ParsingError(String),
 }
 
+/// A syntax error encountered by `Shift::parse_str_tolerant`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+
+    /// The line of the error, starting at 1.
+    pub line: u32,
+
+    /// The column of the error, in UTF-16 code units, starting at 0.
+    pub column: u32,
+}
+
 pub struct NodeConfig {
     /// Paths to the Node executable.
     bin_path: PathBuf,
@@ -125,6 +141,13 @@ impl Script {
     }
 }
 
+/// Parenthesization hints, as exchanged with parens.js.
+#[derive(Default, Deserialize, Serialize)]
+struct Hints {
+    expressions: u32,
+    hints: Vec<(u32, u32)>,
+}
+
 /// Using a Node + Shift binary to parse an AST.
 pub struct Shift {
     parse_str: Script,
@@ -148,13 +171,220 @@ impl Shift {
             .transform(ast)
             .map(escaped_wtf8::to_unicode_escape)
     }
+
+    /// Pretty-print an AST, writing the parentheses designated by the
+    /// parenthesization hints of `Shift::parse_str_tolerant_with_parens`
+    /// in addition to those required by precedence.
+    ///
+    /// Fails if the hints were recorded on an AST with a different number
+    /// of expressions.
+    pub fn to_source_with_parens(&self, ast: &AST, parens: &Parens) -> Result<String, Error> {
+        if parens.is_empty() {
+            return self.to_source(ast);
+        }
+        #[derive(Serialize)]
+        struct Request<'a> {
+            script: &'a AST,
+            parens: Hints,
+        }
+        self.codegen
+            .transform(&Request {
+                script: ast,
+                parens: Hints {
+                    expressions: parens.expressions,
+                    hints: parens
+                        .hints
+                        .iter()
+                        .map(|(index, count)| (*index, *count))
+                        .collect(),
+                },
+            })
+            .map(escaped_wtf8::to_unicode_escape)
+    }
+
+    /// Fail if `data` uses the name reserved for BigInt literals (see
+    /// `source::literals`).
+    fn reject_reserved(data: &str) -> Result<(), Error> {
+        check_reserved(data).map_err(|error| {
+            Error::ParsingError(format!(
+                "{}:{}: {}",
+                error.line, error.column, error.message
+            ))
+        })
+    }
+
+    /// Parse `data`, rewriting the literals the parser does not support, e.g.
+    /// BigInt literals, one at a time, wherever they cause a syntax error
+    /// (see `source::literals`). Sources without such literals are parsed
+    /// once, as they are.
+    fn parse_literals<'a>(&self, data: &'a str) -> Result<Parsed<'a>, Error> {
+        #[derive(Serialize)]
+        struct Request<'a> {
+            code: &'a str,
+            bigints: u32,
+        }
+        #[derive(Deserialize)]
+        struct SyntaxError {
+            message: String,
+            line: u32,
+            column: u32,
+            /// In UTF-16 code units.
+            index: usize,
+        }
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(default)]
+            script: Option<AST>,
+            #[serde(default)]
+            error: Option<SyntaxError>,
+        }
+        let mut code = Cow::Borrowed(data);
+        let mut bigints = 0;
+        loop {
+            let response: Response = self.parse_str.transform(&Request {
+                code: &code,
+                bigints,
+            })?;
+            let error = match (response.script, response.error) {
+                (Some(script), _) => {
+                    return Ok(Parsed {
+                        script: Ok(script),
+                        code,
+                        bigints,
+                    });
+                }
+                (None, Some(error)) => error,
+                (None, None) => {
+                    return Err(Error::ParsingError("No script in response".to_string()));
+                }
+            };
+            match rewrite_literal_at(&code, byte_offset(&code, error.index)) {
+                Some(rewrite) => {
+                    if rewrite.bigint {
+                        bigints += 1;
+                    }
+                    code = Cow::Owned(rewrite.source);
+                }
+                None => {
+                    return Ok(Parsed {
+                        script: Err(format!(
+                            "{}:{}: {}",
+                            error.line, error.column, error.message
+                        )),
+                        code,
+                        bigints,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// The result of `Shift::parse_literals`.
+struct Parsed<'a> {
+    /// The script, or the first syntax error not caused by a literal.
+    script: Result<AST, String>,
+
+    /// The source, with the literals rewritten so far.
+    code: Cow<'a, str>,
+
+    /// The number of BigInt literals of `code` replaced with placeholders.
+    bigints: u32,
+}
+
+/// The byte offset of `source` after `index` UTF-16 code units.
+fn byte_offset(source: &str, index: usize) -> usize {
+    let mut units = 0;
+    for (offset, c) in source.char_indices() {
+        if units >= index {
+            return offset;
+        }
+        units += c.len_utf16();
+    }
+    source.len()
+}
+
+impl Shift {
+    /// Parse a string, recovering from syntax errors where possible.
+    ///
+    /// Whenever a top-level statement causes a syntax error, the parser keeps
+    /// the statements preceding it, then resumes after it, at the next `;`,
+    /// closing `}` or line break outside of brackets. A syntax error within
+    /// a function or block drops the whole top-level statement enclosing it.
+    ///
+    /// Returns the statements that could be parsed, along with all the errors.
+    pub fn parse_str_tolerant(&self, data: &str) -> Result<(AST, Vec<ParseError>), Error> {
+        let (script, errors, _) = self.parse_str_tolerant_aux(data, false)?;
+        Ok((script, errors))
+    }
+
+    /// As `parse_str_tolerant`, also returning the parenthesization hints
+    /// of the source, i.e. the number of parentheses written around each
+    /// expression, other than those of the enclosing construct, e.g. the
+    /// condition of an `if`, by index of the expression in a walk of the
+    /// AST, for `Shift::to_source_with_parens`.
+    ///
+    /// Only parentheses separated from their expression by whitespace are
+    /// counted.
+    pub fn parse_str_tolerant_with_parens(
+        &self,
+        data: &str,
+    ) -> Result<(AST, Vec<ParseError>, Parens), Error> {
+        self.parse_str_tolerant_aux(data, true)
+    }
+
+    fn parse_str_tolerant_aux(
+        &self,
+        data: &str,
+        parens: bool,
+    ) -> Result<(AST, Vec<ParseError>, Parens), Error> {
+        #[derive(Serialize)]
+        struct Request<'a> {
+            code: &'a str,
+            tolerant: bool,
+            parens: bool,
+            bigints: u32,
+        }
+        #[derive(Deserialize)]
+        struct Response {
+            script: AST,
+            errors: Vec<ParseError>,
+            #[serde(default)]
+            parens: Hints,
+        }
+        // Literals are rewritten up to the first other syntax error.
+        Self::reject_reserved(data)?;
+        let parsed = self.parse_literals(data)?;
+        if !parens {
+            if let Ok(script) = parsed.script {
+                return Ok((script, vec![], Parens::default()));
+            }
+        }
+        let response: Response = self.parse_str.transform(&Request {
+            code: &parsed.code,
+            tolerant: true,
+            parens,
+            bigints: parsed.bigints,
+        })?;
+        Ok((
+            response.script,
+            response.errors,
+            Parens {
+                expressions: response.parens.expressions,
+                hints: response.parens.hints.into_iter().collect(),
+            },
+        ))
+    }
 }
 
 impl SourceParser<AST> for Shift {
     type Error = Error;
 
     fn parse_str(&self, data: &str) -> Result<AST, Error> {
-        self.parse_str.transform(data)
+        Self::reject_reserved(data)?;
+        self.parse_literals(data)?
+            .script
+            .map_err(Error::ParsingError)
     }
 
     /// Parse a text source file, using Shift.
@@ -164,8 +394,19 @@ impl SourceParser<AST> for Shift {
             .to_str()
             .ok_or_else(|| Error::InvalidPath(path.as_ref().to_path_buf()))?;
 
+        let data = fs::read_to_string(path).map_err(Error::IOError)?;
+        Self::reject_reserved(&data)?;
+
         // A script to parse a source file, write it to stdout as JSON.
-        self.parse_file.transform(path)
+        match self.parse_file.transform(path) {
+            // Literals the parser does not support require rewriting the
+            // source.
+            Err(Error::ParsingError(_)) => self
+                .parse_literals(&data)?
+                .script
+                .map_err(Error::ParsingError),
+            result => result,
+        }
     }
 }
 
@@ -87,6 +87,14 @@ function convertObject(key, obj) {
         case 'LiteralPropertyName':
             obj.type = 'StaticPropertyName';
             break;
+        case 'LiteralBigIntExpression':
+            // The codegen does not support BigInt literals, but prints
+            // identifiers verbatim.
+            obj = {
+                type: 'IdentifierExpression',
+                name: obj.value + 'n'
+            };
+            break;
         case 'LiteralRegExpExpression': {
             let flags = obj.flags;
             obj.global = flags.includes('g');
@@ -0,0 +1,1317 @@
+//! Stripping TypeScript-specific syntax from a source, to obtain JavaScript.
+//!
+//! The stripper is conservative: it only removes constructs without runtime
+//! semantics, i.e.
+//!
+//! - type annotations, type parameters and type arguments;
+//! - `interface` and `type` declarations, `declare` statements and
+//!   overload signatures;
+//! - `as` and `satisfies` casts, non-null assertions `x!`;
+//! - type-only imports, exports and specifiers;
+//! - modifiers `public`, `private`, `protected`, `readonly`, `override` and
+//!   `abstract`, as well as class fields without initializers.
+//!
+//! Constructs with runtime semantics (`enum`, `namespace`, decorators,
+//! parameter properties, class fields with initializers) are rejected, as
+//! are constructs whose stripping would change how the rest of the source
+//! reads, e.g. a cast followed by a line break and `[`.
+//! Anything else is left untouched, so the parser rejects whatever the
+//! stripper does not understand.
+//!
+//! Stripped characters are replaced with spaces, keeping line breaks, so that
+//! positions in the result, e.g. those of syntax errors, match positions in
+//! the source.
+
+use source::shift::ParseError;
+
+use std;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Ident,
+    Number,
+    String,
+    /// A piece of template literal, e.g. `` `foo${ `` or `` }bar` ``.
+    Template,
+    Regex,
+    Punct,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Token {
+    kind: Kind,
+    start: usize,
+    end: usize,
+    /// `true` if a line break precedes this token.
+    newline_before: bool,
+}
+
+/// Multi-character punctuators, longest first.
+///
+/// `>` is always a token of its own, so that `>>` may close nested type
+/// arguments.
+const PUNCTUATORS: &[&str] = &[
+    "...", "===", "!==", "**=", "<<=", "&&=", "||=", "??=", "=>", "==", "!=", "<=", "+=", "-=",
+    "*=", "/=", "%=", "&=", "|=", "^=", "&&", "||", "??", "?.", "++", "--", "**", "<<",
+];
+
+/// Keywords followed by an expression, e.g. after which `/` starts a regular
+/// expression.
+const KEYWORDS_BEFORE_EXPRESSION: &[&str] = &[
+    "return",
+    "typeof",
+    "instanceof",
+    "in",
+    "of",
+    "new",
+    "delete",
+    "void",
+    "throw",
+    "case",
+    "do",
+    "else",
+    "yield",
+    "await",
+];
+
+/// Modifiers of class members.
+const MODIFIERS: &[&str] = &[
+    "public",
+    "private",
+    "protected",
+    "readonly",
+    "override",
+    "abstract",
+    "declare",
+    "static",
+    "async",
+    "get",
+    "set",
+];
+
+/// Modifiers of class members or of parameters which only exist in TypeScript.
+const TYPESCRIPT_MODIFIERS: &[&str] = &[
+    "public",
+    "private",
+    "protected",
+    "readonly",
+    "override",
+    "abstract",
+    "declare",
+];
+
+/// An error at byte `offset` of `source`.
+pub fn error_at(source: &str, offset: usize, message: &str) -> ParseError {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map(|pos| pos + 1).unwrap_or(0);
+    ParseError {
+        message: message.to_string(),
+        line: before.matches('\n').count() as u32 + 1,
+        column: before[line_start..].encode_utf16().count() as u32,
+    }
+}
+
+fn is_ident_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'$' || byte == b'\\' || byte >= 0x80
+}
+
+/// The offset following the string literal starting at `pos`.
+fn skip_quoted(source: &str, pos: usize, quote: u8) -> Result<usize, ParseError> {
+    let bytes = source.as_bytes();
+    let mut i = pos + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if bytes[i + 1..].starts_with(b"\r\n") => i += 3,
+            b'\\' => i += 2,
+            b'\n' | b'\r' => break,
+            byte if byte == quote => return Ok(i + 1),
+            _ => i += 1,
+        }
+    }
+    Err(error_at(source, pos, "Unterminated string"))
+}
+
+/// The offset following the piece of template literal starting at `pos`,
+/// i.e. after the closing `` ` `` or `${`.
+fn skip_template(source: &str, pos: usize) -> Result<usize, ParseError> {
+    let bytes = source.as_bytes();
+    let mut i = pos + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'`' => return Ok(i + 1),
+            b'$' if bytes.get(i + 1) == Some(&b'{') => return Ok(i + 2),
+            _ => i += 1,
+        }
+    }
+    Err(error_at(source, pos, "Unterminated template"))
+}
+
+/// The offset following the regular expression starting at `pos`.
+fn skip_regex(source: &str, pos: usize) -> Result<usize, ParseError> {
+    let bytes = source.as_bytes();
+    let mut i = pos + 1;
+    let mut in_class = false;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'\n' | b'\r' => break,
+            b'[' => {
+                in_class = true;
+                i += 1;
+            }
+            b']' => {
+                in_class = false;
+                i += 1;
+            }
+            b'/' if !in_class => {
+                i += 1;
+                while i < bytes.len() && is_ident_char(bytes[i]) {
+                    i += 1;
+                }
+                return Ok(i);
+            }
+            _ => i += 1,
+        }
+    }
+    Err(error_at(source, pos, "Unterminated regular expression"))
+}
+
+/// The offset following the number starting at `pos`.
+fn skip_number(bytes: &[u8], pos: usize) -> usize {
+    let is_hex = bytes[pos..].starts_with(b"0x") || bytes[pos..].starts_with(b"0X");
+    let mut i = pos + 1;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        let is_exponent_sign = (byte == b'+' || byte == b'-')
+            && (bytes[i - 1] == b'e' || bytes[i - 1] == b'E')
+            && !is_hex;
+        if is_ident_char(byte) || byte == b'.' || is_exponent_sign {
+            i += 1;
+        } else {
+            break;
+        }
+    }
+    i
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let bytes = source.as_bytes();
+    let mut tokens: Vec<Token> = vec![];
+    let mut pos = 0;
+    let mut newline_before = false;
+
+    // For each template substitution `${ ... }` being read, the number of
+    // braces open when it started.
+    let mut substitutions: Vec<usize> = vec![];
+    let mut braces = 0;
+
+    while pos < bytes.len() {
+        let byte = bytes[pos];
+        let next = bytes.get(pos + 1).cloned().unwrap_or(0);
+        let start = pos;
+        let kind = match byte {
+            b'\n' | b'\r' => {
+                newline_before = true;
+                pos += 1;
+                continue;
+            }
+            b' ' | b'\t' | 0x0B | 0x0C => {
+                pos += 1;
+                continue;
+            }
+            b'/' if next == b'/' => {
+                while pos < bytes.len() && bytes[pos] != b'\n' && bytes[pos] != b'\r' {
+                    pos += 1;
+                }
+                continue;
+            }
+            b'/' if next == b'*' => {
+                let end = source[pos + 2..]
+                    .find("*/")
+                    .ok_or_else(|| error_at(source, pos, "Unterminated comment"))?
+                    + pos
+                    + 4;
+                if source[pos..end].contains('\n') {
+                    newline_before = true;
+                }
+                pos = end;
+                continue;
+            }
+            b'"' | b'\'' => {
+                pos = skip_quoted(source, pos, byte)?;
+                Kind::String
+            }
+            b'`' => {
+                pos = skip_template(source, pos)?;
+                if bytes[pos - 1] == b'{' {
+                    substitutions.push(braces);
+                }
+                Kind::Template
+            }
+            b'}' if substitutions.last() == Some(&braces) => {
+                substitutions.pop();
+                pos = skip_template(source, pos)?;
+                if bytes[pos - 1] == b'{' {
+                    substitutions.push(braces);
+                }
+                Kind::Template
+            }
+            b'0'..=b'9' => {
+                pos = skip_number(bytes, pos);
+                Kind::Number
+            }
+            b'.' if next.is_ascii_digit() => {
+                pos = skip_number(bytes, pos);
+                Kind::Number
+            }
+            b'/' if regex_allowed(source, &tokens) => {
+                pos = skip_regex(source, pos)?;
+                Kind::Regex
+            }
+            _ if is_ident_char(byte) || byte == b'#' => {
+                pos += 1;
+                while pos < bytes.len() && is_ident_char(bytes[pos]) {
+                    pos += 1;
+                }
+                Kind::Ident
+            }
+            _ => {
+                let rest = &source[pos..];
+                let len = PUNCTUATORS
+                    .iter()
+                    .find(|punctuator| {
+                        // `a?.5:b` is a conditional.
+                        rest.starts_with(**punctuator)
+                            && !(**punctuator == "?."
+                                && bytes.get(pos + 2).is_some_and(u8::is_ascii_digit))
+                    })
+                    .map_or(1, |punctuator| punctuator.len());
+                if byte == b'{' {
+                    braces += 1;
+                } else if byte == b'}' && braces > 0 {
+                    braces -= 1;
+                }
+                pos += len;
+                Kind::Punct
+            }
+        };
+        tokens.push(Token {
+            kind,
+            start,
+            end: pos,
+            newline_before,
+        });
+        newline_before = false;
+    }
+    Ok(tokens)
+}
+
+/// `true` if a `/` following `tokens` starts a regular expression, rather
+/// than a division.
+fn regex_allowed(source: &str, tokens: &[Token]) -> bool {
+    match tokens.last() {
+        None => true,
+        Some(token) => {
+            let text = &source[token.start..token.end];
+            match token.kind {
+                Kind::Punct => ![")", "]", "}", "++", "--"].contains(&text),
+                Kind::Ident => KEYWORDS_BEFORE_EXPRESSION.contains(&text),
+                _ => false,
+            }
+        }
+    }
+}
+
+/// The first token of a declaration which may turn out to be an overload
+/// signature, with the last token kept before it.
+type Overload = Option<(usize, Option<usize>)>;
+
+/// The syntactic context of a token.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Context {
+    /// The top level or a block: a list of statements.
+    Block,
+    /// An object literal or a destructuring pattern.
+    Object,
+    /// A class body: a list of members.
+    Class,
+    /// The parameters of a function or method.
+    Params { overload: Overload },
+    /// Any other parenthesized or bracketed construct.
+    Group,
+    /// The `{ ... }` of an `import` or `export`, in which `as` renames.
+    Specifiers,
+}
+
+/// Where we are in a variable declaration, e.g. `let x: T = ..., y`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Declaration {
+    None,
+    /// After `let` or `,`.
+    Binding,
+    /// After the name or pattern being declared, where a type may appear.
+    Type,
+    /// After `=`.
+    Initializer,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Frame {
+    context: Context,
+    declaration: Declaration,
+    /// In `Context::Params`, `true` while in a default value.
+    in_default: bool,
+    /// The number of conditional operators `?` not yet matched by a `:`.
+    conditionals: usize,
+}
+impl Frame {
+    fn new(context: Context) -> Self {
+        Frame {
+            context,
+            declaration: Declaration::None,
+            in_default: false,
+            conditionals: 0,
+        }
+    }
+}
+
+/// A range of bytes to replace with spaces, and whether it must start with
+/// a `;` instead.
+struct Blank {
+    start: usize,
+    end: usize,
+    semicolon: bool,
+}
+
+struct Stripper<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    blanks: Vec<Blank>,
+}
+impl<'a> Stripper<'a> {
+    fn text(&self, i: usize) -> &'a str {
+        let source = self.source;
+        match self.tokens.get(i) {
+            Some(token) => &source[token.start..token.end],
+            None => "",
+        }
+    }
+
+    /// `true` if token `i` is the identifier or punctuator `text`.
+    fn is(&self, i: usize, text: &str) -> bool {
+        match self.tokens.get(i) {
+            Some(token) => {
+                (token.kind == Kind::Ident || token.kind == Kind::Punct) && self.text(i) == text
+            }
+            None => false,
+        }
+    }
+
+    fn is_kind(&self, i: usize, kind: Kind) -> bool {
+        self.tokens.get(i).is_some_and(|token| token.kind == kind)
+    }
+
+    fn is_ident(&self, i: usize) -> bool {
+        self.is_kind(i, Kind::Ident)
+    }
+
+    /// `true` if token `i` exists and is on the same line as the previous token.
+    fn same_line(&self, i: usize) -> bool {
+        self.tokens
+            .get(i)
+            .is_some_and(|token| !token.newline_before)
+    }
+
+    fn error(&self, i: usize, message: &str) -> ParseError {
+        let offset = self
+            .tokens
+            .get(i)
+            .map_or(self.source.len(), |token| token.start);
+        error_at(self.source, offset, message)
+    }
+
+    /// `true` if token `i` may end an expression, e.g. before `as`.
+    fn ends_expression(&self, i: Option<usize>) -> bool {
+        let i = match i {
+            Some(i) => i,
+            None => return false,
+        };
+        match self.tokens[i].kind {
+            Kind::Ident => !KEYWORDS_BEFORE_EXPRESSION.contains(&self.text(i)),
+            Kind::Number | Kind::String | Kind::Regex => true,
+            Kind::Template => self.text(i).ends_with('`'),
+            Kind::Punct => [")", "]", "}"].contains(&self.text(i)),
+        }
+    }
+
+    /// `true` if a `{` following token `last` opens an object literal or
+    /// a destructuring pattern, rather than a block.
+    fn opens_object(&self, last: Option<usize>) -> bool {
+        let last = match last {
+            Some(last) => last,
+            None => return false,
+        };
+        match self.tokens[last].kind {
+            Kind::Punct => ![";", "{", "}", ")", "=>"].contains(&self.text(last)),
+            Kind::Ident => {
+                KEYWORDS_BEFORE_EXPRESSION.contains(&self.text(last))
+                    && !self.is(last, "do")
+                    && !self.is(last, "else")
+            }
+            _ => false,
+        }
+    }
+
+    /// `true` if a `{` following token `last` opens the specifiers of an
+    /// `import` or `export`.
+    fn opens_specifiers(&self, last: Option<usize>) -> bool {
+        match last {
+            None => false,
+            Some(last) => {
+                self.is(last, "import")
+                    || self.is(last, "export")
+                    || (self.is(last, ",") && last >= 2 && self.is(last - 2, "import"))
+            }
+        }
+    }
+
+    /// The index of the bracket closing the bracket at `i`.
+    fn matching(&self, i: usize) -> Option<usize> {
+        let mut depth = 0;
+        for j in i..self.tokens.len() {
+            if self.tokens[j].kind != Kind::Punct {
+                continue;
+            }
+            match self.text(j) {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(j);
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// The index of the `>` closing the type parameters or arguments
+    /// starting with `<` at `i`.
+    fn matching_angle(&self, i: usize) -> Option<usize> {
+        let mut depth = 0;
+        let mut j = i;
+        while j < self.tokens.len() {
+            if self.is_kind(j, Kind::Punct) {
+                match self.text(j) {
+                    "<" => depth += 1,
+                    ">" => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(j);
+                        }
+                    }
+                    "(" | "[" | "{" => j = self.matching(j)?,
+                    ")" | "]" | "}" | ";" => return None,
+                    _ => {}
+                }
+            }
+            j += 1;
+        }
+        None
+    }
+
+    /// If the `<` at `i` starts the type arguments of a call, e.g. `f<T>(x)`,
+    /// the index of the `(`.
+    ///
+    /// As in TypeScript, the tokens up to the matching `>` must be a list of
+    /// types, so that comparisons, e.g. `i < max ? lo : hi > (k)`, are kept.
+    fn type_arguments(&self, i: usize) -> Option<usize> {
+        let end = self.matching_angle(i)?;
+        for j in i + 1..end {
+            let allowed = match self.tokens[j].kind {
+                Kind::Punct => [
+                    ",", ".", "[", "]", "{", "}", "(", ")", "<", ">", "|", "&", ":", ";", "?",
+                    "=>", "...", "-",
+                ]
+                .contains(&self.text(j)),
+                Kind::Regex | Kind::Template => false,
+                _ => true,
+            };
+            if !allowed {
+                return None;
+            }
+        }
+        let mut j = i + 1;
+        loop {
+            j = self.skip_type(j)?;
+            if !self.is(j, ",") {
+                break;
+            }
+            j += 1;
+        }
+        if j == end && self.is(end + 1, "(") {
+            Some(end + 1)
+        } else {
+            None
+        }
+    }
+
+    /// `true` if the `(` at `i` opens the parameters of an arrow function.
+    fn is_arrow_params(&self, i: usize) -> bool {
+        let close = match self.matching(i) {
+            Some(close) => close,
+            None => return false,
+        };
+        if self.is(close + 1, "=>") {
+            return true;
+        }
+        self.is(close + 1, ":")
+            && self
+                .skip_type(close + 2)
+                .is_some_and(|end| self.is(end, "=>"))
+    }
+
+    /// `true` if the `(` at `i`, in an object literal, opens the parameters
+    /// of a method, e.g. `{ foo(x: T): U { ... } }`.
+    fn is_method(&self, i: usize, last: Option<usize>) -> bool {
+        let is_name = last.is_some_and(|last| {
+            self.is_ident(last)
+                || self.is_kind(last, Kind::String)
+                || self.is_kind(last, Kind::Number)
+                || self.is(last, "]")
+        });
+        is_name
+            && self
+                .matching(i)
+                .is_some_and(|close| self.is(close + 1, "{") || self.is(close + 1, ":"))
+    }
+
+    /// `true` if a type may start at `i`.
+    fn starts_type(&self, i: usize) -> bool {
+        self.is_ident(i)
+            || self.is_kind(i, Kind::String)
+            || self.is_kind(i, Kind::Number)
+            || ["(", "[", "{", "<", "-"]
+                .iter()
+                .any(|text| self.is(i, text))
+    }
+
+    /// The index of the token following the type starting at `i`, if any.
+    fn skip_type(&self, i: usize) -> Option<usize> {
+        let mut i = i;
+        if self.is(i, "|") || self.is(i, "&") {
+            i += 1;
+        }
+        loop {
+            i = self.skip_type_operand(i)?;
+            if self.is(i, "|") || self.is(i, "&") {
+                i += 1;
+                continue;
+            }
+            if self.is(i, "extends") && self.same_line(i) {
+                // A conditional type, e.g. `T extends U ? X : Y`.
+                let question = self.skip_type_operand(i + 1)?;
+                if !self.is(question, "?") {
+                    return None;
+                }
+                let colon = self.skip_type(question + 1)?;
+                if !self.is(colon, ":") {
+                    return None;
+                }
+                return self.skip_type(colon + 1);
+            }
+            return Some(i);
+        }
+    }
+
+    /// The index of the token following the operand of a union or
+    /// intersection type starting at `i`, if any.
+    fn skip_type_operand(&self, i: usize) -> Option<usize> {
+        let mut i = i;
+        while ["keyof", "typeof", "readonly", "unique", "infer", "asserts"].contains(&self.text(i))
+            && self.is_ident(i)
+            && self.same_line(i + 1)
+            && self.starts_type(i + 1)
+        {
+            i += 1;
+        }
+        let token = *self.tokens.get(i)?;
+        let mut end = match token.kind {
+            Kind::Ident if self.is(i, "new") && self.is(i + 1, "(") => {
+                // A constructor type, e.g. `new (x: T) => U`.
+                let close = self.matching(i + 1)?;
+                if !self.is(close + 1, "=>") {
+                    return None;
+                }
+                return self.skip_type(close + 2);
+            }
+            Kind::Ident => {
+                let mut j = i + 1;
+                while self.is(j, ".") && self.is_ident(j + 1) {
+                    j += 2;
+                }
+                if self.is(j, "<") && self.same_line(j) {
+                    j = self.matching_angle(j)? + 1;
+                }
+                if self.is(j, "is") && self.same_line(j) {
+                    // A type predicate, e.g. `x is T`.
+                    return self.skip_type(j + 1);
+                }
+                j
+            }
+            Kind::String | Kind::Number => i + 1,
+            Kind::Template if self.text(i).starts_with('`') && self.text(i).ends_with('`') => i + 1,
+            Kind::Punct => match self.text(i) {
+                "-" if self.is_kind(i + 1, Kind::Number) => i + 2,
+                "(" => {
+                    let close = self.matching(i)?;
+                    if self.is(close + 1, "=>") {
+                        // A function type, e.g. `(x: T) => U`.
+                        return self.skip_type(close + 2);
+                    }
+                    close + 1
+                }
+                "[" | "{" => self.matching(i)? + 1,
+                "<" => {
+                    // A generic function type, e.g. `<T>(x: T) => T`.
+                    let open = self.matching_angle(i)? + 1;
+                    if !self.is(open, "(") {
+                        return None;
+                    }
+                    let close = self.matching(open)?;
+                    if !self.is(close + 1, "=>") {
+                        return None;
+                    }
+                    return self.skip_type(close + 2);
+                }
+                _ => return None,
+            },
+            _ => return None,
+        };
+        // Array types and indexed access types, e.g. `T[]` or `T["key"]`.
+        while self.is(end, "[") && self.same_line(end) {
+            end = self.matching(end)? + 1;
+        }
+        Some(end)
+    }
+
+    /// The index of the token following the statement starting at `from`,
+    /// including its `;`, if any.
+    fn end_of_statement(&self, from: usize) -> usize {
+        let mut depth = 0;
+        for j in from..self.tokens.len() {
+            if j > from && depth == 0 && self.tokens[j].newline_before {
+                return j;
+            }
+            if self.tokens[j].kind != Kind::Punct {
+                continue;
+            }
+            match self.text(j) {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => {
+                    if depth == 0 {
+                        return j;
+                    }
+                    depth -= 1;
+                }
+                ";" if depth == 0 => return j + 1,
+                _ => {}
+            }
+        }
+        self.tokens.len()
+    }
+
+    /// If a declaration without runtime semantics, e.g. `interface Foo { ... }`,
+    /// starts at `i`, the index of the token following it.
+    fn type_declaration(&self, i: usize, exported: bool) -> Result<Option<usize>, ParseError> {
+        if !self.same_line(i + 1) {
+            return Ok(None);
+        }
+        if self.is(i, "interface") && self.is_ident(i + 1) {
+            let mut j = i + 2;
+            if self.is(j, "<") {
+                j = self
+                    .matching_angle(j)
+                    .ok_or_else(|| self.error(j, "Invalid type parameters"))?;
+            }
+            let open = (j..self.tokens.len())
+                .find(|&j| self.is(j, "{"))
+                .ok_or_else(|| self.error(i, "Invalid interface"))?;
+            let close = self
+                .matching(open)
+                .ok_or_else(|| self.error(open, "Unbalanced braces"))?;
+            return Ok(Some(close + 1));
+        }
+        if self.is(i, "type") && self.is_ident(i + 1) {
+            let mut j = i + 2;
+            if self.is(j, "<") {
+                j = self
+                    .matching_angle(j)
+                    .ok_or_else(|| self.error(j, "Invalid type parameters"))?
+                    + 1;
+            }
+            if !self.is(j, "=") {
+                return Ok(None);
+            }
+            let end = self
+                .skip_type(j + 1)
+                .ok_or_else(|| self.error(j + 1, "Invalid type"))?;
+            return Ok(Some(if self.is(end, ";") { end + 1 } else { end }));
+        }
+        let type_only = self.is(i, "declare") && (self.is_ident(i + 1) || self.is(i + 1, "{"))
+            || exported && self.is(i, "type") && (self.is(i + 1, "{") || self.is(i + 1, "*"))
+            || self.is(i, "import")
+                && self.is(i + 1, "type")
+                && (self.is(i + 2, "{")
+                    || self.is(i + 2, "*")
+                    || self.is_ident(i + 2) && !self.is(i + 2, "from"));
+        if type_only {
+            return Ok(Some(self.end_of_statement(i)));
+        }
+        Ok(None)
+    }
+
+    /// Replace tokens `from..to` with spaces.
+    fn blank(&mut self, from: usize, to: usize) {
+        if from < to {
+            self.blanks.push(Blank {
+                start: self.tokens[from].start,
+                end: self.tokens[to - 1].end,
+                semicolon: false,
+            });
+        }
+    }
+
+    /// Replace the statement made of tokens `from..to` with spaces, inserting
+    /// a `;` if the statements around it could otherwise be joined, e.g.
+    /// `a \n interface I {} \n (b)`.
+    fn blank_statement(&mut self, from: usize, to: usize, last: Option<usize>) {
+        if from >= to {
+            return;
+        }
+        let next = self.text(to);
+        let semicolon = self.ends_expression(last)
+            && ["(", "[", "+", "-", "/", "`"]
+                .iter()
+                .any(|prefix| next.starts_with(prefix));
+        self.blanks.push(Blank {
+            start: self.tokens[from].start,
+            end: self.tokens[to - 1].end,
+            semicolon,
+        });
+    }
+
+    /// Strip the TypeScript-specific parts of the class member starting at `i`.
+    ///
+    /// Returns the index of the next token to process, and the last token
+    /// kept, if any. If the member is a method, the next token is the `(`
+    /// opening its parameters, and `params` is set.
+    fn class_member(
+        &mut self,
+        i: usize,
+        last: Option<usize>,
+        params: &mut Option<Overload>,
+    ) -> Result<(usize, Option<usize>), ParseError> {
+        let mut j = i;
+        let mut declaration_only = false;
+        loop {
+            let text = self.text(j);
+            let is_modifier = self.is_ident(j)
+                && MODIFIERS.contains(&text)
+                && self.same_line(j + 1)
+                && (self.is_ident(j + 1)
+                    || self.is_kind(j + 1, Kind::String)
+                    || self.is_kind(j + 1, Kind::Number)
+                    || self.is(j + 1, "[")
+                    || self.is(j + 1, "*"));
+            if !is_modifier {
+                break;
+            }
+            if text == "abstract" || text == "declare" {
+                declaration_only = true;
+            }
+            if TYPESCRIPT_MODIFIERS.contains(&text) {
+                self.blank(j, j + 1);
+            }
+            j += 1;
+        }
+        if self.is(j, "*") {
+            j += 1;
+        }
+
+        // Index signatures, e.g. `[key: string]: T;`.
+        if self.is(j, "[") && self.is_ident(j + 1) && self.is(j + 2, ":") {
+            declaration_only = true;
+        }
+        if declaration_only {
+            let end = self.end_of_statement(i);
+            self.blank(i, end);
+            return Ok((end, None));
+        }
+
+        // The name of the member.
+        if j >= self.tokens.len() {
+            return Err(self.error(j, "Unexpected end of class body"));
+        }
+        if self.is(j, "[") {
+            j = self
+                .matching(j)
+                .ok_or_else(|| self.error(j, "Unbalanced brackets"))?;
+        }
+        let name = j;
+        j += 1;
+
+        // Optional members, e.g. `foo?: T`, and definite ones, e.g. `foo!: T`.
+        if (self.is(j, "?") || self.is(j, "!")) && self.same_line(j) {
+            self.blank(j, j + 1);
+            j += 1;
+        }
+        if self.is(j, "<") {
+            let end = self
+                .matching_angle(j)
+                .ok_or_else(|| self.error(j, "Invalid type parameters"))?;
+            self.blank(j, end + 1);
+            j = end + 1;
+        }
+        if self.is(j, "(") {
+            *params = Some(Some((i, last)));
+            return Ok((j, Some(name)));
+        }
+
+        // Otherwise, this is a field, which disappears unless it has an initializer.
+        if self.is(j, ":") {
+            j = self
+                .skip_type(j + 1)
+                .ok_or_else(|| self.error(j + 1, "Invalid type"))?;
+        }
+        if self.is(j, "=") {
+            return Err(self.error(j, "Class fields with initializers are not supported"));
+        }
+        if self.is(j, ";") {
+            j += 1;
+        } else if !self.is(j, "}") && self.same_line(j) {
+            return Err(self.error(j, "Unexpected token in class body"));
+        }
+        self.blank(i, j);
+        Ok((j, None))
+    }
+
+    fn strip(&mut self) -> Result<(), ParseError> {
+        let mut stack = vec![Frame::new(Context::Block)];
+
+        // The last token kept.
+        let mut last: Option<usize> = None;
+
+        // If set, the next `(` opens the parameters of a function.
+        let mut params: Option<Overload> = None;
+
+        // If set, the next `{` at this depth opens a class body.
+        let mut class: Option<usize> = None;
+
+        let mut i = 0;
+        while i < self.tokens.len() {
+            let frame = *stack.last().unwrap();
+            let text = self.text(i);
+            let after_dot = last.is_some_and(|last| self.is(last, "."));
+            let statement_start = frame.context == Context::Block
+                && (self.tokens[i].newline_before
+                    || last.is_none_or(|last| {
+                        self.is(last, ";") || self.is(last, "{") || self.is(last, "}")
+                    }));
+
+            if self.is(i, "@") {
+                return Err(self.error(i, "Decorators are not supported"));
+            }
+
+            if statement_start {
+                // Statements which disappear entirely, possibly exported.
+                let mut j = i;
+                if self.is(j, "export") {
+                    j += 1;
+                    if self.is(j, "default") && self.is(j + 1, "interface") {
+                        j += 1;
+                    }
+                }
+                if let Some(end) = self.type_declaration(j, j > i)? {
+                    self.blank_statement(i, end, last);
+                    i = end;
+                    continue;
+                }
+                if self.is(j, "enum") && self.is_ident(j + 1)
+                    || self.is(j, "const") && self.is(j + 1, "enum")
+                {
+                    return Err(self.error(j, "Enums are not supported"));
+                }
+                if (self.is(j, "namespace") || self.is(j, "module"))
+                    && self.is_ident(j + 1)
+                    && self.same_line(j + 1)
+                {
+                    return Err(self.error(j, "Namespaces are not supported"));
+                }
+            }
+
+            if frame.context == Context::Class
+                && last.is_some_and(|last| {
+                    self.is(last, "{") || self.is(last, ";") || self.is(last, "}")
+                })
+                && !self.is(i, "}")
+                && !self.is(i, ";")
+            {
+                let (next, kept) = self.class_member(i, last, &mut params)?;
+                if kept.is_some() {
+                    last = kept;
+                }
+                i = next;
+                continue;
+            }
+
+            if self.is(i, "abstract") && self.is(i + 1, "class") {
+                self.blank(i, i + 1);
+                i += 1;
+                continue;
+            }
+
+            if self.is(i, "function")
+                && !after_dot
+                && (self.is(i + 1, "(") || self.is(i + 1, "*") || self.is_ident(i + 1))
+            {
+                let mut start = i;
+                while start > 0
+                    && (self.is(start - 1, "async")
+                        || self.is(start - 1, "export")
+                        || self.is(start - 1, "default"))
+                {
+                    start -= 1;
+                }
+                let before = if start == i { last } else { Some(start - 1) };
+                params = Some(if frame.context == Context::Block {
+                    Some((start, before))
+                } else {
+                    None
+                });
+            }
+
+            if self.is(i, "class") && !after_dot && (self.is(i + 1, "{") || self.is_ident(i + 1)) {
+                class = Some(stack.len());
+            }
+            if class == Some(stack.len()) && self.is(i, "implements") {
+                let open = (i..self.tokens.len())
+                    .find(|&j| self.is(j, "{"))
+                    .ok_or_else(|| self.error(i, "Invalid class"))?;
+                self.blank(i, open);
+                i = open;
+                continue;
+            }
+
+            if self.is(i, "<") {
+                let end = if params.is_some() || class == Some(stack.len()) {
+                    // Type parameters of a function or class, or type
+                    // arguments of a base class.
+                    Some(
+                        self.matching_angle(i)
+                            .ok_or_else(|| self.error(i, "Invalid type parameters"))?
+                            + 1,
+                    )
+                } else if last.is_some_and(|last| self.is_ident(last))
+                    && self.ends_expression(last)
+                    && self.same_line(i)
+                {
+                    // Type arguments of a call, e.g. `f<T>(x)`.
+                    self.type_arguments(i)
+                } else if !self.ends_expression(last) {
+                    // Type parameters of an arrow function, e.g. `<T>(x: T) => x`.
+                    self.matching_angle(i).and_then(|end| {
+                        if self.is(end + 1, "(") && self.is_arrow_params(end + 1) {
+                            Some(end + 1)
+                        } else {
+                            None
+                        }
+                    })
+                } else {
+                    None
+                };
+                if let Some(end) = end {
+                    self.blank(i, end);
+                    i = end;
+                    continue;
+                }
+            }
+
+            // Type-only specifiers, e.g. `import { type A, b } from "c"`.
+            if frame.context == Context::Specifiers
+                && self.is(i, "type")
+                && self.is_ident(i + 1)
+                && !self.is(i + 1, "as")
+                && last.is_some_and(|last| self.is(last, "{") || self.is(last, ","))
+            {
+                let mut end = i + 2;
+                if self.is(end, "as") && self.is_ident(end + 1) {
+                    end += 2;
+                }
+                if self.is(end, ",") {
+                    end += 1;
+                }
+                self.blank(i, end);
+                i = end;
+                continue;
+            }
+
+            if (self.is(i, "as") || self.is(i, "satisfies"))
+                && frame.context != Context::Specifiers
+                && self.same_line(i)
+                && self.ends_expression(last)
+            {
+                if let Some(end) = self.skip_type(i + 1) {
+                    // TypeScript ends the statement at the line break, while
+                    // the remaining expression would continue it, e.g.
+                    // `b as Foo\n[1, 2]` would become `b[1, 2]`.
+                    if !self.same_line(end)
+                        && (self.is(end, "(")
+                            || self.is(end, "[")
+                            || self.is_kind(end, Kind::Template))
+                    {
+                        return Err(self.error(end, "Ambiguous line break after a type assertion"));
+                    }
+                    self.blank(i, end);
+                    i = end;
+                    continue;
+                }
+            }
+
+            // Non-null assertions, e.g. `x!.foo`.
+            if self.is(i, "!")
+                && last.is_some_and(|last| {
+                    self.tokens[last].end == self.tokens[i].start
+                        && (self.is_ident(last) || self.is(last, ")") || self.is(last, "]"))
+                })
+                && self.ends_expression(last)
+                && (!self.same_line(i + 1)
+                    || [".", "?.", ")", "]", "}", ",", ";", ":", "=", "as"]
+                        .iter()
+                        .any(|text| self.is(i + 1, text)))
+            {
+                self.blank(i, i + 1);
+                i += 1;
+                continue;
+            }
+
+            if let Context::Params { .. } = frame.context {
+                let after_separator =
+                    last.is_some_and(|last| self.is(last, "(") || self.is(last, ","));
+                if TYPESCRIPT_MODIFIERS.contains(&text)
+                    && self.is_ident(i)
+                    && self.is_ident(i + 1)
+                    && after_separator
+                {
+                    return Err(self.error(i, "Parameter properties are not supported"));
+                }
+                if self.is(i, "this")
+                    && self.is(i + 1, ":")
+                    && last.is_some_and(|last| self.is(last, "("))
+                {
+                    // A declaration of the type of `this`, e.g. `function (this: T, x)`.
+                    let mut end = self
+                        .skip_type(i + 2)
+                        .ok_or_else(|| self.error(i + 2, "Invalid type"))?;
+                    if self.is(end, ",") {
+                        end += 1;
+                    }
+                    self.blank(i, end);
+                    i = end;
+                    continue;
+                }
+                if !frame.in_default {
+                    let after_binding = last.is_some_and(|last| {
+                        self.is_ident(last) || self.is(last, "}") || self.is(last, "]")
+                    });
+                    if self.is(i, "?")
+                        && after_binding
+                        && (self.is(i + 1, ":") || self.is(i + 1, ",") || self.is(i + 1, ")"))
+                    {
+                        self.blank(i, i + 1);
+                        i += 1;
+                        continue;
+                    }
+                    if self.is(i, ":") && after_binding {
+                        let end = self
+                            .skip_type(i + 1)
+                            .ok_or_else(|| self.error(i + 1, "Invalid type"))?;
+                        self.blank(i, end);
+                        i = end;
+                        continue;
+                    }
+                }
+                if self.is(i, "=") {
+                    stack.last_mut().unwrap().in_default = true;
+                } else if self.is(i, ",") {
+                    stack.last_mut().unwrap().in_default = false;
+                }
+            }
+
+            if frame.context == Context::Block || frame.context == Context::Group {
+                let declaration = match frame.declaration {
+                    Declaration::Binding if self.is_ident(i) => Declaration::Type,
+                    Declaration::Binding if self.is(i, "{") || self.is(i, "[") => {
+                        // A pattern, followed by its type.
+                        Declaration::Binding
+                    }
+                    Declaration::Type if self.is(i, ":") => {
+                        let end = self
+                            .skip_type(i + 1)
+                            .ok_or_else(|| self.error(i + 1, "Invalid type"))?;
+                        self.blank(i, end);
+                        i = end;
+                        continue;
+                    }
+                    Declaration::Type if self.is(i, "=") => Declaration::Initializer,
+                    Declaration::Type | Declaration::Initializer if self.is(i, ",") => {
+                        Declaration::Binding
+                    }
+                    Declaration::Initializer if !self.is(i, ";") => Declaration::Initializer,
+                    _ => Declaration::None,
+                };
+                let declaration = if (self.is(i, "let") || self.is(i, "const") || self.is(i, "var"))
+                    && !after_dot
+                    && (self.is_ident(i + 1) || self.is(i + 1, "{") || self.is(i + 1, "["))
+                {
+                    Declaration::Binding
+                } else {
+                    declaration
+                };
+                stack.last_mut().unwrap().declaration = declaration;
+            }
+
+            if self.is(i, "?") {
+                stack.last_mut().unwrap().conditionals += 1;
+            } else if self.is(i, ":") && frame.conditionals > 0 {
+                stack.last_mut().unwrap().conditionals -= 1;
+            }
+
+            if self.is(i, "(")
+                && frame.conditionals > 0
+                && self.is_arrow_params(i)
+                && self
+                    .matching(i)
+                    .is_some_and(|close| self.is(close + 1, ":"))
+            {
+                // E.g. `a ? (b) : c => d`, where `: c` could be the return
+                // type of an arrow function or the alternative.
+                return Err(self.error(
+                    i,
+                    "Ambiguous arrow function with a return type in a conditional expression",
+                ));
+            }
+
+            if self.is_kind(i, Kind::Punct) && (text == "(" || text == "[" || text == "{") {
+                let context = match text {
+                    "(" => match params.take() {
+                        Some(overload) => Context::Params { overload },
+                        None if self.is_arrow_params(i)
+                            || last.is_some_and(|last| self.is(last, "catch")) =>
+                        {
+                            Context::Params { overload: None }
+                        }
+                        None if frame.context == Context::Object && self.is_method(i, last) => {
+                            Context::Params { overload: None }
+                        }
+                        None => Context::Group,
+                    },
+                    "[" => Context::Group,
+                    _ if class == Some(stack.len()) => {
+                        class = None;
+                        Context::Class
+                    }
+                    _ if self.opens_specifiers(last) => Context::Specifiers,
+                    _ if self.opens_object(last) => Context::Object,
+                    _ => Context::Block,
+                };
+                stack.push(Frame::new(context));
+                last = Some(i);
+                i += 1;
+                continue;
+            }
+
+            if self.is_kind(i, Kind::Punct) && (text == ")" || text == "]" || text == "}") {
+                if stack.len() > 1 {
+                    stack.pop();
+                }
+                {
+                    let parent = stack.last_mut().unwrap();
+                    if parent.declaration == Declaration::Binding {
+                        parent.declaration = Declaration::Type;
+                    }
+                }
+                last = Some(i);
+                i += 1;
+                if let Context::Params { overload } = frame.context {
+                    // The return type.
+                    if self.is(i, ":") {
+                        let end = self
+                            .skip_type(i + 1)
+                            .ok_or_else(|| self.error(i + 1, "Invalid type"))?;
+                        self.blank(i, end);
+                        i = end;
+                    }
+                    // Overload signatures, which have no body.
+                    if let Some((start, before)) = overload {
+                        if !self.is(i, "{") {
+                            let end = if self.is(i, ";") { i + 1 } else { i };
+                            self.blank_statement(start, end, before);
+                            last = before;
+                            i = end;
+                        }
+                    }
+                }
+                continue;
+            }
+
+            last = Some(i);
+            i += 1;
+        }
+        Ok(())
+    }
+
+    fn result(mut self) -> String {
+        self.blanks.sort_by_key(|blank| blank.start);
+        let source = self.source;
+        let mut result = String::with_capacity(source.len());
+        let mut pos = 0;
+        for blank in &self.blanks {
+            let start = std::cmp::max(blank.start, pos);
+            if start >= blank.end {
+                continue;
+            }
+            result.push_str(&source[pos..start]);
+            let mut semicolon = blank.semicolon && start == blank.start;
+            for c in source[start..blank.end].chars() {
+                if c == '\n' || c == '\r' {
+                    result.push(c);
+                    continue;
+                }
+                for _ in 0..c.len_utf16() {
+                    result.push(if semicolon { ';' } else { ' ' });
+                    semicolon = false;
+                }
+            }
+            pos = blank.end;
+        }
+        result.push_str(&source[pos..]);
+        result
+    }
+}
+
+/// Strip TypeScript-specific syntax from `source`, to obtain JavaScript.
+///
+/// Returns an error for constructs which cannot be stripped without
+/// changing the behavior of the code, e.g. `enum`.
+pub fn strip_types(source: &str) -> Result<String, ParseError> {
+    let mut stripper = Stripper {
+        source,
+        tokens: tokenize(source)?,
+        blanks: vec![],
+    };
+    stripper.strip()?;
+    Ok(stripper.result())
+}
@@ -0,0 +1,163 @@
+//! Computing the subset of the grammar used by a corpus of BinJS files, e.g.
+//! to produce a decoder which excludes the rest of the grammar.
+
+use binjs_es6::ast::Script;
+use binjs_es6::io::{Deserializer, IOPath};
+use binjs_generic::schema::Schema;
+use binjs_io::multipart::TreeTokenReader;
+use binjs_io::{Deserialization, TokenReaderError};
+use binjs_meta::spec::{NamedType, Spec, Type, TypeSpec};
+
+use serde_json::Value as JSON;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum Error {
+    IOError(std::io::Error),
+    DecodingError(PathBuf, TokenReaderError),
+}
+
+/// The parts of the grammar used by a set of files.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GrammarUsage {
+    /// The number of nodes of each kind, as reported by
+    /// `TreeTokenReader::node_kinds`, added up across files.
+    pub node_kinds: BTreeMap<String, usize>,
+
+    /// The values used by each string enum, by name of the enum.
+    pub string_enums: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl GrammarUsage {
+    /// Decode a multipart file, adding the node kinds and enum values it
+    /// uses.
+    ///
+    /// The contents of lazy fields are decoded too, so they are accounted for.
+    pub fn add_file(&mut self, data: &[u8]) -> Result<(), TokenReaderError> {
+        let spec = binjs_generic::es6::Library::spec();
+        let mut reader = TreeTokenReader::new(Cursor::new(data))?;
+        reader.count_node_kinds();
+        let mut deserializer = Deserializer::new(reader);
+        let _: Script = deserializer.deserialize(&mut IOPath::new())?;
+
+        for (kind, count) in deserializer.reader.node_kinds() {
+            *self
+                .node_kinds
+                .entry(kind.as_str().to_string())
+                .or_insert(0) += count;
+        }
+        for ((interface, field), values) in deserializer.reader.string_enums() {
+            let name = match string_enum_name(&spec, interface.as_str(), field.as_str()) {
+                Some(name) => name,
+                None => continue,
+            };
+            self.string_enums
+                .entry(name)
+                .or_insert_with(BTreeSet::new)
+                .extend(values.iter().map(|value| value.as_str().to_string()));
+        }
+        Ok(())
+    }
+
+    /// Add the node kinds and enum values used by another set of files.
+    pub fn add(&mut self, other: &GrammarUsage) {
+        for (kind, count) in &other.node_kinds {
+            *self.node_kinds.entry(kind.clone()).or_insert(0) += count;
+        }
+        for (name, values) in &other.string_enums {
+            self.string_enums
+                .entry(name.clone())
+                .or_insert_with(BTreeSet::new)
+                .extend(values.iter().cloned());
+        }
+    }
+
+    /// The subset of the grammar schema of `Schema::to_json` used by the
+    /// files: interfaces without nodes are removed, as are the values of
+    /// string enums which are never read, and enums without any value.
+    ///
+    /// Typedefs and the types of fields are kept as they are, so they may
+    /// still mention removed interfaces.
+    pub fn to_schema(&self, spec: &Spec) -> JSON {
+        let mut schema = Schema::to_json(spec);
+        if let Some(interfaces) = schema["interfaces"].as_object_mut() {
+            let names: Vec<_> = interfaces.keys().cloned().collect();
+            for name in names {
+                if !self.node_kinds.contains_key(&name) {
+                    interfaces.remove(&name);
+                }
+            }
+        }
+        if let Some(string_enums) = schema["string_enums"].as_object_mut() {
+            let names: Vec<_> = string_enums.keys().cloned().collect();
+            for name in names {
+                let used = match self.string_enums.get(&name) {
+                    Some(used) => used,
+                    None => {
+                        string_enums.remove(&name);
+                        continue;
+                    }
+                };
+                if let Some(values) = string_enums[&name].as_array_mut() {
+                    // Preserve the order of the grammar.
+                    values
+                        .retain(|value| value.as_str().map_or(false, |value| used.contains(value)));
+                }
+            }
+        }
+        schema
+    }
+}
+
+/// Decode all `files`, then return the parts of the grammar they use.
+pub fn of_files(files: &[PathBuf]) -> Result<GrammarUsage, Error> {
+    let mut usage = GrammarUsage::default();
+    for path in files {
+        let data = fs::read(path).map_err(Error::IOError)?;
+        usage
+            .add_file(&data)
+            .map_err(|err| Error::DecodingError(path.clone(), err))?;
+    }
+    Ok(usage)
+}
+
+/// List the BinJS files of a directory and its subdirectories, i.e. those
+/// with extension `.binjs`, sorted.
+pub fn corpus_files<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>, Error> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.as_ref().to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).map_err(Error::IOError)? {
+            let path = entry.map_err(Error::IOError)?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().map_or(false, |ext| ext == "binjs") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The name of the string enum stored in field `field` of `interface`, if any.
+fn string_enum_name(spec: &Spec, interface: &str, field: &str) -> Option<String> {
+    let interface = spec.get_interface_by_name(spec.get_node_name(interface)?)?;
+    let field = interface.get_field_by_name(spec.get_field_name(field)?)?;
+    enum_name(spec, field.type_())
+}
+
+fn enum_name(spec: &Spec, type_: &Type) -> Option<String> {
+    if let TypeSpec::NamedType(ref name) = *type_.spec() {
+        match spec.get_type_by_name(name)? {
+            NamedType::StringEnum(_) => return Some(name.to_str().to_string()),
+            NamedType::Typedef(ref typedef) => return enum_name(spec, typedef),
+            NamedType::Interface(_) => {}
+        }
+    }
+    None
+}
@@ -0,0 +1,61 @@
+//! Helpers shared by integration tests, included with `mod common;`.
+//!
+//! Not every test uses every helper.
+#![allow(dead_code)]
+
+use binjs::generic::Offset;
+use binjs::io::bytes::compress::Compression;
+use binjs::io::bytes::varnum::WriteVarNum;
+use binjs::io::multipart::{write_footer, ContainerHash, Options, Statistics, Targets};
+use binjs::io::Format;
+use binjs::specialized::es6::ast::{Visitor, WalkPath};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A visitor designed to reset offsets to 0, e.g. to compare an AST with
+/// the result of decoding it.
+pub struct OffsetCleanerVisitor;
+impl Visitor<()> for OffsetCleanerVisitor {
+    fn visit_offset(&mut self, _path: &WalkPath, node: &mut Offset) -> Result<(), ()> {
+        *node = Offset(0);
+        Ok(())
+    }
+}
+
+/// The multipart format with `targets` and `options`.
+pub fn multipart_with_targets(targets: Targets, options: Options) -> Format {
+    Format::Multipart {
+        targets,
+        options,
+        stats: Rc::new(RefCell::new(Statistics::default())),
+    }
+}
+
+/// The multipart format with `options`, leaving sections uncompressed.
+pub fn multipart_with(options: Options) -> Format {
+    multipart_with_targets(Targets::uniform(Compression::Identity), options)
+}
+
+/// The multipart format with the default options, leaving sections
+/// uncompressed.
+pub fn multipart() -> Format {
+    multipart_with(Options::default())
+}
+
+/// Replace the container version of `data`, then close it with a matching
+/// footer, unless `version` predates footers.
+pub fn with_version(data: &[u8], version: u32) -> Vec<u8> {
+    let mut offset = [0; 8];
+    offset.copy_from_slice(&data[data.len() - 16..data.len() - 8]);
+    let body = &data[..u64::from_le_bytes(offset) as usize];
+
+    // Current versions fit in a single byte, after `BINJS`.
+    let mut tampered = b"BINJS".to_vec();
+    tampered.write_varnum(version).unwrap();
+    tampered.extend_from_slice(&body[6..]);
+    if version > 1 {
+        write_footer(&ContainerHash::default(), &mut tampered).expect("Could not write footer");
+    }
+    tampered
+}
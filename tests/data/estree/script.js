@@ -0,0 +1,27 @@
+"use strict";
+function add(a, b = 1, ...rest) {
+    return a + b + rest.length;
+}
+var o = { x: 1, ["y" + 1]: 2, get z() { return this.x; }, m() {} };
+for (let k in o) {
+    if (/^x/i.test(k)) break;
+}
+label: while (o.x--) continue label;
+const f = async (x) => x * 2, big = 10n;
+let [p, , q = 3] = [1, 2], { r, s: t } = o;
+switch (p) {
+    case 1: p++;
+    default: q = `a${p}b${q}c`;
+    case 2: break;
+}
+try {
+    f(...[p, q]);
+} catch (e) {
+    throw new Error(e);
+} finally {
+    o = (p, q);
+}
+class C extends Object {
+    constructor() { super(); }
+    static *gen() { yield* [1]; }
+}
@@ -0,0 +1,66 @@
+//! Check that the JavaScript printed alongside a BinAST, from the same
+//! parse, decodes to the same AST as the BinAST.
+
+extern crate binjs;
+extern crate tempdir;
+
+mod common;
+
+use binjs::batch::BatchEncoder;
+use binjs::io::multipart::Metadata;
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::{Script, WalkPath, Walker};
+use binjs::specialized::es6::io::Decoder;
+use binjs::specialized::es6::Enrich;
+
+use common::{multipart, OffsetCleanerVisitor};
+
+use std::fs;
+use std::io::Cursor;
+
+use tempdir::TempDir;
+
+const SOURCES: &[&str] = &[
+    "function foo(x) { return x + 1.5; }",
+    "#!/usr/bin/env node\nconsole.log('Hello');\n",
+    "class A { constructor() { this.values = [1, 2, 3]; } }",
+];
+
+#[test]
+fn test_also_js() {
+    let dir = TempDir::new("test_also_js").expect("Could not create directory");
+    let mut encoder =
+        BatchEncoder::try_new(multipart(), Enrich::default()).expect("Could not launch encoder");
+    let parser = Shift::try_new().expect("Could not launch Shift");
+
+    for (i, source) in SOURCES.iter().enumerate() {
+        println!("* Encoding {:?}", source);
+        let path = dir.path().join(format!("{}.js", i));
+        fs::write(&path, source).expect("Could not write source");
+        let (result, js) = encoder
+            .encode_file_with_js(&path)
+            .expect("Could not encode");
+
+        println!("* The prologue is kept in the JavaScript");
+        assert_eq!(js.starts_with("#!"), source.starts_with("#!"));
+
+        println!("* Both outputs decode to the same AST");
+        let mut decoded: Script = Decoder::new()
+            .decode(&mut multipart(), Cursor::new(&result.data))
+            .expect("Could not decode");
+        decoded
+            .walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+            .expect("Could not clean offsets");
+
+        // The parser rejects shebangs.
+        let (_, text) = Metadata::from_source(&js);
+        let mut reparsed = parser.parse_str(text).expect("Could not parse JavaScript");
+        Enrich::default()
+            .enrich(&mut reparsed)
+            .expect("Could not enrich AST");
+        reparsed
+            .walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+            .expect("Could not clean offsets");
+        assert_eq!(reparsed, decoded);
+    }
+}
@@ -0,0 +1,58 @@
+//! Check that files encoded in a batch decode like files encoded one by one.
+
+extern crate binjs;
+extern crate tempdir;
+
+mod common;
+
+use binjs::batch::BatchEncoder;
+use binjs::io::multipart::Metadata;
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::Decoder;
+use binjs::specialized::es6::Enrich;
+
+use common::multipart;
+
+use std::fs;
+use std::io::Cursor;
+
+use tempdir::TempDir;
+
+const SOURCES: &[&str] = &[
+    "function foo(x) { return x + 1.5; }",
+    "#!/usr/bin/env node\nconsole.log('Hello');\n",
+    "var x = 'hello';\nconsole.log(x);\n",
+    "class A { constructor() { this.values = [1, 2, 3]; } }",
+];
+
+#[test]
+fn test_batch_encode() {
+    let dir = TempDir::new("test_batch_encode").expect("Could not create directory");
+    let mut encoder =
+        BatchEncoder::try_new(multipart(), Enrich::default()).expect("Could not launch encoder");
+    let parser = Shift::try_new().expect("Could not launch Shift");
+
+    for (i, source) in SOURCES.iter().enumerate() {
+        let path = dir.path().join(format!("{}.js", i));
+        fs::write(&path, source).expect("Could not write source");
+
+        println!("* Encoding {:?}", source);
+        let result = encoder.encode_file(&path).expect("Could not encode");
+        assert_eq!(result.source_len, source.len());
+
+        let (decoded, metadata): (Script, _) = Decoder::new()
+            .decode_with_metadata(&mut multipart(), Cursor::new(result.data))
+            .expect("Could not decode");
+        let decoded_source =
+            metadata.restore(&parser.to_source(&decoded).expect("Could not print"));
+
+        let (expected_metadata, text) = Metadata::from_source(source);
+        assert_eq!(metadata, expected_metadata);
+        let expected = parser.parse_str(text).expect("Could not parse source");
+        assert_eq!(
+            decoded_source,
+            expected_metadata.restore(&parser.to_source(&expected).expect("Could not print"))
+        );
+    }
+}
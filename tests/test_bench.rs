@@ -0,0 +1,70 @@
+//! Check that configurations are compared on a corpus, and that the one
+//! producing the smallest files wins.
+
+extern crate binjs;
+extern crate tempdir;
+
+use binjs::bench::{self, Config};
+use binjs::io::bytes::compress::Compression;
+
+use std::fs;
+
+use tempdir::TempDir;
+
+const SOURCES: &[&str] = &[
+    "function foo(x) { return [x, x, x, x, x, x, x, x]; }",
+    "var hello = 'hello'; console.log(hello, hello, hello, hello);",
+];
+
+#[test]
+fn test_bench() {
+    let dir = TempDir::new("test_bench").expect("Could not create directory");
+    let corpus = dir.path().join("corpus");
+    fs::create_dir_all(corpus.join("nested")).expect("Could not create corpus");
+    fs::write(corpus.join("0.js"), SOURCES[0]).expect("Could not write source");
+    fs::write(corpus.join("nested").join("1.js"), SOURCES[1]).expect("Could not write source");
+    fs::write(corpus.join("README"), "Not a source").expect("Could not write file");
+
+    let identity = dir.path().join("identity.toml");
+    fs::write(&identity, "compression = \"identity\"\n").expect("Could not write config");
+    let brotli = dir.path().join("brotli.toml");
+    fs::write(&brotli, "name = \"br\"\ncompression = \"br\"\n").expect("Could not write config");
+
+    println!("* Configurations are named after their file, unless they specify a name");
+    let configs = [
+        Config::from_file(&identity).expect("Could not read config"),
+        Config::from_file(&brotli).expect("Could not read config"),
+    ];
+    assert_eq!(configs[0].name, "identity");
+    assert_eq!(configs[1].name, "br");
+    assert!(Config::from_toml("typo", "compresion = \"br\"").is_err());
+
+    println!("* Only JS files are encoded, including in subdirectories");
+    let files = bench::corpus_files(&corpus).expect("Could not list corpus");
+    assert_eq!(files.len(), 2);
+
+    println!("* Totals are reported for each configuration");
+    let all_totals: Vec<_> = configs
+        .iter()
+        .map(|config| bench::run(config, &files).expect("Could not encode corpus"))
+        .collect();
+    let source_bytes: usize = SOURCES.iter().map(|source| source.len()).sum();
+    for totals in &all_totals {
+        assert_eq!(totals.files, 2);
+        assert_eq!(totals.source_bytes, source_bytes);
+        let compression = totals.compression.as_ref().expect("Missing compression");
+        assert!(compression.after_bytes < totals.encoded_bytes);
+    }
+    let compression = all_totals[1].compression.as_ref().unwrap();
+    assert!(compression.algorithms.contains(&Compression::Brotli));
+    assert!(all_totals[1].encoded_bytes < all_totals[0].encoded_bytes);
+
+    println!("* The smallest configuration wins");
+    assert_eq!(bench::smallest(&all_totals).unwrap().name, "br");
+    let report = bench::report(&all_totals);
+    let lines: Vec<_> = report.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("identity: 2 files"));
+    assert!(lines[1].starts_with("br: 2 files"));
+    assert_eq!(lines[2], "Winner on size: br");
+}
@@ -0,0 +1,112 @@
+//! Check that BigInt literals and numeric separators are parsed, encoded
+//! and printed back.
+
+extern crate binjs;
+#[macro_use]
+extern crate serde_json;
+
+mod common;
+
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::multipart;
+
+use std::io::Cursor;
+
+#[test]
+fn test_bigint() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+
+    println!("* BigInt literals are parsed, without their separators");
+    let ast = parser
+        .parse_str("const x = 1_000_000n + 5n;")
+        .expect("Could not parse source");
+    let value = serde_json::to_value(&ast).expect("Could not convert AST to JSON");
+    let init = &value["statements"][0]["declarators"][0]["init"];
+    assert_eq!(
+        init["left"],
+        json!({ "type": "LiteralBigIntExpression", "value": "1000000" })
+    );
+    assert_eq!(
+        init["right"],
+        json!({ "type": "LiteralBigIntExpression", "value": "5" })
+    );
+
+    println!("* BigInt values round-trip through encoding");
+    let data = Encoder::new()
+        .encode(None, &mut multipart(), &ast)
+        .expect("Could not encode");
+    let decoded: Script = Decoder::new()
+        .decode(&mut multipart(), Cursor::new(&data))
+        .expect("Could not decode");
+    assert_eq!(decoded, ast);
+
+    println!("* BigInt literals are printed back");
+    let js = parser.to_source(&decoded).expect("Could not print");
+    assert!(js.contains("1000000n"), "{}", js);
+    assert!(js.contains("5n"), "{}", js);
+    let reparsed = parser
+        .parse_str(&js)
+        .expect("Could not parse printed source");
+    assert_eq!(reparsed, ast);
+
+    println!("* Numeric separators are removed");
+    let ast = parser
+        .parse_str("var y = 0x1F_FF + 1_000.5;")
+        .expect("Could not parse source");
+    let expected = parser
+        .parse_str("var y = 0x1FFF + 1000.5;")
+        .expect("Could not parse source");
+    assert_eq!(ast, expected);
+
+    println!("* Literals are only rewritten where the parser fails on them");
+    for source in &[
+        "if (ok) /1_000/.test(s);",
+        "var b = 1n; if (ok) /1_000/.test(s);",
+        "function f() {}\n/ 2n/.test(s)",
+        "var b = 1_0; function f() {}\n/ 2n/.test(s)",
+    ] {
+        let ast = parser.parse_str(source).expect("Could not parse source");
+        let value = serde_json::to_value(&ast).expect("Could not convert AST to JSON");
+        let pattern = if source.contains("1_000/") {
+            "1_000"
+        } else {
+            " 2n"
+        };
+        assert_eq!(regexp_patterns(&value), vec![pattern], "{}", source);
+    }
+
+    println!("* The placeholder of BigInt literals is reserved");
+    assert!(parser.parse_str("__binjs_bigint__(\"1\"); 2n;").is_err());
+    assert!(parser.parse_str("__binjs_bigint__(\"1\");").is_err());
+    assert!(parser
+        .parse_str("\\u005f_binjs_bigint__(\"1\"); 2n;")
+        .is_err());
+    assert!(parser
+        .parse_str_tolerant("__binjs_bigint__(\"1\");")
+        .is_err());
+}
+
+/// The patterns of the regular expressions of `value`, in order.
+fn regexp_patterns(value: &serde_json::Value) -> Vec<String> {
+    let mut patterns = vec![];
+    match *value {
+        serde_json::Value::Object(ref map) => {
+            if map.get("type") == Some(&json!("LiteralRegExpExpression")) {
+                patterns.push(map["pattern"].as_str().unwrap().to_string());
+            }
+            for value in map.values() {
+                patterns.extend(regexp_patterns(value));
+            }
+        }
+        serde_json::Value::Array(ref items) => {
+            for value in items {
+                patterns.extend(regexp_patterns(value));
+            }
+        }
+        _ => {}
+    }
+    patterns
+}
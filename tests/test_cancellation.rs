@@ -0,0 +1,118 @@
+//! Check that encoding and decoding a large file stop promptly once
+//! cancelled.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::io::bytes::compress::Compression;
+use binjs::io::multipart::{OnSection, Options, ReadLimits, Targets, TreeTokenReader};
+use binjs::io::{BinjsError, CancellationToken, Deserialization, TokenReaderError};
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{Decoder, Deserializer, Encoder, IOPath};
+
+use common::multipart_with_targets;
+
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+use std::thread;
+
+const PATH: &str = "tests/data/frameworks/jquery.3.3.1.min.js";
+
+#[test]
+fn test_cancellation() {
+    thread::Builder::new()
+        .name("test_cancellation large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), PATH);
+    let mut ast = parser.parse_file(&path).expect("Could not parse source");
+    let enricher = binjs::specialized::es6::Enrich::default();
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+
+    println!("* Cancelling mid-encode stops at the next section");
+    let token = CancellationToken::new();
+    let sections = Rc::new(RefCell::new(vec![]));
+    let mut format = multipart_with_targets(
+        Targets::uniform(Compression::Brotli),
+        Options {
+            on_section: Some(OnSection({
+                let sections = sections.clone();
+                let token = token.clone();
+                Rc::new(move |header: &str, _: &[u8]| {
+                    sections.borrow_mut().push(header.to_string());
+                    token.cancel();
+                })
+            })),
+            ..Options::default()
+        },
+    );
+    match Encoder::new()
+        .with_cancellation(token.clone())
+        .encode(None, &mut format, &ast)
+    {
+        Err(BinjsError::Cancelled) => {}
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Cancelled encoding succeeded"),
+    }
+    assert_eq!(*sections.borrow(), vec!["[GRAMMAR]".to_string()]);
+
+    println!("* Without cancellation, encoding and decoding succeed");
+    let token = CancellationToken::new();
+    let data = Encoder::new()
+        .with_cancellation(token.clone())
+        .encode(
+            None,
+            &mut multipart_with_targets(Targets::uniform(Compression::Brotli), Options::default()),
+            &ast,
+        )
+        .expect("Could not encode");
+    let decoder = Decoder::new().with_cancellation(token.clone());
+    decoder
+        .decode::<_, Script>(
+            &mut multipart_with_targets(Targets::uniform(Compression::Brotli), Options::default()),
+            Cursor::new(&data),
+        )
+        .expect("Could not decode");
+
+    println!("* Cancelling once the sections are read stops reading the tree");
+    let token = CancellationToken::new();
+    let reader = TreeTokenReader::with_limits(
+        Cursor::new(&data),
+        ReadLimits {
+            cancellation: Some(token.clone()),
+            ..ReadLimits::default()
+        },
+    )
+    .expect("Could not read container");
+    token.cancel();
+    let mut deserializer = Deserializer::new(reader);
+    let result: Result<Script, _> = deserializer.deserialize(&mut IOPath::new());
+    match result {
+        Err(TokenReaderError::Cancelled) => {}
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Cancelled decoding succeeded"),
+    }
+
+    println!("* Once cancelled, decoding fails");
+    token.cancel();
+    match decoder.decode::<_, Script>(
+        &mut multipart_with_targets(Targets::uniform(Compression::Brotli), Options::default()),
+        Cursor::new(&data),
+    ) {
+        Err(BinjsError::Cancelled) => {}
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Cancelled decoding succeeded"),
+    }
+}
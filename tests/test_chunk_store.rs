@@ -0,0 +1,110 @@
+//! Check that chunks shared between files are stored once in a chunk store,
+//! and that files can be reassembled from their manifest.
+
+extern crate binjs;
+extern crate tempdir;
+
+mod common;
+
+use binjs::io::bytes::chunk::Chunker;
+use binjs::io::bytes::compress::Compression;
+use binjs::io::multipart::{
+    resolve_sections, split_chunks, store_chunks, Options, Statistics, Targets,
+};
+use binjs::io::{Format, TokenReaderError};
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::multipart;
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Cursor;
+use std::rc::Rc;
+
+#[test]
+fn test_chunk_store() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let encode = |source: &str| {
+        let ast = parser.parse_str(source).expect("Could not parse source");
+        let data = Encoder::new()
+            .encode(None, &mut multipart(), &ast)
+            .expect("Could not encode");
+        (ast, data)
+    };
+
+    // Two versions of the same script: same grammar table, mostly
+    // the same strings and tree.
+    let common: String = (0..50)
+        .map(|i| format!("function f{i}(x, y) {{ return x * {i} + y; }}\n", i = i))
+        .collect();
+    let (foo, foo_data) = encode(&format!("{}foo(1, 2);", common));
+    let (bar, bar_data) = encode(&format!("{}bar(3, 4, 5);", common));
+
+    // Small chunks, so that each section is split.
+    let chunker = Chunker::new(16, 64, 256).expect("Could not create chunker");
+    let dir = tempdir::TempDir::new("test_chunk_store").expect("Could not create directory");
+    let foo_manifest = store_chunks(&foo_data, dir.path(), &chunker).expect("Could not store foo");
+    let bar_manifest = store_chunks(&bar_data, dir.path(), &chunker).expect("Could not store bar");
+
+    println!("* Shared chunks are stored once");
+    let (_, foo_chunks) = split_chunks(&foo_data, &chunker).expect("Could not split foo");
+    let (_, bar_chunks) = split_chunks(&bar_data, &chunker).expect("Could not split bar");
+    assert!(foo_chunks.len() > 4);
+    let names: HashSet<_> = foo_chunks
+        .iter()
+        .chain(bar_chunks.iter())
+        .map(|&(ref name, _)| name.clone())
+        .collect();
+    assert!(names.len() < foo_chunks.len() + bar_chunks.len());
+    let stored = fs::read_dir(dir.path())
+        .expect("Could not list directory")
+        .count();
+    assert_eq!(stored, names.len());
+
+    println!("* Manifests cannot be decoded without the store");
+    assert!(Decoder::new()
+        .decode::<_, Script>(&mut multipart(), Cursor::new(foo_manifest.clone()))
+        .is_err());
+
+    println!("* Files are reassembled from their manifest and the store");
+    for &(expected, data, manifest) in &[
+        (&foo, &foo_data, &foo_manifest),
+        (&bar, &bar_data, &bar_manifest),
+    ] {
+        let resolved = resolve_sections(manifest, dir.path()).expect("Could not resolve chunks");
+        assert_eq!(&resolved[..], &data[..]);
+        let decoded: Script = Decoder::new()
+            .decode(&mut multipart(), Cursor::new(resolved))
+            .expect("Could not decode");
+        assert_eq!(&decoded, expected);
+    }
+
+    println!("* Missing chunks are reported");
+    let &(ref name, _) = foo_chunks
+        .iter()
+        .find(|&&(ref name, _)| bar_chunks.iter().all(|&(ref other, _)| other != name))
+        .expect("Expected a chunk specific to foo");
+    fs::remove_file(dir.path().join(name)).expect("Could not remove chunk");
+    assert!(resolve_sections(&foo_manifest, dir.path()).is_err());
+    assert!(resolve_sections(&bar_manifest, dir.path()).is_ok());
+
+    println!("* Compressed sections are not chunked");
+    let compressed = Encoder::new()
+        .encode(
+            None,
+            &mut Format::Multipart {
+                targets: Targets::uniform(Compression::Brotli),
+                options: Options::default(),
+                stats: Rc::new(RefCell::new(Statistics::default())),
+            },
+            &foo,
+        )
+        .expect("Could not encode");
+    match split_chunks(&compressed, &chunker) {
+        Err(TokenReaderError::BadCompression(_)) => {}
+        other => panic!("Unexpected result {:?}", other.map(|_| ())),
+    }
+}
@@ -0,0 +1,46 @@
+//! Check that the layout of a container accounts for all its bytes.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::io::multipart::Layout;
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::io::Encoder;
+
+use common::multipart;
+
+use std::io::Cursor;
+
+#[test]
+fn test_container_layout() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let ast = parser
+        .parse_str("function foo(x) { return x + 1.5; }")
+        .expect("Could not parse source");
+    let mut format = multipart();
+    let data = Encoder::new()
+        .encode(None, &mut format, &ast)
+        .expect("Could not encode");
+
+    let layout = Layout::read(&mut Cursor::new(data.as_slice())).expect("Could not read layout");
+    println!("{}", layout);
+
+    let names = layout
+        .sections
+        .iter()
+        .map(|section| section.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["[GRAMMAR]", "[STRINGS]", "[TREE]"]);
+
+    // Sections are contiguous, from the prologue to the footer.
+    let mut offset = layout.prologue_len;
+    for section in &layout.sections {
+        assert_eq!(section.offset, offset);
+        assert!(section.byte_len > section.compressed_bytes as u64);
+        offset += section.byte_len;
+    }
+    assert_eq!(offset, layout.footer_offset);
+    assert_eq!(layout.total_len(), data.len() as u64);
+    assert!(format!("{}", layout).ends_with(&format!("Total: {} bytes", data.len())));
+}
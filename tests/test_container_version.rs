@@ -0,0 +1,57 @@
+//! Check that containers of a more recent format version are rejected,
+//! rather than misread.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::io::multipart::{TreeTokenReader, FORMAT_VERSION};
+use binjs::io::{BinjsError, TokenReaderError};
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::{multipart, with_version};
+
+use std::io::Cursor;
+
+#[test]
+fn test_container_version() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let ast = parser
+        .parse_str("function foo(x) { return x + 1; }")
+        .expect("Could not parse source");
+    let data = Encoder::new()
+        .encode(None, &mut multipart(), &ast)
+        .expect("Could not encode");
+
+    println!("* The current version is written and accepted");
+    assert_eq!(data[b"BINJS".len()], FORMAT_VERSION as u8);
+    let decoded: Script = Decoder::new()
+        .decode(&mut multipart(), Cursor::new(&data))
+        .expect("Could not decode");
+    assert_eq!(decoded, ast);
+
+    println!("* A more recent version is rejected");
+    let newer = with_version(&data, FORMAT_VERSION + 1);
+    match TreeTokenReader::new(Cursor::new(&newer)) {
+        Err(TokenReaderError::UnsupportedContainerVersion(version)) => {
+            assert_eq!(version, FORMAT_VERSION + 1)
+        }
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Newer version was accepted"),
+    }
+    match Decoder::new().decode::<_, Script>(&mut multipart(), Cursor::new(&newer)) {
+        Err(BinjsError::Unsupported(_)) => {}
+        other => panic!("Unexpected result {:?}", other.err()),
+    }
+
+    println!("* Even with a footer that does not match");
+    let mut unchecked = data.to_vec();
+    unchecked[b"BINJS".len()] = (FORMAT_VERSION + 1) as u8;
+    match TreeTokenReader::new(Cursor::new(&unchecked)) {
+        Err(TokenReaderError::UnsupportedContainerVersion(_)) => {}
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Newer version was accepted"),
+    }
+}
@@ -0,0 +1,71 @@
+//! Check that files may be converted between formats without loss.
+
+extern crate binjs;
+
+use binjs::generic::Offset;
+use binjs::io::Format;
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::{Script, Visitor, WalkPath, Walker};
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use std::io::Cursor;
+
+/// A visitor designed to reset offsets to 0.
+struct OffsetCleanerVisitor;
+impl Visitor<()> for OffsetCleanerVisitor {
+    fn visit_offset(&mut self, _path: &WalkPath, node: &mut Offset) -> Result<(), ()> {
+        *node = Offset(0);
+        Ok(())
+    }
+}
+
+/// Decode `data` from format `from`, re-encode it in format `to`.
+fn convert(spec: &binjs::meta::spec::Spec, from: &str, to: &str, data: &[u8]) -> Box<[u8]> {
+    let mut from = Format::from_name(spec, from, &[]).expect("Could not create format");
+    let mut to = Format::from_name(spec, to, &[]).expect("Could not create format");
+    let ast: Script = Decoder::new()
+        .decode(&mut from, Cursor::new(data))
+        .expect("Could not decode");
+    Encoder::new()
+        .encode(None, &mut to, &ast)
+        .expect("Could not encode")
+}
+
+#[test]
+fn test_convert() {
+    let spec = binjs::generic::es6::Library::spec();
+    let parser = Shift::try_new().expect("Could not launch Shift");
+
+    let source = "function foo(x) { if (x) { return 'yes'; } return x + 1.5; }";
+    let mut reference = parser.parse_str(source).expect("Could not parse source");
+    let enricher = binjs::specialized::es6::Enrich::default();
+    enricher
+        .enrich(&mut reference)
+        .expect("Could not enrich AST");
+
+    let mut multipart =
+        Format::from_name(&spec, "multipart", &[]).expect("Could not create format");
+    let original = Encoder::new()
+        .encode(None, &mut multipart, &reference)
+        .expect("Could not encode");
+
+    let entropy = convert(&spec, "multipart", "entropy", &original);
+    let back = convert(&spec, "entropy", "multipart", &entropy);
+
+    let mut decoded: Script = Decoder::new()
+        .decode(&mut multipart, Cursor::new(back))
+        .expect("Could not decode");
+    for tree in &mut [&mut reference, &mut decoded] {
+        tree.walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+            .expect("Could not cleanup offsets");
+    }
+    assert_eq!(decoded, reference);
+
+    let printer = Shift::try_new().expect("Could not launch Shift");
+    assert_eq!(
+        printer.to_source(&decoded).expect("Could not pretty-print"),
+        printer
+            .to_source(&reference)
+            .expect("Could not pretty-print")
+    );
+}
@@ -0,0 +1,90 @@
+//! Check that decoding the same file repeatedly is served from the cache.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::io::metrics::{MetricsSink, PhaseMetrics};
+use binjs::io::Format;
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{CachingDecoder, Encoder};
+
+use common::multipart;
+
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+
+/// A sink that simply records all the cache lookups it receives.
+#[derive(Default)]
+struct RecordingSink {
+    lookups: RefCell<Vec<bool>>,
+}
+impl MetricsSink for RecordingSink {
+    fn record(&self, _metrics: PhaseMetrics) {}
+    fn record_cache_lookup(&self, hit: bool) {
+        self.lookups.borrow_mut().push(hit);
+    }
+}
+
+#[test]
+fn test_decode_cache() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let encode = |source: &str| {
+        let ast = parser.parse_str(source).expect("Could not parse source");
+        let data = Encoder::new()
+            .encode(None, &mut multipart(), &ast)
+            .expect("Could not encode");
+        (ast, data)
+    };
+    let (foo, foo_data) = encode("function foo() { return 1; }");
+    let (_, bar_data) = encode("function bar() { return 2; }");
+
+    let sink = Rc::new(RecordingSink::default());
+    let mut decoder = CachingDecoder::new(1).with_metrics(sink.clone());
+    let mut decode = |data: &[u8]| -> Script {
+        decoder
+            .decode(&mut multipart(), Cursor::new(data.to_vec()))
+            .expect("Could not decode")
+    };
+
+    println!("* Decoding the same file twice hits the cache");
+    let first = decode(&foo_data);
+    let second = decode(&foo_data);
+    assert_eq!(first, second);
+    assert_eq!(first, foo);
+    assert_eq!(*sink.lookups.borrow(), vec![false, true]);
+
+    println!("* Least recently used files are evicted");
+    decode(&bar_data);
+    let third = decode(&foo_data);
+    assert_eq!(third, foo);
+    assert_eq!(*sink.lookups.borrow(), vec![false, true, false, false]);
+
+    println!("* Using a file makes it the most recently used");
+    let (_, baz_data) = encode("function baz() { return 3; }");
+    let sink = Rc::new(RecordingSink::default());
+    let mut decoder = CachingDecoder::new(2).with_metrics(sink.clone());
+    for data in &[
+        &foo_data, &bar_data, &foo_data, &baz_data, &foo_data, &bar_data,
+    ] {
+        let _: Script = decoder
+            .decode(&mut multipart(), Cursor::new(data.to_vec()))
+            .expect("Could not decode");
+    }
+    // `baz` evicts `bar` rather than `foo`, used more recently.
+    assert_eq!(
+        *sink.lookups.borrow(),
+        vec![false, false, true, false, true, false]
+    );
+
+    println!("* Files are identified by their format, too");
+    let sink = Rc::new(RecordingSink::default());
+    let mut decoder = CachingDecoder::new(2).with_metrics(sink.clone());
+    let _: Script = decoder
+        .decode(&mut multipart(), Cursor::new(foo_data.to_vec()))
+        .expect("Could not decode");
+    let _ = decoder.decode(&mut Format::Simple, Cursor::new(foo_data.to_vec()));
+    assert_eq!(*sink.lookups.borrow(), vec![false, false]);
+}
@@ -0,0 +1,112 @@
+//! Decode files held in memory, reading uncompressed trees in place, ensure
+//! that we obtain the same AST as by copying them.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::io::bytes::compress::Compression;
+use binjs::io::multipart::{Options, Targets, TreeOrder};
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::{Script, WalkPath, Walker};
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::{multipart_with_targets, OffsetCleanerVisitor};
+
+use std::io::Cursor;
+use std::rc::Rc;
+use std::thread;
+
+const PATH: &str = "tests/data/frameworks/jquery.3.3.1.min.js";
+
+#[test]
+fn test_decode_shared() {
+    thread::Builder::new()
+        .name("test_decode_shared large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), PATH);
+    let mut ast = parser.parse_file(&path).expect("Could not parse source");
+    let enricher = binjs::specialized::es6::Enrich::default();
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+    ast.walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+        .expect("Could not clean offsets");
+
+    for &(name, ref targets, tree_order) in &[
+        ("fast tree", Targets::fast_tree(), TreeOrder::PreOrder),
+        ("fast tree", Targets::fast_tree(), TreeOrder::BreadthFirst),
+        (
+            "identity",
+            Targets::uniform(Compression::Identity),
+            TreeOrder::PreOrder,
+        ),
+        (
+            "brotli",
+            Targets::uniform(Compression::Brotli),
+            TreeOrder::PreOrder,
+        ),
+    ] {
+        println!("* Decoding {}, {:?}, in place and copied", name, tree_order);
+        let data = Encoder::new()
+            .encode(
+                None,
+                &mut multipart_with_targets(
+                    targets.clone(),
+                    Options {
+                        tree_order,
+                        ..Options::default()
+                    },
+                ),
+                &ast,
+            )
+            .expect("Could not encode");
+
+        let mut shared: Script = Decoder::new()
+            .decode_shared(Rc::new(data.to_vec()))
+            .expect("Could not decode in place");
+        shared
+            .walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+            .expect("Could not clean offsets");
+
+        let mut copied: Script = Decoder::new()
+            .decode(
+                &mut multipart_with_targets(Targets::fast_tree(), Options::default()),
+                Cursor::new(&data),
+            )
+            .expect("Could not decode copied");
+        copied
+            .walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+            .expect("Could not clean offsets");
+
+        assert_eq!(shared, copied);
+        assert_eq!(shared, ast);
+    }
+
+    println!("* Corrupted files are rejected in place too");
+    let data = Encoder::new()
+        .encode(
+            None,
+            &mut multipart_with_targets(
+                Targets::fast_tree(),
+                Options {
+                    tree_order: TreeOrder::PreOrder,
+                    ..Options::default()
+                },
+            ),
+            &ast,
+        )
+        .expect("Could not encode");
+    let mut truncated = data.to_vec();
+    truncated.truncate(data.len() / 2);
+    let result: Result<Script, _> = Decoder::new().decode_shared(Rc::new(truncated));
+    assert!(result.is_err());
+}
@@ -0,0 +1,73 @@
+//! Check that `Decoder::decode_to_binast_json` yields the BinAST JSON of the
+//! source, as written by hand, and that it is read back to the same script.
+
+extern crate binjs;
+#[macro_use]
+extern crate serde_json;
+
+mod common;
+
+use binjs::source::json::script_from_json;
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::multipart;
+
+use std::io::Cursor;
+
+#[test]
+fn test_decode_to_binast_json() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let encode = |source: &str| {
+        let ast = parser.parse_str(source).expect("Could not parse source");
+        Encoder::new()
+            .encode(None, &mut multipart(), &ast)
+            .expect("Could not encode")
+    };
+
+    println!("* The value is the BinAST JSON of the source");
+    let data = encode("foo(\"bar\");");
+    let value = Decoder::new()
+        .decode_to_binast_json(&mut multipart(), Cursor::new(&data))
+        .expect("Could not decode");
+    assert_eq!(
+        value,
+        json!({
+            "type": "Script",
+            "scope": {
+                "type": "AssertedScriptGlobalScope",
+                "declaredNames": [],
+                "hasDirectEval": false
+            },
+            "directives": [],
+            "statements": [{
+                "type": "ExpressionStatement",
+                "expression": {
+                    "type": "CallExpression",
+                    "callee": { "type": "IdentifierExpression", "name": "foo" },
+                    "arguments": [{ "type": "LiteralStringExpression", "value": "bar" }]
+                }
+            }]
+        })
+    );
+
+    println!("* Node kinds are those of the grammar, not ESTree");
+    let data = encode("function foo(x) { return x; }");
+    let value = Decoder::new()
+        .decode_to_binast_json(&mut multipart(), Cursor::new(&data))
+        .expect("Could not decode");
+    assert_eq!(value["statements"][0]["type"], "EagerFunctionDeclaration");
+    assert_eq!(value["statements"][0]["name"]["type"], "BindingIdentifier");
+    assert_eq!(value["statements"][0]["name"]["name"], "foo");
+    assert!(value["statements"][0].get("id").is_none());
+
+    println!("* The value is read back to the decoded script");
+    let decoded: Script = Decoder::new()
+        .decode(&mut multipart(), Cursor::new(&data))
+        .expect("Could not decode");
+    assert_eq!(
+        script_from_json(&value).expect("Could not read JSON"),
+        decoded
+    );
+}
@@ -0,0 +1,105 @@
+//! Check that scripts may be rewritten while decoding, e.g. for instrumentation.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::generic::VisitMe;
+use binjs::io::BinjsError;
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::*;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::multipart;
+
+use std::io::Cursor;
+
+const SOURCE: &str = "
+function add(a, b) { return a + b; }
+const twice = (f) => { return (x) => f(f(x)); };
+class A { method() { return add(1, 2); } }
+";
+
+/// Count calls to each function, by inserting `__coverage[i]++;` at the start
+/// of the body of function `i`.
+struct CoverageVisitor<'a> {
+    parser: &'a Shift,
+    functions: usize,
+}
+impl<'a> CoverageVisitor<'a> {
+    fn instrument(&mut self, body: &mut Vec<Statement>) -> Result<VisitMe<()>, BinjsError> {
+        let mut counter = self
+            .parser
+            .parse_str(&format!("__coverage[{}]++;", self.functions))
+            .map_err(|err| BinjsError::Unsupported(format!("{:?}", err)))?;
+        self.functions += 1;
+        body.insert(0, counter.statements.remove(0));
+        Ok(VisitMe::HoldThis(()))
+    }
+}
+impl<'a> Visitor<BinjsError> for CoverageVisitor<'a> {
+    fn enter_function_or_method_contents(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut FunctionOrMethodContents,
+    ) -> Result<VisitMe<()>, BinjsError> {
+        self.instrument(&mut node.body)
+    }
+
+    fn enter_arrow_expression_contents_with_function_body(
+        &mut self,
+        _path: &WalkPath,
+        node: &mut ArrowExpressionContentsWithFunctionBody,
+    ) -> Result<VisitMe<()>, BinjsError> {
+        self.instrument(&mut node.body)
+    }
+}
+
+#[test]
+fn test_decode_transform() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let mut ast = parser.parse_str(SOURCE).expect("Could not parse source");
+    let enricher = binjs::specialized::es6::Enrich::default();
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+    let data = Encoder::new()
+        .encode(None, &mut multipart(), &ast)
+        .expect("Could not encode");
+
+    let mut visitor = CoverageVisitor {
+        parser: &parser,
+        functions: 0,
+    };
+    let instrumented = Decoder::new()
+        .decode_with_transform(&mut multipart(), Cursor::new(data), &mut visitor)
+        .expect("Could not decode");
+
+    // `add`, `twice` and `method`, but not the arrow with an expression body.
+    assert_eq!(visitor.functions, 3);
+
+    println!("* The instrumented source is valid JavaScript");
+    let source = parser.to_source(&instrumented).expect("Could not print");
+    for i in 0..3 {
+        assert!(
+            source.contains(&format!("__coverage[{}]++", i)),
+            "Missing counter {} in {}",
+            i,
+            source
+        );
+    }
+    let reparsed = parser
+        .parse_str(&source)
+        .expect("Could not parse instrumented source");
+    assert_eq!(
+        parser.to_source(&reparsed).expect("Could not print"),
+        source
+    );
+
+    println!("* The instrumented script round-trips through encoding");
+    let data = Encoder::new()
+        .encode(None, &mut multipart(), &instrumented)
+        .expect("Could not encode instrumented script");
+    let decoded: Script = Decoder::new()
+        .decode(&mut multipart(), Cursor::new(data))
+        .expect("Could not decode instrumented script");
+    assert_eq!(parser.to_source(&decoded).expect("Could not print"), source);
+}
@@ -0,0 +1,209 @@
+//! Check that `decode_untrusted` rejects each class of malicious input.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::io::bytes::compress::Compression;
+use binjs::io::bytes::varnum::WriteVarNum;
+use binjs::io::multipart::{write_footer, ContainerHash, FORMAT_VERSION};
+use binjs::io::{BinjsError, Format};
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{decode_untrusted, Decoder, Encoder};
+
+use common::multipart;
+
+use std::io::{Cursor, Write};
+use std::thread;
+
+fn encode(parser: &Shift, source: &str) -> Vec<u8> {
+    let ast = parser.parse_str(source).expect("Could not parse source");
+    Encoder::new()
+        .encode(None, &mut multipart(), &ast)
+        .expect("Could not encode")
+        .into_vec()
+}
+
+/// The bytes of a container preceding its footer.
+fn body(data: &[u8]) -> Vec<u8> {
+    let mut offset = [0; 8];
+    offset.copy_from_slice(&data[data.len() - 16..data.len() - 8]);
+    data[..u64::from_le_bytes(offset) as usize].to_vec()
+}
+
+/// Close a container with a footer matching its (possibly tampered) contents.
+fn close(mut body: Vec<u8>) -> Vec<u8> {
+    write_footer(&ContainerHash::default(), &mut body).expect("Could not write footer");
+    body
+}
+
+/// The start of a container, up to its first section.
+fn prologue() -> Vec<u8> {
+    let mut data = b"BINJS".to_vec();
+    data.write_varnum(FORMAT_VERSION).unwrap();
+    data
+}
+
+#[test]
+fn test_decode_untrusted_valid() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let data = encode(&parser, "function foo(x) { return [x, 'bar', 1.5]; }");
+    let expected: Script = Decoder::new()
+        .decode(&mut multipart(), Cursor::new(&data))
+        .expect("Could not decode");
+    let decoded = decode_untrusted(Cursor::new(&data)).expect("Could not decode untrusted");
+    assert_eq!(decoded, expected);
+}
+
+#[test]
+fn test_decode_untrusted_bomb() {
+    // 64 MiB of zeros compress to a few dozen KiB.
+    let mut data = prologue();
+    data.write_all(b"[GRAMMAR]").unwrap();
+    Compression::Gzip
+        .compress(&vec![0; 64 * 1024 * 1024], &mut data)
+        .expect("Could not compress");
+    let data = close(data);
+    assert!(data.len() < 1024 * 1024);
+    assert!(decode_untrusted(Cursor::new(&data)).is_err());
+}
+
+#[test]
+fn test_decode_untrusted_many_sections() {
+    let mut data = prologue();
+    for _ in 0..1000 {
+        data.write_all(b"[?EMPTY]identity;").unwrap();
+        data.write_varnum(0).unwrap();
+    }
+    let data = close(data);
+    match decode_untrusted(Cursor::new(&data)) {
+        Err(BinjsError::Limit(_)) => {}
+        other => panic!("Section count not limited: {:?}", other.err()),
+    }
+}
+
+#[test]
+fn test_decode_untrusted_deep_nesting() {
+    // Encoding and decoding deep trees from trusted input requires a large
+    // stack.
+    let data = thread::Builder::new()
+        .stack_size(64 * 1024 * 1024)
+        .spawn(|| {
+            let parser = Shift::try_new().expect("Could not launch Shift");
+            let source = format!("{}0{};", "[".repeat(400), "]".repeat(400));
+            let data = encode(&parser, &source);
+
+            println!("* Deep trees are decoded from trusted input");
+            let _: Script = Decoder::new()
+                .decode(&mut multipart(), Cursor::new(&data))
+                .expect("Could not decode");
+            data
+        })
+        .expect("Could not launch thread")
+        .join()
+        .expect("Error in thread");
+
+    // Untrusted input must be rejected before exhausting a default stack.
+    thread::spawn(move || {
+        println!("* Deep trees are rejected from untrusted input");
+        match decode_untrusted(Cursor::new(&data)) {
+            Err(BinjsError::Limit(_)) => {}
+            other => panic!("Depth not limited: {:?}", other.err()),
+        }
+    })
+    .join()
+    .expect("Error in thread");
+}
+
+#[test]
+fn test_decode_untrusted_framework() {
+    // Parsing and encoding a framework requires a large stack.
+    let (data, statements) = thread::Builder::new()
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            let parser = Shift::try_new().expect("Could not launch Shift");
+            let path = format!(
+                "{}/tests/data/frameworks/jquery.3.3.1.min.js",
+                env!("CARGO_MANIFEST_DIR")
+            );
+            let ast = parser.parse_file(&path).expect("Could not parse source");
+            let data = Encoder::new()
+                .encode(None, &mut multipart(), &ast)
+                .expect("Could not encode")
+                .into_vec();
+            (data, ast.statements.len())
+        })
+        .expect("Could not launch thread")
+        .join()
+        .expect("Error in thread");
+
+    // Real-world code must fit within the limits, on a default stack.
+    thread::spawn(move || {
+        println!("* Frameworks are decoded from untrusted input");
+        let decoded = decode_untrusted(Cursor::new(&data)).expect("Could not decode untrusted");
+        assert_eq!(decoded.statements.len(), statements);
+    })
+    .join()
+    .expect("Error in thread");
+}
+
+#[test]
+fn test_decode_untrusted_huge_lengths() {
+    // A section claiming 1 GiB, followed by a few bytes.
+    let mut data = prologue();
+    data.write_all(b"[GRAMMAR]identity;").unwrap();
+    data.write_varnum(1024 * 1024 * 1024).unwrap();
+    data.write_all(&[0; 16]).unwrap();
+    let data = close(data);
+    assert!(decode_untrusted(Cursor::new(&data)).is_err());
+
+    // An lzw section expanding past the limits of untrusted input.
+    let mut data = prologue();
+    data.write_all(b"[GRAMMAR]").unwrap();
+    Compression::Lzw
+        .compress(&vec![0; 64 * 1024 * 1024], &mut data)
+        .expect("Could not compress");
+    let data = close(data);
+    assert!(decode_untrusted(Cursor::new(&data)).is_err());
+}
+
+#[test]
+fn test_decode_untrusted_bad_utf8() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let data = encode(&parser, "var x = '\u{e9}';");
+
+    // Replace the UTF-8 encoding of `é` with bytes that are not UTF-8.
+    let mut tampered = body(&data);
+    let found: Vec<_> = tampered
+        .windows(2)
+        .enumerate()
+        .filter(|&(_, window)| window == [0xC3, 0xA9])
+        .map(|(index, _)| index)
+        .collect();
+    assert_eq!(found.len(), 1);
+    tampered[found[0]] = 0xFF;
+    tampered[found[0] + 1] = 0xFE;
+    let tampered = close(tampered);
+    match decode_untrusted(Cursor::new(&tampered)) {
+        Err(BinjsError::Format(_)) => {}
+        other => panic!("Invalid UTF-8 not rejected: {:?}", other.err()),
+    }
+}
+
+#[test]
+fn test_decode_untrusted_trailing_bytes() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let data = encode(&parser, "function foo(x) { return x; }");
+    let mut tampered = body(&data);
+    tampered.extend_from_slice(b"[?EXTRA]");
+    let tampered = close(tampered);
+
+    println!("* Trailing bytes are ignored in trusted input");
+    let _: Script = Decoder::new()
+        .decode(&mut multipart(), Cursor::new(&tampered))
+        .expect("Could not decode");
+
+    println!("* Trailing bytes are rejected in untrusted input");
+    assert!(decode_untrusted(Cursor::new(&tampered)).is_err());
+}
@@ -0,0 +1,50 @@
+//! Check that non-fatal observations made while decoding are returned
+//! alongside the AST by `Decoder::decode_with_warnings`.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::io::multipart::FORMAT_VERSION;
+use binjs::io::DecodeWarning;
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::{multipart, with_version};
+
+use std::io::Cursor;
+
+#[test]
+fn test_decode_warnings() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let ast = parser
+        .parse_str("function foo(x) { return x + 1; }")
+        .expect("Could not parse source");
+    let data = Encoder::new()
+        .encode(None, &mut multipart(), &ast)
+        .expect("Could not encode");
+
+    println!("* The current version yields no warning");
+    let (expected, warnings): (Script, _) = Decoder::new()
+        .decode_with_warnings(&mut multipart(), Cursor::new(&data))
+        .expect("Could not decode");
+    assert_eq!(warnings, vec![]);
+
+    println!("* A deprecated version yields a warning, and the AST");
+    // Version 1 predates the footer.
+    let deprecated = with_version(&data, 1);
+    let (decoded, warnings): (Script, _) = Decoder::new()
+        .decode_with_warnings(&mut multipart(), Cursor::new(&deprecated))
+        .expect("Could not decode deprecated version");
+    assert_eq!(warnings, vec![DecodeWarning::DeprecatedVersion(1)]);
+    assert_eq!(decoded, expected);
+
+    println!("* Unknown versions are still rejected");
+    for version in &[0, FORMAT_VERSION + 1] {
+        let unknown = with_version(&data, *version);
+        assert!(Decoder::new()
+            .decode_with_warnings::<_, Script>(&mut multipart(), Cursor::new(&unknown))
+            .is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! Encode a file with identical bodies of lazy functions, ensure that only
+//! one of them is stored, and that all of them decode. Encode a file with
+//! distinct bodies, ensure that it does not grow.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::generic::{IdentifierName, SharedString};
+use binjs::io::bytes::varnum::{ReadVarNum, WriteVarNum};
+use binjs::io::multipart::{OnSection, Options, TreeTokenReader};
+use binjs::io::Deserialization;
+use binjs::specialized::es6::ast::*;
+use binjs::specialized::es6::io::{Decoder, Deserializer, Encoder};
+
+use common::{multipart, multipart_with, OffsetCleanerVisitor};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::rc::Rc;
+
+/// The contents of a function returning `value`.
+fn contents(value: &str) -> FunctionOrMethodContents {
+    FunctionOrMethodContents {
+        body: vec![ReturnStatement {
+            expression: Some(
+                LiteralStringExpression {
+                    value: SharedString::from_str(value),
+                }
+                .into(),
+            ),
+        }
+        .into()],
+        ..Default::default()
+    }
+}
+
+fn function(name: &str, contents: &FunctionOrMethodContents) -> Statement {
+    LazyFunctionDeclaration {
+        name: BindingIdentifier {
+            name: IdentifierName::from_str(name),
+        },
+        contents: contents.clone(),
+        ..Default::default()
+    }
+    .into()
+}
+
+fn script(statements: Vec<Statement>) -> Script {
+    Script {
+        scope: Default::default(),
+        directives: vec![],
+        statements,
+    }
+}
+
+/// Encode `ast`, returning the data and the uncompressed sections, by header.
+fn encode(ast: &Script, dedup_bodies: bool) -> (Box<[u8]>, HashMap<String, Vec<u8>>) {
+    let sections = Rc::new(RefCell::new(HashMap::new()));
+    let options = Options {
+        dedup_bodies,
+        on_section: Some(OnSection({
+            let sections = sections.clone();
+            Rc::new(move |header: &str, bytes: &[u8]| {
+                sections
+                    .borrow_mut()
+                    .insert(header.to_string(), bytes.to_vec());
+            })
+        })),
+        ..Options::default()
+    };
+    let data = Encoder::new()
+        .encode(None, &mut multipart_with(options), ast)
+        .expect("Could not encode");
+    let sections = sections.borrow().clone();
+    (data, sections)
+}
+
+#[test]
+fn test_dedup_bodies() {
+    // As generated from a template.
+    let contents = contents("generated");
+    let ast = script(vec![
+        function("a", &contents),
+        function("b", &contents),
+        function("c", &contents),
+    ]);
+
+    let (_, without) = encode(&ast, false);
+    let (data, with) = encode(&ast, true);
+
+    println!("* One body is stored, the two others are references");
+    assert!(!without.contains_key("[BODIES]"));
+    let mut bodies = Cursor::new(&with["[BODIES]"]);
+    // A single entry, then its position and byte length.
+    assert_eq!(bodies.read_varnum().unwrap(), 1);
+    bodies.read_varnum().unwrap();
+    let body_len = bodies.read_varnum().unwrap() as usize;
+    let mut offset = vec![];
+    offset.write_varnum(body_len as u32).unwrap();
+    // A reference is a byte length of 0, then the id of the body.
+    let reference_len = 2;
+    assert_eq!(
+        without["[TREE]"].len() - with["[TREE]"].len(),
+        2 * (offset.len() + body_len - reference_len)
+    );
+
+    println!("* All three functions decode");
+    let mut decoded: Script = Decoder::new()
+        .decode(&mut multipart(), Cursor::new(&data))
+        .expect("Could not decode");
+    decoded
+        .walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+        .expect("Could not clean offsets");
+    assert_eq!(decoded, ast);
+
+    println!("* Bodies reached through references may be skipped, then decoded");
+    let reader = TreeTokenReader::new(Cursor::new(&data)).expect("Could not read container");
+    let mut deserializer = Deserializer::new(reader).with_deferred_lazy_fields(true);
+    let decoded: Script = deserializer
+        .deserialize(&mut IOPath::new())
+        .expect("Could not decode");
+    assert_eq!(decoded.statements.len(), 3);
+    for statement in &decoded.statements {
+        let position = match *statement {
+            Statement::LazyFunctionDeclaration(ref declaration) => declaration
+                .contents_position
+                .expect("Contents should be left undecoded"),
+            ref other => panic!("Expected a LazyFunctionDeclaration, got {:?}", other),
+        };
+        let decoded_contents: FunctionOrMethodContents = deserializer
+            .deserialize_lazy(position, &mut IOPath::new())
+            .expect("Could not decode lazy contents");
+        assert_eq!(decoded_contents, contents);
+    }
+}
+
+#[test]
+fn test_dedup_unique_bodies() {
+    println!("* Distinct bodies are not entered in the table, nor grow the tree");
+    let ast = script(vec![
+        function("a", &contents("a")),
+        function("b", &contents("b")),
+        function("c", &contents("c")),
+    ]);
+    let (without_data, without) = encode(&ast, false);
+    let (data, with) = encode(&ast, true);
+    assert!(!with.contains_key("[BODIES]"));
+    assert_eq!(with["[TREE]"], without["[TREE]"]);
+    assert_eq!(data.len(), without_data.len());
+
+    println!("* Only bodies referenced again are entered in the table");
+    let ast = script(vec![
+        function("a", &contents("a")),
+        function("b", &contents("b")),
+        function("c", &contents("c")),
+        function("d", &contents("b")),
+    ]);
+    let (data, with) = encode(&ast, true);
+    let mut bodies = Cursor::new(&with["[BODIES]"]);
+    assert_eq!(bodies.read_varnum().unwrap(), 1);
+    let decoded: Script = Decoder::new()
+        .decode(&mut multipart(), Cursor::new(&data))
+        .expect("Could not decode");
+    assert_eq!(decoded.statements.len(), 4);
+}
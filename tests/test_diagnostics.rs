@@ -0,0 +1,106 @@
+//! Check that diagnostics are collected and survive encoding, and that the
+//! constructions they report are encoded as written.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::io::multipart::{Options, TreeTokenReader};
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::diagnostics::DiagnosticsVisitor;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::multipart_with;
+
+use std::io::Cursor;
+
+#[test]
+fn test_diagnostics_roundtrip() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let enricher = binjs::specialized::es6::Enrich::default();
+
+    let encode = |source: &str, with_diagnostics: bool| {
+        let mut ast = parser.parse_str(source).expect("Could not parse source");
+        enricher.enrich(&mut ast).expect("Could not enrich AST");
+        let diagnostics = if with_diagnostics {
+            DiagnosticsVisitor::collect(&mut ast)
+        } else {
+            vec![]
+        };
+        let mut format = multipart_with(Options {
+            diagnostics,
+            ..Options::default()
+        });
+        Encoder::new()
+            .encode(None, &mut format, &ast)
+            .expect("Could not encode")
+    };
+
+    let source = "with (obj) { eval('a'); } foo(); eval('b');";
+
+    println!("* Diagnostics are reported in source order");
+    let data = encode(source, true);
+    let reader = TreeTokenReader::new(Cursor::new(data)).expect("Could not decode container");
+    let kinds: Vec<_> = reader
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| diagnostic.kind.as_str())
+        .collect();
+    assert_eq!(kinds, vec!["with", "eval", "eval"]);
+
+    println!("* Diagnostics are opt-in");
+    let data = encode(source, false);
+    let reader = TreeTokenReader::new(Cursor::new(data)).expect("Could not decode container");
+    assert!(reader.diagnostics().is_empty());
+
+    println!("* Clean sources have no diagnostics");
+    let data = encode("foo(); bar.eval();", true);
+    let reader = TreeTokenReader::new(Cursor::new(data)).expect("Could not decode container");
+    assert!(reader.diagnostics().is_empty());
+}
+
+#[test]
+fn test_diagnostics_object_keys() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let source = "var o = { a: 1, __proto__: p, a: 2, get b() {}, set b(x) {}, c };";
+    let parsed = parser.parse_str(source).expect("Could not parse source");
+    let mut ast = parsed.clone();
+    binjs::specialized::es6::Enrich::default()
+        .enrich(&mut ast)
+        .expect("Could not enrich AST");
+    let diagnostics = DiagnosticsVisitor::collect(&mut ast);
+
+    println!("* Duplicate keys and `__proto__` are reported");
+    let kinds: Vec<_> = diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.kind.as_str())
+        .collect();
+    assert_eq!(kinds, vec!["proto", "duplicate-key"]);
+
+    let format = |diagnostics| {
+        multipart_with(Options {
+            diagnostics,
+            ..Options::default()
+        })
+    };
+    let data = Encoder::new()
+        .encode(None, &mut format(diagnostics), &ast)
+        .expect("Could not encode");
+
+    println!("* The object decodes with all its properties, in order");
+    let decoded: Script = Decoder::new()
+        .decode(&mut format(vec![]), Cursor::new(&data))
+        .expect("Could not decode");
+    let printed = parser.to_source(&decoded).expect("Could not pretty-print");
+    let reparsed = parser
+        .parse_str(&printed)
+        .expect("Could not parse decoded source");
+    assert_eq!(reparsed, parsed, "{} != {}", printed, source);
+
+    println!("* Getters and setters of the same property are not duplicates");
+    let mut ast = parser
+        .parse_str("var o = { get a() {}, set a(x) {}, [b]: 1, [b]: 2, '__proto__'() {} };")
+        .expect("Could not parse source");
+    assert!(DiagnosticsVisitor::collect(&mut ast).is_empty());
+}
@@ -5,6 +5,7 @@ use binjs::generic::{IdentifierName, InterfaceName, Offset, PropertyKey, SharedS
 use binjs::io::entropy;
 use binjs::io::entropy::dictionary::{
     DictionaryBuilder, FilesContaining, LinearTable, Options as DictionaryOptions,
+    UserExtensibleData,
 };
 use binjs::io::entropy::rw::TableRefStreamState;
 use binjs::io::{Deserialization, Serialization, TokenSerializer};
@@ -241,6 +242,62 @@ where
     assert_eq!(found, expected);
 }
 
+test!(test_dictionary_merge, {
+    let literal = |value: &str| Some(SharedString::from_string(value.to_string()));
+
+    let mut existing = UserExtensibleData::default();
+    existing
+        .string_literal_instances
+        .insert(literal("a"), FilesContaining(5));
+    existing
+        .string_literal_instances
+        .insert(literal("b"), FilesContaining(1));
+    existing
+        .unsigned_long_instances
+        .insert(0, FilesContaining(2));
+
+    let mut fresh = UserExtensibleData::default();
+    fresh
+        .string_literal_instances
+        .insert(literal("b"), FilesContaining(3));
+    fresh
+        .string_literal_instances
+        .insert(literal("c"), FilesContaining(2));
+    fresh.unsigned_long_instances.insert(0, FilesContaining(1));
+    fresh.unsigned_long_instances.insert(1, FilesContaining(1));
+
+    println!("* Merging sums frequencies");
+    existing.merge(&fresh);
+    check_strings(
+        &existing.string_literal_instances,
+        vec![("a", 5), ("b", 4), ("c", 2)],
+        literal,
+    );
+    assert_eq!(
+        existing.unsigned_long_instances.get(&0),
+        Some(&FilesContaining(3))
+    );
+    assert_eq!(
+        existing.unsigned_long_instances.get(&1),
+        Some(&FilesContaining(1))
+    );
+
+    println!("* Capping keeps the most frequent values");
+    existing.retain_most_frequent(2);
+    check_strings(
+        &existing.string_literal_instances,
+        vec![("a", 5), ("b", 4)],
+        literal,
+    );
+    assert_eq!(existing.unsigned_long_instances.len(), 2);
+    existing.retain_most_frequent(1);
+    assert_eq!(existing.unsigned_long_instances.len(), 1);
+    assert_eq!(
+        existing.unsigned_long_instances.get(&0),
+        Some(&FilesContaining(3))
+    );
+});
+
 test!(test_linear_table, {
     // Initialize a linear table from a shared dictionary.
     let mut value_to_instances = HashMap::new();
@@ -0,0 +1,91 @@
+//! Check that directive prologues survive encoding and decoding, as directives.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::*;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::multipart;
+
+use std::io::Cursor;
+
+const SOURCE: &str = "
+'use strict';
+function asm() {
+    \"use strict\";
+    \"use asm\";
+    var x = 1;
+    \"not a directive\";
+    return x;
+}
+";
+
+fn raw_values(directives: &[Directive]) -> Vec<&str> {
+    directives
+        .iter()
+        .map(|directive| directive.raw_value.as_str())
+        .collect()
+}
+
+#[test]
+fn test_directives_roundtrip() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let mut ast = parser.parse_str(SOURCE).expect("Could not parse source");
+    let enricher = binjs::specialized::es6::Enrich::default();
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+
+    let data = Encoder::new()
+        .encode(None, &mut multipart(), &ast)
+        .expect("Could not encode");
+    let decoded: Script = Decoder::new()
+        .decode(&mut multipart(), Cursor::new(data))
+        .expect("Could not decode");
+
+    println!("* The prologue of the script is a directive");
+    assert_eq!(raw_values(&decoded.directives), vec!["use strict"]);
+    assert_eq!(decoded.statements.len(), 1);
+
+    println!("* The prologue of the function is made of directives, in order");
+    let (directives, body) = match decoded.statements[0] {
+        Statement::EagerFunctionDeclaration(ref declaration) => {
+            (&declaration.directives, &declaration.contents.body)
+        }
+        Statement::LazyFunctionDeclaration(ref declaration) => {
+            (&declaration.directives, &declaration.contents.body)
+        }
+        ref other => panic!("Expected a function declaration, got {:?}", other),
+    };
+    assert_eq!(raw_values(directives), vec!["use strict", "use asm"]);
+
+    println!("* A string after the prologue is an expression statement");
+    assert_eq!(body.len(), 3);
+    match body[1] {
+        Statement::ExpressionStatement(ref statement) => match statement.expression {
+            Expression::LiteralStringExpression(ref literal) => {
+                assert_eq!(literal.value.as_str(), "not a directive")
+            }
+            ref other => panic!("Expected a string literal, got {:?}", other),
+        },
+        ref other => panic!("Expected an expression statement, got {:?}", other),
+    }
+
+    println!("* The source prints the directives at the top of their bodies");
+    let source = parser.to_source(&decoded).expect("Could not print");
+    let strict = source.find("use strict").expect("Missing use strict");
+    let function = source.find("function asm").expect("Missing function");
+    let asm = source.find("use asm").expect("Missing use asm");
+    let not_directive = source.find("not a directive").expect("Missing string");
+    let var = source.find("var x").expect("Missing var");
+    assert!(strict < function);
+    assert!(function < asm);
+    assert!(asm < var);
+    assert!(var < not_directive);
+    assert_eq!(
+        parser.to_source(&ast).expect("Could not print"),
+        source,
+        "Decoded source should match the original"
+    );
+}
@@ -0,0 +1,96 @@
+//! Check that files with an embedded model may be decoded without the model.
+
+extern crate binjs;
+
+use binjs::generic::Offset;
+use binjs::io::bytes::compress::Compression;
+use binjs::io::entropy;
+use binjs::io::entropy::dictionary::{
+    DictionaryBuilder, DictionaryFamily, Options as DictionaryOptions,
+};
+use binjs::io::{Deserialization, Serialization, TokenSerializer};
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::{Script, Visitor, WalkPath, Walker};
+use binjs::specialized::es6::io::IOPath;
+
+#[macro_use]
+extern crate test_logger;
+
+/// A visitor designed to reset offsets to 0.
+struct OffsetCleanerVisitor;
+impl Visitor<()> for OffsetCleanerVisitor {
+    fn visit_offset(&mut self, _path: &WalkPath, node: &mut Offset) -> Result<(), ()> {
+        *node = binjs::generic::Offset(0);
+        Ok(())
+    }
+}
+
+fn encode(reference: &Script, options: &entropy::Options) -> Vec<u8> {
+    let encoder = entropy::write::Encoder::new(None, options.clone());
+    let mut serializer = binjs::specialized::es6::io::Serializer::new(encoder);
+    serializer
+        .serialize(reference, &mut IOPath::new())
+        .expect("Could not walk");
+    serializer.done().expect("Could not walk")
+}
+
+fn decode(data: Vec<u8>, options: &entropy::Options) -> Result<Script, ()> {
+    let decoder =
+        entropy::read::Decoder::new(options, std::io::Cursor::new(data)).map_err(|_| ())?;
+    let mut deserializer = binjs::specialized::es6::io::Deserializer::new(decoder);
+    let mut extracted: Script = deserializer
+        .deserialize(&mut IOPath::new())
+        .map_err(|_| ())?;
+    extracted
+        .walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+        .expect("Could not cleanup offsets");
+    Ok(extracted)
+}
+
+test!(test_embed_model, {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let source = "function foo(x, y) { console.log('Some text', x, y); }";
+
+    let mut reference = parser.parse_str(source).expect("Could not parse source");
+    let enricher = binjs::specialized::es6::Enrich::default();
+    enricher
+        .enrich(&mut reference)
+        .expect("Could not enrich AST");
+
+    println!("* Building a dictionary from the source");
+    let mut builder =
+        DictionaryBuilder::new(DictionaryOptions::default().with_depth(2).with_width(32));
+    {
+        let mut serializer = binjs::specialized::es6::io::Serializer::new(&mut builder);
+        serializer
+            .serialize(&reference, &mut IOPath::new())
+            .expect("Could not walk");
+        let _ = serializer.done().expect("Could not walk");
+    }
+    let dictionary = builder.done(0.into() /* Keep all user-extensible data */);
+
+    let spec = binjs::generic::es6::Library::spec();
+    let with_dictionary = entropy::Options::new(&spec, dictionary);
+    let without_dictionary = entropy::Options::new(&spec, DictionaryFamily::new());
+
+    println!("* Without an embedded model, decoding with the dictionary");
+    let data = encode(&reference, &with_dictionary);
+    assert_eq!(decode(data, &with_dictionary), Ok(reference.clone()));
+
+    println!("* With an embedded model, decoding does not require the dictionary");
+    let mut embedding = with_dictionary.clone();
+    embedding.with_embed_model(true);
+    let data = encode(&reference, &embedding);
+    assert_eq!(
+        decode(data.clone(), &without_dictionary),
+        Ok(reference.clone())
+    );
+    assert_eq!(decode(data, &with_dictionary), Ok(reference));
+
+    println!("* Embedded models claiming more data than the section holds are rejected");
+    let mut data = b"\x89BJS\r\n\0\n[[model]]".to_vec(); // Header, model section.
+    Compression::Identity
+        .compress(&[0xff; 8], &mut data) // A family of `u64::MAX` dictionaries.
+        .unwrap();
+    assert_eq!(decode(data, &without_dictionary), Err(()));
+});
@@ -0,0 +1,72 @@
+//! Check that a file encoded once per codec yields files that all decode
+//! to the same source.
+
+extern crate binjs;
+extern crate tempdir;
+
+mod common;
+
+use binjs::batch::{codec_extension, BatchEncoder};
+use binjs::io::bytes::compress::Compression;
+use binjs::io::multipart::TreeTokenReader;
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::Decoder;
+use binjs::specialized::es6::Enrich;
+
+use common::multipart;
+
+use std::fs;
+use std::io::Cursor;
+
+use tempdir::TempDir;
+
+const SOURCE: &str = "#!/usr/bin/env node
+function foo(x) { return x + 1.5; }
+console.log(foo('hello'), [1, 2, 3]);
+";
+
+#[test]
+fn test_encode_codecs() {
+    let dir = TempDir::new("test_encode_codecs").expect("Could not create directory");
+    let path = dir.path().join("source.js");
+    fs::write(&path, SOURCE).expect("Could not write source");
+    let parser = Shift::try_new().expect("Could not launch Shift");
+
+    let codecs = [
+        Compression::Brotli,
+        Compression::Gzip,
+        Compression::Identity,
+    ];
+    let extensions: Vec<_> = codecs.iter().map(codec_extension).collect();
+    assert_eq!(extensions, vec!["br.binjs", "gz.binjs", "binjs"]);
+
+    println!("* Encoding once per codec");
+    let mut encoder =
+        BatchEncoder::try_new(multipart(), Enrich::default()).expect("Could not launch encoder");
+    let results = encoder
+        .encode_file_with_codecs(&path, &codecs)
+        .expect("Could not encode");
+    assert_eq!(results.len(), codecs.len());
+
+    println!("* All files decode to the same source");
+    let mut sources = vec![];
+    for (codec, result) in codecs.iter().zip(&results) {
+        assert_eq!(result.source_len, SOURCE.len());
+        let reader = TreeTokenReader::new(Cursor::new(&result.data)).expect("Could not read");
+        assert_eq!(reader.section_codec("[TREE]"), Some(codec.clone()));
+
+        let (decoded, metadata): (Script, _) = Decoder::new()
+            .decode_with_metadata(&mut multipart(), Cursor::new(&result.data))
+            .expect("Could not decode");
+        sources.push(metadata.restore(&parser.to_source(&decoded).expect("Could not print")));
+    }
+    assert!(sources[0].starts_with("#!/usr/bin/env node\n"));
+    assert_eq!(sources[1], sources[0]);
+    assert_eq!(sources[2], sources[0]);
+
+    println!("* The format keeps its own targets");
+    let result = encoder.encode_file(&path).expect("Could not encode");
+    let reader = TreeTokenReader::new(Cursor::new(&result.data)).expect("Could not read");
+    assert_eq!(reader.section_codec("[TREE]"), Some(Compression::Identity));
+}
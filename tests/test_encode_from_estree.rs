@@ -0,0 +1,88 @@
+//! Check that the ESTree JSON produced by Acorn may be encoded directly, to
+//! the same source, and that unsupported node kinds are reported.
+
+extern crate binjs;
+#[macro_use]
+extern crate serde_json;
+
+mod common;
+
+use binjs::source::json::{ESTree, Error};
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::Decoder;
+
+use common::multipart;
+
+use std::fs;
+use std::io::Cursor;
+
+/// `script.acorn.json` is the output of Acorn 8 on `script.js`, with
+/// `ecmaVersion: 2020`, BigInt values replaced with `null`.
+const PATH: &str = "tests/data/estree/script";
+
+#[test]
+fn test_encode_from_estree() {
+    let estree = ESTree::try_new().expect("Could not launch ESTree");
+    let parser = Shift::try_new().expect("Could not launch Shift");
+
+    let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), PATH);
+    let source = fs::read_to_string(format!("{}.js", path)).expect("Could not read source");
+    let value: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(format!("{}.acorn.json", path)).expect("Could not read ESTree"),
+    )
+    .expect("Could not parse ESTree");
+
+    println!("* Acorn output is encoded to the same source");
+    let mut data = vec![];
+    estree
+        .encode_from_estree_json(&mut multipart(), &value, &mut data)
+        .expect("Could not encode");
+    let decoded: Script = Decoder::new()
+        .decode(&mut multipart(), Cursor::new(&data))
+        .expect("Could not decode");
+    let expected = parser.parse_str(&source).expect("Could not parse source");
+    assert_eq!(
+        parser.to_source(&decoded).expect("Could not print decoded"),
+        parser.to_source(&expected).expect("Could not print source")
+    );
+
+    println!("* Unsupported node kinds are reported");
+    let value = json!({
+        "type": "Program",
+        "sourceType": "script",
+        "body": [{
+            "type": "ExpressionStatement",
+            "expression": {
+                "type": "JSXElement",
+                "openingElement": {
+                    "type": "JSXOpeningElement",
+                    "name": { "type": "JSXIdentifier", "name": "div" },
+                    "attributes": [],
+                    "selfClosing": true
+                },
+                "closingElement": null,
+                "children": []
+            }
+        }]
+    });
+    match estree.encode_from_estree_json(&mut multipart(), &value, &mut vec![]) {
+        Err(Error::UnsupportedNodeKind(ref kind)) if kind == "JSXElement" => {}
+        other => panic!("Unexpected result {:?}", other),
+    }
+
+    println!("* So are modules");
+    let value = json!({
+        "type": "Program",
+        "sourceType": "module",
+        "body": [{
+            "type": "ImportDeclaration",
+            "specifiers": [],
+            "source": { "type": "Literal", "value": "foo", "raw": "\"foo\"" }
+        }]
+    });
+    match estree.encode_from_estree_json(&mut multipart(), &value, &mut vec![]) {
+        Err(Error::UnsupportedNodeKind(ref kind)) if kind == "ImportDeclaration" => {}
+        other => panic!("Unexpected result {:?}", other),
+    }
+}
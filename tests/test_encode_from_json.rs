@@ -0,0 +1,72 @@
+//! Check that a hand-written JSON AST may be encoded directly, and that
+//! unknown node kinds are reported.
+
+extern crate binjs;
+#[macro_use]
+extern crate serde_json;
+
+mod common;
+
+use binjs::source::json::{self, Error};
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::Decoder;
+
+use common::multipart;
+
+use std::io::Cursor;
+
+#[test]
+fn test_encode_from_json() {
+    // `foo("bar");`
+    let mut value = json!({
+        "type": "Script",
+        "scope": {
+            "type": "AssertedScriptGlobalScope",
+            "declaredNames": [],
+            "hasDirectEval": false
+        },
+        "directives": [],
+        "statements": [{
+            "type": "ExpressionStatement",
+            "expression": {
+                "type": "CallExpression",
+                "callee": { "type": "IdentifierExpression", "name": "foo" },
+                "arguments": [{ "type": "LiteralStringExpression", "value": "bar" }]
+            }
+        }]
+    });
+    let parser = Shift::try_new().expect("Could not launch Shift");
+
+    println!("* The JSON is encoded, then decoded to the same source");
+    let data = json::encode_from_json(&mut multipart(), &value).expect("Could not encode");
+    let decoded: Script = Decoder::new()
+        .decode(&mut multipart(), Cursor::new(&data))
+        .expect("Could not decode");
+    let expected = parser
+        .parse_str("foo(\"bar\");")
+        .expect("Could not parse source");
+    assert_eq!(
+        parser
+            .to_source(&decoded)
+            .expect("Could not generate source"),
+        parser
+            .to_source(&expected)
+            .expect("Could not generate source")
+    );
+
+    println!("* Unknown node kinds are reported");
+    value["statements"][0]["expression"]["arguments"][0]["type"] = json!("StringLiteral");
+    match json::encode_from_json(&mut multipart(), &value) {
+        Err(Error::SchemaError(errors)) => {
+            assert_eq!(errors.len(), 1);
+            assert_eq!(
+                errors[0].path,
+                "Script.statements[0].expression.arguments[0]"
+            );
+            assert_eq!(errors[0].message, "Unknown node kind StringLiteral");
+        }
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Unknown node kind was accepted"),
+    }
+}
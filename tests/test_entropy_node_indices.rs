@@ -0,0 +1,114 @@
+//! Encode a file with node indices as `varnum`s in the tree, then
+//! range-encoded in a section of their own, ensure that both decode to the
+//! same AST, and compare the sizes of the files.
+
+extern crate binjs;
+
+mod common;
+
+use common::{multipart_with_targets, OffsetCleanerVisitor};
+
+use binjs::io::bytes::compress::Compression;
+use binjs::io::multipart::{OnSection, Options, Targets, TreeOrder, TreeTokenReader};
+use binjs::io::Format;
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::{Script, WalkPath, Walker};
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+use std::thread;
+
+const PATH: &str = "tests/data/frameworks/jquery.3.3.1.min.js";
+
+#[test]
+fn test_entropy_node_indices() {
+    thread::Builder::new()
+        .name("test_entropy_node_indices large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), PATH);
+    let mut ast = parser.parse_file(&path).expect("Could not parse source");
+    let enricher = binjs::specialized::es6::Enrich::default();
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+    ast.walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+        .expect("Could not clean offsets");
+
+    for &tree_order in &[TreeOrder::PreOrder, TreeOrder::BreadthFirst] {
+        let mut sizes = vec![];
+        for &entropy_node_indices in &[false, true] {
+            println!(
+                "* Round-trip with {:?}, entropy_node_indices: {}",
+                tree_order, entropy_node_indices
+            );
+            let node_indices_bytes = Rc::new(RefCell::new(None));
+            let options = Options {
+                tree_order,
+                entropy_node_indices,
+                on_section: Some(OnSection({
+                    let node_indices_bytes = node_indices_bytes.clone();
+                    Rc::new(move |header: &str, bytes: &[u8]| {
+                        if header == "[NODE-INDICES]" {
+                            *node_indices_bytes.borrow_mut() = Some(bytes.len());
+                        }
+                    })
+                })),
+                ..Options::default()
+            };
+            let mut format = multipart_with_targets(Targets::uniform(Compression::Brotli), options);
+            let data = Encoder::new()
+                .encode(None, &mut format, &ast)
+                .expect("Could not encode");
+
+            let mut decoded: Script = Decoder::new()
+                .decode(
+                    &mut multipart_with_targets(
+                        Targets::uniform(Compression::Brotli),
+                        Options::default(),
+                    ),
+                    Cursor::new(&data),
+                )
+                .expect("Could not decode");
+            decoded
+                .walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+                .expect("Could not clean offsets");
+            assert_eq!(decoded, ast);
+
+            // The node indices have a section of their own only with the option.
+            let reader =
+                TreeTokenReader::new(Cursor::new(&data)).expect("Could not read container");
+            assert_eq!(
+                reader.section_codec("[NODE-INDICES]").is_some(),
+                entropy_node_indices
+            );
+
+            // The indices are counted in the statistics of the headers of
+            // tagged tuples, although they are not in the tree.
+            let stats = match format {
+                Format::Multipart { ref stats, .. } => stats.borrow(),
+                _ => unreachable!(),
+            };
+            assert!(stats.tagged_header.own_bytes > 0);
+            if let Some(section_bytes) = *node_indices_bytes.borrow() {
+                assert!(stats.tagged_header.own_bytes >= section_bytes);
+            }
+
+            println!("  {} bytes", data.len());
+            sizes.push(data.len());
+        }
+
+        // Range-encoded indices take less than a byte each. The tree is
+        // compressed, so compare entire files rather than sections.
+        assert!(sizes[1] < sizes[0], "Sizes: {:?}", sizes);
+    }
+}
@@ -184,3 +184,64 @@ fn test_external_roundtrip_dots_in_name() {
     assert!(path_out_encoded.join("test.a.b.c.d.grammar").exists());
     assert!(path_out_encoded.join("test.a.b.c.d.strings").exists());
 }
+
+/// Run a process with `input` as stdin, returning its stdout.
+fn run_piped(mut command: std::process::Command, input: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Could not launch process");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input)
+        .expect("Could not write to stdin");
+    let output = child
+        .wait_with_output()
+        .expect("Could not wait for process");
+    assert!(output.status.success());
+    output.stdout
+}
+
+#[test]
+fn test_external_stdin_stdout() {
+    let tmp_dir =
+        TempDir::new("test_external_stdin_stdout").expect("Could not create test directory");
+    let path_in_source = std::path::Path::new("tests/data/misc/test.a.b.c.d.js");
+    let path_tmp_source = tmp_dir.path().join("sample.js");
+    let path_out_encoded = tmp_dir.path().join("encoded");
+    let path_encoded = path_out_encoded.join("sample.binjs");
+    let path_decoded = tmp_dir.path().join("decoded.js");
+
+    fs::copy(&path_in_source, &path_tmp_source).unwrap();
+    let source = fs::read(&path_tmp_source).unwrap();
+
+    debug!(target: "test", "Encoding from file and from stdin");
+    let mut command = Command::new("target/debug/binjs_encode");
+    command
+        .arg("--in")
+        .arg(&path_tmp_source)
+        .arg("--out")
+        .arg(&path_out_encoded);
+    run(command).unwrap();
+    let encoded = fs::read(&path_encoded).unwrap();
+
+    let mut command = Command::new("target/debug/binjs_encode");
+    command.args(&["--in", "-", "--out", "-"]);
+    assert_eq!(run_piped(command, &source), encoded);
+
+    debug!(target: "test", "Decoding to file and to stdout");
+    let mut command = Command::new("target/debug/binjs_decode");
+    command.arg(&path_encoded).arg(&path_decoded);
+    run(command).unwrap();
+    let decoded = fs::read(&path_decoded).unwrap();
+
+    let mut command = Command::new("target/debug/binjs_decode");
+    command.args(&["-", "-"]);
+    assert_eq!(run_piped(command, &encoded), decoded);
+}
@@ -0,0 +1,115 @@
+//! Check that the source of a single function may be extracted without decoding
+//! the other functions.
+
+extern crate binjs;
+
+use binjs::extract::{Error, FunctionReader};
+use binjs::io::bytes::compress::Compression;
+use binjs::io::multipart::{Targets, TreeTokenReader, TreeTokenWriter};
+use binjs::io::{Serialization, TokenSerializer};
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::*;
+use binjs::specialized::es6::io::Serializer;
+
+use std::io::Cursor;
+
+const SOURCE: &str = "
+function first(x) { var y = x * 2; return function () { return y; }; }
+function second(a, b) { if (a) { return b; } return 'second'; }
+function third() { return [1, 2, 3].map((x) => x + 1); }
+var after = first(1);
+";
+
+/// Parse `SOURCE`, introducing laziness below `lazy_threshold`, then open
+/// the encoded file with a `FunctionReader`.
+fn read_functions(
+    parser: &Shift,
+    lazy_threshold: u32,
+) -> (Script, FunctionReader<TreeTokenReader>) {
+    let mut ast = parser.parse_str(SOURCE).expect("Could not parse source");
+    let mut enricher = binjs::specialized::es6::Enrich::default();
+    enricher.lazy_threshold = lazy_threshold;
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+
+    let writer = binjs::io::TokenWriterTreeAdapter::new(TreeTokenWriter::new(Targets::uniform(
+        Compression::Identity,
+    )));
+    let mut serializer = Serializer::new(writer);
+    serializer
+        .serialize(&ast, &mut IOPath::new())
+        .expect("Could not encode AST");
+    let data = serializer.done().expect("Could not finalize AST encoding");
+
+    let reader = TreeTokenReader::new(Cursor::new(data)).expect("Could not decode AST container");
+    let functions = FunctionReader::new(reader).expect("Could not decode top level");
+    (ast, functions)
+}
+
+/// The source of the top-level function `name` of `ast`, in which nothing
+/// was left undecoded.
+fn full_source(parser: &Shift, ast: &Script, name: &str) -> String {
+    let full = ast
+        .statements
+        .iter()
+        .find(|statement| match **statement {
+            Statement::LazyFunctionDeclaration(ref declaration) => {
+                declaration.name.name.as_str() == name
+            }
+            _ => false,
+        })
+        .expect("Missing function")
+        .clone();
+    parser
+        .to_source(&Script {
+            scope: Default::default(),
+            directives: vec![],
+            statements: vec![full],
+        })
+        .expect("Could not print")
+}
+
+#[test]
+fn test_function_source() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let (ast, mut functions) = read_functions(&parser, 1);
+    assert_eq!(functions.function_names(), vec!["first", "second", "third"]);
+    assert_eq!(functions.bodies_decoded(), 0);
+
+    println!("* The source of a function matches its source in a full decode");
+    let source = functions
+        .function_source(&parser, "second")
+        .expect("Could not extract function");
+    assert_eq!(source, full_source(&parser, &ast, "second"));
+    assert!(source.contains("'second'") || source.contains("\"second\""));
+
+    println!("* Sibling functions are not decoded");
+    assert_eq!(functions.bodies_decoded(), 1);
+    let mut lazy = 0;
+    for statement in &functions.script().statements {
+        if let Statement::LazyFunctionDeclaration(ref declaration) = *statement {
+            // Skipped by the reader, hence still to be decoded.
+            assert!(declaration.contents_position.is_some());
+            lazy += 1;
+        }
+    }
+    assert_eq!(lazy, 3);
+    functions
+        .function_source(&parser, "third")
+        .expect("Could not extract function");
+    assert_eq!(functions.bodies_decoded(), 2);
+
+    println!("* Unknown functions are reported");
+    match functions.function_source(&parser, "fourth") {
+        Err(Error::NoSuchFunction(ref name)) if name == "fourth" => {}
+        other => panic!("Unexpected result {:?}", other),
+    }
+
+    println!("* Lazy functions nested in the function are decoded, too");
+    let (ast, mut functions) = read_functions(&parser, 2);
+    let source = functions
+        .function_source(&parser, "first")
+        .expect("Could not extract function");
+    assert_eq!(source, full_source(&parser, &ast, "first"));
+    assert!(source.contains("return y"));
+    assert_eq!(functions.bodies_decoded(), 1);
+}
@@ -0,0 +1,97 @@
+//! Check that the grammar schema describes the grammar used by the encoder.
+
+extern crate binjs;
+extern crate serde_json;
+
+use binjs::generic::schema::Schema;
+use binjs::source::{Shift, SourceParser};
+
+use std::collections::HashSet;
+
+#[test]
+fn test_grammar_schema() {
+    let spec = binjs::generic::es6::Library::spec();
+    let schema = Schema::to_json(&spec);
+
+    println!("* The schema re-parses");
+    let text = serde_json::to_string_pretty(&schema).expect("Could not serialize schema");
+    let reparsed: serde_json::Value = serde_json::from_str(&text).expect("Could not parse schema");
+    assert_eq!(reparsed, schema);
+
+    println!("* The nodes produced by the parser and the encoder are those of the schema");
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let mut ast = parser
+        .parse_str(
+            "'use strict';
+            function f(a, ...b) { for (let i = 0; i < b.length; ++i) { a += b[i]; } return a; }
+            class C extends Object { get x() { return this._x; } set x(v) { this._x = v; } m() {} }
+            var o = { p: 1, [k]: () => 2, q() {} }, [x, y] = [1, 2];
+            try { throw new Error(`oops ${o.p}`); } catch (e) { o = null; } finally { f(1); }
+            label: while (true) { if (x) break label; else continue; }
+            switch (y) { case 1: y = -y; default: y = typeof y; }",
+        )
+        .expect("Could not parse source");
+    let mut enricher = binjs::specialized::es6::Enrich::default();
+    enricher.lazy_threshold = 10;
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+    let value = serde_json::to_value(&ast).expect("Could not serialize AST");
+    Schema::check(&reparsed, &value).expect("The schema should accept the AST");
+
+    // Each node has exactly the fields listed by the schema.
+    let interfaces = reparsed["interfaces"]
+        .as_object()
+        .expect("Expected interfaces");
+    let mut kinds = HashSet::new();
+    let mut stack = vec![&value];
+    while let Some(value) = stack.pop() {
+        match *value {
+            serde_json::Value::Array(ref items) => stack.extend(items),
+            serde_json::Value::Object(ref obj) => {
+                let kind = obj["type"].as_str().expect("Expected a node kind");
+                kinds.insert(kind.to_string());
+                let mut found: Vec<_> = obj.keys().filter(|key| *key != "type").collect();
+                found.sort();
+                let mut expected: Vec<_> = interfaces[kind]["fields"]
+                    .as_array()
+                    .expect("Expected fields")
+                    .iter()
+                    .map(|field| field["name"].as_str().unwrap())
+                    .collect();
+                expected.sort();
+                assert_eq!(found, expected, "Fields of {}", kind);
+                stack.extend(obj.values());
+            }
+            _ => {}
+        }
+    }
+    for kind in &[
+        "LazyFunctionDeclaration",
+        "LazyGetter",
+        "LazySetter",
+        "TemplateExpression",
+    ] {
+        assert!(kinds.contains(*kind), "{} is not exercised", kind);
+    }
+    assert_eq!(reparsed["root"], "Script");
+
+    println!("* Nodes unknown to the schema are rejected");
+    let mut value = value;
+    value["statements"][0]["type"] = serde_json::Value::from("FutureStatement");
+    let errors = Schema::check(&reparsed, &value).expect_err("The schema should reject the AST");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].path, "Script.statements[0]");
+
+    println!("* Fields and enums match the grammar");
+    let fields = interfaces["BinaryExpression"]["fields"]
+        .as_array()
+        .expect("Expected fields");
+    let names: Vec<_> = fields
+        .iter()
+        .map(|field| field["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["operator", "left", "right"]);
+    let kinds = reparsed["string_enums"]["VariableDeclarationKind"]
+        .as_array()
+        .expect("Expected enum");
+    assert_eq!(kinds.len(), 3);
+}
@@ -0,0 +1,99 @@
+//! Check that the grammar subset used by several files is the union of the
+//! parts of the grammar used by each file.
+
+extern crate binjs;
+#[macro_use]
+extern crate serde_json;
+
+mod common;
+
+use binjs::io::multipart::TreeTokenReader;
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::{IOPath, Script};
+use binjs::specialized::es6::io::{Deserializer, Encoder};
+use binjs::subset::GrammarUsage;
+
+use common::multipart;
+
+use std::collections::BTreeSet;
+use std::io::Cursor;
+
+/// The node kinds reported while decoding `data`.
+fn node_kinds(data: &[u8]) -> BTreeSet<String> {
+    let mut reader = TreeTokenReader::new(Cursor::new(data)).expect("Could not read file");
+    reader.count_node_kinds();
+    let mut deserializer = Deserializer::new(reader);
+    let _: Script = deserializer
+        .deserialize(&mut IOPath::new())
+        .expect("Could not decode");
+    deserializer
+        .reader
+        .node_kinds()
+        .keys()
+        .map(|kind| kind.as_str().to_string())
+        .collect()
+}
+
+#[test]
+fn test_grammar_subset() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let encode = |source: &str| {
+        let ast = parser.parse_str(source).expect("Could not parse source");
+        Encoder::new()
+            .encode(None, &mut multipart(), &ast)
+            .expect("Could not encode")
+    };
+    let first = encode("var x = 1; function foo(y) { return y + x; }");
+    let second = encode("let z = foo(2); if (z) {}");
+
+    let mut usage = GrammarUsage::default();
+    usage.add_file(&first).expect("Could not decode first file");
+    usage
+        .add_file(&second)
+        .expect("Could not decode second file");
+
+    println!("* The node kinds are the union of those of each file");
+    let mut expected = node_kinds(&first);
+    expected.extend(node_kinds(&second));
+    let kinds: BTreeSet<_> = usage.node_kinds.keys().cloned().collect();
+    assert_eq!(kinds, expected);
+    assert!(kinds.contains("EagerFunctionDeclaration"));
+    assert!(kinds.contains("IfStatement"));
+
+    println!("* Adding up the files separately yields the same usage");
+    let mut separately = GrammarUsage::default();
+    for data in &[&first, &second] {
+        let mut single = GrammarUsage::default();
+        single.add_file(data).expect("Could not decode file");
+        separately.add(&single);
+    }
+    assert_eq!(separately, usage);
+
+    println!("* The schema subset only lists the interfaces used");
+    let spec = binjs::generic::es6::Library::spec();
+    let schema = usage.to_schema(&spec);
+    let null_name = spec.get_null_name().to_str().to_string();
+    let interfaces: BTreeSet<_> = schema["interfaces"]
+        .as_object()
+        .expect("Expected interfaces")
+        .keys()
+        .cloned()
+        .collect();
+    let expected: BTreeSet<_> = expected
+        .into_iter()
+        .filter(|kind| *kind != null_name)
+        .collect();
+    assert_eq!(interfaces, expected);
+    assert!(!interfaces.contains("WhileStatement"));
+
+    println!("* The schema subset only lists the enum values used");
+    let kinds: BTreeSet<_> = schema["string_enums"]["VariableDeclarationKind"]
+        .as_array()
+        .expect("Expected enum")
+        .iter()
+        .map(|value| value.as_str().unwrap())
+        .collect();
+    assert_eq!(kinds, ["let", "var"].iter().cloned().collect());
+    assert_eq!(schema["string_enums"]["BinaryOperator"], json!(["+"]));
+    assert!(schema["string_enums"].get("UpdateOperator").is_none());
+}
@@ -0,0 +1,96 @@
+//! Check that tiny files are smaller with inline strings, and that readers
+//! tell both layouts apart.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::io::multipart::{write_footer, ContainerHash, Layout, Options};
+use binjs::io::Format;
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::multipart_with;
+
+use std::io::Cursor;
+
+fn format(inline_strings: bool) -> Format {
+    multipart_with(Options {
+        inline_strings,
+        ..Options::default()
+    })
+}
+
+#[test]
+fn test_inline_strings() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let source = "var x = 'hello';\nconsole.log(x);\n";
+    let mut ast = parser.parse_str(source).expect("Could not parse source");
+    let expected = parser.to_source(&ast).expect("Could not print");
+
+    let enricher = binjs::specialized::es6::Enrich::default();
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+
+    let mut encoded = vec![];
+    for inline_strings in &[false, true] {
+        let mut format = format(*inline_strings);
+        let data = Encoder::new()
+            .encode(None, &mut format, &ast)
+            .expect("Could not encode");
+        let data = data.to_vec();
+
+        println!("* Decoding with inline_strings = {}", inline_strings);
+        let decoded: Script = Decoder::new()
+            .decode(&mut format, Cursor::new(data.clone()))
+            .expect("Could not decode");
+        assert_eq!(
+            parser.to_source(&decoded).expect("Could not print"),
+            expected
+        );
+        // The layout is announced by a section, not guessed by readers.
+        let layout =
+            Layout::read(&mut Cursor::new(data.as_slice())).expect("Could not read layout");
+        let names = layout
+            .sections
+            .iter()
+            .map(|section| section.name.as_str())
+            .collect::<Vec<_>>();
+        let strings = if *inline_strings {
+            "[STRINGS-INLINE]"
+        } else {
+            "[STRINGS]"
+        };
+        assert_eq!(names, vec!["[GRAMMAR]", strings, "[TREE]"]);
+
+        encoded.push(data);
+    }
+
+    println!("* Containers with neither strings table nor marker are rejected");
+    let without_marker = {
+        let inline = &encoded[1];
+        let mut offset = [0; 8];
+        offset.copy_from_slice(&inline[inline.len() - 16..inline.len() - 8]);
+        let mut data = inline[..u64::from_le_bytes(offset) as usize].to_vec();
+        let marker = b"[STRINGS-INLINE]";
+        let position = data
+            .windows(marker.len())
+            .position(|window| window == marker)
+            .expect("Could not find marker");
+        // Turn the marker into an optional section, which readers skip.
+        data[position..position + 3].copy_from_slice(b"[?S");
+        write_footer(&ContainerHash::default(), &mut data).expect("Could not write footer");
+        data
+    };
+    assert!(Decoder::new()
+        .decode::<_, Script>(&mut format(true), Cursor::new(without_marker))
+        .is_err());
+
+    let (with_table, inline) = (&encoded[0], &encoded[1]);
+    assert!(
+        inline.len() < with_table.len(),
+        "Inline strings should shrink tiny files: {} vs. {}",
+        inline.len(),
+        with_table.len()
+    );
+}
@@ -0,0 +1,164 @@
+//! Decode a BinJS file without decoding the contents of lazy functions, then
+//! decode these contents on demand.
+
+extern crate binjs;
+
+use binjs::io::bytes::compress::Compression;
+use binjs::io::multipart::{Targets, TreeTokenReader, TreeTokenWriter};
+use binjs::io::{CompressionTarget, Deserialization, Serialization, TokenSerializer};
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::*;
+use binjs::specialized::es6::io::{Deserializer, Serializer};
+
+use std::io::Cursor;
+
+#[test]
+fn test_lazy_decode() {
+    let contents = FunctionOrMethodContents {
+        body: vec![ReturnStatement {
+            expression: Some(LiteralNumericExpression { value: 42. }.into()),
+        }
+        .into()],
+        ..Default::default()
+    };
+    let ast = Script {
+        scope: Default::default(),
+        directives: vec![],
+        statements: vec![
+            LazyFunctionDeclaration {
+                contents: contents.clone(),
+                ..Default::default()
+            }
+            .into(),
+            ExpressionStatement {
+                expression: LiteralNumericExpression { value: 1. }.into(),
+            }
+            .into(),
+        ],
+    };
+
+    let writer = binjs::io::TokenWriterTreeAdapter::new(TreeTokenWriter::new(Targets {
+        grammar_table: CompressionTarget::new(Compression::Identity),
+        strings_table: CompressionTarget::new(Compression::Identity),
+        numbers_table: CompressionTarget::new(Compression::Identity),
+        tree: CompressionTarget::new(Compression::Identity),
+    }));
+    let mut serializer = Serializer::new(writer);
+    serializer
+        .serialize(&ast, &mut IOPath::new())
+        .expect("Could not encode AST");
+    let data = serializer.done().expect("Could not finalize AST encoding");
+
+    let reader = TreeTokenReader::new(Cursor::new(data)).expect("Could not decode AST container");
+    let mut deserializer = Deserializer::new(reader).with_deferred_lazy_fields(true);
+    let decoded: Script = deserializer
+        .deserialize(&mut IOPath::new())
+        .expect("Could not decode");
+
+    // The function and the statements following it are decoded,
+    // but not the contents of the function.
+    assert_eq!(decoded.statements.len(), 2);
+    assert_eq!(decoded.statements[1], ast.statements[1]);
+    let position = match decoded.statements[0] {
+        Statement::LazyFunctionDeclaration(ref declaration) => {
+            assert_eq!(declaration.contents, FunctionOrMethodContents::default());
+            declaration
+                .contents_position
+                .expect("Contents should be left undecoded")
+        }
+        ref other => panic!("Expected a LazyFunctionDeclaration, got {:?}", other),
+    };
+
+    // Contents are decoded on demand.
+    let decoded_contents: FunctionOrMethodContents = deserializer
+        .deserialize_lazy(position, &mut IOPath::new())
+        .expect("Could not decode lazy contents");
+    assert_eq!(decoded_contents, contents);
+}
+
+/// A container with many lazy functions, nested in one another.
+fn many_functions() -> Vec<u8> {
+    let mut source = String::new();
+    for i in 0..50 {
+        source.push_str(&format!(
+            "function f{i}(x) {{ function g(y) {{ return () => x + y + {i}; }} return g; }}
+            var o{i} = {{ get p() {{ return {i}; }}, set p(v) {{}}, m() {{ return function () {{}}; }} }};
+            ",
+            i = i
+        ));
+    }
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let mut ast = parser.parse_str(&source).expect("Could not parse source");
+    let mut enricher = binjs::specialized::es6::Enrich::default();
+    enricher.lazy_threshold = 10;
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+
+    let writer = binjs::io::TokenWriterTreeAdapter::new(TreeTokenWriter::new(Targets {
+        grammar_table: CompressionTarget::new(Compression::Identity),
+        strings_table: CompressionTarget::new(Compression::Identity),
+        numbers_table: CompressionTarget::new(Compression::Identity),
+        tree: CompressionTarget::new(Compression::Identity),
+    }));
+    let mut serializer = Serializer::new(writer);
+    serializer
+        .serialize(&ast, &mut IOPath::new())
+        .expect("Could not encode AST");
+    serializer
+        .done()
+        .expect("Could not finalize AST encoding")
+        .to_vec()
+}
+
+#[test]
+fn test_decode_all_bodies() {
+    let data = many_functions();
+
+    // Decode everything at once.
+    let reader =
+        TreeTokenReader::new(Cursor::new(data.clone())).expect("Could not decode AST container");
+    let mut deserializer = Deserializer::new(reader);
+    let sequential: Script = deserializer
+        .deserialize(&mut IOPath::new())
+        .expect("Could not decode");
+
+    // Decode the top level, then all the bodies.
+    let reader = TreeTokenReader::new(Cursor::new(data)).expect("Could not decode AST container");
+    let mut deserializer = Deserializer::new(reader).with_deferred_lazy_fields(true);
+    let mut deferred: Script = deserializer
+        .deserialize(&mut IOPath::new())
+        .expect("Could not decode");
+    assert_ne!(deferred, sequential);
+    deserializer
+        .decode_all_bodies(&mut deferred)
+        .expect("Could not decode bodies");
+
+    // The `*_skip` fields keep the byte length of the contents.
+    assert_eq!(deferred, sequential);
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn test_decode_all_bodies_parallel() {
+    let data = many_functions();
+
+    let reader =
+        TreeTokenReader::new(Cursor::new(data.clone())).expect("Could not decode AST container");
+    let mut deserializer = Deserializer::new(reader);
+    let sequential: Script = deserializer
+        .deserialize(&mut IOPath::new())
+        .expect("Could not decode");
+
+    let reader =
+        TreeTokenReader::new(Cursor::new(data.clone())).expect("Could not decode AST container");
+    let mut deserializer = Deserializer::new(reader).with_deferred_lazy_fields(true);
+    let mut deferred: Script = deserializer
+        .deserialize(&mut IOPath::new())
+        .expect("Could not decode");
+    assert_ne!(deferred, sequential);
+    deserializer
+        .decode_all_bodies_parallel(&mut deferred, || {
+            TreeTokenReader::new(Cursor::new(data.clone()))
+        })
+        .expect("Could not decode bodies");
+    assert_eq!(deferred, sequential);
+}
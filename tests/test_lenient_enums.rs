@@ -0,0 +1,84 @@
+//! Check that values of string enums unknown to the grammar may be decoded,
+//! then encoded back, with `Decoder::with_lenient_enums`.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::generic::SharedString;
+use binjs::io::DecodeWarning;
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::*;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::multipart;
+
+use std::io::Cursor;
+
+fn operator(script: &mut Script) -> &mut BinaryOperator {
+    match script.statements[0] {
+        Statement::ExpressionStatement(ref mut statement) => match statement.expression {
+            Expression::BinaryExpression(ref mut expression) => &mut expression.operator,
+            ref other => panic!("Expected a binary expression, got {:?}", other),
+        },
+        ref other => panic!("Expected an expression statement, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_lenient_enums() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let mut ast = parser.parse_str("a + b;").expect("Could not parse source");
+    let enricher = binjs::specialized::es6::Enrich::default();
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+
+    // Craft a file using an operator that the grammar does not define,
+    // as a more recent encoder could.
+    let unknown = BinaryOperator::Unknown(SharedString::from_str("|>"));
+    *operator(&mut ast) = unknown.clone();
+    let data = Encoder::new()
+        .encode(None, &mut multipart(), &ast)
+        .expect("Could not encode");
+
+    println!("* By default, unknown values are rejected");
+    let strict: Result<Script, _> = Decoder::new().decode(&mut multipart(), Cursor::new(&data));
+    assert!(strict.is_err());
+
+    println!("* Lenient decoding preserves unknown values");
+    let mut decoded: Script = Decoder::new()
+        .with_lenient_enums(true)
+        .decode(&mut multipart(), Cursor::new(&data))
+        .expect("Could not decode leniently");
+    assert_eq!(*operator(&mut decoded), unknown);
+
+    println!("* Unknown values are reported as warnings");
+    let (_, warnings): (Script, _) = Decoder::new()
+        .with_lenient_enums(true)
+        .decode_with_warnings(&mut multipart(), Cursor::new(&data))
+        .expect("Could not decode leniently");
+    assert_eq!(
+        warnings,
+        vec![DecodeWarning::UnknownEnumValue {
+            name: "BinaryOperator".to_string(),
+            value: "|>".to_string(),
+        }]
+    );
+
+    println!("* Unknown values are encoded back as is");
+    let reencoded = Encoder::new()
+        .encode(None, &mut multipart(), &decoded)
+        .expect("Could not encode decoded script");
+    assert_eq!(reencoded, data);
+
+    println!("* Lenient decoding does not change known values");
+    let mut ast = parser.parse_str("a + b;").expect("Could not parse source");
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+    let data = Encoder::new()
+        .encode(None, &mut multipart(), &ast)
+        .expect("Could not encode");
+    let mut decoded: Script = Decoder::new()
+        .with_lenient_enums(true)
+        .decode(&mut multipart(), Cursor::new(&data))
+        .expect("Could not decode leniently");
+    assert_eq!(*operator(&mut decoded), BinaryOperator::Plus);
+}
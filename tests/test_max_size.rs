@@ -0,0 +1,111 @@
+//! Check that encoded files larger than the budget of `--max-output-bytes`
+//! are reported with their actual size, by the library and by the CLI.
+
+extern crate binjs;
+extern crate tempdir;
+
+mod common;
+
+use binjs::batch::{BatchEncoder, Error};
+use binjs::io::multipart::Options;
+use binjs::io::Format;
+use binjs::specialized::es6::Enrich;
+
+use common::multipart_with;
+
+use std::fs;
+use std::process::Command;
+
+use tempdir::TempDir;
+
+fn multipart(max_output_bytes: Option<usize>) -> Format {
+    multipart_with(Options {
+        max_output_bytes,
+        ..Options::default()
+    })
+}
+
+#[test]
+fn test_max_size() {
+    let dir = TempDir::new("test_max_size").expect("Could not create directory");
+    let path = dir.path().join("foo.js");
+    fs::write(&path, "function foo(x) { return x + 1; }").expect("Could not write source");
+    let size = BatchEncoder::try_new(multipart(None), Enrich::default())
+        .expect("Could not launch encoder")
+        .encode_file(&path)
+        .expect("Could not encode")
+        .data
+        .len();
+
+    println!("* A file within its budget is accepted");
+    for max_size in &[size, size + 1000] {
+        let result = BatchEncoder::try_new(multipart(Some(*max_size)), Enrich::default())
+            .expect("Could not launch encoder")
+            .encode_file(&path)
+            .expect("Could not encode within budget");
+        assert_eq!(result.data.len(), size);
+    }
+
+    println!("* A file over its budget is reported, with its actual size");
+    let mut encoder = BatchEncoder::try_new(multipart(Some(size - 1)), Enrich::default())
+        .expect("Could not launch encoder");
+    match encoder.encode_file(&path) {
+        Err(err @ Error::SizeExceeded { .. }) => {
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "Encoded size is {} bytes, exceeding the budget of {} bytes",
+                    size,
+                    size - 1
+                )
+            );
+        }
+        Err(err) => panic!("Unexpected error {}", err),
+        Ok(_) => panic!("File over budget was accepted"),
+    }
+
+    println!("* The budget is kept for the next file");
+    match encoder.encode_file(&path) {
+        Err(Error::SizeExceeded { max_size, .. }) => assert_eq!(max_size, size - 1),
+        Err(err) => panic!("Unexpected error {}", err),
+        Ok(_) => panic!("File over budget was accepted"),
+    }
+
+    let encode = |out: &str, max_size: Option<usize>| {
+        let mut command = Command::new("target/debug/binjs_encode");
+        command
+            .arg("--in")
+            .arg(&path)
+            .arg("--out")
+            .arg(dir.path().join(out))
+            .args(&["--quiet", "advanced", "multipart"]);
+        if let Some(max_size) = max_size {
+            command.arg("--max-output-bytes").arg(max_size.to_string());
+        }
+        command.output().expect("Could not launch binjs_encode")
+    };
+
+    println!("* binjs_encode succeeds within the budget");
+    assert!(encode("unbounded", None).status.success());
+    let size = fs::metadata(dir.path().join("unbounded").join("foo.binjs"))
+        .expect("Could not read output")
+        .len() as usize;
+    let output = encode("within", Some(size));
+    assert!(output.status.success());
+    assert!(dir.path().join("within").join("foo.binjs").exists());
+
+    println!("* binjs_encode fails over the budget, printing the actual size");
+    let output = encode("over", Some(size - 1));
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "Encoded size is {} bytes, exceeding the budget of {} bytes",
+            size,
+            size - 1
+        )),
+        "Unexpected stderr {}",
+        stderr
+    );
+    assert!(!dir.path().join("over").join("foo.binjs").exists());
+}
@@ -0,0 +1,74 @@
+//! Check that encoding reports timing metrics for each phase.
+
+extern crate binjs;
+extern crate tempdir;
+
+use binjs::batch::BatchEncoder;
+use binjs::io::bytes::compress::Compression;
+use binjs::io::metrics::{MetricsSink, Phase, PhaseMetrics};
+use binjs::io::multipart::{Options, Statistics, Targets, INLINE_STRINGS_MAX_SOURCE_BYTES};
+use binjs::io::{CompressionTarget, Format};
+use binjs::specialized::es6::Enrich;
+
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+
+use tempdir::TempDir;
+
+/// A sink that simply records all the metrics it receives.
+#[derive(Default)]
+struct RecordingSink {
+    events: RefCell<Vec<PhaseMetrics>>,
+}
+impl MetricsSink for RecordingSink {
+    fn record(&self, metrics: PhaseMetrics) {
+        self.events.borrow_mut().push(metrics);
+    }
+}
+
+#[test]
+fn test_metrics() {
+    let dir = TempDir::new("test_metrics").expect("Could not create directory");
+    let path = dir.path().join("source.js");
+    // Large enough for strings to be stored in a table, rather than inline.
+    let source = "function foo(x) { return x + 1; }
+        function bar(y) { return foo(y) * 2; }
+        function baz(z) { return bar(z) - foo(z); }
+        console.log(baz(42));
+        ";
+    assert!(source.len() >= INLINE_STRINGS_MAX_SOURCE_BYTES);
+    fs::write(&path, source).expect("Could not write source");
+
+    let sink = Rc::new(RecordingSink::default());
+    let format = Format::Multipart {
+        targets: Targets {
+            grammar_table: CompressionTarget::new(Compression::Identity),
+            strings_table: CompressionTarget::new(Compression::Identity),
+            numbers_table: CompressionTarget::new(Compression::Identity),
+            tree: CompressionTarget::new(Compression::Identity),
+        },
+        options: Options::default(),
+        stats: Rc::new(RefCell::new(Statistics::default())),
+    };
+    let mut encoder = BatchEncoder::try_new(format, Enrich::default())
+        .expect("Could not launch encoder")
+        .with_metrics(sink.clone());
+    let data = encoder.encode_file(&path).expect("Could not encode").data;
+
+    let events = sink.events.borrow();
+    let phases: Vec<_> = events.iter().map(|event| event.phase).collect();
+    assert_eq!(
+        phases,
+        vec![
+            Phase::Parse,
+            Phase::Tokenize,
+            Phase::Compress, // Grammar table.
+            Phase::Compress, // Strings table.
+            Phase::Compress, // Tree.
+            Phase::Write,
+        ]
+    );
+    assert_eq!(events[0].bytes, Some(source.len()));
+    assert_eq!(events.last().unwrap().bytes, Some(data.len()));
+}
@@ -0,0 +1,33 @@
+//! Check that malformed command-line arguments of the multipart format are
+//! reported as errors rather than panics.
+
+extern crate binjs;
+
+use binjs::io::Format;
+
+use std::io::ErrorKind;
+
+#[test]
+fn test_multipart_arguments() {
+    let spec = binjs::generic::es6::Library::spec();
+    let parse = |args: &[&str]| Format::from_name(&spec, "multipart", args);
+
+    println!("* Well-formed arguments are accepted");
+    parse(&["--intern-min-count", "2"]).expect("Could not parse arguments");
+    parse(&["--align", "64"]).expect("Could not parse arguments");
+    parse(&["--user-metadata", "commit=0123"]).expect("Could not parse arguments");
+    parse(&["--max-output-bytes", "1024"]).expect("Could not parse arguments");
+
+    println!("* Malformed arguments are rejected");
+    for args in &[
+        &["--intern-min-count", "two"][..],
+        &["--align", "eight"][..],
+        &["--user-metadata", "commit"][..],
+        &["--max-output-bytes", "1k"][..],
+    ] {
+        match parse(args) {
+            Err(err) => assert_eq!(err.kind(), ErrorKind::InvalidInput, "{:?}", args),
+            Ok(_) => panic!("Malformed arguments {:?} were accepted", args),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! Check that the node kinds counted while decoding match the nodes of the
+//! decoded AST.
+
+extern crate binjs;
+extern crate serde_json;
+
+mod common;
+
+use binjs::io::multipart::TreeTokenReader;
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::{IOPath, Script};
+use binjs::specialized::es6::io::{Deserializer, Encoder};
+
+use common::multipart;
+
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+/// Count the nodes of the JSON of an AST, by kind.
+fn count_kinds(value: &serde_json::Value, counts: &mut BTreeMap<String, usize>) {
+    match *value {
+        serde_json::Value::Object(ref fields) => {
+            if let Some(kind) = fields.get("type").and_then(|kind| kind.as_str()) {
+                *counts.entry(kind.to_string()).or_insert(0) += 1;
+            }
+            for field in fields.values() {
+                count_kinds(field, counts);
+            }
+        }
+        serde_json::Value::Array(ref items) => {
+            for item in items {
+                count_kinds(item, counts);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[test]
+fn test_node_kinds() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let ast = parser
+        .parse_str("function foo(x) { return x + 1; } foo(foo(2));")
+        .expect("Could not parse source");
+    let data = Encoder::new()
+        .encode(None, &mut multipart(), &ast)
+        .expect("Could not encode");
+
+    let mut reader = TreeTokenReader::new(Cursor::new(&data)).expect("Could not read file");
+    reader.count_node_kinds();
+    let mut deserializer = Deserializer::new(reader);
+    let decoded: Script = deserializer
+        .deserialize(&mut IOPath::new())
+        .expect("Could not decode");
+    assert_eq!(decoded, ast);
+    let null_name = binjs::generic::es6::Library::spec()
+        .get_null_name()
+        .to_str()
+        .to_string();
+    let node_kinds: BTreeMap<String, usize> = deserializer
+        .reader
+        .node_kinds()
+        .into_iter()
+        .map(|(kind, count)| (kind.as_str().to_string(), count))
+        // Null interfaces have no node in the JSON.
+        .filter(|&(ref kind, _)| *kind != null_name)
+        .collect();
+
+    println!("* The counts match the nodes of the AST");
+    let mut expected = BTreeMap::new();
+    count_kinds(
+        &serde_json::to_value(&decoded).expect("Could not convert AST to JSON"),
+        &mut expected,
+    );
+    assert_eq!(node_kinds, expected);
+    assert_eq!(node_kinds["CallExpression"], 2);
+    assert_eq!(node_kinds["EagerFunctionDeclaration"], 1);
+
+    println!("* Kinds absent from the file are not listed");
+    assert!(!node_kinds.contains_key("ClassDeclaration"));
+    assert!(node_kinds.values().all(|&count| count > 0));
+}
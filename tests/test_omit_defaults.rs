@@ -0,0 +1,85 @@
+//! Encode a file omitting the fields that hold their default value, ensure
+//! that it decodes to the same AST, including lazy functions.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::io::multipart::{Options, TreeTokenReader};
+use binjs::io::Deserialization;
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::*;
+use binjs::specialized::es6::io::{Decoder, Deserializer, Encoder};
+
+use common::{multipart, multipart_with, OffsetCleanerVisitor};
+
+use std::io::Cursor;
+
+#[test]
+fn test_omit_defaults() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let mut ast = parser
+        .parse_str(
+            "function foo(x, y) { if (x) { return [] } return function() { return y; }; }
+             var z = foo(1, null);
+             for (let i of []) {}",
+        )
+        .expect("Could not parse source");
+    let mut enricher = binjs::specialized::es6::Enrich::default();
+    enricher.lazy_threshold = 10;
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+
+    let encode = |omit_defaults: bool| {
+        Encoder::new()
+            .encode(
+                None,
+                &mut multipart_with(Options {
+                    omit_defaults,
+                    ..Options::default()
+                }),
+                &ast,
+            )
+            .expect("Could not encode")
+    };
+    let without = encode(false);
+    let with = encode(true);
+    assert_ne!(with, without);
+
+    println!("* The file decodes to the same AST");
+    let mut decoded: Script = Decoder::new()
+        .decode(&mut multipart(), Cursor::new(&with))
+        .expect("Could not decode");
+    decoded
+        .walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+        .expect("Could not clean offsets");
+    let mut expected = ast.clone();
+    expected
+        .walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+        .expect("Could not clean offsets");
+    assert_eq!(decoded, expected);
+
+    println!("* Lazy functions may still be skipped");
+    let reader = TreeTokenReader::new(Cursor::new(&with)).expect("Could not read container");
+    let mut deserializer = Deserializer::new(reader).with_deferred_lazy_fields(true);
+    let decoded: Script = deserializer
+        .deserialize(&mut IOPath::new())
+        .expect("Could not decode");
+    let position = match decoded.statements[0] {
+        Statement::LazyFunctionDeclaration(ref declaration) => declaration
+            .contents_position
+            .expect("Contents should be left undecoded"),
+        ref other => panic!("Expected a LazyFunctionDeclaration, got {:?}", other),
+    };
+    let mut contents: FunctionOrMethodContents = deserializer
+        .deserialize_lazy(position, &mut IOPath::new())
+        .expect("Could not decode lazy contents");
+    contents
+        .walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+        .expect("Could not clean offsets");
+    match expected.statements[0] {
+        Statement::LazyFunctionDeclaration(ref declaration) => {
+            assert_eq!(contents, declaration.contents)
+        }
+        ref other => panic!("Expected a LazyFunctionDeclaration, got {:?}", other),
+    }
+}
@@ -0,0 +1,91 @@
+//! Encode sources along with their parenthesization hints, ensure that
+//! decoding reproduces the parentheses as written.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::io::multipart::{Metadata, Options};
+use binjs::source::Shift;
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::{multipart, multipart_with};
+
+use std::io::Cursor;
+
+#[test]
+fn test_preserve_parens() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let parse = |source: &str| {
+        let (ast, errors, parens) = parser
+            .parse_str_tolerant_with_parens(source)
+            .expect("Could not parse source");
+        assert_eq!(errors, vec![]);
+        (ast, parens)
+    };
+
+    for source in &[
+        "(a + b) * c;",
+        "a + b * c;",
+        "a + (b * c);",
+        "f(((x)), (y));",
+        "f(1n, (a + b) * c);",
+        "(10n) + (a * b);",
+    ] {
+        println!("* Round-tripping {}", source);
+        let (ast, parens) = parse(source);
+        let data = Encoder::new()
+            .encode(
+                None,
+                &mut multipart_with(Options {
+                    metadata: Metadata {
+                        parens: parens.clone(),
+                        ..Metadata::default()
+                    },
+                    ..Options::default()
+                }),
+                &ast,
+            )
+            .expect("Could not encode");
+
+        let (decoded, metadata): (Script, _) = Decoder::new()
+            .decode_with_metadata(&mut multipart(), Cursor::new(&data))
+            .expect("Could not decode");
+        assert_eq!(metadata.parens, parens);
+
+        // Compare the hints of the output rather than its text, which
+        // may differ from the source in whitespace.
+        let printed = parser
+            .to_source_with_parens(&decoded, &metadata.parens)
+            .expect("Could not pretty-print");
+        let (reparsed, parens_after) = parse(&printed);
+        assert_eq!(reparsed, ast, "{} != {}", printed, source);
+        assert_eq!(parens_after, parens, "{} != {}", printed, source);
+    }
+
+    println!("* The parentheses of conditions and arguments are not hints");
+    assert_eq!(parse("(a + b) * c;").1.hints.len(), 1);
+    assert_eq!(parse("a + b * c;").1.hints.len(), 0);
+    assert_eq!(parse("if (a) f(b);").1.hints.len(), 0);
+    assert_eq!(parse("a + (b * c);").1.hints.len(), 1);
+    assert_eq!(
+        parse("f(((x)), (y));").1.hints.values().collect::<Vec<_>>(),
+        vec![&2, &1]
+    );
+
+    println!("* BigInt literals count as one expression");
+    let (_, parens) = parse("f(1n, (a + b) * c);");
+    let (_, without_bigint) = parse("f(x, (a + b) * c);");
+    assert_eq!(parens, without_bigint);
+
+    println!("* Hints recorded on another AST are rejected");
+    let (_, parens) = parse("(a + b) * c;");
+    let (ast, _) = parse("a;");
+    assert!(parser.to_source_with_parens(&ast, &parens).is_err());
+
+    println!("* Without hints, redundant parentheses are lost");
+    let (ast, _) = parse("a + (b * c);");
+    let printed = parser.to_source(&ast).expect("Could not pretty-print");
+    assert_eq!(parse(&printed).1.hints.len(), 0);
+}
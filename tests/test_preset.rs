@@ -0,0 +1,67 @@
+//! Check that presets trade encoding speed for size, from `fastest` to
+//! `smallest`, without changing the decoded AST.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::io::multipart::{Options, Preset, Targets};
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::*;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::{multipart_with_targets, OffsetCleanerVisitor};
+
+use std::io::Cursor;
+
+#[test]
+fn test_preset() {
+    let mut source = String::new();
+    for i in 0..200 {
+        source.push_str(&format!(
+            "function f{i}(a, b) {{ if (a > {i}) {{ return [a, b, a * b]; }} else {{ return {{ x: a, y: b - {i} }}; }} }}\n",
+            i = i
+        ));
+    }
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let mut ast = parser.parse_str(&source).expect("Could not parse source");
+    let mut enricher = binjs::specialized::es6::Enrich::default();
+    enricher.lazy_threshold = 10;
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+
+    let mut expected = ast.clone();
+    expected
+        .walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+        .expect("Could not clean offsets");
+
+    let mut sizes = vec![];
+    for name in &["fastest", "balanced", "smallest"] {
+        println!("* Encoding with preset {}", name);
+        let preset = Preset::parse(name).expect("Could not parse preset");
+        let data = Encoder::new()
+            .encode(
+                None,
+                &mut multipart_with_targets(preset.targets(), preset.options()),
+                &ast,
+            )
+            .expect("Could not encode");
+        sizes.push(data.len());
+
+        let mut decoded: Script = Decoder::new()
+            .decode(
+                &mut multipart_with_targets(Targets::fast_tree(), Options::default()),
+                Cursor::new(&data),
+            )
+            .expect("Could not decode");
+        decoded
+            .walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+            .expect("Could not clean offsets");
+        assert_eq!(decoded, expected);
+    }
+
+    println!("* Sizes decrease from fastest to smallest: {:?}", sizes);
+    assert!(sizes[2] < sizes[1]);
+    assert!(sizes[1] < sizes[0]);
+
+    assert_eq!(Preset::parse("tiny"), None);
+}
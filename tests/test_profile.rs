@@ -0,0 +1,108 @@
+//! Check that the `fast-tree` compression profile leaves the tree
+//! uncompressed, sparing brotli most of its input compared to compressing
+//! everything with brotli, without changing the decoded AST.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::io::bytes::compress::Compression;
+use binjs::io::multipart::{Layout, Options, SectionLayout, Statistics, Targets};
+use binjs::io::Format;
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::multipart;
+
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+
+#[test]
+fn test_fast_tree_profile() {
+    let mut source = String::new();
+    for i in 0..500 {
+        source.push_str(&format!(
+            "function f{i}(a, b) {{ if (a > {i}) {{ return [a, b, a * b]; }} else {{ return {{ x: a, y: b - {i} }}; }} }}\n",
+            i = i
+        ));
+    }
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let mut ast = parser.parse_str(&source).expect("Could not parse source");
+    let enricher = binjs::specialized::es6::Enrich::default();
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+
+    // The profile is available from the command line.
+    let spec = binjs::generic::es6::Library::spec();
+    let fast_tree = Format::from_name(&spec, "multipart", &["--profile", "fast-tree"])
+        .expect("Could not parse profile");
+    match fast_tree {
+        Format::Multipart { ref targets, .. } => {
+            assert_eq!(*targets.tree.compression(), Compression::Identity);
+            assert_eq!(*targets.strings_table.compression(), Compression::Brotli);
+        }
+        _ => panic!("Expected a multipart format"),
+    }
+
+    // Timing encodings is too noisy for a test, so check instead that the
+    // profile spares brotli the tree, which is where the time goes.
+    let encode = |targets: Targets| -> Box<[u8]> {
+        let mut format = Format::Multipart {
+            targets,
+            options: Options::default(),
+            stats: Rc::new(RefCell::new(Statistics::default())),
+        };
+        Encoder::new()
+            .encode(None, &mut format, &ast)
+            .expect("Could not encode")
+    };
+    let fast_tree_data = encode(Targets::fast_tree());
+    let brotli_data = encode(Targets::uniform(Compression::Brotli));
+
+    let sections = |data: &[u8]| -> Vec<SectionLayout> {
+        Layout::read(&mut Cursor::new(data))
+            .expect("Could not read layout")
+            .sections
+    };
+    let section = |sections: &[SectionLayout], name: &str| -> SectionLayout {
+        sections
+            .iter()
+            .find(|section| section.name == name)
+            .unwrap_or_else(|| panic!("Missing section {}", name))
+            .clone()
+    };
+    let brotli_input = |sections: &[SectionLayout]| -> usize {
+        sections
+            .iter()
+            .filter(|section| section.compression == Compression::Brotli)
+            .map(|section| section.decompressed_bytes)
+            .sum()
+    };
+
+    let fast_tree_sections = sections(&fast_tree_data);
+    let tree = section(&fast_tree_sections, "[TREE]");
+    assert_eq!(tree.compression, Compression::Identity);
+    assert_eq!(tree.compressed_bytes, tree.decompressed_bytes);
+    let strings = section(&fast_tree_sections, "[STRINGS]");
+    assert_eq!(strings.compression, Compression::Brotli);
+    assert!(strings.compressed_bytes < strings.decompressed_bytes);
+
+    let brotli_sections = sections(&brotli_data);
+    assert_eq!(
+        section(&brotli_sections, "[TREE]").compression,
+        Compression::Brotli
+    );
+    assert_eq!(
+        brotli_input(&fast_tree_sections) + tree.decompressed_bytes,
+        brotli_input(&brotli_sections)
+    );
+
+    let decode = |data: Box<[u8]>| -> Script {
+        let mut format = multipart();
+        Decoder::new()
+            .decode(&mut format, Cursor::new(data))
+            .expect("Could not decode")
+    };
+    assert_eq!(decode(fast_tree_data), decode(brotli_data));
+}
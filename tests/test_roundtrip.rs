@@ -76,6 +76,7 @@ fn main() {
                     vec.push(Targets {
                         grammar_table: CompressionTarget::new(grammar_table.clone()),
                         strings_table: CompressionTarget::new(strings_table.clone()),
+                        numbers_table: CompressionTarget::new(strings_table.clone()),
                         tree: CompressionTarget::new(tree.clone()),
                     });
                 }
@@ -112,6 +113,7 @@ fn main() {
                 let options = Targets {
                     grammar_table: CompressionTarget::new(Compression::Identity),
                     strings_table: CompressionTarget::new(Compression::Identity),
+                    numbers_table: CompressionTarget::new(Compression::Identity),
                     tree: CompressionTarget::new(Compression::Identity),
                 };
                 debug!(target: "test_roundtrip", "Encoding.");
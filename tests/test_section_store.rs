@@ -0,0 +1,66 @@
+//! Check that sections shared between files are stored once in a section store,
+//! and that files can be decoded from their manifest.
+
+extern crate binjs;
+extern crate tempdir;
+
+mod common;
+
+use binjs::io::multipart::{resolve_sections, split_sections, store_sections};
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::multipart;
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::Cursor;
+
+#[test]
+fn test_section_store() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let encode = |source: &str| {
+        let ast = parser.parse_str(source).expect("Could not parse source");
+        let data = Encoder::new()
+            .encode(None, &mut multipart(), &ast)
+            .expect("Could not encode");
+        (ast, data)
+    };
+
+    // Same grammar table, different strings tables.
+    let (foo, foo_data) = encode("function foo(x) { return x; }");
+    let (bar, bar_data) = encode("function bar(y) { return y; }");
+
+    let dir = tempdir::TempDir::new("test_section_store").expect("Could not create directory");
+    let foo_manifest = store_sections(&foo_data, dir.path()).expect("Could not store foo");
+    let bar_manifest = store_sections(&bar_data, dir.path()).expect("Could not store bar");
+
+    println!("* Shared sections are stored once");
+    let (_, foo_sections) = split_sections(&foo_data).expect("Could not split foo");
+    let (_, bar_sections) = split_sections(&bar_data).expect("Could not split bar");
+    let names: HashSet<_> = foo_sections
+        .iter()
+        .chain(bar_sections.iter())
+        .map(|&(ref name, _)| name.clone())
+        .collect();
+    assert!(names.len() < foo_sections.len() + bar_sections.len());
+    let stored = fs::read_dir(dir.path())
+        .expect("Could not list directory")
+        .count();
+    assert_eq!(stored, names.len());
+
+    println!("* Manifests cannot be decoded without the store");
+    assert!(Decoder::new()
+        .decode::<_, Script>(&mut multipart(), Cursor::new(foo_manifest.clone()))
+        .is_err());
+
+    println!("* Manifests can be decoded from the store");
+    for &(ref expected, ref manifest) in &[(foo, foo_manifest), (bar, bar_manifest)] {
+        let data = resolve_sections(manifest, dir.path()).expect("Could not resolve sections");
+        let decoded: Script = Decoder::new()
+            .decode(&mut multipart(), Cursor::new(data))
+            .expect("Could not decode");
+        assert_eq!(&decoded, expected);
+    }
+}
@@ -0,0 +1,51 @@
+//! Check that shebangs survive encoding and decoding.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::io::multipart::{Metadata, Options};
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::multipart_with;
+
+use std::io::Cursor;
+
+#[test]
+fn test_shebang_roundtrip() {
+    let source = "#!/usr/bin/env node\nconsole.log('Hello');\n";
+    let parser = Shift::try_new().expect("Could not launch Shift");
+
+    let (metadata, text) = Metadata::from_source(source);
+    assert_eq!(metadata.shebang, Some("#!/usr/bin/env node".to_string()));
+    let mut ast = parser.parse_str(text).expect("Could not parse source");
+    let enricher = binjs::specialized::es6::Enrich::default();
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+
+    let mut format = multipart_with(Options {
+        metadata,
+        ..Options::default()
+    });
+    let data = Encoder::new()
+        .encode(None, &mut format, &ast)
+        .expect("Could not encode");
+
+    let (decoded, metadata): (Script, _) = Decoder::new()
+        .decode_with_metadata(&mut format, Cursor::new(data))
+        .expect("Could not decode");
+    let printer = Shift::try_new().expect("Could not launch Shift");
+    let decoded_source = metadata.restore(&printer.to_source(&decoded).expect("Could not print"));
+
+    let mut lines = decoded_source.lines();
+    assert_eq!(lines.next(), Some("#!/usr/bin/env node"));
+    let rest: Vec<_> = lines.collect();
+    let reparsed = parser
+        .parse_str(&rest.join("\n"))
+        .expect("Could not parse decoded source");
+    assert_eq!(
+        printer.to_source(&reparsed).expect("Could not print"),
+        printer.to_source(&decoded).expect("Could not print")
+    );
+}
@@ -0,0 +1,58 @@
+//! Check that debugging constructs are stripped on request.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::io::Encoder;
+use binjs::specialized::es6::Enrich;
+
+use common::multipart;
+
+#[test]
+fn test_strip() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let source = "debugger;
+        const x = console.log(1);
+        console.log(2);
+        function foo() { debugger; console.warn('foo'); return x; }
+        if (x) debugger;";
+
+    let encode = |enricher: &Enrich| {
+        let mut ast = parser.parse_str(source).expect("Could not parse source");
+        enricher.enrich(&mut ast).expect("Could not enrich AST");
+        let mut format = multipart();
+        let data = Encoder::new()
+            .encode(None, &mut format, &ast)
+            .expect("Could not encode");
+        let printed = parser.to_source(&ast).expect("Could not print");
+        (printed, data.len())
+    };
+
+    let (original, original_len) = encode(&Enrich::default());
+    let (stripped, stripped_len) = encode(&Enrich {
+        strip_debugger: true,
+        strip_console: true,
+        ..Enrich::default()
+    });
+    println!("Stripped: {}", stripped);
+
+    assert!(original.contains("debugger"));
+    assert!(!stripped.contains("debugger"));
+    assert!(!stripped.contains("console.log(2)"));
+    assert!(!stripped.contains("console.warn"));
+
+    // Calls whose result is used are kept.
+    assert!(stripped.contains("console.log(1)"));
+
+    assert!(stripped_len < original_len);
+
+    // Passes are independent.
+    let (debugger_only, _) = encode(&Enrich {
+        strip_debugger: true,
+        ..Enrich::default()
+    });
+    assert!(!debugger_only.contains("debugger"));
+    assert!(debugger_only.contains("console.log(2)"));
+}
@@ -0,0 +1,133 @@
+//! Check that TypeScript sources may be encoded once stripped of their types.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::source::{strip_types, Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::multipart;
+
+use std::io::Cursor;
+
+const TYPESCRIPT: &str = "
+interface Point { x: number; y: number }
+type Pair<T> = [T, T];
+declare const DEBUG: boolean;
+
+function add<T extends number>(a: T, b?: number): number {
+    return a + (b || 0);
+}
+
+class Counter implements Point {
+    private count: number;
+    public x!: number;
+    y: number;
+    constructor(start: number) {
+        this.count = start;
+    }
+    increment(by: number = 1): this {
+        this.count += by;
+        return this;
+    }
+}
+
+const values: Array<number> = [1, 2, 3];
+const first = values[0]!;
+const total = values.reduce((sum: number, value) => sum + value, 0) as number;
+let identity = <T>(x: T): T => x;
+";
+
+const JAVASCRIPT: &str = "
+function add(a, b) {
+    return a + (b || 0);
+}
+
+class Counter {
+    constructor(start) {
+        this.count = start;
+    }
+    increment(by = 1) {
+        this.count += by;
+        return this;
+    }
+}
+
+const values = [1, 2, 3];
+const first = values[0];
+const total = values.reduce((sum, value) => sum + value, 0);
+let identity = (x) => x;
+";
+
+#[test]
+fn test_strip_types() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+
+    println!("* Stripping preserves positions");
+    assert_eq!(
+        strip_types("let x: number = 1;").expect("Could not strip types"),
+        "let x         = 1;"
+    );
+    assert_eq!(
+        strip_types("f(a)\ninterface I {}\n(b)").expect("Could not strip types"),
+        "f(a)\n;             \n(b)"
+    );
+
+    println!("* Constructs with runtime semantics are rejected");
+    let error =
+        strip_types("let a = 1;\nenum Color { Red }").expect_err("Enums should be rejected");
+    assert_eq!((error.line, error.column), (2, 0));
+    assert!(strip_types("class A { constructor(private x: number) {} }").is_err());
+    assert!(strip_types("class A { x: number = 1; }").is_err());
+
+    println!("* Constructs which JavaScript would read differently are kept or rejected");
+    assert!(strip_types("let a = b as Foo\n[1, 2]").is_err());
+    assert_eq!(
+        strip_types("let a = b as Foo;\n[1, 2]").expect("Could not strip types"),
+        "let a = b       ;\n[1, 2]"
+    );
+    assert_eq!(
+        strip_types("let x = i < max ? lo : hi > (k);").expect("Could not strip types"),
+        "let x = i < max ? lo : hi > (k);"
+    );
+    assert_eq!(
+        strip_types("let x = f<T, U[]>(k);").expect("Could not strip types"),
+        "let x = f        (k);"
+    );
+    assert!(strip_types("let f = a ? (b) : c => d;").is_err());
+    assert!(strip_types("let f = a ? (b): T => d : e;").is_err());
+    assert_eq!(
+        strip_types("let f = (b): T => d;").expect("Could not strip types"),
+        "let f = (b)    => d;"
+    );
+
+    println!("* Stripped sources are valid JavaScript");
+    let stripped = strip_types(TYPESCRIPT).expect("Could not strip types");
+    assert_eq!(stripped.lines().count(), TYPESCRIPT.lines().count());
+    let ast = parser
+        .parse_str(&stripped)
+        .expect("Could not parse stripped source");
+    let reference = parser
+        .parse_str(JAVASCRIPT)
+        .expect("Could not parse reference");
+    let expected = parser.to_source(&reference).expect("Could not print");
+    assert_eq!(parser.to_source(&ast).expect("Could not print"), expected);
+
+    println!("* Stripped sources round-trip through encoding");
+    let mut ast = ast;
+    let enricher = binjs::specialized::es6::Enrich::default();
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+    let mut format = multipart();
+    let data = Encoder::new()
+        .encode(None, &mut format, &ast)
+        .expect("Could not encode");
+    let decoded: Script = Decoder::new()
+        .decode(&mut format, Cursor::new(data))
+        .expect("Could not decode");
+    assert_eq!(
+        parser.to_source(&decoded).expect("Could not print"),
+        expected
+    );
+}
@@ -0,0 +1,91 @@
+//! Check that ASTs may be encoded for decoders supporting an older grammar.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::io::Encoder;
+use binjs::specialized::es6::{Enrich, EnrichError, TargetGrammar};
+
+use common::multipart;
+
+#[test]
+fn test_target_grammar() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let source = "var data = [1, 2, { a: 3, b: [4, 5] }];";
+    let enrich = |enricher: &Enrich| {
+        let mut ast = parser.parse_str(source).expect("Could not parse source");
+        enricher.enrich(&mut ast).expect("Could not enrich AST");
+        ast
+    };
+    let encode = |ast| {
+        let mut format = multipart();
+        Encoder::new()
+            .encode(None, &mut format, ast)
+            .expect("Could not encode")
+    };
+    let uses_extension = |data: &[u8]| {
+        let name = b"BinASTExpressionWithProbabilityTable";
+        data.windows(name.len()).any(|window| window == name)
+    };
+
+    println!("* Scoped dictionaries are part of the most recent grammar");
+    let plain = enrich(&Enrich::default());
+    let mut injected = enrich(&Enrich {
+        pure_data_threshold: Some(1),
+        ..Enrich::default()
+    });
+    assert_ne!(injected, plain);
+    assert!(uses_extension(&encode(&injected)));
+    assert!(!uses_extension(&encode(&plain)));
+
+    println!("* Targeting es6, they are desugared");
+    let target = Enrich {
+        target_grammar: TargetGrammar::Es6,
+        ..Enrich::default()
+    };
+    target.enrich(&mut injected).expect("Could not enrich AST");
+    assert_eq!(injected, plain);
+    assert!(!uses_extension(&encode(&injected)));
+
+    println!("* Targeting es6, injecting them fails");
+    let mut ast = parser.parse_str(source).expect("Could not parse source");
+    match (Enrich {
+        pure_data_threshold: Some(1),
+        ..target
+    })
+    .enrich(&mut ast)
+    {
+        Err(EnrichError::NotInTargetGrammar {
+            construct: "BinASTExpressionWithProbabilityTable",
+            target: TargetGrammar::Es6,
+        }) => {}
+        other => panic!("Unexpected result {:?}", other),
+    }
+
+    println!("* Targeting es6, BigInt literals fail");
+    let mut ast = parser
+        .parse_str("var big = 10n;")
+        .expect("Could not parse source");
+    let target = Enrich {
+        target_grammar: TargetGrammar::Es6,
+        ..Enrich::default()
+    };
+    match target.enrich(&mut ast) {
+        Err(EnrichError::NotInTargetGrammar {
+            construct: "LiteralBigIntExpression",
+            target: TargetGrammar::Es6,
+        }) => {}
+        other => panic!("Unexpected result {:?}", other),
+    }
+
+    println!("* Targeting the most recent grammar, BigInt literals are encoded");
+    let mut ast = parser
+        .parse_str("var big = 10n;")
+        .expect("Could not parse source");
+    Enrich::default()
+        .enrich(&mut ast)
+        .expect("Could not enrich AST");
+    encode(&ast);
+}
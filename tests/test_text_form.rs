@@ -0,0 +1,69 @@
+//! Check that containers in text form decode to the same AST as binary
+//! containers, and that both forms are detected automatically.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::io::multipart::{from_text, is_text, Options, TEXT_PREAMBLE};
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::{multipart, multipart_with};
+
+use std::io::Cursor;
+
+#[test]
+fn test_text_form() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let mut ast = parser
+        .parse_str("function foo(x) { return x + \"\\u00e9t\u{e9}\"; }")
+        .expect("Could not parse source");
+    let enricher = binjs::specialized::es6::Enrich::default();
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+    let encode = |options: Options| {
+        Encoder::new()
+            .encode(None, &mut multipart_with(options), &ast)
+            .expect("Could not encode")
+    };
+    let decode = |data: &[u8]| -> Script {
+        Decoder::new()
+            .decode(&mut multipart(), Cursor::new(data))
+            .expect("Could not decode")
+    };
+
+    let binary = encode(Options::default());
+    let text = encode(Options {
+        text: true,
+        ..Options::default()
+    });
+
+    println!("* The text form is printable ASCII, after the preamble");
+    assert!(text.starts_with(TEXT_PREAMBLE));
+    assert!(text.iter().all(|byte| byte.is_ascii_graphic()));
+    assert!(is_text(&text));
+    assert!(!is_text(&binary));
+
+    println!("* The text form holds the binary form");
+    assert_eq!(from_text(&text).expect("Could not decode text"), binary);
+
+    println!("* Both forms decode to the same AST");
+    assert_eq!(decode(&binary), ast);
+    assert_eq!(decode(&text), ast);
+
+    println!("* Line breaks in the text form are ignored");
+    let mut wrapped = vec![];
+    for line in text.chunks(76) {
+        wrapped.extend_from_slice(line);
+        wrapped.extend_from_slice(b"\r\n");
+    }
+    assert_eq!(decode(&wrapped), ast);
+
+    println!("* Corrupted text is rejected");
+    let mut corrupted = text.clone();
+    corrupted.push(b'!');
+    assert!(Decoder::new()
+        .decode::<_, Script>(&mut multipart(), Cursor::new(&corrupted))
+        .is_err());
+}
@@ -0,0 +1,75 @@
+//! Check that a syntax error does not prevent encoding the rest of a file.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::source::Shift;
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::multipart;
+
+use std::io::Cursor;
+
+#[test]
+fn test_tolerant_parse() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+
+    println!("* Valid sources have no errors");
+    let (ast, errors) = parser
+        .parse_str_tolerant("var a = 1;\nvar b = 2;\n")
+        .expect("Could not parse source");
+    assert!(errors.is_empty());
+    assert_eq!(ast.statements.len(), 2);
+
+    println!("* Statements surrounding a broken statement are kept");
+    let source = "var before = 1;\nvar = ;\nvar after = 2;\nfunction last() {}\n";
+    let (mut ast, errors) = parser
+        .parse_str_tolerant(source)
+        .expect("Could not parse source");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line, 2);
+    assert_eq!(ast.statements.len(), 3);
+
+    println!("* Minified sources resume at the next statement");
+    let (minified, errors) = parser
+        .parse_str_tolerant("var a=1;var=;var b=2;function c(){}")
+        .expect("Could not parse source");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line, 1);
+    assert_eq!(minified.statements.len(), 3);
+
+    println!("* A syntax error in a function drops the whole function");
+    let (nested, errors) = parser
+        .parse_str_tolerant(
+            "function f() {\n  var inner = 1;\n  var = ;\n  var hoisted = 2;\n}\nvar after = 3;\n",
+        )
+        .expect("Could not parse source");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line, 3);
+    assert_eq!(nested.statements.len(), 1);
+    let printed = parser.to_source(&nested).expect("Could not print");
+    assert!(printed.contains("after"), "{}", printed);
+    assert!(!printed.contains("hoisted"), "{}", printed);
+
+    println!("* The valid statements are encoded");
+    let enricher = binjs::specialized::es6::Enrich::default();
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+    let mut format = multipart();
+    let data = Encoder::new()
+        .encode(None, &mut format, &ast)
+        .expect("Could not encode");
+    let decoded: Script = Decoder::new()
+        .decode(&mut format, Cursor::new(data))
+        .expect("Could not decode");
+    let printed = parser.to_source(&decoded).expect("Could not print");
+    for name in &["before", "after", "last"] {
+        assert!(
+            printed.contains(name),
+            "{} is missing from {}",
+            name,
+            printed
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//! Encode a file with the tree in pre-order and in breadth-first order,
+//! ensure that both decode to the same AST, and compare their sizes.
+
+extern crate binjs;
+
+mod common;
+
+use binjs::io::bytes::compress::Compression;
+use binjs::io::multipart::{Options, Targets, TreeOrder, TreeTokenReader};
+use binjs::source::{Shift, SourceParser};
+use binjs::specialized::es6::ast::{Script, WalkPath, Walker};
+use binjs::specialized::es6::io::{Decoder, Encoder};
+
+use common::{multipart_with_targets, OffsetCleanerVisitor};
+
+use std::io::Cursor;
+use std::thread;
+
+const PATH: &str = "tests/data/frameworks/jquery.3.3.1.min.js";
+
+#[test]
+fn test_tree_order() {
+    thread::Builder::new()
+        .name("test_tree_order large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main() {
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), PATH);
+    let mut ast = parser.parse_file(&path).expect("Could not parse source");
+    let enricher = binjs::specialized::es6::Enrich::default();
+    enricher.enrich(&mut ast).expect("Could not enrich AST");
+    ast.walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+        .expect("Could not clean offsets");
+
+    let mut sizes = vec![];
+    for &(tree_order, header) in &[
+        (TreeOrder::PreOrder, "[TREE]"),
+        (TreeOrder::BreadthFirst, "[TREE-BFS]"),
+    ] {
+        println!("* Round-trip with {:?}", tree_order);
+        let data = Encoder::new()
+            .encode(
+                None,
+                &mut multipart_with_targets(
+                    Targets::uniform(Compression::Brotli),
+                    Options {
+                        tree_order,
+                        ..Options::default()
+                    },
+                ),
+                &ast,
+            )
+            .expect("Could not encode");
+
+        let mut decoded: Script = Decoder::new()
+            .decode(
+                &mut multipart_with_targets(
+                    Targets::uniform(Compression::Brotli),
+                    Options::default(),
+                ),
+                Cursor::new(&data),
+            )
+            .expect("Could not decode");
+        decoded
+            .walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+            .expect("Could not clean offsets");
+        assert_eq!(decoded, ast);
+
+        // The order is recorded in the header of the tree section.
+        let reader = TreeTokenReader::new(Cursor::new(&data)).expect("Could not read container");
+        assert_eq!(reader.section_codec(header), Some(Compression::Brotli));
+        println!("  {} bytes", data.len());
+        sizes.push(data.len());
+    }
+
+    println!(
+        "* Breadth-first is {:.3} times the size of pre-order",
+        sizes[1] as f64 / sizes[0] as f64
+    );
+}
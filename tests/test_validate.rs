@@ -0,0 +1,83 @@
+//! Check that trees which decode correctly but do not match the grammar
+//! are rejected by validation.
+
+extern crate binjs;
+extern crate serde_json;
+
+use binjs::generic::validate::Validator;
+use binjs::generic::IdentifierName;
+use binjs::io::bytes::compress::Compression;
+use binjs::io::multipart::{Targets, TreeTokenReader, TreeTokenWriter};
+use binjs::io::{CompressionTarget, Deserialization, Serialization, TokenSerializer};
+use binjs::specialized::es6::ast::*;
+use binjs::specialized::es6::io::{Deserializer, Serializer};
+
+use std::io::Cursor;
+
+/// Encode and decode `ast`, returning the decoded tree in JSON format.
+fn roundtrip(ast: &Script) -> serde_json::Value {
+    let writer = binjs::io::TokenWriterTreeAdapter::new(TreeTokenWriter::new(Targets {
+        grammar_table: CompressionTarget::new(Compression::Identity),
+        strings_table: CompressionTarget::new(Compression::Identity),
+        numbers_table: CompressionTarget::new(Compression::Identity),
+        tree: CompressionTarget::new(Compression::Identity),
+    }));
+    let mut serializer = Serializer::new(writer);
+    serializer
+        .serialize(ast, &mut IOPath::new())
+        .expect("Could not encode AST");
+    let data = serializer.done().expect("Could not finalize AST encoding");
+
+    let reader = TreeTokenReader::new(Cursor::new(data)).expect("Could not decode AST container");
+    let mut deserializer = Deserializer::new(reader);
+    let decoded: Script = deserializer
+        .deserialize(&mut IOPath::new())
+        .expect("Could not decode");
+    serde_json::to_value(&decoded).expect("Could not convert AST to JSON")
+}
+
+fn script(declarators: Vec<VariableDeclarator>) -> Script {
+    Script {
+        scope: Default::default(),
+        directives: vec![],
+        statements: vec![VariableDeclaration {
+            kind: VariableDeclarationKind::Var,
+            declarators,
+        }
+        .into()],
+    }
+}
+
+#[test]
+fn test_validate() {
+    let spec = binjs::generic::es6::Library::spec();
+
+    println!("* A well-formed tree is accepted");
+    let mut valid = roundtrip(&script(vec![VariableDeclarator {
+        binding: BindingIdentifier {
+            name: IdentifierName::from_str("x"),
+        }
+        .into(),
+        init: None,
+    }]));
+    Validator::validate(&spec, &valid).expect("Well-formed tree should be accepted");
+
+    println!("* An empty [NonEmpty] list decodes but is rejected");
+    let empty = roundtrip(&script(vec![]));
+    let error = Validator::validate(&spec, &empty).expect_err("Empty list should be rejected");
+    assert_eq!(error.path, "Script.statements[0].declarators");
+
+    println!("* A missing required field is rejected");
+    valid["statements"][0]
+        .as_object_mut()
+        .unwrap()
+        .remove("kind");
+    let error = Validator::validate(&spec, &valid).expect_err("Missing field should be rejected");
+    assert_eq!(error.path, "Script.statements[0].kind");
+
+    println!("* A null in place of a required node is rejected");
+    valid["statements"][0]["kind"] = "var".into();
+    valid["statements"][0]["declarators"][0]["binding"] = serde_json::Value::Null;
+    let error = Validator::validate(&spec, &valid).expect_err("Null node should be rejected");
+    assert_eq!(error.path, "Script.statements[0].declarators[0].binding");
+}
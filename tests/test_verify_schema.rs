@@ -0,0 +1,45 @@
+//! Check that files using node kinds or fields missing from a grammar
+//! schema are reported.
+
+extern crate binjs;
+extern crate serde_json;
+
+use binjs::generic::schema::Schema;
+use binjs::source::{Shift, SourceParser};
+
+#[test]
+fn test_verify_schema() {
+    let spec = binjs::generic::es6::Library::spec();
+    let parser = Shift::try_new().expect("Could not launch Shift");
+    let ast = parser
+        .parse_str("var x = 1; if (x) { x++; }")
+        .expect("Could not parse source");
+    let ast = serde_json::to_value(&ast).expect("Could not convert AST to JSON");
+
+    println!("* A file is accepted by the schema of the current grammar");
+    let schema = Schema::to_json(&spec);
+    Schema::check(&schema, &ast).expect("File should match its own grammar");
+
+    println!("* Node kinds missing from the schema are reported");
+    let mut older = schema.clone();
+    older["interfaces"]
+        .as_object_mut()
+        .unwrap()
+        .remove("UpdateExpression");
+    let errors = Schema::check(&older, &ast).expect_err("Missing node kind should be reported");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        errors[0].path,
+        "Script.statements[1].consequent.statements[0].expression"
+    );
+    assert_eq!(errors[0].message, "Unknown node kind UpdateExpression");
+
+    println!("* Fields missing from the schema are reported");
+    older["interfaces"]["IfStatement"]["fields"]
+        .as_array_mut()
+        .unwrap()
+        .retain(|field| field["name"] != "alternate");
+    let errors = Schema::check(&older, &ast).expect_err("Missing field should be reported");
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].path, "Script.statements[1].alternate");
+}